@@ -0,0 +1,277 @@
+use toml_edit::{DocumentMut, InlineTable, Item, Table, Value};
+
+use crate::archive::EntryInfo;
+
+/// Where `workstation add` takes a package from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// The latest release of a GitHub repository, as `owner/name`
+    Github { repo: String },
+    /// A file to download, a binary or an archive
+    Url(String),
+}
+
+impl Source {
+    /// `owner/name`, a link to a GitHub repository or its releases, or else the URL of a file
+    pub fn parse(source: &str) -> Source {
+        let source = source.trim_end_matches('/');
+        let path = source
+            .strip_prefix("https://github.com/")
+            .or_else(|| source.strip_prefix("http://github.com/"));
+        match path {
+            Some(path) => {
+                let parts: Vec<&str> = path.split('/').collect();
+                match parts[..] {
+                    [owner, name]
+                    | [owner, name, "releases"]
+                    | [owner, name, "releases", "latest"] => return github(owner, name),
+                    _ => {}
+                }
+            }
+            None if !source.contains(':') && !source.starts_with(['/', '~', '.']) => {
+                if let Some((owner, name)) = source.split_once('/') {
+                    if !owner.is_empty() && !name.is_empty() && !name.contains('/') {
+                        return github(owner, name);
+                    }
+                }
+            }
+            None => {}
+        }
+        Source::Url(source.to_string())
+    }
+}
+
+fn github(owner: &str, name: &str) -> Source {
+    Source::Github {
+        repo: format!("{}/{}", owner, name.trim_end_matches(".git")),
+    }
+}
+
+/// The name a package downloaded from `file_name` is likely known by: the part before the first
+/// `-`, `_` or `.`, so `jq-linux-amd64` is `jq`
+pub fn guess_name(file_name: &str) -> String {
+    file_name
+        .split(['-', '_', '.'])
+        .find(|part| !part.is_empty())
+        .unwrap_or(file_name)
+        .to_string()
+}
+
+/// The archive path of the file to install: the file named `name` when there is one, or else the
+/// only executable. Zip archives made on Windows have no modes, then files without an extension
+/// count as executables.
+pub fn guess_bin(entries: &[EntryInfo], name: Option<&str>) -> eyre::Result<String> {
+    let files: Vec<&EntryInfo> = entries.iter().filter(|entry| !entry.is_dir).collect();
+    let base = |path: &str| path.rsplit('/').next().unwrap_or(path).to_string();
+    if let Some(name) = name {
+        let named: Vec<_> = files
+            .iter()
+            .filter(|entry| base(&entry.path) == name)
+            .collect();
+        if let [entry] = named[..] {
+            return Ok(entry.path.clone());
+        }
+    }
+    let executables: Vec<&str> = files
+        .iter()
+        .filter(|entry| match entry.mode {
+            Some(mode) => mode & 0o111 != 0,
+            None => !base(&entry.path).contains('.'),
+        })
+        .map(|entry| entry.path.as_str())
+        .collect();
+    match executables[..] {
+        [path] => Ok(path.to_string()),
+        [] => eyre::bail!("The archive has no executable, pass --bin with the file to install"),
+        _ => eyre::bail!(
+            "The archive has {} executables, pass --bin with the one to install:\n  {}",
+            executables.len(),
+            executables.join("\n  ")
+        ),
+    }
+}
+
+/// The config entry of a package, as the inline table the config writes packages as
+pub fn entry(name: &str, url: &str, bin: Option<&str>) -> InlineTable {
+    let mut table = InlineTable::new();
+    table.insert("name", name.into());
+    match bin {
+        Some(bin) => {
+            table.insert("bin", bin.into());
+            table.insert("archive", url.into());
+        }
+        None => {
+            table.insert("url", url.into());
+        }
+    }
+    table
+}
+
+/// Append `package` to the packages of `section` in `document`, on its own line of an inline
+/// array, or as a `[[section.packages]]` table when the config writes them so
+pub fn append(document: &mut DocumentMut, section: &str, package: InlineTable) -> eyre::Result<()> {
+    let Some(table) = document.get_mut(section).and_then(Item::as_table_like_mut) else {
+        eyre::bail!("The config has no [{}] section", section);
+    };
+    let name = package
+        .get("name")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let packages = table
+        .entry("packages")
+        .or_insert(Item::Value(Value::Array(Default::default())));
+    match packages {
+        Item::Value(Value::Array(array)) => {
+            let exists = array.iter().any(|value| {
+                value
+                    .as_inline_table()
+                    .and_then(|table| table.get("name"))
+                    .and_then(Value::as_str)
+                    == Some(name)
+            });
+            if exists {
+                eyre::bail!("[{}] already has a package named {}", section, name);
+            }
+            // Comments after the last package, such as commented out packages, stay before it
+            let trailing = array.trailing().as_str().unwrap_or_default().trim_end();
+            let mut value = Value::InlineTable(package);
+            value.decor_mut().set_prefix(format!("{}\n  ", trailing));
+            array.push_formatted(value);
+            array.set_trailing("\n");
+            array.set_trailing_comma(true);
+        }
+        Item::ArrayOfTables(tables) => {
+            if tables
+                .iter()
+                .any(|table| table.get("name").and_then(Item::as_str) == Some(name))
+            {
+                eyre::bail!("[{}] already has a package named {}", section, name);
+            }
+            let mut table: Table = package.into_table();
+            table.fmt();
+            tables.push(table);
+        }
+        _ => eyre::bail!("packages of [{}] is not a list", section),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, mode: Option<u32>) -> EntryInfo {
+        EntryInfo {
+            path: path.to_string(),
+            size: 1,
+            mode,
+            is_dir: false,
+        }
+    }
+
+    #[test]
+    fn test_sources_are_repos_or_urls() {
+        let ripgrep = Source::Github {
+            repo: "BurntSushi/ripgrep".to_string(),
+        };
+        for source in [
+            "BurntSushi/ripgrep",
+            "https://github.com/BurntSushi/ripgrep",
+            "https://github.com/BurntSushi/ripgrep.git",
+            "https://github.com/BurntSushi/ripgrep/releases/latest/",
+        ] {
+            assert_eq!(Source::parse(source), ripgrep, "{}", source);
+        }
+        for url in [
+            "https://github.com/jqlang/jq/releases/download/jq-1.7.1/jq-linux-amd64",
+            "https://example.com/tool/tool.tar.gz",
+            "./dist/tool",
+        ] {
+            assert_eq!(Source::parse(url), Source::Url(url.to_string()));
+        }
+        assert_eq!(guess_name("jq-linux-amd64"), "jq");
+    }
+
+    #[test]
+    fn test_the_bin_is_the_named_file_or_the_only_executable() {
+        let entries = [
+            file("ripgrep-14.1.0/rg", Some(0o755)),
+            file("ripgrep-14.1.0/doc/rg.1", Some(0o644)),
+            file("ripgrep-14.1.0/complete/rg.bash", Some(0o644)),
+        ];
+        assert_eq!(guess_bin(&entries, None).unwrap(), "ripgrep-14.1.0/rg");
+        assert_eq!(
+            guess_bin(&entries, Some("rg.1")).unwrap(),
+            "ripgrep-14.1.0/doc/rg.1"
+        );
+
+        let windows = [file("tool", None), file("README.md", None)];
+        assert_eq!(guess_bin(&windows, None).unwrap(), "tool");
+        let two = [file("a", Some(0o755)), file("b", Some(0o755))];
+        assert!(guess_bin(&two, None)
+            .unwrap_err()
+            .to_string()
+            .contains("2 executables"));
+    }
+
+    #[test]
+    fn test_packages_are_appended_in_the_style_of_the_config() {
+        let mut inline: DocumentMut = r#"[linux_x86_64]
+location = "~/.local/bin"
+packages = [
+  { name = "fd", url = "https://example.com/fd" },
+  # { name = "bat", url = "https://example.com/bat" },
+]
+"#
+        .parse()
+        .unwrap();
+        append(
+            &mut inline,
+            "linux_x86_64",
+            entry("rg", "https://example.com/rg.tar.gz", Some("rg")),
+        )
+        .unwrap();
+        assert_eq!(
+            inline.to_string(),
+            r#"[linux_x86_64]
+location = "~/.local/bin"
+packages = [
+  { name = "fd", url = "https://example.com/fd" },
+  # { name = "bat", url = "https://example.com/bat" },
+  { name = "rg", bin = "rg", archive = "https://example.com/rg.tar.gz" },
+]
+"#
+        );
+        assert!(append(
+            &mut inline,
+            "linux_x86_64",
+            entry("fd", "https://example.com/fd", None)
+        )
+        .is_err());
+        assert!(append(
+            &mut inline,
+            "macos_aarch64",
+            entry("jq", "https://example.com/jq", None)
+        )
+        .is_err());
+
+        let mut tables: DocumentMut = r#"[linux_x86_64]
+location = "~/.local/bin"
+
+[[linux_x86_64.packages]]
+name = "fd"
+url = "https://example.com/fd"
+"#
+        .parse()
+        .unwrap();
+        append(
+            &mut tables,
+            "linux_x86_64",
+            entry("jq", "https://example.com/jq", None),
+        )
+        .unwrap();
+        assert!(tables.to_string().ends_with(
+            "\n[[linux_x86_64.packages]]\nname = \"jq\"\nurl = \"https://example.com/jq\"\n"
+        ));
+    }
+}
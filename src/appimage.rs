@@ -0,0 +1,242 @@
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use eyre::Context;
+
+/// Where the desktop entries of AppImages and their icons are written, the directories of the
+/// user that launchers read
+#[derive(Debug, Clone)]
+pub struct Launchers {
+    pub applications: PathBuf,
+    pub icons: PathBuf,
+}
+
+/// Prefix of the files written for a package, so that they never replace those of another app
+const PREFIX: &str = "workstation-";
+
+/// Icon formats launchers show, the ones preferred first
+const ICON_EXTENSIONS: [&str; 3] = ["svg", "png", "xpm"];
+
+/// What an AppImage carries for launchers
+#[derive(Debug, Default)]
+struct Embedded {
+    entry: Option<String>,
+    /// The extension of the icon and its content
+    icon: Option<(&'static str, Vec<u8>)>,
+}
+
+/// Write the desktop entry and icon of the AppImage installed at `path` as package `name`, and
+/// return the files written.
+///
+/// The entry and icon the AppImage carries are used when it can extract them, with the entry
+/// pointed at `path`. Otherwise an entry naming the app after the package is written.
+pub fn integrate(name: &str, path: &Path, launchers: &Launchers) -> eyre::Result<Vec<PathBuf>> {
+    let embedded = extract(path).unwrap_or_default();
+    let icon = match embedded.icon {
+        Some((extension, data)) => {
+            let icon = launchers
+                .icons
+                .join(format!("{}{}.{}", PREFIX, name, extension));
+            write(&icon, &data)?;
+            Some(icon)
+        }
+        None => None,
+    };
+    let entry = launchers
+        .applications
+        .join(format!("{}{}.desktop", PREFIX, name));
+    let content = desktop_entry(name, path, embedded.entry.as_deref(), icon.as_deref());
+    write(&entry, content.as_bytes())?;
+    Ok(std::iter::once(entry).chain(icon).collect())
+}
+
+fn write(path: &Path, data: &[u8]) -> eyre::Result<()> {
+    let parent = path.parent().expect("launcher files are in a directory");
+    std::fs::create_dir_all(parent).with_context(|| format!("Creating {}", parent.display()))?;
+    std::fs::write(path, data).with_context(|| format!("Writing {}", path.display()))
+}
+
+/// The desktop entry and icon of the AppImage at `path`, extracted with its own
+/// `--appimage-extract`. Only type 2 AppImages are asked, older ones start the app instead.
+fn extract(path: &Path) -> Option<Embedded> {
+    let mut magic = [0; 11];
+    std::fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .ok()?;
+    if magic[8..] != *b"AI\x02" {
+        return None;
+    }
+
+    let directory = tempfile::tempdir().ok()?;
+    let extract = |pattern: &str| {
+        Command::new(path)
+            .arg("--appimage-extract")
+            .arg(pattern)
+            .current_dir(directory.path())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    };
+    let root = directory.path().join("squashfs-root");
+    let files = || {
+        std::fs::read_dir(&root)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+    };
+
+    if !extract("*.desktop") {
+        return None;
+    }
+    let entry = files()
+        .filter(|file| {
+            file.extension()
+                .is_some_and(|extension| extension == "desktop")
+        })
+        .find_map(|file| std::fs::read_to_string(file).ok())?;
+    let icon = value(&entry, "Icon").and_then(|icon| {
+        // Named without its extension, the file is next to the entry
+        extract(&format!("{}.*", icon));
+        ICON_EXTENSIONS.into_iter().find_map(|extension| {
+            let data = std::fs::read(root.join(format!("{}.{}", icon, extension))).ok()?;
+            Some((extension, data))
+        })
+    });
+    Some(Embedded {
+        entry: Some(entry),
+        icon,
+    })
+}
+
+/// The value of `key` in the `[Desktop Entry]` group of `entry`
+fn value<'a>(entry: &'a str, key: &str) -> Option<&'a str> {
+    let mut group = "";
+    entry.lines().find_map(|line| {
+        if line.starts_with('[') {
+            group = line.trim();
+            return None;
+        }
+        let (name, value) = line.split_once('=')?;
+        (group == "[Desktop Entry]" && name.trim() == key).then(|| value.trim())
+    })
+}
+
+/// The desktop entry of the AppImage at `path`: `embedded`, the one it carries, starting
+/// `path` and showing `icon`, or one named after the package when it carries none
+fn desktop_entry(name: &str, path: &Path, embedded: Option<&str>, icon: Option<&Path>) -> String {
+    let Some(embedded) = embedded else {
+        let mut entry = format!(
+            "[Desktop Entry]\nType=Application\nName={}\nExec={} %U\n",
+            name,
+            quote(path)
+        );
+        if let Some(icon) = icon {
+            entry.push_str(&format!("Icon={}\n", icon.display()));
+        }
+        entry.push_str("Terminal=false\n");
+        return entry;
+    };
+
+    let mut entry = String::new();
+    for line in embedded.lines() {
+        let rewritten = match line.split_once('=') {
+            // Actions of the app start it too, each with arguments of its own
+            Some((key, value)) if key.trim() == "Exec" => {
+                let arguments = arguments(value.trim());
+                match arguments.is_empty() {
+                    true => format!("Exec={}", quote(path)),
+                    false => format!("Exec={} {}", quote(path), arguments),
+                }
+            }
+            Some((key, _)) if key.trim() == "TryExec" => format!("TryExec={}", path.display()),
+            Some((key, _)) if key.trim() == "Icon" && icon.is_some() => {
+                format!("Icon={}", icon.expect("icon is set").display())
+            }
+            _ => line.to_string(),
+        };
+        entry.push_str(&rewritten);
+        entry.push('\n');
+    }
+    entry
+}
+
+/// What follows the program in the `Exec` line `exec`, such as `%U`
+fn arguments(exec: &str) -> &str {
+    let rest = match exec.strip_prefix('"') {
+        Some(quoted) => {
+            let mut escaped = false;
+            let end = quoted.find(|c| {
+                let closes = c == '"' && !escaped;
+                escaped = c == '\\' && !escaped;
+                closes
+            });
+            end.map_or("", |end| &quoted[end + 1..])
+        }
+        None => exec
+            .split_once(char::is_whitespace)
+            .map_or("", |(_, rest)| rest),
+    };
+    rest.trim()
+}
+
+/// `path` as a quoted argument of an `Exec` line, escaped first for the quoting and then for the
+/// string the line is
+fn quote(path: &Path) -> String {
+    let mut quoted = String::from("\"");
+    for c in path.to_string_lossy().chars() {
+        match c {
+            '\\' => quoted.push_str(r"\\\\"),
+            '"' | '`' | '$' => {
+                quoted.push_str(r"\\");
+                quoted.push(c);
+            }
+            '%' => quoted.push_str("%%"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries_start_the_installed_appimage() {
+        let path = Path::new("/home/u/Applications/obsidian");
+        let icon = Path::new("/home/u/.local/share/icons/workstation-obsidian.png");
+        let embedded = "[Desktop Entry]\n\
+                        Name=Obsidian\n\
+                        Exec=AppRun --no-sandbox %U\n\
+                        TryExec=AppRun\n\
+                        Icon=obsidian\n\
+                        \n\
+                        [Desktop Action new]\n\
+                        Exec=\"App Run\" --new-window\n";
+
+        assert_eq!(
+            desktop_entry("obsidian", path, Some(embedded), Some(icon)),
+            "[Desktop Entry]\n\
+             Name=Obsidian\n\
+             Exec=\"/home/u/Applications/obsidian\" --no-sandbox %U\n\
+             TryExec=/home/u/Applications/obsidian\n\
+             Icon=/home/u/.local/share/icons/workstation-obsidian.png\n\
+             \n\
+             [Desktop Action new]\n\
+             Exec=\"/home/u/Applications/obsidian\" --new-window\n"
+        );
+        assert_eq!(value(embedded, "Icon"), Some("obsidian"));
+        assert_eq!(
+            desktop_entry("kdenlive", Path::new("/opt/100% $app"), None, None),
+            "[Desktop Entry]\nType=Application\nName=kdenlive\n\
+             Exec=\"/opt/100%% \\\\$app\" %U\nTerminal=false\n"
+        );
+    }
+}
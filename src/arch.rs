@@ -0,0 +1,414 @@
+use std::{fmt, process::Command, sync::OnceLock};
+
+use goblin::{
+    elf,
+    mach::{self, cputype},
+};
+
+/// An operating system and CPU architecture, named like [`std::env::consts`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Platform {
+    pub os: &'static str,
+    pub arch: &'static str,
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.os, self.arch)
+    }
+}
+
+/// Other spellings of operating systems and architectures, as `(name, alias)`, from the Go and
+/// Apple toolchains that many release names follow
+const ALIASES: &[(&str, &str)] = &[("macos", "darwin"), ("aarch64", "arm64")];
+
+/// The name of `name` this crate uses
+fn canonical(name: &str) -> &str {
+    ALIASES
+        .iter()
+        .find(|(_, alias)| *alias == name)
+        .map_or(name, |(canonical, _)| canonical)
+}
+
+/// `name` followed by its aliases
+fn names(name: &str) -> impl Iterator<Item = &str> + '_ {
+    std::iter::once(name).chain(
+        ALIASES
+            .iter()
+            .filter(move |(canonical, _)| *canonical == name)
+            .map(|(_, alias)| *alias),
+    )
+}
+
+/// The C library binaries are linked against, which decides whether a Linux build runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Libc {
+    Gnu,
+    Musl,
+    /// Platforms without a choice of C library, such as macOS
+    None,
+}
+
+impl Libc {
+    /// The name used in section names and the `{libc}` template variable
+    pub fn name(&self) -> &'static str {
+        match self {
+            Libc::Gnu => "gnu",
+            Libc::Musl => "musl",
+            Libc::None => "",
+        }
+    }
+}
+
+/// The platform packages are installed for, chosen with `--target` or detected from the host
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub os: String,
+    pub arch: String,
+    pub libc: Libc,
+}
+
+static TARGET: OnceLock<Target> = OnceLock::new();
+
+impl Target {
+    /// The target of this run, the host unless [`Target::init`] chose another one
+    pub fn current() -> &'static Target {
+        TARGET.get_or_init(Target::host)
+    }
+
+    /// Use `target` for the rest of the run, before any config is read
+    pub fn init(target: Target) -> eyre::Result<()> {
+        TARGET
+            .set(target)
+            .map_err(|_| eyre::eyre!("The target was already in use"))
+    }
+
+    pub fn host() -> Target {
+        let libc = match std::env::consts::OS {
+            "linux" => host_libc(),
+            _ => Libc::None,
+        };
+        Target {
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            libc,
+        }
+    }
+
+    /// Parse `<os>_<arch>` or `<os>_<arch>_<libc>`, such as `linux_aarch64_musl`. Linux targets
+    /// without a libc are glibc, and the aliases of [`ALIASES`] are accepted.
+    pub fn parse(name: &str) -> eyre::Result<Target> {
+        let (os, rest) = name.split_once('_').ok_or_else(|| {
+            eyre::eyre!("Invalid target {:?}, expected <os>_<arch>[_<libc>]", name)
+        })?;
+        let os = canonical(os);
+        let (arch, libc) = match rest.rsplit_once('_') {
+            Some((arch, "musl")) => (arch, Libc::Musl),
+            Some((arch, "gnu")) => (arch, Libc::Gnu),
+            _ if os == "linux" => (rest, Libc::Gnu),
+            _ => (rest, Libc::None),
+        };
+        let arch = canonical(arch);
+        if !matches!(os, "linux" | "macos") {
+            eyre::bail!("Unknown operating system {:?} in target {:?}", os, name);
+        }
+        if os != "linux" && libc != Libc::None {
+            eyre::bail!("Only linux targets have a libc, got {:?}", name);
+        }
+
+        Ok(Target {
+            os: os.to_string(),
+            arch: arch.to_string(),
+            libc,
+        })
+    }
+
+    /// Config sections that can serve this target, best first, each also under the names of
+    /// [`ALIASES`], such as `darwin_arm64` for `macos_aarch64`.
+    ///
+    /// A plain `linux_<arch>` section is taken to be glibc. A musl host only falls back to
+    /// `_static`, never to a glibc section, while a glibc host can use both.
+    pub fn sections(&self) -> Vec<String> {
+        let bases: Vec<String> = names(&self.os)
+            .flat_map(|os| names(&self.arch).map(move |arch| format!("{}_{}", os, arch)))
+            .collect();
+        self.suffixes()
+            .iter()
+            .flat_map(|suffix| bases.iter().map(move |base| format!("{}{}", base, suffix)))
+            .collect()
+    }
+
+    /// [`Target::sections`] without the aliases, for suggesting a section to add
+    pub fn canonical_sections(&self) -> Vec<String> {
+        self.suffixes()
+            .iter()
+            .map(|suffix| format!("{}_{}{}", self.os, self.arch, suffix))
+            .collect()
+    }
+
+    fn suffixes(&self) -> &'static [&'static str] {
+        match self.libc {
+            Libc::Gnu => &["_gnu", "", "_static"],
+            Libc::Musl => &["_musl", "_static"],
+            Libc::None => &[""],
+        }
+    }
+
+    /// The platform executables for this target declare, see [`detect`]
+    pub fn platform(&self) -> Platform {
+        const NAMES: &[&str] = &[
+            "linux",
+            "macos",
+            "x86_64",
+            "aarch64",
+            "x86",
+            "arm",
+            "riscv64",
+            "riscv32",
+            "powerpc64",
+            "s390x",
+        ];
+        let known = |name: &str| NAMES.iter().copied().find(|known| *known == name);
+        Platform {
+            os: known(&self.os).unwrap_or("unknown"),
+            arch: known(&self.arch).unwrap_or("unknown"),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}_{}", self.os, self.arch)?;
+        if self.libc != Libc::None {
+            write!(f, "_{}", self.libc.name())?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether the host runs on musl: the interpreter of the running binary names the dynamic
+/// loader, a statically linked binary has none and `ldd --version` tells instead
+fn host_libc() -> Libc {
+    if let Some(interpreter) = std::fs::read("/proc/self/exe")
+        .ok()
+        .and_then(|bytes| interpreter(&bytes))
+    {
+        return libc_of_interpreter(&interpreter);
+    }
+
+    match Command::new("ldd").arg("--version").output() {
+        Ok(output) => {
+            let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+            text.push_str(&String::from_utf8_lossy(&output.stderr));
+            if text.to_lowercase().contains("musl") {
+                Libc::Musl
+            } else {
+                Libc::Gnu
+            }
+        }
+        Err(_) => Libc::Gnu,
+    }
+}
+
+/// The ELF program interpreter of `bytes`, `None` for static binaries
+fn interpreter(bytes: &[u8]) -> Option<String> {
+    elf::Elf::parse(bytes).ok()?.interpreter.map(str::to_string)
+}
+
+fn libc_of_interpreter(interpreter: &str) -> Libc {
+    if interpreter.contains("ld-musl") {
+        Libc::Musl
+    } else {
+        Libc::Gnu
+    }
+}
+
+/// How much of the start of a file [`detect`] needs to see
+pub const HEADER_LEN: u64 = 4096;
+
+/// The platforms the executable header of `bytes` says it runs on. Only the header is read, the
+/// first [`HEADER_LEN`] bytes of a file are enough.
+///
+/// Universal Mach-O binaries run on several, anything that is not an ELF or Mach-O executable
+/// on none. ELF does not reliably record the operating system, release archives only ship ELF
+/// binaries for Linux.
+pub fn detect(bytes: &[u8]) -> Vec<Platform> {
+    if bytes.starts_with(elf::header::ELFMAG) {
+        return match elf::Elf::parse_header(bytes) {
+            Ok(header) => vec![Platform {
+                os: "linux",
+                arch: elf_arch(header.e_machine, header.e_ident[elf::header::EI_CLASS]),
+            }],
+            Err(_) => vec![],
+        };
+    }
+
+    match mach::parse_magic_and_ctx(bytes, 0) {
+        // The CPU type follows the magic, in the byte order the magic gives away
+        Ok((_, Some(ctx))) => bytes
+            .get(4..8)
+            .map(|cpu| {
+                let cpu = cpu.try_into().expect("four bytes");
+                macos(match ctx.le.is_little() {
+                    true => u32::from_le_bytes(cpu),
+                    false => u32::from_be_bytes(cpu),
+                })
+            })
+            .into_iter()
+            .collect(),
+        Ok((mach::fat::FAT_MAGIC, None)) => match mach::MultiArch::new(bytes) {
+            Ok(fat) => fat
+                .iter_arches()
+                .filter_map(Result::ok)
+                .map(|arch| macos(arch.cputype))
+                .collect(),
+            Err(_) => vec![],
+        },
+        _ => vec![],
+    }
+}
+
+fn elf_arch(machine: u16, class: u8) -> &'static str {
+    match machine {
+        elf::header::EM_X86_64 => "x86_64",
+        elf::header::EM_AARCH64 => "aarch64",
+        elf::header::EM_386 => "x86",
+        elf::header::EM_ARM => "arm",
+        elf::header::EM_RISCV if class == elf::header::ELFCLASS64 => "riscv64",
+        elf::header::EM_RISCV => "riscv32",
+        elf::header::EM_PPC64 => "powerpc64",
+        elf::header::EM_S390 => "s390x",
+        _ => "unknown",
+    }
+}
+
+fn macos(cpu: u32) -> Platform {
+    let arch = match cpu {
+        cputype::CPU_TYPE_X86_64 => "x86_64",
+        cputype::CPU_TYPE_ARM64 => "aarch64",
+        cputype::CPU_TYPE_X86 => "x86",
+        cputype::CPU_TYPE_ARM => "arm",
+        _ => "unknown",
+    };
+    Platform { os: "macos", arch }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// A bare 64-bit little endian ELF header
+    pub fn elf(machine: u16) -> Vec<u8> {
+        let mut header = vec![0; 64];
+        header[..4].copy_from_slice(elf::header::ELFMAG);
+        header[4] = elf::header::ELFCLASS64;
+        header[5] = elf::header::ELFDATA2LSB;
+        header[6] = 1;
+        header[16..18].copy_from_slice(&2u16.to_le_bytes());
+        header[18..20].copy_from_slice(&machine.to_le_bytes());
+        header[20..24].copy_from_slice(&1u32.to_le_bytes());
+        header[52..54].copy_from_slice(&64u16.to_le_bytes());
+        header
+    }
+
+    /// A bare 64-bit Mach-O executable header without load commands
+    pub fn mach_o(cpu: u32) -> Vec<u8> {
+        let mut header = vec![];
+        header.extend_from_slice(&mach::header::MH_MAGIC_64.to_le_bytes());
+        header.extend_from_slice(&cpu.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&mach::header::MH_EXECUTE.to_le_bytes());
+        header.extend_from_slice(&[0; 16]);
+        header
+    }
+
+    #[test]
+    fn test_elf_machine_is_detected() {
+        assert_eq!(
+            detect(&elf(elf::header::EM_X86_64)),
+            vec![Platform {
+                os: "linux",
+                arch: "x86_64"
+            }]
+        );
+        assert_eq!(detect(&elf(elf::header::EM_AARCH64))[0].arch, "aarch64");
+    }
+
+    #[test]
+    fn test_mach_o_cpu_is_detected() {
+        assert_eq!(
+            detect(&mach_o(cputype::CPU_TYPE_ARM64)),
+            vec![Platform {
+                os: "macos",
+                arch: "aarch64"
+            }]
+        );
+    }
+
+    #[test]
+    fn test_targets_parse() {
+        let cases = [
+            ("linux_x86_64", "linux", "x86_64", Libc::Gnu),
+            ("linux_x86_64_musl", "linux", "x86_64", Libc::Musl),
+            ("linux_aarch64_gnu", "linux", "aarch64", Libc::Gnu),
+            ("macos_aarch64", "macos", "aarch64", Libc::None),
+            ("darwin_arm64", "macos", "aarch64", Libc::None),
+            ("linux_arm64_musl", "linux", "aarch64", Libc::Musl),
+        ];
+
+        for (name, os, arch, libc) in cases {
+            let target = Target::parse(name).unwrap();
+            assert_eq!((target.os.as_str(), target.arch.as_str()), (os, arch));
+            assert_eq!(target.libc, libc, "{}", name);
+        }
+        assert!(Target::parse("linux").is_err());
+        assert!(Target::parse("windows_x86_64").is_err());
+        assert!(Target::parse("macos_aarch64_musl").is_err());
+    }
+
+    #[test]
+    fn test_musl_never_falls_back_to_glibc() {
+        let sections = |name| Target::parse(name).unwrap().sections();
+
+        assert_eq!(
+            sections("linux_aarch64_musl"),
+            vec![
+                "linux_aarch64_musl",
+                "linux_arm64_musl",
+                "linux_aarch64_static",
+                "linux_arm64_static"
+            ]
+        );
+        assert_eq!(
+            sections("linux_x86_64"),
+            vec!["linux_x86_64_gnu", "linux_x86_64", "linux_x86_64_static"]
+        );
+        assert_eq!(
+            sections("macos_aarch64"),
+            vec![
+                "macos_aarch64",
+                "macos_arm64",
+                "darwin_aarch64",
+                "darwin_arm64"
+            ]
+        );
+        assert_eq!(
+            Target::parse("linux_aarch64").unwrap().canonical_sections(),
+            vec!["linux_aarch64_gnu", "linux_aarch64", "linux_aarch64_static"]
+        );
+    }
+
+    #[test]
+    fn test_libc_follows_the_interpreter() {
+        assert_eq!(libc_of_interpreter("/lib/ld-musl-x86_64.so.1"), Libc::Musl);
+        assert_eq!(
+            libc_of_interpreter("/lib64/ld-linux-x86-64.so.2"),
+            Libc::Gnu
+        );
+    }
+
+    #[test]
+    fn test_scripts_run_nowhere() {
+        assert_eq!(detect(b"#!/bin/sh\necho hi\n"), vec![]);
+    }
+}
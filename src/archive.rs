@@ -0,0 +1,1086 @@
+use std::{
+    io::{Read, Seek, Write},
+    os::unix::fs::PermissionsExt,
+    path::{Component, Path, PathBuf},
+};
+
+use eyre::Context;
+
+use crate::{
+    arch::Platform,
+    error::{code_of, ErrorCode},
+};
+
+/// How an archive is packed
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Tar(Compression),
+    Zip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Compression {
+    Gzip,
+    Xz,
+    Bzip2,
+    Zstd,
+}
+
+const EXTENSIONS: &[(&str, Format)] = &[
+    (".tar.gz", Format::Tar(Compression::Gzip)),
+    (".tgz", Format::Tar(Compression::Gzip)),
+    (".tar.xz", Format::Tar(Compression::Xz)),
+    (".txz", Format::Tar(Compression::Xz)),
+    (".tar.bz2", Format::Tar(Compression::Bzip2)),
+    (".tbz2", Format::Tar(Compression::Bzip2)),
+    (".tar.zst", Format::Tar(Compression::Zstd)),
+    (".tzst", Format::Tar(Compression::Zstd)),
+    (".zip", Format::Zip),
+];
+
+/// Leading bytes of each format, for archives whose name has no known extension. Compressed
+/// streams are taken to hold a tar archive.
+const MAGIC: &[(&[u8], Format)] = &[
+    (b"\x1f\x8b", Format::Tar(Compression::Gzip)),
+    (b"\xfd7zXZ\x00", Format::Tar(Compression::Xz)),
+    (b"BZh", Format::Tar(Compression::Bzip2)),
+    (b"\x28\xb5\x2f\xfd", Format::Tar(Compression::Zstd)),
+    (b"PK\x03\x04", Format::Zip),
+];
+
+/// Extensions of single compressed files, such as `tool-linux-amd64.gz`
+const COMPRESSED: &[(&str, Compression)] = &[
+    (".gz", Compression::Gzip),
+    (".xz", Compression::Xz),
+    (".bz2", Compression::Bzip2),
+    (".zst", Compression::Zstd),
+];
+
+fn format_of_name(archive: &str) -> Option<Format> {
+    let archive = archive.to_lowercase();
+    EXTENSIONS
+        .iter()
+        .find(|(extension, _)| archive.ends_with(extension))
+        .map(|(_, format)| *format)
+}
+
+/// The format of `archive`, from its extension or else from the start of its content. `input`
+/// is left at its start.
+fn format(archive: &str, input: &mut (impl Read + Seek)) -> eyre::Result<Format> {
+    if let Some(format) = format_of_name(archive) {
+        return Ok(format);
+    }
+    let mut start = vec![];
+    input.by_ref().take(8).read_to_end(&mut start)?;
+    input.rewind()?;
+    MAGIC
+        .iter()
+        .find(|(magic, _)| start.starts_with(magic))
+        .map(|(_, format)| *format)
+        .ok_or_else(|| {
+            ErrorCode::UnsupportedArchive.error(format!("Unsupported archive format: {}", archive))
+        })
+}
+
+/// Whether the archive URL has an extension [`extract_entry`] can unpack
+pub fn is_supported(archive: &str) -> bool {
+    format_of_name(archive).is_some()
+}
+
+/// Write the content of `input` to `output`, decompressed when `name` is a single compressed
+/// file rather than an archive
+pub fn decompress_single(
+    name: &str,
+    mut input: impl Read,
+    mut output: impl Write,
+) -> eyre::Result<()> {
+    let lowercase = name.to_lowercase();
+    let compression = COMPRESSED
+        .iter()
+        .find(|(extension, _)| lowercase.ends_with(extension))
+        .filter(|_| format_of_name(name).is_none());
+
+    match compression {
+        Some((_, compression)) => std::io::copy(&mut decoder(input, *compression)?, &mut output)
+            .with_context(|| format!("Decompressing {}", name))?,
+        None => std::io::copy(&mut input, &mut output)?,
+    };
+    Ok(())
+}
+
+/// Extract the `bin` entry from the downloaded `archive` into `output`, see [`format`] for how
+/// the format is chosen.
+///
+/// When no entry is named `bin` and it is a pattern, see [`entry_matcher`], the first file in
+/// archive order that matches it is extracted, so a version in the path does not break `bin`.
+pub fn extract_entry(
+    archive: &str,
+    mut input: impl Read + Seek,
+    bin: &str,
+    mut output: impl Write,
+) -> eyre::Result<()> {
+    let exact = match format(archive, &mut input)? {
+        Format::Tar(compression) => extract_tar(&mut input, compression, bin, &mut output),
+        Format::Zip => extract_zip(&mut input, bin, &mut output),
+    };
+    match exact {
+        Err(e) if code_of(&e) == Some(ErrorCode::EntryNotFound) && is_pattern(bin) => {
+            let matches = entry_matcher(bin)?;
+            input.rewind()?;
+            let path = find_files(archive, &mut input, matches)?
+                .into_iter()
+                .next()
+                .ok_or_else(|| {
+                    ErrorCode::EntryNotFound
+                        .error(format!("No file in the archive matches {}", bin))
+                })?;
+            input.rewind()?;
+            copy_file(archive, input, &path, output)
+        }
+        result => result,
+    }
+}
+
+/// Unpack every entry of `archive` into `destination`, dropping the first `strip_components`
+/// components of each path. Entries with no path left are skipped.
+///
+/// Returns how many files were written, entries reaching outside `destination` are refused.
+pub fn unpack(
+    archive: &str,
+    mut input: impl Read + Seek,
+    strip_components: usize,
+    destination: &Path,
+) -> eyre::Result<usize> {
+    match format(archive, &mut input)? {
+        Format::Tar(compression) => unpack_tar(input, compression, strip_components, destination),
+        Format::Zip => unpack_zip(input, strip_components, destination),
+    }
+}
+
+fn unpack_tar(
+    input: impl Read,
+    compression: Compression,
+    strip_components: usize,
+    destination: &Path,
+) -> eyre::Result<usize> {
+    let mut files = 0;
+    for entry in tar_archive(input, compression)?.entries()? {
+        let mut entry = entry?;
+        let Some(relative) = stripped(&entry.path()?, strip_components)? else {
+            continue;
+        };
+        let path = destination.join(&relative);
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_dir() {
+            std::fs::create_dir_all(&path)
+                .with_context(|| format!("Creating {}", path.display()))?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Creating {}", parent.display()))?;
+        }
+        if entry_type.is_hard_link() {
+            // The link names another entry of the archive, not a path on this machine
+            let link = entry
+                .link_name()?
+                .ok_or_else(|| eyre::eyre!("Hard link {} has no target", relative.display()))?;
+            let Some(target) = stripped(&link, strip_components)? else {
+                eyre::bail!(
+                    "Hard link {} points above the archive root",
+                    relative.display()
+                );
+            };
+            std::fs::hard_link(destination.join(target), &path)
+                .with_context(|| format!("Linking {}", path.display()))?;
+        } else {
+            entry
+                .unpack(&path)
+                .with_context(|| format!("Unpacking {}", relative.display()))?;
+        }
+        files += 1;
+    }
+    Ok(files)
+}
+
+fn unpack_zip(
+    input: impl Read + Seek,
+    strip_components: usize,
+    destination: &Path,
+) -> eyre::Result<usize> {
+    let mut archive = zip::ZipArchive::new(input)?;
+    let mut files = 0;
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        let Some(name) = entry.enclosed_name() else {
+            eyre::bail!("Entry {} reaches outside the archive", entry.name());
+        };
+        let Some(relative) = stripped(&name, strip_components)? else {
+            continue;
+        };
+        let path = destination.join(&relative);
+        if entry.is_dir() {
+            std::fs::create_dir_all(&path)
+                .with_context(|| format!("Creating {}", path.display()))?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Creating {}", parent.display()))?;
+        }
+        let mut file =
+            std::fs::File::create(&path).with_context(|| format!("Writing {}", path.display()))?;
+        std::io::copy(&mut entry, &mut file)
+            .with_context(|| format!("Unpacking {}", relative.display()))?;
+        if let Some(mode) = entry.unix_mode() {
+            file.set_permissions(std::fs::Permissions::from_mode(mode & 0o7777))?;
+        }
+        files += 1;
+    }
+    Ok(files)
+}
+
+/// `path` without its first `strip` components, `None` when nothing is left
+fn stripped(path: &Path, strip: usize) -> eyre::Result<Option<PathBuf>> {
+    let mut components = vec![];
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => components.push(part),
+            Component::CurDir => {}
+            _ => eyre::bail!("Entry {} reaches outside the archive", path.display()),
+        }
+    }
+    Ok(components
+        .get(strip..)
+        .filter(|rest| !rest.is_empty())
+        .map(|rest| rest.iter().collect()))
+}
+
+pub type EntryMatcher = Box<dyn Fn(&str) -> bool>;
+
+/// Marks a `bin` that is a regular expression rather than a path or glob
+const REGEX_PREFIX: &str = "regex:";
+
+fn is_pattern(bin: &str) -> bool {
+    bin.starts_with(REGEX_PREFIX) || bin.contains(['*', '?', '['])
+}
+
+/// Whether an archive path matches `pattern`: the regular expression after `regex:`, which may
+/// match any part of the path, or else a glob whose `*` stays within one directory. A leading
+/// `./` of the path is ignored.
+pub fn entry_matcher(pattern: &str) -> eyre::Result<EntryMatcher> {
+    let relative = |name: &str| name.strip_prefix("./").unwrap_or(name).to_string();
+    if let Some(regex) = pattern.strip_prefix(REGEX_PREFIX) {
+        let regex =
+            regex::Regex::new(regex).with_context(|| format!("Invalid entry regex {:?}", regex))?;
+        return Ok(Box::new(move |name| regex.is_match(&relative(name))));
+    }
+    let glob = glob::Pattern::new(pattern)
+        .with_context(|| format!("Invalid entry pattern {:?}", pattern))?;
+    let options = glob::MatchOptions {
+        require_literal_separator: true,
+        ..Default::default()
+    };
+    Ok(Box::new(move |name| {
+        glob.matches_with(&relative(name), options)
+    }))
+}
+
+/// The archive path of the only file matching the glob `pattern` that runs on `platform`. Only
+/// the executable header of each candidate is read, see [`crate::arch::detect`].
+pub fn find_for_platform(
+    archive: &str,
+    input: impl Read + Seek,
+    pattern: &str,
+    platform: Platform,
+) -> eyre::Result<String> {
+    let glob = glob::Pattern::new(pattern)
+        .with_context(|| format!("Invalid auto_arch_bin pattern {:?}", pattern))?;
+    let options = glob::MatchOptions {
+        require_literal_separator: true,
+        ..Default::default()
+    };
+    let matches = |name: &str| glob.matches_with(name.strip_prefix("./").unwrap_or(name), options);
+
+    let candidates = read_files(archive, input, matches, crate::arch::HEADER_LEN)?;
+    if candidates.is_empty() {
+        return Err(ErrorCode::EntryNotFound
+            .error(format!("No entry matches auto_arch_bin = {:?}", pattern)));
+    }
+
+    let detected: Vec<_> = candidates
+        .into_iter()
+        .map(|(path, header)| {
+            let platforms = crate::arch::detect(&header);
+            (path, platforms)
+        })
+        .collect();
+    let compatible: Vec<_> = detected
+        .iter()
+        .filter(|(_, platforms)| platforms.contains(&platform))
+        .collect();
+    if let [(path, _)] = compatible.as_slice() {
+        return Ok(path.clone());
+    }
+
+    let mut message = match compatible.len() {
+        0 => format!("No entry matching {:?} runs on {}", pattern, platform),
+        count => format!(
+            "{} entries matching {:?} run on {}, set bin to the right one",
+            count, pattern, platform
+        ),
+    };
+    message.push_str("\nCandidates:");
+    for (path, platforms) in &detected {
+        let platforms: Vec<_> = platforms.iter().map(|p| p.to_string()).collect();
+        let platforms = match platforms.is_empty() {
+            true => "not an executable".to_string(),
+            false => platforms.join(", "),
+        };
+        message.push_str(&format!("\n  {} ({})", path, platforms));
+    }
+    Err(ErrorCode::NoBinaryForPlatform.error(message))
+}
+
+/// Path and at most the first `limit` bytes of every regular file whose path satisfies
+/// `matches`, enough to tell what a file is without holding it in memory
+pub fn read_files(
+    archive: &str,
+    mut input: impl Read + Seek,
+    matches: impl Fn(&str) -> bool,
+    limit: u64,
+) -> eyre::Result<Vec<(String, Vec<u8>)>> {
+    match format(archive, &mut input)? {
+        Format::Tar(compression) => tar_files(input, compression, matches, limit),
+        Format::Zip => zip_files(input, matches, limit),
+    }
+}
+
+/// Path of every regular file whose path satisfies `matches`
+pub fn find_files(
+    archive: &str,
+    input: impl Read + Seek,
+    matches: impl Fn(&str) -> bool,
+) -> eyre::Result<Vec<String>> {
+    let files = read_files(archive, input, matches, 0)?;
+    Ok(files.into_iter().map(|(path, _)| path).collect())
+}
+
+/// Write the regular file at `path`, an archive path as [`find_files`] returns it, to `output`
+pub fn copy_file(
+    archive: &str,
+    mut input: impl Read + Seek,
+    path: &str,
+    mut output: impl Write,
+) -> eyre::Result<()> {
+    let not_found = || ErrorCode::EntryNotFound.error(format!("Entry {} not found", path));
+    if let Format::Tar(compression) = format(archive, &mut input)? {
+        for entry in tar_archive(input, compression)?.entries()? {
+            let mut entry = entry?;
+            if entry.header().entry_type().is_file() && entry.path()?.to_string_lossy() == path {
+                std::io::copy(&mut entry, &mut output)
+                    .with_context(|| format!("Extracting {}", path))?;
+                return Ok(());
+            }
+        }
+        Err(not_found())
+    } else {
+        let mut archive = zip::ZipArchive::new(input)?;
+        let mut entry = match archive.by_name(path) {
+            Ok(entry) if entry.is_file() => entry,
+            Ok(_) | Err(zip::result::ZipError::FileNotFound) => return Err(not_found()),
+            Err(e) => return Err(e.into()),
+        };
+        std::io::copy(&mut entry, &mut output).with_context(|| format!("Extracting {}", path))?;
+        Ok(())
+    }
+}
+
+/// An archive entry as `cat --list` shows it
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryInfo {
+    pub path: String,
+    pub size: u64,
+    /// Permission bits, zip archives made on Windows have none
+    pub mode: Option<u32>,
+    pub is_dir: bool,
+}
+
+/// Every entry in archive order, directories included
+pub fn list_entries(archive: &str, mut input: impl Read + Seek) -> eyre::Result<Vec<EntryInfo>> {
+    if let Format::Tar(compression) = format(archive, &mut input)? {
+        let mut entries = vec![];
+        for entry in tar_archive(input, compression)?.entries()? {
+            let entry = entry?;
+            let header = entry.header();
+            entries.push(EntryInfo {
+                path: entry.path()?.to_string_lossy().into_owned(),
+                size: header.size()?,
+                mode: header.mode().ok().map(|mode| mode & 0o7777),
+                is_dir: header.entry_type().is_dir(),
+            });
+        }
+        Ok(entries)
+    } else {
+        let mut archive = zip::ZipArchive::new(input)?;
+        let mut entries = vec![];
+        for index in 0..archive.len() {
+            let entry = archive.by_index(index)?;
+            entries.push(EntryInfo {
+                path: entry.name().to_string(),
+                size: entry.size(),
+                mode: entry.unix_mode().map(|mode| mode & 0o7777),
+                is_dir: entry.is_dir(),
+            });
+        }
+        Ok(entries)
+    }
+}
+
+/// The archive path of the single file named `entry`, or else of the single file matching it as
+/// a pattern, see [`entry_matcher`]. [`copy_file`] extracts it.
+pub fn find_single(
+    archive: &str,
+    mut input: impl Read + Seek,
+    entry: &str,
+) -> eyre::Result<String> {
+    let exact = find_files(archive, &mut input, |name| matches_bin(name, entry))?;
+    if let Some(path) = exact.into_iter().next() {
+        return Ok(path);
+    }
+
+    input.rewind()?;
+    let mut files = find_files(archive, input, entry_matcher(entry)?)?;
+    match files.len() {
+        0 => {
+            Err(ErrorCode::EntryNotFound.error(format!("No file in the archive matches {}", entry)))
+        }
+        1 => Ok(files.remove(0)),
+        count => {
+            let mut message = format!("{} files match {}, name one of them:", count, entry);
+            for path in files.iter().take(LISTED_CHILDREN) {
+                message.push_str(&format!("\n  {}", path));
+            }
+            if count > LISTED_CHILDREN {
+                message.push_str(&format!("\n  ... and {} more", count - LISTED_CHILDREN));
+            }
+            Err(eyre::eyre!(message))
+        }
+    }
+}
+
+fn tar_files(
+    input: impl Read,
+    compression: Compression,
+    matches: impl Fn(&str) -> bool,
+    limit: u64,
+) -> eyre::Result<Vec<(String, Vec<u8>)>> {
+    let mut files = vec![];
+    for entry in tar_archive(input, compression)?.entries()? {
+        let entry = entry?;
+        let path = entry.path()?.to_string_lossy().into_owned();
+        if entry.header().entry_type().is_file() && matches(&path) {
+            let mut data = vec![];
+            entry.take(limit).read_to_end(&mut data)?;
+            files.push((path, data));
+        }
+    }
+    Ok(files)
+}
+
+fn zip_files(
+    input: impl Read + Seek,
+    matches: impl Fn(&str) -> bool,
+    limit: u64,
+) -> eyre::Result<Vec<(String, Vec<u8>)>> {
+    let mut archive = zip::ZipArchive::new(input)?;
+    let mut files = vec![];
+    for index in 0..archive.len() {
+        let entry = archive.by_index(index)?;
+        if entry.is_file() && matches(entry.name()) {
+            let name = entry.name().to_string();
+            let mut data = vec![];
+            entry.take(limit).read_to_end(&mut data)?;
+            files.push((name, data));
+        }
+    }
+    Ok(files)
+}
+
+/// Whether the archive entry `entry_name` is the one configured as `bin`
+fn matches_bin(entry_name: &str, bin: &str) -> bool {
+    let entry_name = entry_name.strip_prefix("./").unwrap_or(entry_name);
+    let bin = bin.strip_prefix("./").unwrap_or(bin);
+
+    entry_name.trim_end_matches('/') == bin.trim_end_matches('/')
+}
+
+fn tar_archive<'a>(
+    input: impl Read + 'a,
+    compression: Compression,
+) -> eyre::Result<tar::Archive<Box<dyn Read + 'a>>> {
+    Ok(tar::Archive::new(decoder(input, compression)?))
+}
+
+/// `input` decompressed as it is read
+/// Round-trip a few bytes through every compression, to tell that the libraries workstation is
+/// built with work on this system
+pub fn self_test() -> Vec<(&'static str, eyre::Result<()>)> {
+    let data = b"workstation";
+    let compress = |compression| -> eyre::Result<Vec<u8>> {
+        Ok(match compression {
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+                std::io::Write::write_all(&mut encoder, data)?;
+                encoder.finish()?
+            }
+            Compression::Xz => {
+                let mut xz = vec![];
+                lzma_rs::xz_compress(&mut &data[..], &mut xz)?;
+                xz
+            }
+            Compression::Bzip2 => {
+                let mut encoder =
+                    bzip2::write::BzEncoder::new(vec![], bzip2::Compression::default());
+                std::io::Write::write_all(&mut encoder, data)?;
+                encoder.finish()?
+            }
+            Compression::Zstd => zstd::encode_all(&data[..], 0)?,
+        })
+    };
+    let round_trip = |compression| -> eyre::Result<()> {
+        let compressed = compress(compression)?;
+        let mut decompressed = vec![];
+        decoder(&compressed[..], compression)?.read_to_end(&mut decompressed)?;
+        if decompressed != data {
+            eyre::bail!("the data changed on the way");
+        }
+        Ok(())
+    };
+    [
+        ("gzip", Compression::Gzip),
+        ("xz", Compression::Xz),
+        ("bzip2", Compression::Bzip2),
+        ("zstd", Compression::Zstd),
+    ]
+    .into_iter()
+    .map(|(name, compression)| (name, round_trip(compression)))
+    .collect()
+}
+
+fn decoder<'a>(
+    input: impl Read + 'a,
+    compression: Compression,
+) -> eyre::Result<Box<dyn Read + 'a>> {
+    Ok(match compression {
+        Compression::Gzip => Box::new(flate2::read::GzDecoder::new(input)),
+        Compression::Bzip2 => Box::new(bzip2::read::BzDecoder::new(input)),
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(input)?),
+        // lzma-rs only decompresses into a writer, which is spooled to disk to keep memory flat
+        Compression::Xz => {
+            let mut spool = tempfile::tempfile()?;
+            lzma_rs::xz_decompress(&mut std::io::BufReader::new(input), &mut spool)
+                .map_err(|e| eyre::eyre!("Decompressing the xz archive: {}", e))?;
+            spool.rewind()?;
+            Box::new(spool)
+        }
+    })
+}
+
+fn extract_tar(
+    mut input: impl Read + Seek,
+    compression: Compression,
+    bin: &str,
+    mut output: impl Write,
+) -> eyre::Result<()> {
+    let mut archive = tar_archive(&mut input, compression)?;
+    let mut entry = archive
+        .entries()?
+        .find(|entry| {
+            let entry_name = entry
+                .as_ref()
+                .expect("entry exists")
+                .path()
+                .expect("entry has path");
+            let entry_name = entry_name.to_str().expect("entry path is string");
+
+            matches_bin(entry_name, bin)
+        })
+        .ok_or(ErrorCode::EntryNotFound.error(format!("Entry {} not found", bin)))
+        .with_context(|| "Searching for entry")??;
+
+    if entry.header().entry_type().is_dir() {
+        drop(entry);
+        drop(archive);
+        input.rewind()?;
+        let mut children = vec![];
+        for child in tar_archive(input, compression)?.entries()? {
+            let child = child?;
+            if !child.header().entry_type().is_file() {
+                continue;
+            }
+            let path = child.path()?.to_string_lossy().into_owned();
+            let executable = child.header().mode().is_ok_and(|mode| mode & 0o111 != 0);
+            children.push((path, executable));
+        }
+        return Err(directory_error(bin, children));
+    }
+
+    std::io::copy(&mut entry, &mut output).with_context(|| format!("Extracting {}", bin))?;
+
+    Ok(())
+}
+
+fn extract_zip(input: impl Read + Seek, bin: &str, mut output: impl Write) -> eyre::Result<()> {
+    let mut archive = zip::ZipArchive::new(input)?;
+
+    let directory = format!("{}/", bin.trim_end_matches('/'));
+    let is_dir = match archive.index_for_name(bin) {
+        Some(index) => archive.by_index(index)?.is_dir(),
+        None => archive.index_for_name(&directory).is_some(),
+    };
+    if is_dir {
+        let mut children = vec![];
+        for index in 0..archive.len() {
+            let child = archive.by_index(index)?;
+            if child.is_file() {
+                let executable = child.unix_mode().is_some_and(|mode| mode & 0o111 != 0);
+                children.push((child.name().to_string(), executable));
+            }
+        }
+        return Err(directory_error(bin, children));
+    }
+
+    let mut entry = match archive.by_name(bin) {
+        Ok(entry) => entry,
+        Err(zip::result::ZipError::FileNotFound) => {
+            return Err(ErrorCode::EntryNotFound.error(format!("Entry {} not found", bin)))
+                .with_context(|| "Searching for entry");
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    std::io::copy(&mut entry, &mut output).with_context(|| format!("Extracting {}", bin))?;
+
+    Ok(())
+}
+
+/// How many files of a directory are listed when `bin` points at it
+const LISTED_CHILDREN: usize = 10;
+
+/// `bin` matched a directory; `entries` are all files of the archive and whether they are executable
+fn directory_error(bin: &str, entries: Vec<(String, bool)>) -> eyre::Report {
+    let prefix = format!(
+        "{}/",
+        bin.strip_prefix("./").unwrap_or(bin).trim_end_matches('/')
+    );
+    let children: Vec<_> = entries
+        .into_iter()
+        .filter(|(path, _)| path.strip_prefix("./").unwrap_or(path).starts_with(&prefix))
+        .collect();
+
+    let mut message = format!("Entry {} is a directory, `bin` must point at a file", bin);
+    // Release archives usually contain a single executable next to docs and completions
+    let executables: Vec<_> = children.iter().filter(|(_, exec)| *exec).collect();
+    if let [(likely, _)] = executables.as_slice() {
+        message.push_str(&format!("\nDid you mean bin = {:?}?", likely));
+    }
+    if !children.is_empty() {
+        message.push_str("\nFiles in the directory:");
+        for (path, _) in children.iter().take(LISTED_CHILDREN) {
+            message.push_str(&format!("\n  {}", path));
+        }
+        if children.len() > LISTED_CHILDREN {
+            message.push_str(&format!(
+                "\n  ... and {} more",
+                children.len() - LISTED_CHILDREN
+            ));
+        }
+    }
+
+    ErrorCode::EntryIsDirectory.error(message)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::io::{Cursor, Write};
+
+    use crate::{arch::tests::elf, error::code_of};
+
+    use super::*;
+
+    pub fn tar_gz(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(&tar(entries)).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn tar(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+        for (path, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            if path.ends_with('/') {
+                header.set_entry_type(tar::EntryType::Directory);
+            }
+            header.set_size(data.len() as u64);
+            header.set_mode(if path.contains("README") {
+                0o644
+            } else {
+                0o755
+            });
+            header.set_cksum();
+            builder.append_data(&mut header, path, *data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    /// The `bin` entry of `archive` in memory, see [`extract_entry`]
+    fn extract(archive: &str, input: impl Read + Seek, bin: &str) -> eyre::Result<Vec<u8>> {
+        let mut data = vec![];
+        extract_entry(archive, input, bin, &mut data)?;
+        Ok(data)
+    }
+
+    fn decompress(name: &str, input: impl Read) -> eyre::Result<Vec<u8>> {
+        let mut data = vec![];
+        decompress_single(name, input, &mut data)?;
+        Ok(data)
+    }
+
+    pub fn zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(vec![]));
+        for (path, data) in entries {
+            if path.ends_with('/') {
+                writer
+                    .add_directory(*path, zip::write::SimpleFileOptions::default())
+                    .unwrap();
+                continue;
+            }
+            let mode = if path.contains("README") {
+                0o644
+            } else {
+                0o755
+            };
+            writer
+                .start_file(
+                    *path,
+                    zip::write::SimpleFileOptions::default().unix_permissions(mode),
+                )
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_extract_tar_gz_entry() {
+        let bytes = tar_gz(&[("./rg", b"binary")]);
+
+        let data = extract("rg.tar.gz", Cursor::new(bytes), "rg").unwrap();
+
+        assert_eq!(data, b"binary");
+    }
+
+    #[test]
+    fn test_missing_tar_entry_is_entry_not_found() {
+        let bytes = tar_gz(&[("ripgrep/rg", b"binary")]);
+
+        let error = extract("rg.tar.gz", Cursor::new(bytes), "rg").unwrap_err();
+
+        assert_eq!(code_of(&error), Some(ErrorCode::EntryNotFound));
+    }
+
+    #[test]
+    fn test_missing_zip_entry_is_entry_not_found() {
+        let bytes = zip(&[("yazi/yazi", b"binary")]);
+
+        let error = extract("yazi.zip", Cursor::new(bytes), "yazi").unwrap_err();
+
+        assert_eq!(code_of(&error), Some(ErrorCode::EntryNotFound));
+    }
+
+    #[test]
+    fn test_unknown_extension_is_unsupported_archive() {
+        let error = extract("tool.rar", Cursor::new(b"Rar!".to_vec()), "tool").unwrap_err();
+
+        assert_eq!(code_of(&error), Some(ErrorCode::UnsupportedArchive));
+    }
+
+    #[test]
+    fn test_every_tar_compression_is_extracted() {
+        let tar = tar(&[("zellij/zellij", b"binary")]);
+        let mut xz = vec![];
+        lzma_rs::xz_compress(&mut &tar[..], &mut xz).unwrap();
+        let mut bzip2 = bzip2::write::BzEncoder::new(vec![], bzip2::Compression::default());
+        bzip2.write_all(&tar).unwrap();
+        let archives = [
+            ("zellij.tar.xz", xz),
+            ("zellij.tar.bz2", bzip2.finish().unwrap()),
+            ("zellij.tar.zst", zstd::encode_all(&tar[..], 0).unwrap()),
+            ("zellij.tgz", tar_gz(&[("zellij/zellij", b"binary")])),
+        ];
+
+        for (name, bytes) in archives {
+            assert!(is_supported(name), "{}", name);
+            assert_eq!(
+                extract(name, Cursor::new(bytes.clone()), "zellij/zellij").unwrap(),
+                b"binary",
+                "{}",
+                name
+            );
+            // Without a known extension the content gives the format away
+            assert_eq!(
+                extract("download?id=1", Cursor::new(bytes), "zellij/zellij").unwrap(),
+                b"binary",
+                "{}",
+                name
+            );
+        }
+        let zipped = zip(&[("zellij", b"binary")]);
+        assert_eq!(
+            extract("asset", Cursor::new(zipped), "zellij").unwrap(),
+            b"binary"
+        );
+    }
+
+    #[test]
+    fn test_bin_pattern_survives_a_version_in_the_path() {
+        let bytes = tar_gz(&[
+            ("ripgrep-14.1.0-x86_64/README.md", b"docs"),
+            ("ripgrep-14.1.0-x86_64/rg", b"rg 14"),
+            ("ripgrep-14.1.0-x86_64/complete/rg.bash", b"complete"),
+        ]);
+        let zipped = zip(&[
+            ("ripgrep-14.1.0-x86_64/doc/rg.1", b"man"),
+            ("ripgrep-14.1.0-x86_64/rg", b"rg 14"),
+        ]);
+
+        for bin in ["ripgrep-*/rg", r"regex:^ripgrep-[\d.]+-x86_64/rg$"] {
+            assert_eq!(
+                extract("rg.tar.gz", Cursor::new(bytes.clone()), bin).unwrap(),
+                b"rg 14"
+            );
+            assert_eq!(
+                extract("rg.zip", Cursor::new(zipped.clone()), bin).unwrap(),
+                b"rg 14"
+            );
+        }
+        // The first match in archive order wins
+        assert_eq!(
+            extract("rg.tar.gz", Cursor::new(bytes.clone()), "*/*").unwrap(),
+            b"docs"
+        );
+        let error = extract("rg.tar.gz", Cursor::new(bytes.clone()), "fd-*/fd").unwrap_err();
+        assert_eq!(code_of(&error), Some(ErrorCode::EntryNotFound));
+        assert!(extract("rg.tar.gz", Cursor::new(bytes), "regex:(").is_err());
+    }
+
+    #[test]
+    fn test_unpack_strips_leading_components() {
+        let directory = tempfile::tempdir().unwrap();
+        let entries: &[(&str, &[u8])] = &[
+            ("node-v20/", b""),
+            ("node-v20/bin/node", b"node"),
+            ("node-v20/lib/README", b"readme"),
+        ];
+
+        let tar = directory.path().join("tar");
+        assert_eq!(
+            unpack("node.tar.gz", Cursor::new(tar_gz(entries)), 1, &tar).unwrap(),
+            2
+        );
+        let zip_dir = directory.path().join("zip");
+        assert_eq!(
+            unpack("node.zip", Cursor::new(zip(entries)), 1, &zip_dir).unwrap(),
+            2
+        );
+
+        for unpacked in [tar, zip_dir] {
+            assert_eq!(std::fs::read(unpacked.join("bin/node")).unwrap(), b"node");
+            let mode = std::fs::metadata(unpacked.join("bin/node"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o755);
+            assert_eq!(
+                std::fs::read(unpacked.join("lib/README")).unwrap(),
+                b"readme"
+            );
+        }
+        assert!(stripped(Path::new("a/../../etc/passwd"), 0).is_err());
+        assert_eq!(stripped(Path::new("./a/b"), 2).unwrap(), None);
+    }
+
+    #[test]
+    fn test_single_compressed_files_are_decompressed() {
+        let mut gzip = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        gzip.write_all(b"binary").unwrap();
+        let gzip = gzip.finish().unwrap();
+
+        assert_eq!(
+            decompress("foo-linux-amd64.gz", Cursor::new(gzip.clone())).unwrap(),
+            b"binary"
+        );
+        let zstd = zstd::encode_all(&b"binary"[..], 0).unwrap();
+        assert_eq!(decompress("foo.ZST", Cursor::new(zstd)).unwrap(), b"binary");
+        // Archives and plain files are left to the caller
+        assert_eq!(
+            decompress("foo.tar.gz", Cursor::new(gzip.clone())).unwrap(),
+            gzip
+        );
+        assert_eq!(
+            decompress("foo", Cursor::new(b"binary".to_vec())).unwrap(),
+            b"binary"
+        );
+        assert!(decompress("foo.gz", Cursor::new(b"binary".to_vec())).is_err());
+    }
+
+    #[test]
+    fn test_tar_directory_entry_is_rejected() {
+        let bytes = tar_gz(&[
+            ("ripgrep/", b""),
+            ("ripgrep/README.md", b"docs"),
+            ("ripgrep/rg", b"binary"),
+        ]);
+
+        let error = extract("rg.tar.gz", Cursor::new(bytes), "ripgrep/").unwrap_err();
+
+        assert_eq!(code_of(&error), Some(ErrorCode::EntryIsDirectory));
+        let message = format!("{:?}", error);
+        assert!(message.contains("Did you mean bin = \"ripgrep/rg\"?"));
+        assert!(message.contains("ripgrep/README.md"));
+    }
+
+    #[test]
+    fn test_zip_directory_entry_is_rejected() {
+        let bytes = zip(&[
+            ("yazi/", b""),
+            ("yazi/README.md", b"docs"),
+            ("yazi/yazi", b"binary"),
+        ]);
+
+        let error = extract("yazi.zip", Cursor::new(bytes), "yazi").unwrap_err();
+
+        assert_eq!(code_of(&error), Some(ErrorCode::EntryIsDirectory));
+        assert!(format!("{:?}", error).contains("Did you mean bin = \"yazi/yazi\"?"));
+    }
+
+    const LINUX_X86_64: Platform = Platform {
+        os: "linux",
+        arch: "x86_64",
+    };
+
+    #[test]
+    fn test_platform_binary_is_selected_by_header() {
+        let amd64 = elf(goblin::elf::header::EM_X86_64);
+        let arm64 = elf(goblin::elf::header::EM_AARCH64);
+        let bytes = tar_gz(&[
+            ("./tool-linux-arm64", &arm64),
+            ("./tool-linux-amd64", &amd64),
+            ("./README.md", b"docs"),
+        ]);
+
+        let path = find_for_platform(
+            "tool.tar.gz",
+            Cursor::new(&bytes),
+            "tool-linux-*",
+            LINUX_X86_64,
+        )
+        .unwrap();
+
+        assert_eq!(path, "tool-linux-amd64");
+        let mut data = vec![];
+        copy_file("tool.tar.gz", Cursor::new(&bytes), &path, &mut data).unwrap();
+        assert_eq!(data, amd64);
+    }
+
+    #[test]
+    fn test_no_compatible_binary_lists_candidates() {
+        let arm64 = elf(goblin::elf::header::EM_AARCH64);
+        let bytes = zip(&[
+            ("tool-linux-arm64", &arm64),
+            ("tool-linux.sh", b"#!/bin/sh"),
+        ]);
+
+        let error = find_for_platform("tool.zip", Cursor::new(bytes), "tool-linux*", LINUX_X86_64)
+            .unwrap_err();
+
+        assert_eq!(code_of(&error), Some(ErrorCode::NoBinaryForPlatform));
+        let message = format!("{:?}", error);
+        assert!(message.contains("tool-linux-arm64 (linux aarch64)"));
+        assert!(message.contains("tool-linux.sh (not an executable)"));
+    }
+
+    #[test]
+    fn test_several_compatible_binaries_are_ambiguous() {
+        let amd64 = elf(goblin::elf::header::EM_X86_64);
+        let bytes = tar_gz(&[("tool-linux-amd64", &amd64), ("tool-linux-x86_64", &amd64)]);
+
+        let error = find_for_platform("tool.tar.gz", Cursor::new(bytes), "tool-*", LINUX_X86_64)
+            .unwrap_err();
+
+        assert_eq!(code_of(&error), Some(ErrorCode::NoBinaryForPlatform));
+        assert!(format!("{:?}", error).contains("2 entries matching"));
+    }
+
+    #[test]
+    fn test_list_entries_has_sizes_and_modes() {
+        let bytes = tar_gz(&[
+            ("tool/", b""),
+            ("tool/README.md", b"docs"),
+            ("tool/bin", b"binary"),
+        ]);
+
+        let entries = list_entries("tool.tar.gz", Cursor::new(bytes)).unwrap();
+
+        assert_eq!(
+            entries,
+            vec![
+                EntryInfo {
+                    path: "tool/".to_string(),
+                    size: 0,
+                    mode: Some(0o755),
+                    is_dir: true
+                },
+                EntryInfo {
+                    path: "tool/README.md".to_string(),
+                    size: 4,
+                    mode: Some(0o644),
+                    is_dir: false
+                },
+                EntryInfo {
+                    path: "tool/bin".to_string(),
+                    size: 6,
+                    mode: Some(0o755),
+                    is_dir: false
+                },
+            ]
+        );
+        let zipped =
+            list_entries("tool.zip", Cursor::new(zip(&[("tool/README.md", b"docs")]))).unwrap();
+        assert_eq!(zipped[0].mode, Some(0o644));
+    }
+
+    #[test]
+    fn test_find_single_by_name_or_glob() {
+        let bytes = zip(&[
+            ("tool/README.md", b"docs"),
+            ("tool/LICENSE-MIT", b"mit"),
+            ("tool/LICENSE-APACHE", b"apache"),
+        ]);
+
+        let path = find_single("tool.zip", Cursor::new(&bytes), "./tool/README.md").unwrap();
+        assert_eq!(path, "tool/README.md");
+        let mut data = vec![];
+        copy_file("tool.zip", Cursor::new(&bytes), &path, &mut data).unwrap();
+        assert_eq!(data, b"docs");
+        let path = find_single("tool.zip", Cursor::new(&bytes), "*/README*").unwrap();
+        assert_eq!(path, "tool/README.md");
+
+        let ambiguous = find_single("tool.zip", Cursor::new(&bytes), "tool/LICENSE*").unwrap_err();
+        assert!(format!("{:?}", ambiguous).contains("2 files match tool/LICENSE*"));
+        let missing = find_single("tool.zip", Cursor::new(&bytes), "README.md").unwrap_err();
+        assert_eq!(code_of(&missing), Some(ErrorCode::EntryNotFound));
+    }
+}
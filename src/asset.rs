@@ -0,0 +1,82 @@
+use std::{
+    fs::File,
+    io::{Seek, Write},
+    path::Path,
+};
+
+use eyre::Context;
+
+/// A fetched package asset, kept on disk rather than in memory: a local archive read in place,
+/// or a download spooled to an unnamed temporary file that is gone once the asset is dropped.
+///
+/// Archives are read from it as a stream, so memory stays flat however large the asset is.
+#[derive(Debug)]
+pub struct Asset {
+    file: File,
+    len: u64,
+}
+
+impl Asset {
+    pub fn open(path: &Path) -> eyre::Result<Self> {
+        let file = File::open(path).with_context(|| format!("Reading {}", path.display()))?;
+        Self::from_file(file)
+    }
+
+    /// The asset written to `file`, which is read from its start
+    pub fn from_file(mut file: File) -> eyre::Result<Self> {
+        file.rewind()?;
+        let len = file.metadata()?.len();
+        Ok(Asset { file, len })
+    }
+
+    /// An asset that was built in memory, such as one rebuilt from a delta
+    pub fn from_bytes(bytes: &[u8]) -> eyre::Result<Self> {
+        let mut file = tempfile::tempfile()?;
+        file.write_all(bytes)?;
+        Self::from_file(file)
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// The content from its start, every call reads it anew
+    pub fn reader(&self) -> eyre::Result<&File> {
+        let mut file = &self.file;
+        file.rewind()?;
+        Ok(file)
+    }
+
+    /// `sha256:<hex>` of the content, read as a stream
+    pub fn hash(&self) -> eyre::Result<String> {
+        Ok(crate::digest::sha256_reader(self.reader()?)?)
+    }
+
+    /// The whole content in memory, only tests look at an asset in one piece
+    #[cfg(test)]
+    pub fn read(&self) -> eyre::Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(self.len as usize);
+        std::io::Read::read_to_end(&mut self.reader()?, &mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn test_asset_is_read_from_the_start_every_time() {
+        let asset = Asset::from_bytes(b"archive").unwrap();
+
+        let mut start = [0; 4];
+        asset.reader().unwrap().read_exact(&mut start).unwrap();
+
+        assert_eq!(&start, b"arch");
+        assert_eq!(asset.read().unwrap(), b"archive");
+        assert_eq!(asset.len(), 7);
+        assert_eq!(asset.hash().unwrap(), crate::digest::sha256(b"archive"));
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+use eyre::Context;
+
+use crate::{digest, manifest::Installed};
+
+const BACKUP_DIR: &str = "previous";
+
+/// The file each package had before `setup` last replaced it, kept in the state directory as
+/// `previous/<name>` with its manifest record next to it, so that `rollback` can put it back.
+///
+/// Only the package's own file is kept, not the other files of an archive package. Like the
+/// manifest, backups taken during a run are staged and only kept for packages that installed.
+pub struct Backups {
+    directory: PathBuf,
+    /// Copies taken during this run, by package
+    staged: Mutex<BTreeMap<String, Staged>>,
+}
+
+struct Staged {
+    file: tempfile::NamedTempFile,
+    record: Option<Installed>,
+}
+
+/// A kept previous version
+#[derive(Debug)]
+pub struct Previous {
+    /// The kept file, in the state directory
+    pub path: PathBuf,
+    /// What the manifest recorded for it, `None` when workstation had not installed it
+    pub record: Option<Installed>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl Backups {
+    pub fn new(state_dir: &Path) -> Self {
+        Backups {
+            directory: state_dir.join(BACKUP_DIR),
+            staged: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Copy the file of package `name` at `path` before content hashing to `hash` replaces it.
+    /// Nothing is kept when there is no file yet or it already holds that content, so
+    /// reinstalling never loses the previous version.
+    pub fn keep(
+        &self,
+        name: &str,
+        path: &Path,
+        hash: &str,
+        record: Option<Installed>,
+    ) -> eyre::Result<()> {
+        let open = || std::fs::File::open(path);
+        let current = match open().and_then(digest::sha256_reader) {
+            Ok(current) => current,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e).with_context(|| format!("Reading {}", path.display())),
+        };
+        if current == hash {
+            return Ok(());
+        }
+        std::fs::create_dir_all(&self.directory)
+            .with_context(|| format!("Creating {}", self.directory.display()))?;
+        let mut file = tempfile::NamedTempFile::new_in(&self.directory)?;
+        open()
+            .and_then(|mut current| std::io::copy(&mut current, &mut file))
+            .with_context(|| format!("Backing up {}", path.display()))?;
+        lock(&self.staged).insert(name.to_string(), Staged { file, record });
+        Ok(())
+    }
+
+    /// Keep the copies staged for `installed`, replacing their earlier backups, and drop the
+    /// others
+    pub fn commit(&self, installed: &[String]) -> eyre::Result<()> {
+        let staged = std::mem::take(&mut *lock(&self.staged));
+        for (name, staged) in staged {
+            if !installed.contains(&name) {
+                continue;
+            }
+            let path = self.directory.join(&name);
+            staged
+                .file
+                .persist(&path)
+                .with_context(|| format!("Writing {}", path.display()))?;
+            let record = serde_json::to_string_pretty(&staged.record).expect("record serializes");
+            let record_path = record_path(&path);
+            std::fs::write(&record_path, record + "\n")
+                .with_context(|| format!("Writing {}", record_path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// The previous version of package `name`, if one is kept
+    pub fn get(&self, name: &str) -> eyre::Result<Option<Previous>> {
+        let path = self.directory.join(name);
+        let hash = match std::fs::File::open(&path).and_then(digest::sha256_reader) {
+            Ok(hash) => hash,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Reading {}", path.display())),
+        };
+        // A record that does not match the file is worse than none
+        let record = std::fs::read(record_path(&path))
+            .ok()
+            .and_then(|json| serde_json::from_slice::<Option<Installed>>(&json).ok())
+            .flatten()
+            .filter(|record| record.hash == hash);
+        Ok(Some(Previous { path, record }))
+    }
+
+    /// Forget the previous version of an uninstalled package
+    pub fn remove(&self, name: &str) {
+        let path = self.directory.join(name);
+        let _ = std::fs::remove_file(record_path(&path));
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+fn record_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().expect("backup has a name").to_os_string();
+    name.push(".json");
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_replaced_files_of_installed_packages_are_kept() {
+        let directory = tempfile::tempdir().unwrap();
+        let location = directory.path().join("bin");
+        std::fs::create_dir(&location).unwrap();
+        for name in ["rg", "fd", "bat"] {
+            std::fs::write(location.join(name), format!("{} 1", name)).unwrap();
+        }
+        let backups = Backups::new(directory.path());
+        let record = Installed {
+            path: location.join("rg"),
+            hash: digest::sha256(b"rg 1"),
+            source: Some("https://example.com/rg-1".to_string()),
+            fields_hash: None,
+            files: vec![],
+            installed_at: None,
+        };
+
+        backups
+            .keep(
+                "rg",
+                &location.join("rg"),
+                &digest::sha256(b"rg 2"),
+                Some(record.clone()),
+            )
+            .unwrap();
+        backups
+            .keep("fd", &location.join("fd"), &digest::sha256(b"fd 1"), None)
+            .unwrap();
+        backups
+            .keep(
+                "bat",
+                &location.join("bat"),
+                &digest::sha256(b"bat 2"),
+                None,
+            )
+            .unwrap();
+        backups
+            .keep(
+                "new",
+                &location.join("new"),
+                &digest::sha256(b"new 1"),
+                None,
+            )
+            .unwrap();
+        // bat failed to install
+        backups
+            .commit(&["rg", "fd", "new"].map(str::to_string))
+            .unwrap();
+
+        let previous = backups.get("rg").unwrap().unwrap();
+        assert_eq!(std::fs::read(&previous.path).unwrap(), b"rg 1");
+        assert_eq!(previous.record, Some(record));
+        for name in ["fd", "bat", "new"] {
+            assert!(backups.get(name).unwrap().is_none(), "{}", name);
+        }
+
+        backups.remove("rg");
+        assert!(backups.get("rg").unwrap().is_none());
+    }
+}
@@ -0,0 +1,193 @@
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+/// Default for `[workstation.download] memory_budget`
+pub const DEFAULT_LIMIT: u64 = 512 * 1024 * 1024;
+
+/// Bytes that packages installing at the same time may hold in memory together.
+///
+/// Every worker reserves its estimated footprint before it allocates it, and waits while the
+/// reservation would exceed the limit. A footprint larger than the whole budget still runs, but
+/// only once nothing else holds memory.
+pub struct Budget {
+    limit: u64,
+    state: Mutex<State>,
+    freed: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    used: u64,
+    /// The most ever in use at once
+    peak: u64,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl Budget {
+    pub fn new(limit: u64) -> Arc<Self> {
+        Arc::new(Budget {
+            limit,
+            state: Mutex::default(),
+            freed: Condvar::new(),
+        })
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    pub fn in_use(&self) -> u64 {
+        lock(&self.state).used
+    }
+
+    #[cfg(test)]
+    fn peak(&self) -> u64 {
+        lock(&self.state).peak
+    }
+
+    /// An empty reservation for one package. `factor` multiplies every size it is grown to,
+    /// 0 for packages that never hold more than a buffer in memory.
+    pub fn reservation(self: &Arc<Self>, factor: u64) -> Reservation {
+        Reservation {
+            budget: self.clone(),
+            factor,
+            held: Mutex::new(0),
+        }
+    }
+
+    fn release(&self, bytes: u64) {
+        if bytes == 0 {
+            return;
+        }
+        lock(&self.state).used -= bytes;
+        self.freed.notify_all();
+    }
+}
+
+/// Memory held by one package, released when dropped
+pub struct Reservation {
+    budget: Arc<Budget>,
+    factor: u64,
+    held: Mutex<u64>,
+}
+
+impl Reservation {
+    /// Hold at least `size` times the factor, waiting until the budget has room. `waiting` is
+    /// called once before blocking, with the bytes in use and the limit.
+    ///
+    /// A reservation that has to grow gives back what it holds before waiting, so a waiting
+    /// worker never holds memory others wait for.
+    pub fn grow(&self, size: u64, waiting: impl FnOnce(u64, u64)) {
+        let bytes = size.saturating_mul(self.factor);
+        let mut held = lock(&self.held);
+        if *held >= bytes {
+            return;
+        }
+        self.budget.release(std::mem::take(&mut *held));
+
+        let budget = &self.budget;
+        let mut state = lock(&budget.state);
+        let fits = |state: &State| state.used == 0 || state.used + bytes <= budget.limit;
+        if !fits(&state) {
+            waiting(state.used, budget.limit);
+            state = budget
+                .freed
+                .wait_while(state, |state| !fits(state))
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        state.used += bytes;
+        state.peak = state.peak.max(state.used);
+        *held = bytes;
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.budget.release(*lock(&self.held));
+    }
+}
+
+/// `12.3 MB`, for progress messages
+pub fn format_bytes(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / 1_000_000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_budget_is_never_exceeded() {
+        let budget = Budget::new(100);
+        let waited = Arc::new(AtomicUsize::new(0));
+        let sizes = [10, 60, 30, 45, 5, 80, 25, 50, 15, 70, 35, 20];
+
+        let handles: Vec<_> = sizes
+            .into_iter()
+            .enumerate()
+            .map(|(index, size)| {
+                let budget = budget.clone();
+                let waited = waited.clone();
+                std::thread::spawn(move || {
+                    // Archives count twice
+                    let factor = if index % 3 == 0 { 2 } else { 1 };
+                    let reservation = budget.reservation(factor);
+                    reservation.grow(size / factor, |used, limit| {
+                        assert!(used <= limit);
+                        waited.fetch_add(1, Ordering::Relaxed);
+                    });
+                    assert!(budget.in_use() <= budget.limit());
+                    std::thread::sleep(Duration::from_millis(5));
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert!(budget.peak() <= 100, "peak {}", budget.peak());
+        assert!(waited.load(Ordering::Relaxed) > 0);
+        assert_eq!(budget.in_use(), 0);
+    }
+
+    #[test]
+    fn test_oversized_footprint_runs_alone() {
+        let budget = Budget::new(100);
+        let small = budget.reservation(1);
+        small.grow(40, |_, _| panic!("fits"));
+
+        let waiting = budget.clone();
+        let handle = std::thread::spawn(move || {
+            let big = waiting.reservation(1);
+            big.grow(250, |used, _| assert_eq!(used, 40));
+            waiting.in_use()
+        });
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(budget.in_use(), 40);
+        drop(small);
+
+        assert_eq!(handle.join().unwrap(), 250);
+        assert_eq!(budget.in_use(), 0);
+    }
+
+    #[test]
+    fn test_growing_again_replaces_the_reservation() {
+        let budget = Budget::new(100);
+        let reservation = budget.reservation(2);
+
+        reservation.grow(10, |_, _| {});
+        reservation.grow(5, |_, _| {});
+        assert_eq!(budget.in_use(), 20);
+        reservation.grow(30, |_, _| {});
+        assert_eq!(budget.in_use(), 60);
+    }
+}
@@ -0,0 +1,268 @@
+use std::path::{Path, PathBuf};
+
+use eyre::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{asset::Asset, digest, download::Validators};
+
+/// Downloaded assets stored by content hash, under the cache directory.
+///
+/// Assets downloaded without `pin_hash` are found by URL too, as long as the server still
+/// answers with the validators it sent for the stored copy.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    directory: PathBuf,
+}
+
+impl Cache {
+    pub fn new(cache_dir: &Path) -> Self {
+        Cache {
+            directory: cache_dir.join("assets"),
+        }
+    }
+
+    fn path(&self, hash: &str) -> eyre::Result<PathBuf> {
+        Ok(self.directory.join("sha256").join(digest::validate(hash)?))
+    }
+
+    /// Whether an asset with `hash` is stored
+    pub fn contains(&self, hash: &str) -> bool {
+        self.path(hash).is_ok_and(|path| path.is_file())
+    }
+
+    /// The stored asset with `hash`, `None` if it is missing or was corrupted on disk
+    pub fn get(&self, hash: &str) -> eyre::Result<Option<Vec<u8>>> {
+        let path = self.path(hash)?;
+        let bytes = match std::fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Reading {}", path.display())),
+        };
+        if digest::sha256(&bytes) != hash {
+            let _ = std::fs::remove_file(&path);
+            return Ok(None);
+        }
+        Ok(Some(bytes))
+    }
+
+    /// The asset last remembered for `key` with [`Cache::set_latest`], such as the previous
+    /// release of a package
+    pub fn latest(&self, key: &str) -> eyre::Result<Option<Vec<u8>>> {
+        let hash = match std::fs::read_to_string(self.latest_path(key)) {
+            Ok(hash) => hash,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Reading the latest {}", key)),
+        };
+        match digest::validate(hash.trim()) {
+            Ok(_) => self.get(hash.trim()),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Remember the stored asset with `hash` as the latest one for `key`
+    pub fn set_latest(&self, key: &str, hash: &str) -> eyre::Result<()> {
+        let path = self.latest_path(key);
+        let directory = path.parent().expect("latest path has a parent");
+        std::fs::create_dir_all(directory)
+            .with_context(|| format!("Creating {}", directory.display()))?;
+        std::fs::write(&path, format!("{}\n", hash))
+            .with_context(|| format!("Writing {}", path.display()))
+    }
+
+    fn latest_path(&self, key: &str) -> PathBuf {
+        self.directory.join("latest").join(key)
+    }
+
+    /// The hash of the asset last downloaded from `url`, with the validators of that download
+    pub fn for_url(&self, url: &str) -> eyre::Result<Option<(String, Validators)>> {
+        let path = self.url_path(url);
+        let json = match std::fs::read_to_string(&path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Reading {}", path.display())),
+        };
+        // An unreadable entry is a miss, the next download replaces it
+        Ok(serde_json::from_str::<UrlEntry>(&json)
+            .ok()
+            .map(|entry| (entry.hash, entry.validators)))
+    }
+
+    /// Remember that `url` served the stored asset with `hash`, sent with `validators`
+    pub fn remember_url(&self, url: &str, hash: &str, validators: &Validators) -> eyre::Result<()> {
+        let path = self.url_path(url);
+        let directory = path.parent().expect("url path has a parent");
+        std::fs::create_dir_all(directory)
+            .with_context(|| format!("Creating {}", directory.display()))?;
+        let entry = UrlEntry {
+            hash: hash.to_string(),
+            validators: validators.clone(),
+        };
+        std::fs::write(
+            &path,
+            serde_json::to_string(&entry).expect("url entry serializes") + "\n",
+        )
+        .with_context(|| format!("Writing {}", path.display()))
+    }
+
+    /// Named by the hash of the URL, which may hold secrets and never lands on disk as is
+    fn url_path(&self, url: &str) -> PathBuf {
+        let key = digest::sha256(url.as_bytes());
+        let hex = digest::validate(&key).expect("sha256 digest");
+        self.directory.join("urls").join(hex)
+    }
+
+    /// The stored asset with `hash` as an [`Asset`], read in place and never loaded into
+    /// memory as a whole
+    pub fn open(&self, hash: &str) -> eyre::Result<Option<Asset>> {
+        let path = self.path(hash)?;
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let asset = Asset::open(&path)?;
+        if asset.hash()? != hash {
+            let _ = std::fs::remove_file(&path);
+            return Ok(None);
+        }
+        Ok(Some(asset))
+    }
+
+    /// Store the content of `asset`, returning its hash
+    pub fn put(&self, asset: &Asset) -> eyre::Result<String> {
+        let hash = asset.hash()?;
+        let path = self.path(&hash)?;
+        let directory = path.parent().expect("asset path has a parent");
+        std::fs::create_dir_all(directory)
+            .with_context(|| format!("Creating {}", directory.display()))?;
+
+        // Write under a temporary name so a crash never leaves a truncated asset behind
+        let mut file = tempfile::NamedTempFile::new_in(directory)?;
+        std::io::copy(&mut asset.reader()?, &mut file)?;
+        file.persist(&path)
+            .with_context(|| format!("Writing {}", path.display()))?;
+
+        Ok(hash)
+    }
+
+    /// Remove every stored asset, returning how many bytes they took
+    pub fn clear(&self) -> eyre::Result<u64> {
+        let bytes = size(&self.directory)
+            .with_context(|| format!("Reading {}", self.directory.display()))?;
+        match std::fs::remove_dir_all(&self.directory) {
+            Ok(()) => Ok(bytes),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e).with_context(|| format!("Removing {}", self.directory.display())),
+        }
+    }
+}
+
+/// Total size of the files below `directory`, nothing when it does not exist
+fn size(directory: &Path) -> std::io::Result<u64> {
+    let entries = match std::fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut total = 0;
+    for entry in entries {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        total += match metadata.is_dir() {
+            true => size(&entry.path())?,
+            false => metadata.len(),
+        };
+    }
+    Ok(total)
+}
+
+#[derive(Serialize, Deserialize)]
+struct UrlEntry {
+    hash: String,
+    #[serde(flatten)]
+    validators: Validators,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assets_are_found_by_hash() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache = Cache::new(directory.path());
+
+        let hash = cache.put(&Asset::from_bytes(b"binary").unwrap()).unwrap();
+
+        assert!(cache.contains(&hash));
+        assert_eq!(cache.get(&hash).unwrap(), Some(b"binary".to_vec()));
+        assert_eq!(cache.get(&digest::sha256(b"other")).unwrap(), None);
+
+        let asset = cache.open(&hash).unwrap().unwrap();
+        assert_eq!(asset.read().unwrap(), b"binary");
+        assert!(cache.open(&digest::sha256(b"other")).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_clearing_removes_every_asset() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache = Cache::new(directory.path());
+        let hash = cache.put(&Asset::from_bytes(b"binary").unwrap()).unwrap();
+        cache.set_latest("rg", &hash).unwrap();
+
+        assert!(cache.clear().unwrap() >= 6);
+        assert!(!cache.contains(&hash));
+        assert_eq!(cache.latest("rg").unwrap(), None);
+        assert_eq!(cache.clear().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_latest_asset_per_key() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache = Cache::new(directory.path());
+        assert_eq!(cache.latest("sdk").unwrap(), None);
+
+        let old = cache.put(&Asset::from_bytes(b"sdk 1").unwrap()).unwrap();
+        cache.set_latest("sdk", &old).unwrap();
+        let new = cache.put(&Asset::from_bytes(b"sdk 2").unwrap()).unwrap();
+        cache.set_latest("sdk", &new).unwrap();
+
+        assert_eq!(cache.latest("sdk").unwrap(), Some(b"sdk 2".to_vec()));
+        assert_eq!(cache.latest("other").unwrap(), None);
+    }
+
+    #[test]
+    fn test_corrupted_asset_is_a_miss() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache = Cache::new(directory.path());
+        let hash = cache.put(&Asset::from_bytes(b"binary").unwrap()).unwrap();
+        std::fs::write(cache.path(&hash).unwrap(), b"bit rot").unwrap();
+
+        assert_eq!(cache.get(&hash).unwrap(), None);
+        assert!(!cache.contains(&hash));
+    }
+
+    #[test]
+    fn test_assets_are_found_by_url() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache = Cache::new(directory.path());
+        let url = "https://example.com/rg.tar.gz?token=secret";
+        let validators = Validators {
+            etag: Some("\"v14\"".to_string()),
+            last_modified: None,
+        };
+        assert_eq!(cache.for_url(url).unwrap(), None);
+
+        let hash = cache.put(&Asset::from_bytes(b"rg 14").unwrap()).unwrap();
+        cache.remember_url(url, &hash, &validators).unwrap();
+
+        assert_eq!(cache.for_url(url).unwrap(), Some((hash, validators)));
+        assert_eq!(
+            cache.for_url("https://example.com/fd.tar.gz").unwrap(),
+            None
+        );
+        let stored = std::fs::read_dir(directory.path().join("assets/urls"))
+            .unwrap()
+            .map(|entry| std::fs::read_to_string(entry.unwrap().path()).unwrap())
+            .collect::<String>();
+        assert!(!stored.contains("secret"));
+    }
+}
@@ -0,0 +1,193 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use eyre::Context;
+
+use crate::{conflicts, custom, expand::Env};
+
+/// Builds from source take a while, they are killed after this long
+pub const TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// A crate to install with cargo
+pub struct Crate<'a> {
+    pub name: &'a str,
+    /// A version requirement as `cargo install --version` takes it, the latest when unset
+    pub version: Option<&'a str>,
+    pub features: &'a [String],
+    /// The binary to keep, which cargo is asked to build alone when set explicitly
+    pub bin: Option<&'a str>,
+}
+
+/// A binary installed by cargo
+pub struct Built {
+    pub data: Vec<u8>,
+    /// The version of the crate cargo installed, such as `14.1.0`
+    pub version: Option<String>,
+}
+
+/// Install `krate` with `cargo install` into a temporary root and return its binary `file`.
+///
+/// `cargo binstall` is used instead when it is on PATH and no features are asked for, it
+/// downloads a prebuilt binary where the crate publishes one. `on_line` sees the progress cargo
+/// writes, such as `Compiling serde v1.0.203`.
+pub fn install(
+    krate: &Crate,
+    file: &str,
+    on_line: impl Fn(&str) + Send + 'static,
+) -> eyre::Result<Built> {
+    let cargo = program("cargo").ok_or_else(|| {
+        eyre::eyre!(
+            "cargo is not on PATH or in ~/.cargo/bin, install Rust first to build {}",
+            krate.name
+        )
+    })?;
+    let root = tempfile::tempdir().with_context(|| "Creating a directory to install into")?;
+    let binstall = program("cargo-binstall").is_some();
+    let mut command = vec![cargo.to_string_lossy().into_owned()];
+    command.extend(args(krate, root.path(), binstall));
+    let shown = format!("cargo {} {}", command[1], krate.name);
+    let output = custom::capture_lines(&command, &shown, &[], TIMEOUT, on_line)?;
+
+    let path = root.path().join("bin").join(file);
+    let data = match std::fs::read(&path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let built: Vec<_> = std::fs::read_dir(root.path().join("bin"))
+                .into_iter()
+                .flatten()
+                .flatten()
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .collect();
+            eyre::bail!(
+                "{} installed {} but no {}, set `bin` to the binary to keep",
+                shown,
+                if built.is_empty() {
+                    "nothing".to_string()
+                } else {
+                    built.join(", ")
+                },
+                file
+            );
+        }
+        Err(e) => return Err(e).with_context(|| format!("Reading {}", path.display())),
+    };
+    Ok(Built {
+        data,
+        version: installed_version(&String::from_utf8_lossy(&output), krate.name),
+    })
+}
+
+/// The arguments of cargo installing `krate` into `root`, never asking anything
+fn args(krate: &Crate, root: &Path, binstall: bool) -> Vec<String> {
+    let binstall = binstall && krate.features.is_empty();
+    let mut args: Vec<String> = match binstall {
+        true => vec!["binstall".into(), "--no-confirm".into()],
+        false => vec!["install".into(), "--locked".into()],
+    };
+    args.push("--root".into());
+    args.push(root.to_string_lossy().into_owned());
+    if let Some(version) = krate.version {
+        args.push("--version".into());
+        args.push(version.into());
+    }
+    if !binstall {
+        if !krate.features.is_empty() {
+            args.push("--features".into());
+            args.push(krate.features.join(","));
+        }
+        if let Some(bin) = krate.bin {
+            args.push("--bin".into());
+            args.push(bin.into());
+        }
+    }
+    args.push(krate.name.into());
+    args
+}
+
+/// The version cargo reports installing `krate` at, from lines like
+/// `Installed package `ripgrep v14.1.0` (executable `rg`)`
+fn installed_version(output: &str, krate: &str) -> Option<String> {
+    let needle = format!("{} v", krate);
+    output.lines().rev().find_map(|line| {
+        let (_, rest) = line.split_once(&needle)?;
+        let version: String = rest
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+'))
+            .collect();
+        version
+            .starts_with(|c: char| c.is_ascii_digit())
+            .then_some(version)
+    })
+}
+
+/// Where `name` is found: on PATH, or in the bin directory of cargo where rustup puts it before
+/// a new shell has it on PATH
+pub fn program(name: &str) -> Option<PathBuf> {
+    let env = Env::current();
+    let cargo_home = env
+        .var("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env.var("HOME").map(|home| Path::new(&home).join(".cargo")));
+    conflicts::path_directories()
+        .into_iter()
+        .chain(cargo_home.map(|home| home.join("bin")))
+        .map(|directory| directory.join(name))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_features_build_from_source() {
+        let features = ["pcre2".to_string()];
+        let mut krate = Crate {
+            name: "ripgrep",
+            version: Some("14.1.0"),
+            features: &[],
+            bin: Some("rg"),
+        };
+        let root = Path::new("/tmp/root");
+
+        assert_eq!(
+            args(&krate, root, true),
+            [
+                "binstall",
+                "--no-confirm",
+                "--root",
+                "/tmp/root",
+                "--version",
+                "14.1.0",
+                "ripgrep"
+            ]
+        );
+        krate.features = &features;
+        assert_eq!(
+            args(&krate, root, true),
+            [
+                "install",
+                "--locked",
+                "--root",
+                "/tmp/root",
+                "--version",
+                "14.1.0",
+                "--features",
+                "pcre2",
+                "--bin",
+                "rg",
+                "ripgrep"
+            ]
+        );
+
+        let output = "  Installing ripgrep v14.1.0\n   Compiling memchr v2.7.4\n    \
+                      Installed package `ripgrep v14.1.0` (executable `rg`)\n";
+        assert_eq!(
+            installed_version(output, "ripgrep").as_deref(),
+            Some("14.1.0")
+        );
+        assert_eq!(installed_version(output, "fd-find"), None);
+    }
+}
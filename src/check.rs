@@ -0,0 +1,712 @@
+use std::{collections::BTreeMap, path::Path, time::Duration};
+
+use serde::Serialize;
+
+use crate::{
+    archive,
+    config::{Config, PackageConfig},
+    dotfiles,
+    expand::{self, Field},
+    exports, http, local, lock, signature, validate,
+};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Debug)]
+pub struct Problem {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub package: Option<String>,
+    pub message: String,
+    /// Where in the config the problem is, when it is known
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub position: Option<Position>,
+}
+
+/// 1-based line and column in the config file
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Problem {
+    pub fn package(package: &str, message: impl Into<String>) -> Self {
+        Problem {
+            package: Some(package.to_string()),
+            message: message.into(),
+            position: None,
+        }
+    }
+}
+
+/// Downloads the asset of a package and checks it against the digests its config sets
+pub type Verify<'a> = dyn Fn(&Config, &PackageConfig) -> eyre::Result<()> + Sync + 'a;
+
+/// One category of checks, e.g. config linting or URL reachability
+#[derive(Serialize, Debug)]
+pub struct Section {
+    pub name: &'static str,
+    pub checked: usize,
+    pub problems: Vec<Problem>,
+}
+
+/// Run every check against the config `source`, the URLs and the lockfile at `lock_path` are
+/// only checked once it parses.
+///
+/// With `verify`, the assets of the packages that set a digest are checked by it too, see
+/// [`check_checksums`].
+pub fn run(source: &str, lock_path: &Path, verify: Option<&Verify<'_>>) -> Vec<Section> {
+    let (mut sections, config) = validate::validate(source);
+    if let Some(config) = config {
+        sections.push(check_urls(&config));
+        sections.extend(check_lockfile(&config, lock_path));
+        if let Some(verify) = verify {
+            sections.push(check_checksums(&config, verify));
+        }
+    }
+    sections
+}
+
+/// Problems that can be found without touching the network
+pub fn lint(config: &Config) -> Section {
+    let mut problems = vec![];
+    let arch = &config.platform;
+
+    if let Err(e) = expand::expand_path(
+        &arch.location.to_string_lossy(),
+        Field::new("location", &format!("section {}", config.section)),
+    ) {
+        problems.push(Problem {
+            package: None,
+            message: e.to_string(),
+            position: None,
+        });
+    }
+
+    if let Some(proxy) = &config.download.proxy {
+        if let Err(e) = reqwest::Proxy::all(proxy) {
+            problems.push(Problem {
+                package: None,
+                message: format!("download proxy {:?} is invalid: {}", proxy, e),
+                position: None,
+            });
+        }
+    }
+
+    for name in config.env.keys() {
+        if let Err(message) = exports::check_name(name) {
+            problems.push(Problem {
+                package: None,
+                message,
+                position: None,
+            });
+        }
+    }
+
+    let templates = dotfiles::links(&config.dotfiles).and_then(|links| {
+        // A template that does not render, such as one naming an unset variable
+        for link in links.iter().filter(|link| link.template) {
+            dotfiles::state(link, &config.dotfiles.variables)?;
+        }
+        Ok(())
+    });
+    if let Err(e) = templates {
+        problems.push(Problem {
+            package: None,
+            message: format!("{:#}", e),
+            position: None,
+        });
+    }
+
+    let mut names: BTreeMap<&str, usize> = BTreeMap::new();
+    for package in &arch.packages {
+        // The `files` of archive packages share the install location with the packages
+        for name in package.file_names() {
+            *names.entry(name).or_default() += 1;
+        }
+
+        if let Err(reason) = crate::name::validate(package.name()) {
+            problems.push(Problem::package(package.name(), reason));
+        }
+        if package.url().is_none() && !package.options().mirrors.is_empty() {
+            problems.push(Problem::package(
+                package.name(),
+                "mirrors only apply to packages with url or archive",
+            ));
+        }
+        let options = package.options();
+        for (_, url) in package.urls() {
+            if let Err(e) = package.expand_url(url) {
+                problems.push(Problem::package(package.name(), e.to_string()));
+            }
+        }
+        for url in package
+            .url()
+            .into_iter()
+            .chain(options.mirrors.iter().map(String::as_str))
+        {
+            let Ok(url) = package.expand_url(url) else {
+                continue;
+            };
+            if local::local_path(&url).is_some() {
+                continue;
+            }
+            if let Err(e) = reqwest::Url::parse(&url) {
+                problems.push(Problem::package(
+                    package.name(),
+                    format!("invalid URL {:?}: {}", url, e),
+                ));
+            }
+        }
+        match (&options.signature, &options.public_key) {
+            (Some(_), None) | (None, Some(_)) => problems.push(Problem::package(
+                package.name(),
+                "signature and public_key must be set together",
+            )),
+            (Some(_), Some(_)) if package.is_self_managed() => problems.push(Problem::package(
+                package.name(),
+                "signature does not apply to custom commands and git repositories, they download no asset",
+            )),
+            (Some(_), Some(key)) => {
+                if let Err(e) = signature::PublicKey::parse(key) {
+                    problems.push(Problem::package(
+                        package.name(),
+                        format!("invalid public_key: {:#}", e),
+                    ));
+                }
+            }
+            (None, None) => {}
+        }
+        if let Some(location) = &options.location {
+            let owner = format!("package {}", package.name());
+            if !package.is_single_file() {
+                problems.push(Problem::package(
+                    package.name(),
+                    "location does not apply to directory packages, git repositories and custom commands, they say where they install",
+                ));
+            } else if let Err(e) =
+                expand::expand_path(&location.to_string_lossy(), Field::new("location", &owner))
+            {
+                problems.push(Problem::package(package.name(), e.to_string()));
+            }
+        }
+        match options.mode {
+            Some(_) if !package.is_single_file() => problems.push(Problem::package(
+                package.name(),
+                "mode does not apply to directory packages, git repositories and custom commands",
+            )),
+            Some(mode) if mode > 0o7777 => problems.push(Problem::package(
+                package.name(),
+                format!("mode {:#o} is not a permission mode, such as 0o644", mode),
+            )),
+            _ => {}
+        }
+        if options.checksum_url.is_some() && package.is_self_managed() {
+            problems.push(Problem::package(
+                package.name(),
+                "checksum_url does not apply to custom commands and git repositories, they download no asset",
+            ));
+        }
+        if options.sha256.is_some() && package.is_self_managed() {
+            problems.push(Problem::package(
+                package.name(),
+                "sha256 does not apply to custom commands and git repositories, they download no asset",
+            ));
+        }
+        if let PackageConfig::Archive {
+            name,
+            bin,
+            archive,
+            files,
+            ..
+        } = package
+        {
+            if !archive::is_supported(archive) {
+                problems.push(Problem::package(
+                    name,
+                    format!("unsupported archive format: {}", archive),
+                ));
+            }
+            let owner = format!("package {}", name);
+            if let Err(e) = expand::expand_vars(bin, Field::new("bin", &owner)) {
+                problems.push(Problem::package(name, e.to_string()));
+            }
+            if let Err(e) = archive::entry_matcher(bin) {
+                problems.push(Problem::package(name, format!("{:#}", e)));
+            }
+            for file in files {
+                if let Err(reason) = crate::name::validate(&file.dest_name) {
+                    problems.push(Problem::package(
+                        name,
+                        format!("invalid dest_name {:?}: {}", file.dest_name, reason),
+                    ));
+                }
+                if let Err(e) = archive::entry_matcher(&file.entry) {
+                    problems.push(Problem::package(name, format!("{:#}", e)));
+                }
+            }
+        }
+        if let PackageConfig::AutoArchArchive {
+            name,
+            auto_arch_bin,
+            archive,
+            ..
+        } = package
+        {
+            if !archive::is_supported(archive) {
+                problems.push(Problem::package(
+                    name,
+                    format!("unsupported archive format: {}", archive),
+                ));
+            }
+            if let Err(e) = glob::Pattern::new(auto_arch_bin) {
+                problems.push(Problem::package(
+                    name,
+                    format!("invalid auto_arch_bin pattern: {}", e),
+                ));
+            }
+        }
+        if let PackageConfig::Directory {
+            name,
+            archive,
+            directory,
+            ..
+        } = package
+        {
+            if !archive::is_supported(archive) {
+                problems.push(Problem::package(
+                    name,
+                    format!("unsupported archive format: {}", archive),
+                ));
+            }
+            let owner = format!("package {}", name);
+            if let Err(e) = expand::expand_path(directory, Field::new("directory", &owner)) {
+                problems.push(Problem::package(name, e.to_string()));
+            }
+        }
+        if let PackageConfig::GithubRelease {
+            name,
+            repo,
+            asset_pattern,
+            bin,
+            ..
+        } = package
+        {
+            let parts: Vec<_> = repo.split('/').collect();
+            if parts.len() != 2 || parts.iter().any(|part| part.is_empty()) {
+                problems.push(Problem::package(
+                    name,
+                    format!("repo must be owner/name, not {:?}", repo),
+                ));
+            }
+            for (key, pattern) in [("asset_pattern", asset_pattern), ("bin", bin)] {
+                if let Some(Err(e)) = pattern.as_deref().map(glob::Pattern::new) {
+                    problems.push(Problem::package(
+                        name,
+                        format!("invalid {} pattern: {}", key, e),
+                    ));
+                }
+            }
+        }
+        if let PackageConfig::Command {
+            name,
+            command,
+            creates,
+            ..
+        } = package
+        {
+            if command.is_empty() {
+                problems.push(Problem::package(name, "command must not be empty"));
+            }
+            let owner = format!("package {}", name);
+            if let Err(e) = expand::expand_path(creates, Field::new("creates", &owner)) {
+                problems.push(Problem::package(name, e.to_string()));
+            }
+        }
+        if let PackageConfig::GitRepo {
+            name, git, dest, ..
+        } = package
+        {
+            if git.trim().is_empty() {
+                problems.push(Problem::package(name, "git must not be empty"));
+            }
+            let owner = format!("package {}", name);
+            if let Err(e) = expand::expand_path(dest, Field::new("dest", &owner)) {
+                problems.push(Problem::package(name, e.to_string()));
+            }
+        }
+    }
+    for (name, count) in names {
+        if count > 1 {
+            problems.push(Problem::package(name, format!("defined {} times", count)));
+        }
+    }
+
+    Section {
+        name: "config",
+        checked: arch.packages.len(),
+        problems,
+    }
+}
+
+/// Check that every package URL and mirror answers, all requests run in parallel
+pub fn check_urls(config: &Config) -> Section {
+    // An invalid proxy is a problem `lint` reports, nothing could be reached
+    let Ok(client) = http::init(&config.download) else {
+        return Section {
+            name: "urls",
+            checked: 0,
+            problems: vec![],
+        };
+    };
+
+    let handles: Vec<_> = config
+        .platform
+        .packages
+        .iter()
+        .flat_map(|package| {
+            let mirrors = package.options().mirrors.iter().map(String::as_str);
+            package
+                .url()
+                .into_iter()
+                .chain(mirrors)
+                .map(move |url| (package, url))
+        })
+        .map(|(package, url)| {
+            let name = package.name().to_string();
+            let url = package.expand_url(url).map_err(|e| e.to_string());
+            std::thread::spawn(move || {
+                let checked = url.and_then(|url| match local::local_path(&url) {
+                    Some(path) => check_local(&name, path),
+                    None => check_url(client, &url),
+                });
+                checked.map_err(|message| Problem::package(&name, message))
+            })
+        })
+        .collect();
+
+    let checked = handles.len();
+    let problems = handles
+        .into_iter()
+        .filter_map(|handle| handle.join().expect("check thread panicked").err())
+        .collect();
+
+    Section {
+        name: "urls",
+        checked,
+        problems,
+    }
+}
+
+/// Check that the lockfile at `path` locks the configured packages as they are configured now.
+/// A config without a lockfile has nothing to drift from.
+pub fn check_lockfile(config: &Config, path: &Path) -> Option<Section> {
+    let stale = match lock::Lockfile::read(path) {
+        Ok(None) => return None,
+        Ok(Some(lockfile)) => lock::stale(config, &lockfile),
+        Err(e) => Err(e),
+    };
+    let problems = match stale {
+        Ok(stale) => stale
+            .iter()
+            .map(|staleness| Problem {
+                package: None,
+                message: format!("{}, run `workstation lock update`", staleness),
+                position: None,
+            })
+            .collect(),
+        Err(e) => vec![Problem {
+            package: None,
+            message: format!("{:#}", e),
+            position: None,
+        }],
+    };
+
+    Some(Section {
+        name: "lockfile",
+        checked: config.platform.packages.len(),
+        problems,
+    })
+}
+
+/// Download the asset of every package that sets a digest, `sha256`, `pin_hash` or
+/// `checksum_url`, and check it with `verify`. All downloads run in parallel.
+pub fn check_checksums(config: &Config, verify: &Verify<'_>) -> Section {
+    let packages: Vec<_> = config
+        .platform
+        .packages
+        .iter()
+        .filter(|package| {
+            let options = package.options();
+            let has_asset =
+                package.url().is_some() || matches!(package, PackageConfig::GithubRelease { .. });
+            has_asset
+                && (options.sha256.is_some()
+                    || options.pin_hash.is_some()
+                    || options.checksum_url.is_some())
+        })
+        .collect();
+
+    let problems = std::thread::scope(|scope| {
+        let handles: Vec<_> = packages
+            .iter()
+            .map(|package| {
+                scope.spawn(move || {
+                    verify(config, package)
+                        .map_err(|e| Problem::package(package.name(), format!("{:#}", e)))
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .filter_map(|handle| handle.join().expect("check thread panicked").err())
+            .collect()
+    });
+
+    Section {
+        name: "checksums",
+        checked: packages.len(),
+        problems,
+    }
+}
+
+fn check_local(name: &str, path: &str) -> Result<(), String> {
+    let owner = format!("package {}", name);
+    let path = expand::expand_source_path(path, Field::new("archive", &owner))
+        .map_err(|e| e.to_string())?;
+    if !path.is_file() {
+        return Err(format!("{} does not exist", path.display()));
+    }
+    Ok(())
+}
+
+fn check_url(client: &reqwest::blocking::Client, url: &str) -> Result<(), String> {
+    let response = client
+        .head(url)
+        .timeout(REQUEST_TIMEOUT)
+        .send()
+        .map_err(|e| request_error(url, e))?;
+
+    // Some servers do not implement HEAD, ask for the first byte instead
+    let status = if response.status() == reqwest::StatusCode::METHOD_NOT_ALLOWED {
+        client
+            .get(url)
+            .header(reqwest::header::RANGE, "bytes=0-0")
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .map_err(|e| request_error(url, e))?
+            .status()
+    } else {
+        response.status()
+    };
+
+    if status.is_client_error() || status.is_server_error() {
+        return Err(format!("{} answered {}", url, status));
+    }
+
+    Ok(())
+}
+
+/// `error` with every cause, which reqwest leaves out of its message
+pub fn request_error(url: &str, error: reqwest::Error) -> String {
+    let error = error.without_url();
+    let mut message = format!("{}: {}", url, error);
+    let mut source = std::error::Error::source(&error);
+    while let Some(cause) = source {
+        message.push_str(&format!(": {}", cause));
+        source = cause.source();
+    }
+    message
+}
+
+pub fn problem_count(sections: &[Section]) -> usize {
+    sections.iter().map(|section| section.problems.len()).sum()
+}
+
+pub fn render(sections: &[Section]) -> String {
+    let mut rendered = String::new();
+
+    for section in sections {
+        rendered.push_str(&format!(
+            "{}: {} checked, {} problem(s)\n",
+            section.name,
+            section.checked,
+            section.problems.len()
+        ));
+        for problem in &section.problems {
+            rendered.push_str("  ");
+            if let Some(position) = problem.position {
+                rendered.push_str(&format!(
+                    "line {}, column {}: ",
+                    position.line, position.column
+                ));
+            }
+            match &problem.package {
+                Some(package) => rendered.push_str(&format!("{}: {}\n", package, problem.message)),
+                None => rendered.push_str(&format!("{}\n", problem.message)),
+            }
+        }
+    }
+
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lint_finds_config_problems() {
+        let config = crate::config::parse_config_for(
+            r#"
+[workstation.download]
+proxy = "not a proxy"
+
+[linux_x86_64]
+location = "/opt/bin"
+packages = [
+  { name = "rg", url = "https://example.com/rg" },
+  { name = "rg", url = "https://example.com/rg2" },
+  { name = "tool", bin = "tool-$UNSET_IN_TESTS/tool", archive = "https://example.com/tool.rar" },
+  { name = "kubectl", bin = "kubectl", archive = "https://example.com/k.tar.gz", files = [
+    { entry = "kubectl-convert", dest_name = "tool" },
+    { entry = "completions/*", dest_name = "../_kubectl" },
+  ] },
+  { name = "eza", repo = "eza-community/eza", mirrors = ["https://example.com/eza"], signature = "{url}.minisig", public_key = "RWQ=" },
+  { name = "fd", url = "https://example.com/fd", signature = "{url}.minisig", mode = 0o100644 },
+  { name = "jdk", archive = "https://example.com/jdk.tar.gz", directory = "/opt/jdk", location = "/opt/lsp" },
+]
+"#,
+            &crate::arch::Target::parse("linux_x86_64").unwrap(),
+        )
+        .unwrap();
+
+        let section = lint(&config);
+
+        let messages: Vec<_> = section
+            .problems
+            .iter()
+            .map(|problem| problem.message.as_str())
+            .collect();
+        assert_eq!(section.checked, 7);
+        assert_eq!(messages.len(), 11, "{:?}", messages);
+        assert!(messages
+            .iter()
+            .any(|m| m.contains("is not a permission mode")));
+        assert!(messages
+            .iter()
+            .any(|m| m.contains("location does not apply to directory packages")));
+        assert!(messages.iter().any(|m| m.contains("invalid public_key")));
+        assert!(messages
+            .iter()
+            .any(|m| m.contains("signature and public_key must be set together")));
+        assert!(messages.iter().any(|m| m.contains("mirrors only apply")));
+        assert!(messages.iter().any(|m| m.contains("download proxy")));
+        assert!(messages
+            .iter()
+            .any(|m| m.contains("invalid dest_name \"../_kubectl\"")));
+        assert!(messages.iter().any(|m| m.contains("unsupported archive")));
+        assert!(messages.iter().any(|m| m.contains("$UNSET_IN_TESTS")));
+        assert_eq!(
+            messages
+                .iter()
+                .filter(|m| m.contains("defined 2 times"))
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_unparsable_config_is_a_parse_problem() {
+        let sections = run("[linux_x86_64", Path::new("/nonexistent.lock"), None);
+
+        assert_eq!(sections.len(), 1);
+        assert_eq!(sections[0].name, "parse");
+        assert_eq!(problem_count(&sections), 1);
+    }
+
+    #[test]
+    fn test_lockfile_drift_is_a_problem() {
+        let config = |packages: &str| {
+            crate::config::parse_config_for(
+                &format!(
+                    "[linux_x86_64]\nlocation = \"/opt/bin\"\npackages = [{}]\n",
+                    packages
+                ),
+                &crate::arch::Target::parse("linux_x86_64").unwrap(),
+            )
+            .unwrap()
+        };
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("workstation.lock");
+        assert!(check_lockfile(&config(""), &path).is_none());
+
+        let locked = config(
+            r#"{ name = "rg", url = "https://example.com/rg" },
+               { name = "fd", url = "https://example.com/fd" }"#,
+        );
+        let mut lockfile = lock::Lockfile::new();
+        lockfile
+            .update(&locked, &[], |_| Ok(Default::default()))
+            .unwrap();
+        lockfile.write(&path).unwrap();
+        assert!(check_lockfile(&locked, &path).unwrap().problems.is_empty());
+
+        let section = check_lockfile(
+            &config(
+                r#"{ name = "rg", url = "https://example.com/rg-14" },
+                   { name = "bat", url = "https://example.com/bat" }"#,
+            ),
+            &path,
+        )
+        .unwrap();
+        let messages: Vec<_> = section
+            .problems
+            .iter()
+            .map(|problem| problem.message.as_str())
+            .collect();
+        assert_eq!(section.checked, 2);
+        assert_eq!(messages.len(), 3, "{:?}", messages);
+        assert!(messages[0].starts_with("rg changed since it was locked"));
+        assert!(messages[1].starts_with("bat is in the config but not locked"));
+        assert!(messages[2].starts_with("fd is locked but no longer in the config"));
+    }
+
+    #[test]
+    fn test_checksums_are_verified_for_packages_with_a_digest() {
+        let config = crate::config::parse_config_for(
+            &format!(
+                r#"
+[linux_x86_64]
+location = "/opt/bin"
+packages = [
+  {{ name = "rg", url = "https://example.com/rg", sha256 = "{}" }},
+  {{ name = "fd", url = "https://example.com/fd", checksum_url = "{{url}}.sha256" }},
+  {{ name = "bat", url = "https://example.com/bat" }},
+  {{ name = "tool", command = "true", creates = "/opt/bin/tool", sha256 = "{}" }},
+]
+"#,
+                "0".repeat(64),
+                "0".repeat(64)
+            ),
+            &crate::arch::Target::parse("linux_x86_64").unwrap(),
+        )
+        .unwrap();
+        let verified = std::sync::Mutex::new(vec![]);
+
+        let section = check_checksums(&config, &|_, package| {
+            verified.lock().unwrap().push(package.name().to_string());
+            match package.name() {
+                "rg" => Err(eyre::eyre!("rg does not match the sha256 of the package")),
+                _ => Ok(()),
+            }
+        });
+
+        let mut verified = verified.into_inner().unwrap();
+        verified.sort();
+        assert_eq!(verified, ["fd", "rg"]);
+        assert_eq!(section.checked, 2);
+        assert_eq!(section.problems.len(), 1);
+        assert_eq!(section.problems[0].package.as_deref(), Some("rg"));
+    }
+}
@@ -0,0 +1,149 @@
+use std::io::Read;
+
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::asset::Asset;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    /// Checksum files rarely name the algorithm, the length of the digest tells
+    fn of_hex(hex: &str) -> Option<Algorithm> {
+        if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        match hex.len() {
+            64 => Some(Algorithm::Sha256),
+            128 => Some(Algorithm::Sha512),
+            _ => None,
+        }
+    }
+}
+
+/// The digest a checksum file lists for one file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listed {
+    pub algorithm: Algorithm,
+    /// Lowercase hex
+    pub hex: String,
+}
+
+/// The digest `content` lists for `file_name`.
+///
+/// Understands what `sha256sum` and `shasum` print (`<hex>  <name>`, with `*` before binary
+/// names), the BSD `SHA256 (<name>) = <hex>` lines, and files holding nothing but the digest of
+/// the one file they were published for. Names are compared without their directories.
+pub fn find(content: &str, file_name: &str) -> Option<Listed> {
+    let lines: Vec<&str> = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+    if let [line] = lines[..] {
+        if let Some(algorithm) = Algorithm::of_hex(line) {
+            return Some(listed(algorithm, line));
+        }
+    }
+    lines.into_iter().find_map(|line| {
+        let (name, hex) = match line.split_once(") = ") {
+            Some((start, hex)) => (start.split_once(" (")?.1, hex.trim()),
+            None => {
+                let (hex, name) = line.split_once(char::is_whitespace)?;
+                (name.trim_start().trim_start_matches('*'), hex)
+            }
+        };
+        let base = name.rsplit('/').next().unwrap_or(name);
+        let algorithm = Algorithm::of_hex(hex)?;
+        (base == file_name).then(|| listed(algorithm, hex))
+    })
+}
+
+fn listed(algorithm: Algorithm, hex: &str) -> Listed {
+    Listed {
+        algorithm,
+        hex: hex.to_ascii_lowercase(),
+    }
+}
+
+/// The name checksum files list the asset at `source` under: the last segment of its path
+pub fn file_name(source: &str) -> &str {
+    let path = source.split(['?', '#']).next().unwrap_or(source);
+    path.rsplit(['/', '\\']).next().unwrap_or(path)
+}
+
+/// Check `asset` against the digest listed for it, read as a stream
+pub fn verify(listed: &Listed, asset: &Asset) -> eyre::Result<()> {
+    let actual = match listed.algorithm {
+        Algorithm::Sha256 => hex(stream(Sha256::new(), asset)?.finalize().as_slice()),
+        Algorithm::Sha512 => hex(stream(Sha512::new(), asset)?.finalize().as_slice()),
+    };
+    if actual != listed.hex {
+        eyre::bail!("the checksum file lists {}, got {}", listed.hex, actual);
+    }
+    Ok(())
+}
+
+fn stream<D: Digest>(mut hasher: D, asset: &Asset) -> eyre::Result<D> {
+    let mut reader = asset.reader()?;
+    let mut chunk = vec![0; 64 * 1024];
+    loop {
+        let read = reader.read(&mut chunk)?;
+        if read == 0 {
+            return Ok(hasher);
+        }
+        hasher.update(&chunk[..read]);
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn test_find_the_line_of_the_asset() {
+        let other = "0".repeat(64);
+        let gnu = format!(
+            "{}  tool-linux.tar.gz\n{} *dist/tool-macos.tar.gz\n",
+            other, SHA256
+        );
+        let bsd = format!("SHA256 (tool-linux.tar.gz) = {}\n", SHA256.to_uppercase());
+        let sha512 = format!("{}  tool-macos.tar.gz\n", "a".repeat(128));
+
+        assert_eq!(find(&gnu, "tool-macos.tar.gz").unwrap().hex, SHA256);
+        assert_eq!(find(&gnu, "tool-linux.tar.gz").unwrap().hex, other);
+        assert_eq!(find(&bsd, "tool-linux.tar.gz").unwrap().hex, SHA256);
+        assert_eq!(
+            find(&sha512, "tool-macos.tar.gz").unwrap().algorithm,
+            Algorithm::Sha512
+        );
+        assert_eq!(
+            find(&format!("{}\n", SHA256), "anything").unwrap().hex,
+            SHA256
+        );
+        assert_eq!(find(&gnu, "tool-windows.zip"), None);
+        assert_eq!(
+            file_name("https://example.com/v1/tool.tar.gz?download=1"),
+            "tool.tar.gz"
+        );
+    }
+
+    #[test]
+    fn test_verify_the_asset() {
+        let asset = Asset::from_bytes(b"hello").unwrap();
+
+        assert!(verify(&listed(Algorithm::Sha256, SHA256), &asset).is_ok());
+        assert!(verify(&listed(Algorithm::Sha256, &"0".repeat(64)), &asset).is_err());
+        let sha512 = hex(Sha512::digest(b"hello").as_slice());
+        assert!(verify(&listed(Algorithm::Sha512, &sha512), &asset).is_ok());
+    }
+}
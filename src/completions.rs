@@ -0,0 +1,154 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use eyre::Context;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Shells whose completion files can be generated by `generate_completions`
+#[derive(
+    Deserialize, Serialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl Shell {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Shell::Bash => "bash",
+            Shell::Zsh => "zsh",
+            Shell::Fish => "fish",
+        }
+    }
+
+    /// The file name each shell looks up for the completions of `command`
+    pub fn file_name(&self, command: &str) -> String {
+        match self {
+            Shell::Bash => command.to_string(),
+            Shell::Zsh => format!("_{}", command),
+            Shell::Fish => format!("{}.fish", command),
+        }
+    }
+}
+
+/// Run the installed `binary` once per configured shell and write what it prints to the shell's
+/// completions directory.
+///
+/// Completions are a convenience, so every failure is returned as a warning instead of failing
+/// the package.
+pub fn generate(
+    binary: &Path,
+    name: &str,
+    completions: &BTreeMap<Shell, Vec<String>>,
+    directories: &BTreeMap<Shell, PathBuf>,
+) -> Vec<eyre::Report> {
+    completions
+        .iter()
+        .filter_map(|(shell, args)| {
+            generate_one(
+                binary,
+                args,
+                &directories[shell].join(shell.file_name(name)),
+            )
+            .with_context(|| format!("Generating {} completions for {}", shell.name(), name))
+            .err()
+        })
+        .collect()
+}
+
+fn generate_one(binary: &Path, args: &[String], path: &Path) -> eyre::Result<()> {
+    let output = Command::new(binary)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("Running {}", binary.display()))?;
+
+    if !output.status.success() {
+        eyre::bail!(
+            "`{} {}` exited with {}: {}",
+            binary.display(),
+            args.join(" "),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    if output.stdout.is_empty() {
+        eyre::bail!("`{} {}` printed nothing", binary.display(), args.join(" "));
+    }
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Creating {}", parent.display()))?;
+    }
+    std::fs::write(path, output.stdout).with_context(|| format!("Writing {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    fn script(directory: &Path, body: &str) -> PathBuf {
+        let path = directory.join("tool");
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    fn directories(root: &Path) -> BTreeMap<Shell, PathBuf> {
+        [Shell::Bash, Shell::Zsh, Shell::Fish]
+            .into_iter()
+            .map(|shell| (shell, root.join(shell.name())))
+            .collect()
+    }
+
+    #[test]
+    fn test_output_is_written_per_shell() {
+        let root = tempfile::tempdir().unwrap();
+        let binary = script(root.path(), "echo \"completions for $2\"");
+        let completions = BTreeMap::from([
+            (
+                Shell::Zsh,
+                vec!["--generate".to_string(), "zsh".to_string()],
+            ),
+            (
+                Shell::Fish,
+                vec!["--generate".to_string(), "fish".to_string()],
+            ),
+        ]);
+
+        let warnings = generate(&binary, "rg", &completions, &directories(root.path()));
+
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(
+            std::fs::read_to_string(root.path().join("zsh/_rg")).unwrap(),
+            "completions for zsh\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(root.path().join("fish/rg.fish")).unwrap(),
+            "completions for fish\n"
+        );
+        assert!(!root.path().join("bash/rg").exists());
+    }
+
+    #[test]
+    fn test_failing_command_is_a_warning() {
+        let root = tempfile::tempdir().unwrap();
+        let binary = script(root.path(), "echo 'unknown flag' >&2; exit 2");
+        let completions = BTreeMap::from([(Shell::Bash, vec!["--completions".to_string()])]);
+
+        let warnings = generate(&binary, "tool", &completions, &directories(root.path()));
+
+        assert_eq!(warnings.len(), 1);
+        assert!(format!("{:?}", warnings[0]).contains("unknown flag"));
+        assert!(!root.path().join("bash/tool").exists());
+    }
+}
@@ -0,0 +1,2078 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+};
+
+use eyre::Context;
+use reqwest::Url;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{
+    arch::Target,
+    completions::Shell,
+    download::{DownloadOptions, RetryPolicy},
+    error::ErrorCode,
+    expand::{self, Field},
+    ignore::IgnoreSet,
+    include::{self, Origin},
+    keys, migrate,
+    paths::PathsConfig,
+};
+
+/// Permissions of installed files when the package sets no `mode`
+pub const DEFAULT_MODE: u32 = 0o755;
+
+/// Where AppImages are installed when the package sets no `location`
+pub const APPLICATIONS: &str = "~/Applications";
+
+/// The table of the settings of workstation itself, see [`WorkstationConfig`]
+pub const ROOT_TABLE: &str = "workstation";
+
+/// The tables of [`WorkstationConfig`], which older configs have at the top level
+pub const SETTINGS: [&str; 7] = [
+    "install", "paths", "download", "hooks", "dotfiles", "env", "rustup",
+];
+
+/// Whether unknown keys only warn, see `--allow-unknown-keys`
+static ALLOW_UNKNOWN_KEYS: AtomicBool = AtomicBool::new(false);
+
+/// Warn about keys of the config that no setting uses instead of refusing the config
+pub fn allow_unknown_keys() {
+    ALLOW_UNKNOWN_KEYS.store(true, Ordering::Relaxed);
+}
+
+/// The profiles chosen with `--profile`
+static PROFILES: OnceLock<Vec<String>> = OnceLock::new();
+
+/// Install the packages of `profiles` instead of those of the profiles matching the hostname
+pub fn use_profiles(profiles: Vec<String>) {
+    let _ = PROFILES.set(profiles);
+}
+
+pub fn unknown_keys_allowed() -> bool {
+    ALLOW_UNKNOWN_KEYS.load(Ordering::Relaxed)
+}
+
+/// The config for this run: the shared settings and the platform section for the target
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Config {
+    pub install: InstallConfig,
+    pub paths: PathsConfig,
+    pub download: DownloadConfig,
+    pub hooks: HooksConfig,
+    pub dotfiles: DotfilesConfig,
+    pub env: BTreeMap<String, String>,
+    pub rustup: RustupConfig,
+    /// Name of the section `platform` comes from, such as `linux_x86_64`
+    pub section: String,
+    pub platform: ArchConfig,
+}
+
+impl Config {
+    /// This config with only the packages tagged with one of `tags`, or all of them when it is
+    /// empty
+    pub fn with_tags(&self, tags: &[String]) -> Config {
+        let mut config = self.clone();
+        config.platform.packages.retain(|package| {
+            tags.is_empty() || package.options().tags.iter().any(|tag| tags.contains(tag))
+        });
+        config
+    }
+
+    /// This config with only the packages named in `only`, or all of them when it is empty,
+    /// minus those named in `skip`
+    pub fn with_names(&self, only: &[String], skip: &[String]) -> Config {
+        let mut config = self.clone();
+        config.platform.packages.retain(|package| {
+            let name = package.name().to_string();
+            (only.is_empty() || only.contains(&name)) && !skip.contains(&name)
+        });
+        config
+    }
+}
+
+/// The top level config file, with a section per platform such as `[linux_aarch64_musl]` and
+/// packages every platform shares in `[common]`.
+///
+/// An optional `schema_version` key is handled by [`migrate`] before deserialization.
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Default)]
+pub struct ConfigFile {
+    /// Config files merged into this one when it is read, relative to it. This file wins over
+    /// the files it includes and later files over earlier ones: tables are merged key by key,
+    /// and a package replaces the package of the same name in the same section.
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub workstation: WorkstationConfig,
+    #[serde(default)]
+    pub common: CommonConfig,
+    /// Named groups of packages, see the `profiles` of a package
+    #[serde(default)]
+    pub profiles: BTreeMap<String, ProfileConfig>,
+    #[serde(flatten)]
+    pub sections: BTreeMap<String, ArchConfig>,
+}
+
+/// The settings of workstation itself rather than of the packages it installs, shared by every
+/// platform section, such as `[workstation.install]`.
+///
+/// Configs written before this table have its tables at the top level, such as `[install]`.
+/// They are still read, with a warning, and `workstation migrate-config` moves them, see
+/// [`hoist_settings`].
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Default)]
+pub struct WorkstationConfig {
+    #[serde(default)]
+    pub install: InstallConfig,
+    #[serde(default)]
+    pub paths: PathsConfig,
+    #[serde(default)]
+    pub download: DownloadConfig,
+    #[serde(default)]
+    pub hooks: HooksConfig,
+    #[serde(default)]
+    pub dotfiles: DotfilesConfig,
+    /// Environment variables exported by the generated env.sh and env.fish, such as
+    /// `EDITOR = "nvim"`. `$VAR` and `${VAR}` in values are expanded by the shell loading them.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub rustup: RustupConfig,
+}
+
+/// A group of packages only some machines install, such as `work`
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Default)]
+pub struct ProfileConfig {
+    /// Hostname globs of the machines the profile is active on, such as `corp-*`. A name
+    /// without a domain also matches the hostname up to its first dot. `--profile` replaces the
+    /// profiles chosen by hostname.
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// Variables of the `[workstation.dotfiles]` templates on machines the profile is active on,
+    /// such as the `email` of a work identity
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+}
+
+/// Packages installed on every platform, before those of the platform section
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Default)]
+pub struct CommonConfig {
+    /// A package of the platform section with the same name replaces the common one
+    #[serde(default)]
+    pub packages: Vec<PackageConfig>,
+}
+
+impl ConfigFile {
+    /// Pick the best section for `target`, see [`Target::sections`] for the fallback order, and
+    /// add the common packages it does not replace.
+    ///
+    /// Packages of profiles that are not active are left out, see [`ConfigFile::active_profiles`].
+    ///
+    /// The template variables of the packages are filled in, see
+    /// [`PackageConfig::fill_templates`].
+    pub fn select(self, target: &Target) -> eyre::Result<Config> {
+        let chosen = PROFILES.get().map(Vec::as_slice);
+        let active = self.active_profiles(chosen, hostname().as_deref())?;
+        self.select_with_profiles(target, &active)
+    }
+
+    fn select_with_profiles(mut self, target: &Target, active: &[String]) -> eyre::Result<Config> {
+        self.keep_profiles(active);
+        let candidates = target.sections();
+        let Some((section, mut platform)) = candidates.iter().find_map(|name| {
+            self.sections
+                .get(name)
+                .map(|section| (name.clone(), section.clone()))
+        }) else {
+            let available: Vec<_> = self.sections.keys().map(String::as_str).collect();
+            eyre::bail!(
+                "The config has no section for {}, add one of [{}] (the config has [{}])",
+                target,
+                target.canonical_sections().join("], ["),
+                available.join("], [")
+            );
+        };
+        let mut packages: Vec<_> = self
+            .common
+            .packages
+            .into_iter()
+            .filter(|common| {
+                !platform
+                    .packages
+                    .iter()
+                    .any(|package| package.name() == common.name())
+            })
+            .collect();
+        packages.append(&mut platform.packages);
+        platform.packages = packages;
+
+        for package in &mut platform.packages {
+            package.fill_templates(target)?;
+        }
+
+        let mut variables = BTreeMap::from([
+            ("hostname".to_string(), hostname().unwrap_or_default()),
+            ("os".to_string(), target.os.clone()),
+            ("arch".to_string(), target.arch.clone()),
+            ("profiles".to_string(), active.join(",")),
+        ]);
+        let mut settings = self.workstation;
+        variables.append(&mut settings.dotfiles.variables);
+        for profile in active.iter().filter_map(|name| self.profiles.get(name)) {
+            variables.extend(profile.variables.clone());
+        }
+        settings.dotfiles.variables = variables;
+
+        Ok(Config {
+            install: settings.install,
+            paths: settings.paths,
+            download: settings.download,
+            hooks: settings.hooks,
+            dotfiles: settings.dotfiles,
+            env: settings.env,
+            rustup: settings.rustup,
+            section,
+            platform,
+        })
+    }
+
+    /// The profiles `chosen` with `--profile`, or else those whose `hosts` match `hostname`
+    pub fn active_profiles(
+        &self,
+        chosen: Option<&[String]>,
+        hostname: Option<&str>,
+    ) -> eyre::Result<Vec<String>> {
+        if let Some(chosen) = chosen {
+            return Ok(chosen.to_vec());
+        }
+        let Some(hostname) = hostname else {
+            return Ok(vec![]);
+        };
+        let short = hostname.split('.').next().unwrap_or(hostname);
+        let options = glob::MatchOptions {
+            case_sensitive: false,
+            ..Default::default()
+        };
+        let mut active = vec![];
+        for (name, profile) in &self.profiles {
+            for host in &profile.hosts {
+                let pattern = glob::Pattern::new(host)
+                    .with_context(|| format!("Invalid host {:?} of profile {}", host, name))?;
+                if [hostname, short]
+                    .iter()
+                    .any(|candidate| pattern.matches_with(candidate, options))
+                {
+                    active.push(name.clone());
+                    break;
+                }
+            }
+        }
+        Ok(active)
+    }
+
+    /// Drop the packages whose `profiles` are all inactive, before the common packages are merged
+    /// in so that a platform package of an inactive profile does not replace a common one
+    fn keep_profiles(&mut self, active: &[String]) {
+        let included = |package: &PackageConfig| {
+            let profiles = &package.options().profiles;
+            profiles.is_empty() || profiles.iter().any(|profile| active.contains(profile))
+        };
+        self.common.packages.retain(included);
+        for section in self.sections.values_mut() {
+            section.packages.retain(included);
+        }
+    }
+}
+
+/// The name of this machine
+fn hostname() -> Option<String> {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| {
+            let output = std::process::Command::new("hostname").output().ok()?;
+            output
+                .status
+                .success()
+                .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+        })
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+/// Settings shared by every platform section
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Default)]
+pub struct InstallConfig {
+    /// Glob patterns, relative to the install location, of files managed by other means
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    /// Keep each installed file once in the shared store, named by its content hash, and link
+    /// to it from the install location instead of copying it there
+    #[serde(default)]
+    pub store: bool,
+    /// Packages installed at the same time, all of them when unset. `--jobs` overrides it.
+    pub jobs: Option<usize>,
+    /// Add the install location to PATH in the workstation block of each existing shell rc
+    /// file, ahead of the `shell_init` snippets that may run installed packages
+    #[serde(default)]
+    pub manage_path: bool,
+}
+
+impl InstallConfig {
+    pub fn ignore_set(&self) -> eyre::Result<IgnoreSet> {
+        IgnoreSet::new(&self.ignore)
+    }
+}
+
+/// Shell commands `setup` runs around the whole run, with the platform location first on PATH.
+/// Their output is only shown when one fails.
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Default)]
+pub struct HooksConfig {
+    /// Run in order before anything is installed, such as `mkdir -p ~/.config/tool`. A failing
+    /// command stops `setup`.
+    #[serde(default)]
+    pub pre_setup: Vec<String>,
+    /// Run in order after every package was installed or failed, such as a notification
+    #[serde(default)]
+    pub post_setup: Vec<String>,
+}
+
+/// Symlinks from where programs look for their config to the files of a dotfiles repository,
+/// created by `setup` after the packages are installed
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Default)]
+pub struct DotfilesConfig {
+    /// The directory relative sources are in, such as the `dest` of a git package
+    pub directory: Option<String>,
+    /// Each file or directory of the dotfiles and the path to link to it, such as
+    /// `nvim = "~/.config/nvim"`. Whatever is already at the path is renamed to
+    /// `<name>.workstation-backup` first.
+    #[serde(default)]
+    pub links: BTreeMap<String, String>,
+    /// Each template and the path to write it to, such as `"gitconfig.tmpl" = "~/.gitconfig"`.
+    /// `{{ name }}` in a template is replaced with the variable `name`. A file at the path that
+    /// `setup` did not write is renamed to `<name>.workstation-backup` first.
+    #[serde(default)]
+    pub templates: BTreeMap<String, String>,
+    /// Variables of the templates, such as `email`, next to the built-in `hostname`, `os`,
+    /// `arch` and `profiles`. Those of the active profiles override these.
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+}
+
+/// Rust toolchains `setup` installs with rustup after the packages, adding what is missing to
+/// those already installed. Nothing is removed or updated.
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Default)]
+pub struct RustupConfig {
+    /// Toolchains as rustup names them, such as `stable`, `nightly` or `1.80.0`
+    #[serde(default)]
+    pub toolchains: Vec<String>,
+    /// The toolchain to make the default, one of `toolchains`
+    pub default: Option<String>,
+    /// Components of every toolchain, such as `clippy`, `rustfmt` or `rust-analyzer`
+    #[serde(default)]
+    pub components: Vec<String>,
+    /// Targets of every toolchain besides the host, such as `wasm32-unknown-unknown`
+    #[serde(default)]
+    pub targets: Vec<String>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Default)]
+pub struct ArchConfig {
+    /// Where packages are installed. A leading `~` and `$VAR` or `${VAR}` are expanded, as in
+    /// every path and URL of the config.
+    pub location: PathBuf,
+    pub packages: Vec<PackageConfig>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum PackageConfig {
+    /// The file `bin` extracted from a `.tar.gz`, `.tar.xz`, `.tar.bz2`, `.tar.zst` or `.zip`
+    /// archive
+    Archive {
+        name: String,
+        /// Path of the file in the archive, or a glob or `regex:<expression>` matching it, the
+        /// first matching file is installed
+        bin: String,
+        archive: String,
+        /// More files of the same archive installed next to `bin`, from the one download
+        #[serde(default)]
+        files: Vec<ArchiveFile>,
+        /// Fills `{version}` in `archive`, `bin` and the `files` entries
+        version: Option<String>,
+        #[serde(flatten)]
+        options: PackageOptions,
+    },
+    /// An archive shipping binaries for several platforms, `auto_arch_bin` is a glob and the
+    /// match whose executable header fits the running platform is installed
+    AutoArchArchive {
+        name: String,
+        auto_arch_bin: String,
+        archive: String,
+        /// Fills `{version}` in `archive` and `auto_arch_bin`
+        version: Option<String>,
+        #[serde(flatten)]
+        options: PackageOptions,
+    },
+    /// Every file of an archive unpacked into `directory`, for toolchains that are more than
+    /// one executable. `directory` is replaced as a whole on every install.
+    Directory {
+        name: String,
+        archive: String,
+        directory: String,
+        /// Leading path components dropped from each entry, like `tar --strip-components`
+        #[serde(default)]
+        strip_components: usize,
+        /// Fills `{version}` in `archive` and `directory`
+        version: Option<String>,
+        #[serde(flatten)]
+        options: PackageOptions,
+    },
+    /// A file downloaded as is, or decompressed when the URL ends in `.gz`, `.xz`, `.bz2` or
+    /// `.zst`
+    Binary {
+        name: String,
+        url: String,
+        /// Fills `{version}` in `url`
+        version: Option<String>,
+        #[serde(flatten)]
+        options: PackageOptions,
+    },
+    /// A desktop application packaged as an AppImage, installed into `~/Applications` unless
+    /// `location` says otherwise. A desktop entry and the icon of the app are written for the
+    /// user so that launchers list it.
+    AppImage {
+        name: String,
+        /// The URL of the AppImage
+        appimage: String,
+        /// Fills `{version}` in `appimage`
+        version: Option<String>,
+        #[serde(flatten)]
+        options: PackageOptions,
+    },
+    /// An asset of a GitHub release, the one built for this platform unless `asset_pattern`
+    /// names it. Archives are unpacked and `bin` installed, other assets are installed as is or
+    /// decompressed like `url`.
+    GithubRelease {
+        name: String,
+        /// The repository, as `owner/name`
+        repo: String,
+        /// Tag of the release, the latest release when unset. Fills `{version}` in
+        /// `asset_pattern` and `bin`.
+        version: Option<String>,
+        /// Glob matching the file name of the asset
+        asset_pattern: Option<String>,
+        /// Glob matching the file to install from an archive asset, a file named like the
+        /// package by default
+        bin: Option<String>,
+        #[serde(flatten)]
+        options: PackageOptions,
+    },
+    /// Installed by running `command`, for installers too unusual to model, such as rustup or
+    /// nvm. `creates` is the file the command produces, the package is skipped while it exists.
+    Command {
+        name: String,
+        command: CommandLine,
+        creates: String,
+        /// Seconds before the command is killed
+        timeout: Option<u64>,
+        #[serde(flatten)]
+        options: PackageOptions,
+    },
+    /// A git repository cloned into `dest`, such as dotfiles, shell plugins or an editor
+    /// config. Later runs fetch and fast-forward the clone, without touching local changes.
+    GitRepo {
+        name: String,
+        /// What `git clone` takes: an HTTPS or SSH URL, or a path
+        git: String,
+        /// The directory of the clone
+        dest: String,
+        /// The branch, tag or commit to check out, the default branch when unset. A branch
+        /// follows its upstream, a tag or commit stays put.
+        rev: Option<String>,
+        #[serde(flatten)]
+        options: PackageOptions,
+    },
+    /// A binary built from a crate with `cargo install`, for tools published without prebuilt
+    /// binaries. `cargo binstall` fetches a prebuilt one instead when it is on PATH and no
+    /// `features` are set. Without `version` the build is kept until `setup --force`.
+    Cargo {
+        name: String,
+        /// The crate on crates.io, such as `ripgrep`
+        #[serde(rename = "crate")]
+        #[schemars(rename = "crate")]
+        krate: String,
+        /// A version requirement, such as `14.1.0` or `^0.10`, the latest version when unset
+        version: Option<String>,
+        /// Cargo features to build with
+        #[serde(default)]
+        features: Vec<String>,
+        /// The binary of the crate to install, such as `rg` for ripgrep, one named like the
+        /// package by default
+        bin: Option<String>,
+        #[serde(flatten)]
+        options: PackageOptions,
+    },
+    /// A binary built from a Go module with `go install`, such as `golang.org/x/tools/gopls`,
+    /// installed under the name of the package. Without `version` the build is kept until
+    /// `setup --force`.
+    Go {
+        name: String,
+        /// The module path of the command to build
+        go: String,
+        /// A version `go install` takes, such as `v0.16.2`, `latest` when unset
+        version: Option<String>,
+        #[serde(flatten)]
+        options: PackageOptions,
+    },
+    /// A Python application installed with `pipx`, such as `ruff-lsp`, in a virtual
+    /// environment of its own with its commands in `~/.local/bin`
+    Pipx {
+        name: String,
+        /// The package on PyPI
+        pipx: String,
+        /// The version to install, the latest when unset. Another version installed is replaced.
+        version: Option<String>,
+        #[serde(flatten)]
+        options: PackageOptions,
+    },
+    /// A Node.js tool installed with `npm install --global`, such as `prettier` or `pyright`
+    Npm {
+        name: String,
+        /// The package on the npm registry
+        npm: String,
+        /// The version to install, the latest when unset. Another version installed is replaced.
+        version: Option<String>,
+        #[serde(flatten)]
+        options: PackageOptions,
+    },
+    /// A desktop application installed for the user with `flatpak install --user`, such as
+    /// `org.mozilla.firefox`
+    Flatpak {
+        name: String,
+        /// The application ID
+        flatpak: String,
+        /// The remote to install from, `flathub` when unset. Flathub is added for the user when
+        /// missing, another remote when `remote_url` is set.
+        remote: Option<String>,
+        /// The `.flatpakrepo` file of `remote`, such as
+        /// `https://dl.flathub.org/repo/flathub.flatpakrepo`
+        remote_url: Option<String>,
+        #[serde(flatten)]
+        options: PackageOptions,
+    },
+    /// An application installed with `snap install`, with sudo unless it is root
+    Snap {
+        name: String,
+        /// The snap, such as `code`
+        snap: String,
+        /// Install with classic confinement, as editors and compilers ask for
+        #[serde(default)]
+        classic: bool,
+        /// The channel to track, such as `latest/stable` or `1.2/beta`, the default of the snap
+        /// when unset
+        channel: Option<String>,
+        #[serde(flatten)]
+        options: PackageOptions,
+    },
+    /// A `.deb` or `.rpm` file installed with `dpkg -i` or `rpm -U`, for tools that only
+    /// publish packages of distributions. The first of `deb` and `rpm` this machine can install
+    /// is used. `setup` installs those missing or at another version in one run per tool, with
+    /// sudo unless it is root.
+    Distro {
+        name: String,
+        #[serde(flatten)]
+        files: DistroFiles,
+        /// The name the package manager knows the package by, such as `gh`, for telling whether
+        /// it is installed. The package name when unset.
+        package: Option<String>,
+        /// Fills `{version}` in `deb` and `rpm`, and the package is only downloaded again when
+        /// another version is installed
+        version: Option<String>,
+        #[serde(flatten)]
+        options: PackageOptions,
+    },
+    /// Installed with the package manager of the distribution or Homebrew, such as
+    /// `build-essential` or `docker.io`. `setup` installs the missing ones in one run per
+    /// package manager, with sudo unless it is root or the manager is Homebrew.
+    System {
+        name: String,
+        #[serde(flatten)]
+        names: SystemNames,
+        #[serde(flatten)]
+        options: PackageOptions,
+    },
+}
+
+/// The name of a system package for each package manager. The first of apt, dnf, pacman, brew
+/// and cask that is on PATH and named here is used, so one entry can serve Linux and macOS.
+/// At least one is needed.
+#[derive(JsonSchema, Debug, Clone, PartialEq, Default)]
+#[schemars(extend("anyOf" = [
+    { "required": ["apt"] },
+    { "required": ["dnf"] },
+    { "required": ["pacman"] },
+    { "required": ["brew"] },
+    { "required": ["cask"] },
+]))]
+pub struct SystemNames {
+    pub apt: Option<String>,
+    pub dnf: Option<String>,
+    pub pacman: Option<String>,
+    /// A Homebrew formula
+    pub brew: Option<String>,
+    /// A Homebrew cask, a macOS application such as `iterm2`
+    pub cask: Option<String>,
+}
+
+impl<'de> Deserialize<'de> for SystemNames {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Names {
+            apt: Option<String>,
+            dnf: Option<String>,
+            pacman: Option<String>,
+            brew: Option<String>,
+            cask: Option<String>,
+        }
+        let Names {
+            apt,
+            dnf,
+            pacman,
+            brew,
+            cask,
+        } = Names::deserialize(deserializer)?;
+        let names = SystemNames {
+            apt,
+            dnf,
+            pacman,
+            brew,
+            cask,
+        };
+        if names == SystemNames::default() {
+            return Err(serde::de::Error::custom(
+                "a system package needs `apt`, `dnf`, `pacman`, `brew` or `cask`",
+            ));
+        }
+        Ok(names)
+    }
+}
+
+/// The URLs of the `.deb` and `.rpm` file of a distribution package, at least one of them
+#[derive(JsonSchema, Debug, Clone, PartialEq, Default)]
+#[schemars(extend("anyOf" = [
+    { "required": ["deb"] },
+    { "required": ["rpm"] },
+]))]
+pub struct DistroFiles {
+    /// Installed with dpkg, on Debian and Ubuntu
+    pub deb: Option<String>,
+    /// Installed with rpm, on Fedora, RHEL and openSUSE
+    pub rpm: Option<String>,
+}
+
+impl<'de> Deserialize<'de> for DistroFiles {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Files {
+            deb: Option<String>,
+            rpm: Option<String>,
+        }
+        let Files { deb, rpm } = Files::deserialize(deserializer)?;
+        if deb.is_none() && rpm.is_none() {
+            return Err(serde::de::Error::custom(
+                "a distribution package needs `deb` or `rpm`",
+            ));
+        }
+        Ok(DistroFiles { deb, rpm })
+    }
+}
+
+/// The command a custom package runs
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum CommandLine {
+    /// A program and its arguments, run as they are
+    Args(Vec<String>),
+    /// A shell command line run with `sh -c`, such as
+    /// `curl -sSf https://sh.rustup.rs | sh -s -- -y`
+    Shell(String),
+}
+
+impl CommandLine {
+    /// The program to run followed by its arguments
+    pub fn args(&self) -> Vec<String> {
+        match self {
+            CommandLine::Args(args) => args.clone(),
+            CommandLine::Shell(line) => vec!["sh".to_string(), "-c".to_string(), line.clone()],
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self {
+            CommandLine::Args(args) => args.is_empty(),
+            CommandLine::Shell(line) => line.trim().is_empty(),
+        }
+    }
+}
+
+impl std::fmt::Display for CommandLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandLine::Args(args) => write!(f, "{}", args.join(" ")),
+            CommandLine::Shell(line) => write!(f, "{}", line),
+        }
+    }
+}
+
+/// Defaults for how packages are downloaded
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Default)]
+pub struct DownloadConfig {
+    /// Concurrent ranged requests used for large assets on servers that support them
+    pub segments: Option<u32>,
+    /// Minimum asset size in bytes for segmented downloads
+    pub segment_threshold: Option<u64>,
+    /// Bytes that packages installing at the same time may hold in memory together
+    pub memory_budget: Option<u64>,
+    /// Packages downloading at the same time, the others wait for one to finish
+    pub concurrency: Option<usize>,
+    /// Times a download that failed with a network error or a 5xx response is tried again
+    pub retries: Option<u32>,
+    /// Milliseconds to wait before the first retry, doubled for every further one
+    pub retry_backoff_ms: Option<u64>,
+    /// Wait a random part of the backoff on top, so packages that failed together do not all
+    /// retry at the same moment. On when unset.
+    pub retry_jitter: Option<bool>,
+    /// Proxy for every request, like `http://proxy.example.com:3128`, the hosts in `NO_PROXY`
+    /// are still reached directly. `HTTP_PROXY` and `HTTPS_PROXY` apply when unset.
+    pub proxy: Option<String>,
+    /// Headers sent with every download, values may reference environment variables like
+    /// `$ARTIFACTORY_API_KEY`
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Sent as `Authorization: Bearer <token>` with every download, usually a reference like
+    /// `$ARTIFACTORY_TOKEN` that keeps the token out of the config
+    pub auth_token: Option<String>,
+    /// Token for looking up and downloading GitHub releases, `$GITHUB_TOKEN` when unset. Without
+    /// one the API allows only a few lookups an hour, and private repositories are not found.
+    pub github_token: Option<String>,
+}
+
+/// A file of an archive package installed besides its `bin`
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct ArchiveFile {
+    /// Path of the file in the archive, or a pattern matching only it, like `bin`
+    pub entry: String,
+    /// Name of the file in the install location
+    pub dest_name: String,
+}
+
+/// Settings every kind of package accepts
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Default)]
+pub struct PackageOptions {
+    /// Install the package even if the file to install is empty
+    #[serde(default)]
+    pub allow_empty: bool,
+    /// Overrides `[workstation.download] segments` for this package
+    pub segments: Option<u32>,
+    /// Do not warn about other copies of this package on PATH
+    #[serde(default)]
+    pub allow_shadowing: bool,
+    /// Only install the package on machines where one of these profiles is active, see
+    /// `[profiles]`. Every machine installs it when empty.
+    #[serde(default)]
+    pub profiles: Vec<String>,
+    /// Groups the package belongs to, such as `devtools` or `k8s`, for `setup --tags` to install
+    /// only some of them
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Permissions of the installed files, such as `0o644` for configs and data files that must
+    /// not be executable. `0o755` when unset.
+    pub mode: Option<u32>,
+    /// Install into this directory instead of the platform `location`, such as
+    /// `~/.local/share/lsp` for language servers. Directory packages and custom commands say
+    /// where they install already.
+    pub location: Option<PathBuf>,
+    /// Only install the downloaded asset if its content has this `sha256:<hex>` digest, a cached
+    /// copy with that digest is used instead of downloading
+    #[schemars(regex(pattern = r"^sha256:[0-9a-f]{64}$"))]
+    pub pin_hash: Option<String>,
+    /// More URLs serving the same asset as `url` or `archive`, tried in order when downloading
+    /// from it fails. Templates are filled in them the same way.
+    #[serde(default)]
+    pub mirrors: Vec<String>,
+    /// Headers sent when downloading this package, replacing `[workstation.download] headers` of
+    /// the same name
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Overrides `[workstation.download] auth_token` for this package, or for a GitHub release the
+    /// `[workstation.download] github_token`
+    pub auth_token: Option<String>,
+    /// URL or local path of a minisign or cosign signature of the asset, verified with
+    /// `public_key` before anything is installed. `{url}` stands for the URL of the asset, as in
+    /// `{url}.minisig`.
+    pub signature: Option<String>,
+    /// The key `signature` must be made with: a minisign public key, or a cosign public key in
+    /// PEM. Only keys are supported, not keyless cosign signatures.
+    pub public_key: Option<String>,
+    /// URL or local path of the checksum file published with the release, such as
+    /// `SHASUMS256.txt`. The asset must match the SHA-256 or SHA-512 digest listed for its file
+    /// name. `{url}` stands for the URL of the asset, as in `{url}.sha256`.
+    pub checksum_url: Option<String>,
+    /// The SHA-256 digest of the asset in hex, as release pages list it. Assets with other
+    /// content, downloaded or local archives, are refused before anything is extracted.
+    #[schemars(regex(pattern = r"^[0-9a-fA-F]{64}$"))]
+    pub sha256: Option<String>,
+    /// A `.zsync` control file for the asset. When set, the downloaded asset is kept in the
+    /// cache and the next release only downloads the blocks that changed.
+    pub zsync_url: Option<String>,
+    /// Arguments that make the installed binary print its own completions, per shell
+    #[serde(default)]
+    pub generate_completions: BTreeMap<Shell, Vec<String>>,
+    /// Lines the package needs in each shell's rc file, kept in a block workstation manages
+    #[serde(default)]
+    pub shell_init: BTreeMap<Shell, String>,
+    /// Shell commands run in order after the package is installed, such as
+    /// `nvim --headless +Lazy! sync +qa`, with the install location first on PATH. A failing
+    /// command fails the package, which is installed again by the next `setup`.
+    #[serde(default)]
+    pub post_install: Vec<String>,
+}
+
+impl PackageConfig {
+    pub fn name(&self) -> &str {
+        match self {
+            PackageConfig::Archive { name, .. } => name,
+            PackageConfig::AutoArchArchive { name, .. } => name,
+            PackageConfig::Directory { name, .. } => name,
+            PackageConfig::Binary { name, .. } => name,
+            PackageConfig::AppImage { name, .. } => name,
+            PackageConfig::GithubRelease { name, .. } => name,
+            PackageConfig::Command { name, .. } => name,
+            PackageConfig::GitRepo { name, .. } => name,
+            PackageConfig::Cargo { name, .. } => name,
+            PackageConfig::Go { name, .. } => name,
+            PackageConfig::Pipx { name, .. } => name,
+            PackageConfig::Npm { name, .. } => name,
+            PackageConfig::Flatpak { name, .. } => name,
+            PackageConfig::Snap { name, .. } => name,
+            PackageConfig::Distro { name, .. } => name,
+            PackageConfig::System { name, .. } => name,
+        }
+    }
+
+    pub fn download_options(&self, defaults: &DownloadConfig) -> DownloadOptions {
+        DownloadOptions {
+            segments: self.options().segments.or(defaults.segments).unwrap_or(1),
+            segment_threshold: defaults
+                .segment_threshold
+                .unwrap_or(DownloadOptions::DEFAULT_SEGMENT_THRESHOLD),
+            retry: RetryPolicy {
+                retries: defaults.retries.unwrap_or(RetryPolicy::DEFAULT_RETRIES),
+                backoff: defaults
+                    .retry_backoff_ms
+                    .map(std::time::Duration::from_millis)
+                    .unwrap_or(RetryPolicy::DEFAULT_BACKOFF),
+                jitter: defaults.retry_jitter.unwrap_or(true),
+            },
+            headers: self.request_headers(defaults),
+            github_token: self.github_token(defaults),
+            offline: false,
+        }
+    }
+
+    /// The token GitHub releases are looked up and downloaded with, references not yet expanded:
+    /// the `auth_token` of the package, `[workstation.download] github_token`, or `$GITHUB_TOKEN`
+    /// when set. The `[workstation.download] auth_token` is meant for other hosts and never sent to
+    /// GitHub.
+    fn github_token(&self, defaults: &DownloadConfig) -> Option<String> {
+        if !matches!(self, PackageConfig::GithubRelease { .. }) {
+            return None;
+        }
+        self.options()
+            .auth_token
+            .clone()
+            .or_else(|| defaults.github_token.clone())
+            .or_else(|| {
+                crate::expand::Env::current()
+                    .var("GITHUB_TOKEN")
+                    .filter(|token| !token.is_empty())
+                    .map(|_| "$GITHUB_TOKEN".to_string())
+            })
+    }
+
+    /// The headers of every download request as configured, references not yet expanded.
+    /// Header names are case insensitive, so the package replaces a default in any case.
+    fn request_headers(&self, defaults: &DownloadConfig) -> Vec<(String, String)> {
+        let options = self.options();
+        let mut headers: Vec<(String, String)> = defaults
+            .headers
+            .iter()
+            .filter(|(name, _)| {
+                !options
+                    .headers
+                    .keys()
+                    .any(|replacing| replacing.eq_ignore_ascii_case(name))
+            })
+            .chain(&options.headers)
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        let (token, accept) = match self.github_token(defaults) {
+            // Only the API serves assets of private repositories, as raw content when asked to
+            Some(token) => (Some(token), Some("application/octet-stream")),
+            None if matches!(self, PackageConfig::GithubRelease { .. }) => (None, None),
+            None => (
+                options.auth_token.clone().or(defaults.auth_token.clone()),
+                None,
+            ),
+        };
+        if let Some(token) = token {
+            headers.retain(|(name, _)| !name.eq_ignore_ascii_case("authorization"));
+            headers.push(("Authorization".to_string(), format!("Bearer {}", token)));
+        }
+        if let Some(accept) = accept {
+            headers.retain(|(name, _)| !name.eq_ignore_ascii_case("accept"));
+            headers.push(("Accept".to_string(), accept.to_string()));
+        }
+        headers
+    }
+
+    /// The URL the package asset is downloaded from. Custom commands have none, and neither do
+    /// GitHub releases before the release is looked up.
+    pub fn url(&self) -> Option<&str> {
+        match self {
+            PackageConfig::Archive { archive, .. } => Some(archive),
+            PackageConfig::AutoArchArchive { archive, .. } => Some(archive),
+            PackageConfig::Directory { archive, .. } => Some(archive),
+            PackageConfig::Binary { url, .. } => Some(url),
+            PackageConfig::AppImage { appimage, .. } => Some(appimage),
+            PackageConfig::GithubRelease { .. }
+            | PackageConfig::Command { .. }
+            | PackageConfig::GitRepo { .. }
+            | PackageConfig::Cargo { .. }
+            | PackageConfig::Go { .. }
+            | PackageConfig::Pipx { .. }
+            | PackageConfig::Npm { .. }
+            | PackageConfig::Flatpak { .. }
+            | PackageConfig::Snap { .. }
+            | PackageConfig::Distro { .. }
+            | PackageConfig::System { .. } => None,
+        }
+    }
+
+    /// What kind of source the package has, for humans
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PackageConfig::Archive { .. } | PackageConfig::AutoArchArchive { .. } => "archive",
+            PackageConfig::Directory { .. } => "directory",
+            PackageConfig::Binary { .. } => "binary",
+            PackageConfig::AppImage { .. } => "appimage",
+            PackageConfig::GithubRelease { .. } => "github",
+            PackageConfig::Command { .. } => "command",
+            PackageConfig::GitRepo { .. } => "git",
+            PackageConfig::Cargo { .. } => "cargo",
+            PackageConfig::Go { .. } => "go",
+            PackageConfig::Pipx { .. } => "pipx",
+            PackageConfig::Npm { .. } => "npm",
+            PackageConfig::Flatpak { .. } => "flatpak",
+            PackageConfig::Snap { .. } => "snap",
+            PackageConfig::Distro { .. } => "distro",
+            PackageConfig::System { .. } => "system",
+        }
+    }
+
+    /// Where the package comes from, for humans: its URL or its command line
+    pub fn source(&self) -> String {
+        match self {
+            PackageConfig::Command { command, .. } => command.to_string(),
+            PackageConfig::GitRepo { git, rev: None, .. } => git.clone(),
+            PackageConfig::GitRepo {
+                git,
+                rev: Some(rev),
+                ..
+            } => format!("{}@{}", git, rev),
+            PackageConfig::GithubRelease { repo, version, .. } => {
+                format!("github:{}@{}", repo, version.as_deref().unwrap_or("latest"))
+            }
+            PackageConfig::Cargo {
+                krate,
+                version,
+                features,
+                ..
+            } => {
+                let mut source = format!(
+                    "crates.io:{}@{}",
+                    krate,
+                    version.as_deref().unwrap_or("latest")
+                );
+                if !features.is_empty() {
+                    source.push_str(&format!(" +{}", features.join(",")));
+                }
+                source
+            }
+            PackageConfig::Go { go, version, .. } => {
+                format!("go:{}@{}", go, version.as_deref().unwrap_or("latest"))
+            }
+            PackageConfig::Pipx { pipx, version, .. } => {
+                format!("pipx:{}@{}", pipx, version.as_deref().unwrap_or("latest"))
+            }
+            PackageConfig::Npm { npm, version, .. } => {
+                format!("npm:{}@{}", npm, version.as_deref().unwrap_or("latest"))
+            }
+            PackageConfig::Flatpak {
+                flatpak, remote, ..
+            } => format!(
+                "flatpak:{}/{}",
+                remote.as_deref().unwrap_or("flathub"),
+                flatpak
+            ),
+            PackageConfig::Snap { snap, channel, .. } => match channel {
+                Some(channel) => format!("snap:{}@{}", snap, channel),
+                None => format!("snap:{}", snap),
+            },
+            PackageConfig::Distro { files, .. } => [("deb", &files.deb), ("rpm", &files.rpm)]
+                .iter()
+                .filter_map(|(key, url)| Some(format!("{}:{}", key, url.as_ref()?)))
+                .collect::<Vec<_>>()
+                .join(" "),
+            PackageConfig::System { names, .. } => [
+                ("apt", &names.apt),
+                ("dnf", &names.dnf),
+                ("pacman", &names.pacman),
+                ("brew", &names.brew),
+                ("cask", &names.cask),
+            ]
+            .iter()
+            .filter_map(|(key, name)| Some(format!("{}:{}", key, name.as_ref()?)))
+            .collect::<Vec<_>>()
+            .join(" "),
+            _ => self.url().unwrap_or_default().to_string(),
+        }
+    }
+
+    pub fn is_custom_command(&self) -> bool {
+        matches!(self, PackageConfig::Command { .. })
+    }
+
+    /// Whether the package looks after what it installs itself, as custom commands, git
+    /// clones and packages of other package managers do, so that nothing is recorded or
+    /// compared about it
+    pub fn is_self_managed(&self) -> bool {
+        matches!(
+            self,
+            PackageConfig::Command { .. }
+                | PackageConfig::GitRepo { .. }
+                | PackageConfig::Pipx { .. }
+                | PackageConfig::Npm { .. }
+                | PackageConfig::Flatpak { .. }
+                | PackageConfig::Snap { .. }
+                | PackageConfig::Distro { .. }
+                | PackageConfig::System { .. }
+        )
+    }
+
+    /// Whether the package installs a single file named after it in the install location, and
+    /// not a directory or whatever a custom command creates
+    pub fn is_single_file(&self) -> bool {
+        !matches!(
+            self,
+            PackageConfig::Command { .. }
+                | PackageConfig::Directory { .. }
+                | PackageConfig::GitRepo { .. }
+                | PackageConfig::Pipx { .. }
+                | PackageConfig::Npm { .. }
+                | PackageConfig::Flatpak { .. }
+                | PackageConfig::Snap { .. }
+                | PackageConfig::Distro { .. }
+                | PackageConfig::System { .. }
+        )
+    }
+
+    /// The names of the files a single-file package writes into the install location, its own
+    /// name first
+    pub fn file_names(&self) -> Vec<&str> {
+        let mut names = vec![self.name()];
+        if let PackageConfig::Archive { files, .. } = self {
+            names.extend(files.iter().map(|file| file.dest_name.as_str()));
+        }
+        names
+    }
+
+    /// Fill in the template variables of the download URL and the archive entries: `{os}` and
+    /// `{arch}` of the target, such as `macos` and `aarch64`, `{libc}`, `gnu`, `musl` or empty,
+    /// and `{version}` from the package's `version`
+    fn fill_templates(&mut self, target: &Target) -> eyre::Result<()> {
+        let name = self.name().to_string();
+        let (fields, version): (Vec<&mut String>, _) = match self {
+            PackageConfig::Archive {
+                bin,
+                archive,
+                files,
+                version,
+                options,
+                ..
+            } => (
+                [archive, bin]
+                    .into_iter()
+                    .chain(files.iter_mut().map(|file| &mut file.entry))
+                    .chain(options.mirrors.iter_mut())
+                    .chain(options.signature.iter_mut())
+                    .chain(options.checksum_url.iter_mut())
+                    .collect(),
+                version.as_deref(),
+            ),
+            PackageConfig::AutoArchArchive {
+                auto_arch_bin,
+                archive,
+                version,
+                options,
+                ..
+            } => (
+                [archive, auto_arch_bin]
+                    .into_iter()
+                    .chain(options.mirrors.iter_mut())
+                    .chain(options.signature.iter_mut())
+                    .chain(options.checksum_url.iter_mut())
+                    .collect(),
+                version.as_deref(),
+            ),
+            PackageConfig::Directory {
+                archive,
+                directory,
+                version,
+                options,
+                ..
+            } => (
+                [archive, directory]
+                    .into_iter()
+                    .chain(options.mirrors.iter_mut())
+                    .chain(options.signature.iter_mut())
+                    .chain(options.checksum_url.iter_mut())
+                    .collect(),
+                version.as_deref(),
+            ),
+            PackageConfig::Binary {
+                url,
+                version,
+                options,
+                ..
+            }
+            | PackageConfig::AppImage {
+                appimage: url,
+                version,
+                options,
+                ..
+            } => (
+                std::iter::once(url)
+                    .chain(options.mirrors.iter_mut())
+                    .chain(options.signature.iter_mut())
+                    .chain(options.checksum_url.iter_mut())
+                    .collect(),
+                version.as_deref(),
+            ),
+            PackageConfig::Distro {
+                files,
+                version,
+                options,
+                ..
+            } => (
+                files
+                    .deb
+                    .iter_mut()
+                    .chain(files.rpm.iter_mut())
+                    .chain(options.mirrors.iter_mut())
+                    .chain(options.signature.iter_mut())
+                    .chain(options.checksum_url.iter_mut())
+                    .collect(),
+                version.as_deref(),
+            ),
+            PackageConfig::GithubRelease {
+                version,
+                asset_pattern,
+                bin,
+                options,
+                ..
+            } => (
+                asset_pattern
+                    .iter_mut()
+                    .chain(bin.iter_mut())
+                    .chain(options.signature.iter_mut())
+                    .chain(options.checksum_url.iter_mut())
+                    .collect(),
+                version.as_deref(),
+            ),
+            PackageConfig::Command { .. }
+            | PackageConfig::GitRepo { .. }
+            | PackageConfig::Cargo { .. }
+            | PackageConfig::Go { .. }
+            | PackageConfig::Pipx { .. }
+            | PackageConfig::Npm { .. }
+            | PackageConfig::Flatpak { .. }
+            | PackageConfig::Snap { .. }
+            | PackageConfig::System { .. } => return Ok(()),
+        };
+
+        for field in fields {
+            if field.contains("{version}") {
+                let Some(version) = version else {
+                    eyre::bail!(
+                        "Package {} uses {{version}} in {:?} but does not set version",
+                        name,
+                        field
+                    );
+                };
+                *field = field.replace("{version}", version);
+            }
+            *field = field
+                .replace("{os}", &target.os)
+                .replace("{arch}", &target.arch)
+                .replace("{libc}", target.libc.name());
+        }
+        Ok(())
+    }
+
+    /// The URLs the package downloads from, with the setting each one is given as
+    pub fn urls(&self) -> Vec<(&'static str, &str)> {
+        let (urls, options) = match self {
+            PackageConfig::Binary { url, options, .. } => (vec![("url", url.as_str())], options),
+            PackageConfig::AppImage {
+                appimage, options, ..
+            } => (vec![("appimage", appimage.as_str())], options),
+            PackageConfig::Distro { files, options, .. } => (
+                files
+                    .deb
+                    .iter()
+                    .map(|url| ("deb", url.as_str()))
+                    .chain(files.rpm.iter().map(|url| ("rpm", url.as_str())))
+                    .collect(),
+                options,
+            ),
+            PackageConfig::Archive {
+                archive, options, ..
+            }
+            | PackageConfig::AutoArchArchive {
+                archive, options, ..
+            }
+            | PackageConfig::Directory {
+                archive, options, ..
+            } => (vec![("archive", archive.as_str())], options),
+            PackageConfig::GitRepo { git, options, .. } => (vec![("git", git.as_str())], options),
+            PackageConfig::GithubRelease { options, .. } => (vec![], options),
+            PackageConfig::Command { .. }
+            | PackageConfig::Cargo { .. }
+            | PackageConfig::Go { .. }
+            | PackageConfig::Pipx { .. }
+            | PackageConfig::Npm { .. }
+            | PackageConfig::Flatpak { .. }
+            | PackageConfig::Snap { .. }
+            | PackageConfig::System { .. } => return vec![],
+        };
+        urls.into_iter()
+            .chain(
+                options
+                    .mirrors
+                    .iter()
+                    .map(|mirror| ("mirrors", mirror.as_str())),
+            )
+            .chain(
+                options
+                    .signature
+                    .iter()
+                    .map(|url| ("signature", url.as_str())),
+            )
+            .chain(
+                options
+                    .checksum_url
+                    .iter()
+                    .map(|url| ("checksum_url", url.as_str())),
+            )
+            .chain(
+                options
+                    .zsync_url
+                    .iter()
+                    .map(|url| ("zsync_url", url.as_str())),
+            )
+            .collect()
+    }
+
+    /// `url`, one of [`Self::urls`], with `$VAR` and `${VAR}` expanded to download from.
+    ///
+    /// URLs are only expanded when they are fetched, so that the lockfile and the manifest
+    /// record them as written on every machine. Local paths are left to be expanded when they
+    /// are read, like every other path.
+    pub fn expand_url(&self, url: &str) -> eyre::Result<String> {
+        if crate::local::local_path(url).is_some() {
+            return Ok(url.to_string());
+        }
+        let key = self
+            .urls()
+            .into_iter()
+            .find(|(_, value)| *value == url)
+            .map_or("url", |(key, _)| key);
+        expand::expand_vars(url, Field::new(key, &format!("package {}", self.name())))
+    }
+
+    /// The options of the package with the URLs among them expanded, see [`Self::expand_url`]
+    pub fn expanded_options(&self) -> eyre::Result<PackageOptions> {
+        let mut options = self.options().clone();
+        for url in options
+            .mirrors
+            .iter_mut()
+            .chain(&mut options.signature)
+            .chain(&mut options.checksum_url)
+            .chain(&mut options.zsync_url)
+        {
+            *url = self.expand_url(url)?;
+        }
+        Ok(options)
+    }
+
+    /// The directory the package's files are installed into: its own `location`, or `default`,
+    /// the `location` of the platform section
+    pub fn location<'a>(&'a self, default: &'a Path) -> &'a Path {
+        self.own_location().unwrap_or(default)
+    }
+
+    /// The directory the package is installed into instead of the platform `location`, if any:
+    /// its own `location`, or [`APPLICATIONS`] for AppImages
+    pub fn own_location(&self) -> Option<&Path> {
+        match (&self.options().location, self) {
+            (Some(location), _) => Some(location),
+            (None, PackageConfig::AppImage { .. }) => Some(Path::new(APPLICATIONS)),
+            (None, _) => None,
+        }
+    }
+
+    /// The permissions of the package's files
+    pub fn mode(&self) -> u32 {
+        self.options().mode.unwrap_or(DEFAULT_MODE)
+    }
+
+    pub fn options(&self) -> &PackageOptions {
+        match self {
+            PackageConfig::Archive { options, .. } => options,
+            PackageConfig::AutoArchArchive { options, .. } => options,
+            PackageConfig::Directory { options, .. } => options,
+            PackageConfig::Binary { options, .. } => options,
+            PackageConfig::AppImage { options, .. } => options,
+            PackageConfig::GithubRelease { options, .. } => options,
+            PackageConfig::Command { options, .. } => options,
+            PackageConfig::GitRepo { options, .. } => options,
+            PackageConfig::Cargo { options, .. } => options,
+            PackageConfig::Go { options, .. } => options,
+            PackageConfig::Pipx { options, .. } => options,
+            PackageConfig::Npm { options, .. } => options,
+            PackageConfig::Flatpak { options, .. } => options,
+            PackageConfig::Snap { options, .. } => options,
+            PackageConfig::Distro { options, .. } => options,
+            PackageConfig::System { options, .. } => options,
+        }
+    }
+
+    pub fn options_mut(&mut self) -> &mut PackageOptions {
+        match self {
+            PackageConfig::Archive { options, .. } => options,
+            PackageConfig::AutoArchArchive { options, .. } => options,
+            PackageConfig::Directory { options, .. } => options,
+            PackageConfig::Binary { options, .. } => options,
+            PackageConfig::AppImage { options, .. } => options,
+            PackageConfig::GithubRelease { options, .. } => options,
+            PackageConfig::Command { options, .. } => options,
+            PackageConfig::GitRepo { options, .. } => options,
+            PackageConfig::Cargo { options, .. } => options,
+            PackageConfig::Go { options, .. } => options,
+            PackageConfig::Pipx { options, .. } => options,
+            PackageConfig::Npm { options, .. } => options,
+            PackageConfig::Flatpak { options, .. } => options,
+            PackageConfig::Snap { options, .. } => options,
+            PackageConfig::Distro { options, .. } => options,
+            PackageConfig::System { options, .. } => options,
+        }
+    }
+}
+
+/// Fetch the config from `remote`, or read it from `path` when no remote is given, with the
+/// files it includes merged in, see [`include::resolve`].
+///
+/// A config encrypted with SOPS is decrypted in memory, see [`read_encrypted_source`] for the
+/// file as stored.
+pub fn read_config_source(remote: Option<&Url>, path: &Path) -> eyre::Result<String> {
+    read_config_source_tracking(remote, path, &|_| {})
+}
+
+/// [`read_config_source`], passing every file the config includes to `included` before it is
+/// read
+pub fn read_config_source_tracking(
+    remote: Option<&Url>,
+    path: &Path,
+    included: &dyn Fn(&Origin),
+) -> eyre::Result<String> {
+    let origin = match remote {
+        Some(url) => Origin::Url(url.clone()),
+        None => Origin::Path(path.to_path_buf()),
+    };
+    include::resolve(read_decrypted(remote, path)?, &origin, &|origin| {
+        included(origin);
+        match origin {
+            Origin::Url(url) => read_decrypted(Some(url), path),
+            Origin::Path(path) => read_decrypted(None, path),
+        }
+    })
+}
+
+fn read_decrypted(remote: Option<&Url>, path: &Path) -> eyre::Result<String> {
+    let source = read_encrypted_source(remote, path)?;
+    if !crate::sops::is_encrypted(&source) {
+        return Ok(source);
+    }
+    crate::sops::decrypt(&source).with_context(|| match remote {
+        Some(url) => format!("Decrypting config from {}", url),
+        None => format!("Decrypting config from {}", path.display()),
+    })
+}
+
+/// The config as stored, still encrypted if it is
+pub fn read_encrypted_source(remote: Option<&Url>, path: &Path) -> eyre::Result<String> {
+    match remote {
+        // The proxy setting is in the config being fetched, only the environment applies
+        Some(url) => crate::http::build(None)?
+            .get(url.clone())
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.text())
+            .with_context(|| format!("Fetching config from {}", url)),
+        None => std::fs::read_to_string(path)
+            .with_context(|| format!("Reading config from {}", path.display())),
+    }
+}
+
+/// Parse a config for the target of this run, upgrading older schema versions in memory
+pub fn parse_config(source: &str) -> eyre::Result<Config> {
+    parse_config_for(source, Target::current())
+}
+
+pub fn parse_config_for(source: &str, target: &Target) -> eyre::Result<Config> {
+    parse_config_file(source)?.select(target)
+}
+
+/// Parse a config with every platform section, upgrading older schema versions in memory
+pub fn parse_config_file(source: &str) -> eyre::Result<ConfigFile> {
+    // Migrating moves them, so they are looked for in the config as it is written
+    let written: toml_edit::DocumentMut = source.parse().context("Parsing config")?;
+    for name in SETTINGS
+        .into_iter()
+        .filter(|name| written.contains_key(name))
+    {
+        eprintln!(
+            "warning: [{}] at the top level of the config is deprecated, move it into [{}] as [{}.{}] or run `workstation migrate-config`",
+            name, ROOT_TABLE, ROOT_TABLE, name
+        );
+    }
+    let source = migrated(source)?;
+    check_keys(&source)?;
+    let mut document: toml_edit::DocumentMut = source.parse().context("Parsing config")?;
+    // Every other top level table is a platform section
+    document.remove(migrate::VERSION_KEY);
+    hoist_settings(&mut document)?;
+
+    let config: ConfigFile = toml::from_str(&document.to_string()).context("Parsing config")?;
+    config.workstation.install.ignore_set()?;
+    for package in config
+        .sections
+        .values()
+        .flat_map(|section| &section.packages)
+        .chain(&config.common.packages)
+    {
+        if let Some(pin) = &package.options().pin_hash {
+            crate::digest::validate(pin)
+                .with_context(|| format!("Invalid pin_hash of package {}", package.name()))?;
+        }
+        if let Some(sha256) = &package.options().sha256 {
+            crate::digest::from_hex(sha256)
+                .with_context(|| format!("Invalid sha256 of package {}", package.name()))?;
+        }
+    }
+
+    Ok(config)
+}
+
+/// Move the [`SETTINGS`] tables at the top level of `document` into [`ROOT_TABLE`], where they
+/// are read from. Returns the tables that were moved.
+pub fn hoist_settings(document: &mut toml_edit::DocumentMut) -> eyre::Result<Vec<&'static str>> {
+    let mut moved = vec![];
+    for name in SETTINGS {
+        let Some(item) = document.remove(name) else {
+            continue;
+        };
+        let root = document.entry(ROOT_TABLE).or_insert_with(|| {
+            let mut table = toml_edit::Table::new();
+            table.set_implicit(true);
+            toml_edit::Item::Table(table)
+        });
+        if let Some(inline) = root.as_inline_table() {
+            *root = toml_edit::Item::Table(inline.clone().into_table());
+        }
+        let root = root
+            .as_table_mut()
+            .ok_or_else(|| eyre::eyre!("`{}` in the config must be a table", ROOT_TABLE))?;
+        if root.contains_key(name) {
+            eyre::bail!(
+                "The config sets both [{}] and [{}.{}], move the keys of [{}] into [{}.{}]",
+                name,
+                ROOT_TABLE,
+                name,
+                name,
+                ROOT_TABLE,
+                name
+            );
+        }
+        root.insert(name, item);
+        moved.push(name);
+    }
+    Ok(moved)
+}
+
+/// `source` upgraded to the current schema version. A config the migrations leave alone apart
+/// from its version stays as written, locations in it are only exact then.
+pub fn migrated(source: &str) -> eyre::Result<Cow<'_, str>> {
+    let mut document: toml_edit::DocumentMut = source.parse().context("Parsing config")?;
+    let applied = migrate::migrate(&migrate::CONFIG_SCHEMA, &mut document)?;
+    if applied.is_empty() {
+        return Ok(Cow::Borrowed(source));
+    }
+    let mut original: toml_edit::DocumentMut = source.parse().context("Parsing config")?;
+    original.remove(migrate::VERSION_KEY);
+    let mut upgraded = document.clone();
+    upgraded.remove(migrate::VERSION_KEY);
+    Ok(match original.to_string() == upgraded.to_string() {
+        true => Cow::Borrowed(source),
+        false => Cow::Owned(document.to_string()),
+    })
+}
+
+/// Refuse a config with keys no setting uses, they are most likely typos
+fn check_keys(source: &str) -> eyre::Result<()> {
+    let unknown = keys::unknown_keys(source, &json_schema()).context("Parsing config")?;
+    if unknown.is_empty() {
+        return Ok(());
+    }
+    let listed: Vec<_> = unknown.iter().map(|key| format!("  {}", key)).collect();
+    if unknown_keys_allowed() {
+        eprintln!(
+            "warning: ignoring unknown keys in the config:\n{}",
+            listed.join("\n")
+        );
+        return Ok(());
+    }
+    Err(ErrorCode::UnknownConfigKey.error(format!(
+        "The config has {} unknown key(s):\n{}\nPass --allow-unknown-keys if the config is meant for a newer workstation",
+        unknown.len(),
+        listed.join("\n")
+    )))
+}
+
+/// JSON Schema of the config file, for completion and validation in editors.
+///
+/// It is generated from the types the config deserializes into, `$id` names the workstation
+/// version it describes.
+pub fn json_schema() -> serde_json::Value {
+    let mut schema = schemars::schema_for!(ConfigFile).to_value();
+    schema["$id"] = format!("urn:workstation:config:{}", env!("CARGO_PKG_VERSION")).into();
+    schema["title"] = "workstation.toml".into();
+    // Read by `migrate` before the rest of the file is deserialized
+    schema["properties"][migrate::VERSION_KEY] = serde_json::json!({
+        "description": "Version of the config format, files without it are read as version 1",
+        "type": "integer",
+        "minimum": 1,
+        "maximum": migrate::CONFIG_SCHEMA.current,
+    });
+    // The spelling from before `[workstation]`, see `hoist_settings`
+    for name in SETTINGS {
+        let mut alias = schema["$defs"]["WorkstationConfig"]["properties"][name].clone();
+        alias["deprecated"] = true.into();
+        schema["properties"][name] = alias;
+    }
+    schema
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECTIONS: &str = r#"
+[linux_x86_64]
+location = "/opt/gnu"
+packages = [{ name = "rg", url = "https://example.com/rg-{libc}" }]
+
+[linux_x86_64_static]
+location = "/opt/static"
+packages = [{ name = "rg", url = "https://example.com/rg-static-{libc}" }]
+
+[linux_aarch64]
+location = "/opt/arm"
+packages = []
+"#;
+
+    fn select(target: &str) -> eyre::Result<Config> {
+        parse_config_for(SECTIONS, &Target::parse(target).unwrap())
+    }
+
+    #[test]
+    fn test_sections_follow_the_fallback_order() {
+        let gnu = select("linux_x86_64").unwrap();
+        assert_eq!(gnu.section, "linux_x86_64");
+        assert_eq!(
+            gnu.platform.packages[0].source(),
+            "https://example.com/rg-gnu"
+        );
+
+        let musl = select("linux_x86_64_musl").unwrap();
+        assert_eq!(musl.section, "linux_x86_64_static");
+        assert_eq!(
+            musl.platform.packages[0].source(),
+            "https://example.com/rg-static-musl"
+        );
+
+        assert_eq!(select("linux_aarch64").unwrap().section, "linux_aarch64");
+    }
+
+    #[test]
+    fn test_common_packages_are_shared() {
+        let source = r#"
+[common]
+packages = [
+    { name = "fd", url = "https://example.com/fd-{libc}" },
+    { name = "rg", url = "https://example.com/rg" },
+]
+
+[linux_x86_64]
+location = "/opt/linux"
+packages = [{ name = "rg", url = "https://example.com/rg-linux" }]
+
+[darwin_arm64]
+location = "/opt/mac"
+packages = [{ name = "bat", url = "https://example.com/bat" }]
+"#;
+        let select = |target| parse_config_for(source, &Target::parse(target).unwrap()).unwrap();
+
+        let linux = select("linux_x86_64");
+        let sources: Vec<_> = linux.platform.packages.iter().map(|p| p.source()).collect();
+        assert_eq!(
+            sources,
+            ["https://example.com/fd-gnu", "https://example.com/rg-linux"]
+        );
+
+        let mac = select("macos_aarch64");
+        assert_eq!(mac.section, "darwin_arm64");
+        let names: Vec<_> = mac.platform.packages.iter().map(|p| p.name()).collect();
+        assert_eq!(names, ["fd", "rg", "bat"]);
+    }
+
+    #[test]
+    fn test_package_location_overrides_the_platform() {
+        let source = r#"
+[linux_x86_64]
+location = "~/.local/bin"
+packages = [
+    { name = "rg", url = "https://example.com/rg" },
+    { name = "rust-analyzer", url = "https://example.com/ra", location = "~/.local/share/lsp" },
+]
+"#;
+        let config = parse_config_for(source, &Target::parse("linux_x86_64").unwrap()).unwrap();
+        let location =
+            |index: usize| config.platform.packages[index].location(&config.platform.location);
+
+        assert_eq!(location(0), Path::new("~/.local/bin"));
+        assert_eq!(location(1), Path::new("~/.local/share/lsp"));
+        assert_ne!(
+            crate::lock::fields_hash(&config.platform.packages[1]),
+            crate::lock::fields_hash(&PackageConfig::Binary {
+                name: "rust-analyzer".to_string(),
+                url: "https://example.com/ra".to_string(),
+                version: None,
+                options: Default::default(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_download_headers_and_tokens() {
+        let source = r#"
+[workstation.download]
+headers = { X-JFrog-Art-Api = "$ARTIFACTORY_API_KEY", accept = "*/*" }
+auth_token = "$ARTIFACTORY_TOKEN"
+github_token = "$GH_TOKEN"
+
+[linux_x86_64]
+location = "/opt/linux"
+packages = [
+    { name = "rg", url = "https://example.com/rg" },
+    { name = "fd", url = "https://example.com/fd", headers = { Accept = "application/octet-stream" }, auth_token = "fd-token" },
+    { name = "eza", repo = "eza-community/eza" },
+]
+"#;
+        let config = parse_config_for(source, &Target::parse("linux_x86_64").unwrap()).unwrap();
+        let options =
+            |index: usize| config.platform.packages[index].download_options(&config.download);
+        let headers = |index: usize| options(index).headers;
+        let pair = |name: &str, value: &str| (name.to_string(), value.to_string());
+
+        assert_eq!(
+            headers(0),
+            [
+                pair("X-JFrog-Art-Api", "$ARTIFACTORY_API_KEY"),
+                pair("accept", "*/*"),
+                pair("Authorization", "Bearer $ARTIFACTORY_TOKEN"),
+            ]
+        );
+        assert_eq!(
+            headers(1),
+            [
+                pair("X-JFrog-Art-Api", "$ARTIFACTORY_API_KEY"),
+                pair("Accept", "application/octet-stream"),
+                pair("Authorization", "Bearer fd-token"),
+            ]
+        );
+        // The `[workstation.download]` token is not meant for GitHub, which gets its own
+        assert_eq!(
+            headers(2),
+            [
+                pair("X-JFrog-Art-Api", "$ARTIFACTORY_API_KEY"),
+                pair("Authorization", "Bearer $GH_TOKEN"),
+                pair("Accept", "application/octet-stream"),
+            ]
+        );
+        assert_eq!(options(2).github_token.as_deref(), Some("$GH_TOKEN"));
+        assert_eq!(options(0).github_token, None);
+    }
+
+    #[test]
+    fn test_profiles_are_chosen_by_hostname_or_flag() {
+        let source = r#"
+[profiles.work]
+hosts = ["corp-*", "build"]
+
+[profiles.personal]
+hosts = ["laptop"]
+
+[common]
+packages = [
+    { name = "rg", url = "https://example.com/rg" },
+    { name = "fd", url = "https://example.com/fd-personal", profiles = ["personal"] },
+]
+
+[linux_x86_64]
+location = "/opt"
+packages = [
+    { name = "fd", url = "https://example.com/fd-work", profiles = ["work"] },
+    { name = "kubectl", url = "https://example.com/kubectl", profiles = ["work"] },
+]
+"#;
+        let file = parse_config_file(source).unwrap();
+        let active =
+            |chosen: Option<&[String]>, hostname| file.active_profiles(chosen, hostname).unwrap();
+        assert_eq!(active(None, Some("CORP-42")), ["work"]);
+        assert_eq!(active(None, Some("build.example.com")), ["work"]);
+        assert_eq!(active(None, Some("laptop.local")), ["personal"]);
+        assert_eq!(active(None, Some("server")), Vec::<String>::new());
+        assert_eq!(
+            active(Some(&["personal".to_string()]), Some("corp-1")),
+            ["personal"]
+        );
+
+        let names = |active: &[&str]| {
+            let active: Vec<_> = active.iter().map(|p| p.to_string()).collect();
+            let config = file
+                .clone()
+                .select_with_profiles(&Target::parse("linux_x86_64").unwrap(), &active)
+                .unwrap();
+            config
+                .platform
+                .packages
+                .iter()
+                .map(|package| package.source())
+                .collect::<Vec<_>>()
+        };
+        // The work fd replaces the common one only on work machines
+        assert_eq!(
+            names(&["work"]),
+            [
+                "https://example.com/rg",
+                "https://example.com/fd-work",
+                "https://example.com/kubectl"
+            ]
+        );
+        assert_eq!(
+            names(&["personal"]),
+            ["https://example.com/rg", "https://example.com/fd-personal"]
+        );
+    }
+
+    #[test]
+    fn test_tags_and_names_select_packages() {
+        let config = parse_config_for(
+            r#"
+[common]
+packages = [{ name = "rg", url = "https://example.com/rg", tags = ["devtools"] }]
+
+[linux_x86_64]
+location = "/opt"
+packages = [
+    { name = "kubectl", url = "https://example.com/kubectl", tags = ["k8s", "devtools"] },
+    { name = "helm", url = "https://example.com/helm", tags = ["k8s"] },
+    { name = "jq", url = "https://example.com/jq" },
+]
+"#,
+            &Target::parse("linux_x86_64").unwrap(),
+        )
+        .unwrap();
+        let strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        let names = |config: Config| {
+            config
+                .platform
+                .packages
+                .iter()
+                .map(|package| package.name().to_string())
+                .collect::<Vec<_>>()
+        };
+        let tagged = |tags: &[&str]| names(config.with_tags(&strings(tags)));
+        let named =
+            |only: &[&str], skip: &[&str]| names(config.with_names(&strings(only), &strings(skip)));
+
+        assert_eq!(tagged(&["k8s"]), ["kubectl", "helm"]);
+        assert_eq!(tagged(&["devtools"]), ["rg", "kubectl"]);
+        assert_eq!(tagged(&["k8s", "devtools"]), ["rg", "kubectl", "helm"]);
+        assert_eq!(tagged(&[]).len(), 4);
+        assert!(tagged(&["none"]).is_empty());
+        assert_eq!(named(&["jq", "rg"], &[]), ["rg", "jq"]);
+        assert_eq!(named(&[], &["kubectl", "helm"]), ["rg", "jq"]);
+        assert_eq!(named(&["jq", "rg"], &["rg"]), ["jq"]);
+    }
+
+    #[test]
+    fn test_variables_in_urls_are_expanded_when_fetched() {
+        let home = std::env::var("HOME").unwrap();
+        let source = r#"
+[linux_x86_64]
+location = "/opt"
+packages = [
+    { name = "fd", url = "https://example.com/${HOME}/fd", mirrors = ["https://mirror.example.com$HOME/fd"] },
+    { name = "rg", archive = "$HOME/rg.tar.gz", bin = "rg" },
+    { name = "jq", url = "~/bin/jq-$UNSET_IN_TESTS" },
+    { name = "bat", url = "https://example.com/bat?price=$$5" },
+    { name = "gh", url = "https://example.com/${UNSET_IN_TESTS}/gh" },
+]
+"#;
+        let target = Target::parse("linux_x86_64").unwrap();
+
+        let config = parse_config_for(source, &target).unwrap();
+        let packages = &config.platform.packages;
+        // The config keeps them as written, for the lockfile and the manifest
+        assert_eq!(packages[0].source(), "https://example.com/${HOME}/fd");
+        assert_eq!(
+            packages[0].expand_url(&packages[0].source()).unwrap(),
+            format!("https://example.com/{}/fd", home)
+        );
+        assert_eq!(
+            packages[0].expanded_options().unwrap().mirrors,
+            [format!("https://mirror.example.com{}/fd", home)]
+        );
+        // Only a variable can tell this one is a local path
+        assert_eq!(
+            packages[1].expand_url("$HOME/rg.tar.gz").unwrap(),
+            format!("{}/rg.tar.gz", home)
+        );
+        // Local paths are expanded when they are read
+        assert_eq!(
+            packages[2].expand_url(&packages[2].source()).unwrap(),
+            "~/bin/jq-$UNSET_IN_TESTS"
+        );
+        assert_eq!(
+            packages[3].expand_url(&packages[3].source()).unwrap(),
+            "https://example.com/bat?price=$5"
+        );
+
+        let error = packages[4].expand_url(&packages[4].source()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "`url` of package gh uses $UNSET_IN_TESTS, which is not set: \"https://example.com/${UNSET_IN_TESTS}/gh\""
+        );
+    }
+
+    #[test]
+    fn test_templates_are_filled_in() {
+        let source = r#"
+[common]
+packages = [
+    { name = "rg", version = "14.1.0", archive = "https://example.com/{version}/rg-{arch}-{os}.tar.gz", bin = "rg-{version}/rg" },
+    { name = "fd", url = "https://example.com/fd-{arch}-{libc}", mirrors = ["https://mirror.example.com/fd-{arch}"] },
+    { name = "eza", repo = "eza-community/eza", version = "v0.20", asset_pattern = "eza_{arch}-*-{os}-*-{version}.tar.gz" },
+]
+
+[linux_x86_64]
+location = "/opt"
+packages = []
+
+[macos_aarch64]
+location = "/opt"
+packages = []
+"#;
+        let sources = |target| {
+            let config = parse_config_for(source, &Target::parse(target).unwrap()).unwrap();
+            let mut fields: Vec<String> = config
+                .platform
+                .packages
+                .iter()
+                .map(|p| p.source())
+                .collect();
+            match &config.platform.packages[0] {
+                PackageConfig::Archive { bin, .. } => fields.push(bin.clone()),
+                other => panic!("{:?}", other),
+            }
+            match &config.platform.packages[2] {
+                PackageConfig::GithubRelease { asset_pattern, .. } => {
+                    fields.push(asset_pattern.clone().unwrap())
+                }
+                other => panic!("{:?}", other),
+            }
+            fields
+        };
+
+        assert_eq!(
+            sources("macos_aarch64"),
+            [
+                "https://example.com/14.1.0/rg-aarch64-macos.tar.gz",
+                "https://example.com/fd-aarch64-",
+                "github:eza-community/eza@v0.20",
+                "rg-14.1.0/rg",
+                "eza_aarch64-*-macos-*-v0.20.tar.gz",
+            ]
+        );
+        assert_eq!(
+            sources("linux_x86_64")[1],
+            "https://example.com/fd-x86_64-gnu"
+        );
+        let linux = parse_config_for(source, &Target::parse("linux_x86_64").unwrap()).unwrap();
+        assert_eq!(
+            linux.platform.packages[1].options().mirrors,
+            ["https://mirror.example.com/fd-x86_64"]
+        );
+
+        let missing = r#"
+[linux_x86_64]
+location = "/opt"
+packages = [{ name = "fd", url = "https://example.com/fd-{version}" }]
+"#;
+        let error = parse_config_for(missing, &Target::parse("linux_x86_64").unwrap()).unwrap_err();
+        assert!(error.to_string().contains("uses {version}"), "{}", error);
+    }
+
+    #[test]
+    fn test_musl_host_never_gets_a_glibc_section() {
+        let error = select("linux_aarch64_musl").unwrap_err().to_string();
+
+        assert!(
+            error.contains("no section for linux_aarch64_musl"),
+            "{}",
+            error
+        );
+        assert!(error.contains("[linux_aarch64_musl], [linux_aarch64_static]"));
+    }
+
+    fn schema_errors(source: &str) -> Vec<String> {
+        let schema = json_schema();
+        let validator = jsonschema::validator_for(&schema).unwrap();
+        let config: toml::Value = toml::from_str(source).unwrap();
+        let instance = serde_json::to_value(config).unwrap();
+        validator
+            .iter_errors(&instance)
+            .map(|error| format!("{} at {}", error, error.instance_path()))
+            .collect()
+    }
+
+    #[test]
+    fn test_fixture_configs_match_the_schema() {
+        let fixtures = [
+            include_str!("../workstation.toml"),
+            SECTIONS,
+            r#"
+            schema_version = 1
+
+            [workstation.install]
+            ignore = ["*.local"]
+
+            [workstation.download]
+            segments = 4
+
+            [linux_x86_64]
+            location = "~/.local/bin"
+            packages = [
+                { name = "fd", url = "https://example.com/fd", pin_hash = "sha256:0000000000000000000000000000000000000000000000000000000000000000" },
+                { name = "tool", auto_arch_bin = "tool-*", archive = "https://example.com/t.zip" },
+                { name = "kubectl", bin = "kubectl", archive = "https://example.com/k.tar.gz", files = [{ entry = "kubectl-convert", dest_name = "kubectl-convert" }] },
+                { name = "node", archive = "https://example.com/node.tar.xz", directory = "~/.local/share/node", strip_components = 1 },
+                { name = "rustup", command = ["sh", "-c", "true"], creates = "~/.cargo/bin/rustup", shell_init = { fish = "fish_add_path ~/.cargo/bin" } },
+            ]
+            "#,
+        ];
+        for fixture in fixtures {
+            parse_config_file(fixture).unwrap();
+            assert_eq!(schema_errors(fixture), Vec::<String>::new());
+        }
+        assert!(json_schema()["$id"]
+            .as_str()
+            .unwrap()
+            .ends_with(env!("CARGO_PKG_VERSION")));
+    }
+
+    #[test]
+    fn test_settings_at_the_top_level_are_still_read() {
+        let platform = r#"
+            [linux_x86_64]
+            location = "~/.local/bin"
+            packages = []
+        "#;
+        let current = format!(
+            "[workstation]\nenv = {{ EDITOR = \"nvim\" }}\n\n[workstation.install]\nstore = true\n{}",
+            platform
+        );
+        let deprecated = format!(
+            "env = {{ EDITOR = \"nvim\" }}\n\n[install]\nstore = true\n{}",
+            platform
+        );
+
+        let file = parse_config_file(&current).unwrap();
+        assert!(file.workstation.install.store);
+        assert_eq!(file.workstation.env["EDITOR"], "nvim");
+        assert_eq!(parse_config_file(&deprecated).unwrap(), file);
+        assert_eq!(schema_errors(&deprecated), Vec::<String>::new());
+        assert!(check_keys(&deprecated).is_ok());
+        assert_eq!(json_schema()["properties"]["install"]["deprecated"], true);
+
+        let both = format!("[install]\nstore = true\n{}", current);
+        let error = parse_config_file(&both).unwrap_err();
+        assert!(format!("{:#}", error).contains("both [install] and [workstation.install]"));
+    }
+
+    #[test]
+    fn test_schema_rejects_invalid_configs() {
+        let invalid = r#"
+        [linux_x86_64]
+        location = "~/.local/bin"
+        packages = [
+            { name = "fd", url = "https://example.com/fd", pin_hash = "md5:abc" },
+            { name = "rg", url = "https://example.com/rg", shell_init = { powershell = "" } },
+            { name = "bat" },
+        ]
+        "#;
+
+        let errors = schema_errors(invalid);
+
+        assert_eq!(errors.len(), 3, "{:#?}", errors);
+        assert_eq!(schema_errors("schema_version = 3").len(), 1);
+    }
+}
@@ -0,0 +1,361 @@
+use std::{
+    collections::BTreeMap,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+/// A file a package installs
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub package: String,
+    pub directory: PathBuf,
+    pub file_name: String,
+}
+
+/// Several packages installing the same effective file name
+#[derive(Debug, PartialEq)]
+pub struct Conflict {
+    pub file_name: String,
+    /// Ordered by precedence on PATH, directories not on PATH last
+    pub targets: Vec<Target>,
+}
+
+/// Find file names installed by more than one package.
+///
+/// `path` is the list of PATH directories used to decide which copy the shell would run.
+pub fn find_conflicts(targets: &[Target], path: &[PathBuf]) -> Vec<Conflict> {
+    let mut by_name: BTreeMap<&str, Vec<&Target>> = BTreeMap::new();
+    for target in targets {
+        by_name.entry(&target.file_name).or_default().push(target);
+    }
+
+    by_name
+        .into_iter()
+        .filter(|(_, targets)| targets.len() > 1)
+        .map(|(file_name, targets)| {
+            let mut targets: Vec<Target> = targets.into_iter().cloned().collect();
+            targets.sort_by_key(|target| path_position(path, &target.directory));
+            Conflict {
+                file_name: file_name.to_string(),
+                targets,
+            }
+        })
+        .collect()
+}
+
+impl Conflict {
+    /// The file name and the packages installing it, on one line
+    pub fn summary(&self) -> String {
+        let packages: Vec<_> = self
+            .targets
+            .iter()
+            .map(|target| format!("{} ({})", target.directory.display(), target.package))
+            .collect();
+        format!(
+            "{} is installed by several packages: {}",
+            self.file_name,
+            packages.join(", ")
+        )
+    }
+}
+
+fn path_position(path: &[PathBuf], directory: &Path) -> usize {
+    path.iter()
+        .position(|entry| entry == directory)
+        .unwrap_or(usize::MAX)
+}
+
+pub fn render(conflicts: &[Conflict], path: &[PathBuf]) -> String {
+    let mut rendered = String::from("Conflicts:\n");
+
+    for conflict in conflicts {
+        rendered.push_str(&format!("  {}\n", conflict.summary()));
+
+        for pair in conflict.targets.windows(2) {
+            let (winner, loser) = (&pair[0], &pair[1]);
+            let line = if winner.directory == loser.directory {
+                format!(
+                    "{} and {} write the same file, whichever finishes last wins",
+                    winner.package, loser.package
+                )
+            } else if path_position(path, &winner.directory) == usize::MAX {
+                format!(
+                    "neither {} nor {} is on PATH",
+                    winner.directory.display(),
+                    loser.directory.display()
+                )
+            } else {
+                format!(
+                    "{} from {} shadows {} from {}",
+                    winner.directory.join(&winner.file_name).display(),
+                    winner.package,
+                    loser.directory.join(&loser.file_name).display(),
+                    loser.package
+                )
+            };
+            rendered.push_str(&format!("    {}\n", line));
+        }
+    }
+
+    rendered
+}
+
+/// Another executable on PATH with the same name as a managed file, e.g. one installed by apt
+#[derive(Debug, PartialEq)]
+pub struct Shadow {
+    pub target: Target,
+    pub other: PathBuf,
+    /// The system package owning `other`, e.g. `dpkg: ripgrep`
+    pub owner: Option<String>,
+    /// Whether the managed copy comes first on PATH
+    pub managed_first: bool,
+}
+
+impl Shadow {
+    /// Where the other executable is and which one the shell runs, on one line
+    pub fn summary(&self) -> String {
+        let owner = match &self.owner {
+            Some(owner) => format!(" (owned by {})", owner),
+            None => String::new(),
+        };
+        let order = if self.managed_first {
+            format!("{} precedes it", self.target.directory.display())
+        } else {
+            format!(
+                "it precedes {}, the installed copy is shadowed",
+                self.target.directory.display()
+            )
+        };
+        format!(
+            "{} is also present at {}{}; {}",
+            self.target.file_name,
+            self.other.display(),
+            owner,
+            order
+        )
+    }
+}
+
+/// Find executables on `path` outside the managed directories that have the name of a target.
+///
+/// `owner` looks up which system package manager owns a file, see [`system_owner`].
+pub fn find_shadows(
+    targets: &[Target],
+    path: &[PathBuf],
+    owner: impl Fn(&Path) -> Option<String>,
+) -> Vec<Shadow> {
+    let mut shadows = vec![];
+
+    for target in targets {
+        let position = path_position(path, &target.directory);
+        if position == usize::MAX {
+            // Not being on PATH at all is reported separately
+            continue;
+        }
+        let managed = target.directory.join(&target.file_name).canonicalize().ok();
+
+        for (index, directory) in path.iter().enumerate() {
+            if targets.iter().any(|t| t.directory == *directory) {
+                continue;
+            }
+            let other = directory.join(&target.file_name);
+            if !is_executable(&other) || other.canonicalize().ok() == managed {
+                continue;
+            }
+            if shadows
+                .iter()
+                .any(|s: &Shadow| s.other == other && s.target == *target)
+            {
+                continue;
+            }
+            shadows.push(Shadow {
+                owner: owner(&other),
+                target: target.clone(),
+                other,
+                managed_first: position < index,
+            });
+        }
+    }
+
+    shadows
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+/// The dpkg or pacman package owning `path`, when either is installed
+pub fn system_owner(path: &Path) -> Option<String> {
+    let query = |program: &str, args: &[&str]| {
+        let output = Command::new(program)
+            .args(args)
+            .arg(path)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .ok()
+            .filter(|output| output.status.success())?;
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+
+    // dpkg prints `ripgrep: /usr/bin/rg`
+    if let Some(line) = query("dpkg-query", &["-S"]) {
+        let package = line.split(':').next().unwrap_or(&line);
+        return Some(format!("dpkg: {}", package));
+    }
+    query("pacman", &["-Qoq"]).map(|package| format!("pacman: {}", package))
+}
+
+pub fn render_shadows(shadows: &[Shadow]) -> String {
+    let mut rendered = String::from("Shadowing:\n");
+
+    for shadow in shadows {
+        rendered.push_str(&format!("  {}\n", shadow.summary()));
+    }
+    rendered.push_str("  Set allow_shadowing = true on a package to silence this\n");
+
+    rendered
+}
+
+/// The directories on `PATH`, in order
+pub fn path_directories() -> Vec<PathBuf> {
+    std::env::var_os("PATH")
+        .map(|path| std::env::split_paths(&path).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(package: &str, directory: &str, file_name: &str) -> Target {
+        Target {
+            package: package.to_string(),
+            directory: PathBuf::from(directory),
+            file_name: file_name.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_unique_names_do_not_conflict() {
+        let targets = [
+            target("rg", "/home/u/.local/bin", "rg"),
+            target("fd", "/home/u/.local/bin", "fd"),
+        ];
+
+        assert!(find_conflicts(&targets, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_path_order_decides_shadowing() {
+        let path = [
+            PathBuf::from("/opt/team/bin"),
+            PathBuf::from("/home/u/.local/bin"),
+        ];
+        let targets = [
+            target("ripgrep", "/home/u/.local/bin", "rg"),
+            target("team-rg", "/opt/team/bin", "rg"),
+        ];
+
+        let conflicts = find_conflicts(&targets, &path);
+
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].targets[0].package, "team-rg");
+        let rendered = render(&conflicts, &path);
+        assert!(rendered
+            .contains("/opt/team/bin/rg from team-rg shadows /home/u/.local/bin/rg from ripgrep"));
+    }
+
+    #[test]
+    fn test_same_directory_is_reported_as_overwrite() {
+        let targets = [
+            target("nvim", "/home/u/.local/bin", "nvim"),
+            target("neovim", "/home/u/.local/bin", "nvim"),
+        ];
+
+        let conflicts = find_conflicts(&targets, &[]);
+
+        assert!(render(&conflicts, &[]).contains("whichever finishes last wins"));
+    }
+
+    #[test]
+    fn test_directories_off_path() {
+        let targets = [target("a", "/one", "tool"), target("b", "/two", "tool")];
+
+        let conflicts = find_conflicts(&targets, &[]);
+
+        assert!(render(&conflicts, &[]).contains("neither /one nor /two is on PATH"));
+    }
+
+    fn executable(directory: &Path, name: &str) {
+        std::fs::create_dir_all(directory).unwrap();
+        let path = directory.join(name);
+        std::fs::write(&path, b"#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn test_system_copies_are_found_on_both_sides() {
+        let root = tempfile::tempdir().unwrap();
+        let managed = root.path().join("home/.local/bin");
+        let early = root.path().join("opt/bin");
+        let late = root.path().join("usr/bin");
+        executable(&managed, "rg");
+        executable(&early, "fd");
+        executable(&late, "rg");
+        executable(&managed, "fd");
+        let path = [early.clone(), managed.clone(), late.clone()];
+        let targets = [
+            Target {
+                package: "rg".to_string(),
+                directory: managed.clone(),
+                file_name: "rg".to_string(),
+            },
+            Target {
+                package: "fd".to_string(),
+                directory: managed.clone(),
+                file_name: "fd".to_string(),
+            },
+        ];
+
+        let shadows = find_shadows(&targets, &path, |other| {
+            other
+                .ends_with("usr/bin/rg")
+                .then(|| "dpkg: ripgrep".to_string())
+        });
+
+        assert_eq!(shadows.len(), 2);
+        let rendered = render_shadows(&shadows);
+        assert!(rendered.contains(&format!(
+            "rg is also present at {} (owned by dpkg: ripgrep); {} precedes it",
+            late.join("rg").display(),
+            managed.display()
+        )));
+        assert!(rendered.contains(&format!(
+            "fd is also present at {}; it precedes {}, the installed copy is shadowed",
+            early.join("fd").display(),
+            managed.display()
+        )));
+    }
+
+    #[test]
+    fn test_non_executables_and_symlinks_to_ours_are_ignored() {
+        let root = tempfile::tempdir().unwrap();
+        let managed = root.path().join("bin");
+        let other = root.path().join("other");
+        executable(&managed, "rg");
+        std::fs::create_dir_all(&other).unwrap();
+        std::os::unix::fs::symlink(managed.join("rg"), other.join("rg")).unwrap();
+        std::fs::write(other.join("fd"), b"not executable").unwrap();
+        let targets = [
+            target("rg", managed.to_str().unwrap(), "rg"),
+            target("fd", managed.to_str().unwrap(), "fd"),
+        ];
+
+        let shadows = find_shadows(&targets, &[managed.clone(), other], |_| None);
+
+        assert_eq!(shadows, vec![]);
+    }
+}
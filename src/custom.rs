@@ -0,0 +1,252 @@
+use std::{
+    ffi::OsStr,
+    io::{BufRead, BufReader, Read},
+    path::Path,
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use eyre::Context;
+
+use crate::{config::CommandLine, expand::Env};
+
+/// Commands without a configured `timeout` are killed after this long
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Lines of output shown when a command fails
+const OUTPUT_LINES: usize = 20;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Run a custom install `command` and check that it created `creates`.
+///
+/// Output is captured rather than shown, the tail of it is part of the error on failure.
+pub fn run(command: &CommandLine, creates: &Path, timeout: Duration) -> eyre::Result<()> {
+    let output = capture(&command.args(), &command.to_string(), &[], timeout)?;
+    if !creates.exists() {
+        eyre::bail!(
+            "`{}` succeeded but did not create {}{}",
+            command,
+            creates.display(),
+            tail(&output)
+        );
+    }
+
+    Ok(())
+}
+
+/// Run a `post_install` command of a package with `sh -c`, `directory` coming first on PATH so
+/// that it finds what was just installed there.
+///
+/// Output is captured like that of custom commands.
+pub fn run_hook(script: &str, directory: &Path, timeout: Duration) -> eyre::Result<()> {
+    let command = ["sh".to_string(), "-c".to_string(), script.to_string()];
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    let path = std::env::join_paths(
+        std::iter::once(directory.to_path_buf()).chain(std::env::split_paths(&path)),
+    )
+    .with_context(|| format!("Adding {} to PATH", directory.display()))?;
+    capture(&command, script, &[("PATH", &path)], timeout)?;
+    Ok(())
+}
+
+/// Run `command`, shown as `shown` in errors, with the environment variables `envs` set,
+/// returning its output when it succeeds
+pub fn capture(
+    command: &[String],
+    shown: &str,
+    envs: &[(&str, &OsStr)],
+    timeout: Duration,
+) -> eyre::Result<Vec<u8>> {
+    capture_lines(command, shown, envs, timeout, |_| {})
+}
+
+/// Like [`capture`], calling `on_line` with each line the command writes to stderr while it
+/// runs, to show its progress
+pub fn capture_lines(
+    command: &[String],
+    shown: &str,
+    envs: &[(&str, &OsStr)],
+    timeout: Duration,
+    on_line: impl Fn(&str) + Send + 'static,
+) -> eyre::Result<Vec<u8>> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| eyre::eyre!("`command` must not be empty"))?;
+
+    let mut child = Command::new(program);
+    // A sandboxed run points the command at the sandbox home, see `setup --sandbox`
+    if let Some(home) = Env::current().sandbox_home() {
+        child.env("HOME", home);
+    }
+    child.envs(envs.iter().copied());
+    let mut child = child
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Running {}", program))?;
+
+    // Drain both pipes while waiting, a full pipe would stall the command
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+    let stdout = std::thread::spawn(move || {
+        let mut output = vec![];
+        let _ = stdout.read_to_end(&mut output);
+        output
+    });
+    let stderr = std::thread::spawn(move || {
+        let mut output = vec![];
+        let mut stderr = BufReader::new(stderr);
+        loop {
+            let start = output.len();
+            match stderr.read_until(b'\n', &mut output) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => on_line(String::from_utf8_lossy(&output[start..]).trim_end()),
+            }
+        }
+        output
+    });
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if started.elapsed() > timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            eyre::bail!(
+                "`{}` did not finish within {}s and was killed",
+                shown,
+                timeout.as_secs()
+            );
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    };
+
+    let mut output = stdout.join().unwrap_or_default();
+    output.extend(stderr.join().unwrap_or_default());
+    if !status.success() {
+        eyre::bail!("`{}` exited with {}{}", shown, status, tail(&output));
+    }
+
+    Ok(output)
+}
+
+fn tail(output: &[u8]) -> String {
+    let output = String::from_utf8_lossy(output);
+    let lines: Vec<_> = output.lines().collect();
+    if lines.is_empty() {
+        return String::new();
+    }
+    let skip = lines.len().saturating_sub(OUTPUT_LINES);
+    format!("\nOutput:\n  {}", lines[skip..].join("\n  "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> CommandLine {
+        CommandLine::Args(vec!["sh".to_string(), "-c".to_string(), script.to_string()])
+    }
+
+    #[test]
+    fn test_command_creates_the_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let creates = directory.path().join("weirdtool");
+        let script = format!("echo installing; touch {}", creates.display());
+
+        run(&sh(&script), &creates, DEFAULT_TIMEOUT).unwrap();
+
+        assert!(creates.exists());
+    }
+
+    #[test]
+    fn test_command_line_runs_in_a_shell() {
+        let directory = tempfile::tempdir().unwrap();
+        let creates = directory.path().join("weirdtool");
+        let line = format!("echo installed | cat > {}", creates.display());
+
+        run(&CommandLine::Shell(line.clone()), &creates, DEFAULT_TIMEOUT).unwrap();
+        assert_eq!(std::fs::read_to_string(&creates).unwrap(), "installed\n");
+
+        let error = run(
+            &CommandLine::Shell("exit 4".to_string()),
+            &creates,
+            DEFAULT_TIMEOUT,
+        )
+        .unwrap_err();
+        assert!(error.to_string().starts_with("`exit 4` exited with"));
+    }
+
+    #[test]
+    fn test_failure_shows_the_output() {
+        let directory = tempfile::tempdir().unwrap();
+
+        let error = run(
+            &sh("echo 'vendor says no' >&2; exit 3"),
+            &directory.path().join("weirdtool"),
+            DEFAULT_TIMEOUT,
+        )
+        .unwrap_err();
+
+        let message = error.to_string();
+        assert!(message.contains("exit status: 3"), "{}", message);
+        assert!(message.contains("vendor says no"), "{}", message);
+    }
+
+    #[test]
+    fn test_missing_creates_is_an_error() {
+        let directory = tempfile::tempdir().unwrap();
+
+        let error = run(
+            &sh("true"),
+            &directory.path().join("weirdtool"),
+            DEFAULT_TIMEOUT,
+        )
+        .unwrap_err();
+
+        assert!(error.to_string().contains("did not create"));
+    }
+
+    #[test]
+    fn test_hooks_find_what_was_installed() {
+        let directory = tempfile::tempdir().unwrap();
+        let tool = directory.path().join("weirdtool");
+        std::fs::write(&tool, "#!/bin/sh\necho configured > \"$1\"\n").unwrap();
+        std::fs::set_permissions(&tool, std::os::unix::fs::PermissionsExt::from_mode(0o755))
+            .unwrap();
+        let created = directory.path().join("config");
+
+        let script = format!("weirdtool {}", created.display());
+        run_hook(&script, directory.path(), DEFAULT_TIMEOUT).unwrap();
+        assert_eq!(std::fs::read_to_string(&created).unwrap(), "configured\n");
+
+        let error = run_hook(
+            "echo 'no plugins' >&2; false",
+            directory.path(),
+            DEFAULT_TIMEOUT,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("no plugins"), "{}", error);
+    }
+
+    #[test]
+    fn test_slow_command_is_killed() {
+        let directory = tempfile::tempdir().unwrap();
+        let started = Instant::now();
+
+        let error = run(
+            &sh("sleep 10"),
+            &directory.path().join("weirdtool"),
+            Duration::from_millis(200),
+        )
+        .unwrap_err();
+
+        assert!(error.to_string().contains("was killed"));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
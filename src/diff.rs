@@ -0,0 +1,323 @@
+use std::path::{Path, PathBuf};
+
+use console::Style;
+
+/// Unchanged lines shown around every change
+const CONTEXT: usize = 3;
+/// Diff lines printed per file before the rest is cut off
+const MAX_LINES: usize = 200;
+/// Above this many compared line pairs the files are shown as fully replaced
+const MAX_COMPARISONS: usize = 4_000_000;
+
+/// What would change in a single file
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileDiff {
+    pub path: PathBuf,
+    pub change: Change,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// Unified diff lines, without the file header
+    Text(Vec<Line>),
+    Binary {
+        old_len: usize,
+        new_len: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Line {
+    Hunk(String),
+    Context(String),
+    Removed(String),
+    Added(String),
+}
+
+impl FileDiff {
+    /// Compare the current content of `path` with the content that would be written.
+    ///
+    /// `old` is `None` when the file does not exist yet. Returns `None` when nothing would change.
+    /// This only computes the diff, so it is safe to call from several workers at once.
+    pub fn compute(path: &Path, old: Option<&[u8]>, new: &[u8]) -> Option<FileDiff> {
+        if old == Some(new) {
+            return None;
+        }
+        let old_bytes = old.unwrap_or_default();
+
+        let change = match (std::str::from_utf8(old_bytes), std::str::from_utf8(new)) {
+            (Ok(old_text), Ok(new_text)) if !is_binary(old_bytes) && !is_binary(new) => {
+                Change::Text(unified(old_text, new_text))
+            }
+            _ => Change::Binary {
+                old_len: old_bytes.len(),
+                new_len: new.len(),
+            },
+        };
+
+        Some(FileDiff {
+            path: path.to_path_buf(),
+            change,
+        })
+    }
+
+    pub fn render(&self, color: bool) -> String {
+        let path = self.path.display();
+        let lines = match &self.change {
+            Change::Binary { old_len, new_len } => {
+                return format!(
+                    "binary files differ: {} ({} bytes \u{2192} {} bytes)\n",
+                    path, old_len, new_len
+                );
+            }
+            Change::Text(lines) => lines,
+        };
+
+        let paint = |style: Style, text: String| {
+            if color {
+                style.apply_to(text).to_string()
+            } else {
+                text
+            }
+        };
+
+        let mut rendered = paint(Style::new().bold(), format!("--- {}\n+++ {}", path, path));
+        rendered.push('\n');
+        for line in lines.iter().take(MAX_LINES) {
+            let line = match line {
+                Line::Hunk(text) => paint(Style::new().cyan(), text.clone()),
+                Line::Context(text) => format!(" {}", text),
+                Line::Removed(text) => paint(Style::new().red(), format!("-{}", text)),
+                Line::Added(text) => paint(Style::new().green(), format!("+{}", text)),
+            };
+            rendered.push_str(&line);
+            rendered.push('\n');
+        }
+        if lines.len() > MAX_LINES {
+            rendered.push_str(&format!(
+                "... {} more diff lines not shown\n",
+                lines.len() - MAX_LINES
+            ));
+        }
+
+        rendered
+    }
+}
+
+/// Render every diff sorted by path, followed by the number of changed files.
+///
+/// Workers compute their diffs independently and hand them over once finished, so the output
+/// never interleaves no matter in which order they arrive.
+pub fn render_all(diffs: &mut [FileDiff], color: bool) -> String {
+    diffs.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut rendered = String::new();
+    for diff in diffs.iter() {
+        rendered.push_str(&diff.render(color));
+    }
+    rendered.push_str(&format!("{} file(s) would change\n", diffs.len()));
+
+    rendered
+}
+
+fn is_binary(bytes: &[u8]) -> bool {
+    bytes.contains(&0)
+}
+
+enum Op<'a> {
+    Equal(&'a str),
+    Delete(&'a str),
+    Insert(&'a str),
+}
+
+/// Line operations turning `old` into `new`, from the longest common subsequence
+fn operations<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Op<'a>> {
+    if old.len().saturating_mul(new.len()) > MAX_COMPARISONS {
+        return old
+            .iter()
+            .map(|line| Op::Delete(line))
+            .chain(new.iter().map(|line| Op::Insert(line)))
+            .collect();
+    }
+
+    // lengths[i][j] is the common subsequence length of old[i..] and new[j..]
+    let width = new.len() + 1;
+    let mut lengths = vec![0u32; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i * width + j] = if old[i] == new[j] {
+                lengths[(i + 1) * width + j + 1] + 1
+            } else {
+                lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut ops = vec![];
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            ops.push(Op::Equal(old[i]));
+            i += 1;
+            j += 1;
+        } else if j == new.len()
+            || (i < old.len() && lengths[(i + 1) * width + j] >= lengths[i * width + j + 1])
+        {
+            ops.push(Op::Delete(old[i]));
+            i += 1;
+        } else {
+            ops.push(Op::Insert(new[j]));
+            j += 1;
+        }
+    }
+
+    ops
+}
+
+fn unified(old: &str, new: &str) -> Vec<Line> {
+    let old_lines: Vec<_> = old.lines().collect();
+    let new_lines: Vec<_> = new.lines().collect();
+    let ops = operations(&old_lines, &new_lines);
+
+    let changed: Vec<usize> = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| !matches!(op, Op::Equal(_)))
+        .map(|(index, _)| index)
+        .collect();
+
+    // Group changes whose context overlaps into the same hunk
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    for index in changed {
+        let start = index.saturating_sub(CONTEXT);
+        let end = (index + CONTEXT + 1).min(ops.len());
+        match ranges.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+
+    let mut lines = vec![];
+    for (start, end) in ranges {
+        // Line numbers of the hunk start in both files
+        let (mut old_line, mut new_line) = (1, 1);
+        for op in &ops[..start] {
+            match op {
+                Op::Equal(_) => {
+                    old_line += 1;
+                    new_line += 1;
+                }
+                Op::Delete(_) => old_line += 1,
+                Op::Insert(_) => new_line += 1,
+            }
+        }
+        let hunk = &ops[start..end];
+        let old_count = hunk
+            .iter()
+            .filter(|op| !matches!(op, Op::Insert(_)))
+            .count();
+        let new_count = hunk
+            .iter()
+            .filter(|op| !matches!(op, Op::Delete(_)))
+            .count();
+        lines.push(Line::Hunk(format!(
+            "@@ -{},{} +{},{} @@",
+            if old_count == 0 {
+                old_line - 1
+            } else {
+                old_line
+            },
+            old_count,
+            if new_count == 0 {
+                new_line - 1
+            } else {
+                new_line
+            },
+            new_count
+        )));
+
+        for op in hunk {
+            lines.push(match op {
+                Op::Equal(line) => Line::Context(line.to_string()),
+                Op::Delete(line) => Line::Removed(line.to_string()),
+                Op::Insert(line) => Line::Added(line.to_string()),
+            });
+        }
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unchanged_file_has_no_diff() {
+        assert_eq!(
+            FileDiff::compute(Path::new("a"), Some(b"x\n"), b"x\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_renders_unified_hunks() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let new = "1\n2\n3\n4\nfive\n6\n7\n8\n9\n10\n";
+
+        let diff = FileDiff::compute(
+            Path::new("config.toml"),
+            Some(old.as_bytes()),
+            new.as_bytes(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            diff.render(false),
+            "--- config.toml\n+++ config.toml\n@@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n"
+        );
+    }
+
+    #[test]
+    fn test_new_file_is_all_additions() {
+        let diff = FileDiff::compute(Path::new("new"), None, b"a\nb\n").unwrap();
+
+        assert!(diff.render(false).ends_with("@@ -0,0 +1,2 @@\n+a\n+b\n"));
+    }
+
+    #[test]
+    fn test_binary_files_show_sizes() {
+        let diff = FileDiff::compute(Path::new("bin"), Some(&[0, 1, 2]), &[0, 1, 2, 3]).unwrap();
+
+        assert_eq!(
+            diff.render(false),
+            "binary files differ: bin (3 bytes \u{2192} 4 bytes)\n"
+        );
+    }
+
+    #[test]
+    fn test_large_diffs_are_truncated() {
+        let new: String = (0..500).map(|line| format!("{}\n", line)).collect();
+
+        let rendered = FileDiff::compute(Path::new("big"), None, new.as_bytes())
+            .unwrap()
+            .render(false);
+
+        assert!(rendered.contains("\n+198\n"));
+        assert!(!rendered.contains("\n+199\n"));
+        assert!(rendered.ends_with("... 301 more diff lines not shown\n"));
+    }
+
+    #[test]
+    fn test_render_all_is_sorted_and_counted() {
+        let mut diffs = vec![
+            FileDiff::compute(Path::new("b"), None, b"b\n").unwrap(),
+            FileDiff::compute(Path::new("a"), None, b"a\n").unwrap(),
+        ];
+
+        let rendered = render_all(&mut diffs, false);
+
+        assert!(rendered.find("--- a").unwrap() < rendered.find("--- b").unwrap());
+        assert!(rendered.ends_with("2 file(s) would change\n"));
+    }
+}
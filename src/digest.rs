@@ -0,0 +1,84 @@
+use sha2::{Digest, Sha256};
+
+const PREFIX: &str = "sha256:";
+
+/// The `sha256:<hex>` digest of `bytes`
+pub fn sha256(bytes: &[u8]) -> String {
+    finish(Sha256::new_with_prefix(bytes))
+}
+
+/// The digest of everything `reader` reads, hashed as a stream
+pub fn sha256_reader(mut reader: impl std::io::Read) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut chunk = vec![0; 64 * 1024];
+    loop {
+        let read = reader.read(&mut chunk)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&chunk[..read]);
+    }
+    Ok(finish(hasher))
+}
+
+/// Format a finished hasher like [`sha256`]
+pub fn finish(hasher: Sha256) -> String {
+    let hex: String = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("{}{}", PREFIX, hex)
+}
+
+/// Check that `digest` looks like `sha256:` followed by 64 lowercase hex digits
+pub fn validate(digest: &str) -> eyre::Result<&str> {
+    let hex = digest
+        .strip_prefix(PREFIX)
+        .ok_or_else(|| eyre::eyre!("{:?} must start with {:?}", digest, PREFIX))?;
+    if hex.len() != 64
+        || !hex
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    {
+        eyre::bail!(
+            "{:?} must have 64 lowercase hex digits after {:?}",
+            digest,
+            PREFIX
+        );
+    }
+    Ok(hex)
+}
+
+/// The digest of the bare SHA-256 `hex` a release page lists, such as the `sha256` of a package,
+/// formatted like [`sha256`]. Either case is accepted.
+pub fn from_hex(hex: &str) -> eyre::Result<String> {
+    let digest = format!("{}{}", PREFIX, hex.to_ascii_lowercase());
+    if validate(&digest).is_err() {
+        eyre::bail!("{:?} must have 64 hex digits", hex);
+    }
+    Ok(digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_digest_round_trips_through_validate() {
+        let digest = sha256(b"hello");
+
+        assert_eq!(
+            digest,
+            "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        assert!(validate(&digest).is_ok());
+        assert!(validate("sha256:2CF2").is_err());
+        assert!(validate("md5:abc").is_err());
+        assert_eq!(
+            from_hex("2CF24DBA5FB0A30E26E83B2AC5B9E29E1B161E5C1FA7425E73043362938B9824").unwrap(),
+            digest
+        );
+        assert!(from_hex("2cf24dba").is_err());
+    }
+}
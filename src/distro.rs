@@ -0,0 +1,220 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use eyre::Context;
+
+use crate::{config::PackageConfig, conflicts, system};
+
+/// The format of a distribution package, by the tool installing it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Format {
+    Deb,
+    Rpm,
+}
+
+impl Format {
+    /// dpkg first, rpm is found on Debian too where it is not the package manager
+    const ALL: [Format; 2] = [Format::Deb, Format::Rpm];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Deb => "deb",
+            Format::Rpm => "rpm",
+        }
+    }
+
+    fn program(&self) -> &'static str {
+        match self {
+            Format::Deb => "dpkg",
+            Format::Rpm => "rpm",
+        }
+    }
+
+    fn is_available(&self) -> bool {
+        conflicts::path_directories()
+            .iter()
+            .any(|directory| directory.join(self.program()).is_file())
+    }
+
+    /// The URL `package` has for this format, if it sets one
+    fn url<'a>(&self, package: &'a PackageConfig) -> Option<&'a str> {
+        let PackageConfig::Distro { files, .. } = package else {
+            return None;
+        };
+        match self {
+            Format::Deb => files.deb.as_deref(),
+            Format::Rpm => files.rpm.as_deref(),
+        }
+    }
+
+    /// The version of `name` installed, if it is
+    pub fn installed_version(&self, name: &str) -> eyre::Result<Option<String>> {
+        let args: &[&str] = match self {
+            Format::Deb => &[
+                "dpkg-query",
+                "-W",
+                "-f=${db:Status-Abbrev} ${Version}",
+                name,
+            ],
+            Format::Rpm => &["rpm", "-q", "--queryformat", "%{VERSION}-%{RELEASE}", name],
+        };
+        let output = Command::new(args[0])
+            .args(&args[1..])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .with_context(|| format!("Running {}", args[0]))?;
+        if !output.status.success() {
+            return Ok(None);
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(match self {
+            // Removed packages keep their status, only `ii` is installed
+            Format::Deb => stdout
+                .strip_prefix("ii")
+                .map(|version| version.trim().to_string()),
+            Format::Rpm => Some(stdout.trim().to_string()),
+        })
+    }
+
+    /// The name and version of the package in `file`
+    pub fn file_version(&self, file: &Path) -> eyre::Result<(String, String)> {
+        let file = file.to_string_lossy();
+        let args: &[&str] = match self {
+            Format::Deb => &[
+                "dpkg-deb",
+                "--show",
+                "--showformat=${Package} ${Version}",
+                &file,
+            ],
+            Format::Rpm => &[
+                "rpm",
+                "-q",
+                "-p",
+                "--queryformat",
+                "%{NAME} %{VERSION}-%{RELEASE}",
+                &file,
+            ],
+        };
+        let output = Command::new(args[0])
+            .args(&args[1..])
+            .stdin(Stdio::null())
+            .output()
+            .with_context(|| format!("Running {}", args[0]))?;
+        if !output.status.success() {
+            eyre::bail!(
+                "`{}` failed, the download is no {} package: {}",
+                args.join(" "),
+                self.extension(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let (name, version) = stdout
+            .trim()
+            .split_once(' ')
+            .ok_or_else(|| eyre::eyre!("`{}` printed {:?}", args.join(" "), stdout.trim()))?;
+        Ok((name.to_string(), version.to_string()))
+    }
+
+    /// The command line installing `files` over the versions installed. rpm needs `-U` for
+    /// that, `-i` refuses a package that is installed at any version.
+    fn install_args(&self, files: &[PathBuf]) -> Vec<String> {
+        let args: &[&str] = match self {
+            Format::Deb => &["dpkg", "-i"],
+            Format::Rpm => &["rpm", "-U", "--replacepkgs", "--oldpackage"],
+        };
+        let mut args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        if !system::is_root() {
+            args.insert(0, "sudo".to_string());
+        }
+        args.extend(files.iter().map(|file| file.to_string_lossy().into_owned()));
+        args
+    }
+
+    /// Install `files` in one run. sudo may ask for a password on the terminal, so the command
+    /// inherits it and its output is shown.
+    pub fn install(&self, files: &[PathBuf]) -> eyre::Result<()> {
+        let args = self.install_args(files);
+        let status = Command::new(&args[0])
+            .args(&args[1..])
+            .status()
+            .with_context(|| format!("Running {}", args[0]))?;
+        if !status.success() {
+            eyre::bail!("`{}` failed with {}", args.join(" "), status);
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.program())
+    }
+}
+
+/// The first format this machine installs that `package` has a file for, with its URL
+pub fn resolve(package: &PackageConfig) -> Option<(Format, &str)> {
+    Format::ALL
+        .iter()
+        .filter(|format| format.is_available())
+        .find_map(|format| Some((*format, format.url(package)?)))
+}
+
+/// The name the package manager knows `package` by
+pub fn package_name(package: &PackageConfig) -> &str {
+    match package {
+        PackageConfig::Distro {
+            package: Some(name),
+            ..
+        } => name,
+        _ => package.name(),
+    }
+}
+
+/// The version of `package` installed, if it is
+pub fn installed_version(package: &PackageConfig) -> eyre::Result<Option<String>> {
+    match resolve(package) {
+        Some((format, _)) => format.installed_version(package_name(package)),
+        None => Ok(None),
+    }
+}
+
+/// Whether `installed`, a version as the package manager reports it, is `version` as configured.
+/// The revision of the package may follow it, as `2.40.1-1` does for `2.40.1`.
+pub fn is_version(installed: &str, version: &str) -> bool {
+    let installed = installed
+        .split_once(':')
+        .map_or(installed, |(_, version)| version);
+    let version = version.strip_prefix('v').unwrap_or(version);
+    installed == version
+        || installed
+            .strip_prefix(version)
+            .is_some_and(|revision| revision.starts_with('-'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_versions_match_with_revisions_and_epochs() {
+        assert!(is_version("2.40.1", "2.40.1"));
+        assert!(is_version("2.40.1-1", "v2.40.1"));
+        assert!(is_version("1:2.40.1-1ubuntu2", "2.40.1"));
+        assert!(!is_version("2.40.10", "2.40.1"));
+        assert!(!is_version("2.40.1", "2.40.1-1"));
+
+        let mut args = Format::Rpm.install_args(&[PathBuf::from("/tmp/gh.rpm")]);
+        if args[0] == "sudo" {
+            args.remove(0);
+        }
+        assert_eq!(
+            args,
+            ["rpm", "-U", "--replacepkgs", "--oldpackage", "/tmp/gh.rpm"]
+        );
+    }
+}
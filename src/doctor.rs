@@ -0,0 +1,440 @@
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use reqwest::Url;
+
+use crate::{
+    archive,
+    check::{self, Problem, Section},
+    config::{Config, PackageConfig},
+    conflicts,
+    expand::{self, Field},
+    http, local, paths,
+};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Check that this machine can run `setup` with `config`, each problem saying how to fix it.
+/// `targets` are the files the packages install.
+pub fn run(config: &Config, targets: &[conflicts::Target]) -> Vec<Section> {
+    let path = conflicts::path_directories();
+    vec![
+        locations(config),
+        on_path(config),
+        network(config),
+        decompression(),
+        conflicting(targets, &path),
+        shadowing(config, targets, &path, conflicts::system_owner),
+    ]
+}
+
+/// The install locations and workstation's own directories must be writable
+fn locations(config: &Config) -> Section {
+    let mut directories = vec![(
+        config.platform.location.clone(),
+        format!("section {}", config.section),
+    )];
+    for package in &config.platform.packages {
+        if let Some(location) = package.own_location() {
+            directories.push((
+                location.to_path_buf(),
+                format!("package {}", package.name()),
+            ));
+        }
+    }
+    let mut problems = vec![];
+    let mut checked = BTreeSet::new();
+    for (location, owner) in directories {
+        match expand::expand_path(&location.to_string_lossy(), Field::new("location", &owner)) {
+            Ok(directory) => {
+                checked.insert(directory);
+            }
+            Err(e) => problems.push(problem(e.to_string())),
+        }
+    }
+    match paths::Paths::resolve(Some(&config.paths)) {
+        Ok(paths) => checked.extend([paths.state.path, paths.cache.path]),
+        Err(e) => problems.push(problem(format!("{:#}", e))),
+    }
+    problems.extend(
+        checked
+            .iter()
+            .filter_map(|directory| writable(directory).err().map(problem)),
+    );
+
+    Section {
+        name: "locations",
+        checked: checked.len(),
+        problems,
+    }
+}
+
+/// Whether files can be created in `directory`, or in the closest parent that exists when
+/// `setup` would create it
+fn writable(directory: &Path) -> Result<(), String> {
+    let existing = directory
+        .ancestors()
+        .find(|ancestor| ancestor.exists())
+        .unwrap_or(directory);
+    if !existing.is_dir() {
+        return Err(format!(
+            "{} is not a directory, move it away or choose another location",
+            existing.display()
+        ));
+    }
+    tempfile::tempfile_in(existing).map(drop).map_err(|e| {
+        format!(
+            "{} is not writable ({}), fix its permissions or choose a location you own",
+            existing.display(),
+            e
+        )
+    })
+}
+
+/// The platform location must be on PATH for the shell to find what is installed. Package
+/// locations are left out, they are usually meant for other tools.
+fn on_path(config: &Config) -> Section {
+    let location = &config.platform.location;
+    let owner = format!("section {}", config.section);
+    let mut problems = vec![];
+    // A sandboxed location is never meant to be on PATH
+    if expand::Env::current().sandbox_home().is_none() {
+        if let Ok(directory) =
+            expand::expand_path(&location.to_string_lossy(), Field::new("location", &owner))
+        {
+            if !conflicts::path_directories().contains(&directory) {
+                problems.push(problem(format!(
+                    "{} is not on PATH, add `export PATH=\"{}:$PATH\"` to your shell's rc file",
+                    directory.display(),
+                    directory.display()
+                )));
+            }
+        }
+    }
+
+    Section {
+        name: "path",
+        checked: 1,
+        problems,
+    }
+}
+
+/// Every host packages are downloaded from must answer, whatever the answer is
+fn network(config: &Config) -> Section {
+    let hosts = hosts(&config.platform.packages);
+    let client = match http::init(&config.download) {
+        Ok(client) => client,
+        Err(e) => {
+            return Section {
+                name: "network",
+                checked: 0,
+                problems: vec![problem(format!(
+                    "{:#}, fix `proxy` in the [workstation.download] section",
+                    e
+                ))],
+            }
+        }
+    };
+
+    let handles: Vec<_> = hosts
+        .iter()
+        .cloned()
+        .map(|host| {
+            std::thread::spawn(move || {
+                client
+                    .head(host.as_str())
+                    .timeout(REQUEST_TIMEOUT)
+                    .send()
+                    .map(drop)
+                    .map_err(|e| {
+                        problem(format!(
+                            "cannot reach {}, check the network connection, or set `proxy` in the [workstation.download] section when a proxy is needed",
+                            check::request_error(host.as_str(), e)
+                        ))
+                    })
+            })
+        })
+        .collect();
+    let problems = handles
+        .into_iter()
+        .filter_map(|handle| handle.join().expect("doctor thread panicked").err())
+        .collect();
+
+    Section {
+        name: "network",
+        checked: hosts.len(),
+        problems,
+    }
+}
+
+/// The root URL of every host the packages download from
+fn hosts(packages: &[PackageConfig]) -> BTreeSet<Url> {
+    let mut urls = vec![];
+    for package in packages {
+        if let PackageConfig::GithubRelease { .. } = package {
+            urls.push("https://api.github.com".to_string());
+            urls.push("https://github.com".to_string());
+        }
+        if let PackageConfig::Cargo { .. } = package {
+            urls.push("https://index.crates.io".to_string());
+        }
+        if let PackageConfig::Go { .. } = package {
+            urls.push("https://proxy.golang.org".to_string());
+        }
+        if let PackageConfig::Pipx { .. } = package {
+            urls.push("https://pypi.org".to_string());
+        }
+        if let PackageConfig::Npm { .. } = package {
+            urls.push("https://registry.npmjs.org".to_string());
+        }
+        if let PackageConfig::Flatpak { remote: None, .. } = package {
+            urls.push("https://dl.flathub.org".to_string());
+        }
+        if let PackageConfig::Snap { .. } = package {
+            urls.push("https://api.snapcraft.io".to_string());
+        }
+        // SSH remotes such as `git@github.com:me/dotfiles` are no URLs and left out
+        for (key, url) in package.urls() {
+            if matches!(key, "signature" | "checksum_url" | "zsync_url") {
+                continue;
+            }
+            match package.expand_url(url) {
+                Ok(url) if local::local_path(&url).is_none() => urls.push(url),
+                _ => {}
+            }
+        }
+    }
+    urls.iter()
+        .filter_map(|url| Url::parse(url).ok())
+        .filter_map(|url| url.join("/").ok())
+        .collect()
+}
+
+/// The compression libraries workstation is built with must work on this system
+fn decompression() -> Section {
+    let results = archive::self_test();
+    Section {
+        name: "decompression",
+        checked: results.len(),
+        problems: results
+            .into_iter()
+            .filter_map(|(name, result)| {
+                result.err().map(|e| {
+                    problem(format!(
+                        "{} archives cannot be unpacked: {:#}, reinstall workstation",
+                        name, e
+                    ))
+                })
+            })
+            .collect(),
+    }
+}
+
+/// No two packages may install the same file name, the shell only runs one of them
+fn conflicting(targets: &[conflicts::Target], path: &[PathBuf]) -> Section {
+    let problems = conflicts::find_conflicts(targets, path)
+        .iter()
+        .map(|conflict| {
+            problem(format!(
+                "{}, remove all but one of them or install them into different locations",
+                conflict.summary()
+            ))
+        })
+        .collect();
+
+    Section {
+        name: "conflicts",
+        checked: targets.len(),
+        problems,
+    }
+}
+
+/// Other executables on PATH with the name of an installed file, such as one installed by apt,
+/// unless the package sets `allow_shadowing`
+fn shadowing(
+    config: &Config,
+    targets: &[conflicts::Target],
+    path: &[PathBuf],
+    owner: impl Fn(&Path) -> Option<String>,
+) -> Section {
+    let targets: Vec<_> = targets
+        .iter()
+        .filter(|target| {
+            config
+                .platform
+                .packages
+                .iter()
+                .any(|p| p.name() == target.package && !p.options().allow_shadowing)
+        })
+        .cloned()
+        .collect();
+    let problems = conflicts::find_shadows(&targets, path, owner)
+        .iter()
+        .map(|shadow| {
+            let package = &shadow.target.package;
+            problem(format!(
+                "{}, uninstall the other copy or set allow_shadowing = true on {}",
+                shadow.summary(),
+                package
+            ))
+        })
+        .collect();
+
+    Section {
+        name: "shadowing",
+        checked: targets.len(),
+        problems,
+    }
+}
+
+fn problem(message: String) -> Problem {
+    Problem {
+        package: None,
+        message,
+        position: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    #[test]
+    fn test_hosts_are_the_roots_of_remote_urls() {
+        let config = crate::config::parse_config_for(
+            r#"
+            [linux_x86_64]
+            location = "/unused"
+            packages = [
+                { name = "rg", url = "https://example.com/rg", mirrors = ["https://mirror.example.com/a/rg"] },
+                { name = "fd", url = "https://example.com/fd" },
+                { name = "tool", url = "./tool" },
+                { name = "eza", repo = "eza-community/eza" },
+            ]
+            "#,
+            &crate::arch::Target::parse("linux_x86_64").unwrap(),
+        )
+        .unwrap();
+
+        let hosts: Vec<_> = hosts(&config.platform.packages)
+            .into_iter()
+            .map(String::from)
+            .collect();
+
+        assert_eq!(
+            hosts,
+            [
+                "https://api.github.com/",
+                "https://example.com/",
+                "https://github.com/",
+                "https://mirror.example.com/",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_locations_must_be_writable() {
+        let directory = tempfile::tempdir().unwrap();
+        let locked = directory.path().join("locked");
+        std::fs::create_dir(&locked).unwrap();
+
+        assert!(writable(&directory.path().join("bin/new")).is_ok());
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o555)).unwrap();
+        // Root writes anywhere, the check can only fail for other users
+        let is_root = tempfile::tempfile_in(&locked).is_ok();
+        assert_eq!(writable(&locked.join("bin")).is_err(), !is_root);
+        std::fs::write(directory.path().join("file"), "").unwrap();
+        let error = writable(&directory.path().join("file/bin")).unwrap_err();
+        assert!(error.contains("is not a directory"));
+    }
+
+    fn target(package: &str, directory: &Path) -> conflicts::Target {
+        conflicts::Target {
+            package: package.to_string(),
+            directory: directory.to_path_buf(),
+            file_name: "rg".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_packages_installing_the_same_file_conflict() {
+        let path = [
+            PathBuf::from("/opt/bin"),
+            PathBuf::from("/home/u/.local/bin"),
+        ];
+        let targets = [
+            target("ripgrep", Path::new("/home/u/.local/bin")),
+            target("rg", Path::new("/opt/bin")),
+        ];
+
+        let section = conflicting(&targets, &path);
+
+        assert_eq!(section.checked, 2);
+        let messages: Vec<_> = section.problems.iter().map(|p| &p.message).collect();
+        assert_eq!(
+            messages,
+            [
+                "rg is installed by several packages: /opt/bin (rg), /home/u/.local/bin (ripgrep), \
+                 remove all but one of them or install them into different locations"
+            ]
+        );
+        assert!(conflicting(&targets[..1], &path).problems.is_empty());
+    }
+
+    #[test]
+    fn test_shadowed_files_are_reported_unless_allowed() {
+        let directory = tempfile::tempdir().unwrap();
+        let managed = directory.path().join("bin");
+        let system = directory.path().join("usr/bin");
+        for dir in [&managed, &system] {
+            std::fs::create_dir_all(dir).unwrap();
+            std::fs::write(dir.join("rg"), "rg").unwrap();
+            std::fs::set_permissions(dir.join("rg"), std::fs::Permissions::from_mode(0o755))
+                .unwrap();
+        }
+        let config = |allow: bool| {
+            crate::config::parse_config_for(
+                &format!(
+                    r#"
+                    [linux_x86_64]
+                    location = "{}"
+                    packages = [{{ name = "rg", url = "https://example.com/rg", allow_shadowing = {} }}]
+                    "#,
+                    managed.display(),
+                    allow
+                ),
+                &crate::arch::Target::parse("linux_x86_64").unwrap(),
+            )
+            .unwrap()
+        };
+        let targets = [target("rg", &managed)];
+        let path = [system.clone(), managed.clone()];
+        let owner = |_: &Path| Some("dpkg: ripgrep".to_string());
+
+        let section = shadowing(&config(false), &targets, &path, owner);
+
+        let messages: Vec<_> = section.problems.into_iter().map(|p| p.message).collect();
+        assert_eq!(
+            messages,
+            [format!(
+                "rg is also present at {} (owned by dpkg: ripgrep); it precedes {}, the installed \
+                 copy is shadowed, uninstall the other copy or set allow_shadowing = true on rg",
+                system.join("rg").display(),
+                managed.display()
+            )]
+        );
+        assert!(shadowing(&config(true), &targets, &path, owner)
+            .problems
+            .is_empty());
+    }
+
+    #[test]
+    fn test_every_compression_works() {
+        assert!(decompression().problems.is_empty());
+    }
+}
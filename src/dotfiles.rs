@@ -0,0 +1,544 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use eyre::Context;
+use regex::Regex;
+
+use crate::{
+    config::DotfilesConfig,
+    digest,
+    expand::{self, Field},
+};
+
+const OWNER: &str = "the [workstation.dotfiles] section";
+const RECORDS_FILE: &str = "templates.json";
+/// The newest version of the file this binary reads and writes, see [`crate::state`]
+const SCHEMA_VERSION: u32 = 1;
+
+/// A symlink at `target` pointing to `source`, a file or directory of the dotfiles, or the file
+/// at `target` written from the template `source`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub template: bool,
+}
+
+/// The links and then the templates of the `[workstation.dotfiles]` section, with their paths
+/// expanded and sources made absolute
+pub fn links(config: &DotfilesConfig) -> eyre::Result<Vec<Link>> {
+    let directory = config
+        .directory
+        .as_deref()
+        .map(|directory| expand::expand_path(directory, Field::new("directory", OWNER)))
+        .transpose()?;
+    let entries = config
+        .links
+        .iter()
+        .map(|entry| (entry, false))
+        .chain(config.templates.iter().map(|entry| (entry, true)));
+    let mut links = vec![];
+    for ((source, target), template) in entries {
+        let field = match template {
+            true => format!("template {}", source),
+            false => format!("link {}", source),
+        };
+        let path = expand::expand_path(source, Field::new(&field, OWNER))?;
+        let source = match &directory {
+            _ if path.is_absolute() => path,
+            Some(directory) => directory.join(path),
+            None => eyre::bail!(
+                "The dotfile {} is relative, set `directory` in the [workstation.dotfiles] section",
+                source
+            ),
+        };
+        let expanded = expand::expand_path(target, Field::new(&field, OWNER))?;
+        if !expanded.is_absolute() {
+            eyre::bail!(
+                "The link to the dotfile {} must be an absolute path or start with ~, not {:?}",
+                source.display(),
+                target
+            );
+        }
+        links.push(Link {
+            source,
+            target: expanded,
+            template,
+        });
+    }
+    Ok(links)
+}
+
+/// `text` with every `{{ name }}` replaced with the variable `name`. A name no variable sets is
+/// an error rather than an empty string, so that a typo cannot write a broken config.
+pub fn render_template(text: &str, variables: &BTreeMap<String, String>) -> eyre::Result<String> {
+    static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    let placeholder = PLACEHOLDER.get_or_init(|| {
+        Regex::new(r"\{\{\s*([A-Za-z_][A-Za-z0-9_]*)\s*\}\}").expect("placeholder regex is valid")
+    });
+    let mut rendered = String::with_capacity(text.len());
+    let mut end = 0;
+    for captures in placeholder.captures_iter(text) {
+        let whole = captures.get(0).expect("a match has the whole group");
+        let name = &captures[1];
+        let Some(value) = variables.get(name) else {
+            let known: Vec<_> = variables.keys().map(String::as_str).collect();
+            eyre::bail!(
+                "No variable is named {}, set it in [workstation.dotfiles] variables (there are {})",
+                name,
+                known.join(", ")
+            );
+        };
+        rendered.push_str(&text[end..whole.start()]);
+        rendered.push_str(value);
+        end = whole.end();
+    }
+    rendered.push_str(&text[end..]);
+    Ok(rendered)
+}
+
+/// The file the template of `link` renders to
+fn rendered(link: &Link, variables: &BTreeMap<String, String>) -> eyre::Result<String> {
+    let text = std::fs::read_to_string(&link.source)
+        .with_context(|| format!("Reading the template {}", link.source.display()))?;
+    render_template(&text, variables)
+        .with_context(|| format!("Rendering the template {}", link.source.display()))
+}
+
+/// The hashes of the files written from templates, kept in the state directory so that a file
+/// `setup` wrote is replaced when the template changes, while one edited since is kept aside
+pub struct Written {
+    path: PathBuf,
+    records: BTreeMap<PathBuf, String>,
+}
+
+impl Written {
+    /// Load the records, a missing or unreadable file only means files that differ are kept aside
+    pub fn load(state_dir: &Path) -> Self {
+        let path = state_dir.join(RECORDS_FILE);
+        let records = std::fs::read(&path)
+            .ok()
+            .and_then(|bytes| crate::state::parse(&bytes, SCHEMA_VERSION).ok())
+            .unwrap_or_default();
+        Written { path, records }
+    }
+
+    pub fn save(&self) -> eyre::Result<()> {
+        let directory = self.path.parent().expect("records path has a parent");
+        std::fs::create_dir_all(directory)
+            .with_context(|| format!("Creating {}", directory.display()))?;
+        let json = crate::state::to_json(&self.records, SCHEMA_VERSION);
+        std::fs::write(&self.path, json).with_context(|| format!("Writing {}", self.path.display()))
+    }
+
+    /// Whether the file at `target` is what `setup` last wrote there
+    fn wrote(&self, target: &Path) -> bool {
+        let Some(hash) = self.records.get(target) else {
+            return false;
+        };
+        std::fs::read(target).is_ok_and(|bytes| digest::sha256(&bytes) == *hash)
+    }
+}
+
+/// Where a link stands
+#[derive(Debug, Clone, PartialEq)]
+pub enum State {
+    Linked,
+    /// A file written from its template, as the template renders now
+    Rendered,
+    /// A file that differs from what its template renders
+    Outdated,
+    Missing,
+    /// Links to the source, which does not exist
+    Broken,
+    /// A link to somewhere else
+    Drifted(PathBuf),
+    /// A file or directory that is not a link
+    Occupied,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            State::Linked => write!(f, "linked"),
+            State::Rendered => write!(f, "rendered"),
+            State::Outdated => write!(f, "differs from the template"),
+            State::Missing => write!(f, "missing"),
+            State::Broken => write!(f, "broken, the dotfile does not exist"),
+            State::Drifted(to) => write!(f, "links to {} instead", to.display()),
+            State::Occupied => write!(f, "a file that is not a link is in the way"),
+        }
+    }
+}
+
+pub fn state(link: &Link, variables: &BTreeMap<String, String>) -> eyre::Result<State> {
+    if link.template {
+        return template_state(link, variables);
+    }
+    match link.target.symlink_metadata() {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(State::Missing),
+        Err(e) => Err(e).with_context(|| format!("Reading {}", link.target.display())),
+        Ok(metadata) if metadata.is_symlink() => {
+            let to = std::fs::read_link(&link.target)
+                .with_context(|| format!("Reading {}", link.target.display()))?;
+            Ok(match to == link.source {
+                true if link.source.exists() => State::Linked,
+                true => State::Broken,
+                false => State::Drifted(to),
+            })
+        }
+        Ok(_) => Ok(State::Occupied),
+    }
+}
+
+fn template_state(link: &Link, variables: &BTreeMap<String, String>) -> eyre::Result<State> {
+    if !link.source.exists() {
+        return Ok(State::Broken);
+    }
+    let rendered = rendered(link, variables)?;
+    match link.target.symlink_metadata() {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(State::Missing),
+        Err(e) => Err(e).with_context(|| format!("Reading {}", link.target.display())),
+        Ok(metadata) if !metadata.is_file() => Ok(State::Occupied),
+        Ok(_) => {
+            let current = std::fs::read(&link.target)
+                .with_context(|| format!("Reading {}", link.target.display()))?;
+            Ok(match current == rendered.as_bytes() {
+                true => State::Rendered,
+                false => State::Outdated,
+            })
+        }
+    }
+}
+
+/// What linking changed
+#[derive(Debug, Clone, PartialEq)]
+pub enum Applied {
+    Unchanged,
+    /// With what was at the target moved to `backup`
+    Linked {
+        backup: Option<PathBuf>,
+    },
+}
+
+/// Point the target of `link` at its source, or write its template there. Whatever else is
+/// there, a file, a directory or another link, is renamed next to it first, nothing is deleted
+/// but a file `written` from an earlier render of the template.
+pub fn apply(
+    link: &Link,
+    variables: &BTreeMap<String, String>,
+    written: &mut Written,
+) -> eyre::Result<Applied> {
+    if link.template {
+        return write_template(link, variables, written);
+    }
+    let state = state(link, variables)?;
+    if state == State::Linked {
+        return Ok(Applied::Unchanged);
+    }
+    if !link.source.exists() {
+        eyre::bail!(
+            "{} does not exist, {} cannot link to it",
+            link.source.display(),
+            link.target.display()
+        );
+    }
+
+    let backup = match state {
+        State::Missing => None,
+        // A link to the source is only broken while the source is missing
+        State::Broken => {
+            std::fs::remove_file(&link.target)
+                .with_context(|| format!("Removing {}", link.target.display()))?;
+            None
+        }
+        _ => Some(move_aside(&link.target)?),
+    };
+    create_parent(&link.target)?;
+    std::os::unix::fs::symlink(&link.source, &link.target).with_context(|| {
+        format!(
+            "Linking {} to {}",
+            link.target.display(),
+            link.source.display()
+        )
+    })?;
+    Ok(Applied::Linked { backup })
+}
+
+fn write_template(
+    link: &Link,
+    variables: &BTreeMap<String, String>,
+    written: &mut Written,
+) -> eyre::Result<Applied> {
+    let backup = match template_state(link, variables)? {
+        State::Rendered => return Ok(Applied::Unchanged),
+        State::Broken => eyre::bail!(
+            "The template {} does not exist, {} cannot be written",
+            link.source.display(),
+            link.target.display()
+        ),
+        State::Missing => None,
+        State::Outdated if written.wrote(&link.target) => None,
+        _ => Some(move_aside(&link.target)?),
+    };
+    let rendered = rendered(link, variables)?;
+    create_parent(&link.target)?;
+    std::fs::write(&link.target, &rendered)
+        .with_context(|| format!("Writing {}", link.target.display()))?;
+    written
+        .records
+        .insert(link.target.clone(), digest::sha256(rendered.as_bytes()));
+    Ok(Applied::Linked { backup })
+}
+
+/// Rename `target` to a free name next to it, returning that name
+fn move_aside(target: &Path) -> eyre::Result<PathBuf> {
+    let backup = backup_path(target);
+    std::fs::rename(target, &backup)
+        .with_context(|| format!("Moving {} aside to {}", target.display(), backup.display()))?;
+    Ok(backup)
+}
+
+fn create_parent(target: &Path) -> eyre::Result<()> {
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Creating {}", parent.display()))?;
+    }
+    Ok(())
+}
+
+/// A free name next to `target` to keep what was there
+fn backup_path(target: &Path) -> PathBuf {
+    let name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    (0..)
+        .map(|index| match index {
+            0 => target.with_file_name(format!("{}.workstation-backup", name)),
+            index => target.with_file_name(format!("{}.workstation-backup.{}", name, index)),
+        })
+        .find(|path| path.symlink_metadata().is_err())
+        .expect("some backup name is free")
+}
+
+/// The state of every link, as `status` prints it
+pub fn render(states: &[(Link, State)]) -> String {
+    let width = states
+        .iter()
+        .map(|(link, _)| link.target.as_os_str().len())
+        .max()
+        .unwrap_or(0);
+    let mut rendered = String::new();
+    for (link, state) in states {
+        rendered.push_str(&format!(
+            "{:width$}  {} {}  {}\n",
+            link.target.display().to_string(),
+            if link.template { "<-" } else { "->" },
+            link.source.display(),
+            state,
+        ));
+    }
+    let linked = states
+        .iter()
+        .filter(|(_, state)| matches!(state, State::Linked | State::Rendered))
+        .count();
+    rendered.push_str(&format!(
+        "{} of {} dotfile(s) in place\n",
+        linked,
+        states.len()
+    ));
+    rendered
+}
+
+/// The links `setup --dry-run` would create and the templates it would write, in the layout of
+/// [`crate::preview::render`]
+pub fn render_preview(states: &[(Link, State)], written: &Written) -> String {
+    let mut rendered = String::new();
+    for (link, state) in states {
+        let action = match state {
+            State::Linked | State::Rendered => continue,
+            State::Missing | State::Broken => "",
+            State::Outdated if written.wrote(&link.target) => "",
+            State::Outdated | State::Drifted(_) | State::Occupied => {
+                " (moving what is there aside)"
+            }
+        };
+        rendered.push_str(&format!(
+            "{}\n  {}{}{}\n",
+            link.target.display(),
+            if link.template {
+                "render   "
+            } else {
+                "link     "
+            },
+            link.source.display(),
+            action
+        ));
+    }
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    fn link(directory: &Path, source: &str, target: &str) -> Link {
+        Link {
+            source: directory.join(source),
+            target: directory.join(target),
+            template: false,
+        }
+    }
+
+    #[test]
+    fn test_links_are_relative_to_the_directory() {
+        let config = DotfilesConfig {
+            directory: Some("/home/me/dotfiles".to_string()),
+            links: BTreeMap::from([
+                ("nvim".to_string(), "/home/me/.config/nvim".to_string()),
+                (
+                    "/etc/gitconfig".to_string(),
+                    "/home/me/.gitconfig".to_string(),
+                ),
+            ]),
+            templates: BTreeMap::from([(
+                "ssh.tmpl".to_string(),
+                "/home/me/.ssh/config".to_string(),
+            )]),
+            ..Default::default()
+        };
+        assert_eq!(
+            links(&config).unwrap(),
+            [
+                Link {
+                    source: PathBuf::from("/etc/gitconfig"),
+                    target: PathBuf::from("/home/me/.gitconfig"),
+                    template: false,
+                },
+                Link {
+                    source: PathBuf::from("/home/me/dotfiles/nvim"),
+                    target: PathBuf::from("/home/me/.config/nvim"),
+                    template: false,
+                },
+                Link {
+                    source: PathBuf::from("/home/me/dotfiles/ssh.tmpl"),
+                    target: PathBuf::from("/home/me/.ssh/config"),
+                    template: true,
+                },
+            ]
+        );
+
+        let relative = DotfilesConfig {
+            directory: None,
+            links: BTreeMap::from([("zshrc".to_string(), "/home/me/.zshrc".to_string())]),
+            ..Default::default()
+        };
+        assert!(links(&relative)
+            .unwrap_err()
+            .to_string()
+            .contains("set `directory`"));
+    }
+
+    #[test]
+    fn test_whatever_is_in_the_way_is_kept() {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path();
+        std::fs::create_dir_all(root.join("dotfiles/nvim")).unwrap();
+        std::fs::write(root.join("dotfiles/zshrc"), "mine").unwrap();
+        std::fs::write(root.join(".zshrc"), "theirs").unwrap();
+        let nvim = link(root, "dotfiles/nvim", "home/.config/nvim");
+        let zshrc = link(root, "dotfiles/zshrc", ".zshrc");
+        let variables = BTreeMap::new();
+        let mut written = Written::load(root);
+        let state = |link: &Link| state(link, &variables);
+        let mut apply = |link: &Link| apply(link, &variables, &mut written);
+
+        assert_eq!(state(&nvim).unwrap(), State::Missing);
+        assert_eq!(state(&zshrc).unwrap(), State::Occupied);
+        assert_eq!(apply(&nvim).unwrap(), Applied::Linked { backup: None });
+        let backup = root.join(".zshrc.workstation-backup");
+        assert_eq!(
+            apply(&zshrc).unwrap(),
+            Applied::Linked {
+                backup: Some(backup.clone())
+            }
+        );
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), "theirs");
+        assert_eq!(std::fs::read_to_string(&zshrc.target).unwrap(), "mine");
+        assert_eq!(apply(&zshrc).unwrap(), Applied::Unchanged);
+
+        let moved = link(root, "elsewhere/zshrc", ".zshrc");
+        assert_eq!(state(&moved).unwrap(), State::Drifted(zshrc.source.clone()));
+        assert!(apply(&moved).is_err());
+        std::fs::remove_dir_all(root.join("dotfiles")).unwrap();
+        assert_eq!(state(&zshrc).unwrap(), State::Broken);
+        assert_eq!(
+            render(&[(zshrc.clone(), State::Broken)]),
+            format!(
+                "{}  -> {}  broken, the dotfile does not exist\n0 of 1 dotfile(s) in place\n",
+                zshrc.target.display(),
+                zshrc.source.display()
+            )
+        );
+    }
+
+    #[test]
+    fn test_templates_are_rendered_and_edits_kept() {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path();
+        std::fs::write(
+            root.join("gitconfig.tmpl"),
+            "[user]\n\temail = {{ email }}\n\t# {{hostname}}\n",
+        )
+        .unwrap();
+        let gitconfig = Link {
+            template: true,
+            ..link(root, "gitconfig.tmpl", ".gitconfig")
+        };
+        let mut variables = BTreeMap::from([
+            ("email".to_string(), "me@work.example".to_string()),
+            ("hostname".to_string(), "corp-1".to_string()),
+        ]);
+        let mut written = Written::load(root);
+
+        assert_eq!(
+            apply(&gitconfig, &variables, &mut written).unwrap(),
+            Applied::Linked { backup: None }
+        );
+        assert_eq!(
+            std::fs::read_to_string(&gitconfig.target).unwrap(),
+            "[user]\n\temail = me@work.example\n\t# corp-1\n"
+        );
+        assert_eq!(state(&gitconfig, &variables).unwrap(), State::Rendered);
+
+        // What setup wrote is replaced, an edit made since is kept aside
+        variables.insert("email".to_string(), "me@home.example".to_string());
+        assert_eq!(state(&gitconfig, &variables).unwrap(), State::Outdated);
+        assert_eq!(
+            apply(&gitconfig, &variables, &mut written).unwrap(),
+            Applied::Linked { backup: None }
+        );
+        written.save().unwrap();
+        std::fs::write(&gitconfig.target, "mine").unwrap();
+        variables.insert("email".to_string(), "me@work.example".to_string());
+        let backup = root.join(".gitconfig.workstation-backup");
+        assert_eq!(
+            apply(&gitconfig, &variables, &mut Written::load(root)).unwrap(),
+            Applied::Linked {
+                backup: Some(backup.clone())
+            }
+        );
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), "mine");
+
+        variables.remove("email");
+        assert!(format!("{:#}", state(&gitconfig, &variables).unwrap_err())
+            .contains("No variable is named email"));
+    }
+}
@@ -0,0 +1,1161 @@
+use std::{
+    collections::hash_map::RandomState,
+    fs::File,
+    hash::{BuildHasher, Hasher},
+    io::{Read, Write},
+    os::unix::fs::FileExt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use eyre::Context;
+use indicatif::ProgressBar;
+use reqwest::header::{self, HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    asset::Asset,
+    error::ErrorCode,
+    expand::{self, Field},
+};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Rate limited requests are retried this many times
+const RATE_LIMIT_RETRIES: u32 = 3;
+/// A rate limit lifting later than this fails the download instead of waiting for it
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(30);
+/// An interrupted download is resumed where it stopped this many times before it fails
+const RESUME_ATTEMPTS: u32 = 5;
+/// Added to the wait before every further attempt to resume, the first one is immediate
+const RESUME_BACKOFF: Duration = Duration::from_secs(1);
+
+/// How a single package is downloaded
+#[derive(Debug, Clone, PartialEq)]
+pub struct DownloadOptions {
+    /// Number of concurrent ranged requests for large assets, 1 disables segmenting
+    pub segments: u32,
+    /// Assets smaller than this many bytes are always downloaded in one stream
+    pub segment_threshold: u64,
+    pub retry: RetryPolicy,
+    /// Sent with every request, values may reference environment variables
+    pub headers: Vec<(String, String)>,
+    /// What a GitHub release is looked up with, may reference environment variables. The
+    /// asset is then downloaded through the API, with the token in `headers`.
+    pub github_token: Option<String>,
+    /// Make no requests, assets only come from the cache, see `setup --offline`
+    pub offline: bool,
+}
+
+impl DownloadOptions {
+    pub const DEFAULT_SEGMENT_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+    /// The `github_token` with its references expanded
+    pub fn github_token(&self) -> eyre::Result<Option<String>> {
+        self.github_token
+            .as_deref()
+            .map(|token| {
+                expand::expand_vars(token, Field::new("github_token", "the download settings"))
+            })
+            .transpose()
+    }
+
+    /// The `headers` to send, with their references expanded
+    pub fn header_map(&self) -> eyre::Result<HeaderMap> {
+        let mut map = HeaderMap::new();
+        for (name, value) in &self.headers {
+            let value = expand::expand_vars(value, Field::new(name, "the download headers"))?;
+            let mut value = HeaderValue::from_str(&value)
+                .with_context(|| format!("Invalid value of the download header {}", name))?;
+            // Kept out of debug output and traces
+            value.set_sensitive(true);
+            map.insert(
+                HeaderName::from_bytes(name.as_bytes())
+                    .with_context(|| format!("Invalid download header name {:?}", name))?,
+                value,
+            );
+        }
+        Ok(map)
+    }
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        DownloadOptions {
+            segments: 1,
+            segment_threshold: Self::DEFAULT_SEGMENT_THRESHOLD,
+            retry: RetryPolicy::default(),
+            headers: vec![],
+            github_token: None,
+            offline: false,
+        }
+    }
+}
+
+/// The error for `what` needing the network while [`DownloadOptions::offline`] is set
+pub fn offline_error(what: impl std::fmt::Display) -> eyre::Report {
+    ErrorCode::Offline.error(format!(
+        "{} needs the network, which --offline does not use",
+        what
+    ))
+}
+
+/// How a download that failed with a network error or a 5xx response is tried again
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts after the first one
+    pub retries: u32,
+    /// The wait before the first retry, doubled for every further one
+    pub backoff: Duration,
+    /// Wait a random part of the backoff on top, so packages that failed together do not all
+    /// retry at the same moment
+    pub jitter: bool,
+}
+
+impl RetryPolicy {
+    pub const DEFAULT_RETRIES: u32 = 3;
+    pub const DEFAULT_BACKOFF: Duration = Duration::from_secs(1);
+
+    /// The wait before retry number `retry`, counting from 0
+    fn delay(&self, retry: u32) -> Duration {
+        let backoff = self.backoff.saturating_mul(2u32.saturating_pow(retry));
+        if !self.jitter {
+            return backoff;
+        }
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u32(retry);
+        let nanos = backoff.as_nanos().min(u128::from(u64::MAX)) as u64;
+        backoff + Duration::from_nanos(hasher.finish() % nanos.max(1))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: Self::DEFAULT_RETRIES,
+            backoff: Self::DEFAULT_BACKOFF,
+            jitter: true,
+        }
+    }
+}
+
+/// A failure another attempt may not run into, such as a 5xx response or a dropped connection
+/// that could not be resumed. It is reported as [`ErrorCode::DownloadFailed`] once the retries
+/// are used up.
+#[derive(Debug)]
+struct Transient(String);
+
+impl std::fmt::Display for Transient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Transient {}
+
+/// Whether `error` is worth another attempt: a [`Transient`] failure, or the request not
+/// getting through at all
+fn is_transient(error: &eyre::Report) -> bool {
+    error.downcast_ref::<Transient>().is_some()
+        || error.chain().any(|cause| {
+            cause
+                .downcast_ref::<reqwest::Error>()
+                .is_some_and(|e| e.is_connect() || e.is_timeout() || e.is_request() || e.is_body())
+        })
+}
+
+/// Default for `[workstation.download] concurrency`
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+/// What the server said identifies the version of an asset, asked again on the next download
+/// to tell whether a cached copy is still current
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Validators {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        Validators {
+            etag: header(header::ETAG),
+            last_modified: header(header::LAST_MODIFIED),
+        }
+    }
+
+    /// The server sent neither, a cached copy can never be known to be current
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// A finished download
+#[derive(Debug)]
+pub struct Download {
+    pub asset: Asset,
+    pub validators: Validators,
+}
+
+/// The shared client with the headers every request for one package carries
+#[derive(Clone, Copy)]
+pub struct Client<'a> {
+    http: &'a reqwest::blocking::Client,
+    headers: &'a HeaderMap,
+}
+
+impl<'a> Client<'a> {
+    pub fn new(headers: &'a HeaderMap) -> Self {
+        Client {
+            http: crate::http::client(),
+            headers,
+        }
+    }
+
+    pub fn get(&self, url: &str) -> reqwest::blocking::RequestBuilder {
+        self.http.get(url).headers(self.headers.clone())
+    }
+
+    pub fn head(&self, url: &str) -> reqwest::blocking::RequestBuilder {
+        self.http.head(url).headers(self.headers.clone())
+    }
+}
+
+/// Whether `url` still serves the version `validators` were sent with, asked with a conditional
+/// `HEAD` request. Any failure counts as changed.
+pub fn unchanged(url: &str, validators: &Validators, options: &DownloadOptions) -> bool {
+    if validators.is_empty() {
+        return false;
+    }
+    let Ok(headers) = options.header_map() else {
+        return false;
+    };
+    let mut request = Client::new(&headers).head(url);
+    if let Some(etag) = &validators.etag {
+        request = request.header(header::IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &validators.last_modified {
+        request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+    }
+    let Ok(response) = request.send() else {
+        return false;
+    };
+    // Servers that ignore the conditions still send the current validators
+    response.status() == reqwest::StatusCode::NOT_MODIFIED
+        || response.status().is_success()
+            && Validators::from_headers(response.headers()) == *validators
+}
+
+/// The async client asset downloads go through, with the headers of one package, see
+/// [`Client`]
+#[derive(Clone, Copy)]
+struct AsyncClient<'a> {
+    http: &'a reqwest::Client,
+    headers: &'a HeaderMap,
+}
+
+impl<'a> AsyncClient<'a> {
+    fn new(http: &'a reqwest::Client, headers: &'a HeaderMap) -> Self {
+        AsyncClient { http, headers }
+    }
+
+    fn get(&self, url: &str) -> reqwest::RequestBuilder {
+        self.http.get(url).headers(self.headers.clone())
+    }
+}
+
+/// [`download`] for callers that are not async, blocking until it finished on the runtime of
+/// this run
+pub fn download_with_progress(
+    url: &str,
+    pb: &ProgressBar,
+    options: &DownloadOptions,
+) -> eyre::Result<Download> {
+    // Built outside the runtime, the blocking client built with it cannot be built inside
+    let http = crate::http::async_client();
+    crate::http::runtime().block_on(download(http, url, pb, options))
+}
+
+/// Download `url` with `http` into a temporary file as it arrives, the asset never sits in
+/// memory.
+///
+/// Network errors and 5xx responses are retried as `options.retry` says.
+pub async fn download(
+    http: &reqwest::Client,
+    url: &str,
+    pb: &ProgressBar,
+    options: &DownloadOptions,
+) -> eyre::Result<Download> {
+    let headers = options.header_map()?;
+    let client = AsyncClient::new(http, &headers);
+    let mut retry = 0;
+    loop {
+        let error = match download_once(client, url, pb, options).await {
+            Ok(download) => return Ok(download),
+            Err(e) => e,
+        };
+        if retry == options.retry.retries || !is_transient(&error) {
+            return Err(match error.downcast::<Transient>() {
+                Ok(transient) => ErrorCode::DownloadFailed.error(transient.0),
+                Err(error) => error,
+            });
+        }
+        pb.set_message(format!(
+            "Retrying the download, attempt {} of {}",
+            retry + 2,
+            options.retry.retries + 1
+        ));
+        tokio::time::sleep(options.retry.delay(retry)).await;
+        retry += 1;
+        pb.set_position(0);
+    }
+}
+
+async fn download_once(
+    client: AsyncClient<'_>,
+    url: &str,
+    pb: &ProgressBar,
+    options: &DownloadOptions,
+) -> eyre::Result<Download> {
+    if options.segments > 1 {
+        let probe = probe_range_support(client, url)
+            .await
+            .filter(|(length, _)| *length >= options.segment_threshold);
+        if let Some((length, validators)) = probe {
+            let file = tempfile::tempfile()?;
+            file.set_len(length)?;
+            let segmented = download_segmented(client, url, pb, length, options.segments, &file);
+            if segmented.await.is_ok() {
+                return Ok(Download {
+                    asset: Asset::from_file(file)?,
+                    validators,
+                });
+            }
+            // Some servers advertise ranges but misbehave under load, start over in one stream
+            pb.set_position(0);
+        }
+    }
+
+    download_single(client, url, pb).await
+}
+
+async fn download_single(
+    client: AsyncClient<'_>,
+    url: &str,
+    pb: &ProgressBar,
+) -> eyre::Result<Download> {
+    let mut response = client.get(url).send().await?;
+    let mut backoff = Duration::from_secs(1);
+    for _ in 0..RATE_LIMIT_RETRIES {
+        let Some(wait) = rate_limit_wait(response.status(), response.headers(), SystemTime::now())
+        else {
+            break;
+        };
+        let wait = wait.unwrap_or(backoff);
+        if wait > MAX_RATE_LIMIT_WAIT {
+            break;
+        }
+        tokio::time::sleep(wait).await;
+        backoff *= 2;
+        response = client.get(url).send().await?;
+    }
+
+    if let Some(wait) = rate_limit_wait(response.status(), response.headers(), SystemTime::now()) {
+        return Err(ErrorCode::RateLimited.error(format!(
+            "Failed to download {}: rate limited{}",
+            url,
+            until(wait)
+        )));
+    }
+    if response.status().is_server_error() {
+        return Err(Transient(format!("Failed to download {}: {}", url, response.status())).into());
+    }
+    if !response.status().is_success() {
+        return Err(ErrorCode::DownloadFailed.error(format!(
+            "Failed to download {}: {}",
+            url,
+            response.status()
+        )));
+    }
+
+    let total_length = response.content_length().ok_or_else(|| {
+        ErrorCode::MissingContentLength.error(format!("Failed to get content length of {}", url))
+    })?;
+
+    let validators = Validators::from_headers(response.headers());
+    let resumable = response
+        .headers()
+        .get(header::ACCEPT_RANGES)
+        .is_some_and(|value| value.as_bytes() == b"bytes");
+    let mut file = tempfile::tempfile()?;
+    let mut written = 0;
+    let mut progress = ThrottledProgress::new(pb, total_length);
+    let mut resumes = 0;
+
+    while written < total_length {
+        let interrupted = match response.chunk().await {
+            Ok(None) => eyre::Report::new(std::io::Error::from(std::io::ErrorKind::UnexpectedEof)),
+            Ok(Some(chunk)) => {
+                file.write_all(&chunk)?;
+                written += chunk.len() as u64;
+                progress.set_position(written);
+                continue;
+            }
+            Err(e) => eyre::Report::new(e),
+        };
+
+        // Ask for the rest, keeping what already arrived
+        let mut error = interrupted;
+        loop {
+            if !resumable || resumes == RESUME_ATTEMPTS {
+                return Err(Transient(format!(
+                    "Failed to download {}: interrupted after {} of {} bytes: {:#}",
+                    url, written, total_length, error
+                ))
+                .into());
+            }
+            tokio::time::sleep(RESUME_BACKOFF * resumes).await;
+            resumes += 1;
+            match resume(client, url, written, &validators).await {
+                Ok(rest) => {
+                    response = rest;
+                    break;
+                }
+                Err(e) => error = e,
+            }
+        }
+    }
+    progress.flush();
+
+    Ok(Download {
+        asset: Asset::from_file(file)?,
+        validators,
+    })
+}
+
+/// The rest of `url` from byte `start` on, as long as it still is the version `validators`
+/// were sent with
+async fn resume(
+    client: AsyncClient<'_>,
+    url: &str,
+    start: u64,
+    validators: &Validators,
+) -> eyre::Result<reqwest::Response> {
+    let mut request = client
+        .get(url)
+        .header(header::RANGE, format!("bytes={}-", start));
+    // A changed asset is answered in full instead, which is not the rest of this one
+    if let Some(validator) = validators
+        .etag
+        .as_ref()
+        .or(validators.last_modified.as_ref())
+    {
+        request = request.header(header::IF_RANGE, validator);
+    }
+    let response = request.send().await?;
+
+    // Content-Range: bytes 1024-12344/12345
+    let resumed_at = response
+        .headers()
+        .get(header::CONTENT_RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|range| range.strip_prefix("bytes "))
+        .and_then(|range| range.split_once('-'))
+        .and_then(|(first, _)| first.parse::<u64>().ok());
+    if response.status() != reqwest::StatusCode::PARTIAL_CONTENT || resumed_at != Some(start) {
+        eyre::bail!(
+            "the server did not resume at byte {}: {}",
+            start,
+            response.status()
+        );
+    }
+    Ok(response)
+}
+
+/// ` until <time> UTC` after waiting `wait` from now, nothing when the wait is unknown
+pub fn until(wait: Option<Duration>) -> String {
+    match wait {
+        Some(wait) => format!(
+            " until {} UTC",
+            crate::stat::format_utc(
+                (SystemTime::now() + wait)
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs()
+            )
+        ),
+        None => String::new(),
+    }
+}
+
+/// `Some` when the server refused the request for making too many, with how long it asked to
+/// wait when it said so.
+///
+/// GitHub answers 403 with `x-ratelimit-remaining: 0` and the reset time as epoch seconds in
+/// `x-ratelimit-reset`, other servers answer 429 with `Retry-After` in seconds.
+pub fn rate_limit_wait(
+    status: reqwest::StatusCode,
+    headers: &HeaderMap,
+    now: SystemTime,
+) -> Option<Option<Duration>> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok())
+    };
+    let exhausted =
+        status == reqwest::StatusCode::FORBIDDEN && header("x-ratelimit-remaining") == Some(0);
+    if status != reqwest::StatusCode::TOO_MANY_REQUESTS && !exhausted {
+        return None;
+    }
+
+    let reset = header("x-ratelimit-reset").map(|reset| {
+        (UNIX_EPOCH + Duration::from_secs(reset))
+            .duration_since(now)
+            .unwrap_or_default()
+    });
+    Some(header("retry-after").map(Duration::from_secs).or(reset))
+}
+
+/// The total length of `url` if the server answers ranged requests, with the validators it sent
+async fn probe_range_support(client: AsyncClient<'_>, url: &str) -> Option<(u64, Validators)> {
+    let response = client
+        .get(url)
+        .header(header::RANGE, "bytes=0-0")
+        .send()
+        .await
+        .ok()?;
+    if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+        return None;
+    }
+
+    // Content-Range: bytes 0-0/12345
+    let content_range = response
+        .headers()
+        .get(header::CONTENT_RANGE)?
+        .to_str()
+        .ok()?;
+    let length = content_range.rsplit_once('/')?.1.parse().ok()?;
+    Some((length, Validators::from_headers(response.headers())))
+}
+
+/// Split `length` bytes into `segments` contiguous inclusive ranges
+fn segment_ranges(length: u64, segments: u32) -> Vec<(u64, u64)> {
+    let segment_length = length.div_ceil(u64::from(segments.max(1))).max(1);
+
+    (0..length)
+        .step_by(segment_length as usize)
+        .map(|start| (start, (start + segment_length).min(length) - 1))
+        .collect()
+}
+
+/// Download the segments of `url` at the same time, each writing its own part of `file` at its
+/// offset
+async fn download_segmented(
+    client: AsyncClient<'_>,
+    url: &str,
+    pb: &ProgressBar,
+    length: u64,
+    segments: u32,
+    file: &File,
+) -> eyre::Result<()> {
+    let progress = Mutex::new(ThrottledProgress::new(pb, length));
+    let downloaded = AtomicU64::new(0);
+    let on_progress = |read: u64| {
+        let position = downloaded.fetch_add(read, Ordering::Relaxed) + read;
+        progress
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .set_position(position);
+    };
+
+    let segments = segment_ranges(length, segments)
+        .into_iter()
+        .map(|(start, end)| download_segment(client, url, start, end, file, &on_progress));
+    futures_util::future::try_join_all(segments).await?;
+
+    progress
+        .into_inner()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .flush();
+
+    Ok(())
+}
+
+/// Write the inclusive byte range `start..=end` of `url` into `file` at the same offsets
+async fn download_segment(
+    client: AsyncClient<'_>,
+    url: &str,
+    start: u64,
+    end: u64,
+    file: &File,
+    on_progress: &impl Fn(u64),
+) -> eyre::Result<()> {
+    let mut response = client
+        .get(url)
+        .header(header::RANGE, format!("bytes={}-{}", start, end))
+        .send()
+        .await?;
+    if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+        eyre::bail!(
+            "Expected partial content for bytes {}-{} of {}, got {}",
+            start,
+            end,
+            url,
+            response.status()
+        );
+    }
+
+    let length = end - start + 1;
+    let mut filled = 0;
+    while filled < length {
+        let Some(chunk) = response.chunk().await? else {
+            eyre::bail!("Segment {}-{} of {} ended early", start, end, url);
+        };
+        let chunk = &chunk[..chunk.len().min((length - filled) as usize)];
+        file.write_all_at(chunk, start + filled)?;
+        filled += chunk.len() as u64;
+        on_progress(chunk.len() as u64);
+    }
+
+    Ok(())
+}
+
+/// Fill `slice` with the inclusive byte range `start..=end` of `url`
+pub fn fetch_range(
+    client: &Client,
+    url: &str,
+    start: u64,
+    end: u64,
+    slice: &mut [u8],
+    on_progress: &(impl Fn(u64) + Sync),
+) -> eyre::Result<()> {
+    let write = |offset: u64, chunk: &[u8]| {
+        let offset = (offset - start) as usize;
+        slice[offset..offset + chunk.len()].copy_from_slice(chunk);
+        Ok(())
+    };
+    fetch_range_with(client, url, start, end, write, on_progress)
+}
+
+/// Hand the inclusive byte range `start..=end` of `url` to `write` chunk by chunk, with the
+/// offset of each chunk in the asset
+fn fetch_range_with(
+    client: &Client,
+    url: &str,
+    start: u64,
+    end: u64,
+    mut write: impl FnMut(u64, &[u8]) -> std::io::Result<()>,
+    on_progress: &(impl Fn(u64) + Sync),
+) -> eyre::Result<()> {
+    let mut response = client
+        .get(url)
+        .header(header::RANGE, format!("bytes={}-{}", start, end))
+        .send()?;
+    if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+        eyre::bail!(
+            "Expected partial content for bytes {}-{} of {}, got {}",
+            start,
+            end,
+            url,
+            response.status()
+        );
+    }
+
+    let length = end - start + 1;
+    let mut filled = 0;
+    let mut chunk = vec![0; CHUNK_SIZE];
+    while filled < length {
+        let until = (length - filled).min(CHUNK_SIZE as u64) as usize;
+        let read = response.read(&mut chunk[..until])?;
+        if read == 0 {
+            eyre::bail!("Segment {}-{} of {} ended early", start, end, url);
+        }
+        write(start + filled, &chunk[..read])?;
+        filled += read as u64;
+        on_progress(read as u64);
+    }
+
+    Ok(())
+}
+
+/// Where throttled progress ends up, a [`ProgressBar`] outside of tests
+pub trait ProgressSink {
+    fn set_length(&self, length: u64);
+    fn set_position(&self, position: u64);
+}
+
+impl ProgressSink for ProgressBar {
+    fn set_length(&self, length: u64) {
+        ProgressBar::set_length(self, length);
+    }
+
+    fn set_position(&self, position: u64) {
+        ProgressBar::set_position(self, position);
+    }
+}
+
+/// Decides when a position update is worth forwarding to the progress bar.
+///
+/// Every update takes the bar's lock and may trigger a redraw, which adds up when it happens for
+/// every chunk of a large download. An update is forwarded once [`Throttle::INTERVAL`] has passed
+/// or the position moved by [`Throttle::STEP_PERCENT`] of the length, whichever comes first.
+pub struct Throttle {
+    length: u64,
+    last_position: u64,
+    last_update: Option<Instant>,
+}
+
+impl Throttle {
+    pub const INTERVAL: Duration = Duration::from_millis(50);
+    pub const STEP_PERCENT: u64 = 1;
+
+    pub fn new(length: u64) -> Self {
+        Throttle {
+            length,
+            last_position: 0,
+            last_update: None,
+        }
+    }
+
+    pub fn should_update(&mut self, position: u64, now: Instant) -> bool {
+        let step = (self.length * Self::STEP_PERCENT / 100).max(1);
+        let due = match self.last_update {
+            None => true,
+            Some(last_update) => {
+                now.duration_since(last_update) >= Self::INTERVAL
+                    || position.abs_diff(self.last_position) >= step
+            }
+        };
+
+        if due {
+            self.last_position = position;
+            self.last_update = Some(now);
+        }
+        due
+    }
+}
+
+pub struct ThrottledProgress<'a, S: ProgressSink> {
+    sink: &'a S,
+    throttle: Throttle,
+    position: u64,
+    reported: u64,
+}
+
+impl<'a, S: ProgressSink> ThrottledProgress<'a, S> {
+    pub fn new(sink: &'a S, length: u64) -> Self {
+        sink.set_length(length);
+        ThrottledProgress {
+            sink,
+            throttle: Throttle::new(length),
+            position: 0,
+            reported: 0,
+        }
+    }
+
+    pub fn set_position(&mut self, position: u64) {
+        self.set_position_at(position, Instant::now());
+    }
+
+    fn set_position_at(&mut self, position: u64, now: Instant) {
+        self.position = position;
+        if self.throttle.should_update(position, now) {
+            self.sink.set_position(position);
+            self.reported = position;
+        }
+    }
+
+    /// Forward the latest position, even when it would be throttled
+    pub fn flush(&mut self) {
+        if self.reported != self.position {
+            self.sink.set_position(self.position);
+            self.reported = self.position;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use crate::test_server::{Route, TestServer};
+
+    use super::*;
+
+    #[derive(Default)]
+    struct CountingSink {
+        length: Cell<u64>,
+        position: Cell<u64>,
+        updates: Cell<u64>,
+    }
+
+    impl ProgressSink for CountingSink {
+        fn set_length(&self, length: u64) {
+            self.length.set(length);
+        }
+
+        fn set_position(&self, position: u64) {
+            self.position.set(position);
+            self.updates.set(self.updates.get() + 1);
+        }
+    }
+
+    #[test]
+    fn test_large_download_is_throttled() {
+        const LENGTH: u64 = 1024 * 1024 * 1024;
+        const CHUNK: u64 = 8 * 1024;
+        let chunks = LENGTH / CHUNK;
+
+        let sink = CountingSink::default();
+        let mut progress = ThrottledProgress::new(&sink, LENGTH);
+        let start = Instant::now();
+        for chunk in 1..=chunks {
+            // Simulate a fast link delivering a chunk every 10 microseconds
+            let now = start + Duration::from_micros(chunk * 10);
+            progress.set_position_at(chunk * CHUNK, now);
+        }
+        progress.flush();
+
+        assert_eq!(sink.length.get(), LENGTH);
+        assert_eq!(sink.position.get(), LENGTH);
+        // Unthrottled, every one of the 131072 chunks would be an update
+        assert!(
+            sink.updates.get() < chunks / 500,
+            "{} updates",
+            sink.updates.get()
+        );
+    }
+
+    #[test]
+    fn test_slow_download_updates_on_interval() {
+        let mut throttle = Throttle::new(1_000_000);
+        let start = Instant::now();
+
+        assert!(throttle.should_update(1, start));
+        assert!(!throttle.should_update(2, start + Duration::from_millis(10)));
+        assert!(throttle.should_update(3, start + Throttle::INTERVAL));
+    }
+
+    #[test]
+    fn test_flush_reports_final_position() {
+        let sink = CountingSink::default();
+        let mut progress = ThrottledProgress::new(&sink, 1_000_000);
+        let start = Instant::now();
+
+        progress.set_position_at(1, start);
+        progress.set_position_at(2, start + Duration::from_millis(1));
+        assert_eq!(sink.position.get(), 1);
+
+        progress.flush();
+
+        assert_eq!(sink.position.get(), 2);
+        assert_eq!(sink.updates.get(), 2);
+    }
+
+    #[test]
+    fn test_segment_ranges_cover_everything() {
+        assert_eq!(segment_ranges(10, 3), vec![(0, 3), (4, 7), (8, 9)]);
+        assert_eq!(segment_ranges(2, 4), vec![(0, 0), (1, 1)]);
+        assert_eq!(segment_ranges(5, 1), vec![(0, 4)]);
+    }
+
+    fn segmented(segments: u32) -> DownloadOptions {
+        DownloadOptions {
+            segments,
+            segment_threshold: 0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_segmented_download_reassembles_the_asset() {
+        let server = TestServer::start();
+        let body: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        server.route("/sdk.tar.gz", Route::ok(body.clone()).with_ranges());
+
+        let data = download_with_progress(
+            &server.url("/sdk.tar.gz"),
+            &ProgressBar::hidden(),
+            &segmented(4),
+        )
+        .unwrap()
+        .asset
+        .read()
+        .unwrap();
+
+        assert_eq!(data, body);
+        let ranged = server
+            .requests()
+            .iter()
+            .filter(|request| request.headers.contains_key("range"))
+            .count();
+        // The probe plus one request per segment
+        assert_eq!(ranged, 5);
+    }
+
+    #[test]
+    fn test_server_without_ranges_falls_back_to_one_stream() {
+        let server = TestServer::start();
+        server.route("/tool", Route::ok(b"whole body".to_vec()));
+
+        let data =
+            download_with_progress(&server.url("/tool"), &ProgressBar::hidden(), &segmented(4))
+                .unwrap()
+                .asset
+                .read()
+                .unwrap();
+
+        assert_eq!(data, b"whole body");
+        let requests: Vec<_> = server
+            .requests()
+            .into_iter()
+            .map(|request| (request.method, request.path))
+            .collect();
+        assert_eq!(
+            requests,
+            vec![
+                ("GET".to_string(), "/tool".to_string()),
+                ("GET".to_string(), "/tool".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_interrupted_download_resumes_where_it_stopped() {
+        let server = TestServer::start();
+        let mut route = Route::ok(b"large asset".to_vec()).with_ranges();
+        route
+            .headers
+            .push(("ETag".to_string(), "\"v1\"".to_string()));
+        server.route("/tool", route.clone().truncated(5));
+        server.route("/tool", route);
+
+        let data = download_with_progress(
+            &server.url("/tool"),
+            &ProgressBar::hidden(),
+            &Default::default(),
+        )
+        .unwrap()
+        .asset
+        .read()
+        .unwrap();
+
+        assert_eq!(data, b"large asset");
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].headers["range"], "bytes=5-");
+        assert_eq!(requests[1].headers["if-range"], "\"v1\"");
+    }
+
+    #[test]
+    fn test_interrupted_download_without_ranges_fails() {
+        let server = TestServer::start();
+        server.route("/tool", Route::ok(b"large asset".to_vec()).truncated(5));
+
+        let error =
+            download_with_progress(&server.url("/tool"), &ProgressBar::hidden(), &retrying(1))
+                .unwrap_err();
+
+        assert_eq!(
+            crate::error::code_of(&error),
+            Some(ErrorCode::DownloadFailed)
+        );
+        assert!(error.to_string().contains("after 5 of 11 bytes"));
+        // Started over once, then given up
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    fn retrying(retries: u32) -> DownloadOptions {
+        DownloadOptions {
+            retry: RetryPolicy {
+                retries,
+                backoff: Duration::ZERO,
+                jitter: false,
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_server_errors_are_retried() {
+        let server = TestServer::start();
+        server.route("/tool", Route::status(503));
+        server.route("/tool", Route::status(502));
+        server.route("/tool", Route::ok(b"binary".to_vec()));
+
+        let data =
+            download_with_progress(&server.url("/tool"), &ProgressBar::hidden(), &retrying(3))
+                .unwrap()
+                .asset
+                .read()
+                .unwrap();
+
+        assert_eq!(data, b"binary");
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[test]
+    fn test_retries_give_up_and_client_errors_are_final() {
+        let server = TestServer::start();
+        server.route("/flaky", Route::status(500));
+        server.route("/missing", Route::status(404));
+
+        let flaky =
+            download_with_progress(&server.url("/flaky"), &ProgressBar::hidden(), &retrying(2))
+                .unwrap_err();
+        let missing = download_with_progress(
+            &server.url("/missing"),
+            &ProgressBar::hidden(),
+            &retrying(2),
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            crate::error::code_of(&flaky),
+            Some(ErrorCode::DownloadFailed)
+        );
+        assert!(flaky.to_string().contains("500"));
+        assert_eq!(
+            crate::error::code_of(&missing),
+            Some(ErrorCode::DownloadFailed)
+        );
+        let paths: Vec<_> = server.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(paths, ["/flaky", "/flaky", "/flaky", "/missing"]);
+    }
+
+    #[test]
+    fn test_backoff_doubles_with_jitter_on_top() {
+        let policy = RetryPolicy {
+            retries: 3,
+            backoff: Duration::from_millis(100),
+            jitter: false,
+        };
+        assert_eq!(policy.delay(0), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(400));
+
+        let jittered = RetryPolicy {
+            jitter: true,
+            ..policy
+        }
+        .delay(1);
+        assert!(jittered >= Duration::from_millis(200) && jittered < Duration::from_millis(400));
+    }
+
+    fn rate_limited(retry_after: &str) -> Route {
+        let mut route = Route::status(429);
+        route
+            .headers
+            .push(("Retry-After".to_string(), retry_after.to_string()));
+        route
+    }
+
+    #[test]
+    fn test_short_rate_limits_are_waited_out() {
+        let server = TestServer::start();
+        server.route("/tool", rate_limited("0"));
+        let mut exhausted = Route::status(403);
+        exhausted.headers = vec![
+            ("x-ratelimit-remaining".to_string(), "0".to_string()),
+            ("x-ratelimit-reset".to_string(), "0".to_string()),
+        ];
+        server.route("/tool", exhausted);
+        server.route("/tool", Route::ok(b"tool".to_vec()));
+
+        let data = download_with_progress(
+            &server.url("/tool"),
+            &ProgressBar::hidden(),
+            &DownloadOptions::default(),
+        )
+        .unwrap()
+        .asset
+        .read()
+        .unwrap();
+
+        assert_eq!(data, b"tool");
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[test]
+    fn test_long_rate_limits_fail_with_the_reset_time() {
+        let server = TestServer::start();
+        server.route("/tool", rate_limited("3600"));
+
+        let error = download_with_progress(
+            &server.url("/tool"),
+            &ProgressBar::hidden(),
+            &DownloadOptions::default(),
+        )
+        .unwrap_err();
+
+        assert_eq!(crate::error::code_of(&error), Some(ErrorCode::RateLimited));
+        assert!(
+            error.to_string().contains("rate limited until "),
+            "{}",
+            error
+        );
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn test_small_assets_are_not_segmented() {
+        let server = TestServer::start();
+        server.route("/tool", Route::ok(b"small".to_vec()).with_ranges());
+        let options = DownloadOptions {
+            segments: 4,
+            segment_threshold: 1024,
+            ..Default::default()
+        };
+
+        let data = download_with_progress(&server.url("/tool"), &ProgressBar::hidden(), &options)
+            .unwrap()
+            .asset
+            .read()
+            .unwrap();
+
+        assert_eq!(data, b"small");
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn test_headers_are_sent_with_every_request() {
+        let server = TestServer::start();
+        let asset: Vec<u8> = (0..4096u32).map(|i| (i % 251) as u8).collect();
+        server.route("/tool", Route::ok(asset.clone()).with_ranges());
+        let options = DownloadOptions {
+            headers: vec![("Authorization".to_string(), "Bearer secret".to_string())],
+            ..segmented(4)
+        };
+
+        let data = download_with_progress(&server.url("/tool"), &ProgressBar::hidden(), &options)
+            .unwrap()
+            .asset
+            .read()
+            .unwrap();
+
+        assert_eq!(data, asset);
+        let requests = server.requests();
+        assert_eq!(requests.len(), 5);
+        assert!(requests
+            .iter()
+            .all(|request| request.headers["authorization"] == "Bearer secret"));
+
+        let unset = DownloadOptions {
+            headers: vec![("X-Api-Key".to_string(), "$UNSET_IN_TESTS".to_string())],
+            ..Default::default()
+        };
+        let error = download_with_progress(&server.url("/tool"), &ProgressBar::hidden(), &unset)
+            .unwrap_err();
+        assert!(error.to_string().contains("UNSET_IN_TESTS"), "{:#}", error);
+    }
+}
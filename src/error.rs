@@ -0,0 +1,430 @@
+use std::fmt;
+
+/// Stable codes for the failures users commonly run into.
+///
+/// The codes are part of the user interface: they are printed with errors and looked up by
+/// `workstation explain`, so existing codes must never be renumbered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    DownloadFailed,
+    MissingContentLength,
+    PinnedContentChanged,
+    RateLimited,
+    SignatureInvalid,
+    ChecksumMismatch,
+    Offline,
+    EntryNotFound,
+    UnsupportedArchive,
+    EntryIsDirectory,
+    EmptyContent,
+    NoBinaryForPlatform,
+    UnknownConfigKey,
+    NotOnPath,
+}
+
+impl ErrorCode {
+    pub const ALL: &'static [ErrorCode] = &[
+        ErrorCode::DownloadFailed,
+        ErrorCode::MissingContentLength,
+        ErrorCode::PinnedContentChanged,
+        ErrorCode::RateLimited,
+        ErrorCode::SignatureInvalid,
+        ErrorCode::ChecksumMismatch,
+        ErrorCode::Offline,
+        ErrorCode::EntryNotFound,
+        ErrorCode::UnsupportedArchive,
+        ErrorCode::EntryIsDirectory,
+        ErrorCode::EmptyContent,
+        ErrorCode::NoBinaryForPlatform,
+        ErrorCode::UnknownConfigKey,
+        ErrorCode::NotOnPath,
+    ];
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorCode::DownloadFailed => "E001",
+            ErrorCode::MissingContentLength => "E002",
+            ErrorCode::PinnedContentChanged => "E003",
+            ErrorCode::RateLimited => "E004",
+            ErrorCode::SignatureInvalid => "E005",
+            ErrorCode::ChecksumMismatch => "E006",
+            ErrorCode::Offline => "E007",
+            ErrorCode::EntryNotFound => "E010",
+            ErrorCode::UnsupportedArchive => "E011",
+            ErrorCode::EntryIsDirectory => "E012",
+            ErrorCode::EmptyContent => "E013",
+            ErrorCode::NoBinaryForPlatform => "E014",
+            ErrorCode::UnknownConfigKey => "E020",
+            ErrorCode::NotOnPath => "E030",
+        }
+    }
+
+    pub fn parse(code: &str) -> Option<ErrorCode> {
+        ErrorCode::ALL
+            .iter()
+            .copied()
+            .find(|candidate| candidate.code().eq_ignore_ascii_case(code))
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            ErrorCode::DownloadFailed => "Download failed",
+            ErrorCode::MissingContentLength => "Missing content length",
+            ErrorCode::PinnedContentChanged => "Downloaded content differs from the pin",
+            ErrorCode::RateLimited => "Rate limited by the server",
+            ErrorCode::SignatureInvalid => "Signature verification failed",
+            ErrorCode::ChecksumMismatch => {
+                "Downloaded content differs from its sha256 or checksum file"
+            }
+            ErrorCode::Offline => "Needs the network in an offline run",
+            ErrorCode::EntryNotFound => "Entry not found in archive",
+            ErrorCode::UnsupportedArchive => "Unsupported archive format",
+            ErrorCode::EntryIsDirectory => "Archive entry is a directory",
+            ErrorCode::EmptyContent => "Downloaded content is empty",
+            ErrorCode::NoBinaryForPlatform => "No unique binary for this platform",
+            ErrorCode::UnknownConfigKey => "Unknown key in the config",
+            ErrorCode::NotOnPath => "Install location is not on PATH",
+        }
+    }
+
+    pub fn explanation(&self) -> &'static str {
+        match self {
+            ErrorCode::DownloadFailed => {
+                "The server answered the download request with an error status.
+
+- Open the URL in a browser or run `curl -I <url>` to see the status code.
+- Release URLs change when a project renames its assets; check the release page
+  and update `url` or `archive` in the config.
+- A 404 for a private repository usually means the request was not authenticated.
+- 5xx responses and dropped connections were already retried; raise `retries` in
+  the `[workstation.download]` table for a server that is often briefly unavailable."
+            }
+            ErrorCode::MissingContentLength => {
+                "The server did not send a Content-Length header, so the download size is unknown.
+
+- Some mirrors and proxies strip the header; try the canonical release URL.
+- Run `curl -I <url>` and look for `content-length` in the response."
+            }
+            ErrorCode::PinnedContentChanged => {
+                "The package sets `pin_hash`, but the asset now served at its URL has different
+content. Upstream most likely republished the asset under the same URL.
+
+- Nothing was installed; the previously installed version is untouched.
+- If the new content is expected, set `pin_hash` to the digest printed with the
+  error and run `workstation lock update <name>`.
+- Otherwise keep the pin; it keeps working offline while the pinned asset is in
+  the cache."
+            }
+            ErrorCode::RateLimited => {
+                "The server refused the download because too many requests were made, GitHub does
+this once the hourly quota of an address is used up.
+
+- Short waits announced by the server are waited out and the download retried;
+  the error names the time the limit lifts when the wait is longer.
+- For GitHub, set `GITHUB_TOKEN` or `[workstation.download] github_token`; lookups with a
+  token have a far higher quota.
+- Run setup again after that time. Packages that are already installed keep
+  working in the meantime."
+            }
+            ErrorCode::SignatureInvalid => {
+                "The package sets `signature` and `public_key`, but the downloaded asset is not
+signed with that key, or the signature could not be read.
+
+- Nothing was installed; the previously installed version is untouched.
+- Check that `signature` points at the signature of this very asset; with
+  `{url}` it is derived from the asset URL, for example `{url}.minisig`.
+- A new release may be signed with a new key. Only update `public_key` after
+  confirming the key change with the project, that is what the check is for."
+            }
+            ErrorCode::ChecksumMismatch => {
+                "The package sets `sha256` or `checksum_url`, and the asset does not match the
+digest it sets or the checksum file lists for its file name.
+
+- Nothing was installed; the previously installed version is untouched.
+- The download may have been corrupted or tampered with on the way, or a
+  mirror serves something else than the release. Try again, or without the
+  mirror.
+- Check that `sha256` or `checksum_url` belongs to the same release as the
+  asset. A local archive replaced by another release needs its new digest."
+            }
+            ErrorCode::Offline => {
+                "`setup --offline` makes no requests, and the package cannot be installed without
+one. Assets only come from the download cache and local paths then.
+
+- An asset is only in the cache once an online run downloaded it, and only if it
+  has a `pin_hash` or its server sent an ETag or Last-Modified header.
+- GitHub releases install the release locked in the lockfile; run
+  `workstation lock update` while online to lock one.
+- Packages built or installed by another tool, such as `crate`, `go`, `pipx`,
+  `git` or system packages, need an online run.
+- Packages that installed fine stay installed; run setup again once online."
+            }
+            ErrorCode::EntryNotFound => {
+                "The archive was downloaded, but it contains no entry matching `bin`.
+
+- `bin` must be the full path of the file inside the archive, for example
+  `ripgrep-14.1.0-x86_64-unknown-linux-musl/rg` rather than `rg`.
+- To survive version changes in the path, use a glob such as `ripgrep-*/rg` or a
+  regular expression such as `regex:^ripgrep-[0-9.]+-x86_64[^/]*/rg$`.
+- List the archive entries to find the right path, the archive is read from the
+  download cache when it is there:
+    workstation cat <name> --list"
+            }
+            ErrorCode::UnsupportedArchive => {
+                "The archive URL does not end in an extension workstation knows how to unpack,
+and its content does not start like a known format either.
+
+- Supported formats are `.tar.gz` (`.tgz`), `.tar.xz` (`.txz`), `.tar.bz2`
+  (`.tbz2`), `.tar.zst` (`.tzst`) and `.zip`.
+- If the URL points at a plain executable, use `url` instead of `archive`."
+            }
+            ErrorCode::EntryIsDirectory => {
+                "`bin` matched a directory inside the archive instead of a file.
+
+- Point `bin` at the executable inside the directory; the error lists the files
+  it contains and suggests the executable when there is exactly one.
+- Directory entries usually end in `/`, and `bin` should not."
+            }
+            ErrorCode::EmptyContent => {
+                "The file that would be installed is empty, which is almost always a config or
+upstream bug.
+
+- Check that `url` or `bin` points at the right asset.
+- If the empty file is intentional, set `allow_empty = true` on the package."
+            }
+            ErrorCode::NoBinaryForPlatform => {
+                "`auto_arch_bin` matched files in the archive, but not exactly one of them is an
+executable for this platform.
+
+- The error lists every candidate with the platforms its ELF or Mach-O header
+  declares; files that are not executables (scripts, docs) never match.
+- Narrow the pattern so only one binary per platform matches, or set `bin` to
+  the exact path instead.
+- For a GitHub release, the error lists the release assets instead; set
+  `asset_pattern` to a glob that only matches the right one."
+            }
+            ErrorCode::UnknownConfigKey => {
+                "The config has a key that no setting uses. Usually it is a typo, and the setting
+it was meant for silently keeps its default.
+
+- Fix the key at the line and column given; a close known key is suggested when
+  there is one.
+- `workstation schema` prints every key the config accepts.
+- A config written for a newer workstation may use keys this version does not
+  know yet. Pass `--allow-unknown-keys` to only warn about them."
+            }
+            ErrorCode::NotOnPath => {
+                "Packages were installed, but the install location is not on PATH, so your shell
+will not find them.
+
+- Set `manage_path = true` in the `[workstation.install]` table, and `setup` adds the location
+  to PATH in the workstation block of your shell rc files.
+- Or add it to PATH in your shell rc file yourself, for example:
+    export PATH=\"$HOME/.local/bin:$PATH\"
+- Open a new shell afterwards and check with `command -v <name>`."
+            }
+        }
+    }
+
+    /// Create an error of this category
+    pub fn error(self, message: impl Into<String>) -> eyre::Report {
+        eyre::Report::new(CodedError {
+            code: self,
+            message: message.into(),
+        })
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+#[derive(Debug)]
+pub struct CodedError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl fmt::Display for CodedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for CodedError {}
+
+/// The code of the first categorized error in the chain of `report`
+pub fn code_of(report: &eyre::Report) -> Option<ErrorCode> {
+    report
+        .chain()
+        .find_map(|error| error.downcast_ref::<CodedError>())
+        .map(|error| error.code)
+}
+
+/// Render an error for the user, pointing at `workstation explain` when it has a code
+pub fn render(report: &eyre::Report) -> String {
+    match code_of(report) {
+        Some(code) => format!(
+            "{:?}\n\nRun `workstation explain {}` for help",
+            report, code
+        ),
+        None => format!("{:?}", report),
+    }
+}
+
+/// The root cause of `report` without the details that differ between packages, so packages
+/// failing for the same reason share it.
+///
+/// URLs are shortened to their host, and the first host in the chain is added when the root
+/// cause does not mention one, as with TLS and connection errors.
+pub fn cause(report: &eyre::Report) -> String {
+    let messages: Vec<String> = report.chain().map(|error| error.to_string()).collect();
+    let root = messages
+        .last()
+        .map(|root| shorten_urls(root))
+        .unwrap_or_default();
+
+    match messages.iter().find_map(|message| first_host(message)) {
+        Some(host) if !root.contains(host.as_str()) => format!("{} ({})", root, host),
+        _ => root,
+    }
+}
+
+/// Where a URL in `text` ends
+fn url_end(text: &str) -> usize {
+    text.find(|c: char| c.is_whitespace() || matches!(c, ')' | '"' | '\'' | ',' | '>'))
+        .unwrap_or(text.len())
+}
+
+fn url_host(url: &str) -> Option<String> {
+    reqwest::Url::parse(url.trim_end_matches([':', '.']))
+        .ok()?
+        .host_str()
+        .map(str::to_string)
+}
+
+fn first_host(text: &str) -> Option<String> {
+    let start = text.find("://")?;
+    let start = text[..start]
+        .rfind(|c: char| !c.is_ascii_alphabetic())
+        .map_or(0, |index| index + 1);
+    let url = &text[start..];
+    url_host(&url[..url_end(url)])
+}
+
+fn shorten_urls(text: &str) -> String {
+    let mut shortened = String::new();
+    let mut rest = text;
+    while let Some(index) = ["https://", "http://"]
+        .iter()
+        .filter_map(|scheme| rest.find(scheme))
+        .min()
+    {
+        shortened.push_str(&rest[..index]);
+        let end = index + url_end(&rest[index..]);
+        let url = &rest[index..end];
+        let trailing = &url[url.trim_end_matches([':', '.']).len()..];
+        match url_host(url) {
+            Some(host) => {
+                shortened.push_str(&host);
+                shortened.push_str(trailing);
+            }
+            None => shortened.push_str(url),
+        }
+        rest = &rest[end..];
+    }
+    shortened.push_str(rest);
+
+    shortened
+}
+
+pub fn explain(code: &str) -> eyre::Result<()> {
+    let Some(code) = ErrorCode::parse(code) else {
+        let known: Vec<_> = ErrorCode::ALL.iter().map(|code| code.code()).collect();
+        eyre::bail!(
+            "Unknown error code {}, known codes: {}",
+            code,
+            known.join(", ")
+        );
+    };
+
+    println!("{}: {}\n", code, code.title());
+    println!("{}", code.explanation());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use eyre::Context;
+
+    use super::*;
+
+    #[test]
+    fn test_codes_are_stable() {
+        let codes: Vec<_> = ErrorCode::ALL.iter().map(|code| code.code()).collect();
+
+        assert_eq!(
+            codes,
+            vec![
+                "E001", "E002", "E003", "E004", "E005", "E006", "E007", "E010", "E011", "E012",
+                "E013", "E014", "E020", "E030"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_codes_round_trip() {
+        for code in ErrorCode::ALL {
+            assert_eq!(ErrorCode::parse(code.code()), Some(*code));
+        }
+        assert_eq!(ErrorCode::parse("e010"), Some(ErrorCode::EntryNotFound));
+        assert_eq!(ErrorCode::parse("E999"), None);
+    }
+
+    #[test]
+    fn test_code_is_found_through_context() {
+        let report = Err::<(), _>(ErrorCode::EntryNotFound.error("rg"))
+            .context("Searching for entry")
+            .context("Installing rg")
+            .unwrap_err();
+
+        assert_eq!(code_of(&report), Some(ErrorCode::EntryNotFound));
+        assert!(render(&report).contains("workstation explain E010"));
+        assert!(ErrorCode::EntryNotFound
+            .explanation()
+            .contains("workstation cat <name> --list"));
+    }
+
+    #[test]
+    fn test_cause_drops_package_details() {
+        let cases = [
+            (
+                ErrorCode::DownloadFailed
+                    .error("Failed to download https://github.com/a/rg.tar.gz: 404 Not Found"),
+                "[E001] Failed to download github.com: 404 Not Found",
+            ),
+            (
+                eyre::eyre!("certificate verify failed")
+                    .wrap_err("error sending request for url (https://proxy.corp/fd)"),
+                "certificate verify failed (proxy.corp)",
+            ),
+            (eyre::eyre!("disk full"), "disk full"),
+        ];
+
+        for (report, expected) in cases {
+            let report = report.wrap_err("Installing rg");
+            assert_eq!(cause(&report), expected);
+        }
+    }
+
+    #[test]
+    fn test_uncategorized_errors_have_no_hint() {
+        let report = eyre::eyre!("something else");
+
+        assert_eq!(code_of(&report), None);
+        assert!(!render(&report).contains("workstation explain"));
+    }
+}
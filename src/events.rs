@@ -0,0 +1,305 @@
+use std::{
+    io::Write,
+    os::{fd::FromRawFd, unix::net::UnixStream},
+    path::Path,
+    sync::{mpsc, Arc, Mutex, MutexGuard, Weak},
+    time::Duration,
+};
+
+use eyre::Context;
+use indicatif::ProgressBar;
+use serde::Serialize;
+
+/// Bumped whenever an event changes incompatibly, consumers should check it on every line
+pub const EVENTS_VERSION: u32 = 1;
+
+/// Position updates are sent at most this often per package
+const TICK: Duration = Duration::from_millis(250);
+
+/// Events queued for a slow consumer before new ones are dropped
+const QUEUE: usize = 1024;
+
+/// How long the end of the run waits for a slow consumer to take the last events
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Started {
+        package: String,
+    },
+    Length {
+        package: String,
+        bytes: u64,
+    },
+    Progress {
+        package: String,
+        position: u64,
+    },
+    Finished {
+        package: String,
+    },
+    Failed {
+        package: String,
+        error: String,
+    },
+    Summary {
+        installed: usize,
+        failed: usize,
+        rolled_back: usize,
+        skipped: usize,
+    },
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    version: u32,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+struct Tracked {
+    package: String,
+    progress: ProgressBar,
+    length: Option<u64>,
+    position: u64,
+}
+
+/// Newline delimited JSON progress events for wrapper UIs, see `setup --progress-fd`.
+///
+/// Events are handed to a writer thread through a bounded queue, so a slow, closed or missing
+/// consumer only loses events and never blocks the install workers.
+pub struct EventStream {
+    sender: Mutex<Option<mpsc::SyncSender<String>>>,
+    closed: Mutex<Option<mpsc::Receiver<()>>>,
+    tracked: Mutex<Vec<Tracked>>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl EventStream {
+    pub fn start(mut writer: Box<dyn Write + Send>) -> Arc<Self> {
+        let (sender, receiver) = mpsc::sync_channel::<String>(QUEUE);
+        let (closed_sender, closed) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in receiver {
+                if writeln!(writer, "{}", line)
+                    .and_then(|_| writer.flush())
+                    .is_err()
+                {
+                    // The consumer went away, dropping the receiver disconnects the senders
+                    break;
+                }
+            }
+            let _ = closed_sender.send(());
+        });
+
+        let stream = Arc::new(EventStream {
+            sender: Mutex::new(Some(sender)),
+            closed: Mutex::new(Some(closed)),
+            tracked: Mutex::new(vec![]),
+        });
+
+        let ticker = Arc::downgrade(&stream);
+        std::thread::spawn(move || loop {
+            std::thread::sleep(TICK);
+            match Weak::upgrade(&ticker) {
+                Some(stream) => stream.tick(),
+                None => return,
+            }
+        });
+
+        stream
+    }
+
+    fn send(&self, event: &Event) {
+        let line = serde_json::to_string(&Envelope {
+            version: EVENTS_VERSION,
+            event,
+        })
+        .expect("event serializes");
+        if let Some(sender) = &*lock(&self.sender) {
+            let _ = sender.try_send(line);
+        }
+    }
+
+    /// A package started, its position is followed through `progress` until it finishes
+    pub fn started(&self, package: &str, progress: &ProgressBar) {
+        self.send(&Event::Started {
+            package: package.to_string(),
+        });
+        lock(&self.tracked).push(Tracked {
+            package: package.to_string(),
+            progress: progress.clone(),
+            length: None,
+            position: 0,
+        });
+    }
+
+    /// Send the length and position of every package that changed since the last tick
+    fn tick(&self) {
+        for tracked in lock(&self.tracked).iter_mut() {
+            self.update(tracked);
+        }
+    }
+
+    fn update(&self, tracked: &mut Tracked) {
+        let length = tracked.progress.length().filter(|length| *length > 0);
+        if length.is_some() && length != tracked.length {
+            tracked.length = length;
+            self.send(&Event::Length {
+                package: tracked.package.clone(),
+                bytes: length.unwrap_or_default(),
+            });
+        }
+        let position = tracked.progress.position();
+        if position != tracked.position {
+            tracked.position = position;
+            self.send(&Event::Progress {
+                package: tracked.package.clone(),
+                position,
+            });
+        }
+    }
+
+    /// A package finished, with the rendered error if it failed
+    pub fn finished(&self, package: &str, error: Option<String>) {
+        {
+            let mut tracked = lock(&self.tracked);
+            if let Some(index) = tracked.iter().position(|t| t.package == package) {
+                let mut finished = tracked.remove(index);
+                self.update(&mut finished);
+            }
+        }
+
+        let package = package.to_string();
+        self.send(&match error {
+            Some(error) => Event::Failed { package, error },
+            None => Event::Finished { package },
+        });
+    }
+
+    /// Send the run summary and give the consumer a moment to read everything
+    pub fn close(&self, installed: usize, failed: usize, rolled_back: usize, skipped: usize) {
+        self.send(&Event::Summary {
+            installed,
+            failed,
+            rolled_back,
+            skipped,
+        });
+        lock(&self.sender).take();
+        if let Some(closed) = lock(&self.closed).take() {
+            let _ = closed.recv_timeout(CLOSE_TIMEOUT);
+        }
+    }
+}
+
+/// Open the stream for `--progress-fd` or `--progress-socket`
+pub fn open(fd: Option<i32>, socket: Option<&Path>) -> eyre::Result<Option<Arc<EventStream>>> {
+    let writer: Box<dyn Write + Send> = match (fd, socket) {
+        (Some(fd), _) => {
+            // Fail early on a descriptor the parent did not pass down
+            if fd < 0 || !Path::new(&format!("/dev/fd/{}", fd)).exists() {
+                eyre::bail!("File descriptor {} given to --progress-fd is not open", fd);
+            }
+            // SAFETY: the descriptor is open and nothing else in workstation uses it
+            Box::new(unsafe { std::fs::File::from_raw_fd(fd) })
+        }
+        (None, Some(path)) => Box::new(
+            UnixStream::connect(path)
+                .with_context(|| format!("Connecting to {}", path.display()))?,
+        ),
+        (None, None) => return Ok(None),
+    };
+
+    Ok(Some(EventStream::start(writer)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone)]
+    struct Recorder(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Recorder {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct Closed;
+
+    impl Write for Closed {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_recorded_stream_parses() {
+        let recorder = Recorder(Arc::new(Mutex::new(vec![])));
+        let stream = EventStream::start(Box::new(recorder.clone()));
+        let rg = ProgressBar::hidden();
+        let fd = ProgressBar::hidden();
+
+        stream.started("rg", &rg);
+        stream.started("fd", &fd);
+        rg.set_length(100);
+        rg.set_position(100);
+        stream.finished("rg", None);
+        stream.finished("fd", Some("[E001] Failed to download fd: 404".to_string()));
+        stream.close(1, 1, 0, 0);
+
+        let recorded = String::from_utf8(recorder.0.lock().unwrap().clone()).unwrap();
+        let events: Vec<serde_json::Value> = recorded
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert!(events.iter().all(|e| e["version"] == EVENTS_VERSION));
+        let kinds: Vec<_> = events
+            .iter()
+            .map(|e| (e["event"].as_str().unwrap(), e["package"].as_str()))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("started", Some("rg")),
+                ("started", Some("fd")),
+                ("length", Some("rg")),
+                ("progress", Some("rg")),
+                ("finished", Some("rg")),
+                ("failed", Some("fd")),
+                ("summary", None),
+            ]
+        );
+        assert_eq!(events[2]["bytes"], 100);
+        assert_eq!(events[5]["error"], "[E001] Failed to download fd: 404");
+        assert_eq!(events[6]["installed"], 1);
+    }
+
+    #[test]
+    fn test_closed_consumer_never_blocks() {
+        let stream = EventStream::start(Box::new(Closed));
+        let progress = ProgressBar::hidden();
+
+        for index in 0..(QUEUE * 4) {
+            let package = format!("package-{}", index);
+            stream.started(&package, &progress);
+            stream.finished(&package, None);
+        }
+        stream.close(QUEUE * 4, 0, 0, 0);
+    }
+}
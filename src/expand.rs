@@ -0,0 +1,307 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+/// The environment user-supplied paths are expanded against
+#[derive(Debug, Clone)]
+pub struct Env {
+    overrides: HashMap<String, String>,
+    inherit: bool,
+    /// Every expanded path is moved below this directory, see [`Env::sandboxed`]
+    root: Option<PathBuf>,
+}
+
+static CURRENT: OnceLock<Env> = OnceLock::new();
+
+impl Env {
+    /// The environment of this run, the process environment unless [`Env::init`] replaced it
+    pub fn current() -> &'static Env {
+        CURRENT.get_or_init(Env::process)
+    }
+
+    /// The process environment
+    pub fn process() -> Env {
+        Env {
+            overrides: HashMap::new(),
+            inherit: true,
+            root: None,
+        }
+    }
+
+    /// Replace the process environment for the rest of the run, before anything expanded a path
+    pub fn init(env: Env) -> eyre::Result<()> {
+        CURRENT
+            .set(env)
+            .map_err(|_| eyre::eyre!("The environment was already in use"))
+    }
+
+    /// This environment with `root` as a fake file system root.
+    ///
+    /// `/home/u/.local/bin` becomes `<root>/home/u/.local/bin`, relative paths are taken relative
+    /// to `root`, so nothing is written outside of it.
+    pub fn sandboxed(&self, root: &Path) -> Env {
+        Env {
+            root: Some(root.to_path_buf()),
+            ..self.clone()
+        }
+    }
+
+    /// Move `path` below the sandbox root, if there is one
+    pub fn rooted(&self, path: PathBuf) -> PathBuf {
+        match &self.root {
+            Some(root) if !path.starts_with(root) => {
+                root.join(path.strip_prefix("/").unwrap_or(&path))
+            }
+            _ => path,
+        }
+    }
+
+    /// The home directory inside the sandbox, for commands that should not see the real one
+    pub fn sandbox_home(&self) -> Option<PathBuf> {
+        self.root.as_ref()?;
+        Some(self.rooted(PathBuf::from(self.var("HOME")?)))
+    }
+
+    /// An environment made only of `vars`
+    #[cfg(test)]
+    pub fn from_vars(vars: &[(&str, &str)]) -> Self {
+        Env {
+            overrides: vars
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            inherit: false,
+            root: None,
+        }
+    }
+
+    pub fn var(&self, name: &str) -> Option<String> {
+        match self.overrides.get(name) {
+            Some(value) => Some(value.clone()),
+            None if self.inherit => std::env::var(name).ok(),
+            None => None,
+        }
+    }
+}
+
+/// Names the config field a value came from, so errors can point at it
+#[derive(Debug, Clone, Copy)]
+pub struct Field<'a> {
+    pub name: &'a str,
+    /// The package or section owning the field
+    pub owner: &'a str,
+}
+
+impl<'a> Field<'a> {
+    pub fn new(name: &'a str, owner: &'a str) -> Self {
+        Field { name, owner }
+    }
+}
+
+impl std::fmt::Display for Field<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "`{}` of {}", self.name, self.owner)
+    }
+}
+
+/// Expand a leading `~` and `$VAR`/`${VAR}` references in a filesystem path
+pub fn expand_path(value: &str, field: Field) -> eyre::Result<PathBuf> {
+    expand_path_in(Env::current(), value, field)
+}
+
+/// Expand a path workstation only reads from, such as a local archive. A sandbox does not apply,
+/// the file is read from where it really is.
+pub fn expand_source_path(value: &str, field: Field) -> eyre::Result<PathBuf> {
+    expand_path_in(&Env::process(), value, field)
+}
+
+/// Expand `$VAR`/`${VAR}` references in a value that is not a filesystem path
+pub fn expand_vars(value: &str, field: Field) -> eyre::Result<String> {
+    expand_vars_in(Env::current(), value, field)
+}
+
+pub fn expand_path_in(env: &Env, value: &str, field: Field) -> eyre::Result<PathBuf> {
+    let expanded = expand_vars_in(env, value, field)?;
+
+    let rest = match value.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            expanded.strip_prefix('~').expect("value starts with ~")
+        }
+        _ => return Ok(env.rooted(PathBuf::from(expanded))),
+    };
+    let home = env
+        .var("HOME")
+        .ok_or_else(|| eyre::eyre!("{} starts with ~, but HOME is not set: {:?}", field, value))?;
+
+    Ok(env.rooted(PathBuf::from(home + rest)))
+}
+
+fn expand_vars_in(env: &Env, value: &str, field: Field) -> eyre::Result<String> {
+    let mut expanded = String::with_capacity(value.len());
+    for piece in split_vars(value, field)? {
+        match piece {
+            Piece::Text(text) => expanded.push_str(&text),
+            Piece::Var(name) => {
+                let variable = env.var(&name).ok_or_else(|| {
+                    eyre::eyre!("{} uses ${}, which is not set: {:?}", field, name, value)
+                })?;
+                expanded.push_str(&variable);
+            }
+        }
+    }
+
+    Ok(expanded)
+}
+
+/// A part of a value with `$VAR`/`${VAR}` references, see [`split_vars`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Piece {
+    Text(String),
+    Var(String),
+}
+
+/// `value` cut into its literal text and the variables it references, by the rules
+/// [`expand_vars`] expands them with. Consecutive text is one piece.
+pub fn split_vars(value: &str, field: Field) -> eyre::Result<Vec<Piece>> {
+    let mut pieces = vec![];
+    let mut text = String::new();
+    let mut rest = value;
+
+    while let Some(index) = rest.find('$') {
+        text.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+
+        let (name, remaining) = if let Some(braced) = rest.strip_prefix('{') {
+            let end = braced
+                .find('}')
+                .ok_or_else(|| eyre::eyre!("{} has an unclosed ${{: {:?}", field, value))?;
+            (&braced[..end], &braced[end + 1..])
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        };
+
+        if name.is_empty() {
+            // `$$` is a literal dollar, so is a `$` not followed by a name
+            text.push('$');
+            rest = rest.strip_prefix('$').unwrap_or(rest);
+            continue;
+        }
+
+        if !text.is_empty() {
+            pieces.push(Piece::Text(std::mem::take(&mut text)));
+        }
+        pieces.push(Piece::Var(name.to_string()));
+        rest = remaining;
+    }
+    text.push_str(rest);
+    if !text.is_empty() {
+        pieces.push(Piece::Text(text));
+    }
+
+    Ok(pieces)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> Env {
+        Env::from_vars(vars)
+    }
+
+    #[test]
+    fn test_path_fields() {
+        let env = env(&[("HOME", "/home/u"), ("DOTFILES_DIR", "/home/u/dotfiles")]);
+        let cases = [
+            ("location", "~/.local/bin", "/home/u/.local/bin"),
+            ("location", "~", "/home/u"),
+            ("location", "/opt/bin", "/opt/bin"),
+            ("location", "$HOME/bin", "/home/u/bin"),
+            ("location", "${HOME}/bin", "/home/u/bin"),
+            ("location", "~user/bin", "~user/bin"),
+            ("source", "$DOTFILES_DIR/nvim", "/home/u/dotfiles/nvim"),
+            (
+                "hook",
+                "~/dotfiles/scripts/setup-fzf.sh",
+                "/home/u/dotfiles/scripts/setup-fzf.sh",
+            ),
+        ];
+
+        for (name, value, expected) in cases {
+            let field = Field::new(name, "package rg");
+            assert_eq!(
+                expand_path_in(&env, value, field).unwrap(),
+                PathBuf::from(expected),
+                "{} = {:?}",
+                name,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn test_non_path_fields() {
+        let env = env(&[("HOME", "/home/u"), ("VERSION", "14.1.0")]);
+        let cases = [
+            ("bin", "ripgrep-$VERSION/rg", "ripgrep-14.1.0/rg"),
+            ("bin", "~/rg", "~/rg"),
+            ("bin", "cost$$", "cost$"),
+            ("bin", "a$-b", "a$-b"),
+        ];
+
+        for (name, value, expected) in cases {
+            let field = Field::new(name, "package rg");
+            assert_eq!(expand_vars_in(&env, value, field).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_sandbox_roots_every_path() {
+        let env = env(&[("HOME", "/home/u"), ("DATA", "/srv/data")]).sandboxed(Path::new("/sb"));
+        let cases = [
+            ("~/.local/bin", "/sb/home/u/.local/bin"),
+            ("$DATA/x", "/sb/srv/data/x"),
+            ("bin", "/sb/bin"),
+            ("/sb/already/inside", "/sb/already/inside"),
+        ];
+
+        for (value, expected) in cases {
+            let field = Field::new("location", "section linux_x86_64");
+            assert_eq!(
+                expand_path_in(&env, value, field).unwrap(),
+                PathBuf::from(expected)
+            );
+        }
+        assert_eq!(env.sandbox_home(), Some(PathBuf::from("/sb/home/u")));
+    }
+
+    #[test]
+    fn test_errors_name_the_field() {
+        let env = env(&[]);
+        let cases = [
+            (
+                "location",
+                "$NOPE/bin",
+                "`location` of section linux_x86_64 uses $NOPE",
+            ),
+            ("location", "~/bin", "HOME is not set"),
+            ("location", "${HOME/bin", "unclosed"),
+        ];
+
+        for (name, value, expected) in cases {
+            let field = Field::new(name, "section linux_x86_64");
+            let error = expand_path_in(&env, value, field).unwrap_err();
+            assert!(
+                error.to_string().contains(expected),
+                "{:?} does not mention {:?}",
+                error.to_string(),
+                expected
+            );
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use eyre::Context;
+
+use crate::{
+    completions::Shell,
+    expand::{self, Field, Piece},
+};
+
+const HEADER: &str =
+    "# Generated by workstation from the [workstation.env] table of its config, changes are overwritten";
+
+/// The file of `shell` in `directory` that exports the `[workstation.env]` variables
+pub fn file(shell: Shell, directory: &Path) -> PathBuf {
+    match shell {
+        Shell::Bash | Shell::Zsh => directory.join("env.sh"),
+        Shell::Fish => directory.join("env.fish"),
+    }
+}
+
+/// The rc file line of `shell` that loads its file in `directory`
+pub fn source_line(shell: Shell, directory: &Path) -> String {
+    let path = file(shell, directory).to_string_lossy().into_owned();
+    match shell {
+        Shell::Bash | Shell::Zsh => format!(". {}", quote(shell, &path)),
+        Shell::Fish => format!("source {}", quote(shell, &path)),
+    }
+}
+
+/// Why `name` cannot be exported, if it cannot
+pub fn check_name(name: &str) -> Result<(), String> {
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    match valid {
+        true => Ok(()),
+        false => Err(format!(
+            "{:?} in [workstation.env] is no variable name, use letters, digits and _ and start with a letter",
+            name
+        )),
+    }
+}
+
+/// The file of `shell` exporting `variables`. `$VAR` and `${VAR}` in values are left for the
+/// shell to expand when it loads the file, so `PATH = "$HOME/bin:$PATH"` extends the `PATH` of
+/// that shell rather than the one `setup` ran in.
+pub fn render(shell: Shell, variables: &BTreeMap<String, String>) -> eyre::Result<String> {
+    let mut rendered = format!("{}\n", HEADER);
+    for (name, value) in variables {
+        check_name(name).map_err(|message| eyre::eyre!(message))?;
+        let pieces = expand::split_vars(value, Field::new(name, "the [workstation.env] table"))?;
+        rendered.push_str(&match shell {
+            Shell::Bash | Shell::Zsh => format!("export {}={}\n", name, sh_value(&pieces)),
+            Shell::Fish => format!("set -gx {} {}\n", name, fish_value(&pieces)),
+        });
+    }
+    Ok(rendered)
+}
+
+/// `pieces` as one double quoted sh word, in which only the variables expand
+fn sh_value(pieces: &[Piece]) -> String {
+    let mut value = String::from("\"");
+    for piece in pieces {
+        match piece {
+            Piece::Text(text) => {
+                for c in text.chars() {
+                    if matches!(c, '"' | '\\' | '$' | '`') {
+                        value.push('\\');
+                    }
+                    value.push(c);
+                }
+            }
+            Piece::Var(name) => value.push_str(&format!("${{{}}}", name)),
+        }
+    }
+    value.push('"');
+    value
+}
+
+/// `pieces` as one fish word: text single quoted and each variable double quoted next to it, so
+/// that a list such as `PATH` stays one value
+fn fish_value(pieces: &[Piece]) -> String {
+    if pieces.is_empty() {
+        return "''".to_string();
+    }
+    pieces
+        .iter()
+        .map(|piece| match piece {
+            Piece::Text(text) => quote(Shell::Fish, text),
+            Piece::Var(name) => format!("\"${}\"", name),
+        })
+        .collect()
+}
+
+/// `value` single quoted for `shell`, sh has no escapes there while fish has `\'` and `\\`
+fn quote(shell: Shell, value: &str) -> String {
+    match shell {
+        Shell::Bash | Shell::Zsh => format!("'{}'", value.replace('\'', "'\\''")),
+        Shell::Fish => format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'")),
+    }
+}
+
+/// Write the files exporting `variables` to `directory`, or remove them when there are none.
+/// Returns the files that changed.
+pub fn write(variables: &BTreeMap<String, String>, directory: &Path) -> eyre::Result<Vec<PathBuf>> {
+    let mut changed = vec![];
+    for shell in [Shell::Bash, Shell::Fish] {
+        let path = file(shell, directory);
+        let old = match std::fs::read_to_string(&path) {
+            Ok(content) => Some(content),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e).with_context(|| format!("Reading {}", path.display())),
+        };
+        if variables.is_empty() {
+            if old.is_some() {
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("Removing {}", path.display()))?;
+                changed.push(path);
+            }
+            continue;
+        }
+        let new = render(shell, variables)?;
+        if old.as_deref() == Some(new.as_str()) {
+            continue;
+        }
+        std::fs::create_dir_all(directory)
+            .with_context(|| format!("Creating {}", directory.display()))?;
+        std::fs::write(&path, new).with_context(|| format!("Writing {}", path.display()))?;
+        changed.push(path);
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exports_are_quoted_for_each_shell() {
+        let directory = tempfile::tempdir().unwrap();
+        let variables = BTreeMap::from([
+            ("EDITOR".to_string(), "nvim".to_string()),
+            (
+                "FZF_DEFAULT_OPTS".to_string(),
+                "--height 40% --prompt='> '".to_string(),
+            ),
+        ]);
+
+        let changed = write(&variables, directory.path()).unwrap();
+        assert_eq!(changed.len(), 2);
+        assert_eq!(
+            std::fs::read_to_string(file(Shell::Zsh, directory.path())).unwrap(),
+            format!(
+                "{}\nexport EDITOR=\"nvim\"\nexport FZF_DEFAULT_OPTS=\"--height 40% --prompt='> '\"\n",
+                HEADER
+            )
+        );
+        assert!(std::fs::read_to_string(file(Shell::Fish, directory.path()))
+            .unwrap()
+            .ends_with("set -gx EDITOR 'nvim'\nset -gx FZF_DEFAULT_OPTS '--height 40% --prompt=\\'> \\''\n"));
+        assert!(write(&variables, directory.path()).unwrap().is_empty());
+
+        assert!(check_name("GOPATH").is_ok());
+        assert!(check_name("1PASSWORD").is_err());
+        assert_eq!(write(&BTreeMap::new(), directory.path()).unwrap().len(), 2);
+        assert!(!file(Shell::Fish, directory.path()).exists());
+    }
+
+    #[test]
+    fn test_variables_are_expanded_by_the_shell() {
+        let variables = BTreeMap::from([
+            ("PATH".to_string(), "${HOME}/bin:$PATH".to_string()),
+            ("PRICE".to_string(), r#"$$5 "`now`" \"#.to_string()),
+            ("EMPTY".to_string(), String::new()),
+        ]);
+
+        assert_eq!(
+            render(Shell::Bash, &variables).unwrap(),
+            format!(
+                r#"{}
+export EMPTY=""
+export PATH="${{HOME}}/bin:${{PATH}}"
+export PRICE="\$5 \"\`now\`\" \\"
+"#,
+                HEADER
+            )
+        );
+        assert_eq!(
+            render(Shell::Fish, &variables).unwrap(),
+            format!(
+                r#"{}
+set -gx EMPTY ''
+set -gx PATH "$HOME"'/bin:'"$PATH"
+set -gx PRICE '$5 "`now`" \\'
+"#,
+                HEADER
+            )
+        );
+
+        // What a shell loading the file ends up with
+        let directory = tempfile::tempdir().unwrap();
+        write(&variables, directory.path()).unwrap();
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(format!(
+                r#". '{}' && printf '%s|%s' "$PATH" "$PRICE""#,
+                file(Shell::Bash, directory.path()).display()
+            ))
+            .env("HOME", "/home/u")
+            .env("PATH", "/usr/bin:/bin")
+            .output()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            r#"/home/u/bin:/usr/bin:/bin|$5 "`now`" \"#
+        );
+    }
+}
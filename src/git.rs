@@ -0,0 +1,161 @@
+use std::{ffi::OsStr, path::Path, time::Duration};
+
+use eyre::Context;
+
+use crate::custom;
+
+/// Clone `url` into `dest`, or bring the clone already there up to date, with `rev` checked out
+/// when set. Returns whether the checked out commit changed.
+///
+/// An existing clone is fetched and its branch fast-forwarded to its upstream, a tag or commit
+/// checked out stays where it is. Local commits or changes that stand in the way of a
+/// fast-forward are an error, they are never overwritten.
+pub fn sync(url: &str, dest: &Path, rev: Option<&str>, timeout: Duration) -> eyre::Result<bool> {
+    let dest_arg = dest.to_string_lossy();
+    if !dest.exists() {
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Creating {}", parent.display()))?;
+        }
+        git(None, &["clone", "--quiet", url, &dest_arg], timeout)?;
+        if let Some(rev) = rev {
+            git(Some(dest), &["checkout", "--quiet", rev], timeout)?;
+        }
+        return Ok(true);
+    }
+    if !dest.join(".git").exists() {
+        eyre::bail!(
+            "{} exists and is not a git repository, move it away to clone {} there",
+            dest.display(),
+            url
+        );
+    }
+
+    let before = head(dest, timeout)?;
+    git(
+        Some(dest),
+        &["fetch", "--quiet", "--tags", "origin"],
+        timeout,
+    )?;
+    if let Some(rev) = rev {
+        git(Some(dest), &["checkout", "--quiet", rev], timeout)?;
+    }
+    let on_branch = git(Some(dest), &["symbolic-ref", "--quiet", "HEAD"], timeout).is_ok();
+    let tracking = git(
+        Some(dest),
+        &["rev-parse", "--quiet", "--verify", "@{upstream}"],
+        timeout,
+    )
+    .is_ok();
+    if on_branch && tracking {
+        git(
+            Some(dest),
+            &["merge", "--quiet", "--ff-only", "@{upstream}"],
+            timeout,
+        )?;
+    }
+    Ok(head(dest, timeout)? != before)
+}
+
+/// The commit checked out in `repository`
+fn head(repository: &Path, timeout: Duration) -> eyre::Result<String> {
+    let output = git(Some(repository), &["rev-parse", "HEAD"], timeout)?;
+    Ok(String::from_utf8_lossy(&output).trim().to_string())
+}
+
+/// Run git with `args`, in `repository` when given. Git never asks for credentials, a
+/// repository that needs them must be reachable with an SSH key or a credential helper.
+fn git(repository: Option<&Path>, args: &[&str], timeout: Duration) -> eyre::Result<Vec<u8>> {
+    let mut command = vec!["git".to_string()];
+    if let Some(repository) = repository {
+        command.push("-C".to_string());
+        command.push(repository.to_string_lossy().into_owned());
+    }
+    command.extend(args.iter().map(|arg| arg.to_string()));
+    let shown = format!("git {}", args.join(" "));
+    custom::capture(
+        &command,
+        &shown,
+        &[("GIT_TERMINAL_PROMPT", OsStr::new("0"))],
+        timeout,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(60);
+
+    fn commit(repository: &Path, file: &str, content: &str) {
+        std::fs::write(repository.join(file), content).unwrap();
+        for args in [
+            vec!["add", file],
+            vec![
+                "-c",
+                "user.name=test",
+                "-c",
+                "user.email=test@example.com",
+                "commit",
+                "--quiet",
+                "-m",
+                content,
+            ],
+        ] {
+            git(Some(repository), &args, TIMEOUT).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_clones_are_fast_forwarded() {
+        let directory = tempfile::tempdir().unwrap();
+        let origin = directory.path().join("origin");
+        std::fs::create_dir(&origin).unwrap();
+        git(Some(&origin), &["init", "--quiet", "-b", "main"], TIMEOUT).unwrap();
+        commit(&origin, "init.lua", "first");
+        let url = origin.to_string_lossy();
+        let dest = directory.path().join("config/nvim");
+
+        assert!(sync(&url, &dest, None, TIMEOUT).unwrap());
+        assert!(!sync(&url, &dest, None, TIMEOUT).unwrap());
+        commit(&origin, "init.lua", "second");
+        assert!(sync(&url, &dest, None, TIMEOUT).unwrap());
+        assert_eq!(
+            std::fs::read_to_string(dest.join("init.lua")).unwrap(),
+            "second"
+        );
+
+        // Local changes are kept, the update fails instead
+        commit(&origin, "init.lua", "third");
+        std::fs::write(dest.join("init.lua"), "mine").unwrap();
+        assert!(sync(&url, &dest, None, TIMEOUT).is_err());
+        assert_eq!(
+            std::fs::read_to_string(dest.join("init.lua")).unwrap(),
+            "mine"
+        );
+    }
+
+    #[test]
+    fn test_rev_is_checked_out() {
+        let directory = tempfile::tempdir().unwrap();
+        let origin = directory.path().join("origin");
+        std::fs::create_dir(&origin).unwrap();
+        git(Some(&origin), &["init", "--quiet", "-b", "main"], TIMEOUT).unwrap();
+        commit(&origin, "plugin.zsh", "v1");
+        git(Some(&origin), &["tag", "v1"], TIMEOUT).unwrap();
+        commit(&origin, "plugin.zsh", "v2");
+        let url = origin.to_string_lossy();
+        let dest = directory.path().join("plugin");
+
+        sync(&url, &dest, Some("v1"), TIMEOUT).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dest.join("plugin.zsh")).unwrap(),
+            "v1"
+        );
+        assert!(!sync(&url, &dest, Some("v1"), TIMEOUT).unwrap());
+
+        std::fs::write(directory.path().join("file"), "").unwrap();
+        let error = sync(&url, &directory.path().join("file"), None, TIMEOUT).unwrap_err();
+        assert!(error.to_string().contains("is not a git repository"));
+    }
+}
@@ -0,0 +1,395 @@
+use eyre::Context;
+use serde::Deserialize;
+
+use std::time::SystemTime;
+
+use crate::{
+    arch::Target,
+    archive, download,
+    error::{CodedError, ErrorCode},
+};
+
+const DEFAULT_API: &str = "https://api.github.com";
+
+/// A published release, with only the fields needed to pick an asset
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Release {
+    pub tag_name: String,
+    pub assets: Vec<Asset>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Asset {
+    pub name: String,
+    pub browser_download_url: String,
+    /// The asset in the API, the only place that serves assets of private repositories
+    #[serde(default)]
+    pub url: String,
+}
+
+impl Asset {
+    /// Where to download the asset: through the API when there is a token to send, since the
+    /// public link does not take one
+    pub fn download_url(&self, token: bool) -> &str {
+        match token && !self.url.is_empty() {
+            true => &self.url,
+            false => &self.browser_download_url,
+        }
+    }
+}
+
+/// The GitHub API, `$WORKSTATION_GITHUB_API` for GitHub Enterprise
+pub fn api() -> String {
+    std::env::var("WORKSTATION_GITHUB_API")
+        .ok()
+        .filter(|api| !api.is_empty())
+        .unwrap_or_else(|| DEFAULT_API.to_string())
+}
+
+/// The release of `repo` (`owner/name`) tagged `version`, or its latest release.
+///
+/// `token` is sent when given, for private repositories and the higher rate limit.
+pub fn fetch_release(
+    api: &str,
+    repo: &str,
+    version: Option<&str>,
+    token: Option<&str>,
+) -> eyre::Result<Release> {
+    let url = match version {
+        Some(tag) => format!("{}/repos/{}/releases/tags/{}", api, repo, tag),
+        None => format!("{}/repos/{}/releases/latest", api, repo),
+    };
+    let mut request = crate::http::client()
+        .get(&url)
+        .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+        .header(
+            reqwest::header::USER_AGENT,
+            concat!("workstation/", env!("CARGO_PKG_VERSION")),
+        );
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+
+    let release = match version {
+        Some(tag) => format!("release {} of {}", tag, repo),
+        None => format!("latest release of {}", repo),
+    };
+    let response = request
+        .send()
+        .with_context(|| format!("Looking up the {}", release))?;
+    let status = response.status();
+    if status == reqwest::StatusCode::FORBIDDEN || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+    {
+        let limited = match download::rate_limit_wait(
+            response.status(),
+            response.headers(),
+            SystemTime::now(),
+        ) {
+            Some(wait) => format!(", rate limited{}", download::until(wait)),
+            None => String::new(),
+        };
+        return Err(ErrorCode::RateLimited.error(format!(
+            "GitHub refused looking up the {} ({}){}, set GITHUB_TOKEN or [workstation.download] github_token to raise the limit",
+            release, status, limited
+        )));
+    }
+    if !status.is_success() {
+        return Err(ErrorCode::DownloadFailed
+            .error(format!("Looking up the {} failed: {}", release, status)));
+    }
+    let body = response
+        .bytes()
+        .with_context(|| format!("Reading the {}", release))?;
+    serde_json::from_slice(&body).with_context(|| format!("Reading the {}", release))
+}
+
+/// The asset of `repo` to install on the target of this run, with the tag of its release
+pub fn resolve(
+    repo: &str,
+    version: Option<&str>,
+    asset_pattern: Option<&str>,
+    token: Option<&str>,
+) -> eyre::Result<(Asset, String)> {
+    let release = fetch_release(&api(), repo, version, token)?;
+    let asset = select_asset(&release, asset_pattern, Target::current())?.clone();
+    Ok((asset, release.tag_name))
+}
+
+/// Why GitHub could not be asked about a release, when `error` says it rate limited the lookup,
+/// could not be reached or was not asked in an offline run, rather than anything about the
+/// release itself
+pub fn unavailable(error: &eyre::Report) -> Option<String> {
+    let limited = error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<CodedError>())
+        .find(|coded| matches!(coded.code, ErrorCode::RateLimited | ErrorCode::Offline));
+    if let Some(limited) = limited {
+        return Some(limited.message.clone());
+    }
+    error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<reqwest::Error>())
+        .find(|e| e.is_connect() || e.is_timeout())
+        .map(|e| format!("GitHub is unreachable: {}", e))
+}
+
+/// The asset to install: the only one whose name matches the glob `pattern`, or without a
+/// pattern, the only installable one built for `target`
+pub fn select_asset<'a>(
+    release: &'a Release,
+    pattern: Option<&str>,
+    target: &Target,
+) -> eyre::Result<&'a Asset> {
+    let candidates: Vec<&Asset> = match pattern {
+        Some(pattern) => {
+            let glob = glob::Pattern::new(pattern)
+                .with_context(|| format!("Invalid asset_pattern {:?}", pattern))?;
+            release
+                .assets
+                .iter()
+                .filter(|asset| glob.matches(&asset.name))
+                .collect()
+        }
+        None => {
+            let built: Vec<&Asset> = release
+                .assets
+                .iter()
+                .filter(|asset| is_installable(&asset.name) && builds_for(&asset.name, target))
+                .collect();
+            // Prefer the build for our C library, then the static musl one
+            let libc = target.libc.name();
+            let preferred = [libc, "musl"]
+                .into_iter()
+                .filter(|libc| !libc.is_empty())
+                .map(|libc| {
+                    built
+                        .iter()
+                        .copied()
+                        .filter(|asset| asset.name.to_lowercase().contains(libc))
+                        .collect::<Vec<_>>()
+                })
+                .find(|preferred| !preferred.is_empty());
+            preferred.unwrap_or(built)
+        }
+    };
+    if let [asset] = candidates.as_slice() {
+        return Ok(asset);
+    }
+
+    let wanted = match pattern {
+        Some(pattern) => format!("matching asset_pattern = {:?}", pattern),
+        None => format!("built for {}_{}", target.os, target.arch),
+    };
+    let mut message = match candidates.len() {
+        0 => format!("No asset of release {} is {}", release.tag_name, wanted),
+        count => format!(
+            "{} assets of release {} are {}, set asset_pattern to pick one",
+            count, release.tag_name, wanted
+        ),
+    };
+    message.push_str("\nAssets:");
+    for asset in &release.assets {
+        message.push_str(&format!("\n  {}", asset.name));
+    }
+    Err(ErrorCode::NoBinaryForPlatform.error(message))
+}
+
+/// Archives and plain executables, not checksums, signatures or system packages
+fn is_installable(name: &str) -> bool {
+    const SKIPPED: &[&str] = &[
+        ".sha256",
+        ".sha256sum",
+        ".sha512",
+        ".md5",
+        ".asc",
+        ".sig",
+        ".pem",
+        ".sbom",
+        ".json",
+        ".txt",
+        ".deb",
+        ".rpm",
+        ".apk",
+        ".msi",
+        ".pkg",
+        ".dmg",
+        ".exe",
+    ];
+    let name = name.to_lowercase();
+    archive::is_supported(&name) || !SKIPPED.iter().any(|suffix| name.ends_with(suffix))
+}
+
+/// Whether the asset `name` follows one of the usual naming schemes for `target`
+fn builds_for(name: &str, target: &Target) -> bool {
+    let name = name.to_lowercase();
+    let has = |aliases: &[&str]| aliases.iter().any(|alias| name.contains(alias));
+    let os_aliases: &[&str] = match target.os.as_str() {
+        "macos" => &["darwin", "macos", "apple", "osx"],
+        os => &[os],
+    };
+    let x86_64: &[&str] = &["x86_64", "x86-64", "amd64", "x64"];
+    let aarch64: &[&str] = &["aarch64", "arm64"];
+    let arch = match target.arch.as_str() {
+        "x86_64" => has(x86_64),
+        "aarch64" => has(aarch64),
+        "x86" => has(&["i386", "i686", "x86"]) && !has(x86_64),
+        "arm" => has(&["armv7", "armhf", "arm"]) && !has(aarch64),
+        arch => has(&[arch]),
+    };
+    has(os_aliases) && arch
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{Route, TestServer};
+
+    fn release(names: &[&str]) -> Release {
+        Release {
+            tag_name: "v1.0.0".to_string(),
+            assets: names
+                .iter()
+                .map(|name| Asset {
+                    name: name.to_string(),
+                    browser_download_url: format!("https://example.com/{}", name),
+                    url: String::new(),
+                })
+                .collect(),
+        }
+    }
+
+    fn selected(names: &[&str], pattern: Option<&str>, target: &str) -> eyre::Result<String> {
+        let release = release(names);
+        let target = Target::parse(target).unwrap();
+        select_asset(&release, pattern, &target).map(|asset| asset.name.clone())
+    }
+
+    #[test]
+    fn test_asset_for_the_platform_is_picked() {
+        let ripgrep = [
+            "ripgrep-14.1.0-aarch64-unknown-linux-gnu.tar.gz",
+            "ripgrep-14.1.0-aarch64-unknown-linux-gnu.tar.gz.sha256",
+            "ripgrep-14.1.0-x86_64-apple-darwin.tar.gz",
+            "ripgrep-14.1.0-x86_64-unknown-linux-musl.tar.gz",
+            "ripgrep-14.1.0-x86_64-unknown-linux-musl.tar.gz.sha256",
+            "ripgrep_14.1.0-1_amd64.deb",
+            "ripgrep-14.1.0-i686-unknown-linux-gnu.tar.gz",
+        ];
+
+        assert_eq!(
+            selected(&ripgrep, None, "linux_x86_64").unwrap(),
+            "ripgrep-14.1.0-x86_64-unknown-linux-musl.tar.gz"
+        );
+        assert_eq!(
+            selected(&ripgrep, None, "linux_aarch64").unwrap(),
+            "ripgrep-14.1.0-aarch64-unknown-linux-gnu.tar.gz"
+        );
+        assert_eq!(
+            selected(&ripgrep, None, "macos_x86_64").unwrap(),
+            "ripgrep-14.1.0-x86_64-apple-darwin.tar.gz"
+        );
+        assert_eq!(
+            selected(&ripgrep, None, "linux_x86").unwrap(),
+            "ripgrep-14.1.0-i686-unknown-linux-gnu.tar.gz"
+        );
+        assert_eq!(
+            selected(&ripgrep, Some("*_amd64.deb"), "linux_x86_64").unwrap(),
+            "ripgrep_14.1.0-1_amd64.deb"
+        );
+    }
+
+    #[test]
+    fn test_ambiguous_or_missing_assets_are_refused() {
+        let error = selected(
+            &["tool-linux-amd64", "tool-linux-amd64-v3"],
+            None,
+            "linux_x86_64",
+        )
+        .unwrap_err();
+        assert_eq!(
+            crate::error::code_of(&error),
+            Some(ErrorCode::NoBinaryForPlatform)
+        );
+        assert!(error.to_string().contains("2 assets of release v1.0.0"));
+        assert!(error.to_string().contains("\n  tool-linux-amd64-v3"));
+
+        let error = selected(&["tool-windows-amd64.zip"], None, "linux_x86_64").unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("No asset of release v1.0.0 is built for linux_x86_64"));
+    }
+
+    #[test]
+    fn test_release_is_looked_up_by_tag() {
+        let server = TestServer::start();
+        server.route(
+            "/repos/BurntSushi/ripgrep/releases/tags/14.1.0",
+            Route::ok(
+                r#"{"tag_name": "14.1.0", "id": 1, "assets": [
+                    {"name": "rg.tar.gz", "browser_download_url": "https://example.com/rg.tar.gz", "size": 3,
+                     "url": "https://api.github.com/repos/BurntSushi/ripgrep/releases/assets/7"}
+                ]}"#,
+            ),
+        );
+
+        let release = fetch_release(
+            &server.url(""),
+            "BurntSushi/ripgrep",
+            Some("14.1.0"),
+            Some("secret"),
+        )
+        .unwrap();
+
+        assert_eq!(release.tag_name, "14.1.0");
+        assert_eq!(
+            release.assets[0].download_url(false),
+            "https://example.com/rg.tar.gz"
+        );
+        assert_eq!(
+            release.assets[0].download_url(true),
+            "https://api.github.com/repos/BurntSushi/ripgrep/releases/assets/7"
+        );
+        let request = &server.requests()[0];
+        assert!(request.headers["user-agent"].starts_with("workstation/"));
+        assert_eq!(request.headers["authorization"], "Bearer secret");
+        let error = fetch_release(&server.url(""), "BurntSushi/ripgrep", None, None).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("latest release of BurntSushi/ripgrep"));
+        assert_eq!(unavailable(&error), None);
+    }
+
+    #[test]
+    fn test_rate_limits_and_unreachable_servers_make_github_unavailable() {
+        let server = TestServer::start();
+        server.route(
+            "/repos/neovim/neovim/releases/latest",
+            Route {
+                headers: vec![
+                    ("x-ratelimit-remaining".to_string(), "0".to_string()),
+                    ("x-ratelimit-reset".to_string(), "0".to_string()),
+                ],
+                ..Route::status(403)
+            },
+        );
+
+        let error = fetch_release(&server.url(""), "neovim/neovim", None, None).unwrap_err();
+        let reason = unavailable(&error).unwrap();
+        assert!(reason.starts_with(
+            "GitHub refused looking up the latest release of neovim/neovim (403 Forbidden), \
+             rate limited until "
+        ));
+
+        // Nothing listens on a port that was free a moment ago
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let url = format!("http://127.0.0.1:{}", port);
+        let error = fetch_release(&url, "neovim/neovim", None, None).unwrap_err();
+        assert!(unavailable(&error)
+            .unwrap()
+            .starts_with("GitHub is unreachable"));
+    }
+}
@@ -0,0 +1,331 @@
+use std::{
+    fmt,
+    process::{Command, Stdio},
+    sync::Mutex,
+};
+
+use eyre::Context;
+
+use crate::{config::PackageConfig, conflicts, custom, system};
+
+/// The remote flatpak apps come from unless they name another
+const FLATHUB: &str = "flathub";
+const FLATHUB_URL: &str = "https://dl.flathub.org/repo/flathub.flatpakrepo";
+
+/// A package manager besides the one of the system that installs tools and applications, each
+/// in a place of its own with its commands linked onto PATH
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Installer {
+    /// Python applications, each in its own virtual environment
+    Pipx,
+    /// Global npm packages, in the npm prefix
+    Npm,
+    /// Desktop applications, installed for the user
+    Flatpak,
+    /// Applications in snaps, installed for every user
+    Snap,
+}
+
+impl Installer {
+    /// The installer of `package` with the package it installs and the pinned version, for
+    /// packages of these installers
+    pub fn of(package: &PackageConfig) -> Option<(Installer, &str, Option<&str>)> {
+        match package {
+            PackageConfig::Pipx { pipx, version, .. } => {
+                Some((Installer::Pipx, pipx, version.as_deref()))
+            }
+            PackageConfig::Npm { npm, version, .. } => {
+                Some((Installer::Npm, npm, version.as_deref()))
+            }
+            PackageConfig::Flatpak { flatpak, .. } => Some((Installer::Flatpak, flatpak, None)),
+            PackageConfig::Snap { snap, .. } => Some((Installer::Snap, snap, None)),
+            _ => None,
+        }
+    }
+
+    fn program(&self) -> &'static str {
+        match self {
+            Installer::Pipx => "pipx",
+            Installer::Npm => "npm",
+            Installer::Flatpak => "flatpak",
+            Installer::Snap => "snap",
+        }
+    }
+
+    fn is_available(&self) -> bool {
+        conflicts::path_directories()
+            .iter()
+            .any(|directory| directory.join(self.program()).is_file())
+    }
+
+    /// The command removing `package`, for humans
+    pub fn uninstall_command(&self, package: &str) -> String {
+        match self {
+            Installer::Pipx => format!("pipx uninstall {}", package),
+            Installer::Npm => format!("npm uninstall --global {}", package),
+            Installer::Flatpak => format!("flatpak uninstall --user {}", package),
+            Installer::Snap => format!("snap remove {}", package),
+        }
+    }
+
+    /// The version of `package` installed, if it is. Nothing is installed without the installer.
+    pub fn installed_version(&self, package: &str) -> eyre::Result<Option<String>> {
+        if !self.is_available() {
+            return Ok(None);
+        }
+        let args: &[&str] = match self {
+            Installer::Pipx => &["pipx", "list", "--json"],
+            Installer::Npm => &["npm", "ls", "--global", "--depth=0", "--json"],
+            Installer::Flatpak => &["flatpak", "info", package],
+            Installer::Snap => &["snap", "list", package],
+        };
+        // npm exits with an error for problems of unrelated packages, the listing is still there
+        let output = Command::new(args[0])
+            .args(&args[1..])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .with_context(|| format!("Running {}", args[0]))?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let version = match self {
+            Installer::Pipx | Installer::Npm => {
+                let listing: serde_json::Value = serde_json::from_str(&stdout)
+                    .with_context(|| format!("Reading the packages `{}` lists", args.join(" ")))?;
+                json_version(*self, &listing, package)
+            }
+            // Both only succeed for what is installed, apps without a version have an empty one
+            _ if !output.status.success() => None,
+            Installer::Flatpak => Some(
+                stdout
+                    .lines()
+                    .find_map(|line| line.trim().strip_prefix("Version:"))
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string(),
+            ),
+            Installer::Snap => Some(
+                stdout
+                    .lines()
+                    .nth(1)
+                    .and_then(|line| line.split_whitespace().nth(1))
+                    .unwrap_or_default()
+                    .to_string(),
+            ),
+        };
+        Ok(version)
+    }
+}
+
+impl fmt::Display for Installer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.program())
+    }
+}
+
+/// The version of `package` in the JSON listing of pipx or npm
+fn json_version(
+    installer: Installer,
+    listing: &serde_json::Value,
+    package: &str,
+) -> Option<String> {
+    let version = match installer {
+        Installer::Pipx => listing
+            .pointer("/venvs")
+            .and_then(serde_json::Value::as_object)
+            .and_then(|venvs| {
+                venvs.values().find_map(|venv| {
+                    let main = venv.pointer("/metadata/main_package")?;
+                    (main.get("package")?.as_str()? == package)
+                        .then(|| main.get("package_version")?.as_str())?
+                })
+            }),
+        _ => listing
+            .get("dependencies")
+            .and_then(|dependencies| dependencies.get(package))
+            .and_then(|dependency| dependency.get("version")?.as_str()),
+    };
+    version.map(str::to_string)
+}
+
+/// The command lines installing `package`, at the version it pins, replacing the version
+/// installed before. Flathub is added first when a flatpak app needs it.
+fn install_commands(package: &PackageConfig) -> Vec<Vec<String>> {
+    let strings = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+    match package {
+        PackageConfig::Pipx { pipx, version, .. } => {
+            let mut args = strings(&["pipx", "install", "--force"]);
+            args.push(match version {
+                Some(version) => format!("{}=={}", pipx, version),
+                None => pipx.clone(),
+            });
+            vec![args]
+        }
+        PackageConfig::Npm { npm, version, .. } => {
+            let mut args = strings(&["npm", "install", "--global", "--no-fund", "--no-audit"]);
+            args.push(match version {
+                Some(version) => format!("{}@{}", npm, version),
+                None => npm.clone(),
+            });
+            vec![args]
+        }
+        PackageConfig::Flatpak {
+            flatpak,
+            remote,
+            remote_url,
+            ..
+        } => {
+            let remote = remote.as_deref().unwrap_or(FLATHUB);
+            let url = remote_url
+                .as_deref()
+                .or((remote == FLATHUB).then_some(FLATHUB_URL));
+            let mut commands = vec![];
+            if let Some(url) = url {
+                commands.push(strings(&[
+                    "flatpak",
+                    "remote-add",
+                    "--user",
+                    "--if-not-exists",
+                    remote,
+                    url,
+                ]));
+            }
+            commands.push(strings(&[
+                "flatpak",
+                "install",
+                "--user",
+                "--noninteractive",
+                "-y",
+                remote,
+                flatpak,
+            ]));
+            commands
+        }
+        PackageConfig::Snap {
+            snap,
+            classic,
+            channel,
+            ..
+        } => {
+            let mut args = strings(&["snap", "install", snap]);
+            if *classic {
+                args.push("--classic".to_string());
+            }
+            if let Some(channel) = channel {
+                args.push(format!("--channel={}", channel));
+            }
+            if !system::is_root() {
+                args.insert(0, "sudo".to_string());
+            }
+            vec![args]
+        }
+        _ => vec![],
+    }
+}
+
+/// Install `package`. One install runs at a time per installer, none expects another to
+/// change its files at the same time.
+pub fn install(package: &PackageConfig) -> eyre::Result<()> {
+    static RUNNING: [Mutex<()>; 4] = [
+        Mutex::new(()),
+        Mutex::new(()),
+        Mutex::new(()),
+        Mutex::new(()),
+    ];
+    let Some((installer, _, _)) = Installer::of(package) else {
+        return Ok(());
+    };
+    let _running = RUNNING[installer as usize]
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if !installer.is_available() {
+        eyre::bail!("{} is not on PATH, install it first", installer.program());
+    }
+    for args in install_commands(package) {
+        custom::capture(&args, &args.join(" "), &[], custom::DEFAULT_TIMEOUT)?;
+    }
+    Ok(())
+}
+
+/// Whether `package` is installed at the version it pins, or at any version when it pins none
+pub fn is_current(package: &PackageConfig) -> eyre::Result<bool> {
+    let Some((installer, name, version)) = Installer::of(package) else {
+        return Ok(false);
+    };
+    let installed = installer.installed_version(name)?;
+    Ok(match version {
+        Some(version) => installed.as_deref() == Some(version),
+        None => installed.is_some(),
+    })
+}
+
+/// Whether `package` is installed at any version
+pub fn is_installed(package: &PackageConfig) -> eyre::Result<bool> {
+    let Some((installer, name, _)) = Installer::of(package) else {
+        return Ok(false);
+    };
+    Ok(installer.installed_version(name)?.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(toml: &str) -> PackageConfig {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn test_pinned_versions_are_installed_over_others() {
+        assert_eq!(
+            install_commands(&package(
+                "name = \"ruff-lsp\"\npipx = \"ruff-lsp\"\nversion = \"0.0.53\"\n"
+            )),
+            [["pipx", "install", "--force", "ruff-lsp==0.0.53"]]
+        );
+        assert_eq!(
+            install_commands(&package("name = \"prettier\"\nnpm = \"prettier\"\n")),
+            [[
+                "npm",
+                "install",
+                "--global",
+                "--no-fund",
+                "--no-audit",
+                "prettier"
+            ]]
+        );
+
+        let pyright = package("name = \"pyright\"\nnpm = \"pyright\"\nversion = \"1.1.380\"\n");
+        assert_eq!(
+            Installer::of(&pyright),
+            Some((Installer::Npm, "pyright", Some("1.1.380")))
+        );
+    }
+
+    #[test]
+    fn test_flathub_is_added_before_installing() {
+        let firefox = package("name = \"firefox\"\nflatpak = \"org.mozilla.firefox\"\n");
+        let commands = install_commands(&firefox);
+        assert_eq!(
+            commands[0],
+            [
+                "flatpak",
+                "remote-add",
+                "--user",
+                "--if-not-exists",
+                "flathub",
+                FLATHUB_URL
+            ]
+        );
+        assert_eq!(commands[1][5..], ["flathub", "org.mozilla.firefox"]);
+
+        let own = package("name = \"app\"\nflatpak = \"org.example.App\"\nremote = \"mine\"\n");
+        assert_eq!(install_commands(&own).len(), 1);
+
+        let code = package("name = \"code\"\nsnap = \"code\"\nclassic = true\n");
+        let mut args = install_commands(&code).remove(0);
+        if args[0] == "sudo" {
+            args.remove(0);
+        }
+        assert_eq!(args, ["snap", "install", "code", "--classic"]);
+    }
+}
@@ -0,0 +1,119 @@
+use std::{path::PathBuf, time::Duration};
+
+use eyre::Context;
+
+use crate::{conflicts, custom};
+
+/// Builds from source take a while, they are killed after this long
+pub const TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// Where the official installer puts go, which is not on PATH before a new shell
+const INSTALL_DIR: &str = "/usr/local/go/bin";
+
+/// A binary installed by `go install`
+pub struct Built {
+    pub data: Vec<u8>,
+    /// The version of the module go installed, such as `v0.16.2`
+    pub version: Option<String>,
+}
+
+/// Install `module` at `version` with `go install`, into a temporary `GOBIN`, and return the
+/// binary it built. `on_line` sees the progress go writes, such as the modules it downloads.
+pub fn install(
+    module: &str,
+    version: &str,
+    on_line: impl Fn(&str) + Send + 'static,
+) -> eyre::Result<Built> {
+    let go = program().ok_or_else(|| {
+        eyre::eyre!(
+            "go is not on PATH or in {}, install Go first to build {}",
+            INSTALL_DIR,
+            module
+        )
+    })?;
+    let bin = tempfile::tempdir().with_context(|| "Creating a directory to install into")?;
+    let target = format!("{}@{}", module, version);
+    let command = [
+        go.to_string_lossy().into_owned(),
+        "install".to_string(),
+        target.clone(),
+    ];
+    let shown = format!("go install {}", target);
+    custom::capture_lines(
+        &command,
+        &shown,
+        &[("GOBIN", bin.path().as_os_str())],
+        TIMEOUT,
+        on_line,
+    )?;
+
+    let path = bin.path().join(binary_name(module));
+    let data = std::fs::read(&path)
+        .with_context(|| format!("{} did not install {}", shown, path.display()))?;
+    let version = custom::capture(
+        &[
+            go.to_string_lossy().into_owned(),
+            "version".to_string(),
+            "-m".to_string(),
+            path.to_string_lossy().into_owned(),
+        ],
+        "go version -m",
+        &[],
+        TIMEOUT,
+    )
+    .ok()
+    .and_then(|output| built_version(&String::from_utf8_lossy(&output)));
+    Ok(Built { data, version })
+}
+
+/// The file `go install` writes for `module`: its last path element, or the one before a major
+/// version suffix such as `/v2`
+fn binary_name(module: &str) -> &str {
+    let mut elements = module.trim_end_matches('/').rsplit('/');
+    let last = elements.next().unwrap_or(module);
+    let is_major = last
+        .strip_prefix('v')
+        .is_some_and(|major| !major.is_empty() && major.chars().all(|c| c.is_ascii_digit()));
+    match is_major {
+        true => elements.next().unwrap_or(last),
+        false => last,
+    }
+}
+
+/// The version of the main module in the build info `go version -m` prints, from its line
+/// `mod <module> <version> <sum>`
+fn built_version(info: &str) -> Option<String> {
+    info.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        (fields.next()? == "mod").then_some(())?;
+        fields.nth(1).map(str::to_string)
+    })
+}
+
+fn program() -> Option<PathBuf> {
+    conflicts::path_directories()
+        .into_iter()
+        .chain([PathBuf::from(INSTALL_DIR)])
+        .map(|directory| directory.join("go"))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_binaries_are_named_like_go_names_them() {
+        assert_eq!(binary_name("golang.org/x/tools/gopls"), "gopls");
+        assert_eq!(
+            binary_name("github.com/golangci/golangci-lint/v2/cmd/golangci-lint"),
+            "golangci-lint"
+        );
+        assert_eq!(binary_name("github.com/go-delve/delve/cmd/dlv/v2"), "dlv");
+
+        let info = "/tmp/gopls: go1.22.4\n\tpath\tgolang.org/x/tools/gopls\n\t\
+                    mod\tgolang.org/x/tools/gopls\tv0.16.2\th1:K1z03MlikHfaMTtG01cUeL5FAOTJnITuNe0TWOcg8tM=\n\t\
+                    dep\tgolang.org/x/mod\tv0.20.0\th1:abc=\n";
+        assert_eq!(built_version(info).as_deref(), Some("v0.16.2"));
+    }
+}
@@ -0,0 +1,123 @@
+use std::{sync::OnceLock, time::Duration};
+
+use eyre::Context;
+use reqwest::{blocking::Client, NoProxy, Proxy};
+use tokio::runtime::Runtime;
+
+use crate::config::DownloadConfig;
+
+/// A server that does not accept the connection in this time is taken to be down
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The clients of this run, built for the same `[workstation.download] proxy`
+struct Clients {
+    proxy: Option<String>,
+    blocking: Client,
+    asynchronous: reqwest::Client,
+}
+
+static CLIENTS: OnceLock<Clients> = OnceLock::new();
+
+/// The runtime asset downloads and install tasks run on, see [`runtime`]
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+/// The client every blocking request of this run goes through, built once so connections are
+/// reused. Asset downloads use [`async_client`] instead.
+///
+/// Until [`init`] chose a proxy, `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY` apply as usual.
+pub fn client() -> &'static Client {
+    &clients().blocking
+}
+
+/// The async client asset downloads go through, with the proxy of [`client`]. Its connections
+/// live on [`runtime`], the only runtime it may be used on.
+pub fn async_client() -> &'static reqwest::Client {
+    &clients().asynchronous
+}
+
+/// The runtime of this run, shared so the connections of [`async_client`] outlive each
+/// `setup`
+pub fn runtime() -> &'static Runtime {
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .thread_name("workstation-io")
+            .enable_all()
+            .build()
+            .expect("the runtime starts")
+    })
+}
+
+fn clients() -> &'static Clients {
+    CLIENTS.get_or_init(|| Clients::build(None).expect("clients build"))
+}
+
+impl Clients {
+    fn build(proxy: Option<&str>) -> eyre::Result<Self> {
+        Ok(Clients {
+            proxy: proxy.map(str::to_string),
+            blocking: build(proxy)?,
+            asynchronous: build_async(proxy)?,
+        })
+    }
+}
+
+/// Build the clients of this run for the `[workstation.download] proxy` of `config`, before any
+/// request.
+///
+/// Calling it again with the same proxy returns the same client, a different one needs a
+/// restart since connections through the old proxy are already open.
+pub fn init(config: &DownloadConfig) -> eyre::Result<&'static Client> {
+    if let Some(clients) = CLIENTS.get() {
+        if clients.proxy == config.proxy {
+            return Ok(&clients.blocking);
+        }
+        eyre::bail!("The download proxy changed, restart workstation to use it");
+    }
+    let clients = Clients::build(config.proxy.as_deref())?;
+    Ok(&CLIENTS.get_or_init(|| clients).blocking)
+}
+
+/// A client sending every request through `proxy`, except to the hosts in `NO_PROXY`. Without
+/// a proxy the environment decides.
+pub fn build(proxy: Option<&str>) -> eyre::Result<Client> {
+    let mut builder = Client::builder();
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(self::proxy(proxy)?);
+    }
+    builder.build().context("Building the HTTP client")
+}
+
+/// [`build`] for the async client. Only connecting has a timeout, a large asset may take long.
+fn build_async(proxy: Option<&str>) -> eyre::Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder().connect_timeout(CONNECT_TIMEOUT);
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(self::proxy(proxy)?);
+    }
+    builder.build().context("Building the HTTP client")
+}
+
+fn proxy(proxy: &str) -> eyre::Result<Proxy> {
+    Ok(Proxy::all(proxy)
+        .with_context(|| format!("Invalid download proxy {:?}", proxy))?
+        .no_proxy(NoProxy::from_env()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_server::{Route, TestServer};
+
+    use super::*;
+
+    #[test]
+    fn test_requests_go_through_the_proxy() {
+        // A proxy is asked for the whole URL, of a host that does not even resolve
+        let proxy = TestServer::start();
+        proxy.route("http://example.invalid/asset", Route::ok("proxied"));
+        let client = build(Some(&proxy.url(""))).unwrap();
+
+        let response = client.get("http://example.invalid/asset").send().unwrap();
+
+        assert_eq!(response.bytes().unwrap().as_ref(), b"proxied");
+        assert!(build(Some("not a proxy")).is_err());
+    }
+}
@@ -0,0 +1,80 @@
+use std::path::Path;
+
+use eyre::Context;
+
+const MATCH_OPTIONS: glob::MatchOptions = glob::MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Files in the install location that workstation must never list or touch,
+/// from `[workstation.install] ignore`.
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreSet {
+    patterns: Vec<glob::Pattern>,
+}
+
+impl IgnoreSet {
+    pub fn new(patterns: &[String]) -> eyre::Result<Self> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| {
+                glob::Pattern::new(pattern)
+                    .with_context(|| format!("Invalid pattern {:?} in install.ignore", pattern))
+            })
+            .collect::<eyre::Result<_>>()?;
+
+        Ok(IgnoreSet { patterns })
+    }
+
+    /// The pattern ignoring `relative`, a path relative to the install location
+    pub fn matching_pattern(&self, relative: &Path) -> Option<&str> {
+        self.patterns
+            .iter()
+            .find(|pattern| pattern.matches_path_with(relative, MATCH_OPTIONS))
+            .map(|pattern| pattern.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(patterns: &[&str]) -> IgnoreSet {
+        let patterns: Vec<_> = patterns.iter().map(|p| p.to_string()).collect();
+        IgnoreSet::new(&patterns).unwrap()
+    }
+
+    fn is_ignored(ignore: &IgnoreSet, relative: &str) -> bool {
+        ignore.matching_pattern(Path::new(relative)).is_some()
+    }
+
+    #[test]
+    fn test_matches_file_names() {
+        let ignore = set(&["pyenv-*", "my-script.sh"]);
+
+        assert!(is_ignored(&ignore, "pyenv-shim"));
+        assert!(is_ignored(&ignore, "my-script.sh"));
+        assert!(!is_ignored(&ignore, "rg"));
+        assert_eq!(
+            ignore.matching_pattern(Path::new("pyenv-python")),
+            Some("pyenv-*")
+        );
+    }
+
+    #[test]
+    fn test_wildcards_do_not_cross_directories() {
+        let ignore = set(&["lsp/*"]);
+
+        assert!(is_ignored(&ignore, "lsp/gopls"));
+        assert!(!is_ignored(&ignore, "lsp/nested/gopls"));
+    }
+
+    #[test]
+    fn test_invalid_pattern_is_rejected() {
+        let error = IgnoreSet::new(&["[unclosed".to_string()]).unwrap_err();
+
+        assert!(format!("{:?}", error).contains("[unclosed"));
+    }
+}
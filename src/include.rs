@@ -0,0 +1,292 @@
+use std::path::PathBuf;
+
+use eyre::Context;
+use reqwest::Url;
+use toml_edit::{Array, DocumentMut, Item, Table, Value};
+
+use crate::{
+    expand::{self, Field},
+    migrate,
+};
+
+/// The key listing the files a config includes
+pub const INCLUDE_KEY: &str = "include";
+
+/// Where a config file comes from, included paths are relative to it
+#[derive(Debug, Clone, PartialEq)]
+pub enum Origin {
+    Path(PathBuf),
+    Url(Url),
+}
+
+impl Origin {
+    /// The file `include` names, relative to the directory of this one
+    fn join(&self, include: &str) -> eyre::Result<Origin> {
+        match self {
+            Origin::Path(path) => {
+                let owner = format!("the config {}", path.display());
+                let included = expand::expand_source_path(include, Field::new("include", &owner))?;
+                let directory = path.parent().unwrap_or(path);
+                Ok(Origin::Path(directory.join(included)))
+            }
+            Origin::Url(url) => {
+                Ok(Origin::Url(url.join(include).with_context(|| {
+                    format!("Invalid include {:?} of {}", include, url)
+                })?))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Origin::Path(path) => write!(f, "{}", path.display()),
+            Origin::Url(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+/// `source`, read from `origin`, with the files its `include` list names merged in, `read`
+/// reading each of them.
+///
+/// What a file sets wins over what it includes, and a later include wins over an earlier one.
+/// Tables are merged key by key, and a package replaces the package of the same name in the same
+/// section of an earlier file. Included files may include others in turn.
+///
+/// A config without `include` is returned as written. Otherwise every file is upgraded to the
+/// current schema version before merging, so they need not all use the same one.
+pub fn resolve(
+    source: String,
+    origin: &Origin,
+    read: &dyn Fn(&Origin) -> eyre::Result<String>,
+) -> eyre::Result<String> {
+    let document: DocumentMut = source
+        .parse()
+        .with_context(|| format!("Parsing config {}", origin))?;
+    if document.get(INCLUDE_KEY).is_none() {
+        return Ok(source);
+    }
+    let mut merged = merge_file(document, origin, read, &mut vec![origin.clone()])?;
+    merged.insert(
+        migrate::VERSION_KEY,
+        toml_edit::value(i64::from(migrate::CONFIG_SCHEMA.current)),
+    );
+    Ok(merged.to_string())
+}
+
+/// `document` merged over its includes, `chain` holding the files including it to catch cycles
+fn merge_file(
+    mut document: DocumentMut,
+    origin: &Origin,
+    read: &dyn Fn(&Origin) -> eyre::Result<String>,
+    chain: &mut Vec<Origin>,
+) -> eyre::Result<DocumentMut> {
+    migrate::migrate(&migrate::CONFIG_SCHEMA, &mut document)
+        .with_context(|| format!("Migrating config {}", origin))?;
+    document.remove(migrate::VERSION_KEY);
+    let includes = match document.remove(INCLUDE_KEY) {
+        None => vec![],
+        Some(item) => item
+            .as_array()
+            .filter(|array| array.iter().all(|value| value.is_str()))
+            .ok_or_else(|| eyre::eyre!("`include` of {} must be a list of files", origin))?
+            .iter()
+            .filter_map(|value| value.as_str().map(str::to_string))
+            .collect(),
+    };
+
+    let mut merged = DocumentMut::new();
+    for include in includes {
+        let included = origin.join(&include)?;
+        if chain.contains(&included) {
+            eyre::bail!("{} includes {}, which includes it back", origin, included);
+        }
+        let source = read(&included).with_context(|| format!("Including {}", included))?;
+        let document: DocumentMut = source
+            .parse()
+            .with_context(|| format!("Parsing config {}", included))?;
+        chain.push(included.clone());
+        let document = merge_file(document, &included, read, chain)?;
+        chain.pop();
+        merge_tables(merged.as_table_mut(), document.as_table());
+    }
+    merge_tables(merged.as_table_mut(), document.as_table());
+    Ok(merged)
+}
+
+/// Merge `overlay` into `base`, the values of `overlay` winning
+fn merge_tables(base: &mut Table, overlay: &Table) {
+    for (key, item) in overlay.iter() {
+        match (base.get_mut(key), item) {
+            (Some(existing), _) if key == "packages" => merge_packages(existing, item),
+            (Some(Item::Table(existing)), Item::Table(table)) => merge_tables(existing, table),
+            _ => {
+                base.insert(key, item.clone());
+            }
+        }
+    }
+}
+
+/// Replace the packages of `base` named like one of `overlay`, and append the others
+fn merge_packages(base: &mut Item, overlay: &Item) {
+    let mut packages = tables(base);
+    for package in tables(overlay) {
+        let name = package
+            .get("name")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        let existing = packages.iter_mut().find(|existing| {
+            name.is_some() && existing.get("name").and_then(Value::as_str) == name.as_deref()
+        });
+        match existing {
+            Some(existing) => *existing = package,
+            None => packages.push(package),
+        }
+    }
+    let mut array = Array::new();
+    for package in packages {
+        let mut value = Value::InlineTable(package);
+        value.decor_mut().set_prefix("\n  ");
+        array.push_formatted(value);
+    }
+    array.set_trailing("\n");
+    array.set_trailing_comma(true);
+    *base = toml_edit::value(array);
+}
+
+/// The packages of a `packages` item, whichever way the file writes them
+fn tables(item: &Item) -> Vec<toml_edit::InlineTable> {
+    match item {
+        Item::ArrayOfTables(tables) => tables
+            .iter()
+            .map(|table| table.clone().into_inline_table())
+            .collect(),
+        Item::Value(Value::Array(array)) => array
+            .iter()
+            .filter_map(|value| value.as_inline_table().cloned())
+            .collect(),
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::{arch::Target, config};
+
+    fn resolve_all(files: &[(&str, &str)]) -> eyre::Result<String> {
+        let files: BTreeMap<PathBuf, String> = files
+            .iter()
+            .map(|(path, source)| (PathBuf::from(path), source.to_string()))
+            .collect();
+        let read = |origin: &Origin| match origin {
+            Origin::Path(path) => files
+                .get(path)
+                .cloned()
+                .ok_or_else(|| eyre::eyre!("{} does not exist", path.display())),
+            Origin::Url(url) => eyre::bail!("{} is not a file", url),
+        };
+        let root = PathBuf::from("/config/workstation.toml");
+        resolve(files[&root].clone(), &Origin::Path(root), &read)
+    }
+
+    #[test]
+    fn test_includes_are_merged_with_the_including_file_winning() {
+        let merged = resolve_all(&[
+            (
+                "/config/workstation.toml",
+                r#"
+include = ["shared/common.toml", "work.toml"]
+
+[workstation.download]
+retries = 5
+
+[linux_x86_64]
+location = "~/.local/bin"
+packages = [
+  { name = "fd", url = "https://example.com/fd-mine" },
+]
+"#,
+            ),
+            (
+                "/config/shared/common.toml",
+                r#"
+schema_version = 1
+include = ["base.toml"]
+
+[workstation.download]
+retries = 1
+segments = 4
+
+[linux_x86_64]
+location = "/opt/bin"
+packages = [
+  { name = "rg", url = "https://example.com/rg" },
+  { name = "fd", url = "https://example.com/fd" },
+]
+"#,
+            ),
+            (
+                "/config/shared/base.toml",
+                r#"
+[[linux_x86_64.packages]]
+name = "bat"
+url = "https://example.com/bat"
+"#,
+            ),
+            (
+                "/config/work.toml",
+                r#"
+[linux_x86_64]
+packages = [{ name = "rg", url = "https://example.com/rg-work" }]
+"#,
+            ),
+        ])
+        .unwrap();
+
+        let config =
+            config::parse_config_for(&merged, &Target::parse("linux_x86_64").unwrap()).unwrap();
+        let packages: Vec<_> = config
+            .platform
+            .packages
+            .iter()
+            .map(|package| format!("{} {}", package.name(), package.source()))
+            .collect();
+
+        assert_eq!(config.platform.location, PathBuf::from("~/.local/bin"));
+        assert_eq!(config.download.retries, Some(5));
+        assert_eq!(config.download.segments, Some(4));
+        assert_eq!(
+            packages,
+            [
+                "bat https://example.com/bat",
+                "rg https://example.com/rg-work",
+                "fd https://example.com/fd-mine",
+            ]
+        );
+        assert!(merged.starts_with("schema_version = 2\n"), "{}", merged);
+    }
+
+    #[test]
+    fn test_include_errors_name_the_file() {
+        let cycle = resolve_all(&[
+            ("/config/workstation.toml", "include = [\"a.toml\"]\n"),
+            ("/config/a.toml", "include = [\"workstation.toml\"]\n"),
+        ])
+        .unwrap_err();
+        assert!(format!("{:#}", cycle).contains("includes /config/workstation.toml"));
+
+        let missing =
+            resolve_all(&[("/config/workstation.toml", "include = [\"b.toml\"]\n")]).unwrap_err();
+        assert!(format!("{:#}", missing).contains("Including /config/b.toml"));
+
+        let plain = "[linux_x86_64]\nlocation = \"/opt/bin\" # mine\npackages = []\n";
+        assert_eq!(
+            resolve_all(&[("/config/workstation.toml", plain)]).unwrap(),
+            plain
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use std::path::Path;
+
+use eyre::Context;
+
+use crate::{arch::Target, migrate};
+
+/// A starter config for `target`: its platform section installing to `~/.local/bin`, with one
+/// commented example of each common kind of package
+pub fn starter_config(target: &Target) -> String {
+    // The plain `linux_x86_64` for glibc, `linux_x86_64_musl` for musl
+    let section = target
+        .canonical_sections()
+        .into_iter()
+        .min_by_key(String::len)
+        .expect("every target has a section");
+    let arch = match target.arch.as_str() {
+        "aarch64" => "aarch64",
+        _ => "x86_64",
+    };
+    // ripgrep has no musl build for aarch64
+    let triple = match (target.os.as_str(), arch) {
+        ("macos", arch) => format!("{}-apple-darwin", arch),
+        (_, "aarch64") => "aarch64-unknown-linux-gnu".to_string(),
+        (_, arch) => format!("{}-unknown-linux-musl", arch),
+    };
+
+    format!(
+        r#"schema_version = {version}
+
+# Packages for this machine, run `workstation setup` to install them. Other platforms get their
+# own section, such as [linux_aarch64] or [macos_aarch64], packages they all share go in
+# [common]. Run `workstation schema` for every setting.
+[{section}]
+location = "~/.local/bin"
+packages = [
+  # A single binary
+  # {{ name = "jq", url = "https://github.com/jqlang/jq/releases/download/jq-1.7.1/jq-{jq}" }},
+  # A file from an archive, `bin` is its path in the archive
+  # {{ name = "rg", bin = "ripgrep-14.1.0-{triple}/rg", archive = "https://github.com/BurntSushi/ripgrep/releases/download/14.1.0/ripgrep-14.1.0-{triple}.tar.gz" }},
+  # The latest GitHub release, the asset for this platform is picked from its name
+  # {{ name = "fd", repo = "sharkdp/fd" }},
+]
+"#,
+        version = migrate::CONFIG_SCHEMA.current,
+        section = section,
+        jq = jq_asset(target),
+        triple = triple,
+    )
+}
+
+/// The name jq publishes its binary for `target` under
+fn jq_asset(target: &Target) -> String {
+    let os = match target.os.as_str() {
+        "macos" => "macos",
+        _ => "linux",
+    };
+    let arch = match target.arch.as_str() {
+        "aarch64" => "arm64",
+        _ => "amd64",
+    };
+    format!("{}-{}", os, arch)
+}
+
+/// Write the starter config for `target` to `path`, never over an existing config unless `force`
+pub fn run(path: &Path, target: &Target, force: bool) -> eyre::Result<()> {
+    if path.exists() && !force {
+        eyre::bail!(
+            "{} already exists, pass --force to replace it",
+            path.display()
+        );
+    }
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Creating {}", parent.display()))?;
+    }
+    std::fs::write(path, starter_config(target))
+        .with_context(|| format!("Writing {}", path.display()))?;
+    println!(
+        "Created {}, uncomment or add packages, then run `workstation setup`",
+        path.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+
+    #[test]
+    fn test_starter_config_and_its_examples_parse() {
+        for name in ["linux_x86_64", "linux_aarch64_musl", "macos_aarch64"] {
+            let target = Target::parse(name).unwrap();
+            let starter = starter_config(&target);
+            let examples = starter.replace("  # {", "  {");
+
+            let config = config::parse_config_for(&starter, &target).unwrap();
+            assert!(config.platform.packages.is_empty());
+            let config = config::parse_config_for(&examples, &target).unwrap();
+            assert_eq!(config.platform.packages.len(), 3, "{}", examples);
+            assert!(crate::check::lint(&config).problems.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_an_existing_config_is_kept() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("config/workstation.toml");
+        let target = Target::parse("linux_x86_64").unwrap();
+
+        run(&path, &target, false).unwrap();
+        std::fs::write(&path, "# mine\n").unwrap();
+        assert!(run(&path, &target, false).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "# mine\n");
+        run(&path, &target, true).unwrap();
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .contains("[linux_x86_64]"));
+    }
+}
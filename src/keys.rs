@@ -0,0 +1,320 @@
+use std::fmt;
+
+use serde_json::Value as Schema;
+use toml_edit::{Item, Key, Value};
+
+/// A key in the config that no setting has, most often a typo
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownKey {
+    /// Dotted path of the key, such as `linux_x86_64.packages[0].urll`
+    pub path: String,
+    /// 1-based line and column of the key in the source
+    pub line: usize,
+    pub column: usize,
+    /// The known key closest to it, if any is close
+    pub suggestion: Option<String>,
+}
+
+impl UnknownKey {
+    /// What is wrong, without where
+    pub fn message(&self) -> String {
+        match &self.suggestion {
+            Some(suggestion) => format!("unknown key {}, did you mean {}?", self.path, suggestion),
+            None => format!("unknown key {}", self.path),
+        }
+    }
+}
+
+impl fmt::Display for UnknownKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line,
+            self.column,
+            self.message()
+        )
+    }
+}
+
+/// 1-based line and column of the byte `offset` of `source`
+pub fn position(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .count()
+        + 1;
+    (line, column)
+}
+
+/// Every key of the TOML `source` that `schema`, the config's JSON Schema, does not describe.
+///
+/// Checking against the schema keeps the known keys in step with the types, and unlike
+/// `deny_unknown_fields` it works through the untagged package variants and flattened options.
+pub fn unknown_keys(source: &str, schema: &Schema) -> eyre::Result<Vec<UnknownKey>> {
+    let document = toml_edit::ImDocument::parse(source)?;
+    let mut walker = Walker {
+        source,
+        root: schema,
+        unknown: vec![],
+    };
+    walker.table(
+        document.as_table().iter().map(|(name, item)| {
+            let key = document.as_table().key(name).expect("iterated key exists");
+            (key, Node::Item(item))
+        }),
+        "",
+        schema,
+    );
+    Ok(walker.unknown)
+}
+
+enum Node<'a> {
+    Item(&'a Item),
+    Value(&'a Value),
+}
+
+struct Walker<'a> {
+    source: &'a str,
+    root: &'a Schema,
+    unknown: Vec<UnknownKey>,
+}
+
+impl<'a> Walker<'a> {
+    /// Follow `$ref` to the definition it names
+    fn resolve(&self, schema: &'a Schema) -> &'a Schema {
+        match schema["$ref"]
+            .as_str()
+            .and_then(|name| name.strip_prefix("#/$defs/"))
+        {
+            Some(name) => &self.root["$defs"][name],
+            None => schema,
+        }
+    }
+
+    /// The object schemas a table may match, every variant of an `anyOf`
+    fn variants(&self, schema: &'a Schema) -> Vec<&'a Schema> {
+        let schema = self.resolve(schema);
+        match schema["anyOf"].as_array() {
+            Some(variants) => variants
+                .iter()
+                .map(|variant| self.resolve(variant))
+                .collect(),
+            None => vec![schema],
+        }
+    }
+
+    fn table<'t>(
+        &mut self,
+        entries: impl Iterator<Item = (&'t Key, Node<'t>)>,
+        path: &str,
+        schema: &'a Schema,
+    ) {
+        let variants = self.variants(schema);
+        let known: Vec<&str> = variants
+            .iter()
+            .filter_map(|variant| variant["properties"].as_object())
+            .flat_map(|properties| properties.keys().map(String::as_str))
+            .collect();
+
+        for (key, node) in entries {
+            let name = key.get();
+            let child_path = match path {
+                "" => name.to_string(),
+                path => format!("{}.{}", path, name),
+            };
+            let property = variants
+                .iter()
+                .find_map(|variant| variant["properties"].get(name));
+            let additional = variants
+                .iter()
+                .map(|variant| &variant["additionalProperties"])
+                .find(|additional| additional.is_object());
+            match property.or(additional) {
+                Some(child) => self.node(node, &child_path, child),
+                None => {
+                    let (line, column) = self.position(key);
+                    self.unknown.push(UnknownKey {
+                        path: child_path,
+                        line,
+                        column,
+                        suggestion: suggest(name, &known),
+                    });
+                }
+            }
+        }
+    }
+
+    fn node<'t>(&mut self, node: Node<'t>, path: &str, schema: &'a Schema) {
+        let items = &self.resolve(schema)["items"];
+        match node {
+            Node::Item(Item::Table(table)) => self.table(
+                table.iter().map(|(name, item)| {
+                    (
+                        table.key(name).expect("iterated key exists"),
+                        Node::Item(item),
+                    )
+                }),
+                path,
+                schema,
+            ),
+            Node::Item(Item::ArrayOfTables(array)) => {
+                for (index, table) in array.iter().enumerate() {
+                    self.table(
+                        table.iter().map(|(name, item)| {
+                            (
+                                table.key(name).expect("iterated key exists"),
+                                Node::Item(item),
+                            )
+                        }),
+                        &format!("{}[{}]", path, index),
+                        items,
+                    );
+                }
+            }
+            Node::Item(Item::Value(value)) | Node::Value(value) => match value {
+                Value::InlineTable(table) => self.table(
+                    table.iter().map(|(name, value)| {
+                        (
+                            table.key(name).expect("iterated key exists"),
+                            Node::Value(value),
+                        )
+                    }),
+                    path,
+                    schema,
+                ),
+                Value::Array(array) => {
+                    for (index, value) in array.iter().enumerate() {
+                        self.node(Node::Value(value), &format!("{}[{}]", path, index), items);
+                    }
+                }
+                _ => {}
+            },
+            Node::Item(Item::None) => {}
+        }
+    }
+
+    fn position(&self, key: &Key) -> (usize, usize) {
+        position(
+            self.source,
+            key.span().map(|span| span.start).unwrap_or_default(),
+        )
+    }
+}
+
+/// The known key within a third of its length in edits of `name`
+fn suggest(name: &str, known: &[&str]) -> Option<String> {
+    let limit = (name.chars().count() / 3).max(1);
+    known
+        .iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .min()
+        .map(|(_, candidate)| candidate.to_string())
+}
+
+/// Levenshtein distance, with a swap of two neighbouring characters counting as one edit
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let substitution = rows[i - 1][j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = substitution.min(rows[i - 1][j] + 1).min(rows[i][j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = distance;
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unknown(source: &str) -> Vec<String> {
+        unknown_keys(source, &crate::config::json_schema())
+            .unwrap()
+            .iter()
+            .map(UnknownKey::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_typos_are_found_with_suggestions() {
+        let source = r#"
+[workstation.install]
+ignroe = ["*.local"]
+
+[linux_x86_64]
+location = "~/.local/bin"
+pacakges = []
+"#;
+
+        assert_eq!(
+            unknown(source),
+            vec![
+                "line 3, column 1: unknown key workstation.install.ignroe, did you mean ignore?",
+                "line 7, column 1: unknown key linux_x86_64.pacakges, did you mean packages?",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nested_keys_in_packages() {
+        let source = r#"
+[linux_x86_64]
+location = "~/.local/bin"
+packages = [
+  { name = "fd", urll = "https://example.com/fd" },
+  { name = "rg", url = "https://example.com/rg", shell_init = { zhs = "eval" }, frobnicate = true },
+]
+
+[[linux_aarch64.packages]]
+name = "bat"
+archive = "https://example.com/bat.tar.gz"
+bin = "bat"
+pin_hsah = "sha256:0"
+"#;
+
+        assert_eq!(
+            unknown(source),
+            vec![
+                "line 5, column 18: unknown key linux_x86_64.packages[0].urll, did you mean url?",
+                "line 6, column 65: unknown key linux_x86_64.packages[1].shell_init.zhs, did you mean zsh?",
+                "line 6, column 81: unknown key linux_x86_64.packages[1].frobnicate",
+                "line 13, column 1: unknown key linux_aarch64.packages[0].pin_hsah, did you mean pin_hash?",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_valid_config_has_no_unknown_keys() {
+        assert_eq!(
+            unknown(include_str!("../workstation.toml")),
+            Vec::<String>::new()
+        );
+        assert_eq!(unknown("schema_version = 1\n"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("packages", "pacakges"), 1);
+        assert_eq!(edit_distance("url", "urll"), 1);
+        assert_eq!(edit_distance("bin", "archive"), 6);
+        assert_eq!(suggest("frobnicate", &["name", "url"]), None);
+    }
+}
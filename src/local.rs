@@ -0,0 +1,196 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+    time::UNIX_EPOCH,
+};
+
+use eyre::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::digest;
+
+const RECORDS_FILE: &str = "extracted.json";
+/// The newest version of the file this binary reads and writes, see [`crate::state`]
+const SCHEMA_VERSION: u32 = 1;
+
+/// The path of a local archive source: a `file://` URL, or a path starting with `/`, `~` or `.`
+pub fn local_path(source: &str) -> Option<&str> {
+    if let Some(path) = source.strip_prefix("file://") {
+        return Some(path);
+    }
+    source.starts_with(['/', '~', '.']).then_some(source)
+}
+
+/// What was extracted from a local archive the last time a package was installed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Extraction {
+    archive: PathBuf,
+    size: u64,
+    /// Modification time in nanoseconds since the epoch
+    modified: u128,
+    archive_hash: String,
+    entry_hash: String,
+}
+
+/// Size and modification time, cheap to compare without reading the archive
+fn stamp(path: &Path) -> eyre::Result<(u64, u128)> {
+    let metadata =
+        std::fs::metadata(path).with_context(|| format!("Reading {}", path.display()))?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|modified| modified.as_nanos())
+        .unwrap_or_default();
+    Ok((metadata.len(), modified))
+}
+
+/// Records of local archive extractions, kept in the state directory so that an unchanged
+/// archive is not read and extracted again on every run
+pub struct Extractions {
+    path: PathBuf,
+    records: Mutex<BTreeMap<String, Extraction>>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl Extractions {
+    /// Load the records, a missing or unreadable file only means everything is extracted again
+    pub fn load(state_dir: &Path) -> Self {
+        let path = state_dir.join(RECORDS_FILE);
+        let records = std::fs::read(&path)
+            .ok()
+            .and_then(|bytes| crate::state::parse(&bytes, SCHEMA_VERSION).ok())
+            .unwrap_or_default();
+        Extractions {
+            path,
+            records: Mutex::new(records),
+        }
+    }
+
+    pub fn save(&self) -> eyre::Result<()> {
+        let directory = self.path.parent().expect("records path has a parent");
+        std::fs::create_dir_all(directory)
+            .with_context(|| format!("Creating {}", directory.display()))?;
+        let json = crate::state::to_json(&*lock(&self.records), SCHEMA_VERSION);
+        std::fs::write(&self.path, json).with_context(|| format!("Writing {}", self.path.display()))
+    }
+
+    /// Whether `installed` still holds the entry extracted from `archive`, and the archive did
+    /// not change since.
+    ///
+    /// The archive is only hashed when its size or modification time changed, a merely touched
+    /// archive then counts as unchanged.
+    pub fn unchanged(&self, package: &str, archive: &Path, installed: &Path) -> eyre::Result<bool> {
+        let Some(record) = lock(&self.records).get(package).cloned() else {
+            return Ok(false);
+        };
+        if record.archive != archive {
+            return Ok(false);
+        }
+        match std::fs::File::open(installed).and_then(digest::sha256_reader) {
+            Ok(hash) if hash == record.entry_hash => {}
+            _ => return Ok(false),
+        }
+
+        let (size, modified) = stamp(archive)?;
+        if (size, modified) == (record.size, record.modified) {
+            return Ok(true);
+        }
+        if crate::asset::Asset::open(archive)?.hash()? != record.archive_hash {
+            return Ok(false);
+        }
+        lock(&self.records).insert(
+            package.to_string(),
+            Extraction {
+                size,
+                modified,
+                ..record
+            },
+        );
+        Ok(true)
+    }
+
+    /// Forget a package that was uninstalled
+    pub fn remove(&self, package: &str) {
+        lock(&self.records).remove(package);
+    }
+
+    /// Remember that the entry hashing to `entry_hash` was extracted from `archive`, whose
+    /// content hashes to `archive_hash`
+    pub fn record(
+        &self,
+        package: &str,
+        archive: &Path,
+        archive_hash: String,
+        entry_hash: String,
+    ) -> eyre::Result<()> {
+        let (size, modified) = stamp(archive)?;
+        lock(&self.records).insert(
+            package.to_string(),
+            Extraction {
+                archive: archive.to_path_buf(),
+                size,
+                modified,
+                archive_hash,
+                entry_hash,
+            },
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_sources() {
+        assert_eq!(
+            local_path("file:///srv/sdk.tar.gz"),
+            Some("/srv/sdk.tar.gz")
+        );
+        assert_eq!(local_path("~/sdk.zip"), Some("~/sdk.zip"));
+        assert_eq!(local_path("./sdk.zip"), Some("./sdk.zip"));
+        assert_eq!(local_path("https://example.com/sdk.zip"), None);
+    }
+
+    #[test]
+    fn test_unchanged_archive_is_detected() {
+        let directory = tempfile::tempdir().unwrap();
+        let archive = directory.path().join("sdk.tar.gz");
+        let installed = directory.path().join("sdk");
+        std::fs::write(&archive, b"archive").unwrap();
+        std::fs::write(&installed, b"entry").unwrap();
+
+        let extractions = Extractions::load(directory.path());
+        assert!(!extractions.unchanged("sdk", &archive, &installed).unwrap());
+        extractions
+            .record(
+                "sdk",
+                &archive,
+                digest::sha256(b"archive"),
+                digest::sha256(b"entry"),
+            )
+            .unwrap();
+        extractions.save().unwrap();
+
+        let extractions = Extractions::load(directory.path());
+        assert!(extractions.unchanged("sdk", &archive, &installed).unwrap());
+
+        // Same content written again only changes the modification time
+        std::fs::write(&archive, b"archive").unwrap();
+        assert!(extractions.unchanged("sdk", &archive, &installed).unwrap());
+
+        std::fs::write(&installed, b"edited by hand").unwrap();
+        assert!(!extractions.unchanged("sdk", &archive, &installed).unwrap());
+
+        std::fs::write(&installed, b"entry").unwrap();
+        std::fs::write(&archive, b"new release").unwrap();
+        assert!(!extractions.unchanged("sdk", &archive, &installed).unwrap());
+    }
+}
@@ -0,0 +1,730 @@
+use std::{
+    borrow::Cow,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use eyre::Context;
+use serde::Deserialize;
+use sha2::Digest;
+use toml_edit::{value, ArrayOfTables, DocumentMut, Table};
+
+use crate::{
+    config::{CommandLine, Config, PackageConfig},
+    migrate::{self, Schema},
+};
+
+pub const LOCK_SCHEMA: Schema = Schema {
+    kind: "lock",
+    current: 1,
+    version_required: true,
+    migrations: &[],
+};
+
+const HEADER: &str = "# Generated by `workstation lock update`, do not edit by hand\n";
+
+/// The lockfile belongs to the local config file and lives next to it
+pub fn lock_path(config_path: &Path) -> PathBuf {
+    config_path.with_extension("lock")
+}
+
+/// A locked package, as stored in the lockfile
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct LockEntry {
+    pub name: String,
+    /// Where the package is downloaded from
+    pub source: String,
+    /// Hash of every config field that decides what gets installed
+    pub fields_hash: String,
+    #[serde(flatten)]
+    pub resolved: Resolved,
+}
+
+/// What a package resolved to when it was locked, custom commands resolve to nothing
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Resolved {
+    /// The URL the asset was downloaded from, for a GitHub release the asset that was picked
+    pub url: Option<String>,
+    /// The release tag, or else the version inferred from the asset
+    pub version: Option<String>,
+    /// Digest of the asset, `setup --locked` refuses any other content
+    pub hash: Option<String>,
+}
+
+impl Resolved {
+    fn fields(&self) -> [(&'static str, Option<&str>); 3] {
+        [
+            ("url", self.url.as_deref()),
+            ("version", self.version.as_deref()),
+            ("hash", self.hash.as_deref()),
+        ]
+    }
+
+    /// Write the fields into a lockfile entry, returning whether any changed
+    fn write_to(&self, table: &mut Table) -> bool {
+        let mut changed = false;
+        for (key, field) in self.fields() {
+            if table.get(key).and_then(|item| item.as_str()) == field {
+                continue;
+            }
+            match field {
+                Some(field) => table.insert(key, value(field)),
+                None => table.remove(key),
+            };
+            changed = true;
+        }
+        changed
+    }
+}
+
+impl LockEntry {
+    /// Resolve `package` into the entry that would be locked for it.
+    ///
+    /// Values decrypted from a SOPS config are redacted from the source, the lockfile is not
+    /// encrypted.
+    pub fn resolve(package: &PackageConfig) -> Self {
+        LockEntry {
+            name: package.name().to_string(),
+            source: crate::sops::redact(&package.source()),
+            fields_hash: fields_hash(package),
+            resolved: Resolved::default(),
+        }
+    }
+
+    fn to_table(&self) -> Table {
+        let mut table = Table::new();
+        table.insert("name", value(&self.name));
+        table.insert("source", value(&self.source));
+        table.insert("fields_hash", value(&self.fields_hash));
+        self.resolved.write_to(&mut table);
+        table
+    }
+}
+
+/// The fields that change what is installed, settings such as download segments do not count
+fn source_fields(package: &PackageConfig) -> Vec<(&'static str, Cow<'_, str>)> {
+    let mut fields: Vec<(&'static str, &str)> = match package {
+        PackageConfig::Archive {
+            name,
+            bin,
+            archive,
+            files,
+            ..
+        } => {
+            let mut fields = vec![("name", name.as_str()), ("archive", archive), ("bin", bin)];
+            for file in files {
+                fields.push(("entry", &file.entry));
+                fields.push(("dest_name", &file.dest_name));
+            }
+            fields
+        }
+        PackageConfig::AutoArchArchive {
+            name,
+            auto_arch_bin,
+            archive,
+            ..
+        } => vec![
+            ("name", name),
+            ("archive", archive),
+            ("auto_arch_bin", auto_arch_bin),
+        ],
+        PackageConfig::Directory {
+            name,
+            archive,
+            directory,
+            ..
+        } => vec![
+            ("name", name),
+            ("archive", archive),
+            ("directory", directory),
+        ],
+        PackageConfig::Binary { name, url, .. } => vec![("name", name), ("url", url)],
+        PackageConfig::AppImage { name, appimage, .. } => {
+            vec![("name", name), ("appimage", appimage)]
+        }
+        PackageConfig::GithubRelease {
+            name,
+            repo,
+            version,
+            asset_pattern,
+            bin,
+            ..
+        } => {
+            let mut fields = vec![("name", name.as_str()), ("repo", repo)];
+            for (key, field) in [
+                ("version", version),
+                ("asset_pattern", asset_pattern),
+                ("bin", bin),
+            ] {
+                if let Some(field) = field {
+                    fields.push((key, field));
+                }
+            }
+            fields
+        }
+        PackageConfig::Command {
+            name,
+            command,
+            creates,
+            ..
+        } => {
+            let mut fields = vec![("name", name.as_str()), ("creates", creates)];
+            match command {
+                CommandLine::Args(args) => {
+                    fields.extend(args.iter().map(|arg| ("command", arg.as_str())))
+                }
+                CommandLine::Shell(line) => fields.push(("command", line)),
+            }
+            fields
+        }
+        PackageConfig::GitRepo {
+            name,
+            git,
+            dest,
+            rev,
+            ..
+        } => {
+            let mut fields = vec![("name", name.as_str()), ("git", git), ("dest", dest)];
+            if let Some(rev) = rev {
+                fields.push(("rev", rev));
+            }
+            fields
+        }
+        PackageConfig::Cargo {
+            name,
+            krate,
+            version,
+            features,
+            bin,
+            ..
+        } => {
+            let mut fields = vec![("name", name.as_str()), ("crate", krate)];
+            fields.extend(version.as_deref().map(|version| ("version", version)));
+            fields.extend(
+                features
+                    .iter()
+                    .map(|feature| ("features", feature.as_str())),
+            );
+            fields.extend(bin.as_deref().map(|bin| ("bin", bin)));
+            fields
+        }
+        PackageConfig::Go {
+            name, go, version, ..
+        } => {
+            let mut fields = vec![("name", name.as_str()), ("go", go)];
+            fields.extend(version.as_deref().map(|version| ("version", version)));
+            fields
+        }
+        PackageConfig::Pipx {
+            name,
+            pipx: tool,
+            version,
+            ..
+        }
+        | PackageConfig::Npm {
+            name,
+            npm: tool,
+            version,
+            ..
+        } => {
+            let mut fields = vec![("name", name.as_str()), (package.kind(), tool.as_str())];
+            fields.extend(version.as_deref().map(|version| ("version", version)));
+            fields
+        }
+        PackageConfig::Flatpak {
+            name,
+            flatpak,
+            remote,
+            remote_url,
+            ..
+        } => {
+            let mut fields = vec![("name", name.as_str()), ("flatpak", flatpak)];
+            fields.extend(remote.as_deref().map(|remote| ("remote", remote)));
+            fields.extend(remote_url.as_deref().map(|url| ("remote_url", url)));
+            fields
+        }
+        PackageConfig::Snap {
+            name,
+            snap,
+            classic,
+            channel,
+            ..
+        } => {
+            let mut fields = vec![("name", name.as_str()), ("snap", snap)];
+            if *classic {
+                fields.push(("classic", "true"));
+            }
+            fields.extend(channel.as_deref().map(|channel| ("channel", channel)));
+            fields
+        }
+        PackageConfig::Distro {
+            name,
+            files,
+            package,
+            ..
+        } => {
+            let mut fields = vec![("name", name.as_str())];
+            fields.extend(files.deb.as_deref().map(|deb| ("deb", deb)));
+            fields.extend(files.rpm.as_deref().map(|rpm| ("rpm", rpm)));
+            fields.extend(package.as_deref().map(|package| ("package", package)));
+            fields
+        }
+        PackageConfig::System { name, names, .. } => {
+            let mut fields = vec![("name", name.as_str())];
+            fields.extend(names.apt.as_deref().map(|apt| ("apt", apt)));
+            fields.extend(names.dnf.as_deref().map(|dnf| ("dnf", dnf)));
+            fields.extend(names.pacman.as_deref().map(|pacman| ("pacman", pacman)));
+            fields.extend(names.brew.as_deref().map(|brew| ("brew", brew)));
+            fields.extend(names.cask.as_deref().map(|cask| ("cask", cask)));
+            fields
+        }
+    };
+    if let Some(pin) = &package.options().pin_hash {
+        fields.push(("pin_hash", pin));
+    }
+    let mut fields: Vec<_> = fields
+        .into_iter()
+        .map(|(key, field)| (key, Cow::Borrowed(field)))
+        .collect();
+    if let PackageConfig::Directory {
+        strip_components, ..
+    } = package
+    {
+        fields.push(("strip_components", strip_components.to_string().into()));
+    }
+    if let Some(location) = &package.options().location {
+        fields.push(("location", location.to_string_lossy()));
+    }
+    if let Some(mode) = package.options().mode {
+        fields.push(("mode", format!("{:o}", mode).into()));
+    }
+    fields
+}
+
+/// Hash of the fields [`source_fields`] of `package`
+pub fn fields_hash(package: &PackageConfig) -> String {
+    let mut hasher = sha2::Sha256::new();
+    for (key, field) in source_fields(package) {
+        // Length prefixes keep `"ab" + "c"` and `"a" + "bc"` apart
+        for part in [key, &field] {
+            hasher.update((part.len() as u64).to_le_bytes());
+            hasher.update(part.as_bytes());
+        }
+    }
+    crate::digest::finish(hasher)
+}
+
+/// Why a lockfile no longer matches the config
+#[derive(Debug, Clone, PartialEq)]
+pub enum Staleness {
+    /// The package is in the config but not in the lockfile
+    NotLocked(String),
+    /// The package is locked but no longer in the config
+    NotConfigured(String),
+    /// The package fields changed since it was locked
+    Changed {
+        name: String,
+        locked_source: String,
+        source: String,
+    },
+}
+
+impl fmt::Display for Staleness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Staleness::NotLocked(name) => write!(f, "{} is in the config but not locked", name),
+            Staleness::NotConfigured(name) => {
+                write!(f, "{} is locked but no longer in the config", name)
+            }
+            Staleness::Changed {
+                name,
+                locked_source,
+                source,
+            } if locked_source != source => write!(
+                f,
+                "{} changed since it was locked: source was {}, is now {}",
+                name, locked_source, source
+            ),
+            Staleness::Changed { name, .. } => write!(
+                f,
+                "{} changed since it was locked: its bin or name fields differ",
+                name
+            ),
+        }
+    }
+}
+
+/// A lockfile, kept as a document so updates leave untouched entries byte-identical
+pub struct Lockfile {
+    document: DocumentMut,
+}
+
+impl Lockfile {
+    pub fn new() -> Self {
+        let mut document = DocumentMut::new();
+        document.insert("schema_version", value(i64::from(LOCK_SCHEMA.current)));
+        if let Some(mut key) = document.key_mut("schema_version") {
+            key.leaf_decor_mut().set_prefix(HEADER);
+        }
+        document.insert(
+            "package",
+            toml_edit::Item::ArrayOfTables(ArrayOfTables::new()),
+        );
+        Lockfile { document }
+    }
+
+    /// Read the lockfile at `path`, `None` if there is none
+    pub fn read(path: &Path) -> eyre::Result<Option<Self>> {
+        match std::fs::read_to_string(path) {
+            Ok(source) => Self::parse(&source)
+                .with_context(|| format!("Reading lockfile {}", path.display()))
+                .map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Reading lockfile {}", path.display())),
+        }
+    }
+
+    pub fn parse(source: &str) -> eyre::Result<Self> {
+        let mut document: DocumentMut = source.parse().context("Parsing lockfile")?;
+        migrate::migrate(&LOCK_SCHEMA, &mut document)?;
+        let lockfile = Lockfile { document };
+        lockfile.entries()?;
+        Ok(lockfile)
+    }
+
+    pub fn entries(&self) -> eyre::Result<Vec<LockEntry>> {
+        #[derive(Deserialize)]
+        struct Entries {
+            #[serde(default)]
+            package: Vec<LockEntry>,
+        }
+
+        let entries: Entries =
+            toml::from_str(&self.document.to_string()).context("Parsing lockfile")?;
+        Ok(entries.package)
+    }
+
+    fn packages(&mut self) -> &mut ArrayOfTables {
+        if self.document.get("package").is_none() {
+            self.document.insert(
+                "package",
+                toml_edit::Item::ArrayOfTables(ArrayOfTables::new()),
+            );
+        }
+        self.document["package"]
+            .as_array_of_tables_mut()
+            .expect("package is an array of tables")
+    }
+
+    /// Re-lock the packages called `names`, or every package when `names` is empty. `resolve`
+    /// finds out what each of them resolves to now.
+    ///
+    /// Returns the names of the entries that were added, changed or removed.
+    pub fn update(
+        &mut self,
+        config: &Config,
+        names: &[String],
+        mut resolve: impl FnMut(&PackageConfig) -> eyre::Result<Resolved>,
+    ) -> eyre::Result<Vec<String>> {
+        let packages = &config.platform.packages;
+        let locked: Vec<String> = self.entries()?.into_iter().map(|e| e.name).collect();
+        for name in names {
+            if !packages.iter().any(|p| p.name() == name) && !locked.contains(name) {
+                eyre::bail!("{} is neither in the config nor in the lockfile", name);
+            }
+        }
+        let selected = |name: &str| names.is_empty() || names.iter().any(|n| n == name);
+
+        let mut updated = vec![];
+        let entries = self.entries()?;
+        let tables = self.packages();
+
+        // Remove selected entries that are no longer configured, back to front to keep indices
+        for (index, entry) in entries.iter().enumerate().rev() {
+            if selected(&entry.name) && !packages.iter().any(|p| p.name() == entry.name) {
+                tables.remove(index);
+                updated.push(entry.name.clone());
+            }
+        }
+
+        for package in packages.iter().filter(|p| selected(p.name())) {
+            let mut resolved = LockEntry::resolve(package);
+            resolved.resolved = resolve(package)?;
+            let existing = tables
+                .iter_mut()
+                .find(|table| table.get("name").and_then(|n| n.as_str()) == Some(package.name()));
+            match existing {
+                Some(table) => {
+                    // Only touch the values, comments and formatting of the entry stay
+                    if table.get("source").and_then(|s| s.as_str()) != Some(&resolved.source) {
+                        table.insert("source", value(&resolved.source));
+                        updated.push(resolved.name.clone());
+                    }
+                    if table.get("fields_hash").and_then(|s| s.as_str())
+                        != Some(&resolved.fields_hash)
+                    {
+                        table.insert("fields_hash", value(&resolved.fields_hash));
+                        if !updated.contains(&resolved.name) {
+                            updated.push(resolved.name.clone());
+                        }
+                    }
+                    if resolved.resolved.write_to(table) && !updated.contains(&resolved.name) {
+                        updated.push(resolved.name.clone());
+                    }
+                }
+                None => {
+                    tables.push(resolved.to_table());
+                    updated.push(resolved.name.clone());
+                }
+            }
+        }
+
+        Ok(updated)
+    }
+
+    /// `config` with every package held to what it resolved to when it was locked: its asset
+    /// pinned to the locked hash and a GitHub release to the locked tag
+    pub fn pin(&self, config: &Config) -> eyre::Result<Config> {
+        let entries = self.entries()?;
+        let mut config = config.clone();
+        for package in &mut config.platform.packages {
+            let Some(entry) = entries.iter().find(|entry| entry.name == package.name()) else {
+                continue;
+            };
+            if let PackageConfig::GithubRelease { version, .. } = package {
+                if version.is_none() {
+                    version.clone_from(&entry.resolved.version);
+                }
+            }
+            let options = package.options_mut();
+            if options.pin_hash.is_none() {
+                options.pin_hash.clone_from(&entry.resolved.hash);
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn write(&self, path: &Path) -> eyre::Result<()> {
+        std::fs::write(path, self.to_string())
+            .with_context(|| format!("Writing lockfile {}", path.display()))
+    }
+}
+
+impl fmt::Display for Lockfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.document)
+    }
+}
+
+/// Every way `lockfile` differs from `config`, in config order
+pub fn stale(config: &Config, lockfile: &Lockfile) -> eyre::Result<Vec<Staleness>> {
+    let entries = lockfile.entries()?;
+    let packages = &config.platform.packages;
+    let mut problems = vec![];
+
+    for package in packages {
+        let resolved = LockEntry::resolve(package);
+        match entries.iter().find(|entry| entry.name == resolved.name) {
+            None => problems.push(Staleness::NotLocked(resolved.name)),
+            Some(entry) if entry.fields_hash != resolved.fields_hash => {
+                problems.push(Staleness::Changed {
+                    name: resolved.name,
+                    locked_source: entry.source.clone(),
+                    source: resolved.source,
+                })
+            }
+            Some(_) => {}
+        }
+    }
+    for entry in &entries {
+        if !packages.iter().any(|package| package.name() == entry.name) {
+            problems.push(Staleness::NotConfigured(entry.name.clone()));
+        }
+    }
+
+    Ok(problems)
+}
+
+/// The error for a `--locked` run whose lockfile does not match the config
+pub fn stale_error(path: &Path, problems: &[Staleness]) -> eyre::Report {
+    let mut message = format!(
+        "The lockfile {} is out of date with the config:",
+        path.display()
+    );
+    for problem in problems {
+        message.push_str(&format!("\n  {}", problem));
+    }
+    message.push_str("\nRun `workstation lock update` to refresh it");
+    eyre::eyre!(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(packages: &[(&str, &str)]) -> Config {
+        let packages = packages
+            .iter()
+            .map(|(name, url)| format!("{{ name = {:?}, url = {:?} }}", name, url))
+            .collect::<Vec<_>>()
+            .join(",\n");
+        crate::config::parse_config_for(
+            &format!(
+                "[linux_x86_64]\nlocation = \"/opt/bin\"\npackages = [\n{}\n]\n",
+                packages
+            ),
+            &crate::arch::Target::parse("linux_x86_64").unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn unresolved(_: &PackageConfig) -> eyre::Result<Resolved> {
+        Ok(Resolved::default())
+    }
+
+    fn locked(config: &Config) -> Lockfile {
+        let mut lockfile = Lockfile::new();
+        lockfile.update(config, &[], unresolved).unwrap();
+        Lockfile::parse(&lockfile.to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_fresh_lock_is_not_stale() {
+        let config = config(&[
+            ("rg", "https://example.com/rg"),
+            ("fd", "https://example.com/fd"),
+        ]);
+
+        assert_eq!(stale(&config, &locked(&config)).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_every_kind_of_staleness_is_reported() {
+        let before = config(&[
+            ("rg", "https://example.com/rg"),
+            ("fd", "https://example.com/fd"),
+        ]);
+        let after = config(&[
+            ("rg", "https://example.com/rg-15"),
+            ("fzf", "https://example.com/fzf"),
+        ]);
+
+        let problems = stale(&after, &locked(&before)).unwrap();
+
+        assert_eq!(
+            problems,
+            vec![
+                Staleness::Changed {
+                    name: "rg".to_string(),
+                    locked_source: "https://example.com/rg".to_string(),
+                    source: "https://example.com/rg-15".to_string(),
+                },
+                Staleness::NotLocked("fzf".to_string()),
+                Staleness::NotConfigured("fd".to_string()),
+            ]
+        );
+        assert!(problems[0]
+            .to_string()
+            .contains("source was https://example.com/rg,"));
+    }
+
+    #[test]
+    fn test_updating_one_package_leaves_the_others_untouched() {
+        let before = config(&[
+            ("rg", "https://example.com/rg"),
+            ("fd", "https://example.com/fd"),
+        ]);
+        let source = locked(&before)
+            .to_string()
+            .replace("name = \"fd\"", "name = \"fd\" # keep this comment");
+        let mut lockfile = Lockfile::parse(&source).unwrap();
+        let after = config(&[
+            ("rg", "https://example.com/rg-15"),
+            ("fd", "https://example.com/fd-11"),
+        ]);
+
+        let updated = lockfile
+            .update(&after, &["rg".to_string()], unresolved)
+            .unwrap();
+
+        assert_eq!(updated, vec!["rg"]);
+        let fd = |text: &str| {
+            let start = text.find("name = \"fd\"").unwrap();
+            text[start..].to_string()
+        };
+        assert_eq!(fd(&lockfile.to_string()), fd(&source));
+        assert_eq!(
+            stale(&after, &lockfile).unwrap(),
+            vec![Staleness::Changed {
+                name: "fd".to_string(),
+                locked_source: "https://example.com/fd".to_string(),
+                source: "https://example.com/fd-11".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_full_update_drops_removed_packages() {
+        let before = config(&[
+            ("rg", "https://example.com/rg"),
+            ("fd", "https://example.com/fd"),
+        ]);
+        let mut lockfile = locked(&before);
+        let after = config(&[("rg", "https://example.com/rg")]);
+
+        let updated = lockfile.update(&after, &[], unresolved).unwrap();
+
+        assert_eq!(updated, vec!["fd"]);
+        assert_eq!(stale(&after, &lockfile).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_resolved_assets_are_pinned() {
+        let config = config(&[
+            ("rg", "https://example.com/rg"),
+            ("fd", "https://example.com/fd"),
+        ]);
+        let rg = crate::digest::sha256(b"rg");
+        let mut lockfile = Lockfile::new();
+        lockfile
+            .update(&config, &[], |package| {
+                Ok(Resolved {
+                    url: package.url().map(str::to_string),
+                    version: Some("14.1.0".to_string()),
+                    hash: (package.name() == "rg").then(|| rg.clone()),
+                })
+            })
+            .unwrap();
+        let lockfile = Lockfile::parse(&lockfile.to_string()).unwrap();
+        assert!(lockfile
+            .to_string()
+            .contains("url = \"https://example.com/rg\"\nversion = \"14.1.0\"\nhash = "));
+
+        let pinned = lockfile.pin(&config).unwrap();
+
+        let pins: Vec<_> = pinned
+            .platform
+            .packages
+            .iter()
+            .map(|package| package.options().pin_hash.clone())
+            .collect();
+        assert_eq!(pins, vec![Some(rg), None]);
+        assert_eq!(stale(&config, &lockfile).unwrap(), vec![]);
+
+        // Re-locking what resolves the same leaves the lockfile as it is
+        let mut relocked = Lockfile::parse(&lockfile.to_string()).unwrap();
+        let same = lockfile.entries().unwrap();
+        let updated = relocked
+            .update(&config, &[], |package| {
+                let entry = same.iter().find(|e| e.name == package.name()).unwrap();
+                Ok(entry.resolved.clone())
+            })
+            .unwrap();
+        assert!(updated.is_empty());
+    }
+
+    #[test]
+    fn test_lockfile_needs_a_schema_version() {
+        assert!(Lockfile::parse("[[package]]\nname = \"rg\"\n").is_err());
+    }
+}
@@ -1,10 +1,86 @@
-use std::{io::Read, os::unix::fs::PermissionsExt, path::PathBuf};
+use std::{
+    io::Write,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
+use config::{Config, PackageConfig};
+use error::ErrorCode;
 use eyre::Context;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use report::{PackageReport, Reporter};
 use reqwest::Url;
-use serde::Deserialize;
+
+mod add;
+mod appimage;
+mod arch;
+mod archive;
+mod asset;
+mod backup;
+mod budget;
+mod cache;
+mod cargo;
+mod check;
+mod checksums;
+mod completions;
+mod config;
+mod conflicts;
+mod custom;
+mod diff;
+mod digest;
+mod distro;
+mod doctor;
+mod dotfiles;
+mod download;
+mod error;
+mod events;
+mod expand;
+mod exports;
+mod git;
+mod github;
+mod global;
+mod go;
+mod http;
+mod ignore;
+mod include;
+mod init;
+mod keys;
+mod local;
+mod lock;
+mod manifest;
+mod migrate;
+mod name;
+mod paths;
+mod preview;
+mod prune;
+mod reconcile;
+mod report;
+mod rustup;
+mod shell_init;
+mod signature;
+mod slots;
+mod sops;
+mod staged;
+mod stat;
+mod state;
+mod status;
+mod store;
+mod system;
+#[cfg(test)]
+mod test_server;
+mod trace;
+mod transaction;
+mod tui;
+mod update;
+mod validate;
+mod version;
+mod watch;
+mod zsync;
+
+/// Redraws per second of the progress bars
+const PROGRESS_REFRESH_RATE: u8 = 10;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -13,6 +89,26 @@ struct Cli {
     #[arg(short, long, value_name = "URL")]
     remote_config: Option<Url>,
 
+    /// Use the config section for this platform instead of the host's, such as
+    /// linux_aarch64_musl, to check a config meant for another machine
+    #[arg(long, global = true, value_name = "TARGET")]
+    target: Option<String>,
+
+    /// Decrypt a SOPS encrypted config with the age identities in FILE, instead of those in
+    /// $SOPS_AGE_KEY, $SOPS_AGE_KEY_FILE or ~/.config/sops/age/keys.txt
+    #[arg(long, global = true, value_name = "FILE")]
+    age_identity: Option<PathBuf>,
+
+    /// Only warn about config keys this version does not know, for a config written for a
+    /// newer workstation
+    #[arg(long, global = true)]
+    allow_unknown_keys: bool,
+
+    /// Install the packages of this profile instead of the profiles whose hosts match the
+    /// hostname, repeat it for several
+    #[arg(long = "profile", global = true, value_name = "PROFILE")]
+    profiles: Vec<String>,
+
     // /// Turn debugging information on
     // #[arg(short, long, action = clap::ArgAction::Count)]
     // debug: u8,
@@ -23,232 +119,4965 @@ struct Cli {
 #[derive(Subcommand)]
 enum Command {
     /// Set up this computer with the workstation config
-    Setup,
+    Setup(SetupArgs),
+    /// Rewrite a local config file in the newest schema version
+    MigrateConfig {
+        /// Path to the config file, found on the config search path by default
+        path: Option<PathBuf>,
+
+        /// Show the changes that would be written instead of writing them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Create a starter config for this platform, with commented example packages
+    Init {
+        /// Path to write the config to, the first entry of the config search path by default
+        path: Option<PathBuf>,
+
+        /// Replace an existing config
+        #[arg(long)]
+        force: bool,
+    },
+    /// Add a package to the config from the latest release of a GitHub repository (`owner/name`
+    /// or its link) or the URL of a file, picking the asset for this platform and the file to
+    /// install from archives
+    Add {
+        /// The repository or URL
+        source: String,
+
+        /// Name of the package, the name of the installed file by default
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Path of the file to install in the archive, when it cannot be told
+        #[arg(long)]
+        bin: Option<String>,
+
+        /// Show the change to the config instead of writing it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Show help for an error code such as E010
+    Explain {
+        /// The error code printed with the error
+        code: String,
+    },
+    /// Validate the config and check that every package URL is reachable and the lockfile
+    /// matches the config, for CI
+    Check {
+        /// Print the results as JSON
+        #[arg(long)]
+        json: bool,
+
+        /// Also download the assets of the packages that set `sha256`, `pin_hash` or
+        /// `checksum_url`, and check that they still match
+        #[arg(long)]
+        verify_checksums: bool,
+    },
+    /// Report every problem of the config without touching the network: syntax errors, unknown
+    /// keys, values of the wrong type, invalid URLs and duplicate packages, each with its line
+    /// and column
+    Validate {
+        /// Print the results as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check that this machine can install the config: writable install locations, PATH,
+    /// network access to the download hosts and working decompression, with how to fix each
+    /// problem
+    Doctor {
+        /// Print the results as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print every directory workstation uses and whether it exists
+    Paths {
+        /// Print the directories as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// List the configured packages with their source and whether each one is installed
+    List {
+        /// Also show the size, mode, modification time and hash of each installed file
+        #[arg(short, long)]
+        verbose: bool,
+
+        /// Only list the packages present on disk
+        #[arg(long)]
+        installed: bool,
+
+        /// Print the packages as JSON, with the file details of `--verbose`
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show each configured package with its recorded version and hash, and whether it is
+    /// missing, modified or out of date with the config
+    Status {
+        /// Print the packages as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show how a package is configured and whether it is installed
+    Info {
+        /// The package name
+        name: String,
+    },
+    /// Print a file from a package's archive without installing anything
+    Cat {
+        /// The package name
+        name: String,
+
+        /// Path of the file in the archive, or a glob matching exactly one file
+        #[arg(long, value_name = "PATH", required_unless_present = "list")]
+        entry: Option<String>,
+
+        /// Print every entry of the archive with its size and mode instead
+        #[arg(long, conflicts_with_all = ["entry", "out", "binary"])]
+        list: bool,
+
+        /// Write the file here instead of to stdout
+        #[arg(long, value_name = "FILE")]
+        out: Option<PathBuf>,
+
+        /// Write binary content even when stdout is a terminal
+        #[arg(long)]
+        binary: bool,
+    },
+    /// Reinstall packages whose file was deleted since `setup` installed it, report files
+    /// changed by hand and prune packages that left the config
+    Reconcile {
+        /// Also install again the packages whose file changed since it was installed
+        #[arg(long)]
+        restore_modified: bool,
+
+        /// Show what would be installed again or pruned without changing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Install the packages whose config or resolved release changed since they were installed,
+    /// leaving the others alone
+    Update {
+        /// Packages to update, all of them when none are given
+        names: Vec<String>,
+
+        /// Show what would be installed without installing anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Update all packages or none: stage everything first and undo every swap if one fails
+        #[arg(long)]
+        rollback_on_failure: bool,
+    },
+    /// Remove the files installed for packages and forget them, whether or not they are still
+    /// in the config
+    Uninstall {
+        #[arg(required = true)]
+        names: Vec<String>,
+
+        /// Print what would be removed without removing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Put back the file a package had before `setup` last replaced it. Rolling back again
+    /// returns to the newer file.
+    Rollback {
+        /// The package name
+        name: String,
+    },
+    /// Remove files workstation no longer needs: the download cache and the store entries no
+    /// installed package links to, such as releases kept for rolling back
+    Clean {
+        /// Only remove the unused store entries, keeping the download cache
+        #[arg(long, conflicts_with = "cache")]
+        store: bool,
+
+        /// Only remove the download cache, keeping the store
+        #[arg(long)]
+        cache: bool,
+    },
+    /// Check that every installed link into the store points at a file, and that the content of
+    /// every store entry still has the hash it is stored under
+    Verify,
+    /// Update the workstation block in each shell's rc file with the `shell_init` snippets of
+    /// the installed packages
+    ShellInit {
+        /// Show the changes to the rc files instead of writing them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Print the JSON Schema of workstation.toml, for editor completion and validation
+    Schema,
+    /// Manage the lockfile next to the config file
+    Lock {
+        #[command(subcommand)]
+        command: LockCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum LockCommand {
+    /// Re-lock the named packages, or every package, leaving other entries untouched
+    Update {
+        /// Packages to re-lock, all of them when none are given
+        names: Vec<String>,
+    },
+}
+
+#[derive(Args)]
+struct SetupArgs {
+    /// Print the results as a JSON array instead of the summary
+    #[arg(long, conflicts_with = "json_lines")]
+    json: bool,
+
+    /// Stream each result as a JSON line as soon as the package finishes
+    #[arg(long)]
+    json_lines: bool,
+
+    /// Install at most N packages at the same time, overrides `[workstation.install] jobs`
+    #[arg(short, long, value_name = "N")]
+    jobs: Option<usize>,
+
+    /// Also write the results as a JSON array to this file
+    #[arg(long, value_name = "FILE")]
+    report: Option<PathBuf>,
+
+    /// Re-run whenever the config file changes, installing only the changed packages
+    #[arg(long)]
+    watch: bool,
+
+    /// Only install the packages with one of these tags
+    #[arg(long, value_delimiter = ',', value_name = "TAG")]
+    tags: Vec<String>,
+
+    /// Only install these packages
+    #[arg(long, value_delimiter = ',', value_name = "NAME")]
+    only: Vec<String>,
+
+    /// Install every package but these
+    #[arg(long, value_delimiter = ',', value_name = "NAME")]
+    skip: Vec<String>,
+
+    /// Exit with an error when several packages install the same file name
+    #[arg(long)]
+    fail_on_conflict: bool,
+
+    /// Install all packages or none: stage everything first and undo every swap if one fails
+    #[arg(long)]
+    rollback_on_failure: bool,
+
+    /// Install packages that are already up to date, and run custom command packages even when
+    /// the file they create already exists
+    #[arg(long)]
+    force: bool,
+
+    /// Fail instead of installing when the lockfile is missing or out of date with the config,
+    /// and refuse assets whose content differs from the locked hash
+    #[arg(long)]
+    locked: bool,
+
+    /// Fail GitHub release packages whose release cannot be looked up, when rate limited or
+    /// offline, instead of installing the release locked in the lockfile
+    #[arg(long)]
+    strict_resolve: bool,
+
+    /// Write newline delimited JSON progress events to this open file descriptor
+    #[arg(long, value_name = "N", conflicts_with = "progress_socket")]
+    progress_fd: Option<i32>,
+
+    /// Write newline delimited JSON progress events to this Unix socket
+    #[arg(long, value_name = "PATH")]
+    progress_socket: Option<PathBuf>,
+
+    /// Write a span per package and install phase to this file as Chrome trace-event JSON, for
+    /// loading in Perfetto
+    #[arg(long, value_name = "FILE")]
+    trace_out: Option<PathBuf>,
+
+    /// Treat DIR as the file system root: install locations, home, cache and state all end up
+    /// below it, for testing a config end to end. The config itself is still read from its
+    /// usual place.
+    #[arg(long, value_name = "DIR", visible_alias = "target-dir")]
+    sandbox: Option<PathBuf>,
+
+    /// Make no network requests: assets only come from the download cache and local paths, and
+    /// packages that need the network fail
+    #[arg(long)]
+    offline: bool,
+
+    /// Remove the packages setup installed earlier that are no longer in the config, unless
+    /// their file changed since
+    #[arg(long)]
+    prune: bool,
+
+    /// Resolve every package and print what would be downloaded, extracted and written where,
+    /// without changing anything on disk
+    #[arg(long, conflicts_with_all = ["watch", "json", "json_lines", "tui"])]
+    dry_run: bool,
+
+    /// Show a full screen dashboard instead of progress bars, needs an interactive terminal
+    #[arg(long, conflicts_with_all = ["json_lines", "watch", "rollback_on_failure"])]
+    tui: bool,
 }
 
-fn main() {
+fn main() -> eyre::Result<()> {
     let cli = Cli::parse();
+    if let Some(target) = &cli.target {
+        arch::Target::init(arch::Target::parse(target)?)?;
+    }
+    if let Some(path) = cli.age_identity {
+        sops::init_identity_file(path)?;
+    }
+    if !cli.profiles.is_empty() {
+        config::use_profiles(cli.profiles);
+    }
+    if cli.allow_unknown_keys {
+        config::allow_unknown_keys();
+    }
 
     match cli.command {
-        Command::Setup => {
-            let config = match cli.remote_config {
-                Some(url) => {
-                    let string = reqwest::blocking::get(url).unwrap().text().unwrap();
-                    toml::from_str(string.as_str()).unwrap()
-                }
-                None => {
-                    let string = std::fs::read_to_string("workstation.toml").unwrap();
-                    toml::from_str(string.as_str()).unwrap()
+        Command::Setup(args) => {
+            let path = config_path()?;
+            if let Some(sandbox) = &args.sandbox {
+                enter_sandbox(sandbox)?;
+            }
+
+            if args.watch {
+                if cli.remote_config.is_some() {
+                    eyre::bail!("--watch only works with a local config file");
                 }
+                let lock_path = lock::lock_path(&path);
+                return watch::watch(&path, |changed, full| {
+                    run_setup(changed, full, &lock_path, &args)
+                });
+            }
+
+            if args.offline && cli.remote_config.is_some() {
+                return Err(download::offline_error("Reading the remote config"));
+            }
+            let source = config::read_config_source(cli.remote_config.as_ref(), &path)?;
+            let config = config::parse_config(&source)?;
+
+            run_setup(&config, &config, &lock::lock_path(&path), &args)?;
+        }
+        Command::MigrateConfig { path, dry_run } => {
+            let path = match path {
+                Some(path) => path,
+                None => config_path()?,
+            };
+            migrate_config(&path, dry_run)?
+        }
+        Command::Init { path, force } => {
+            let path = match path {
+                Some(path) => path,
+                None => config_path()?,
             };
+            init::run(&path, arch::Target::current(), force)?
+        }
+        Command::Add {
+            source,
+            name,
+            bin,
+            dry_run,
+        } => add(&config_path()?, &source, name, bin, dry_run)?,
+        Command::Explain { code } => error::explain(&code)?,
+        Command::Schema => println!(
+            "{}",
+            serde_json::to_string_pretty(&config::json_schema()).expect("schema serializes")
+        ),
+        Command::Check {
+            json,
+            verify_checksums,
+        } => {
+            let path = config_path()?;
+            let source = config::read_config_source(cli.remote_config.as_ref(), &path)?;
+            let sections = check::run(
+                &source,
+                &lock::lock_path(&path),
+                verify_checksums.then_some(&verify_digests),
+            );
 
-            setup(&config);
+            if json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&sections).expect("sections serialize")
+                );
+            } else {
+                print!("{}", check::render(&sections));
+            }
+
+            let problems = check::problem_count(&sections);
+            if problems > 0 {
+                eyre::bail!("{} problem(s) found", problems);
+            }
         }
-    }
-}
+        Command::Validate { json } => {
+            let source = config::read_config_source(cli.remote_config.as_ref(), &config_path()?)?;
+            let (sections, _) = validate::validate(&source);
 
-fn setup(config: &Config) {
-    let multi_progress = MultiProgress::new();
-    let progress_style = ProgressStyle::with_template(
-        "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}",
-    )
-    .unwrap()
-    .progress_chars("##-");
+            if json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&sections).expect("sections serialize")
+                );
+            } else {
+                print!("{}", check::render(&sections));
+            }
 
-    let mut handles = vec![];
+            let problems = check::problem_count(&sections);
+            if problems > 0 {
+                eyre::bail!("{} problem(s) found", problems);
+            }
+        }
+        Command::Doctor { json } => {
+            let source = config::read_config_source(cli.remote_config.as_ref(), &config_path()?)?;
+            let config = config::parse_config(&source)?;
+            let sections = doctor::run(&config, &install_targets(&config)?);
 
-    for package in config.linux_x86_64.packages.iter() {
-        let progress_bar = multi_progress.add(ProgressBar::new(0));
-        progress_bar.set_style(progress_style.clone());
-        progress_bar.set_message(format!("Installing {}", package.name()));
+            if json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&sections).expect("sections serialize")
+                );
+            } else {
+                print!("{}", check::render(&sections));
+            }
 
-        let loc = config.linux_x86_64.location.clone();
-        let pkg = package.clone();
-        let pb = progress_bar.clone();
-        let handle = std::thread::spawn(move || {
-            match install_package(&loc, &pkg, pb)
-                .with_context(|| format!("Installing {}", pkg.name()))
-            {
-                Ok(_) => {}
-                Err(e) => {
-                    progress_bar.finish_with_message(format!(
-                        "Error installing {}: {:?}",
-                        pkg.name(),
-                        e
-                    ));
+            let problems = check::problem_count(&sections);
+            if problems > 0 {
+                eyre::bail!("{} problem(s) found", problems);
+            }
+        }
+        Command::Paths { json } => {
+            let path = config_path()?;
+            // The directories can be shown even without a usable config
+            let config = if cli.remote_config.is_some() || path.exists() {
+                match config::read_config_source(cli.remote_config.as_ref(), &path)
+                    .and_then(|source| config::parse_config(&source))
+                {
+                    Ok(config) => Some(config),
+                    Err(e) => {
+                        eprintln!("warning: ignoring the config: {:?}", e);
+                        None
+                    }
                 }
+            } else {
+                None
+            };
+            let paths = paths::Paths::resolve(config.as_ref().map(|config| &config.paths))?;
+            paths::print(&paths, json);
+        }
+        Command::List {
+            verbose,
+            installed,
+            json,
+        } => {
+            let source = config::read_config_source(cli.remote_config.as_ref(), &config_path()?)?;
+            let config = config::parse_config(&source)?;
+            list(&config, verbose, installed, json)?;
+        }
+        Command::Status { json } => {
+            let source = config::read_config_source(cli.remote_config.as_ref(), &config_path()?)?;
+            let config = config::parse_config(&source)?;
+            status(&config, json)?;
+        }
+        Command::Info { name } => {
+            let source = config::read_config_source(cli.remote_config.as_ref(), &config_path()?)?;
+            let config = config::parse_config(&source)?;
+            info(&config, &name)?;
+        }
+        Command::Cat {
+            name,
+            entry,
+            list,
+            out,
+            binary,
+        } => {
+            let source = config::read_config_source(cli.remote_config.as_ref(), &config_path()?)?;
+            let config = config::parse_config(&source)?;
+            let package = find_package(&config, &name)?;
+            let archive = package_archive(package)?;
+            let asset = read_asset(
+                &config,
+                package,
+                archive,
+                &package.download_options(&config.download),
+            )?;
+            match entry {
+                Some(entry) if !list => cat(archive, &asset, &entry, out.as_deref(), binary)?,
+                _ => list_archive(archive, &asset)?,
             }
-        });
+        }
+        Command::Reconcile {
+            restore_modified,
+            dry_run,
+        } => {
+            let source = config::read_config_source(cli.remote_config.as_ref(), &config_path()?)?;
+            let config = config::parse_config(&source)?;
+            reconcile(&config, restore_modified, dry_run)?;
+        }
+        Command::Update {
+            names,
+            dry_run,
+            rollback_on_failure,
+        } => {
+            let source = config::read_config_source(cli.remote_config.as_ref(), &config_path()?)?;
+            let config = config::parse_config(&source)?;
+            update(&config, &names, dry_run, rollback_on_failure)?;
+        }
+        Command::Uninstall { names, dry_run } => {
+            let source = config::read_config_source(cli.remote_config.as_ref(), &config_path()?)?;
+            let config = config::parse_config(&source)?;
+            uninstall(&config, &names, dry_run, &mut std::io::stdout())?;
+        }
+        Command::Rollback { name } => {
+            let source = config::read_config_source(cli.remote_config.as_ref(), &config_path()?)?;
+            let config = config::parse_config(&source)?;
+            rollback(&config, &name, &mut std::io::stdout())?;
+        }
+        Command::Clean { store, cache } => {
+            let source = config::read_config_source(cli.remote_config.as_ref(), &config_path()?)?;
+            let config = config::parse_config(&source)?;
+            if !cache {
+                clean_store(&config)?;
+            }
+            if !store {
+                clean_cache(&config)?;
+            }
+        }
+        Command::Verify => {
+            let source = config::read_config_source(cli.remote_config.as_ref(), &config_path()?)?;
+            let config = config::parse_config(&source)?;
+            verify_store(&config, &mut std::io::stdout())?;
+        }
+        Command::ShellInit { dry_run } => {
+            let source = config::read_config_source(cli.remote_config.as_ref(), &config_path()?)?;
+            let config = config::parse_config(&source)?;
+            let changes = plan_shell_init(&config)?;
+
+            if dry_run {
+                let mut diffs: Vec<_> = changes.iter().filter_map(|c| c.diff()).collect();
+                print!(
+                    "{}",
+                    diff::render_all(&mut diffs, console::colors_enabled())
+                );
+            } else {
+                write_env(&config)?;
+                shell_init::apply(&changes)?;
+                for change in &changes {
+                    println!("Updated {}", change.path.display());
+                }
+            }
+        }
+        Command::Lock {
+            command: LockCommand::Update { names },
+        } => {
+            let path = config_path()?;
+            let source = config::read_config_source(cli.remote_config.as_ref(), &path)?;
+            let config = config::parse_config(&source)?;
+            let lock_path = lock::lock_path(&path);
+            http::init(&config.download)?;
 
-        handles.push(handle);
+            let mut lockfile =
+                lock::Lockfile::read(&lock_path)?.unwrap_or_else(lock::Lockfile::new);
+            let updated =
+                lockfile.update(&config, &names, |package| resolve_lock(&config, package))?;
+            lockfile.write(&lock_path)?;
+
+            if updated.is_empty() {
+                println!("{} is up to date", lock_path.display());
+            } else {
+                println!("Updated {}: {}", lock_path.display(), updated.join(", "));
+            }
+        }
     }
 
-    for handle in handles {
-        handle.join().unwrap();
+    Ok(())
+}
+
+/// The local config file, the first existing entry of the config search path.
+///
+/// A sandbox does not apply, the config is only read and comes from the real environment.
+fn config_path() -> eyre::Result<PathBuf> {
+    Ok(paths::Paths::resolve_in(&expand::Env::process(), None)?
+        .find_config()
+        .to_path_buf())
+}
+
+/// Expand every path below `sandbox` from now on, see `setup --sandbox`
+fn enter_sandbox(sandbox: &Path) -> eyre::Result<()> {
+    std::fs::create_dir_all(sandbox)
+        .with_context(|| format!("Creating the sandbox {}", sandbox.display()))?;
+    let root = sandbox
+        .canonicalize()
+        .with_context(|| format!("Resolving the sandbox {}", sandbox.display()))?;
+
+    let env = expand::Env::process().sandboxed(&root);
+    // Custom commands expect their home to exist
+    if let Some(home) = env.sandbox_home() {
+        std::fs::create_dir_all(&home).with_context(|| format!("Creating {}", home.display()))?;
     }
+
+    expand::Env::init(env)
 }
 
-fn install_package(
-    location: &PathBuf,
-    package: &PackageConfig,
-    pb: ProgressBar,
+/// Set up the packages of `config`. `full` is the whole config it was selected from, what
+/// `--prune` compares the manifest against, as `config` only holds the changed packages while
+/// watching.
+fn run_setup(
+    config: &Config,
+    full: &Config,
+    lock_path: &Path,
+    args: &SetupArgs,
 ) -> eyre::Result<()> {
-    match package {
-        PackageConfig::Archive { name, bin, archive } => {
-            let bytes = download_with_progress(archive, &pb)
-                .with_context(|| format!("Failed to download {}", name))?;
-            pb.finish_with_message(format!("Downloaded {}", name));
-
-            if archive.ends_with(".tar.gz") {
-                let tar = flate2::read::GzDecoder::new(std::io::Cursor::new(bytes));
-                let mut archive = tar::Archive::new(tar);
-                let entry = archive
-                    .entries()?
-                    .find(|entry| {
-                        let entry_name = entry
-                            .as_ref()
-                            .expect("entry exists")
-                            .path()
-                            .expect("entry has path");
-                        let entry_name = entry_name.to_str().expect("entry path is string");
-
-                        entry_name == bin || entry_name == format!("./{}", bin)
-                    })
-                    .ok_or(eyre::eyre!("Entry not found"))
-                    .with_context(|| "Searching for entry")??;
-
-                let data: Vec<u8> = entry.bytes().map(|b| b.unwrap()).collect();
-
-                install(location, name, data.as_ref()).with_context(|| format!("Installing"))?;
-            } else if archive.ends_with(".zip") {
-                let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes))?;
-                let mut entry = archive.by_name(bin)?;
-
-                let mut buff = vec![];
-                entry.read_to_end(&mut buff)?;
-
-                install(location, name, buff.as_ref()).with_context(|| "Installing")?;
-            } else {
-                eyre::bail!("Unsupported archive format");
+    if args.tui && !tui::is_supported() {
+        eyre::bail!(
+            "--tui needs an interactive terminal, run without it to get plain progress bars"
+        );
+    }
+    http::init(&config.download)?;
+    check_lockfile(config, lock_path, args.locked)?;
+    let lockfile = lock::Lockfile::read(lock_path)?;
+    // Only entries locked from the config as it is now, others may be another release entirely
+    let fallback: Vec<lock::LockEntry> = match &lockfile {
+        Some(lockfile) if !args.strict_resolve => lockfile
+            .entries()?
+            .into_iter()
+            .filter(|entry| {
+                config.platform.packages.iter().any(|package| {
+                    package.name() == entry.name && lock::fields_hash(package) == entry.fields_hash
+                })
+            })
+            .collect(),
+        _ => vec![],
+    };
+    let pinned;
+    let config = match &lockfile {
+        Some(lockfile) if args.locked => {
+            pinned = lockfile.pin(config)?;
+            &pinned
+        }
+        _ => config,
+    };
+    let limited;
+    let config = match args.jobs {
+        Some(jobs) => {
+            limited = Config {
+                install: config::InstallConfig {
+                    jobs: Some(jobs),
+                    ..config.install.clone()
+                },
+                ..config.clone()
+            };
+            &limited
+        }
+        None => config,
+    };
+    let selected;
+    let config = if args.tags.is_empty() && args.only.is_empty() && args.skip.is_empty() {
+        config
+    } else {
+        selected = config
+            .with_names(&args.only, &args.skip)
+            .with_tags(&args.tags);
+        // While watching, the config only holds the packages that changed
+        if !args.watch {
+            for name in args.only.iter().chain(&args.skip) {
+                find_package(config, name)?;
+            }
+            if selected.platform.packages.is_empty() {
+                eyre::bail!(
+                    "No package of section {} is left to install with --only, --skip and --tags",
+                    config.section
+                );
             }
         }
-        PackageConfig::Binary { name, url } => {
-            let bytes = download_with_progress(url, &pb).with_context(|| "Downloading")?;
-            pb.finish_with_message(format!("Downloaded {}", name));
-            install(location, name, bytes.as_ref()).with_context(|| "Installing")?;
+        &selected
+    };
+    if args.dry_run {
+        let prune = if args.prune { prunable(full)? } else { vec![] };
+        let state = paths::Paths::resolve(Some(&config.paths))?.state.path;
+        let written = dotfiles::Written::load(&state);
+        print!(
+            "{}",
+            dotfiles::render_preview(&dotfile_states(config)?, &written)
+        );
+        match rustup::plan(&config.rustup) {
+            Ok(steps) => print!("{}", rustup::render_preview(&steps)),
+            Err(e) => println!("rustup\n  fail     {:#}", e),
         }
+        print!(
+            "{}",
+            preview::render(&preview_setup(config, args.force)?, &prune)
+        );
+        return Ok(());
+    }
+
+    let reporter = if args.json_lines {
+        Reporter::with_stream(Box::new(std::io::stdout()))
+    } else {
+        Reporter::new()
+    };
+    let reporter = Arc::new(reporter);
+
+    run_setup_hooks(config, "pre_setup", &config.hooks.pre_setup)?;
+    write_env(config)?;
+
+    let dashboard = args.tui.then(|| Arc::new(tui::Dashboard::new()));
+    let events = events::open(args.progress_fd, args.progress_socket.as_deref())?;
+    let tracer = args.trace_out.as_ref().map(|_| trace::Tracer::new());
+    setup(
+        config,
+        &reporter,
+        SetupOptions {
+            rollback_on_failure: args.rollback_on_failure,
+            force: args.force,
+            offline: args.offline,
+            dashboard,
+            events: events.clone(),
+            tracer: tracer.clone(),
+            only: None,
+            locked: &fallback,
+        },
+    )?;
+    if let (Some(tracer), Some(path)) = (&tracer, &args.trace_out) {
+        tracer.write(path)?;
+    }
+
+    let reports = Arc::into_inner(reporter)
+        .expect("all workers finished")
+        .finish();
+    if let Some(events) = events {
+        let count = |outcome| reports.iter().filter(|r| r.outcome == outcome).count();
+        events.close(
+            count(report::Outcome::Installed),
+            count(report::Outcome::Failed),
+            count(report::Outcome::RolledBack),
+            count(report::Outcome::Skipped) + count(report::Outcome::Unchanged),
+        );
+    }
+    let path = conflicts::path_directories();
+    let conflicts = conflicts::find_conflicts(&install_targets(config)?, &path);
+
+    if args.json {
+        println!("{}", report::render_json(&reports));
+    } else if !args.json_lines {
+        print!("{}", report::render_summary(&reports));
+    }
+    if !conflicts.is_empty() {
+        eprint!("{}", conflicts::render(&conflicts, &path));
+    }
+    let shadow_targets: Vec<_> = install_targets(config)?
+        .into_iter()
+        .filter(|target| {
+            config
+                .platform
+                .packages
+                .iter()
+                .any(|p| p.name() == target.package && !p.options().allow_shadowing)
+        })
+        .collect();
+    let shadows = conflicts::find_shadows(&shadow_targets, &path, conflicts::system_owner);
+    if !shadows.is_empty() {
+        eprint!("{}", conflicts::render_shadows(&shadows));
+    }
+    if let Some(path) = &args.report {
+        report::write_report_file(path, &reports)?;
+    }
+    if args.prune {
+        // Prune against every package, not only the selected or changed ones
+        prune(full, args.json || args.json_lines)?;
+    }
+    let out: &mut dyn Write = if args.json || args.json_lines {
+        &mut std::io::stderr()
+    } else {
+        &mut std::io::stdout()
+    };
+    let rustup_failed = sync_rustup(config, args.offline, out)?;
+    let unlinked = link_dotfiles(config, out)?;
+    run_setup_hooks(config, "post_setup", &config.hooks.post_setup)?;
+
+    let failed = reports
+        .iter()
+        .filter(|report| report.outcome == report::Outcome::Failed)
+        .count();
+    if failed > 0 {
+        eyre::bail!("{} package(s) could not be installed", failed);
+    }
+    if rustup_failed > 0 {
+        eyre::bail!("{} rustup change(s) failed", rustup_failed);
+    }
+    if unlinked > 0 {
+        eyre::bail!("{} dotfile(s) could not be linked", unlinked);
+    }
+
+    if args.fail_on_conflict && !conflicts.is_empty() {
+        eyre::bail!(
+            "{} file name(s) are installed by more than one package",
+            conflicts.len()
+        );
     }
 
     Ok(())
 }
 
-fn download_with_progress(url: &str, pb: &ProgressBar) -> eyre::Result<Vec<u8>> {
-    let client = reqwest::blocking::Client::new();
-    let response = client.get(url).send()?;
+/// Download the asset of `package` to record what it resolves to. The asset is kept in the cache,
+/// where `setup --locked` finds it.
+fn resolve_lock(config: &Config, package: &PackageConfig) -> eyre::Result<lock::Resolved> {
+    // The lockfile records the public link even when the asset comes through the API
+    let (url, from, tag) = match package {
+        PackageConfig::Command { .. }
+        | PackageConfig::GitRepo { .. }
+        | PackageConfig::Cargo { .. }
+        | PackageConfig::Go { .. }
+        | PackageConfig::Pipx { .. }
+        | PackageConfig::Npm { .. }
+        | PackageConfig::Flatpak { .. }
+        | PackageConfig::Snap { .. }
+        | PackageConfig::Distro { .. }
+        | PackageConfig::System { .. } => return Ok(lock::Resolved::default()),
+        PackageConfig::GithubRelease {
+            repo,
+            version,
+            asset_pattern,
+            ..
+        } => {
+            let token = package.download_options(&config.download).github_token()?;
+            let (asset, tag) = github::resolve(
+                repo,
+                version.as_deref(),
+                asset_pattern.as_deref(),
+                token.as_deref(),
+            )?;
+            let from = asset.download_url(token.is_some()).to_string();
+            (asset.browser_download_url, from, Some(tag))
+        }
+        _ => {
+            let url = package.url().expect("asset packages have a URL");
+            (url.to_string(), url.to_string(), None)
+        }
+    };
+    let asset = read_asset(
+        config,
+        package,
+        &from,
+        &package.download_options(&config.download),
+    )
+    .with_context(|| format!("Locking {}", package.name()))?;
 
-    if !response.status().is_success() {
-        eyre::bail!("Failed to download {}", url);
+    let paths = paths::Paths::resolve(Some(&config.paths))?;
+    if let Err(e) = cache::Cache::new(&paths.cache.path).put(&asset) {
+        eprintln!("warning: could not cache {}: {:?}", url, e);
     }
+    let archive = archive::is_supported(&url).then_some(&asset);
+    let version = tag.or_else(|| version::infer(&url, archive).map(|inferred| inferred.version));
+    Ok(lock::Resolved {
+        url: Some(sops::redact(&url)),
+        version,
+        hash: Some(asset.hash()?),
+    })
+}
 
-    let total_length = response
-        .content_length()
-        .ok_or(eyre::eyre!("Failed to get content length"))?;
+/// Compare the lockfile with the config, a stale lockfile is fatal only with `--locked`
+fn check_lockfile(config: &Config, lock_path: &Path, locked: bool) -> eyre::Result<()> {
+    let Some(lockfile) = lock::Lockfile::read(lock_path)? else {
+        if locked {
+            eyre::bail!(
+                "--locked needs a lockfile at {}, run `workstation lock update` to create it",
+                lock_path.display()
+            );
+        }
+        return Ok(());
+    };
 
-    let mut buf = Vec::with_capacity(total_length as usize);
-    let mut downloaded = 0;
+    let problems = lock::stale(config, &lockfile)?;
+    if problems.is_empty() {
+        return Ok(());
+    }
+    let error = lock::stale_error(lock_path, &problems);
+    if locked {
+        return Err(error);
+    }
+    eprintln!("warning: {}", error);
 
-    pb.set_length(total_length);
+    Ok(())
+}
 
-    for chunk in response.bytes().into_iter() {
-        buf.extend_from_slice(&chunk);
-        downloaded += chunk.len() as u64;
-        pb.set_position(downloaded);
+/// Every file the config installs, with the install location expanded
+fn install_targets(config: &Config) -> eyre::Result<Vec<conflicts::Target>> {
+    let mut targets = vec![];
+    for package in &config.platform.packages {
+        if matches!(
+            package,
+            PackageConfig::Directory { .. }
+                | PackageConfig::GitRepo { .. }
+                | PackageConfig::Pipx { .. }
+                | PackageConfig::Npm { .. }
+                | PackageConfig::Flatpak { .. }
+                | PackageConfig::Snap { .. }
+                | PackageConfig::Distro { .. }
+                | PackageConfig::System { .. }
+        ) {
+            continue;
+        }
+        let mut paths = vec![package_path(
+            package.location(&config.platform.location),
+            package,
+        )?];
+        for file in package.file_names().into_iter().skip(1) {
+            paths.push(get_install_path(
+                package.location(&config.platform.location),
+                file,
+            )?);
+        }
+        for path in paths {
+            targets.push(conflicts::Target {
+                package: package.name().to_string(),
+                directory: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+                file_name: path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+            });
+        }
     }
-
-    Ok(buf)
+    Ok(targets)
 }
 
-fn get_install_path(location: &PathBuf, name: &str) -> eyre::Result<PathBuf> {
-    let path = location.join(name);
-    let path = expanduser::expanduser(path.to_str().expect("string path"))?;
-    Ok(PathBuf::from(path))
+#[derive(serde::Serialize)]
+struct ListEntry {
+    name: String,
+    /// See [`PackageConfig::kind`]
+    kind: &'static str,
+    /// The URL it was installed from, or what the config asks for when it is not installed
+    source: String,
+    path: PathBuf,
+    installed: bool,
+    #[serde(flatten)]
+    stat: Option<stat::FileStat>,
+    /// Guessed at install time, see [`version::infer`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inferred_version: Option<version::Inferred>,
 }
 
-fn install(location: &PathBuf, name: &str, data: &[u8]) -> eyre::Result<()> {
-    let path = get_install_path(location, name)?;
+fn list(config: &Config, verbose: bool, only_installed: bool, json: bool) -> eyre::Result<()> {
+    let paths = paths::Paths::resolve(Some(&config.paths))?;
+    let mut cache = stat::StatCache::load(&paths.state.path);
+    let versions = version::Versions::load(&paths.state.path);
+    let manifest = manifest::Manifest::load(&paths.state.path)?;
+
+    let mut entries = vec![];
+    for package in &config.platform.packages {
+        let path = package_path(package.location(&config.platform.location), package)?;
+        let stat = if (verbose || json) && package.is_single_file() {
+            cache.stat(&path)?
+        } else {
+            None
+        };
+        let installed = stat.is_some() || is_present(&path, package)?;
+        if only_installed && !installed {
+            continue;
+        }
+        // A release resolves to an asset URL only when it is installed
+        let source = manifest
+            .get(package.name())
+            .filter(|recorded| installed && recorded.path == path)
+            .and_then(|recorded| recorded.source)
+            .unwrap_or_else(|| sops::redact(&package.source()));
+        entries.push(ListEntry {
+            name: package.name().to_string(),
+            kind: package.kind(),
+            source,
+            installed,
+            path,
+            stat,
+            inferred_version: versions.get(package.name()).filter(|_| installed),
+        });
+    }
+    if let Err(e) = cache.save() {
+        eprintln!("warning: could not save the file hashes: {:?}", e);
+    }
 
-    std::fs::write(&path, data)?;
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&entries).expect("entries serialize")
+        );
+        return Ok(());
+    }
 
-    // Add executable permissions
-    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+    let width = entries.iter().map(|e| e.name.len()).max().unwrap_or(0);
+    let version = |entry: &ListEntry| match &entry.inferred_version {
+        Some(inferred) => format!("{} (inferred)", inferred.version),
+        None => "-".to_string(),
+    };
+    let version_width = entries.iter().map(|e| version(e).len()).max().unwrap_or(0);
+    let source_width = entries.iter().map(|e| e.source.len()).max().unwrap_or(0);
+    for entry in &entries {
+        match (&entry.stat, verbose) {
+            (Some(stat), true) => println!(
+                "{:width$}  {:>10}  {:04o}  {}  {}  {:version_width$}  {}",
+                entry.name,
+                stat.size,
+                stat.mode,
+                stat.modified_utc(),
+                stat.short_hash(),
+                version(entry),
+                entry.path.display()
+            ),
+            (None, true) => println!(
+                "{:width$}  {:>10}  {:4}  {:16}  {:12}  {:version_width$}  {} (missing)",
+                entry.name,
+                "-",
+                "-",
+                "-",
+                "-",
+                "-",
+                entry.path.display()
+            ),
+            _ => println!(
+                "{:width$}  {:9}  {:9}  {:version_width$}  {:source_width$}  {}",
+                entry.name,
+                entry.kind,
+                if entry.installed {
+                    "installed"
+                } else {
+                    "missing"
+                },
+                version(entry),
+                entry.source,
+                entry.path.display()
+            ),
+        }
+    }
 
     Ok(())
 }
 
-#[derive(Deserialize, Debug)]
-struct Config {
-    linux_x86_64: ArchConfig,
+fn status(config: &Config, json: bool) -> eyre::Result<()> {
+    let paths = paths::Paths::resolve(Some(&config.paths))?;
+    let mut hashes = stat::StatCache::load(&paths.state.path);
+    let statuses = status::status(
+        &config.platform.packages,
+        &manifest::Manifest::load(&paths.state.path)?,
+        &version::Versions::load(&paths.state.path),
+        &mut hashes,
+        &config.install.ignore_set()?,
+        |package| package_path(package.location(&config.platform.location), package),
+    )?;
+    if let Err(e) = hashes.save() {
+        eprintln!("warning: file hashes were not cached: {:?}", e);
+    }
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&statuses).expect("statuses serialize")
+        );
+    } else {
+        print!("{}", status::render(&statuses));
+        let links = dotfile_states(config)?;
+        if !links.is_empty() {
+            print!("{}", dotfiles::render(&links));
+        }
+    }
+    Ok(())
 }
 
-#[derive(Deserialize, Debug)]
-struct ArchConfig {
-    location: PathBuf,
-    packages: Vec<PackageConfig>,
+fn info(config: &Config, name: &str) -> eyre::Result<()> {
+    let package = find_package(config, name)?;
+
+    let path = package_path(package.location(&config.platform.location), package)?;
+    let installed = if is_present(&path, package)? {
+        "installed"
+    } else {
+        "missing"
+    };
+    println!("{:10} {}", "name", name);
+    println!("{:10} {}", "source", package.source());
+    for mirror in &package.options().mirrors {
+        println!("{:10} {}", "mirror", sops::redact(mirror));
+    }
+    if let Some(signature) = &package.options().signature {
+        println!("{:10} {}", "signature", sops::redact(signature));
+    }
+    println!("{:10} {} ({})", "path", path.display(), installed);
+    if path.exists() {
+        let paths = paths::Paths::resolve(Some(&config.paths))?;
+        if let Some(inferred) = version::Versions::load(&paths.state.path).get(name) {
+            println!("{:10} {}", "version", inferred);
+        }
+        let installed = manifest::Manifest::load(&paths.state.path)?.get(name);
+        if let Some(at) = installed.and_then(|installed| installed.installed_at) {
+            println!("{:10} {} UTC", "installed", stat::format_utc(at));
+        }
+    }
+
+    if let Some(pin) = &package.options().pin_hash {
+        let cache = cache::Cache::new(&paths::Paths::resolve(Some(&config.paths))?.cache.path);
+        let cached = if cache.contains(pin) {
+            "satisfied by the cache"
+        } else {
+            "not cached, the next install downloads and verifies it"
+        };
+        println!("{:10} {} ({})", "pin", pin, cached);
+    }
+
+    Ok(())
 }
 
-#[derive(Deserialize, Debug, Clone)]
-#[serde(untagged)]
-enum PackageConfig {
-    Archive {
-        name: String,
-        bin: String,
-        archive: String,
-    },
-    Binary {
-        name: String,
-        url: String,
-    },
+fn find_package<'a>(config: &'a Config, name: &str) -> eyre::Result<&'a PackageConfig> {
+    config
+        .platform
+        .packages
+        .iter()
+        .find(|package| package.name() == name)
+        .ok_or_else(|| eyre::eyre!("No package named {} in the config", name))
 }
 
-impl PackageConfig {
-    pub fn name(&self) -> &str {
-        match self {
-            PackageConfig::Archive { name, .. } => name,
-            PackageConfig::Binary { name, .. } => name,
+fn package_archive(package: &PackageConfig) -> eyre::Result<&str> {
+    match package {
+        PackageConfig::Archive { archive, .. } | PackageConfig::AutoArchArchive { archive, .. } => {
+            Ok(archive)
         }
+        _ => eyre::bail!("{} is not installed from an archive", package.name()),
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// The asset of `package` at `source`, from disk, the cache or a download with `download`.
+/// Nothing is installed and no state is recorded.
+fn read_asset(
+    config: &Config,
+    package: &PackageConfig,
+    source: &str,
+    download: &download::DownloadOptions,
+) -> eyre::Result<asset::Asset> {
+    let name = package.name();
+    let source = package.expand_url(source)?;
+    if let Some(path) = local::local_path(&source) {
+        let owner = format!("package {}", name);
+        let path = expand::expand_source_path(path, expand::Field::new("archive", &owner))?;
+        return asset::Asset::open(&path);
+    }
 
-    #[test]
-    fn test_get_install_path() {
-        let location = PathBuf::from("~/.local/bin");
-        let name = "test";
-        let expected = PathBuf::from("/Users/jurajpetras/.local/bin/test");
+    http::init(&config.download)?;
+    let paths = paths::Paths::resolve(Some(&config.paths))?;
+    let pb = ProgressBar::with_draw_target(None, ProgressDrawTarget::stderr());
+    pb.set_style(
+        ProgressStyle::with_template("{bar:40.cyan/blue} {bytes:>10}/{total_bytes:10} {msg}")
+            .unwrap()
+            .progress_chars("##-"),
+    );
+    pb.set_message(format!("Downloading {}", name));
+    let (asset, _) = fetch_asset(
+        &source,
+        &package.expanded_options()?,
+        download,
+        &cache::Cache::new(&paths.cache.path),
+        &pb,
+    )
+    .with_context(|| format!("Failed to download {}", name))?;
+    pb.finish_and_clear();
 
-        let path = get_install_path(&location, name);
+    Ok(asset)
+}
 
-        assert_eq!(path.unwrap(), expected);
+/// Print the entries of an archive with their mode and size
+fn list_archive(archive: &str, asset: &asset::Asset) -> eyre::Result<()> {
+    for entry in archive::list_entries(archive, asset.reader()?)? {
+        let mode = match entry.mode {
+            Some(mode) => format!("{:04o}", mode),
+            None => "----".to_string(),
+        };
+        let path = match entry.is_dir && !entry.path.ends_with('/') {
+            true => format!("{}/", entry.path),
+            false => entry.path,
+        };
+        println!("{}  {:>10}  {}", mode, entry.size, path);
     }
+    Ok(())
+}
 
-    #[test]
-    fn text_eza_archive() {
-        // Read file from disk
-        let bytes = std::fs::read("eza_archive.tar.gz").unwrap();
-        let archive = flate2::read::GzDecoder::new(std::io::Cursor::new(bytes));
-        let mut archive = tar::Archive::new(archive);
-        for entry in archive.entries().unwrap() {
-            let entry = entry.unwrap();
-            println!("{:?}", entry.path().unwrap());
+/// Whether `data` looks binary, by git's rule: a NUL byte within the first 8000 bytes
+fn is_binary(data: &[u8]) -> bool {
+    data.iter().take(8000).any(|&byte| byte == 0)
+}
+
+/// Write the archive entry matching `entry` to `out`, or to stdout
+fn cat(
+    archive: &str,
+    asset: &asset::Asset,
+    entry: &str,
+    out: Option<&Path>,
+    binary: bool,
+) -> eyre::Result<()> {
+    use std::io::{IsTerminal, Write};
+
+    let path = archive::find_single(archive, asset.reader()?, entry)?;
+    if let Some(out) = out {
+        let file =
+            std::fs::File::create(out).with_context(|| format!("Writing {}", out.display()))?;
+        return archive::copy_file(archive, asset.reader()?, &path, file);
+    }
+
+    let mut stdout = std::io::stdout().lock();
+    if !binary && stdout.is_terminal() {
+        let start = archive::read_files(archive, asset.reader()?, |name| name == path, 8000)?;
+        if start.iter().any(|(_, data)| is_binary(data)) {
+            eyre::bail!(
+                "{} is a binary file, pass --binary to print it to the terminal anyway or --out FILE to save it",
+                path
+            );
         }
-        let entry = archive
-            .entries()
-            .unwrap()
-            .find(|entry| entry.as_ref().unwrap().path().unwrap().to_str().unwrap() == "eza");
+    }
+    archive::copy_file(archive, asset.reader()?, &path, &mut stdout)?;
+    stdout.flush()?;
 
-        assert!(entry.is_some());
+    Ok(())
+}
+
+/// Install again what was deleted since the last `setup` and prune what left the config, see
+/// [`reconcile::plan`]
+fn reconcile(config: &Config, restore_modified: bool, dry_run: bool) -> eyre::Result<()> {
+    let paths = paths::Paths::resolve(Some(&config.paths))?;
+    let manifest = manifest::Manifest::load(&paths.state.path)?;
+    let mut hashes = stat::StatCache::load(&paths.state.path);
+    let plan = reconcile::plan(
+        &config.platform.packages,
+        &manifest,
+        &mut hashes,
+        &config.install.ignore_set()?,
+        |package| package_path(package.location(&config.platform.location), package),
+        restore_modified,
+    )?;
+    if let Err(e) = hashes.save() {
+        eprintln!("warning: file hashes were not cached: {:?}", e);
+    }
+
+    let reinstall = plan.reinstall();
+    let mut failed = vec![];
+    if !dry_run && !reinstall.is_empty() {
+        let reporter = Arc::new(Reporter::new());
+        setup(
+            config,
+            &reporter,
+            SetupOptions {
+                only: Some(&reinstall),
+                ..Default::default()
+            },
+        )?;
+        let reports = Arc::into_inner(reporter)
+            .expect("all workers finished")
+            .finish();
+        failed = reports
+            .into_iter()
+            .filter(|report| report.outcome == report::Outcome::Failed)
+            .map(|report| (report.name, report.error.unwrap_or_default()))
+            .collect();
+    }
+    if !dry_run && !plan.pruned.is_empty() {
+        uninstall(config, &plan.pruned, false, &mut std::io::stdout())?;
+    }
+    print!("{}", reconcile::render(&plan, &failed, dry_run));
+
+    if !failed.is_empty() {
+        eyre::bail!("{} package(s) could not be installed again", failed.len());
+    }
+    Ok(())
+}
+
+fn update(
+    config: &Config,
+    names: &[String],
+    dry_run: bool,
+    rollback_on_failure: bool,
+) -> eyre::Result<()> {
+    for name in names {
+        find_package(config, name)?;
+    }
+    http::init(&config.download)?;
+    let selected: Vec<_> = config
+        .platform
+        .packages
+        .iter()
+        .filter(|package| names.is_empty() || names.iter().any(|name| name == package.name()))
+        .cloned()
+        .collect();
+    let paths = paths::Paths::resolve(Some(&config.paths))?;
+    let manifest = manifest::Manifest::load(&paths.state.path)?;
+    let plan = update::plan(
+        &selected,
+        &manifest,
+        &config.install.ignore_set()?,
+        |package| package_path(package.location(&config.platform.location), package),
+        |package| resolve_source(config, package),
+    )?;
+    print!("{}", update::render(&plan, dry_run));
+
+    let install = plan.install();
+    let mut failed = plan.failed.len();
+    if !dry_run && !install.is_empty() {
+        let reporter = Arc::new(Reporter::new());
+        setup(
+            config,
+            &reporter,
+            SetupOptions {
+                only: Some(&install),
+                rollback_on_failure,
+                ..Default::default()
+            },
+        )?;
+        let reports = Arc::into_inner(reporter)
+            .expect("all workers finished")
+            .finish();
+        print!("{}", report::render_summary(&reports));
+        failed += reports
+            .iter()
+            .filter(|report| report.outcome == report::Outcome::Failed)
+            .count();
+    }
+
+    if failed > 0 {
+        eyre::bail!("{} package(s) could not be updated", failed);
+    }
+    Ok(())
+}
+
+/// The asset `package` would be installed from now, as recorded in the manifest
+fn resolve_source(config: &Config, package: &PackageConfig) -> eyre::Result<String> {
+    let url = match package {
+        PackageConfig::GithubRelease {
+            repo,
+            version,
+            asset_pattern,
+            ..
+        } => {
+            let token = package.download_options(&config.download).github_token()?;
+            github::resolve(
+                repo,
+                version.as_deref(),
+                asset_pattern.as_deref(),
+                token.as_deref(),
+            )?
+            .0
+            .browser_download_url
+        }
+        _ => package
+            .url()
+            .expect("asset packages have a URL")
+            .to_string(),
+    };
+    Ok(sops::redact(&url))
+}
+
+/// Uninstall the packages in the manifest that are no longer configured, see [`prune::plan`].
+/// With `json` the results go to stdout, so what was removed goes to stderr.
+fn prune(config: &Config, json: bool) -> eyre::Result<()> {
+    let names: Vec<String> = prunable(config)?
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    if names.is_empty() {
+        return Ok(());
+    }
+    if json {
+        uninstall(config, &names, false, &mut std::io::stderr())
+    } else {
+        uninstall(config, &names, false, &mut std::io::stdout())
+    }
+}
+
+/// The packages `prune` removes with their recorded path, warning about those it keeps
+fn prunable(config: &Config) -> eyre::Result<Vec<(String, PathBuf)>> {
+    let paths = paths::Paths::resolve(Some(&config.paths))?;
+    let manifest = manifest::Manifest::load(&paths.state.path)?;
+    let plan = prune::plan(
+        &config.platform.packages,
+        &manifest,
+        &mut stat::StatCache::load(&paths.state.path),
+        &config.install.ignore_set()?,
+    )?;
+
+    for (name, reason) in &plan.kept {
+        eprintln!("warning: not pruning {}: {}", name, reason);
+    }
+    Ok(plan
+        .remove
+        .into_iter()
+        .filter_map(|name| Some((name.clone(), manifest.get(&name)?.path)))
+        .collect())
+}
+
+/// What `setup` would do for each package. Nothing is downloaded or written, but GitHub
+/// releases are looked up to know the asset they resolve to.
+fn preview_setup(config: &Config, force: bool) -> eyre::Result<Vec<preview::Preview>> {
+    let ignore = config.install.ignore_set()?;
+    let paths = paths::Paths::resolve(Some(&config.paths))?;
+    let cache = cache::Cache::new(&paths.cache.path);
+    let extractions = local::Extractions::load(&paths.state.path);
+    let manifest = manifest::Manifest::load(&paths.state.path)?;
+
+    Ok(config
+        .platform
+        .packages
+        .iter()
+        .map(|package| preview::Preview {
+            name: package.name().to_string(),
+            action: preview_package(
+                config,
+                package,
+                &ignore,
+                &cache,
+                &extractions,
+                &manifest,
+                force,
+            )
+            .unwrap_or_else(|e| preview::Action::Failed(format!("{:#}", e))),
+        })
+        .collect())
+}
+
+fn preview_package(
+    config: &Config,
+    package: &PackageConfig,
+    ignore: &ignore::IgnoreSet,
+    cache: &cache::Cache,
+    extractions: &local::Extractions,
+    manifest: &manifest::Manifest,
+    force: bool,
+) -> eyre::Result<preview::Action> {
+    use preview::{Action, Fetch};
+
+    let name = package.name();
+    if let Some(pattern) = ignore.matching_pattern(Path::new(name)) {
+        return Ok(Action::Skip(format!(
+            "matches the install ignore pattern {:?}",
+            pattern
+        )));
+    }
+    let path = package_path(package.location(&config.platform.location), package)?;
+    let replaces = path.symlink_metadata().is_ok();
+    let mut source = package.url().map(str::to_string);
+    let owner = format!("package {}", name);
+    let cached = package
+        .options()
+        .pin_hash
+        .as_ref()
+        .is_some_and(|pin| cache.contains(pin));
+    let fetch = |url: &str| -> eyre::Result<Fetch> {
+        Ok(match local::local_path(&package.expand_url(url)?) {
+            Some(archive) => {
+                let archive =
+                    expand::expand_source_path(archive, expand::Field::new("archive", &owner))?;
+                Fetch::Read(archive)
+            }
+            None => Fetch::Download {
+                url: sops::redact(url),
+                cached,
+            },
+        })
+    };
+
+    let (fetch, extract) = match package {
+        PackageConfig::Archive {
+            bin,
+            archive,
+            files,
+            ..
+        } => {
+            let mut extract = expand::expand_vars(bin, expand::Field::new("bin", &owner))?;
+            for file in files {
+                extract.push_str(&format!(", {} as {}", file.entry, file.dest_name));
+            }
+            (fetch(archive)?, Some(extract))
+        }
+        PackageConfig::AutoArchArchive {
+            auto_arch_bin,
+            archive,
+            ..
+        } => (
+            fetch(archive)?,
+            Some(format!(
+                "{} (the match for {})",
+                auto_arch_bin,
+                arch::Target::current().platform()
+            )),
+        ),
+        PackageConfig::Directory {
+            archive,
+            strip_components,
+            ..
+        } => {
+            if !force
+                && local::local_path(&package.expand_url(archive)?).is_none()
+                && installed_from(manifest, package, &path, archive)
+            {
+                return Ok(Action::Skip("up to date".to_string()));
+            }
+            return Ok(Action::Install {
+                fetch: fetch(archive)?,
+                extract: Some(format!(
+                    "every file, stripping {} path component(s)",
+                    strip_components
+                )),
+                path,
+                replaces,
+                store: false,
+                post_install: package.options().post_install.clone(),
+            });
+        }
+        PackageConfig::Binary { url, .. } => (fetch(url)?, None),
+        PackageConfig::AppImage { appimage, .. } => (
+            fetch(appimage)?,
+            Some("a desktop entry and icon for launchers".to_string()),
+        ),
+        PackageConfig::GithubRelease {
+            repo,
+            version,
+            asset_pattern,
+            bin,
+            ..
+        } => {
+            let token = package.download_options(&config.download).github_token()?;
+            let (asset, _) = github::resolve(
+                repo,
+                version.as_deref(),
+                asset_pattern.as_deref(),
+                token.as_deref(),
+            )?;
+            let extract = archive::is_supported(&asset.name)
+                .then(|| bin.clone().unwrap_or_else(|| format!("**/{}", name)));
+            source = Some(asset.browser_download_url.clone());
+            (fetch(&asset.browser_download_url)?, extract)
+        }
+        PackageConfig::System { .. } => {
+            let Some((manager, system_name)) = system::resolve(package) else {
+                return Ok(Action::Skip(
+                    "this machine has none of the package managers it names".to_string(),
+                ));
+            };
+            if manager.is_installed(system_name)? {
+                return Ok(Action::Skip(format!("installed with {}", manager)));
+            }
+            return Ok(Action::Install {
+                fetch: Fetch::System {
+                    manager: manager.to_string(),
+                    name: system_name.to_string(),
+                },
+                extract: None,
+                path,
+                replaces: false,
+                store: false,
+                post_install: package.options().post_install.clone(),
+            });
+        }
+        PackageConfig::Distro { version, .. } => {
+            let Some((format, url)) = distro::resolve(package) else {
+                return Ok(Action::Skip(
+                    "this machine has neither dpkg nor rpm for its files".to_string(),
+                ));
+            };
+            let installed = format.installed_version(distro::package_name(package))?;
+            if let (false, Some(installed), Some(version)) = (force, &installed, version) {
+                if distro::is_version(installed, version) {
+                    return Ok(Action::Skip(format!("installed with {}", format)));
+                }
+            }
+            return Ok(Action::Install {
+                fetch: Fetch::System {
+                    manager: format.to_string(),
+                    name: sops::redact(url),
+                },
+                extract: None,
+                path,
+                replaces: false,
+                store: false,
+                post_install: package.options().post_install.clone(),
+            });
+        }
+        PackageConfig::Pipx { .. }
+        | PackageConfig::Npm { .. }
+        | PackageConfig::Flatpak { .. }
+        | PackageConfig::Snap { .. } => {
+            let (installer, tool, version) = global::Installer::of(package)
+                .expect("packages of other package managers have an installer");
+            if !force && global::is_current(package)? {
+                return Ok(Action::Skip(format!("installed with {}", installer)));
+            }
+            return Ok(Action::Install {
+                fetch: Fetch::System {
+                    manager: installer.to_string(),
+                    name: match version {
+                        Some(version) => format!("{} {}", tool, version),
+                        None => tool.to_string(),
+                    },
+                },
+                extract: None,
+                path,
+                replaces: false,
+                store: false,
+                post_install: package.options().post_install.clone(),
+            });
+        }
+        PackageConfig::Cargo { .. } | PackageConfig::Go { .. } => {
+            source = Some(package.source());
+            let with = match package {
+                PackageConfig::Cargo { .. } => "cargo",
+                _ => "go",
+            };
+            let fetch = Fetch::Build {
+                source: package.source(),
+                with,
+            };
+            (fetch, None)
+        }
+        PackageConfig::GitRepo { git, rev, .. } => {
+            return Ok(Action::Install {
+                fetch: Fetch::Git {
+                    url: git.clone(),
+                    rev: rev.clone(),
+                },
+                extract: None,
+                path,
+                replaces,
+                store: false,
+                post_install: package.options().post_install.clone(),
+            });
+        }
+        PackageConfig::Command { command, .. } => {
+            if path.exists() && !force {
+                return Ok(Action::Skip(format!("{} already exists", path.display())));
+            }
+            return Ok(Action::Install {
+                fetch: Fetch::Run(command.to_string()),
+                extract: None,
+                path,
+                replaces,
+                store: false,
+                post_install: package.options().post_install.clone(),
+            });
+        }
+    };
+    if let Fetch::Read(archive) = &fetch {
+        if !force && extractions.unchanged(name, archive, &path)? {
+            return Ok(Action::Skip(format!(
+                "{} did not change since it was extracted",
+                archive.display()
+            )));
+        }
+    }
+    if let (Fetch::Download { .. } | Fetch::Build { .. }, Some(source)) = (&fetch, &source) {
+        if !force && installed_from(manifest, package, &path, source) {
+            return Ok(Action::Skip("up to date".to_string()));
+        }
+    }
+
+    Ok(Action::Install {
+        fetch,
+        extract,
+        path,
+        replaces,
+        store: config.install.store,
+        post_install: package.options().post_install.clone(),
+    })
+}
+
+/// Remove the files of the packages `names` and everything recorded about them, reporting each
+/// removed file to `out`. Custom commands and ignored files are refused, workstation does not
+/// know what else they touch.
+fn uninstall(
+    config: &Config,
+    names: &[String],
+    dry_run: bool,
+    out: &mut dyn Write,
+) -> eyre::Result<()> {
+    let paths = paths::Paths::resolve(Some(&config.paths))?;
+    let manifest = manifest::Manifest::load(&paths.state.path)?;
+    let versions = version::Versions::load(&paths.state.path);
+    let extractions = local::Extractions::load(&paths.state.path);
+    let ignore = config.install.ignore_set()?;
+    let store = store::Store::new(&paths.store.path);
+    let backups = backup::Backups::new(&paths.state.path);
+
+    for name in names {
+        let package = config.platform.packages.iter().find(|p| p.name() == name);
+        let recorded = manifest.get(name);
+        if package.is_none() && recorded.is_none() {
+            eyre::bail!("No package named {} is configured or installed", name);
+        }
+        if let Some(PackageConfig::Command { creates, .. }) = package {
+            eyre::bail!(
+                "{} is installed by a custom command, remove {} and what else it installed by hand",
+                name,
+                creates
+            );
+        }
+        if let Some(PackageConfig::GitRepo { dest, .. }) = package {
+            eyre::bail!(
+                "{} is a git clone that may hold local changes, remove {} by hand",
+                name,
+                dest
+            );
+        }
+        if let Some(PackageConfig::System { .. }) = package {
+            eyre::bail!(
+                "{} is a system package, remove it with the package manager",
+                name
+            );
+        }
+        if let Some(package @ PackageConfig::Distro { .. }) = package {
+            eyre::bail!(
+                "{} is a distribution package, remove {} with the package manager",
+                name,
+                distro::package_name(package)
+            );
+        }
+        if let Some((installer, tool, _)) = package.and_then(global::Installer::of) {
+            eyre::bail!(
+                "{} is installed with {}, remove it with `{}`",
+                name,
+                installer,
+                installer.uninstall_command(tool)
+            );
+        }
+        if let Some(pattern) = ignore.matching_pattern(Path::new(name)) {
+            eyre::bail!(
+                "{} matches the install.ignore pattern {:?}, refusing to remove it",
+                name,
+                pattern
+            );
+        }
+
+        // Only directory packages record a path other than their file in the location
+        let directory = match (&recorded, package) {
+            (_, Some(package)) => matches!(package, PackageConfig::Directory { .. }),
+            (Some(installed), None) => {
+                installed.path != get_install_path(&config.platform.location, name)?
+            }
+            (None, None) => false,
+        };
+        // The other files of an archive package, as recorded and as configured now
+        let mut files: Vec<PathBuf> = recorded
+            .iter()
+            .flat_map(|installed| installed.files.clone())
+            .collect();
+        for (package, file) in package.iter().flat_map(|p| {
+            p.file_names()
+                .into_iter()
+                .skip(1)
+                .map(move |file| (p, file))
+        }) {
+            let file = get_install_path(package.location(&config.platform.location), file)?;
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+        let path = match (recorded, package) {
+            (Some(installed), _) => installed.path,
+            (None, Some(package)) => {
+                package_path(package.location(&config.platform.location), package)?
+            }
+            (None, None) => get_install_path(&config.platform.location, name)?,
+        };
+        if dry_run {
+            for path in std::iter::once(&path).chain(&files) {
+                if path.symlink_metadata().is_ok() {
+                    writeln!(out, "Would remove {}", path.display())?;
+                }
+            }
+            continue;
+        }
+        let mut targets: Vec<PathBuf> = std::fs::read_link(&path).ok().into_iter().collect();
+        let removed = if directory && path.is_dir() && targets.is_empty() {
+            std::fs::remove_dir_all(&path)
+        } else {
+            std::fs::remove_file(&path)
+        };
+        match removed {
+            Ok(()) => writeln!(out, "Removed {}", path.display())?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                writeln!(out, "{} was already removed", path.display())?
+            }
+            Err(e) => return Err(e).with_context(|| format!("Removing {}", path.display())),
+        }
+        for file in &files {
+            targets.extend(std::fs::read_link(file).ok());
+            match std::fs::remove_file(file) {
+                Ok(()) => writeln!(out, "Removed {}", file.display())?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e).with_context(|| format!("Removing {}", file.display())),
+            }
+        }
+        manifest.remove(name);
+        versions.remove(name);
+        extractions.remove(name);
+        backups.remove(name);
+
+        let links = store_links(config, &manifest)?;
+        for target in targets {
+            if store.release(&target, &links)? {
+                writeln!(out, "Removed its store entry")?;
+            }
+        }
+        let completions = package.map(|p| &p.options().generate_completions);
+        for shell in completions.into_iter().flat_map(|c| c.keys()) {
+            let path = paths.completions[shell].join(shell.file_name(name));
+            if std::fs::remove_file(&path).is_ok() {
+                writeln!(out, "Removed {}", path.display())?;
+            }
+        }
+        if package.is_some() {
+            eprintln!(
+                "warning: {} is still in the config, the next setup installs it again",
+                name
+            );
+        }
+    }
+
+    if dry_run {
+        return Ok(());
+    }
+    manifest.save()?;
+    versions.save()?;
+    extractions.save()?;
+    if let Err(e) = plan_shell_init(config).and_then(|changes| shell_init::apply(&changes)) {
+        eprintln!("warning: shell rc files were not updated: {:?}", e);
+    }
+    Ok(())
+}
+
+/// Put back the file package `name` had before `setup` last replaced it. The file it replaces is
+/// kept in turn, so rolling back twice returns to where it started.
+fn rollback(config: &Config, name: &str, out: &mut dyn Write) -> eyre::Result<()> {
+    let paths = paths::Paths::resolve(Some(&config.paths))?;
+    let package = config.platform.packages.iter().find(|p| p.name() == name);
+    if package.is_some_and(|package| !package.is_single_file()) {
+        eyre::bail!(
+            "{} is not installed as a single file, only those are kept for rolling back",
+            name
+        );
+    }
+    let backups = backup::Backups::new(&paths.state.path);
+    let Some(previous) = backups.get(name)? else {
+        eyre::bail!(
+            "No previous version of {} is kept, setup keeps one when it replaces the file",
+            name
+        );
+    };
+    // A package no longer configured goes back where it was installed
+    let recorded = previous
+        .record
+        .as_ref()
+        .and_then(|record| record.path.parent());
+    let location = match (package, recorded) {
+        (Some(package), _) => install_dir(package.location(&config.platform.location))?,
+        (None, Some(directory)) => directory.to_path_buf(),
+        (None, None) => install_dir(&config.platform.location)?,
+    };
+    let manifest = manifest::Manifest::load(&paths.state.path)?;
+    let versions = version::Versions::load(&paths.state.path);
+    let store = config
+        .install
+        .store
+        .then(|| store::Store::new(&paths.store.path));
+
+    let path = get_install_path(&location, name)?;
+    let data = staged::Staged::copy(&path, &previous.path)?;
+    let hash = data.hash().to_string();
+    backups.keep(name, &path, &hash, manifest.get(name))?;
+    let mode = package.map_or(config::DEFAULT_MODE, |package| package.mode());
+    install(&location, name, data, mode, store.as_ref())?;
+    let installed = [name.to_string()];
+    backups.commit(&installed)?;
+
+    let record = previous.record.as_ref();
+    let source = record.and_then(|record| record.source.clone());
+    manifest.stage(
+        name,
+        manifest::Staged {
+            hash,
+            source: source.clone(),
+            fields_hash: record.and_then(|record| record.fields_hash.clone()),
+            path: None,
+            location: None,
+            files: record
+                .iter()
+                .flat_map(|record| &record.files)
+                .map(|file| file.to_string_lossy().into_owned())
+                .collect(),
+        },
+    );
+    manifest.commit(&installed, &location);
+    manifest.save()?;
+    versions.stage(
+        name,
+        source.as_deref().and_then(|s| version::infer(s, None)),
+    );
+    versions.commit(&installed);
+    versions.save()?;
+
+    writeln!(
+        out,
+        "Rolled back {} to {}",
+        name,
+        source.as_deref().unwrap_or("its previous version")
+    )?;
+    if package.is_some() {
+        eprintln!(
+            "warning: {} is still configured as before, the next setup installs that again",
+            name
+        );
+    }
+    Ok(())
+}
+
+/// Where the installed packages link to, those recorded and those configured
+fn store_links(config: &Config, manifest: &manifest::Manifest) -> eyre::Result<Vec<PathBuf>> {
+    Ok(installed_paths(config, manifest)?
+        .iter()
+        .filter_map(|path| std::fs::read_link(path).ok())
+        .collect())
+}
+
+/// The files of the installed packages, those recorded and those configured
+fn installed_paths(config: &Config, manifest: &manifest::Manifest) -> eyre::Result<Vec<PathBuf>> {
+    let mut installed: Vec<PathBuf> = manifest
+        .entries()
+        .into_values()
+        .flat_map(|installed| std::iter::once(installed.path).chain(installed.files))
+        .collect();
+    for package in &config.platform.packages {
+        installed.push(package_path(
+            package.location(&config.platform.location),
+            package,
+        )?);
+        for file in package.file_names().into_iter().skip(1) {
+            installed.push(get_install_path(
+                package.location(&config.platform.location),
+                file,
+            )?);
+        }
+    }
+    installed.sort();
+    installed.dedup();
+    Ok(installed)
+}
+
+/// Remove the store entries that neither a recorded install nor a configured package links to
+fn clean_store(config: &Config) -> eyre::Result<()> {
+    let paths = paths::Paths::resolve(Some(&config.paths))?;
+    let manifest = manifest::Manifest::load(&paths.state.path)?;
+    let links = store_links(config, &manifest)?;
+
+    let collected = store::Store::new(&paths.store.path).collect_garbage(&links)?;
+    println!(
+        "Removed {} store entr{}, {} freed",
+        collected.entries,
+        if collected.entries == 1 { "y" } else { "ies" },
+        budget::format_bytes(collected.bytes)
+    );
+    Ok(())
+}
+
+/// Remove the downloaded assets, each is downloaded again when it is needed
+fn clean_cache(config: &Config) -> eyre::Result<()> {
+    let paths = paths::Paths::resolve(Some(&config.paths))?;
+    let bytes = cache::Cache::new(&paths.cache.path).clear()?;
+    println!(
+        "Removed the download cache, {} freed",
+        budget::format_bytes(bytes)
+    );
+    Ok(())
+}
+
+/// Check the links of the installed packages and the content of the store, see
+/// [`store::Store::verify`]
+fn verify_store(config: &Config, out: &mut dyn Write) -> eyre::Result<()> {
+    let paths = paths::Paths::resolve(Some(&config.paths))?;
+    let manifest = manifest::Manifest::load(&paths.state.path)?;
+    let installed = installed_paths(config, &manifest)?;
+
+    let verified = store::Store::new(&paths.store.path).verify(&installed)?;
+    for problem in &verified.problems {
+        writeln!(out, "{}", problem)?;
+    }
+    writeln!(
+        out,
+        "Verified {} link(s) and {} store file(s)",
+        verified.links, verified.files
+    )?;
+    if !verified.problems.is_empty() {
+        eyre::bail!("{} problem(s) found", verified.problems.len());
+    }
+    Ok(())
+}
+
+/// Append the package at `source` to the platform section of the config at `path`, see
+/// `workstation add`
+fn add(
+    path: &Path,
+    source: &str,
+    name: Option<String>,
+    bin: Option<String>,
+    dry_run: bool,
+) -> eyre::Result<()> {
+    let text = config::read_encrypted_source(None, path)?;
+    if sops::is_encrypted(&text) {
+        eyre::bail!(
+            "{} is encrypted with SOPS, add the package with `sops edit` instead",
+            path.display()
+        );
+    }
+    let config = config::parse_config(&text)?;
+
+    let url = match add::Source::parse(source) {
+        add::Source::Github { repo } => {
+            let lookup = PackageConfig::GithubRelease {
+                name: repo.clone(),
+                repo: repo.clone(),
+                version: None,
+                asset_pattern: None,
+                bin: None,
+                options: Default::default(),
+            };
+            let token = lookup.download_options(&config.download).github_token()?;
+            http::init(&config.download)?;
+            let (asset, tag) = github::resolve(&repo, None, None, token.as_deref())?;
+            println!("Release {} of {}: {}", tag, repo, asset.name);
+            asset.browser_download_url
+        }
+        add::Source::Url(url) => url,
+    };
+    let file_name = checksums::file_name(&url).to_string();
+
+    let bin = match (bin, archive::is_supported(&url)) {
+        (Some(bin), _) => Some(bin),
+        (None, true) => {
+            let download = PackageConfig::Binary {
+                name: name.clone().unwrap_or_else(|| add::guess_name(&file_name)),
+                url: url.clone(),
+                version: None,
+                options: Default::default(),
+            };
+            let asset = read_asset(
+                &config,
+                &download,
+                &url,
+                &download.download_options(&config.download),
+            )?;
+            let entries = archive::list_entries(&url, asset.reader()?)?;
+            Some(add::guess_bin(&entries, name.as_deref())?)
+        }
+        (None, false) => None,
+    };
+    let name = name.unwrap_or_else(|| {
+        let detected = match &bin {
+            Some(bin) => bin.rsplit('/').next().unwrap_or(bin).to_string(),
+            None => add::guess_name(&file_name),
+        };
+        let name = crate::name::sanitize(&detected);
+        if name != detected {
+            println!(
+                "Naming the package {} instead of {:?}, which is not a valid package name",
+                name, detected
+            );
+        }
+        name
+    });
+    crate::name::validate(&name).map_err(|reason| {
+        eyre::eyre!(
+            "{:?} is not a valid package name, {}, pass --name",
+            name,
+            reason
+        )
+    })?;
+
+    let mut document: toml_edit::DocumentMut = text
+        .parse()
+        .with_context(|| format!("Parsing {}", path.display()))?;
+    add::append(
+        &mut document,
+        &config.section,
+        add::entry(&name, &url, bin.as_deref()),
+    )?;
+    let added = document.to_string();
+    // Make sure the result still deserializes before touching the file
+    config::parse_config(&added)
+        .with_context(|| format!("Validating {} with {} added", path.display(), name))?;
+
+    if dry_run {
+        let mut diffs: Vec<_> =
+            diff::FileDiff::compute(path, Some(text.as_bytes()), added.as_bytes())
+                .into_iter()
+                .collect();
+        print!(
+            "{}",
+            diff::render_all(&mut diffs, console::colors_enabled())
+        );
+        return Ok(());
+    }
+    std::fs::write(path, added).with_context(|| format!("Writing {}", path.display()))?;
+    println!(
+        "Added {} to [{}], run `workstation setup` to install it",
+        name, config.section
+    );
+    Ok(())
+}
+
+fn migrate_config(path: &Path, dry_run: bool) -> eyre::Result<()> {
+    let source = config::read_encrypted_source(None, path)?;
+    if sops::is_encrypted(&source) {
+        // Writing the migrated config would put the decrypted values on disk
+        eyre::bail!(
+            "{} is encrypted with SOPS, migrate it with `sops edit` instead",
+            path.display()
+        );
+    }
+    let mut document: toml_edit::DocumentMut = source
+        .parse()
+        .with_context(|| format!("Parsing {}", path.display()))?;
+
+    let applied = migrate::migrate(&migrate::CONFIG_SCHEMA, &mut document)?;
+    if applied.is_empty() {
+        println!(
+            "{} is already at schema version {}",
+            path.display(),
+            migrate::CONFIG_SCHEMA.current
+        );
+        return Ok(());
+    }
+
+    // Make sure the result still deserializes before touching the file
+    config::parse_config_file(&document.to_string())
+        .with_context(|| format!("Validating migrated {}", path.display()))?;
+    if dry_run {
+        let migrated = document.to_string();
+        let mut diffs: Vec<_> =
+            diff::FileDiff::compute(path, Some(source.as_bytes()), migrated.as_bytes())
+                .into_iter()
+                .collect();
+        print!(
+            "{}",
+            diff::render_all(&mut diffs, console::colors_enabled())
+        );
+        return Ok(());
+    }
+    std::fs::write(path, document.to_string())
+        .with_context(|| format!("Writing {}", path.display()))?;
+
+    for step in applied {
+        println!("Applied: {}", step);
+    }
+    println!(
+        "Migrated {} to schema version {}",
+        path.display(),
+        migrate::CONFIG_SCHEMA.current
+    );
+
+    Ok(())
+}
+
+/// How [`setup`] installs the packages, everything off by default
+#[derive(Default)]
+struct SetupOptions<'a> {
+    /// Install single files in one transaction, undone when any package fails
+    rollback_on_failure: bool,
+    /// Install packages that are up to date too
+    force: bool,
+    /// Make no requests, see `setup --offline`
+    offline: bool,
+    dashboard: Option<Arc<tui::Dashboard>>,
+    events: Option<Arc<events::EventStream>>,
+    tracer: Option<Arc<trace::Tracer>>,
+    /// Only the packages with these names, every package when `None`
+    only: Option<&'a [String]>,
+    /// The lockfile entries whose release is installed when GitHub cannot be asked for it
+    locked: &'a [lock::LockEntry],
+}
+
+/// Install the configured packages, or only those named in `options.only`
+fn setup(config: &Config, reporter: &Arc<Reporter>, options: SetupOptions) -> eyre::Result<()> {
+    let SetupOptions {
+        rollback_on_failure,
+        force,
+        offline,
+        dashboard,
+        events,
+        tracer,
+        only,
+        locked,
+    } = options;
+    let ignore = config.install.ignore_set()?;
+    let transaction = if rollback_on_failure {
+        let location = install_dir(&config.platform.location)?;
+        Some(transaction::Transaction::new(&location)?)
+    } else {
+        None
+    };
+
+    let multi_progress =
+        MultiProgress::with_draw_target(ProgressDrawTarget::stderr_with_hz(PROGRESS_REFRESH_RATE));
+    let progress_style = ProgressStyle::with_template(
+        "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}",
+    )
+    .unwrap()
+    .progress_chars("##-");
+
+    let mut failed = vec![];
+
+    let paths = paths::Paths::resolve(Some(&config.paths))?;
+    let cache = cache::Cache::new(&paths.cache.path);
+    let extractions = Arc::new(local::Extractions::load(&paths.state.path));
+    let versions = Arc::new(version::Versions::load(&paths.state.path));
+    let manifest = Arc::new(manifest::Manifest::load(&paths.state.path)?);
+    let backups = Arc::new(backup::Backups::new(&paths.state.path));
+    let store = config
+        .install
+        .store
+        .then(|| store::Store::new(&paths.store.path));
+    let launchers = appimage::Launchers {
+        applications: paths.applications.clone(),
+        icons: paths.icons.clone(),
+    };
+    let budget = budget::Budget::new(
+        config
+            .download
+            .memory_budget
+            .unwrap_or(budget::DEFAULT_LIMIT),
+    );
+    if let Some(dashboard) = &dashboard {
+        dashboard.track_memory(budget.clone());
+    }
+    let downloads = Arc::new(slots::Slots::new(
+        config
+            .download
+            .concurrency
+            .unwrap_or(download::DEFAULT_CONCURRENCY),
+    ));
+    // Without `jobs` every package starts at once
+    let jobs = Arc::new(slots::Slots::new(config.install.jobs.unwrap_or(usize::MAX)));
+    // Every package is a task, installing on the blocking pool once it has a job slot
+    let runtime = http::runtime();
+    let mut tasks = tokio::task::JoinSet::new();
+
+    let spawn = |package: &PackageConfig, progress_bar: ProgressBar| {
+        let destination = package.location(&config.platform.location).to_path_buf();
+        // With a transaction, packages are installed into the staging directory first
+        let staged = transaction.is_some() && stageable(package);
+        let worker = Worker {
+            location: match &transaction {
+                Some(transaction) if staged => transaction.staging_dir().to_path_buf(),
+                _ => destination.clone(),
+            },
+            destination,
+            package: package.clone(),
+            locked: locked
+                .iter()
+                .find(|entry| entry.name == package.name())
+                .map(|entry| entry.resolved.clone()),
+            download: download::DownloadOptions {
+                offline,
+                ..package.download_options(&config.download)
+            },
+            cache: cache.clone(),
+            staged,
+            force,
+            reporter: reporter.clone(),
+            dashboard: dashboard.clone(),
+            events: events.clone(),
+            tracer: tracer.clone(),
+            extractions: extractions.clone(),
+            versions: versions.clone(),
+            manifest: manifest.clone(),
+            backups: backups.clone(),
+            store: store.clone(),
+            launchers: launchers.clone(),
+            budget: budget.clone(),
+            downloads: downloads.clone(),
+            delta_saved: Default::default(),
+            cache_hit: Default::default(),
+            locked_fallback: Default::default(),
+        };
+        let jobs = jobs.clone();
+        async move {
+            let _job = jobs
+                .acquire(|limit| {
+                    progress_bar.set_message(format!("Waiting, {} package(s) installing", limit))
+                })
+                .await;
+            tokio::task::spawn_blocking(move || worker.run(progress_bar))
+                .await
+                .expect("install task panicked")
+        }
+    };
+
+    let (system, selected): (Vec<_>, Vec<_>) = config
+        .platform
+        .packages
+        .iter()
+        .filter(|package| only.is_none_or(|only| only.iter().any(|name| name == package.name())))
+        .partition(|package| {
+            matches!(
+                package,
+                PackageConfig::System { .. } | PackageConfig::Distro { .. }
+            )
+        });
+    let (distro, system): (Vec<_>, Vec<_>) = system
+        .into_iter()
+        .partition(|package| matches!(package, PackageConfig::Distro { .. }));
+    // Other packages may need what system packages provide, such as a compiler
+    failed.extend(install_system(
+        &system,
+        &config.platform.location,
+        offline,
+        reporter,
+    ));
+    failed.extend(install_distro(config, &distro, force, offline, reporter));
+    for package in selected {
+        if let Some(error) = ignored_error(&ignore, package) {
+            if let Some(dashboard) = &dashboard {
+                dashboard.add(package.name());
+                dashboard.finish(package.name(), Some(error.to_string()));
+            }
+            if let Some(events) = &events {
+                events.finished(package.name(), Some(error.to_string()));
+            }
+            reporter.record(PackageReport::failed(package.name(), &error));
+            failed.push(package.name().to_string());
+            continue;
+        }
+
+        let progress_bar = match &dashboard {
+            Some(dashboard) => dashboard.add(package.name()),
+            None => multi_progress.add(ProgressBar::new(0)),
+        };
+        progress_bar.set_style(progress_style.clone());
+        progress_bar.set_message(format!("Installing {}", package.name()));
+
+        tasks.spawn_on(spawn(package, progress_bar), runtime.handle());
+    }
+
+    if let Some(dashboard) = &dashboard {
+        tui::run(dashboard, |name, progress_bar| {
+            let package = config
+                .platform
+                .packages
+                .iter()
+                .find(|package| package.name() == name)
+                .expect("retried package is configured");
+            match ignored_error(&ignore, package) {
+                Some(error) => dashboard.finish(name, Some(error.to_string())),
+                None => {
+                    tasks.spawn_on(spawn(package, progress_bar), runtime.handle());
+                }
+            }
+        })?;
+    }
+
+    let mut succeeded = vec![];
+    runtime.block_on(async {
+        while let Some(finished) = tasks.join_next().await {
+            match finished.expect("install task panicked") {
+                Ok(finished) => succeeded.push(finished),
+                Err(name) => failed.push(name),
+            }
+        }
+    });
+
+    let installed = match transaction {
+        Some(transaction) => {
+            // Unchanged packages left nothing in the staging directory
+            let (staged, unchanged): (Vec<_>, Vec<_>) =
+                succeeded.into_iter().partition(|finished| finished.staged);
+            let mut installed: Vec<String> = unchanged
+                .into_iter()
+                .map(|finished| finished.name)
+                .collect();
+            let staged = staged.into_iter().map(|finished| finished.name).collect();
+            installed.extend(commit_transaction(
+                transaction,
+                &config.platform.packages,
+                &config.platform.location,
+                staged,
+                &failed,
+                reporter,
+            ));
+            installed
+        }
+        None => succeeded
+            .into_iter()
+            .map(|finished| finished.name)
+            .collect(),
+    };
+
+    if let Err(e) = extractions.save() {
+        eprintln!("warning: local archives will be extracted again: {:?}", e);
+    }
+    versions.commit(&installed);
+    if let Err(e) = versions.save() {
+        eprintln!("warning: package versions were not recorded: {:?}", e);
+    }
+    manifest.commit(&installed, &install_dir(&config.platform.location)?);
+    if let Err(e) = manifest.save() {
+        eprintln!("warning: installed files were not recorded: {:?}", e);
+    }
+    if let Err(e) = backups.commit(&installed) {
+        eprintln!(
+            "warning: replaced files were not kept for rollback: {:?}",
+            e
+        );
+    }
+    generate_completions(config, &installed)?;
+    if let Err(e) = plan_shell_init(config).and_then(|changes| shell_init::apply(&changes)) {
+        eprintln!("warning: shell rc files were not updated: {:?}", e);
+    }
+    warn_if_not_on_path(&config.platform.location, config.install.manage_path);
+
+    Ok(())
+}
+
+/// Never overwrite files the user manages by other means
+fn ignored_error(ignore: &ignore::IgnoreSet, package: &PackageConfig) -> Option<eyre::Report> {
+    let pattern = ignore.matching_pattern(Path::new(package.name()))?;
+    Some(eyre::eyre!(
+        "{} matches the install.ignore pattern {:?}, refusing to overwrite it",
+        package.name(),
+        pattern
+    ))
+}
+
+/// A package whose install task succeeded
+struct Finished {
+    name: String,
+    /// Its files were written to the staging directory, for the transaction to swap in
+    staged: bool,
+}
+
+/// Everything an install task needs to install one package
+struct Worker {
+    /// Where the package is written, the staging directory of a transaction for staged packages
+    location: PathBuf,
+    /// The install location the package ends up in
+    destination: PathBuf,
+    package: PackageConfig,
+    download: download::DownloadOptions,
+    cache: cache::Cache,
+    /// Staged packages are only reported once the transaction commits
+    staged: bool,
+    force: bool,
+    reporter: Arc<Reporter>,
+    dashboard: Option<Arc<tui::Dashboard>>,
+    events: Option<Arc<events::EventStream>>,
+    tracer: Option<Arc<trace::Tracer>>,
+    extractions: Arc<local::Extractions>,
+    /// Inferred versions, for display only
+    versions: Arc<version::Versions>,
+    /// Hashes of the installed files, see `reconcile`
+    manifest: Arc<manifest::Manifest>,
+    /// The files replaced by this run, see `rollback`
+    backups: Arc<backup::Backups>,
+    /// Installed files are links into the store when set, copies otherwise
+    store: Option<store::Store>,
+    /// Where the desktop entries of AppImages are written
+    launchers: appimage::Launchers,
+    /// Memory shared by all workers, see [`budget::Budget`]
+    budget: Arc<budget::Budget>,
+    /// Downloads shared by all workers, see [`slots::Slots`]
+    downloads: Arc<slots::Slots>,
+    /// Bytes a zsync delta update did not download, for the report
+    delta_saved: std::cell::Cell<Option<u64>>,
+    /// The asset came from the download cache, for the report
+    cache_hit: std::cell::Cell<bool>,
+    /// What the package was locked to, installed when its GitHub release cannot be looked up
+    locked: Option<lock::Resolved>,
+    /// The locked release was installed without looking it up, for the report
+    locked_fallback: std::cell::Cell<bool>,
+}
+
+impl Worker {
+    /// Install the package, returning its name either way
+    fn run(self, progress_bar: ProgressBar) -> Result<Finished, String> {
+        let name = self.package.name();
+        if let Some(dashboard) = &self.dashboard {
+            dashboard.start(name);
+        }
+        if let Some(events) = &self.events {
+            events.started(name, &progress_bar);
+        }
+
+        let trace = trace::PackageTrace::new(self.tracer.clone(), name);
+        let span = trace.package();
+        let result = self
+            .install_package(progress_bar.clone(), &trace)
+            .and_then(|outcome| {
+                // Staged packages only run their hooks once the transaction commits
+                if outcome == InstallOutcome::Installed && !self.staged {
+                    let span = trace.phase("post_install");
+                    run_post_install(&self.package, &self.destination, &progress_bar)?;
+                    span.done(None);
+                }
+                Ok(outcome)
+            })
+            .with_context(|| format!("Installing {}", name));
+        match &result {
+            Ok(InstallOutcome::Installed) => span.done(None),
+            Ok(InstallOutcome::AlreadyPresent) => span.finish("skipped"),
+            Ok(InstallOutcome::ArchiveUnchanged | InstallOutcome::UpToDate) => {
+                span.finish("unchanged")
+            }
+            Err(_) => span.finish("failed"),
+        }
+        let custom_command = self.package.is_custom_command();
+
+        match result {
+            Ok(outcome @ (InstallOutcome::ArchiveUnchanged | InstallOutcome::UpToDate)) => {
+                if let Some(dashboard) = &self.dashboard {
+                    dashboard.finish(name, None);
+                }
+                if let Some(events) = &self.events {
+                    events.finished(name, None);
+                }
+                let reason = match outcome {
+                    InstallOutcome::UpToDate => "up to date",
+                    _ => "archive not modified",
+                };
+                self.reporter.record(PackageReport::unchanged(name, reason));
+                Ok(Finished {
+                    name: name.to_string(),
+                    staged: false,
+                })
+            }
+            Ok(InstallOutcome::AlreadyPresent) => {
+                if let Some(dashboard) = &self.dashboard {
+                    dashboard.finish(name, None);
+                }
+                if let Some(events) = &self.events {
+                    events.finished(name, None);
+                }
+                self.reporter.record(
+                    PackageReport::skipped(name, "already present, use --force to run it again")
+                        .with_custom_command(custom_command),
+                );
+                Ok(Finished {
+                    name: name.to_string(),
+                    staged: false,
+                })
+            }
+            Ok(InstallOutcome::Installed) => {
+                if let Some(dashboard) = &self.dashboard {
+                    dashboard.finish(name, None);
+                }
+                if let Some(events) = &self.events {
+                    events.finished(name, None);
+                }
+                if !self.staged {
+                    self.reporter.record(
+                        PackageReport::installed(name)
+                            .with_custom_command(custom_command)
+                            .with_delta_saved(self.delta_saved.get())
+                            .with_cached(self.cache_hit.get())
+                            .with_locked_fallback(self.locked_fallback.get()),
+                    );
+                }
+                Ok(Finished {
+                    name: name.to_string(),
+                    staged: self.staged,
+                })
+            }
+            Err(e) => {
+                progress_bar.finish_with_message(format!(
+                    "Error installing {}: {}",
+                    name,
+                    error::render(&e)
+                ));
+                if let Some(dashboard) = &self.dashboard {
+                    dashboard.finish(name, Some(error::render(&e)));
+                }
+                if let Some(events) = &self.events {
+                    events.finished(name, Some(error::render(&e)));
+                }
+                self.reporter
+                    .record(PackageReport::failed(name, &e).with_custom_command(custom_command));
+                Err(name.to_string())
+            }
+        }
+    }
+}
+
+/// Whether a transaction can stage the package. Custom commands write where they like and
+/// directories are swapped in on their own, and the staging directory is only in the platform
+/// location, where a rename is sure not to cross filesystems.
+fn stageable(package: &PackageConfig) -> bool {
+    package.is_single_file() && package.own_location().is_none()
+}
+
+/// Swap the staged packages into place, unless anything failed already.
+///
+/// Returns the packages that ended up installed.
+fn commit_transaction(
+    transaction: transaction::Transaction,
+    packages: &[PackageConfig],
+    location: &Path,
+    staged: Vec<String>,
+    failed: &[String],
+    reporter: &Reporter,
+) -> Vec<String> {
+    if !failed.is_empty() {
+        let reason = format!("Not installed because {} failed", failed.join(", "));
+        for name in &staged {
+            reporter.record(PackageReport::rolled_back(name, &reason));
+        }
+        return vec![];
+    }
+
+    let files: Vec<String> = packages
+        .iter()
+        .filter(|package| staged.iter().any(|name| name == package.name()))
+        .flat_map(|package| package.file_names())
+        .map(str::to_string)
+        .collect();
+    match transaction.commit(&files) {
+        // The `post_install` commands run once every staged file is in place
+        Ok(()) => staged
+            .into_iter()
+            .filter(|name| {
+                let package = packages
+                    .iter()
+                    .find(|package| package.name() == name)
+                    .expect("staged package is configured");
+                match run_post_install(package, location, &ProgressBar::hidden())
+                    .with_context(|| format!("Installing {}", name))
+                {
+                    Ok(()) => {
+                        reporter.record(PackageReport::installed(name));
+                        true
+                    }
+                    Err(e) => {
+                        reporter.record(PackageReport::failed(name, &e));
+                        false
+                    }
+                }
+            })
+            .collect(),
+        Err(e) => {
+            // The file that could not be swapped in may be one of the `files` of an archive
+            let failed = packages
+                .iter()
+                .find(|package| package.file_names().contains(&e.name.as_str()))
+                .map_or(e.name.as_str(), |package| package.name());
+            reporter.record(PackageReport::failed(failed, &e.error));
+            let reason = format!("Rolled back because {} could not be swapped in", e.name);
+            for name in staged.iter().filter(|name| *name != failed) {
+                reporter.record(PackageReport::rolled_back(name, &reason));
+            }
+            vec![]
+        }
+    }
+}
+
+/// Regenerate the completions of every installed package, so they match the new binary
+fn generate_completions(config: &Config, installed: &[String]) -> eyre::Result<()> {
+    let packages: Vec<_> = config
+        .platform
+        .packages
+        .iter()
+        .filter(|package| !package.options().generate_completions.is_empty())
+        .filter(|package| installed.iter().any(|name| name == package.name()))
+        .collect();
+    if packages.is_empty() {
+        return Ok(());
+    }
+
+    let paths = paths::Paths::resolve(Some(&config.paths))?;
+    for package in packages {
+        let binary = get_install_path(package.location(&config.platform.location), package.name())?;
+        for warning in completions::generate(
+            &binary,
+            package.name(),
+            &package.options().generate_completions,
+            &paths.completions,
+        ) {
+            eprintln!("warning: {:?}", warning);
+        }
+    }
+
+    Ok(())
+}
+
+/// The rc file changes that leave exactly the `shell_init` snippets of installed packages
+fn plan_shell_init(config: &Config) -> eyre::Result<Vec<shell_init::Change>> {
+    let mut packages = std::collections::BTreeMap::new();
+    for package in &config.platform.packages {
+        let snippets = &package.options().shell_init;
+        if !snippets.is_empty()
+            && is_present(
+                &package_path(package.location(&config.platform.location), package)?,
+                package,
+            )?
+        {
+            packages.insert(package.name(), snippets);
+        }
+    }
+
+    let path = match config.install.manage_path {
+        true => Some(install_dir(&config.platform.location)?),
+        false => None,
+    };
+    let paths = paths::Paths::resolve(Some(&config.paths))?;
+    let env = (!config.env.is_empty()).then_some(paths.env.as_path());
+    shell_init::plan(path.as_deref(), env, &packages, &paths.rc_files)
+}
+
+/// Write the `[workstation.env]` exports, before the rc files that load them are updated
+fn write_env(config: &Config) -> eyre::Result<()> {
+    let paths = paths::Paths::resolve(Some(&config.paths))?;
+    for path in exports::write(&config.env, &paths.env)? {
+        eprintln!("Updated {}", path.display());
+    }
+    Ok(())
+}
+
+fn warn_if_not_on_path(location: &Path, managed: bool) {
+    // A sandboxed location is never meant to be on PATH
+    if expand::Env::current().sandbox_home().is_some() {
+        return;
+    }
+    let Ok(location_path) = install_dir(location) else {
+        return;
+    };
+    if conflicts::path_directories().contains(&location_path) {
+        return;
+    }
+    if managed {
+        eprintln!(
+            "{} is on PATH in new shells, open one to use the installed packages",
+            location.display()
+        );
+        return;
+    }
+
+    eprintln!(
+        "warning: [{code}] {} is not on PATH, installed packages will not be found by your shell\nRun `workstation explain {code}` for help",
+        location.display(),
+        code = ErrorCode::NotOnPath
+    );
+}
+
+/// What installing a package did
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum InstallOutcome {
+    Installed,
+    /// A custom command whose file already exists
+    AlreadyPresent,
+    /// A local archive that did not change since it was last extracted
+    ArchiveUnchanged,
+    /// Already installed from the same source, nothing was downloaded
+    UpToDate,
+}
+
+impl Worker {
+    fn install_package(
+        &self,
+        pb: ProgressBar,
+        trace: &trace::PackageTrace,
+    ) -> eyre::Result<InstallOutcome> {
+        let package = &self.package;
+        let archive = match package {
+            PackageConfig::Archive { archive, .. }
+            | PackageConfig::AutoArchArchive { archive, .. } => Some(package.expand_url(archive)?),
+            _ => None,
+        };
+        let local = archive
+            .as_deref()
+            .and_then(local::local_path)
+            .map(|path| {
+                let owner = format!("package {}", package.name());
+                expand::expand_source_path(path, expand::Field::new("archive", &owner))
+            })
+            .transpose()?;
+        if let Some(path) = &local {
+            let installed = get_install_path(&self.destination, package.name())?;
+            if !self.force
+                && self
+                    .extractions
+                    .unchanged(package.name(), path, &installed)?
+            {
+                pb.finish_with_message(format!("{} is unchanged", package.name()));
+                return Ok(InstallOutcome::ArchiveUnchanged);
+            }
+        }
+        if let Some(url) = package.url().filter(|_| local.is_none()) {
+            if self.up_to_date(url, &pb)? {
+                return Ok(InstallOutcome::UpToDate);
+            }
+        }
+        let mut archive_hash = None;
+        let inferred;
+        let mut source = package.url().map(str::to_string);
+        // The other files of an archive, installed next to the package
+        let mut files = vec![];
+        // What is installed is staged next to where it goes, see `staged::Staged`
+        let target = get_install_path(&self.location, package.name())?;
+        // Assets and what is extracted from them stay on disk, the asset size is reserved as the
+        // estimate of what a package holds while it installs. Directory packages unpack
+        // straight to disk.
+        let reservation = self.budget.reservation(match package {
+            PackageConfig::Directory { .. }
+            | PackageConfig::GitRepo { .. }
+            | PackageConfig::Pipx { .. }
+            | PackageConfig::Npm { .. }
+            | PackageConfig::Flatpak { .. }
+            | PackageConfig::Snap { .. } => 0,
+            _ => 1,
+        });
+
+        let data = match package {
+            PackageConfig::Archive {
+                name,
+                bin,
+                archive,
+                files: entries,
+                ..
+            } => {
+                let span = trace.phase("resolve");
+                let owner = format!("package {}", name);
+                let bin = expand::expand_vars(bin, expand::Field::new("bin", &owner))?;
+                span.done(None);
+
+                let span = trace.phase("download");
+                let asset = self
+                    .fetch(archive, &pb, &reservation)
+                    .with_context(|| format!("Failed to download {}", name))?;
+                pb.finish_with_message(format!("Downloaded {}", name));
+                span.done(Some(asset.len() as usize));
+                archive_hash = local.is_some().then(|| asset.hash()).transpose()?;
+                inferred = version::infer(archive, Some(&asset));
+
+                let span = trace.phase("extract");
+                for file in entries {
+                    let path = archive::find_single(archive, asset.reader()?, &file.entry)
+                        .with_context(|| format!("Extracting {}", file.dest_name))?;
+                    let staged = staged::Staged::new(
+                        &get_install_path(&self.location, &file.dest_name)?,
+                        |output| archive::copy_file(archive, asset.reader()?, &path, output),
+                    )
+                    .with_context(|| format!("Extracting {}", file.dest_name))?;
+                    files.push((file.dest_name.clone(), staged));
+                }
+                let data = staged::Staged::new(&target, |output| {
+                    archive::extract_entry(archive, asset.reader()?, &bin, output)
+                })?;
+                span.done(Some(data.len() as usize));
+                data
+            }
+            PackageConfig::AutoArchArchive {
+                name,
+                auto_arch_bin,
+                archive,
+                ..
+            } => {
+                let span = trace.phase("download");
+                let asset = self
+                    .fetch(archive, &pb, &reservation)
+                    .with_context(|| format!("Failed to download {}", name))?;
+                pb.finish_with_message(format!("Downloaded {}", name));
+                span.done(Some(asset.len() as usize));
+                archive_hash = local.is_some().then(|| asset.hash()).transpose()?;
+                inferred = version::infer(archive, Some(&asset));
+
+                let span = trace.phase("extract");
+                let path = archive::find_for_platform(
+                    archive,
+                    asset.reader()?,
+                    auto_arch_bin,
+                    arch::Target::current().platform(),
+                )?;
+                let data = staged::Staged::new(&target, |output| {
+                    archive::copy_file(archive, asset.reader()?, &path, output)
+                })?;
+                span.done(Some(data.len() as usize));
+                data
+            }
+            PackageConfig::Directory {
+                name,
+                archive,
+                strip_components,
+                ..
+            } => {
+                let span = trace.phase("download");
+                let asset = self
+                    .fetch(archive, &pb, &reservation)
+                    .with_context(|| format!("Failed to download {}", name))?;
+                pb.finish_with_message(format!("Downloaded {}", name));
+                span.done(Some(asset.len() as usize));
+
+                let span = trace.phase("install");
+                let destination = package_path(&self.location, package)?;
+                install_directory(archive, &asset, *strip_components, &destination)
+                    .with_context(|| "Installing")?;
+                span.done(Some(asset.len() as usize));
+
+                // Directory packages are never staged, they are final once moved into place
+                self.versions
+                    .stage(name, version::infer(archive, Some(&asset)));
+                self.manifest.stage(
+                    name,
+                    manifest::Staged {
+                        hash: asset.hash()?,
+                        source: Some(sops::redact(archive)),
+                        fields_hash: Some(lock::fields_hash(package)),
+                        path: Some(destination),
+                        location: None,
+                        files: vec![],
+                    },
+                );
+                return Ok(InstallOutcome::Installed);
+            }
+            PackageConfig::Binary { name, url, .. }
+            | PackageConfig::AppImage {
+                name,
+                appimage: url,
+                ..
+            } => {
+                let span = trace.phase("download");
+                let asset = self
+                    .fetch(url, &pb, &reservation)
+                    .with_context(|| "Downloading")?;
+                pb.finish_with_message(format!("Downloaded {}", name));
+                span.done(Some(asset.len() as usize));
+                inferred = version::infer(url, None);
+                staged::Staged::new(&target, |output| {
+                    archive::decompress_single(url, asset.reader()?, output)
+                })?
+            }
+            PackageConfig::GithubRelease {
+                name,
+                repo,
+                version,
+                asset_pattern,
+                bin,
+                ..
+            } => {
+                let span = trace.phase("resolve");
+                let token = self.download.github_token()?;
+                let resolved = match self.download.offline {
+                    true => Err(download::offline_error(format!(
+                        "Looking up the release of {}",
+                        repo
+                    ))),
+                    false => github::resolve(
+                        repo,
+                        version.as_deref(),
+                        asset_pattern.as_deref(),
+                        token.as_deref(),
+                    ),
+                };
+                let (url, location, asset_name) = match resolved {
+                    Ok((asset, _)) => {
+                        let location = asset.download_url(token.is_some()).to_string();
+                        (asset.browser_download_url, location, asset.name)
+                    }
+                    Err(e) => {
+                        let url = self.locked_release(e, &pb)?;
+                        let asset_name = url.rsplit('/').next().unwrap_or(&url).to_string();
+                        (url.clone(), url, asset_name)
+                    }
+                };
+                span.done(None);
+                let url = &url;
+                if self.up_to_date(url, &pb)? {
+                    return Ok(InstallOutcome::UpToDate);
+                }
+
+                let span = trace.phase("download");
+                source = Some(url.clone());
+                let downloaded = self
+                    .fetch_from(url, &location, &pb, &reservation)
+                    .with_context(|| format!("Failed to download {}", asset_name))?;
+                pb.finish_with_message(format!("Downloaded {}", name));
+                span.done(Some(downloaded.len() as usize));
+                inferred = version::infer(url, Some(&downloaded));
+
+                if archive::is_supported(&asset_name) {
+                    let span = trace.phase("extract");
+                    let entry = match bin {
+                        Some(bin) => bin.clone(),
+                        None => format!("**/{}", name),
+                    };
+                    let path = archive::find_single(&asset_name, downloaded.reader()?, &entry)?;
+                    let data = staged::Staged::new(&target, |output| {
+                        archive::copy_file(&asset_name, downloaded.reader()?, &path, output)
+                    })?;
+                    span.done(Some(data.len() as usize));
+                    data
+                } else {
+                    staged::Staged::new(&target, |output| {
+                        archive::decompress_single(&asset_name, downloaded.reader()?, output)
+                    })?
+                }
+            }
+            PackageConfig::Cargo {
+                name,
+                krate,
+                version,
+                features,
+                bin,
+                ..
+            } => {
+                let built_from = package.source();
+                if self.up_to_date(&built_from, &pb)? {
+                    return Ok(InstallOutcome::UpToDate);
+                }
+
+                if self.download.offline {
+                    return Err(download::offline_error(format!(
+                        "Building {} with cargo",
+                        krate
+                    )));
+                }
+                let span = trace.phase("build");
+                source = Some(built_from);
+                pb.set_message(format!("Installing {} with cargo", krate));
+                let progress = pb.clone();
+                let shown = name.clone();
+                let built = cargo::install(
+                    &cargo::Crate {
+                        name: krate,
+                        version: version.as_deref(),
+                        features,
+                        bin: bin.as_deref(),
+                    },
+                    bin.as_deref().unwrap_or(name),
+                    move |line| {
+                        if !line.trim().is_empty() {
+                            progress.set_message(format!("{}: {}", shown, line.trim()));
+                        }
+                    },
+                )?;
+                pb.finish_with_message(format!("Built {}", name));
+                span.done(Some(built.data.len()));
+                inferred = built.version.map(|version| version::Inferred {
+                    version,
+                    from: version::Provenance::Cargo {
+                        krate: krate.clone(),
+                    },
+                });
+                staged::Staged::from_bytes(&target, &built.data)?
+            }
+            PackageConfig::Go {
+                name, go, version, ..
+            } => {
+                let built_from = package.source();
+                if self.up_to_date(&built_from, &pb)? {
+                    return Ok(InstallOutcome::UpToDate);
+                }
+
+                if self.download.offline {
+                    return Err(download::offline_error(format!("Building {} with go", go)));
+                }
+                let span = trace.phase("build");
+                source = Some(built_from);
+                pb.set_message(format!("Installing {} with go", go));
+                let progress = pb.clone();
+                let shown = name.clone();
+                let built = go::install(go, version.as_deref().unwrap_or("latest"), move |line| {
+                    if !line.trim().is_empty() {
+                        progress.set_message(format!("{}: {}", shown, line.trim()));
+                    }
+                })?;
+                pb.finish_with_message(format!("Built {}", name));
+                span.done(Some(built.data.len()));
+                inferred = built.version.map(|version| version::Inferred {
+                    version,
+                    from: version::Provenance::Go { module: go.clone() },
+                });
+                staged::Staged::from_bytes(&target, &built.data)?
+            }
+            PackageConfig::Pipx { name, .. }
+            | PackageConfig::Npm { name, .. }
+            | PackageConfig::Flatpak { name, .. }
+            | PackageConfig::Snap { name, .. } => {
+                let (installer, tool, _) = global::Installer::of(package)
+                    .expect("packages of other package managers have an installer");
+                let span = trace.phase(installer.to_string().as_str());
+                if !self.force && global::is_current(package)? {
+                    span.finish("unchanged");
+                    pb.finish_with_message(format!("{} is up to date", name));
+                    return Ok(InstallOutcome::UpToDate);
+                }
+                if self.download.offline {
+                    return Err(download::offline_error(format!(
+                        "Installing {} with {}",
+                        tool, installer
+                    )));
+                }
+                pb.set_message(format!("Installing {} with {}", tool, installer));
+                global::install(package)?;
+                span.finish("ok");
+                pb.finish_with_message(format!("Installed {} with {}", name, installer));
+                return Ok(InstallOutcome::Installed);
+            }
+            // Installed in one batch before the workers start, see `install_system`
+            PackageConfig::System { name, .. } => {
+                eyre::bail!(
+                    "{} is a system package, setup installs it with the others",
+                    name
+                )
+            }
+            // Likewise, see `install_distro`
+            PackageConfig::Distro { name, .. } => {
+                eyre::bail!(
+                    "{} is a distribution package, setup installs it with the others",
+                    name
+                )
+            }
+            PackageConfig::GitRepo { name, git, rev, .. } => {
+                let span = trace.phase("git");
+                let owner = format!("package {}", name);
+                let git = package.expand_url(git)?;
+                let url = match local::local_path(&git) {
+                    Some(path) => {
+                        expand::expand_source_path(path, expand::Field::new("git", &owner))?
+                            .to_string_lossy()
+                            .into_owned()
+                    }
+                    None if self.download.offline => {
+                        return Err(download::offline_error(format!("Syncing {}", git)));
+                    }
+                    None => git,
+                };
+                let dest = package_path(&self.destination, package)?;
+                pb.set_message(format!("Syncing {} into {}", name, dest.display()));
+                let moved = git::sync(&url, &dest, rev.as_deref(), custom::DEFAULT_TIMEOUT)?;
+                span.finish(if moved { "ok" } else { "unchanged" });
+                if !moved {
+                    pb.finish_with_message(format!("{} is up to date", name));
+                    return Ok(InstallOutcome::UpToDate);
+                }
+                pb.finish_with_message(format!("Synced {}", name));
+                return Ok(InstallOutcome::Installed);
+            }
+            PackageConfig::Command {
+                name,
+                command,
+                creates,
+                timeout,
+                ..
+            } => {
+                let span = trace.phase("command");
+                let ran = run_custom_command(name, command, creates, *timeout, self.force, &pb)?;
+                span.finish(if ran { "ok" } else { "skipped" });
+                return Ok(if ran {
+                    InstallOutcome::Installed
+                } else {
+                    InstallOutcome::AlreadyPresent
+                });
+            }
+        };
+
+        if data.is_empty() && !package.options().allow_empty {
+            return Err(ErrorCode::EmptyContent.error(format!(
+                "Refusing to install an empty file as {}, set allow_empty = true if this is intended",
+                package.name()
+            )));
+        }
+
+        let span = trace.phase("install");
+        self.backups.keep(
+            package.name(),
+            &get_install_path(&self.destination, package.name())?,
+            data.hash(),
+            self.manifest.get(package.name()),
+        )?;
+        let (len, hash) = (data.len(), data.hash().to_string());
+        install(
+            &self.location,
+            package.name(),
+            data,
+            package.mode(),
+            self.store.as_ref(),
+        )
+        .with_context(|| "Installing")?;
+        let mut installed = vec![];
+        for (name, data) in files {
+            install(
+                &self.location,
+                &name,
+                data,
+                package.mode(),
+                self.store.as_ref(),
+            )
+            .with_context(|| format!("Installing {}", name))?;
+            installed.push(name);
+        }
+        span.done(Some(len as usize));
+        let mut files = installed;
+
+        if let PackageConfig::AppImage { name, .. } = package {
+            let span = trace.phase("desktop");
+            let path = get_install_path(&self.destination, name)?;
+            let written = appimage::integrate(name, &path, &self.launchers)
+                .with_context(|| "Adding the desktop entry")?;
+            span.done(None);
+            // Recorded with the package, so that uninstalling it removes them too
+            files.extend(
+                written
+                    .into_iter()
+                    .map(|file| file.to_string_lossy().into_owned()),
+            );
+        }
+
+        if let (Some(path), Some(archive_hash)) = (&local, archive_hash) {
+            self.extractions
+                .record(package.name(), path, archive_hash, hash.clone())?;
+        }
+        self.versions.stage(package.name(), inferred);
+        self.manifest.stage(
+            package.name(),
+            manifest::Staged {
+                hash,
+                source: source.map(|source| sops::redact(&source)),
+                fields_hash: Some(lock::fields_hash(package)),
+                path: None,
+                location: package.own_location().map(install_dir).transpose()?,
+                files,
+            },
+        );
+
+        Ok(InstallOutcome::Installed)
+    }
+
+    /// The URL of the locked release of the package, to install when `error` says GitHub could
+    /// not be asked for the release. `error` is returned as is without a locked release, or
+    /// with `--strict-resolve`.
+    fn locked_release(&self, error: eyre::Report, pb: &ProgressBar) -> eyre::Result<String> {
+        let Some(reason) = github::unavailable(&error) else {
+            return Err(error);
+        };
+        let Some(url) = self.locked.as_ref().and_then(|locked| locked.url.clone()) else {
+            return Err(error);
+        };
+        let version = self
+            .locked
+            .as_ref()
+            .and_then(|locked| locked.version.as_deref())
+            .unwrap_or("release");
+        pb.println(format!(
+            "warning: using locked {} {}; upstream check skipped: {}",
+            self.package.name(),
+            version,
+            reason
+        ));
+        self.locked_fallback.set(true);
+        Ok(url)
+    }
+
+    /// Whether the package is already installed from `source` as configured, so that downloading
+    /// it again is pointless. `--force` always installs.
+    fn up_to_date(&self, source: &str, pb: &ProgressBar) -> eyre::Result<bool> {
+        if self.force {
+            return Ok(false);
+        }
+        let path = package_path(&self.destination, &self.package)?;
+        let current = installed_from(&self.manifest, &self.package, &path, source);
+        if current {
+            pb.finish_with_message(format!("{} is up to date", self.package.name()));
+        }
+        Ok(current)
+    }
+
+    /// The asset at `source`, a URL of the package as configured, opened in place for local
+    /// archives and downloaded otherwise.
+    ///
+    /// The memory the package needs is reserved from the budget before anything is extracted
+    /// from the asset, and held by `reservation` until the package is installed.
+    fn fetch(
+        &self,
+        source: &str,
+        pb: &ProgressBar,
+        reservation: &budget::Reservation,
+    ) -> eyre::Result<asset::Asset> {
+        let source = self.package.expand_url(source)?;
+        self.fetch_from(&source, &source, pb, reservation)
+    }
+
+    /// The asset published at `source`, fetched from `location` where that differs, as for
+    /// private GitHub assets. Signatures and checksum files are found from `source`.
+    fn fetch_from(
+        &self,
+        source: &str,
+        location: &str,
+        pb: &ProgressBar,
+        reservation: &budget::Reservation,
+    ) -> eyre::Result<asset::Asset> {
+        let reserve = |size: u64| {
+            reservation.grow(size, |used, limit| {
+                pb.set_message(format!(
+                    "Waiting for memory, {} of {} in use",
+                    budget::format_bytes(used),
+                    budget::format_bytes(limit)
+                ))
+            })
+        };
+        let asset = match local::local_path(location) {
+            Some(path) => {
+                let owner = format!("package {}", self.package.name());
+                let path = expand::expand_source_path(path, expand::Field::new("archive", &owner))?;
+                let asset = asset::Asset::open(&path)?;
+                verify_pin(location, self.package.options(), &asset)?;
+                asset
+            }
+            None => {
+                let _slot = http::runtime().block_on(self.downloads.acquire(|limit| {
+                    pb.set_message(format!("Waiting for a download slot, {} running", limit))
+                }));
+                pb.set_message(format!("Downloading {}", self.package.name()));
+                self.fetch_remote(location, pb, &reserve)?
+            }
+        };
+        verify_sha256(source, self.package.options(), &asset)?;
+        verify_signature(source, &self.package, &self.download, &asset)?;
+        verify_checksum(source, &self.package, &self.download, &asset)?;
+        reserve(asset.len());
+        Ok(asset)
+    }
+
+    fn fetch_remote(
+        &self,
+        url: &str,
+        pb: &ProgressBar,
+        reserve: &dyn Fn(u64),
+    ) -> eyre::Result<asset::Asset> {
+        let options = &self.package.expanded_options()?;
+        let Some(zsync_url) = &options.zsync_url else {
+            let (asset, cached) = fetch_asset(url, options, &self.download, &self.cache, pb)?;
+            self.cache_hit.set(cached);
+            return Ok(asset);
+        };
+        let pinned_in_cache = options
+            .pin_hash
+            .as_ref()
+            .is_some_and(|pin| self.cache.contains(pin));
+        if !pinned_in_cache && !self.download.offline {
+            if let Some(asset) = self.fetch_delta(url, zsync_url, pb, reserve) {
+                return Ok(asset);
+            }
+        }
+        let (asset, cached) = fetch_asset(url, options, &self.download, &self.cache, pb)?;
+        self.cache_hit.set(cached);
+        self.keep_for_delta(&asset, pb);
+        Ok(asset)
+    }
+
+    /// The asset at `url` rebuilt from the release kept in the cache, `None` when there is no
+    /// earlier release or anything goes wrong, which means downloading all of it
+    fn fetch_delta(
+        &self,
+        url: &str,
+        zsync_url: &str,
+        pb: &ProgressBar,
+        reserve: &dyn Fn(u64),
+    ) -> Option<asset::Asset> {
+        let seed = self.cache.latest(self.package.name()).ok()??;
+        pb.set_message(format!(
+            "Updating {} from the cached release",
+            self.package.name()
+        ));
+        let headers = self.download.header_map().ok()?;
+        let client = download::Client::new(&headers);
+        let delta = zsync::download(&client, url, zsync_url, &seed, pb, reserve).ok()?;
+        drop(seed);
+        if let Some(pin) = &self.package.options().pin_hash {
+            if digest::sha256(&delta.bytes) != *pin {
+                return None;
+            }
+        }
+        let asset = asset::Asset::from_bytes(&delta.bytes).ok()?;
+        self.delta_saved.set(Some(delta.saved()));
+        drop(delta);
+        self.keep_for_delta(&asset, pb);
+        Some(asset)
+    }
+
+    /// Keep `asset` in the cache as the release the next delta update starts from
+    fn keep_for_delta(&self, asset: &asset::Asset, pb: &ProgressBar) {
+        let kept = self
+            .cache
+            .put(asset)
+            .and_then(|hash| self.cache.set_latest(self.package.name(), &hash));
+        if let Err(e) = kept {
+            pb.println(format!(
+                "warning: {} will be downloaded in full next time: {:?}",
+                self.package.name(),
+                e
+            ));
+        }
+    }
+}
+
+/// Install the `[workstation.rustup]` toolchains, components and targets that are missing,
+/// reporting each to `out`. Returns how many changes failed, every one of them when `offline`.
+fn sync_rustup(config: &Config, offline: bool, out: &mut dyn Write) -> eyre::Result<usize> {
+    let steps = match rustup::plan(&config.rustup) {
+        Ok(steps) => steps,
+        Err(e) => {
+            eprintln!("Error checking [workstation.rustup]: {:#}", e);
+            return Ok(1);
+        }
+    };
+    let mut failed = 0;
+    for step in &steps {
+        let applied = match offline {
+            true => Err(download::offline_error("rustup")),
+            false => rustup::apply(step),
+        };
+        match applied {
+            Ok(()) => writeln!(out, "{} {}", step.verbs().1, step)?,
+            Err(e) => {
+                eprintln!(
+                    "Error with rustup, could not {} {}: {:#}",
+                    step.verbs().0,
+                    step,
+                    e
+                );
+                failed += 1;
+            }
+        }
+    }
+    Ok(failed)
+}
+
+/// Create the `[workstation.dotfiles]` links and write the templates that are not in place,
+/// reporting each to `out`. Returns how many could not be created.
+fn link_dotfiles(config: &Config, out: &mut dyn Write) -> eyre::Result<usize> {
+    let mut failed = 0;
+    let state = paths::Paths::resolve(Some(&config.paths))?.state.path;
+    let mut written = dotfiles::Written::load(&state);
+    for link in dotfiles::links(&config.dotfiles)? {
+        match dotfiles::apply(&link, &config.dotfiles.variables, &mut written) {
+            Ok(dotfiles::Applied::Unchanged) => {}
+            Ok(dotfiles::Applied::Linked { backup }) => {
+                write!(
+                    out,
+                    "{} {} {} {}",
+                    if link.template { "Wrote" } else { "Linked" },
+                    link.target.display(),
+                    if link.template { "from" } else { "to" },
+                    link.source.display()
+                )?;
+                match backup {
+                    Some(backup) => writeln!(out, ", what was there is now {}", backup.display())?,
+                    None => writeln!(out)?,
+                }
+            }
+            Err(e) => {
+                eprintln!("Error linking {}: {:#}", link.target.display(), e);
+                failed += 1;
+            }
+        }
+    }
+    written.save()?;
+    Ok(failed)
+}
+
+/// Every `[workstation.dotfiles]` link and template with where it stands
+fn dotfile_states(config: &Config) -> eyre::Result<Vec<(dotfiles::Link, dotfiles::State)>> {
+    dotfiles::links(&config.dotfiles)?
+        .into_iter()
+        .map(|link| {
+            let state = dotfiles::state(&link, &config.dotfiles.variables)?;
+            Ok((link, state))
+        })
+        .collect()
+}
+
+/// Run the `[workstation.hooks]` commands `name` of `setup`
+fn run_setup_hooks(config: &Config, name: &str, hooks: &[String]) -> eyre::Result<()> {
+    if hooks.is_empty() {
+        return Ok(());
+    }
+    let directory = install_dir(&config.platform.location)?;
+    for hook in hooks {
+        custom::run_hook(hook, &directory, custom::DEFAULT_TIMEOUT)
+            .with_context(|| format!("Running {}", name))?;
+    }
+    Ok(())
+}
+
+/// Run the `post_install` commands of `package`, installed in `location`
+fn run_post_install(
+    package: &PackageConfig,
+    location: &Path,
+    pb: &ProgressBar,
+) -> eyre::Result<()> {
+    let hooks = &package.options().post_install;
+    if hooks.is_empty() {
+        return Ok(());
+    }
+    let directory = install_dir(location)?;
+    for hook in hooks {
+        pb.set_message(format!("Running `{}` for {}", hook, package.name()));
+        custom::run_hook(hook, &directory, custom::DEFAULT_TIMEOUT)
+            .with_context(|| "Running post_install")?;
+    }
+    pb.finish_with_message(format!("Installed {}", package.name()));
+    Ok(())
+}
+
+/// Install the system packages that are missing with one run of each package manager, so that
+/// sudo asks for the password at most once. Returns the names of the packages that failed.
+fn install_system(
+    packages: &[&PackageConfig],
+    location: &Path,
+    offline: bool,
+    reporter: &Reporter,
+) -> Vec<String> {
+    let mut failed = vec![];
+    let mut fail = |name: &str, error: eyre::Report| {
+        reporter.record(PackageReport::failed(name, &error));
+        failed.push(name.to_string());
+    };
+
+    let mut missing: std::collections::BTreeMap<system::Manager, Vec<(&PackageConfig, &str)>> =
+        std::collections::BTreeMap::new();
+    for package in packages {
+        let Some((manager, system_name)) = system::resolve(package) else {
+            fail(
+                package.name(),
+                eyre::eyre!(
+                    "This machine has none of the package managers it names, {} are on PATH",
+                    match system::Manager::available() {
+                        [] => "none".to_string(),
+                        available => available
+                            .iter()
+                            .map(|manager| manager.to_string())
+                            .collect::<Vec<_>>()
+                            .join(", "),
+                    }
+                ),
+            );
+            continue;
+        };
+        match manager.is_installed(system_name) {
+            Ok(true) => reporter.record(PackageReport::skipped(
+                package.name(),
+                &format!("installed with {}", manager),
+            )),
+            Ok(false) => missing
+                .entry(manager)
+                .or_default()
+                .push((*package, system_name)),
+            Err(e) => fail(package.name(), e),
+        }
+    }
+
+    for (manager, missing) in missing {
+        let names: Vec<_> = missing.iter().map(|(_, name)| *name).collect();
+        if offline {
+            for (package, name) in &missing {
+                fail(
+                    package.name(),
+                    download::offline_error(format!("Installing {} with {}", name, manager)),
+                );
+            }
+            continue;
+        }
+        eprintln!("Installing {} with {}", names.join(" "), manager);
+        if let Err(e) = manager.install(&names) {
+            for (package, _) in &missing {
+                fail(package.name(), eyre::eyre!("{:#}", e));
+            }
+            continue;
+        }
+        for (package, _) in missing {
+            match run_post_install(package, location, &ProgressBar::hidden()) {
+                Ok(()) => reporter.record(PackageReport::installed(package.name())),
+                Err(e) => fail(package.name(), e),
+            }
+        }
+    }
+    failed
+}
+
+/// Install the `.deb` and `.rpm` packages that are missing or at another version than their
+/// file, with one run of dpkg or rpm each, so that sudo asks for the password at most once.
+/// Returns the names of the packages that failed.
+fn install_distro(
+    config: &Config,
+    packages: &[&PackageConfig],
+    force: bool,
+    offline: bool,
+    reporter: &Reporter,
+) -> Vec<String> {
+    let mut failed = vec![];
+    let mut fail = |name: &str, error: eyre::Report| {
+        reporter.record(PackageReport::failed(name, &error));
+        failed.push(name.to_string());
+    };
+    if packages.is_empty() {
+        return failed;
+    }
+    // The files only need to be there until they are installed
+    let directory = match tempfile::tempdir() {
+        Ok(directory) => directory,
+        Err(e) => {
+            for package in packages {
+                fail(
+                    package.name(),
+                    eyre::eyre!("Creating a download directory: {}", e),
+                );
+            }
+            return failed;
+        }
+    };
+
+    let mut missing: std::collections::BTreeMap<distro::Format, Vec<(&PackageConfig, PathBuf)>> =
+        std::collections::BTreeMap::new();
+    for package in packages {
+        match fetch_distro(config, package, directory.path(), force, offline) {
+            Ok((format, Some(file))) => missing.entry(format).or_default().push((*package, file)),
+            Ok((format, None)) => reporter.record(PackageReport::skipped(
+                package.name(),
+                &format!("installed with {}", format),
+            )),
+            Err(e) => fail(package.name(), e),
+        }
+    }
+
+    for (format, missing) in missing {
+        let files: Vec<_> = missing.iter().map(|(_, file)| file.clone()).collect();
+        let names: Vec<_> = missing.iter().map(|(package, _)| package.name()).collect();
+        eprintln!("Installing {} with {}", names.join(" "), format);
+        if let Err(e) = format.install(&files) {
+            for (package, _) in &missing {
+                fail(package.name(), eyre::eyre!("{:#}", e));
+            }
+            continue;
+        }
+        for (package, _) in missing {
+            match run_post_install(package, &config.platform.location, &ProgressBar::hidden()) {
+                Ok(()) => reporter.record(PackageReport::installed(package.name())),
+                Err(e) => fail(package.name(), e),
+            }
+        }
+    }
+    failed
+}
+
+/// The format of the distribution package `package` and its file downloaded into `directory`,
+/// no file when the version of the file is installed already. The configured version is trusted
+/// without downloading anything when it is installed.
+fn fetch_distro(
+    config: &Config,
+    package: &PackageConfig,
+    directory: &Path,
+    force: bool,
+    offline: bool,
+) -> eyre::Result<(distro::Format, Option<PathBuf>)> {
+    let PackageConfig::Distro { name, version, .. } = package else {
+        eyre::bail!("{} is no distribution package", package.name());
+    };
+    let Some((format, url)) = distro::resolve(package) else {
+        eyre::bail!(
+            "This machine cannot install the files of {}, neither dpkg for `deb` nor rpm for \
+             `rpm` is on PATH with a file for it",
+            name
+        );
+    };
+    let installed = format.installed_version(distro::package_name(package))?;
+    if let (false, Some(installed), Some(version)) = (force, &installed, version) {
+        if distro::is_version(installed, version) {
+            return Ok((format, None));
+        }
+    }
+
+    let download = download::DownloadOptions {
+        offline,
+        ..package.download_options(&config.download)
+    };
+    let asset = read_asset(config, package, url, &download)?;
+    let url = package.expand_url(url)?;
+    verify_sha256(&url, package.options(), &asset)?;
+    verify_signature(&url, package, &download, &asset)?;
+    verify_checksum(&url, package, &download, &asset)?;
+    let path = directory.join(format!("{}.{}", name, format.extension()));
+    let mut file =
+        std::fs::File::create(&path).with_context(|| format!("Creating {}", path.display()))?;
+    std::io::copy(&mut asset.reader()?, &mut file)
+        .with_context(|| format!("Writing {}", path.display()))?;
+
+    let (file_name, file_version) = format.file_version(&path)?;
+    let current = format.installed_version(&file_name)?;
+    if !force && current.as_deref() == Some(file_version.as_str()) {
+        return Ok((format, None));
+    }
+    Ok((format, Some(path)))
+}
+
+/// Run the command of a custom package, returning whether it ran.
+///
+/// The command is skipped when `creates` already exists, unless `force` is set.
+fn run_custom_command(
+    name: &str,
+    command: &config::CommandLine,
+    creates: &str,
+    timeout: Option<u64>,
+    force: bool,
+    pb: &ProgressBar,
+) -> eyre::Result<bool> {
+    let owner = format!("package {}", name);
+    let creates = expand::expand_path(creates, expand::Field::new("creates", &owner))?;
+    if creates.exists() && !force {
+        pb.finish_with_message(format!("{} is already present", name));
+        return Ok(false);
+    }
+
+    pb.set_message(format!("Running the custom command of {}", name));
+    let timeout = timeout
+        .map(std::time::Duration::from_secs)
+        .unwrap_or(custom::DEFAULT_TIMEOUT);
+    custom::run(command, &creates, timeout)?;
+    pb.finish_with_message(format!("Ran the custom command of {}", name));
+
+    Ok(true)
+}
+
+/// Download the asset at `url` unless the cache has it, returning whether it came from the
+/// cache. When the download fails, the `mirrors` of the package are tried in order.
+///
+/// A cached copy with the `pin_hash` digest is used as is, and a download with different
+/// content is refused. Without a pin, the copy last downloaded from one of the URLs is used
+/// while its server says it did not change since, or without asking when offline.
+fn fetch_asset(
+    url: &str,
+    options: &config::PackageOptions,
+    download: &download::DownloadOptions,
+    cache: &cache::Cache,
+    pb: &ProgressBar,
+) -> eyre::Result<(asset::Asset, bool)> {
+    let sources: Vec<&str> = std::iter::once(url)
+        .chain(options.mirrors.iter().map(String::as_str))
+        .collect();
+    let mut cached = None;
+    match &options.pin_hash {
+        Some(pin) => cached = cache.open(pin)?,
+        None => {
+            for source in &sources {
+                if let Some((hash, validators)) = cache.for_url(source)? {
+                    if download.offline || download::unchanged(source, &validators, download) {
+                        cached = cache.open(&hash)?;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    if let Some(asset) = cached {
+        pb.set_length(asset.len());
+        pb.set_position(asset.len());
+        pb.set_message(format!("Reusing {} from the cache", sops::redact(url)));
+        return Ok((asset, true));
+    }
+    if download.offline {
+        return Err(download::offline_error(format!(
+            "{} is not in the download cache, downloading it",
+            sops::redact(url)
+        )));
+    }
+
+    let mut failure = None;
+    for (index, source) in sources.iter().enumerate() {
+        if index > 0 {
+            pb.set_position(0);
+            pb.set_message(format!("Downloading from {}", sops::redact(source)));
+        }
+        // A mirror serving other content than the pin is skipped like one that is down
+        let fetched = download::download_with_progress(source, pb, download).and_then(|fetched| {
+            verify_pin(source, options, &fetched.asset)?;
+            Ok(fetched)
+        });
+        match fetched {
+            Ok(fetched) => {
+                keep_in_cache(source, options, &fetched, cache, pb);
+                return Ok((fetched.asset, false));
+            }
+            Err(e) => {
+                if index + 1 < sources.len() {
+                    pb.println(format!(
+                        "warning: {}, trying the next mirror",
+                        sops::redact(&e.to_string())
+                    ));
+                }
+                failure = Some(e);
+            }
+        }
+    }
+
+    Err(failure.expect("the url itself is always tried"))
+}
+
+/// Keep a download from `source` in the cache, as far as it can be reused later
+fn keep_in_cache(
+    source: &str,
+    options: &config::PackageOptions,
+    fetched: &download::Download,
+    cache: &cache::Cache,
+    pb: &ProgressBar,
+) {
+    // Without a pin or validators, there is no telling whether a cached copy is still current
+    if options.pin_hash.is_none() && fetched.validators.is_empty() {
+        return;
+    }
+    let kept = cache
+        .put(&fetched.asset)
+        .and_then(|hash| match fetched.validators.is_empty() {
+            true => Ok(()),
+            false => cache.remember_url(source, &hash, &fetched.validators),
+        });
+    if let Err(e) = kept {
+        pb.println(format!(
+            "warning: could not cache {}: {:?}",
+            sops::redact(source),
+            e
+        ));
+    }
+}
+
+/// Refuse `asset` read from `source` unless it has the digest of `pin_hash`, if it is set.
+///
+/// Nothing is extracted before this passes, which is why downloads are spooled to disk rather
+/// than streamed straight into the decoders.
+fn verify_pin(
+    source: &str,
+    options: &config::PackageOptions,
+    asset: &asset::Asset,
+) -> eyre::Result<()> {
+    let Some(pin) = &options.pin_hash else {
+        return Ok(());
+    };
+    let actual = asset.hash()?;
+    if actual != *pin {
+        return Err(ErrorCode::PinnedContentChanged.error(format!(
+            "{} does not have the pinned content: pinned {}, got {}\nIf the change is intended, update pin_hash and run `workstation lock update`",
+            source, pin, actual
+        )));
+    }
+    Ok(())
+}
+
+/// Refuse `asset` read from `source` unless it has the package's `sha256`, if it is set. Local
+/// archives are checked too, nothing is extracted before this passes.
+fn verify_sha256(
+    source: &str,
+    options: &config::PackageOptions,
+    asset: &asset::Asset,
+) -> eyre::Result<()> {
+    let Some(sha256) = &options.sha256 else {
+        return Ok(());
+    };
+    let expected = digest::from_hex(sha256)?;
+    let actual = asset.hash()?;
+    if actual != expected {
+        return Err(ErrorCode::ChecksumMismatch.error(format!(
+            "{} does not match the sha256 of the package: expected {}, got {}",
+            sops::redact(source),
+            expected.trim_start_matches("sha256:"),
+            actual.trim_start_matches("sha256:")
+        )));
+    }
+    Ok(())
+}
+
+/// Check the `signature` of `asset`, downloaded from `source`, against the `public_key`
+fn verify_signature(
+    source: &str,
+    package: &PackageConfig,
+    download: &download::DownloadOptions,
+    asset: &asset::Asset,
+) -> eyre::Result<()> {
+    let options = package.expanded_options()?;
+    let (Some(signature), Some(public_key)) = (&options.signature, &options.public_key) else {
+        return Ok(());
+    };
+    let key = signature::PublicKey::parse(public_key).context("Reading public_key")?;
+    let location = signature.replace("{url}", source);
+    let content = fetch_text(&location, "signature", package, download)?;
+    signature::verify(&key, &content, asset).map_err(|e| {
+        ErrorCode::SignatureInvalid.error(format!(
+            "{} is not signed with the {} public_key: {:#}",
+            sops::redact(source),
+            key.kind(),
+            e
+        ))
+    })
+}
+
+/// Check the asset against the digest the package's `checksum_url` lists for it
+fn verify_checksum(
+    source: &str,
+    package: &PackageConfig,
+    download: &download::DownloadOptions,
+    asset: &asset::Asset,
+) -> eyre::Result<()> {
+    let options = package.expanded_options()?;
+    let Some(checksum_url) = &options.checksum_url else {
+        return Ok(());
+    };
+    let location = checksum_url.replace("{url}", source);
+    let content = fetch_text(&location, "checksum_url", package, download)?;
+    let file_name = checksums::file_name(source);
+    let listed = checksums::find(&content, file_name).ok_or_else(|| {
+        eyre::eyre!(
+            "The checksum file {} lists no digest for {}",
+            sops::redact(&location),
+            file_name
+        )
+    })?;
+    checksums::verify(&listed, asset).map_err(|e| {
+        ErrorCode::ChecksumMismatch.error(format!(
+            "{} does not match its checksum file: {:#}",
+            sops::redact(source),
+            e
+        ))
+    })
+}
+
+/// Download the asset `package` is installed from now, bypassing the cache, and check it against
+/// the `sha256`, `pin_hash` and `checksum_url` it sets, for `check --verify-checksums`
+fn verify_digests(config: &Config, package: &PackageConfig) -> eyre::Result<()> {
+    http::init(&config.download)?;
+    let download = package.download_options(&config.download);
+    let source = match package {
+        PackageConfig::GithubRelease {
+            repo,
+            version,
+            asset_pattern,
+            ..
+        } => {
+            github::resolve(
+                repo,
+                version.as_deref(),
+                asset_pattern.as_deref(),
+                download.github_token()?.as_deref(),
+            )?
+            .0
+            .browser_download_url
+        }
+        _ => package.expand_url(package.url().expect("asset packages have a URL"))?,
+    };
+    let asset = match local::local_path(&source) {
+        Some(path) => {
+            let owner = format!("package {}", package.name());
+            let path = expand::expand_source_path(path, expand::Field::new("archive", &owner))?;
+            asset::Asset::open(&path)?
+        }
+        None => {
+            let headers = download.header_map()?;
+            let bytes = download::Client::new(&headers)
+                .get(&source)
+                .send()
+                .and_then(|response| response.error_for_status())
+                .and_then(|response| response.bytes())
+                .with_context(|| format!("Downloading {}", sops::redact(&source)))?;
+            asset::Asset::from_bytes(&bytes)?
+        }
+    };
+    let options = package.expanded_options()?;
+    verify_sha256(&source, &options, &asset)?;
+    verify_pin(&source, &options, &asset)?;
+    verify_checksum(&source, package, &download, &asset)
+}
+
+/// A small file that goes with the asset, such as its signature, read from `location` on disk
+/// or downloaded with the package's headers. `field` names the setting it came from.
+fn fetch_text(
+    location: &str,
+    field: &str,
+    package: &PackageConfig,
+    download: &download::DownloadOptions,
+) -> eyre::Result<String> {
+    match local::local_path(location) {
+        Some(path) => {
+            let owner = format!("package {}", package.name());
+            let path = expand::expand_source_path(path, expand::Field::new(field, &owner))?;
+            std::fs::read_to_string(&path)
+                .with_context(|| format!("Reading the {} {}", field, path.display()))
+        }
+        None if download.offline => Err(download::offline_error(format!(
+            "Downloading the {} {}",
+            field,
+            sops::redact(location)
+        ))),
+        None => {
+            let headers = download.header_map()?;
+            download::Client::new(&headers)
+                .get(location)
+                .send()
+                .and_then(|response| response.error_for_status())
+                .and_then(|response| response.text())
+                .with_context(|| format!("Downloading the {} {}", field, sops::redact(location)))
+        }
+    }
+}
+
+/// Whether the manifest records `package` as installed at `path` from `source`, with the config
+/// it has now, and the installed file is still as it was written
+fn installed_from(
+    manifest: &manifest::Manifest,
+    package: &PackageConfig,
+    path: &Path,
+    source: &str,
+) -> bool {
+    let Some(installed) = manifest.get(package.name()) else {
+        return false;
+    };
+    if installed.path != path
+        || installed.source.as_deref() != Some(sops::redact(source).as_str())
+        || installed.fields_hash.as_deref() != Some(lock::fields_hash(package).as_str())
+        || !installed.files.iter().all(|file| file.exists())
+    {
+        return false;
+    }
+    // What custom commands and directories hold is not hashed, see `status`
+    if !package.is_single_file() {
+        return path.exists();
+    }
+    std::fs::read(path).is_ok_and(|data| digest::sha256(&data) == installed.hash)
+}
+
+/// The expanded install location
+fn install_dir(location: &Path) -> eyre::Result<PathBuf> {
+    expand::expand_path(
+        location.to_str().expect("string path"),
+        expand::Field::new("location", "the platform section"),
+    )
+}
+
+fn get_install_path(location: &Path, name: &str) -> eyre::Result<PathBuf> {
+    name::validate(name)
+        .map_err(|reason| eyre::eyre!("Invalid package name {:?}: {}", name, reason))?;
+    Ok(install_dir(location)?.join(name))
+}
+
+/// Whether `package` is installed at `path`, or for system packages, by the package manager
+fn is_present(path: &Path, package: &PackageConfig) -> eyre::Result<bool> {
+    match package {
+        PackageConfig::System { .. } => system::is_installed(package),
+        PackageConfig::Distro { .. } => Ok(distro::installed_version(package)?.is_some()),
+        PackageConfig::Pipx { .. }
+        | PackageConfig::Npm { .. }
+        | PackageConfig::Flatpak { .. }
+        | PackageConfig::Snap { .. } => global::is_installed(package),
+        _ => Ok(path.exists()),
+    }
+}
+
+/// Where `package` ends up, custom commands decide that themselves through `creates`, directory
+/// packages through `directory` and git clones through `dest`. System, pipx and npm packages have
+/// no path of their own, they are shown as the package manager and name, such as
+/// `apt:build-essential`.
+fn package_path(location: &Path, package: &PackageConfig) -> eyre::Result<PathBuf> {
+    match package {
+        PackageConfig::System { .. } => {
+            let named =
+                system::resolve(package).map(|(manager, name)| format!("{}:{}", manager, name));
+            Ok(PathBuf::from(named.unwrap_or_else(|| package.source())))
+        }
+        PackageConfig::Pipx { .. }
+        | PackageConfig::Npm { .. }
+        | PackageConfig::Flatpak { .. }
+        | PackageConfig::Snap { .. } => {
+            let (installer, tool, _) = global::Installer::of(package)
+                .expect("packages of other package managers have an installer");
+            Ok(PathBuf::from(format!("{}:{}", installer, tool)))
+        }
+        PackageConfig::Distro { .. } => {
+            let named = distro::resolve(package)
+                .map(|(format, _)| format!("{}:{}", format, distro::package_name(package)));
+            Ok(PathBuf::from(named.unwrap_or_else(|| package.source())))
+        }
+        PackageConfig::GitRepo { name, dest, .. } => {
+            let owner = format!("package {}", name);
+            expand::expand_path(dest, expand::Field::new("dest", &owner))
+        }
+        PackageConfig::Command { name, creates, .. } => {
+            let owner = format!("package {}", name);
+            expand::expand_path(creates, expand::Field::new("creates", &owner))
+        }
+        PackageConfig::Directory {
+            name, directory, ..
+        } => {
+            let owner = format!("package {}", name);
+            expand::expand_path(directory, expand::Field::new("directory", &owner))
+        }
+        _ => get_install_path(location, package.name()),
+    }
+}
+
+/// Install `data`, staged for `name` in `location`, with permissions `mode`
+fn install(
+    location: &Path,
+    name: &str,
+    data: staged::Staged,
+    mode: u32,
+    store: Option<&store::Store>,
+) -> eyre::Result<()> {
+    let path = get_install_path(location, name)?;
+    if let Some(store) = store {
+        return store::link(&store.put(name, &data, mode)?, &path);
+    }
+
+    // Renamed over the destination, so an interrupted install never leaves a truncated
+    // executable behind. The rename also replaces a link left from the store rather than
+    // writing through it into the store entry.
+    data.install(&path, mode)
+}
+
+/// Unpack `archive` next to `destination` and swap it in for whatever was there, so that a
+/// failed unpack leaves the previous directory untouched
+fn install_directory(
+    archive: &str,
+    asset: &asset::Asset,
+    strip_components: usize,
+    destination: &Path,
+) -> eyre::Result<()> {
+    let parent = destination.parent().expect("directory path has a parent");
+    let name = destination
+        .file_name()
+        .expect("directory path has a name")
+        .to_string_lossy();
+    std::fs::create_dir_all(parent).with_context(|| format!("Creating {}", parent.display()))?;
+
+    let unpacked = tempfile::Builder::new()
+        .prefix(&format!(".{}.workstation-", name))
+        .tempdir_in(parent)
+        .with_context(|| format!("Creating a directory in {}", parent.display()))?;
+    if archive::unpack(archive, asset.reader()?, strip_components, unpacked.path())? == 0 {
+        return Err(ErrorCode::EntryNotFound.error(format!(
+            "No file of {} is left after stripping {} path component(s)",
+            archive, strip_components
+        )));
+    }
+    std::fs::set_permissions(unpacked.path(), std::fs::Permissions::from_mode(0o755))?;
+
+    let previous = parent.join(format!(".{}.workstation-previous", name));
+    let _ = std::fs::remove_dir_all(&previous);
+    match std::fs::rename(destination, &previous) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e).with_context(|| format!("Moving {} aside", destination.display())),
+    }
+    std::fs::rename(unpacked.into_path(), destination)
+        .with_context(|| format!("Moving {} into place", destination.display()))?;
+    let _ = std::fs::remove_dir_all(&previous);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_install_path() {
+        let location = PathBuf::from("~/.local/bin");
+        let name = "test";
+        let expected = PathBuf::from(std::env::var("HOME").unwrap()).join(".local/bin/test");
+
+        let path = get_install_path(&location, name);
+
+        assert_eq!(path.unwrap(), expected);
+    }
+
+    /// Install `data`, staged as setup stages what it extracts
+    fn install_bytes(
+        location: &Path,
+        name: &str,
+        data: &[u8],
+        mode: u32,
+        store: Option<&store::Store>,
+    ) -> eyre::Result<()> {
+        let staged = staged::Staged::from_bytes(&get_install_path(location, name)?, data)?;
+        install(location, name, staged, mode, store)
+    }
+
+    #[test]
+    fn test_install_replaces_the_file_in_one_rename() {
+        let directory = tempfile::tempdir().unwrap();
+        let entry = directory.path().join("entry");
+        std::fs::write(&entry, "stored").unwrap();
+        let location = directory.path().join("bin");
+        std::fs::create_dir(&location).unwrap();
+        std::os::unix::fs::symlink(&entry, location.join("rg")).unwrap();
+
+        install_bytes(&location, "rg", b"rg 14", 0o755, None).unwrap();
+
+        let path = location.join("rg");
+        assert_eq!(std::fs::read(&path).unwrap(), b"rg 14");
+        assert_eq!(std::fs::read(&entry).unwrap(), b"stored");
+        let mode = path.metadata().unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o755);
+        assert_eq!(std::fs::read_dir(&location).unwrap().count(), 1);
+
+        // Data files are installed without the executable bit
+        install_bytes(&location, "rg.conf", b"--smart-case", 0o644, None).unwrap();
+        let mode = location
+            .join("rg.conf")
+            .metadata()
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o644);
+    }
+
+    #[test]
+    fn text_eza_archive() {
+        let bytes = eza_archive();
+        let archive = flate2::read::GzDecoder::new(std::io::Cursor::new(bytes));
+        let mut archive = tar::Archive::new(archive);
+        let entry = archive
+            .entries()
+            .unwrap()
+            .find(|entry| entry.as_ref().unwrap().path().unwrap().to_str().unwrap() == "eza");
+
+        assert!(entry.is_some());
+    }
+
+    #[test]
+    fn test_detected_package_names_are_sanitized() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("workstation.toml");
+        std::fs::write(
+            &path,
+            "[linux_x86_64]\nlocation = \"~/.local/bin\"\npackages = []\n",
+        )
+        .unwrap();
+        let archive = directory.path().join("tool.tar.gz");
+        std::fs::write(
+            &archive,
+            archive::tests::tar_gz(&[("tool/Foo_Bar.v1", b"binary")]),
+        )
+        .unwrap();
+        let binary = directory.path().join("Foo_Bar.v1");
+        std::fs::write(&binary, b"binary").unwrap();
+
+        add(&path, &archive.to_string_lossy(), None, None, false).unwrap();
+        add(&path, &binary.to_string_lossy(), None, None, false).unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(
+            text.contains(r#"name = "foo_bar.v1", bin = "tool/Foo_Bar.v1""#),
+            "{}",
+            text
+        );
+        assert!(text.contains(r#"name = "foo", url = ""#), "{}", text);
+    }
+
+    #[test]
+    fn test_rollback_swaps_in_the_previous_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let state = directory.path().join("state");
+        let location = directory.path().join("bin");
+        let config = config::parse_config_for(
+            &format!(
+                r#"
+                [workstation.paths]
+                state = "{}"
+
+                [linux_x86_64]
+                location = "{}"
+                packages = [{{ name = "rg", url = "https://example.com/rg-15" }}]
+                "#,
+                state.display(),
+                location.display()
+            ),
+            &arch::Target::parse("linux_x86_64").unwrap(),
+        )
+        .unwrap();
+        install_bytes(&location, "rg", b"rg 14", 0o755, None).unwrap();
+        let backups = backup::Backups::new(&state);
+        let record = manifest::Installed {
+            path: location.join("rg"),
+            hash: digest::sha256(b"rg 14"),
+            source: Some("https://example.com/rg-14".to_string()),
+            fields_hash: None,
+            files: vec![],
+            installed_at: None,
+        };
+        backups
+            .keep(
+                "rg",
+                &location.join("rg"),
+                &digest::sha256(b"rg 15"),
+                Some(record),
+            )
+            .unwrap();
+        backups.commit(&["rg".to_string()]).unwrap();
+        install_bytes(&location, "rg", b"rg 15", 0o755, None).unwrap();
+
+        let mut out = vec![];
+        rollback(&config, "rg", &mut out).unwrap();
+
+        assert_eq!(std::fs::read(location.join("rg")).unwrap(), b"rg 14");
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Rolled back rg to https://example.com/rg-14\n"
+        );
+        let installed = manifest::Manifest::load(&state).unwrap().get("rg").unwrap();
+        assert_eq!(installed.hash, digest::sha256(b"rg 14"));
+
+        rollback(&config, "rg", &mut vec![]).unwrap();
+        assert_eq!(std::fs::read(location.join("rg")).unwrap(), b"rg 15");
+        assert!(rollback(&config, "fd", &mut vec![]).is_err());
+    }
+
+    #[test]
+    fn test_unchanged_packages_are_not_swapped_in_again() {
+        let directory = tempfile::tempdir().unwrap();
+        let location = directory.path().join("bin");
+        let archive = directory.path().join("fd.tar.gz");
+        std::fs::write(&archive, archive::tests::tar_gz(&[("fd", b"fd 10")])).unwrap();
+        let config = config::parse_config_for(
+            &format!(
+                r#"
+                [workstation.paths]
+                state = "{}"
+                cache = "{}"
+
+                [linux_x86_64]
+                location = "{}"
+                packages = [{{ name = "fd", bin = "fd", archive = "{}" }}]
+                "#,
+                directory.path().join("state").display(),
+                directory.path().join("cache").display(),
+                location.display(),
+                archive.display()
+            ),
+            &arch::Target::parse("linux_x86_64").unwrap(),
+        )
+        .unwrap();
+        let run = || {
+            let reporter = Arc::new(Reporter::with_stream(Box::new(std::io::sink())));
+            setup(
+                &config,
+                &reporter,
+                SetupOptions {
+                    rollback_on_failure: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            Arc::into_inner(reporter).unwrap().finish()
+        };
+
+        assert_eq!(run()[0].outcome, report::Outcome::Installed);
+        assert_eq!(run()[0].outcome, report::Outcome::Unchanged);
+        assert_eq!(std::fs::read(location.join("fd")).unwrap(), b"fd 10");
+        update(&config, &[], false, true).unwrap();
+    }
+
+    #[test]
+    fn test_offline_setup_installs_from_the_cache_only() {
+        let server = test_server::TestServer::start();
+        server.route(
+            "/tool",
+            test_server::Route {
+                headers: vec![("ETag".to_string(), "\"1\"".to_string())],
+                ..test_server::Route::ok(b"tool 1".to_vec())
+            },
+        );
+        // Every path below one directory, as `--sandbox` puts them
+        let sandbox = tempfile::tempdir().unwrap();
+        let config = |run: &str, packages: &str| {
+            config::parse_config_for(
+                &format!(
+                    r#"
+                    [workstation.paths]
+                    state = "{}"
+                    cache = "{}"
+
+                    [linux_x86_64]
+                    location = "{}"
+                    packages = [{}]
+                    "#,
+                    sandbox.path().join(run).join("state").display(),
+                    sandbox.path().join("cache").display(),
+                    sandbox.path().join(run).join("bin").display(),
+                    packages
+                ),
+                &arch::Target::parse("linux_x86_64").unwrap(),
+            )
+            .unwrap()
+        };
+        let run = |config: &Config, offline: bool| {
+            let reporter = Arc::new(Reporter::with_stream(Box::new(std::io::sink())));
+            setup(
+                config,
+                &reporter,
+                SetupOptions {
+                    offline,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            Arc::into_inner(reporter).unwrap().finish()
+        };
+        let tool = format!(r#"{{ name = "tool", url = "{}" }}"#, server.url("/tool"));
+        assert_eq!(
+            run(&config("online", &tool), false)[0].outcome,
+            report::Outcome::Installed
+        );
+        assert_eq!(server.requests().len(), 1);
+
+        let uncached = format!(
+            r#"{}, {{ name = "other", url = "{}" }}, {{ name = "rg", crate = "ripgrep" }}"#,
+            tool,
+            server.url("/other")
+        );
+        let reports = run(&config("offline", &uncached), true);
+
+        let report = |name: &str| reports.iter().find(|report| report.name == name).unwrap();
+        assert_eq!(report("tool").outcome, report::Outcome::Installed);
+        assert!(report("tool").cached);
+        for name in ["other", "rg"] {
+            assert_eq!(report(name).outcome, report::Outcome::Failed);
+            let error = report(name).error.as_deref().unwrap();
+            assert!(error.contains("[E007]"), "{}", error);
+        }
+        assert_eq!(
+            std::fs::read(sandbox.path().join("offline/bin/tool")).unwrap(),
+            b"tool 1"
+        );
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn test_failed_update_restores_the_previous_version() {
+        let directory = tempfile::tempdir().unwrap();
+        let location = directory.path().join("bin");
+        let archive = |name: &str| directory.path().join(format!("{}.tar.gz", name));
+        let config = config::parse_config_for(
+            &format!(
+                r#"
+                [workstation.paths]
+                state = "{}"
+                cache = "{}"
+
+                [linux_x86_64]
+                location = "{}"
+                packages = [
+                    {{ name = "fd", bin = "fd", archive = "{}" }},
+                    {{ name = "rg", bin = "rg", archive = "{}" }},
+                ]
+                "#,
+                directory.path().join("state").display(),
+                directory.path().join("cache").display(),
+                location.display(),
+                archive("fd").display(),
+                archive("rg").display()
+            ),
+            &arch::Target::parse("linux_x86_64").unwrap(),
+        )
+        .unwrap();
+        std::fs::write(archive("fd"), archive::tests::tar_gz(&[("fd", b"fd 9")])).unwrap();
+        std::fs::write(archive("rg"), archive::tests::tar_gz(&[("rg", b"rg 14")])).unwrap();
+        update(&config, &[], false, false).unwrap();
+        assert_eq!(std::fs::read(location.join("fd")).unwrap(), b"fd 9");
+
+        // fd has a new release, while the one of rg lost its binary
+        std::fs::write(archive("fd"), archive::tests::tar_gz(&[("fd", b"fd 10")])).unwrap();
+        std::fs::write(
+            archive("rg"),
+            archive::tests::tar_gz(&[("README", b"rg 15")]),
+        )
+        .unwrap();
+        assert!(update(&config, &[], false, true).is_err());
+        assert_eq!(std::fs::read(location.join("fd")).unwrap(), b"fd 9");
+        assert_eq!(std::fs::read(location.join("rg")).unwrap(), b"rg 14");
+
+        assert!(update(&config, &[], false, false).is_err());
+        assert_eq!(std::fs::read(location.join("fd")).unwrap(), b"fd 10");
+        assert_eq!(std::fs::read(location.join("rg")).unwrap(), b"rg 14");
+    }
+
+    #[test]
+    fn test_watch_prune_keeps_the_unchanged_packages() {
+        let directory = tempfile::tempdir().unwrap();
+        let state = directory.path().join("state");
+        let location = directory.path().join("bin");
+        let source = |packages: &str| {
+            format!(
+                r#"
+                [workstation.paths]
+                state = "{}"
+
+                [linux_x86_64]
+                location = "{}"
+                packages = [{}]
+                "#,
+                state.display(),
+                location.display(),
+                packages
+            )
+        };
+        let target = arch::Target::parse("linux_x86_64").unwrap();
+        let rg = r#"{ name = "rg", url = "https://example.com/rg-14" }"#;
+        let previous = config::parse_config_for(
+            &source(&format!(
+                r#"{}, {{ name = "fd", url = "https://example.com/fd-9" }}"#,
+                rg
+            )),
+            &target,
+        )
+        .unwrap();
+        let full = config::parse_config_for(
+            &source(&format!(
+                r#"{}, {{ name = "bat", url = "https://example.com/bat-1" }}"#,
+                rg
+            )),
+            &target,
+        )
+        .unwrap();
+        let manifest = manifest::Manifest::load(&state).unwrap();
+        for name in ["rg", "fd"] {
+            install_bytes(&location, name, name.as_bytes(), 0o755, None).unwrap();
+            manifest.stage(
+                name,
+                manifest::Staged {
+                    hash: digest::sha256(name.as_bytes()),
+                    source: None,
+                    fields_hash: None,
+                    path: None,
+                    location: None,
+                    files: vec![],
+                },
+            );
+        }
+        manifest.commit(&["rg".to_string(), "fd".to_string()], &location);
+        manifest.save().unwrap();
+
+        let changed = watch::changed_packages(Some(&previous), &full);
+        assert_eq!(changed.platform.packages.len(), 1);
+        prune(&full, false).unwrap();
+
+        assert!(location.join("rg").exists());
+        assert!(!location.join("fd").exists());
+        let manifest = manifest::Manifest::load(&state).unwrap();
+        assert!(manifest.get("rg").is_some());
+        assert!(manifest.get("fd").is_none());
+    }
+
+    #[test]
+    fn test_locked_release_is_installed_while_github_is_rate_limited() {
+        let server = test_server::TestServer::start();
+        let rate_limited = || test_server::Route {
+            headers: vec![("x-ratelimit-remaining".to_string(), "0".to_string())],
+            ..test_server::Route::status(403)
+        };
+        server.route("/repos/neovim/neovim/releases/latest", rate_limited());
+        server.route("/repos/neovim/neovim/releases/latest", rate_limited());
+        server.route("/nvim", test_server::Route::ok(b"nvim 0.10.1".to_vec()));
+        std::env::set_var("WORKSTATION_GITHUB_API", server.url(""));
+        let directory = tempfile::tempdir().unwrap();
+        let location = directory.path().join("bin");
+        let config = config::parse_config_for(
+            &format!(
+                r#"
+                [workstation.paths]
+                state = "{}"
+                cache = "{}"
+
+                [linux_x86_64]
+                location = "{}"
+                packages = [{{ name = "nvim", repo = "neovim/neovim" }}]
+                "#,
+                directory.path().join("state").display(),
+                directory.path().join("cache").display(),
+                location.display()
+            ),
+            &arch::Target::parse("linux_x86_64").unwrap(),
+        )
+        .unwrap();
+        let locked = [lock::LockEntry {
+            name: "nvim".to_string(),
+            source: "github:neovim/neovim@latest".to_string(),
+            fields_hash: lock::fields_hash(&config.platform.packages[0]),
+            resolved: lock::Resolved {
+                url: Some(server.url("/nvim")),
+                version: Some("v0.10.1".to_string()),
+                hash: None,
+            },
+        }];
+        let run = |locked| {
+            let reporter = Arc::new(Reporter::with_stream(Box::new(std::io::sink())));
+            setup(
+                &config,
+                &reporter,
+                SetupOptions {
+                    locked,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            Arc::into_inner(reporter).unwrap().finish()
+        };
+
+        // --strict-resolve passes no lockfile entries
+        let reports = run(&[]);
+        assert_eq!(reports[0].outcome, report::Outcome::Failed);
+        assert!(!location.join("nvim").exists());
+
+        let reports = run(&locked);
+        std::env::remove_var("WORKSTATION_GITHUB_API");
+        assert_eq!(reports[0].outcome, report::Outcome::Installed);
+        assert!(reports[0].locked_fallback);
+        assert_eq!(
+            std::fs::read(location.join("nvim")).unwrap(),
+            b"nvim 0.10.1"
+        );
+    }
+
+    #[test]
+    fn test_installed_package_is_not_downloaded_again() {
+        let directory = tempfile::tempdir().unwrap();
+        let config = config::parse_config_for(
+            r#"
+            [linux_x86_64]
+            location = "/unused"
+            packages = [{ name = "rg", url = "https://example.com/rg-14" }]
+            "#,
+            &arch::Target::parse("linux_x86_64").unwrap(),
+        )
+        .unwrap();
+        let package = &config.platform.packages[0];
+        let path = directory.path().join("rg");
+        std::fs::write(&path, "rg 14").unwrap();
+        let manifest = manifest::Manifest::load(directory.path()).unwrap();
+        manifest.stage(
+            "rg",
+            manifest::Staged {
+                hash: digest::sha256(b"rg 14"),
+                source: Some("https://example.com/rg-14".to_string()),
+                fields_hash: Some(lock::fields_hash(package)),
+                path: None,
+                location: None,
+                files: vec![],
+            },
+        );
+        manifest.commit(&["rg".to_string()], directory.path());
+
+        assert!(installed_from(
+            &manifest,
+            package,
+            &path,
+            "https://example.com/rg-14"
+        ));
+        assert!(!installed_from(
+            &manifest,
+            package,
+            &path,
+            "https://example.com/rg-15"
+        ));
+
+        std::fs::write(&path, "edited").unwrap();
+        assert!(!installed_from(
+            &manifest,
+            package,
+            &path,
+            "https://example.com/rg-14"
+        ));
+    }
+
+    #[test]
+    fn test_setup_hooks_run_in_order() {
+        let directory = tempfile::tempdir().unwrap();
+        let log = directory.path().join("log");
+        let config = config::parse_config_for(
+            &format!(
+                r#"
+                [workstation.hooks]
+                pre_setup = ["echo first > {log}", "echo second >> {log}"]
+                post_setup = ["echo 'no notifier' >&2; exit 1", "echo never >> {log}"]
+
+                [linux_x86_64]
+                location = "{location}"
+                packages = []
+                "#,
+                log = log.display(),
+                location = directory.path().display(),
+            ),
+            &arch::Target::parse("linux_x86_64").unwrap(),
+        )
+        .unwrap();
+
+        run_setup_hooks(&config, "pre_setup", &config.hooks.pre_setup).unwrap();
+        let error = run_setup_hooks(&config, "post_setup", &config.hooks.post_setup).unwrap_err();
+
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "first\nsecond\n");
+        assert!(format!("{:#}", error).starts_with("Running post_setup: "));
+        assert!(format!("{:#}", error).contains("no notifier"));
+    }
+
+    fn pinned(pin: &str) -> config::PackageOptions {
+        config::PackageOptions {
+            pin_hash: Some(pin.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_republished_asset_is_refused() {
+        let server = test_server::TestServer::start();
+        server.route("/rg", test_server::Route::ok(b"republished".to_vec()));
+        let directory = tempfile::tempdir().unwrap();
+        let cache = cache::Cache::new(directory.path());
+
+        let error = fetch_asset(
+            &server.url("/rg"),
+            &pinned(&digest::sha256(b"original")),
+            &Default::default(),
+            &cache,
+            &ProgressBar::hidden(),
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            error::code_of(&error),
+            Some(ErrorCode::PinnedContentChanged)
+        );
+        assert!(!cache.contains(&digest::sha256(b"republished")));
+    }
+
+    #[test]
+    fn test_assets_without_a_valid_signature_are_refused() {
+        let server = test_server::TestServer::start();
+        server.route(
+            "/tool.sig",
+            test_server::Route::ok(
+                "MEQCIBk+BMmAhMB5aKYB83qGPVSbj4ObFqZ1/iCSNN26r5o6AiAvTbE5iZLk7MIKlknTB/qbH8fGfzklISr/xIWuWMluDA==",
+            ),
+        );
+        let config = config::parse_config_for(
+            r#"
+            [linux_x86_64]
+            location = "/unused"
+            [[linux_x86_64.packages]]
+            name = "tool"
+            url = "https://example.com/tool"
+            signature = "{url}.sig"
+            public_key = """
+            -----BEGIN PUBLIC KEY-----
+            MFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEN8zaLTtF9crjMutwEW/Z0QqIqWsy
+            cfD2k9jr4Ey81b0Lqff7GBx6CI5cYLo2RYci95tdxikrjZUButYD81pUFQ==
+            -----END PUBLIC KEY-----
+            """
+            "#,
+            &arch::Target::parse("linux_x86_64").unwrap(),
+        )
+        .unwrap();
+        let package = &config.platform.packages[0];
+        let verify = |content: &[u8]| {
+            verify_signature(
+                &server.url("/tool"),
+                package,
+                &Default::default(),
+                &asset::Asset::from_bytes(content).unwrap(),
+            )
+        };
+
+        verify(b"tool 1.0\n").unwrap();
+
+        let error = verify(b"tool 1.0 with a backdoor\n").unwrap_err();
+        assert_eq!(error::code_of(&error), Some(ErrorCode::SignatureInvalid));
+        assert!(format!("{:#}", error).contains("not signed with the cosign public_key"));
+    }
+
+    #[test]
+    fn test_assets_are_checked_against_the_checksum_file() {
+        let server = test_server::TestServer::start();
+        server.route(
+            "/v1/SHASUMS256.txt",
+            test_server::Route::ok(format!(
+                "{}  tool-linux.tar.gz\n{}  tool-macos.tar.gz\n",
+                "0".repeat(64),
+                digest::validate(&digest::sha256(b"tool 1.0\n")).unwrap()
+            )),
+        );
+        let config = config::parse_config_for(
+            &format!(
+                r#"
+                [linux_x86_64]
+                location = "/unused"
+                packages = [
+                    {{ name = "tool", url = "https://example.com/unused", checksum_url = "{}" }},
+                ]
+                "#,
+                server.url("/v1/SHASUMS256.txt")
+            ),
+            &arch::Target::parse("linux_x86_64").unwrap(),
+        )
+        .unwrap();
+        let package = &config.platform.packages[0];
+        let verify = |file: &str, content: &[u8]| {
+            verify_checksum(
+                &format!("https://example.com/v1/{}", file),
+                package,
+                &Default::default(),
+                &asset::Asset::from_bytes(content).unwrap(),
+            )
+        };
+
+        verify("tool-macos.tar.gz", b"tool 1.0\n").unwrap();
+
+        let error = verify("tool-linux.tar.gz", b"tool 1.0\n").unwrap_err();
+        assert_eq!(error::code_of(&error), Some(ErrorCode::ChecksumMismatch));
+        let error = verify("tool-windows.zip", b"tool 1.0\n").unwrap_err();
+        assert!(format!("{:#}", error).contains("lists no digest for tool-windows.zip"));
+    }
+
+    #[test]
+    fn test_local_archives_are_checked_against_their_sha256() {
+        let directory = tempfile::tempdir().unwrap();
+        let archive = directory.path().join("eza.tar.gz");
+        std::fs::write(&archive, eza_archive()).unwrap();
+        let hex = digest::validate(&digest::sha256(&eza_archive()))
+            .unwrap()
+            .to_string();
+        let install = |sha256: &str, location: &Path| {
+            let config = config::parse_config_for(
+                &format!(
+                    r#"
+                    [workstation.paths]
+                    state = "{}"
+                    cache = "{}"
+
+                    [linux_x86_64]
+                    location = "{}"
+                    packages = [{{ name = "eza", bin = "eza", archive = "{}", sha256 = "{}" }}]
+                    "#,
+                    directory.path().join("state").display(),
+                    directory.path().join("cache").display(),
+                    location.display(),
+                    archive.display(),
+                    sha256
+                ),
+                &arch::Target::parse("linux_x86_64").unwrap(),
+            )
+            .unwrap();
+            let reporter = Arc::new(Reporter::with_stream(Box::new(std::io::sink())));
+            setup(&config, &reporter, Default::default()).unwrap();
+            Arc::into_inner(reporter).unwrap().finish()
+        };
+
+        let matching = directory.path().join("matching");
+        let reports = install(&hex.to_ascii_uppercase(), &matching);
+        assert_eq!(reports[0].outcome, report::Outcome::Installed);
+        assert_eq!(std::fs::read(matching.join("eza")).unwrap(), b"eza");
+
+        let mismatching = directory.path().join("mismatching");
+        let reports = install(&"0".repeat(64), &mismatching);
+        assert_eq!(reports[0].outcome, report::Outcome::Failed);
+        assert!(!mismatching.join("eza").exists());
+    }
+
+    #[test]
+    fn test_pinned_asset_comes_from_the_cache() {
+        let server = test_server::TestServer::start();
+        server.route("/rg", test_server::Route::ok(b"original".to_vec()));
+        let directory = tempfile::tempdir().unwrap();
+        let cache = cache::Cache::new(directory.path());
+        let options = pinned(&digest::sha256(b"original"));
+        let fetch = || {
+            fetch_asset(
+                &server.url("/rg"),
+                &options,
+                &Default::default(),
+                &cache,
+                &ProgressBar::hidden(),
+            )
+            .unwrap()
+        };
+
+        assert!(!fetch().1);
+        let (asset, cached) = fetch();
+        assert!(cached);
+        assert_eq!(asset.read().unwrap(), b"original");
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn test_mirrors_are_tried_in_order() {
+        let server = test_server::TestServer::start();
+        server.route("/down/rg", test_server::Route::status(404));
+        server.route("/other/rg", test_server::Route::ok(b"republished".to_vec()));
+        server.route("/mirror/rg", test_server::Route::ok(b"original".to_vec()));
+        let directory = tempfile::tempdir().unwrap();
+        let options = config::PackageOptions {
+            mirrors: vec![server.url("/other/rg"), server.url("/mirror/rg")],
+            ..pinned(&digest::sha256(b"original"))
+        };
+
+        let (asset, cached) = fetch_asset(
+            &server.url("/down/rg"),
+            &options,
+            &Default::default(),
+            &cache::Cache::new(directory.path()),
+            &ProgressBar::hidden(),
+        )
+        .unwrap();
+
+        assert_eq!(asset.read().unwrap(), b"original");
+        assert!(!cached);
+        let paths: Vec<_> = server.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(paths, ["/down/rg", "/other/rg", "/mirror/rg"]);
+    }
+
+    #[test]
+    fn test_unpinned_asset_is_reused_while_unchanged() {
+        let server = test_server::TestServer::start();
+        let release = |body: &[u8], etag: &str| test_server::Route {
+            headers: vec![("ETag".to_string(), etag.to_string())],
+            ..test_server::Route::ok(body.to_vec())
+        };
+        // Download, then the check before reusing it, then a new release
+        server.route("/rg", release(b"rg 13", "\"13\""));
+        server.route("/rg", release(b"rg 13", "\"13\""));
+        server.route("/rg", release(b"rg 14", "\"14\""));
+        let directory = tempfile::tempdir().unwrap();
+        let cache = cache::Cache::new(directory.path());
+        let fetch = || {
+            let (asset, cached) = fetch_asset(
+                &server.url("/rg"),
+                &Default::default(),
+                &Default::default(),
+                &cache,
+                &ProgressBar::hidden(),
+            )
+            .unwrap();
+            (asset.read().unwrap(), cached)
+        };
+
+        assert_eq!(fetch(), (b"rg 13".to_vec(), false));
+        assert_eq!(fetch(), (b"rg 13".to_vec(), true));
+        assert_eq!(fetch(), (b"rg 14".to_vec(), false));
+        let methods: Vec<_> = server
+            .requests()
+            .into_iter()
+            .map(|request| request.method)
+            .collect();
+        assert_eq!(methods, ["GET", "HEAD", "HEAD", "GET"]);
+        assert_eq!(server.requests()[1].headers["if-none-match"], "\"13\"");
+    }
+
+    /// Same layout as the eza release tarball: a single `eza` entry
+    fn eza_archive() -> Vec<u8> {
+        let encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        let data = b"eza";
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o755);
+        header.set_cksum();
+        builder.append_data(&mut header, "eza", &data[..]).unwrap();
+        builder.into_inner().unwrap().finish().unwrap()
     }
 }
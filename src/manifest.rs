@@ -0,0 +1,217 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use eyre::Context;
+use serde::{Deserialize, Serialize};
+
+const RECORDS_FILE: &str = "installed.json";
+
+/// The newest version of the records file this binary reads and writes, see [`crate::state`]
+const SCHEMA_VERSION: u32 = 1;
+
+/// A file workstation installed, as it was written
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Installed {
+    pub path: PathBuf,
+    pub hash: String,
+    /// Where the file came from, the asset URL a GitHub release resolved to or the archive path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Hash of the config fields it was installed with, see [`crate::lock::fields_hash`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fields_hash: Option<String>,
+    /// The other files of the package, such as the `files` of an archive package
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<PathBuf>,
+    /// When it was installed, in seconds since the epoch. Records from before it was kept have
+    /// none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub installed_at: Option<u64>,
+}
+
+/// A package file just written, recorded once [`Manifest::commit`] confirms the install
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Staged {
+    pub hash: String,
+    pub source: Option<String>,
+    pub fields_hash: Option<String>,
+    /// Where the package was installed when it is not a file named after it in the location
+    pub path: Option<PathBuf>,
+    /// The package's own install location, when it is not the one given to
+    /// [`Manifest::commit`]
+    pub location: Option<PathBuf>,
+    /// Names of the other files written into the location
+    pub files: Vec<String>,
+}
+
+/// The files installed by `setup`, kept in the state directory so that files deleted or edited
+/// by hand can be found later
+pub struct Manifest {
+    path: PathBuf,
+    records: Mutex<BTreeMap<String, Installed>>,
+    /// Packages being installed, kept once the install is final
+    staged: Mutex<BTreeMap<String, Staged>>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl Manifest {
+    /// Load the records, a missing file only means nothing is installed yet.
+    ///
+    /// A file that cannot be read is an error rather than an empty record: `setup` would
+    /// otherwise forget every package and `prune` would find nothing to remove.
+    pub fn load(state_dir: &Path) -> eyre::Result<Self> {
+        let path = state_dir.join(RECORDS_FILE);
+        let records = match std::fs::read(&path) {
+            Ok(bytes) => crate::state::parse(&bytes, SCHEMA_VERSION).with_context(|| {
+                format!(
+                    "Reading {}, move it aside to start with no packages recorded",
+                    path.display()
+                )
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e).with_context(|| format!("Reading {}", path.display())),
+        };
+        Ok(Manifest {
+            path,
+            records: Mutex::new(records),
+            staged: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    pub fn save(&self) -> eyre::Result<()> {
+        let directory = self.path.parent().expect("records path has a parent");
+        std::fs::create_dir_all(directory)
+            .with_context(|| format!("Creating {}", directory.display()))?;
+        let json = crate::state::to_json(&*lock(&self.records), SCHEMA_VERSION);
+        std::fs::write(&self.path, json).with_context(|| format!("Writing {}", self.path.display()))
+    }
+
+    pub fn get(&self, package: &str) -> Option<Installed> {
+        lock(&self.records).get(package).cloned()
+    }
+
+    /// Every recorded package, by name
+    pub fn entries(&self) -> BTreeMap<String, Installed> {
+        lock(&self.records).clone()
+    }
+
+    /// Forget a package that was uninstalled
+    pub fn remove(&self, package: &str) -> Option<Installed> {
+        lock(&self.records).remove(package)
+    }
+
+    /// Remember the file just written for a package
+    pub fn stage(&self, package: &str, staged: Staged) {
+        lock(&self.staged).insert(package.to_string(), staged);
+    }
+
+    /// Record the staged files of `installed`, which now live in `location`, dropping those of
+    /// rolled back packages
+    pub fn commit(&self, installed: &[String], location: &Path) {
+        let staged = std::mem::take(&mut *lock(&self.staged));
+        let mut records = lock(&self.records);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        for (package, staged) in staged {
+            if installed.contains(&package) {
+                let location = staged.location.as_deref().unwrap_or(location);
+                let path = staged.path.unwrap_or_else(|| location.join(&package));
+                records.insert(
+                    package,
+                    Installed {
+                        path,
+                        hash: staged.hash,
+                        source: staged.source,
+                        fields_hash: staged.fields_hash,
+                        files: staged
+                            .files
+                            .iter()
+                            .map(|file| location.join(file))
+                            .collect(),
+                        installed_at: Some(now),
+                    },
+                );
+            }
+        }
+    }
+}
+
+/// The packages recorded in the records file `bytes`
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_committed_installs_are_recorded() {
+        let directory = tempfile::tempdir().unwrap();
+        let manifest = Manifest::load(directory.path()).unwrap();
+        let staged = |hash: &str| Staged {
+            hash: hash.to_string(),
+            source: Some("https://example.com/fd".to_string()),
+            fields_hash: None,
+            path: None,
+            location: None,
+            files: vec!["fd-extra".to_string()],
+        };
+        manifest.stage("fd", staged("sha256:fd"));
+        manifest.stage("rg", staged("sha256:rg"));
+        manifest.commit(&["fd".to_string()], Path::new("/opt/bin"));
+        manifest.save().unwrap();
+
+        let manifest = Manifest::load(directory.path()).unwrap();
+        let mut fd = manifest.get("fd").unwrap();
+        assert!(fd.installed_at.is_some());
+        fd.installed_at = None;
+        assert_eq!(
+            Some(fd),
+            Some(Installed {
+                path: PathBuf::from("/opt/bin/fd"),
+                hash: "sha256:fd".to_string(),
+                source: Some("https://example.com/fd".to_string()),
+                fields_hash: None,
+                files: vec![PathBuf::from("/opt/bin/fd-extra")],
+                installed_at: None,
+            })
+        );
+        assert_eq!(manifest.get("rg"), None);
+        assert_eq!(manifest.entries().len(), 1);
+    }
+
+    #[test]
+    fn test_records_that_cannot_be_read_are_an_error() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join(RECORDS_FILE);
+        let record = r#"{"path": "/opt/bin/fd", "hash": "sha256:fd"}"#;
+
+        std::fs::write(&path, format!(r#"{{"fd": {}}}"#, record)).unwrap();
+        let manifest = Manifest::load(directory.path()).unwrap();
+        assert!(manifest.get("fd").is_some());
+        manifest.save().unwrap();
+        let saved: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(saved["schema_version"], SCHEMA_VERSION);
+
+        std::fs::write(&path, "{\"fd\": ").unwrap();
+        assert!(Manifest::load(directory.path()).is_err());
+        std::fs::write(
+            &path,
+            format!(
+                r#"{{"schema_version": 2, "records": {{"fd": {}}}}}"#,
+                record
+            ),
+        )
+        .unwrap();
+        let error = Manifest::load(directory.path()).err().unwrap();
+        assert!(format!("{:#}", error).contains("Upgrade workstation"));
+    }
+}
@@ -0,0 +1,191 @@
+use toml_edit::DocumentMut;
+
+/// A single upgrade step from schema version `from` to `from + 1`
+pub struct Migration {
+    pub from: u32,
+    pub description: &'static str,
+    pub apply: fn(&mut DocumentMut) -> eyre::Result<()>,
+}
+
+/// Describes a versioned file format understood by this binary
+pub struct Schema {
+    /// Human readable name used in error messages
+    pub kind: &'static str,
+    /// The newest version this binary reads and writes
+    pub current: u32,
+    /// Whether files without `schema_version` are rejected instead of treated as version 1
+    pub version_required: bool,
+    pub migrations: &'static [Migration],
+}
+
+pub const CONFIG_SCHEMA: Schema = Schema {
+    kind: "config",
+    current: 2,
+    version_required: false,
+    migrations: &[Migration {
+        from: 1,
+        description: "move the settings tables at the top level into [workstation]",
+        apply: hoist_settings,
+    }],
+};
+
+fn hoist_settings(document: &mut DocumentMut) -> eyre::Result<()> {
+    crate::config::hoist_settings(document)?;
+    Ok(())
+}
+
+pub const VERSION_KEY: &str = "schema_version";
+
+/// Read the schema version of a document
+pub fn version_of(schema: &Schema, document: &DocumentMut) -> eyre::Result<u32> {
+    match document.get(VERSION_KEY) {
+        None if schema.version_required => {
+            eyre::bail!("The {} file is missing `{}`", schema.kind, VERSION_KEY)
+        }
+        None => Ok(1),
+        Some(item) => item
+            .as_integer()
+            .and_then(|version| u32::try_from(version).ok())
+            .filter(|version| *version >= 1)
+            .ok_or_else(|| {
+                eyre::eyre!(
+                    "`{}` in the {} file must be a positive integer",
+                    VERSION_KEY,
+                    schema.kind
+                )
+            }),
+    }
+}
+
+/// Upgrade `document` to `schema.current` in place.
+///
+/// Returns the descriptions of the applied steps, empty when the document was already current.
+pub fn migrate(schema: &Schema, document: &mut DocumentMut) -> eyre::Result<Vec<&'static str>> {
+    let mut version = version_of(schema, document)?;
+
+    if version > schema.current {
+        eyre::bail!(
+            "The {} file uses schema version {}, but this build of workstation only understands up to version {}. Upgrade workstation to use it.",
+            schema.kind,
+            version,
+            schema.current
+        );
+    }
+
+    let mut applied = vec![];
+    while version < schema.current {
+        let migration = schema
+            .migrations
+            .iter()
+            .find(|migration| migration.from == version)
+            .ok_or_else(|| {
+                eyre::eyre!(
+                    "No migration for {} schema version {}",
+                    schema.kind,
+                    version
+                )
+            })?;
+
+        (migration.apply)(document).map_err(|e| {
+            e.wrap_err(format!(
+                "Migrating {} from schema version {}",
+                schema.kind, version
+            ))
+        })?;
+        applied.push(migration.description);
+        version += 1;
+    }
+
+    if !applied.is_empty() {
+        document[VERSION_KEY] = toml_edit::value(i64::from(version));
+    }
+
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rename_items(document: &mut DocumentMut) -> eyre::Result<()> {
+        let items = document
+            .remove("items")
+            .ok_or_else(|| eyre::eyre!("missing items"))?;
+        document["entries"] = items;
+        Ok(())
+    }
+
+    const TEST_SCHEMA: Schema = Schema {
+        kind: "test",
+        current: 2,
+        version_required: true,
+        migrations: &[Migration {
+            from: 1,
+            description: "rename items to entries",
+            apply: rename_items,
+        }],
+    };
+
+    #[test]
+    fn test_config_without_version_is_version_1() {
+        let source = "[linux_x86_64]\nlocation = \"~/.local/bin\"\npackages = []\n";
+        let mut document: DocumentMut = source.parse().unwrap();
+
+        let applied = migrate(&CONFIG_SCHEMA, &mut document).unwrap();
+
+        assert_eq!(applied.len(), 1);
+        assert_eq!(
+            document.to_string(),
+            format!("schema_version = 2\n{}", source)
+        );
+    }
+
+    #[test]
+    fn test_config_settings_are_moved_into_the_workstation_table() {
+        let mut document: DocumentMut = include_str!("../fixtures/config_v1.toml").parse().unwrap();
+
+        let applied = migrate(&CONFIG_SCHEMA, &mut document).unwrap();
+
+        assert_eq!(
+            applied,
+            vec!["move the settings tables at the top level into [workstation]"]
+        );
+        assert_eq!(
+            document.to_string(),
+            include_str!("../fixtures/config_v2.toml")
+        );
+        let config = crate::config::parse_config_file(&document.to_string()).unwrap();
+        assert_eq!(config.workstation.download.segments, Some(4));
+        assert_eq!(config.workstation.hooks.post_setup.len(), 1);
+    }
+
+    #[test]
+    fn test_newer_version_is_refused() {
+        let mut document: DocumentMut = "schema_version = 99\n".parse().unwrap();
+
+        let error = migrate(&CONFIG_SCHEMA, &mut document).unwrap_err();
+
+        assert!(error.to_string().contains("Upgrade workstation"));
+    }
+
+    #[test]
+    fn test_required_version_is_enforced() {
+        let mut document: DocumentMut = "items = []\n".parse().unwrap();
+
+        assert!(migrate(&TEST_SCHEMA, &mut document).is_err());
+    }
+
+    #[test]
+    fn test_migration_steps_are_applied() {
+        let mut document: DocumentMut = "schema_version = 1\n# keep me\nitems = [1, 2]\n"
+            .parse()
+            .unwrap();
+
+        let applied = migrate(&TEST_SCHEMA, &mut document).unwrap();
+
+        assert_eq!(applied, vec!["rename items to entries"]);
+        assert_eq!(document[VERSION_KEY].as_integer(), Some(2));
+        assert!(document.get("items").is_none());
+        assert_eq!(document["entries"].as_array().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,131 @@
+/// Longest file name most file systems accept, in bytes
+pub const MAX_LEN: usize = 255;
+
+/// Check that a package name from the config can be used as the installed file name as is.
+///
+/// Names are never rewritten, a name that would need quoting in a shell or that the file system
+/// may mangle is rejected with the reason.
+pub fn validate(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("name must not be empty".to_string());
+    }
+    if name == "." || name == ".." {
+        return Err(format!("{:?} is not a file name", name));
+    }
+    if name.len() > MAX_LEN {
+        return Err(format!(
+            "name is {} bytes long, at most {} are allowed",
+            name.len(),
+            MAX_LEN
+        ));
+    }
+    if let Some(c) = name.chars().find(|&c| !allowed(c)) {
+        return Err(match c {
+            '/' | '\\' => "name must be a plain file name, without a directory".to_string(),
+            c if c.is_whitespace() => "name must not contain whitespace".to_string(),
+            c if c.is_control() => "name must not contain control characters".to_string(),
+            c => format!(
+                "name must not contain {:?}, the shell treats it specially",
+                c
+            ),
+        });
+    }
+    if name.starts_with('-') {
+        return Err("name must not start with -, commands would take it for an option".to_string());
+    }
+    if name.ends_with('.') {
+        return Err("name must not end with a dot".to_string());
+    }
+
+    Ok(())
+}
+
+/// A name [`validate`] accepts made from `detected`, a name guessed from a file name: lowercase,
+/// with each character a name must not contain replaced by `-`. The leading `-` and trailing
+/// dots this may leave are dropped, nothing is left of a name of only those.
+pub fn sanitize(detected: &str) -> String {
+    let name: String = detected
+        .to_lowercase()
+        .chars()
+        .map(|c| if allowed(c) { c } else { '-' })
+        .collect();
+    let mut name = name
+        .trim_start_matches('-')
+        .trim_end_matches('.')
+        .to_string();
+    while name.len() > MAX_LEN {
+        name.pop();
+    }
+    name.trim_end_matches('.').to_string()
+}
+
+/// Letters, digits and the punctuation found in the names of released tools, such as `g++`,
+/// `python3.12` or `clang-format`
+fn allowed(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '.' | '_' | '-' | '+' | '@')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_names_are_valid() {
+        for name in [
+            "rg",
+            "g++",
+            "python3.12",
+            "clang-format",
+            "node@20",
+            "bat_",
+            "ÿt",
+        ] {
+            assert_eq!(validate(name), Ok(()), "{}", name);
+        }
+        assert_eq!(validate(&"a".repeat(MAX_LEN)), Ok(()));
+    }
+
+    #[test]
+    fn test_troublesome_names_are_rejected() {
+        let cases = [
+            ("", "must not be empty"),
+            (".", "is not a file name"),
+            ("..", "is not a file name"),
+            ("bin/rg", "without a directory"),
+            ("bin\\rg", "without a directory"),
+            ("my tool", "whitespace"),
+            ("tool\t", "whitespace"),
+            ("tool\u{7}", "control characters"),
+            ("rg;rm", "';'"),
+            ("$(tool)", "'$'"),
+            ("tool*", "'*'"),
+            ("`tool`", "'`'"),
+            ("tool&", "'&'"),
+            ("'tool'", "'\\''"),
+            ("-rf", "start with -"),
+            ("tool.", "end with a dot"),
+        ];
+        for (name, reason) in cases {
+            let error = validate(name).unwrap_err();
+            assert!(error.contains(reason), "{:?}: {}", name, error);
+        }
+        assert!(validate(&"a".repeat(MAX_LEN + 1))
+            .unwrap_err()
+            .contains("256 bytes long"));
+    }
+
+    #[test]
+    fn test_detected_names_are_sanitized() {
+        let cases = [
+            ("rg", "rg"),
+            ("Foo_Bar.v1", "foo_bar.v1"),
+            ("My Tool (x64).", "my-tool--x64-"),
+            ("--Tool$", "tool-"),
+            ("...", ""),
+        ];
+        for (detected, name) in cases {
+            assert_eq!(sanitize(detected), name, "{:?}", detected);
+        }
+        assert_eq!(sanitize(&"a".repeat(MAX_LEN + 1)).len(), MAX_LEN);
+    }
+}
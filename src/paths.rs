@@ -0,0 +1,447 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    completions::Shell,
+    expand::{self, Env, Field},
+};
+
+pub const CONFIG_FILE_NAME: &str = "workstation.toml";
+const APP_DIR: &str = "workstation";
+
+/// The `[workstation.paths]` section, overriding where workstation keeps its own files
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Default)]
+pub struct PathsConfig {
+    pub cache: Option<String>,
+    pub state: Option<String>,
+    pub logs: Option<String>,
+    /// Where `[workstation.install] store = true` keeps installed files
+    pub store: Option<String>,
+}
+
+/// Where a directory setting came from
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    Env,
+    Config,
+    Xdg,
+    Default,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Dir {
+    pub path: PathBuf,
+    pub source: Source,
+}
+
+/// Every directory workstation uses.
+///
+/// Each one is resolved in order from a `WORKSTATION_*` environment variable, the
+/// `[workstation.paths]` config section, the matching `XDG_*` variable, and finally the XDG default
+/// under `$HOME`.
+#[derive(Debug, Clone)]
+pub struct Paths {
+    /// Config files tried in order when no config is given explicitly
+    pub config_search: Vec<PathBuf>,
+    pub cache: Dir,
+    pub state: Dir,
+    pub logs: Dir,
+    /// The content addressed store of installed files, see `[workstation.install] store`
+    pub store: Dir,
+    pub default_location: PathBuf,
+    /// Where generated shell completions are written, the directories each shell loads by default
+    pub completions: BTreeMap<Shell, PathBuf>,
+    /// The rc file of each shell, where `shell_init` snippets are kept
+    pub rc_files: BTreeMap<Shell, PathBuf>,
+    /// Where the exports of `[workstation.env]` are written, as env.sh and env.fish
+    pub env: PathBuf,
+    /// Where the desktop entries of AppImages are written, the directory launchers read
+    pub applications: PathBuf,
+    /// Where the icons of AppImages are written
+    pub icons: PathBuf,
+}
+
+impl Paths {
+    pub fn resolve(config: Option<&PathsConfig>) -> eyre::Result<Self> {
+        Self::resolve_in(Env::current(), config)
+    }
+
+    pub fn resolve_in(env: &Env, config: Option<&PathsConfig>) -> eyre::Result<Self> {
+        let home = env
+            .var("HOME")
+            .map(|home| env.rooted(PathBuf::from(home)))
+            .ok_or_else(|| eyre::eyre!("HOME is not set"))?;
+        let default = PathsConfig::default();
+        let config = config.unwrap_or(&default);
+
+        let xdg_base = |variable: &str, fallback: &str| match env.var(variable) {
+            // The XDG spec says relative paths are invalid and must be ignored
+            Some(value) if Path::new(&value).is_absolute() => {
+                (env.rooted(PathBuf::from(value)), Source::Xdg)
+            }
+            _ => (home.join(fallback), Source::Default),
+        };
+        let xdg = |variable: &str, fallback: &str| {
+            let (base, source) = xdg_base(variable, fallback);
+            Dir {
+                path: base.join(APP_DIR),
+                source,
+            }
+        };
+
+        let config_home = xdg("XDG_CONFIG_HOME", ".config").path;
+        let (data_base, _) = xdg_base("XDG_DATA_HOME", ".local/share");
+        let (config_base, _) = xdg_base("XDG_CONFIG_HOME", ".config");
+        let completions = BTreeMap::from([
+            (Shell::Bash, data_base.join("bash-completion/completions")),
+            (Shell::Zsh, data_base.join("zsh/site-functions")),
+            (Shell::Fish, config_base.join("fish/completions")),
+        ]);
+        let zdotdir = env
+            .var("ZDOTDIR")
+            .filter(|value| Path::new(value).is_absolute())
+            .map(|value| env.rooted(PathBuf::from(value)))
+            .unwrap_or_else(|| home.clone());
+        let rc_files = BTreeMap::from([
+            (Shell::Bash, home.join(".bashrc")),
+            (Shell::Zsh, zdotdir.join(".zshrc")),
+            (Shell::Fish, config_base.join("fish/config.fish")),
+        ]);
+        let cache = resolve_dir(
+            env,
+            "WORKSTATION_CACHE_DIR",
+            config.cache.as_deref(),
+            "cache",
+            || xdg("XDG_CACHE_HOME", ".cache"),
+        )?;
+        let state = resolve_dir(
+            env,
+            "WORKSTATION_STATE_DIR",
+            config.state.as_deref(),
+            "state",
+            || xdg("XDG_STATE_HOME", ".local/state"),
+        )?;
+        let logs = resolve_dir(
+            env,
+            "WORKSTATION_LOG_DIR",
+            config.logs.as_deref(),
+            "logs",
+            || Dir {
+                path: state.path.join("logs"),
+                source: state.source,
+            },
+        )?;
+        let store = resolve_dir(
+            env,
+            "WORKSTATION_STORE_DIR",
+            config.store.as_deref(),
+            "store",
+            || {
+                let data = xdg("XDG_DATA_HOME", ".local/share");
+                Dir {
+                    path: data.path.join("store"),
+                    source: data.source,
+                }
+            },
+        )?;
+
+        Ok(Paths {
+            config_search: vec![
+                PathBuf::from(CONFIG_FILE_NAME),
+                config_home.join(CONFIG_FILE_NAME),
+            ],
+            cache,
+            state,
+            logs,
+            store,
+            default_location: home.join(".local/bin"),
+            completions,
+            rc_files,
+            env: config_home,
+            applications: data_base.join("applications"),
+            icons: data_base.join("icons"),
+        })
+    }
+
+    /// The first config file of the search path that exists, or the first entry if none does
+    pub fn find_config(&self) -> &Path {
+        self.config_search
+            .iter()
+            .find(|path| path.exists())
+            .unwrap_or(&self.config_search[0])
+    }
+}
+
+fn resolve_dir(
+    env: &Env,
+    variable: &str,
+    configured: Option<&str>,
+    key: &str,
+    fallback: impl FnOnce() -> Dir,
+) -> eyre::Result<Dir> {
+    if let Some(value) = env.var(variable).filter(|value| !value.is_empty()) {
+        return Ok(Dir {
+            path: env.rooted(PathBuf::from(value)),
+            source: Source::Env,
+        });
+    }
+    if let Some(value) = configured {
+        return Ok(Dir {
+            path: expand::expand_path_in(env, value, Field::new(key, "section paths"))?,
+            source: Source::Config,
+        });
+    }
+
+    Ok(fallback())
+}
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    name: &'a str,
+    path: &'a Path,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<Source>,
+    exists: bool,
+}
+
+fn entries(paths: &Paths) -> Vec<Entry<'_>> {
+    let mut entries: Vec<_> = paths
+        .config_search
+        .iter()
+        .map(|path| Entry {
+            name: "config",
+            path,
+            source: None,
+            exists: path.exists(),
+        })
+        .collect();
+
+    for (name, dir) in [
+        ("cache", &paths.cache),
+        ("state", &paths.state),
+        ("logs", &paths.logs),
+        ("store", &paths.store),
+    ] {
+        entries.push(Entry {
+            name,
+            path: &dir.path,
+            source: Some(dir.source),
+            exists: dir.path.exists(),
+        });
+    }
+
+    entries.push(Entry {
+        name: "default_location",
+        path: &paths.default_location,
+        source: None,
+        exists: paths.default_location.exists(),
+    });
+
+    entries.push(Entry {
+        name: "env",
+        path: &paths.env,
+        source: None,
+        exists: paths.env.exists(),
+    });
+
+    for (name, path) in [
+        ("applications", &paths.applications),
+        ("icons", &paths.icons),
+    ] {
+        entries.push(Entry {
+            name,
+            path,
+            source: None,
+            exists: path.exists(),
+        });
+    }
+
+    for (shell, path) in &paths.completions {
+        entries.push(Entry {
+            name: match shell {
+                Shell::Bash => "bash_completions",
+                Shell::Zsh => "zsh_completions",
+                Shell::Fish => "fish_completions",
+            },
+            path,
+            source: None,
+            exists: path.exists(),
+        });
+    }
+
+    for (shell, path) in &paths.rc_files {
+        entries.push(Entry {
+            name: match shell {
+                Shell::Bash => "bash_rc",
+                Shell::Zsh => "zsh_rc",
+                Shell::Fish => "fish_rc",
+            },
+            path,
+            source: None,
+            exists: path.exists(),
+        });
+    }
+
+    entries
+}
+
+pub fn print(paths: &Paths, json: bool) {
+    let entries = entries(paths);
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&entries).expect("paths serialize")
+        );
+        return;
+    }
+
+    for entry in entries {
+        let source = match entry.source {
+            Some(Source::Env) => ", from environment",
+            Some(Source::Config) => ", from config",
+            _ => "",
+        };
+        let exists = if entry.exists { "exists" } else { "missing" };
+        println!(
+            "{:18} {} ({}{})",
+            entry.name,
+            entry.path.display(),
+            exists,
+            source
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> Env {
+        Env::from_vars(vars)
+    }
+
+    #[test]
+    fn test_defaults_follow_xdg_fallbacks() {
+        let paths = Paths::resolve_in(&env(&[("HOME", "/home/u")]), None).unwrap();
+
+        assert_eq!(
+            paths.cache.path,
+            PathBuf::from("/home/u/.cache/workstation")
+        );
+        assert_eq!(
+            paths.state.path,
+            PathBuf::from("/home/u/.local/state/workstation")
+        );
+        assert_eq!(
+            paths.logs.path,
+            PathBuf::from("/home/u/.local/state/workstation/logs")
+        );
+        assert_eq!(
+            paths.config_search[1],
+            PathBuf::from("/home/u/.config/workstation/workstation.toml")
+        );
+        assert_eq!(paths.cache.source, Source::Default);
+        assert_eq!(
+            paths.completions[&Shell::Zsh],
+            PathBuf::from("/home/u/.local/share/zsh/site-functions")
+        );
+        assert_eq!(
+            paths.completions[&Shell::Fish],
+            PathBuf::from("/home/u/.config/fish/completions")
+        );
+        assert_eq!(paths.rc_files[&Shell::Zsh], PathBuf::from("/home/u/.zshrc"));
+        assert_eq!(
+            paths.store.path,
+            PathBuf::from("/home/u/.local/share/workstation/store")
+        );
+    }
+
+    #[test]
+    fn test_xdg_variables_are_honored() {
+        let env = env(&[
+            ("HOME", "/home/u"),
+            ("XDG_CACHE_HOME", "/tmp/cache"),
+            ("XDG_STATE_HOME", "relative/is/ignored"),
+        ]);
+
+        let paths = Paths::resolve_in(&env, None).unwrap();
+
+        assert_eq!(paths.cache.path, PathBuf::from("/tmp/cache/workstation"));
+        assert_eq!(paths.cache.source, Source::Xdg);
+        assert_eq!(paths.state.source, Source::Default);
+    }
+
+    #[test]
+    fn test_sandbox_keeps_everything_inside() {
+        let canary = tempfile::tempdir().unwrap();
+        let sandbox = tempfile::tempdir().unwrap();
+        let home = canary.path().to_str().unwrap();
+        let cache = format!("{}/cache", home);
+        let env = env(&[
+            ("HOME", home),
+            ("XDG_CACHE_HOME", &cache),
+            ("WORKSTATION_STATE_DIR", "/srv/state"),
+        ])
+        .sandboxed(sandbox.path());
+        let config = PathsConfig {
+            logs: Some("~/logs".to_string()),
+            ..Default::default()
+        };
+
+        let paths = Paths::resolve_in(&env, Some(&config)).unwrap();
+        let mut written = vec![
+            paths.cache.path,
+            paths.state.path,
+            paths.logs.path,
+            paths.store.path,
+            paths.default_location,
+        ];
+        written.extend(paths.completions.into_values());
+        written.extend(
+            paths
+                .rc_files
+                .into_values()
+                .map(|rc| rc.parent().unwrap().to_path_buf()),
+        );
+        for directory in &written {
+            std::fs::create_dir_all(directory).unwrap();
+            std::fs::write(directory.join("file"), b"").unwrap();
+        }
+
+        for directory in &written {
+            assert!(directory.starts_with(sandbox.path()), "{:?}", directory);
+        }
+        assert_eq!(std::fs::read_dir(canary.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_env_beats_config_beats_xdg() {
+        let env = env(&[
+            ("HOME", "/home/u"),
+            ("XDG_CACHE_HOME", "/tmp/cache"),
+            ("XDG_STATE_HOME", "/tmp/state"),
+            ("WORKSTATION_CACHE_DIR", "/srv/cache"),
+        ]);
+        let config = PathsConfig {
+            cache: Some("~/ignored".to_string()),
+            state: Some("/srv/state".to_string()),
+            logs: None,
+            store: None,
+        };
+
+        let paths = Paths::resolve_in(&env, Some(&config)).unwrap();
+
+        assert_eq!(paths.cache.path, PathBuf::from("/srv/cache"));
+        assert_eq!(paths.cache.source, Source::Env);
+        assert_eq!(paths.state.path, PathBuf::from("/srv/state"));
+        assert_eq!(paths.state.source, Source::Config);
+        assert_eq!(paths.logs.path, PathBuf::from("/srv/state/logs"));
+    }
+}
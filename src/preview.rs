@@ -0,0 +1,205 @@
+use std::path::PathBuf;
+
+/// Where the asset of a package would come from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fetch {
+    /// `cached` when a copy with the pinned hash is in the cache and nothing is downloaded
+    Download {
+        url: String,
+        cached: bool,
+    },
+    Read(PathBuf),
+    /// A custom command line
+    Run(String),
+    /// A git repository cloned, or pulled when the clone exists
+    Git {
+        url: String,
+        rev: Option<String>,
+    },
+    /// Built from source by `with`, such as `crates.io:ripgrep@14.1.0` with cargo
+    Build {
+        source: String,
+        with: &'static str,
+    },
+    /// A package installed by a package manager, such as apt or pipx
+    System {
+        manager: String,
+        name: String,
+    },
+}
+
+/// What `setup` would do for one package
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Install {
+        fetch: Fetch,
+        /// The archive entry, or glob of entries, that would be extracted
+        extract: Option<String>,
+        path: PathBuf,
+        /// A file is already there and would be replaced
+        replaces: bool,
+        /// Written into the store with `path` linking to it
+        store: bool,
+        /// The `post_install` commands run afterwards
+        post_install: Vec<String>,
+    },
+    Skip(String),
+    /// Resolving the package failed, `setup` would fail it the same way
+    Failed(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preview {
+    pub name: String,
+    pub action: Action,
+}
+
+/// The previews as the plan `setup --dry-run` prints, with the packages `prune` would remove
+pub fn render(previews: &[Preview], prune: &[(String, PathBuf)]) -> String {
+    let mut rendered = String::new();
+    for preview in previews {
+        match &preview.action {
+            Action::Install {
+                fetch,
+                extract,
+                path,
+                replaces,
+                store,
+                post_install,
+            } => {
+                rendered.push_str(&format!("{}\n", preview.name));
+                match fetch {
+                    Fetch::Download { url, cached: false } => {
+                        rendered.push_str(&format!("  download {}\n", url))
+                    }
+                    Fetch::Download { url, cached: true } => {
+                        rendered.push_str(&format!("  reuse    {} (cached)\n", url))
+                    }
+                    Fetch::Read(path) => {
+                        rendered.push_str(&format!("  read     {}\n", path.display()))
+                    }
+                    Fetch::Run(command) => rendered.push_str(&format!("  run      {}\n", command)),
+                    Fetch::Build { source, with } => {
+                        rendered.push_str(&format!("  build    {} with {}\n", source, with))
+                    }
+                    Fetch::Git { url, rev } => rendered.push_str(&format!(
+                        "  {:8} {}{}\n",
+                        if *replaces { "pull" } else { "clone" },
+                        url,
+                        rev.as_ref()
+                            .map(|rev| format!(" at {}", rev))
+                            .unwrap_or_default()
+                    )),
+                    Fetch::System { manager, name } => {
+                        rendered.push_str(&format!("  install  {} with {}\n", name, manager))
+                    }
+                }
+                if let Some(entry) = extract {
+                    rendered.push_str(&format!("  extract  {}\n", entry));
+                }
+                let verb = match (fetch, store) {
+                    // Where a system package goes is up to the package manager
+                    (Fetch::System { .. }, _) => None,
+                    (Fetch::Run(_), _) => Some("creates"),
+                    (Fetch::Git { .. }, _) => Some("into"),
+                    (_, true) => Some("link"),
+                    _ => Some("write"),
+                };
+                if let Some(verb) = verb {
+                    rendered.push_str(&format!(
+                        "  {:8} {}{}\n",
+                        verb,
+                        path.display(),
+                        // A clone is updated, not replaced
+                        if *replaces && !matches!(fetch, Fetch::Git { .. }) {
+                            " (replacing it)"
+                        } else {
+                            ""
+                        }
+                    ));
+                }
+                for hook in post_install {
+                    rendered.push_str(&format!("  then     {}\n", hook));
+                }
+            }
+            Action::Skip(reason) => {
+                rendered.push_str(&format!("{}\n  skip     {}\n", preview.name, reason))
+            }
+            Action::Failed(error) => {
+                rendered.push_str(&format!("{}\n  fail     {}\n", preview.name, error))
+            }
+        }
+    }
+    for (name, path) in prune {
+        rendered.push_str(&format!("{}\n  remove   {}\n", name, path.display()));
+    }
+
+    let installs = previews
+        .iter()
+        .filter(|preview| matches!(preview.action, Action::Install { .. }))
+        .count();
+    rendered.push_str(&format!(
+        "Dry run: {} package(s) would be installed, {} removed, nothing was changed\n",
+        installs,
+        prune.len()
+    ));
+
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_lists_every_step() {
+        let previews = [
+            Preview {
+                name: "rg".to_string(),
+                action: Action::Install {
+                    fetch: Fetch::Download {
+                        url: "https://example.com/rg.tar.gz".to_string(),
+                        cached: false,
+                    },
+                    extract: Some("rg-*/rg".to_string()),
+                    path: PathBuf::from("/home/me/.local/bin/rg"),
+                    replaces: true,
+                    store: false,
+                    post_install: vec!["rg --version".to_string()],
+                },
+            },
+            Preview {
+                name: "tool".to_string(),
+                action: Action::Install {
+                    fetch: Fetch::Run("sh install.sh".to_string()),
+                    extract: None,
+                    path: PathBuf::from("/opt/tool"),
+                    replaces: false,
+                    store: false,
+                    post_install: vec![],
+                },
+            },
+            Preview {
+                name: "fd.local".to_string(),
+                action: Action::Skip("matches the install ignore pattern \"*.local\"".to_string()),
+            },
+        ];
+
+        assert_eq!(
+            render(&previews, &[("bat".to_string(), PathBuf::from("/bin/bat"))]),
+            "rg\n\
+             \x20 download https://example.com/rg.tar.gz\n\
+             \x20 extract  rg-*/rg\n\
+             \x20 write    /home/me/.local/bin/rg (replacing it)\n\
+             \x20 then     rg --version\n\
+             tool\n\
+             \x20 run      sh install.sh\n\
+             \x20 creates  /opt/tool\n\
+             fd.local\n\
+             \x20 skip     matches the install ignore pattern \"*.local\"\n\
+             bat\n\
+             \x20 remove   /bin/bat\n\
+             Dry run: 2 package(s) would be installed, 1 removed, nothing was changed\n"
+        );
+    }
+}
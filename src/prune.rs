@@ -0,0 +1,112 @@
+use std::path::Path;
+
+use crate::{config::PackageConfig, ignore::IgnoreSet, manifest::Manifest, stat::StatCache};
+
+/// What `setup --prune` does with the packages installed before but no longer configured
+#[derive(Debug, Default, PartialEq)]
+pub struct Plan {
+    pub remove: Vec<String>,
+    /// Packages that stay installed, with the reason
+    pub kept: Vec<(String, String)>,
+}
+
+/// Sort the manifest entries without a configured package.
+///
+/// Only files workstation recorded are candidates, and only while they still have the content
+/// it installed, so a file replaced by hand or by another tool is never deleted.
+pub fn plan(
+    packages: &[PackageConfig],
+    manifest: &Manifest,
+    hashes: &mut StatCache,
+    ignore: &IgnoreSet,
+) -> eyre::Result<Plan> {
+    let mut plan = Plan::default();
+
+    for (name, installed) in manifest.entries() {
+        if packages.iter().any(|package| package.name() == name) {
+            continue;
+        }
+        if let Some(pattern) = ignore.matching_pattern(Path::new(&name)) {
+            plan.kept.push((
+                name,
+                format!("matches the install ignore pattern {:?}", pattern),
+            ));
+            continue;
+        }
+        // Directory packages are replaced as a whole, there is no hash to compare
+        if installed.path.is_dir() {
+            plan.remove.push(name);
+            continue;
+        }
+        match hashes.stat(&installed.path)? {
+            Some(stat) if stat.hash != installed.hash => plan.kept.push((
+                name,
+                format!(
+                    "{} changed since it was installed",
+                    installed.path.display()
+                ),
+            )),
+            // A file that is already gone only leaves its records to forget
+            _ => plan.remove.push(name),
+        }
+    }
+
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{config, digest, manifest::Staged};
+
+    use super::*;
+
+    #[test]
+    fn test_only_unchanged_unconfigured_packages_are_removed() {
+        let directory = tempfile::tempdir().unwrap();
+        let bin = directory.path().join("bin");
+        std::fs::create_dir(&bin).unwrap();
+        for name in ["rg", "fd", "bat", "tool.local"] {
+            std::fs::write(bin.join(name), name).unwrap();
+        }
+        std::fs::write(bin.join("bat"), "edited").unwrap();
+        let config = config::parse_config_for(
+            r#"
+            [workstation.install]
+            ignore = ["*.local"]
+
+            [linux_x86_64]
+            location = "/unused"
+            packages = [{ name = "rg", url = "https://example.com/rg" }]
+            "#,
+            &crate::arch::Target::parse("linux_x86_64").unwrap(),
+        )
+        .unwrap();
+
+        let manifest = Manifest::load(directory.path()).unwrap();
+        let names = ["rg", "fd", "bat", "tool.local", "gone"].map(str::to_string);
+        for name in &names {
+            manifest.stage(
+                name,
+                Staged {
+                    hash: digest::sha256(name.as_bytes()),
+                    ..Default::default()
+                },
+            );
+        }
+        manifest.commit(&names, &bin);
+        // An unrelated file in the install location is not in the manifest
+        std::fs::write(bin.join("unrelated"), "unrelated").unwrap();
+
+        let plan = plan(
+            &config.platform.packages,
+            &manifest,
+            &mut StatCache::load(directory.path()),
+            &config.install.ignore_set().unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(plan.remove, ["fd", "gone"]);
+        let kept: Vec<_> = plan.kept.iter().map(|(name, _)| name).collect();
+        assert_eq!(kept, ["bat", "tool.local"]);
+    }
+}
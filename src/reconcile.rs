@@ -0,0 +1,220 @@
+use std::path::{Path, PathBuf};
+
+use crate::{config::PackageConfig, ignore::IgnoreSet, manifest::Manifest, prune, stat::StatCache};
+
+/// How the installed files differ from what `setup` last wrote
+#[derive(Debug, Default, PartialEq)]
+pub struct Plan {
+    /// Configured packages whose file is gone
+    pub missing: Vec<String>,
+    /// Packages whose file changed since it was installed, to be installed again
+    pub modified: Vec<String>,
+    /// Packages no longer in the config, removed as `setup --prune` removes them
+    pub pruned: Vec<String>,
+    /// Packages that differ but are not touched, with the reason
+    pub left_alone: Vec<(String, String)>,
+    /// How many packages are exactly as installed
+    pub intact: usize,
+}
+
+impl Plan {
+    /// The packages `setup` has to install again
+    pub fn reinstall(&self) -> Vec<String> {
+        self.missing.iter().chain(&self.modified).cloned().collect()
+    }
+}
+
+/// Compare every configured package and every manifest entry with the file system.
+///
+/// `path_of` is where a package ends up. Files only count as modified against a hash recorded
+/// for the same path, a file installed before the manifest existed is assumed to be right.
+/// Entries of packages that left the config are sorted by [`prune::plan`].
+pub fn plan(
+    packages: &[PackageConfig],
+    manifest: &Manifest,
+    hashes: &mut StatCache,
+    ignore: &IgnoreSet,
+    path_of: impl Fn(&PackageConfig) -> eyre::Result<PathBuf>,
+    restore_modified: bool,
+) -> eyre::Result<Plan> {
+    let mut plan = Plan::default();
+
+    for package in packages {
+        let name = package.name();
+        if let Some(pattern) = ignore.matching_pattern(Path::new(name)) {
+            plan.left_alone.push((
+                name.to_string(),
+                format!("matches the install ignore pattern {:?}", pattern),
+            ));
+            continue;
+        }
+
+        let path = path_of(package)?;
+        // Custom commands decide themselves what `creates` holds and directories are not
+        // hashed, only their absence is drift
+        if !package.is_single_file() {
+            match path.exists() {
+                true => plan.intact += 1,
+                false => plan.missing.push(name.to_string()),
+            }
+            continue;
+        }
+        let Some(stat) = hashes.stat(&path)? else {
+            plan.missing.push(name.to_string());
+            continue;
+        };
+        let recorded = manifest
+            .get(name)
+            .filter(|installed| installed.path == path && !package.is_self_managed());
+        match recorded {
+            Some(installed) if installed.hash != stat.hash => {
+                if restore_modified {
+                    plan.modified.push(name.to_string());
+                } else {
+                    plan.left_alone.push((
+                        name.to_string(),
+                        "modified since it was installed, pass --restore-modified to restore it"
+                            .to_string(),
+                    ));
+                }
+            }
+            _ => plan.intact += 1,
+        }
+    }
+
+    let prune = prune::plan(packages, manifest, hashes, ignore)?;
+    plan.pruned = prune.remove;
+    plan.left_alone.extend(
+        prune
+            .kept
+            .into_iter()
+            .map(|(name, reason)| (name, format!("no longer in the config and {}", reason))),
+    );
+
+    Ok(plan)
+}
+
+/// The plan as a report of what was done, `failed` are the packages that did not install again
+/// with their error
+pub fn render(plan: &Plan, failed: &[(String, String)], dry_run: bool) -> String {
+    let mut rendered = String::new();
+    let mut section = |title: &str, names: &[String]| {
+        let names: Vec<_> = names
+            .iter()
+            .filter(|name| !failed.iter().any(|(failed, _)| failed == *name))
+            .collect();
+        if names.is_empty() {
+            return;
+        }
+        rendered.push_str(&format!("{}:\n", title));
+        for name in names {
+            rendered.push_str(&format!("  {}\n", name));
+        }
+    };
+    if dry_run {
+        section("Would reinstall missing", &plan.missing);
+        section("Would restore modified", &plan.modified);
+        section("Would prune", &plan.pruned);
+    } else {
+        section("Reinstalled missing", &plan.missing);
+        section("Restored modified", &plan.modified);
+        section("Pruned", &plan.pruned);
+    }
+
+    for (title, entries) in [
+        ("Left alone", plan.left_alone.as_slice()),
+        ("Failed", failed),
+    ] {
+        if entries.is_empty() {
+            continue;
+        }
+        rendered.push_str(&format!("{}:\n", title));
+        for (name, reason) in entries {
+            rendered.push_str(&format!("  {}: {}\n", name, reason));
+        }
+    }
+    rendered.push_str(&format!("{} package(s) as installed\n", plan.intact));
+
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{config, digest, manifest::Staged};
+
+    use super::*;
+
+    #[test]
+    fn test_plan_sorts_drift() {
+        let directory = tempfile::tempdir().unwrap();
+        let bin = directory.path().join("bin");
+        std::fs::create_dir(&bin).unwrap();
+        std::fs::write(bin.join("rg"), b"rg").unwrap();
+        std::fs::write(bin.join("bat"), b"edited").unwrap();
+        let config = config::parse_config_for(
+            r#"
+            [linux_x86_64]
+            location = "/unused"
+            packages = [
+                { name = "fd", url = "https://example.com/fd" },
+                { name = "rg", url = "https://example.com/rg" },
+                { name = "bat", url = "https://example.com/bat" },
+            ]
+            "#,
+            &crate::arch::Target::parse("linux_x86_64").unwrap(),
+        )
+        .unwrap();
+
+        let manifest = Manifest::load(directory.path()).unwrap();
+        for (name, content) in [("fd", "fd"), ("rg", "rg"), ("bat", "bat"), ("old", "old")] {
+            let hash = digest::sha256(content.as_bytes());
+            manifest.stage(
+                name,
+                Staged {
+                    hash,
+                    ..Default::default()
+                },
+            );
+        }
+        let all = ["fd", "rg", "bat", "old"].map(str::to_string);
+        manifest.commit(&all, &bin);
+        let mut hashes = StatCache::load(directory.path());
+        let ignore = IgnoreSet::new(&[]).unwrap();
+        let path_of = |package: &PackageConfig| Ok(bin.join(package.name()));
+
+        let plan = plan(
+            &config.platform.packages,
+            &manifest,
+            &mut hashes,
+            &ignore,
+            path_of,
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(plan.missing, ["fd"]);
+        assert!(plan.modified.is_empty());
+        assert_eq!(plan.intact, 1);
+        let left_alone: Vec<_> = plan.left_alone.iter().map(|(name, _)| name).collect();
+        assert_eq!(left_alone, ["bat"]);
+        assert_eq!(plan.pruned, ["old"]);
+
+        let restoring = super::plan(
+            &config.platform.packages,
+            &manifest,
+            &mut hashes,
+            &ignore,
+            path_of,
+            true,
+        )
+        .unwrap();
+        assert_eq!(restoring.reinstall(), ["fd", "bat"]);
+
+        let failed = [("fd".to_string(), "404 Not Found".to_string())];
+        let rendered = render(&restoring, &failed, false);
+        assert!(rendered.starts_with("Restored modified:\n  bat\n"));
+        assert!(rendered.contains("Failed:\n  fd: 404 Not Found\n"));
+        assert!(rendered.contains("Pruned:\n  old\n"));
+        assert!(render(&restoring, &[], true).starts_with("Would reinstall missing:\n  fd\n"));
+    }
+}
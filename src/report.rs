@@ -0,0 +1,565 @@
+use std::{
+    io::Write,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard,
+    },
+};
+
+use eyre::Context;
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Installed,
+    Failed,
+    /// Installed successfully, then undone because the transaction failed
+    RolledBack,
+    /// Not installed because it is already present, see custom commands
+    Skipped,
+    /// Not installed because its local archive did not change since the last install
+    Unchanged,
+}
+
+impl Outcome {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Outcome::Installed => "installed",
+            Outcome::Failed => "failed",
+            Outcome::RolledBack => "rolled back",
+            Outcome::Skipped => "skipped",
+            Outcome::Unchanged => "unchanged",
+        }
+    }
+}
+
+/// The final result of a single package
+#[derive(Serialize, Debug, Clone)]
+pub struct PackageReport {
+    pub name: String,
+    pub outcome: Outcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Installed by a custom command, which is less reproducible than a download
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub custom_command: bool,
+    /// The error without package specific details, failures are grouped by it in the summary
+    #[serde(skip)]
+    pub cause: Option<String>,
+    /// Bytes not downloaded thanks to a zsync delta update
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delta_saved: Option<u64>,
+    /// The asset came from the download cache instead of being downloaded
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub cached: bool,
+    /// The release locked in the lockfile was installed, GitHub could not be asked for it
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub locked_fallback: bool,
+}
+
+impl PackageReport {
+    pub fn installed(name: &str) -> Self {
+        PackageReport {
+            name: name.to_string(),
+            outcome: Outcome::Installed,
+            error: None,
+            custom_command: false,
+            cause: None,
+            delta_saved: None,
+            cached: false,
+            locked_fallback: false,
+        }
+    }
+
+    pub fn failed(name: &str, error: &eyre::Report) -> Self {
+        PackageReport {
+            name: name.to_string(),
+            outcome: Outcome::Failed,
+            error: Some(crate::error::render(error)),
+            custom_command: false,
+            cause: Some(crate::error::cause(error)),
+            delta_saved: None,
+            cached: false,
+            locked_fallback: false,
+        }
+    }
+
+    pub fn rolled_back(name: &str, reason: &str) -> Self {
+        PackageReport {
+            name: name.to_string(),
+            outcome: Outcome::RolledBack,
+            error: Some(reason.to_string()),
+            custom_command: false,
+            cause: None,
+            delta_saved: None,
+            cached: false,
+            locked_fallback: false,
+        }
+    }
+
+    pub fn skipped(name: &str, reason: &str) -> Self {
+        PackageReport {
+            name: name.to_string(),
+            outcome: Outcome::Skipped,
+            error: Some(reason.to_string()),
+            custom_command: false,
+            cause: None,
+            delta_saved: None,
+            cached: false,
+            locked_fallback: false,
+        }
+    }
+
+    pub fn unchanged(name: &str, reason: &str) -> Self {
+        PackageReport {
+            name: name.to_string(),
+            outcome: Outcome::Unchanged,
+            error: Some(reason.to_string()),
+            custom_command: false,
+            cause: None,
+            delta_saved: None,
+            cached: false,
+            locked_fallback: false,
+        }
+    }
+
+    /// Note the bytes a delta update did not have to download
+    pub fn with_delta_saved(mut self, saved: Option<u64>) -> Self {
+        self.delta_saved = saved;
+        self
+    }
+
+    /// Note whether the asset came from the download cache
+    pub fn with_cached(mut self, cached: bool) -> Self {
+        self.cached = cached;
+        self
+    }
+
+    /// Note whether the locked release was installed without asking GitHub
+    pub fn with_locked_fallback(mut self, locked_fallback: bool) -> Self {
+        self.locked_fallback = locked_fallback;
+        self
+    }
+
+    /// Mark the report as coming from a custom command package
+    pub fn with_custom_command(mut self, custom_command: bool) -> Self {
+        self.custom_command = custom_command;
+        self
+    }
+}
+
+/// A streamed JSON line, emitted in completion order
+#[derive(Serialize)]
+struct StreamedReport<'a> {
+    sequence: u64,
+    #[serde(flatten)]
+    report: &'a PackageReport,
+}
+
+/// Collects package results from the install workers.
+///
+/// Results may arrive in any order; everything produced by [`Reporter::finish`] is sorted by
+/// package name so that repeated runs produce identical output.
+pub struct Reporter {
+    reports: Mutex<Vec<PackageReport>>,
+    sequence: AtomicU64,
+    stream: Option<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl Reporter {
+    pub fn new() -> Self {
+        Reporter {
+            reports: Mutex::new(vec![]),
+            sequence: AtomicU64::new(0),
+            stream: None,
+        }
+    }
+
+    /// Also write every result as a JSON line to `stream` as soon as it is recorded
+    pub fn with_stream(stream: Box<dyn Write + Send>) -> Self {
+        Reporter {
+            reports: Mutex::new(vec![]),
+            sequence: AtomicU64::new(0),
+            stream: Some(Mutex::new(stream)),
+        }
+    }
+
+    /// Record the result of a package, replacing an earlier result of a retried package
+    pub fn record(&self, report: PackageReport) {
+        let mut reports = lock(&self.reports);
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(stream) = &self.stream {
+            let line = serde_json::to_string(&StreamedReport {
+                sequence,
+                report: &report,
+            })
+            .expect("report serializes");
+            let mut stream = lock(stream);
+            // A closed consumer must not take the install down with it
+            let _ = writeln!(stream, "{}", line).and_then(|_| stream.flush());
+        }
+
+        match reports
+            .iter_mut()
+            .find(|earlier| earlier.name == report.name)
+        {
+            Some(earlier) => *earlier = report,
+            None => reports.push(report),
+        }
+    }
+
+    /// All recorded results, sorted by package name
+    pub fn finish(self) -> Vec<PackageReport> {
+        let mut reports = self
+            .reports
+            .into_inner()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        reports.sort_by(|a, b| a.name.cmp(&b.name));
+        reports
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Failed packages grouped by cause, in the order the causes first appear
+pub fn group_failures(reports: &[PackageReport]) -> Vec<(&str, Vec<&str>)> {
+    let mut groups: Vec<(&str, Vec<&str>)> = vec![];
+    for report in reports {
+        let Some(cause) = report.cause.as_deref() else {
+            continue;
+        };
+        match groups.iter_mut().find(|(existing, _)| *existing == cause) {
+            Some((_, names)) => names.push(&report.name),
+            None => groups.push((cause, vec![&report.name])),
+        }
+    }
+    groups
+}
+
+pub fn render_summary(reports: &[PackageReport]) -> String {
+    let width = reports
+        .iter()
+        .map(|report| report.name.len())
+        .max()
+        .unwrap_or(0);
+
+    let mut summary = String::from("Summary:\n");
+    for report in reports {
+        let custom = if report.custom_command {
+            " (custom command)".to_string()
+        } else if report.outcome == Outcome::Unchanged {
+            format!(" ({})", report.error.as_deref().unwrap_or_default())
+        } else if let Some(saved) = report.delta_saved {
+            format!(
+                " (delta update, {} saved)",
+                crate::budget::format_bytes(saved)
+            )
+        } else if report.locked_fallback {
+            " (locked release, upstream check skipped)".to_string()
+        } else if report.cached {
+            " (from the cache)".to_string()
+        } else {
+            String::new()
+        };
+        summary.push_str(&format!(
+            "  {:width$}  {}{}\n",
+            report.name,
+            report.outcome.label(),
+            custom,
+            width = width
+        ));
+    }
+
+    let failures = group_failures(reports);
+    if !failures.is_empty() {
+        summary.push_str("Failures:\n");
+        for (cause, names) in failures {
+            summary.push_str(&format!("  {}\n    {}\n", cause, names.join(", ")));
+        }
+    }
+
+    let count = |outcome| {
+        reports
+            .iter()
+            .filter(|report| report.outcome == outcome)
+            .count()
+    };
+    summary.push_str(&format!(
+        "{} installed, {} failed",
+        count(Outcome::Installed),
+        count(Outcome::Failed)
+    ));
+    let rolled_back = count(Outcome::RolledBack);
+    if rolled_back > 0 {
+        summary.push_str(&format!(", {} rolled back", rolled_back));
+    }
+    let skipped = count(Outcome::Skipped);
+    if skipped > 0 {
+        summary.push_str(&format!(", {} skipped", skipped));
+    }
+    let unchanged = count(Outcome::Unchanged);
+    if unchanged > 0 {
+        summary.push_str(&format!(", {} unchanged", unchanged));
+    }
+    let fallbacks = reports
+        .iter()
+        .filter(|report| report.locked_fallback)
+        .count();
+    if fallbacks > 0 {
+        summary.push_str(&format!(
+            ", {} locked without the upstream check",
+            fallbacks
+        ));
+    }
+    let cached = reports.iter().filter(|report| report.cached).count();
+    if cached > 0 {
+        summary.push_str(&format!(", {} from the cache", cached));
+    }
+    let saved: u64 = reports.iter().filter_map(|report| report.delta_saved).sum();
+    if saved > 0 {
+        summary.push_str(&format!(
+            ", {} saved by delta updates",
+            crate::budget::format_bytes(saved)
+        ));
+    }
+    summary.push('\n');
+
+    summary
+}
+
+pub fn render_json(reports: &[PackageReport]) -> String {
+    serde_json::to_string_pretty(reports).expect("reports serialize")
+}
+
+pub fn write_report_file(path: &Path, reports: &[PackageReport]) -> eyre::Result<()> {
+    std::fs::write(path, render_json(reports) + "\n")
+        .with_context(|| format!("Writing report to {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::hash_map::RandomState,
+        hash::{BuildHasher, Hasher},
+        sync::Arc,
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[derive(Clone)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn random_delay() -> Duration {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u8(0);
+        Duration::from_millis(hasher.finish() % 20)
+    }
+
+    #[test]
+    fn test_final_order_is_stable() {
+        let names = [
+            "rg", "fd", "yazi", "curl", "nvim", "fzf", "tmux", "starship",
+        ];
+        let mut expected = names.to_vec();
+        expected.sort();
+
+        let buffer = SharedBuffer(Arc::new(Mutex::new(vec![])));
+        let reporter = Arc::new(Reporter::with_stream(Box::new(buffer.clone())));
+
+        let handles: Vec<_> = names
+            .iter()
+            .copied()
+            .map(|name| {
+                let reporter = reporter.clone();
+                std::thread::spawn(move || {
+                    std::thread::sleep(random_delay());
+                    reporter.record(PackageReport::installed(name));
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let reports = Arc::into_inner(reporter).unwrap().finish();
+        let final_names: Vec<_> = reports.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(final_names, expected);
+
+        let json: Vec<serde_json::Value> = serde_json::from_str(&render_json(&reports)).unwrap();
+        let json_names: Vec<_> = json.iter().map(|r| r["name"].as_str().unwrap()).collect();
+        assert_eq!(json_names, expected);
+
+        let streamed = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<serde_json::Value> = streamed
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), names.len());
+        for (index, line) in lines.iter().enumerate() {
+            assert_eq!(line["sequence"].as_u64(), Some(index as u64));
+            assert!(line["name"].is_string());
+        }
+    }
+
+    #[test]
+    fn test_retried_package_keeps_the_latest_result() {
+        let reporter = Reporter::new();
+        reporter.record(PackageReport::failed("rg", &eyre::eyre!("timed out")));
+        reporter.record(PackageReport::installed("fd"));
+        reporter.record(PackageReport::installed("rg"));
+
+        let reports = reporter.finish();
+
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[1].name, "rg");
+        assert_eq!(reports[1].outcome, Outcome::Installed);
+    }
+
+    #[test]
+    fn test_summary_counts_failures() {
+        let reports = vec![
+            PackageReport::installed("fd"),
+            PackageReport::failed("rg", &eyre::eyre!("Entry not found")),
+        ];
+
+        let summary = render_summary(&reports);
+
+        assert!(summary.contains("1 installed, 1 failed"));
+    }
+
+    #[test]
+    fn test_summary_reports_delta_savings() {
+        let reports = vec![
+            PackageReport::installed("fd"),
+            PackageReport::installed("sdk").with_delta_saved(Some(1_700_000_000)),
+        ];
+
+        let summary = render_summary(&reports);
+
+        assert!(summary.contains("  sdk  installed (delta update, 1700.0 MB saved)\n"));
+        assert!(summary.ends_with("2 installed, 0 failed, 1700.0 MB saved by delta updates\n"));
+    }
+
+    #[test]
+    fn test_summary_reports_cache_hits() {
+        let reports = vec![
+            PackageReport::installed("fd").with_cached(true),
+            PackageReport::installed("rg"),
+        ];
+
+        let summary = render_summary(&reports);
+
+        assert!(summary.contains("  fd  installed (from the cache)\n"));
+        assert!(summary.ends_with("2 installed, 0 failed, 1 from the cache\n"));
+        assert!(render_json(&reports).contains("\"cached\": true"));
+    }
+
+    #[test]
+    fn test_summary_counts_locked_fallbacks() {
+        let reports = vec![
+            PackageReport::installed("nvim").with_locked_fallback(true),
+            PackageReport::installed("rg"),
+        ];
+
+        let summary = render_summary(&reports);
+
+        assert!(summary.contains("  nvim  installed (locked release, upstream check skipped)\n"));
+        assert!(summary.ends_with("2 installed, 0 failed, 1 locked without the upstream check\n"));
+        assert!(render_json(&reports).contains("\"locked_fallback\": true"));
+    }
+
+    #[test]
+    fn test_identical_failures_are_grouped() {
+        let blocked = |name: &str| {
+            eyre::eyre!("certificate verify failed")
+                .wrap_err(format!(
+                    "error sending request for url (https://github.com/{}/releases)",
+                    name
+                ))
+                .wrap_err(format!("Installing {}", name))
+        };
+        let status = |name: &str, status: &str| {
+            crate::error::ErrorCode::DownloadFailed
+                .error(format!(
+                    "Failed to download https://example.com/{}.tar.gz: {}",
+                    name, status
+                ))
+                .wrap_err(format!("Installing {}", name))
+        };
+        let reports = vec![
+            PackageReport::failed("bat", &blocked("bat")),
+            PackageReport::failed("eza", &status("eza", "404 Not Found")),
+            PackageReport::failed("fd", &blocked("fd")),
+            PackageReport::failed("jq", &status("jq", "503 Service Unavailable")),
+            PackageReport::installed("nvim"),
+            PackageReport::failed("rg", &status("rg", "404 Not Found")),
+        ];
+
+        let groups = group_failures(&reports);
+
+        assert_eq!(
+            groups,
+            vec![
+                ("certificate verify failed (github.com)", vec!["bat", "fd"]),
+                (
+                    "[E001] Failed to download example.com: 404 Not Found",
+                    vec!["eza", "rg"]
+                ),
+                (
+                    "[E001] Failed to download example.com: 503 Service Unavailable",
+                    vec!["jq"]
+                ),
+            ]
+        );
+        let summary = render_summary(&reports);
+        assert_eq!(summary.matches("certificate verify failed").count(), 1);
+        assert!(summary.contains("certificate verify failed (github.com)\n    bat, fd\n"));
+        let json = render_json(&reports);
+        assert_eq!(json.matches("certificate verify failed").count(), 2);
+    }
+
+    #[test]
+    fn test_custom_commands_are_marked() {
+        let reports = vec![
+            PackageReport::installed("weirdtool").with_custom_command(true),
+            PackageReport::skipped("vendortool", "already present").with_custom_command(true),
+        ];
+
+        let summary = render_summary(&reports);
+
+        assert!(summary.contains("weirdtool   installed (custom command)"));
+        assert!(summary.contains("vendortool  skipped (custom command)"));
+        assert!(summary.contains("1 installed, 0 failed, 1 skipped"));
+    }
+
+    #[test]
+    fn test_unchanged_archives_show_the_reason() {
+        let reports = vec![
+            PackageReport::installed("fd"),
+            PackageReport::unchanged("sdk", "archive not modified"),
+        ];
+
+        let summary = render_summary(&reports);
+
+        assert!(summary.contains("sdk  unchanged (archive not modified)"));
+        assert!(summary.contains("1 installed, 0 failed, 1 unchanged"));
+    }
+}
@@ -0,0 +1,330 @@
+use std::{fmt, path::Path, process::Command};
+
+use eyre::Context;
+
+use crate::{cargo, config::RustupConfig, custom};
+
+/// What rustup has installed
+#[derive(Debug, Clone, PartialEq, Default)]
+struct Installed {
+    toolchains: Vec<Toolchain>,
+    /// The full name of the default toolchain
+    default: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Toolchain {
+    /// The name with the host, such as `stable-x86_64-unknown-linux-gnu`
+    name: String,
+    components: Vec<String>,
+    targets: Vec<String>,
+}
+
+/// A change `setup` makes for `[workstation.rustup]`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    Install {
+        toolchain: String,
+        components: Vec<String>,
+        targets: Vec<String>,
+    },
+    AddComponents {
+        toolchain: String,
+        components: Vec<String>,
+    },
+    AddTargets {
+        toolchain: String,
+        targets: Vec<String>,
+    },
+    Default(String),
+}
+
+impl Step {
+    /// What the step does, and how that reads once done
+    pub fn verbs(&self) -> (&'static str, &'static str) {
+        match self {
+            Step::Install { .. } => ("install", "Installed"),
+            Step::AddComponents { .. } | Step::AddTargets { .. } => ("add", "Added"),
+            Step::Default(_) => ("set", "Set"),
+        }
+    }
+
+    fn args(&self) -> Vec<String> {
+        let mut args: Vec<String> = vec![];
+        match self {
+            Step::Install {
+                toolchain,
+                components,
+                targets,
+            } => {
+                args.extend(
+                    ["toolchain", "install", toolchain, "--no-self-update"].map(String::from),
+                );
+                for component in components {
+                    args.extend(["--component".to_string(), component.clone()]);
+                }
+                for target in targets {
+                    args.extend(["--target".to_string(), target.clone()]);
+                }
+            }
+            Step::AddComponents {
+                toolchain,
+                components,
+            } => {
+                args.extend(["component", "add", "--toolchain", toolchain].map(String::from));
+                args.extend(components.iter().cloned());
+            }
+            Step::AddTargets { toolchain, targets } => {
+                args.extend(["target", "add", "--toolchain", toolchain].map(String::from));
+                args.extend(targets.iter().cloned());
+            }
+            Step::Default(toolchain) => args.extend(["default", toolchain].map(String::from)),
+        }
+        args
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Step::Install {
+                toolchain,
+                components,
+                targets,
+            } => {
+                write!(f, "toolchain {}", toolchain)?;
+                let extras: Vec<_> = components.iter().chain(targets).cloned().collect();
+                if !extras.is_empty() {
+                    write!(f, " with {}", extras.join(", "))?;
+                }
+                Ok(())
+            }
+            Step::AddComponents {
+                toolchain,
+                components,
+            } => write!(f, "{} to toolchain {}", components.join(", "), toolchain),
+            Step::AddTargets { toolchain, targets } => {
+                write!(f, "{} to toolchain {}", targets.join(", "), toolchain)
+            }
+            Step::Default(toolchain) => write!(f, "{} as the default toolchain", toolchain),
+        }
+    }
+}
+
+/// The steps that bring rustup in line with `config`. rustup is only run when something is
+/// configured.
+pub fn plan(config: &RustupConfig) -> eyre::Result<Vec<Step>> {
+    if config == &RustupConfig::default() {
+        return Ok(vec![]);
+    }
+    let installed = installed(&program()?)?;
+    Ok(steps(config, &installed))
+}
+
+fn steps(config: &RustupConfig, installed: &Installed) -> Vec<Step> {
+    let mut steps = vec![];
+    let wanted = config.toolchains.iter().chain(
+        config
+            .default
+            .iter()
+            .filter(|default| !config.toolchains.contains(default)),
+    );
+    for toolchain in wanted {
+        let Some(present) = installed
+            .toolchains
+            .iter()
+            .find(|present| is_named(&present.name, toolchain))
+        else {
+            steps.push(Step::Install {
+                toolchain: toolchain.clone(),
+                components: config.components.clone(),
+                targets: config.targets.clone(),
+            });
+            continue;
+        };
+        let components: Vec<_> = config
+            .components
+            .iter()
+            .filter(|component| {
+                !present
+                    .components
+                    .iter()
+                    .any(|installed| is_named(installed, component))
+            })
+            .cloned()
+            .collect();
+        if !components.is_empty() {
+            steps.push(Step::AddComponents {
+                toolchain: toolchain.clone(),
+                components,
+            });
+        }
+        let targets: Vec<_> = config
+            .targets
+            .iter()
+            .filter(|target| !present.targets.contains(target))
+            .cloned()
+            .collect();
+        if !targets.is_empty() {
+            steps.push(Step::AddTargets {
+                toolchain: toolchain.clone(),
+                targets,
+            });
+        }
+    }
+    if let Some(default) = &config.default {
+        if !installed
+            .default
+            .as_ref()
+            .is_some_and(|installed| is_named(installed, default))
+        {
+            steps.push(Step::Default(default.clone()));
+        }
+    }
+    steps
+}
+
+/// Whether rustup's `full` name is `name`, which leaves out the host rustup adds to toolchains
+/// and components, as `stable` does in `stable-x86_64-unknown-linux-gnu`
+fn is_named(full: &str, name: &str) -> bool {
+    let is_host = |host: &str| {
+        host.starts_with(|c: char| c.is_ascii_alphabetic())
+            && (3..=4).contains(&host.split('-').count())
+    };
+    full == name
+        || full
+            .strip_prefix(name)
+            .and_then(|rest| rest.strip_prefix('-'))
+            .is_some_and(is_host)
+}
+
+/// Take `step`, rustup's output is only shown when it fails
+pub fn apply(step: &Step) -> eyre::Result<()> {
+    let mut command = vec![program()?.to_string_lossy().into_owned()];
+    command.extend(step.args());
+    let shown = format!("rustup {}", step.args().join(" "));
+    custom::capture(&command, &shown, &[], cargo::TIMEOUT)?;
+    Ok(())
+}
+
+/// The `setup --dry-run` lines of `steps`
+pub fn render_preview(steps: &[Step]) -> String {
+    let mut rendered = String::new();
+    for step in steps {
+        rendered.push_str(&format!("rustup\n  {:8} {}\n", step.verbs().0, step));
+    }
+    rendered
+}
+
+fn program() -> eyre::Result<std::path::PathBuf> {
+    cargo::program("rustup").ok_or_else(|| {
+        eyre::eyre!(
+            "rustup is not on PATH or in ~/.cargo/bin, install it first for [workstation.rustup], such as \
+             with a custom package running rustup-init"
+        )
+    })
+}
+
+fn installed(rustup: &Path) -> eyre::Result<Installed> {
+    let list = |args: &[&str]| -> eyre::Result<Vec<String>> {
+        let output = Command::new(rustup)
+            .args(args)
+            .output()
+            .with_context(|| format!("Running rustup {}", args.join(" ")))?;
+        if !output.status.success() {
+            eyre::bail!(
+                "`rustup {}` failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::to_string)
+            .collect())
+    };
+
+    let mut installed = Installed::default();
+    for line in list(&["toolchain", "list"])? {
+        // `stable-x86_64-unknown-linux-gnu (active, default)`, or a note that there are none
+        let Some(name) = line
+            .split_whitespace()
+            .next()
+            .filter(|name| name.contains('-'))
+        else {
+            continue;
+        };
+        if line[name.len()..].contains("default") {
+            installed.default = Some(name.to_string());
+        }
+        installed.toolchains.push(Toolchain {
+            name: name.to_string(),
+            components: list(&["component", "list", "--installed", "--toolchain", name])?,
+            targets: list(&["target", "list", "--installed", "--toolchain", name])?,
+        });
+    }
+    Ok(installed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_what_is_missing_is_added() {
+        let config = RustupConfig {
+            toolchains: vec!["stable".to_string(), "nightly".to_string()],
+            default: Some("stable".to_string()),
+            components: vec!["clippy".to_string(), "rust-src".to_string()],
+            targets: vec!["wasm32-unknown-unknown".to_string()],
+        };
+        let installed = Installed {
+            toolchains: vec![Toolchain {
+                name: "stable-x86_64-unknown-linux-gnu".to_string(),
+                components: vec![
+                    "cargo-x86_64-unknown-linux-gnu".to_string(),
+                    "clippy-x86_64-unknown-linux-gnu".to_string(),
+                ],
+                targets: vec!["wasm32-unknown-unknown".to_string()],
+            }],
+            default: Some("nightly-2024-05-01-x86_64-unknown-linux-gnu".to_string()),
+        };
+
+        let steps = steps(&config, &installed);
+        assert_eq!(
+            steps,
+            [
+                Step::AddComponents {
+                    toolchain: "stable".to_string(),
+                    components: vec!["rust-src".to_string()],
+                },
+                Step::Install {
+                    toolchain: "nightly".to_string(),
+                    components: vec!["clippy".to_string(), "rust-src".to_string()],
+                    targets: vec!["wasm32-unknown-unknown".to_string()],
+                },
+                Step::Default("stable".to_string()),
+            ]
+        );
+        assert_eq!(
+            steps[1].args(),
+            [
+                "toolchain",
+                "install",
+                "nightly",
+                "--no-self-update",
+                "--component",
+                "clippy",
+                "--component",
+                "rust-src",
+                "--target",
+                "wasm32-unknown-unknown"
+            ]
+        );
+        assert!(!is_named(
+            "nightly-2024-05-01-x86_64-unknown-linux-gnu",
+            "nightly"
+        ));
+        assert!(!is_named("rust-analyzer-x86_64-unknown-linux-gnu", "rust"));
+    }
+}
@@ -0,0 +1,331 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use eyre::Context;
+
+use crate::{completions::Shell, diff::FileDiff, exports};
+
+const BEGIN: &str = "# >>> workstation shell_init >>>";
+const END: &str = "# <<< workstation shell_init <<<";
+const NOTE: &str = "# Managed by workstation, changes between these markers are overwritten";
+
+/// Appended to the rc file name for the copy taken before the first change
+const BACKUP_SUFFIX: &str = ".workstation-backup";
+
+/// A rewrite of one rc file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub path: PathBuf,
+    /// `None` when the rc file does not exist yet
+    pub old: Option<String>,
+    pub new: String,
+}
+
+impl Change {
+    pub fn diff(&self) -> Option<FileDiff> {
+        FileDiff::compute(
+            &self.path,
+            self.old.as_deref().map(str::as_bytes),
+            self.new.as_bytes(),
+        )
+    }
+}
+
+/// The line of `shell` that puts `directory` first on PATH, unless it is already on it
+pub fn path_line(shell: Shell, directory: &Path) -> String {
+    let directory = directory.to_string_lossy();
+    match shell {
+        Shell::Bash | Shell::Zsh => {
+            let quoted: String = directory
+                .chars()
+                .flat_map(|c| match c {
+                    '"' | '$' | '`' | '\\' => vec!['\\', c],
+                    c => vec![c],
+                })
+                .collect();
+            format!(
+                "case \":$PATH:\" in *\":{0}:\"*) ;; *) export PATH=\"{0}:$PATH\" ;; esac",
+                quoted
+            )
+        }
+        Shell::Fish => format!(
+            "fish_add_path --path '{}'",
+            directory.replace('\\', "\\\\").replace('\'', "\\'")
+        ),
+    }
+}
+
+/// The managed block with the `managed` lines of workstation itself, each after its comment, and
+/// the snippets of every package, `None` when there are none.
+///
+/// Packages are in name order, and a line that an earlier package already added is left out.
+pub fn render_block(managed: &[(&str, String)], snippets: &BTreeMap<&str, &str>) -> Option<String> {
+    if managed.is_empty() && snippets.is_empty() {
+        return None;
+    }
+
+    let mut block = format!("{}\n{}\n", BEGIN, NOTE);
+    for (comment, line) in managed {
+        block.push_str(&format!("# {}\n{}\n", comment, line));
+    }
+    let mut seen = vec![];
+    for (package, snippet) in snippets {
+        let lines: Vec<_> = snippet
+            .lines()
+            .map(str::trim_end)
+            .filter(|line| !line.is_empty() && !seen.contains(line))
+            .collect();
+        if lines.is_empty() {
+            continue;
+        }
+        block.push_str(&format!("# {}\n", package));
+        for line in lines {
+            block.push_str(line);
+            block.push('\n');
+            seen.push(line);
+        }
+    }
+    block.push_str(END);
+    block.push('\n');
+
+    Some(block)
+}
+
+/// Put `block` in place of the managed block of `content`, appending it when there is none and
+/// removing the managed block when `block` is `None`. Nothing outside the markers changes.
+pub fn replace_block(content: &str, block: Option<&str>) -> eyre::Result<String> {
+    let lines: Vec<_> = content.split_inclusive('\n').collect();
+    let begin = lines.iter().position(|line| line.trim_end() == BEGIN);
+    let end = lines.iter().position(|line| line.trim_end() == END);
+
+    let (before, after) = match (begin, end) {
+        (Some(begin), Some(end)) if begin < end => (&lines[..begin], &lines[end + 1..]),
+        (None, None) => (&lines[..], &[][..]),
+        _ => eyre::bail!(
+            "The workstation block is damaged, one of its markers is missing or out of order. Fix or remove it by hand: {:?} ... {:?}",
+            BEGIN,
+            END
+        ),
+    };
+
+    let mut updated: String = before.concat();
+    if let Some(block) = block {
+        if begin.is_none() {
+            // Keep the appended block apart from what the user wrote
+            if !updated.is_empty() && !updated.ends_with('\n') {
+                updated.push('\n');
+            }
+            if !updated.is_empty() && !updated.ends_with("\n\n") {
+                updated.push('\n');
+            }
+        }
+        updated.push_str(block);
+    }
+    updated.push_str(&after.concat());
+
+    Ok(updated)
+}
+
+/// The rc file rewrites needed so that each shell has exactly the snippets of `packages`, which
+/// maps package names to their `shell_init` option, `location` on PATH and the `[workstation.env]`
+/// exports in `env` loaded.
+///
+/// `location` and `env` alone do not create an rc file, only shells that are set up get them.
+pub fn plan(
+    location: Option<&Path>,
+    env: Option<&Path>,
+    packages: &BTreeMap<&str, &BTreeMap<Shell, String>>,
+    rc_files: &BTreeMap<Shell, PathBuf>,
+) -> eyre::Result<Vec<Change>> {
+    let mut changes = vec![];
+
+    for (shell, path) in rc_files {
+        let snippets: BTreeMap<&str, &str> = packages
+            .iter()
+            .filter_map(|(name, snippets)| Some((*name, snippets.get(shell)?.as_str())))
+            .collect();
+        let old = match std::fs::read_to_string(path) {
+            Ok(content) => Some(content),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e).with_context(|| format!("Reading {}", path.display())),
+        };
+        let mut managed = vec![];
+        if old.is_some() || !snippets.is_empty() {
+            if let Some(location) = location {
+                managed.push(("PATH", path_line(*shell, location)));
+            }
+            if let Some(env) = env {
+                managed.push(("env", exports::source_line(*shell, env)));
+            }
+        }
+        let block = render_block(&managed, &snippets);
+        if old.is_none() && block.is_none() {
+            continue;
+        }
+
+        let new = replace_block(old.as_deref().unwrap_or_default(), block.as_deref())
+            .with_context(|| format!("Updating {}", path.display()))?;
+        if old.as_deref() != Some(new.as_str()) {
+            changes.push(Change {
+                path: path.clone(),
+                old,
+                new,
+            });
+        }
+    }
+
+    Ok(changes)
+}
+
+/// Write the changes, keeping a copy of each rc file from before workstation first changed it
+pub fn apply(changes: &[Change]) -> eyre::Result<()> {
+    for change in changes {
+        if change.old.is_some() {
+            let backup = backup_path(&change.path);
+            if !backup.exists() {
+                std::fs::copy(&change.path, &backup)
+                    .with_context(|| format!("Backing up {}", change.path.display()))?;
+            }
+        }
+        if let Some(parent) = change.path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Creating {}", parent.display()))?;
+        }
+        std::fs::write(&change.path, &change.new)
+            .with_context(|| format!("Writing {}", change.path.display()))?;
+    }
+
+    Ok(())
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(BACKUP_SUFFIX);
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(snippets: &[(&str, &str)]) -> Option<String> {
+        render_block(&[], &snippets.iter().copied().collect())
+    }
+
+    #[test]
+    fn test_block_is_appended_then_updated_in_place() {
+        let rc = "export EDITOR=nvim\n";
+        let zoxide = block(&[("zoxide", "eval \"$(zoxide init zsh)\"")]);
+
+        let first = replace_block(rc, zoxide.as_deref()).unwrap();
+        assert_eq!(
+            first,
+            format!(
+                "export EDITOR=nvim\n\n{}\n{}\n# zoxide\neval \"$(zoxide init zsh)\"\n{}\n",
+                BEGIN, NOTE, END
+            )
+        );
+        assert_eq!(replace_block(&first, zoxide.as_deref()).unwrap(), first);
+
+        let edited = first.clone() + "alias ll='ls -l'\n";
+        let both = block(&[
+            ("zoxide", "eval \"$(zoxide init zsh)\""),
+            ("fzf", "source <(fzf --zsh)"),
+        ]);
+        let second = replace_block(&edited, both.as_deref()).unwrap();
+        assert!(second.starts_with("export EDITOR=nvim\n\n"));
+        assert!(second.ends_with(&format!("{}\nalias ll='ls -l'\n", END)));
+        assert!(second.find("# fzf").unwrap() < second.find("# zoxide").unwrap());
+
+        let removed = replace_block(&second, None).unwrap();
+        assert_eq!(removed, "export EDITOR=nvim\n\nalias ll='ls -l'\n");
+    }
+
+    #[test]
+    fn test_lines_are_never_duplicated() {
+        let rendered = block(&[
+            ("a", "eval \"$(starship init zsh)\""),
+            ("b", "eval \"$(starship init zsh)\"\nexport B=1"),
+        ])
+        .unwrap();
+
+        assert_eq!(rendered.matches("starship init").count(), 1);
+        assert!(rendered.contains("# b\nexport B=1\n"));
+    }
+
+    #[test]
+    fn test_damaged_block_is_refused() {
+        let rc = format!("{}\neval stuff\n", BEGIN);
+
+        assert!(replace_block(&rc, None).is_err());
+    }
+
+    #[test]
+    fn test_apply_backs_up_once() {
+        let directory = tempfile::tempdir().unwrap();
+        let rc = directory.path().join(".zshrc");
+        std::fs::write(&rc, "original\n").unwrap();
+        let rc_files = BTreeMap::from([(Shell::Zsh, rc.clone())]);
+        let zoxide = BTreeMap::from([(Shell::Zsh, "eval \"$(zoxide init zsh)\"".to_string())]);
+        let fzf = BTreeMap::from([(Shell::Zsh, "source <(fzf --zsh)".to_string())]);
+
+        let changes = plan(
+            None,
+            None,
+            &BTreeMap::from([("zoxide", &zoxide)]),
+            &rc_files,
+        )
+        .unwrap();
+        assert_eq!(changes.len(), 1);
+        assert!(changes[0].diff().is_some());
+        apply(&changes).unwrap();
+        let changes = plan(None, None, &BTreeMap::from([("fzf", &fzf)]), &rc_files).unwrap();
+        apply(&changes).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(backup_path(&rc)).unwrap(),
+            "original\n"
+        );
+        let content = std::fs::read_to_string(&rc).unwrap();
+        assert!(content.contains("fzf --zsh") && !content.contains("zoxide"));
+        assert!(
+            plan(None, None, &BTreeMap::from([("fzf", &fzf)]), &rc_files)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_managed_lines_come_first_in_existing_rc_files() {
+        let directory = tempfile::tempdir().unwrap();
+        let bashrc = directory.path().join(".bashrc");
+        let fish = directory.path().join("fish/config.fish");
+        std::fs::write(&bashrc, "alias ll='ls -l'\n").unwrap();
+        let rc_files = BTreeMap::from([
+            (Shell::Bash, bashrc.clone()),
+            (Shell::Zsh, directory.path().join(".zshrc")),
+            (Shell::Fish, fish.clone()),
+        ]);
+        let zoxide = BTreeMap::from([(Shell::Fish, "zoxide init fish | source".to_string())]);
+        let location = Path::new("/home/me/my \"bin\"");
+
+        let changes = plan(
+            Some(location),
+            Some(Path::new("/home/me/.config/workstation")),
+            &BTreeMap::from([("zoxide", &zoxide)]),
+            &rc_files,
+        )
+        .unwrap();
+        let paths: Vec<_> = changes.iter().map(|change| &change.path).collect();
+        assert_eq!(paths, [&bashrc, &fish]);
+        assert!(changes[0].new.contains(&format!(
+            "{}\n# PATH\ncase \":$PATH:\" in *\":/home/me/my \\\"bin\\\":\"*) ;; *) export PATH=\"/home/me/my \\\"bin\\\":$PATH\" ;; esac\n# env\n. '/home/me/.config/workstation/env.sh'\n{}",
+            NOTE, END
+        )));
+        assert!(changes[1].new.contains(
+            "# PATH\nfish_add_path --path '/home/me/my \"bin\"'\n# env\nsource '/home/me/.config/workstation/env.fish'\n# zoxide\nzoxide init fish | source\n"
+        ));
+    }
+}
@@ -0,0 +1,275 @@
+use std::io::Read;
+
+use base64::Engine;
+use ed25519_dalek::Verifier;
+use eyre::Context;
+use p256::{ecdsa::signature::hazmat::PrehashVerifier, pkcs8::DecodePublicKey};
+use sha2::Digest;
+
+use crate::asset::Asset;
+
+/// The key a package asset must be signed with, from `public_key`
+#[derive(Debug, Clone, PartialEq)]
+pub enum PublicKey {
+    /// An Ed25519 key as `minisign -G` writes it, with the id signatures name their key by
+    Minisign { id: [u8; 8], key: [u8; 32] },
+    /// A P-256 key as `cosign generate-key-pair` writes it
+    Cosign(p256::ecdsa::VerifyingKey),
+}
+
+impl PublicKey {
+    /// Read a cosign key in PEM, or a minisign key: the `RW...` line, or the whole `.pub` file
+    /// with its comment
+    pub fn parse(text: &str) -> eyre::Result<Self> {
+        let text = text.trim();
+        if text.starts_with("-----BEGIN PUBLIC KEY-----") {
+            // Keys pasted into a multi-line TOML string come indented
+            let pem: Vec<&str> = text.lines().map(str::trim).collect();
+            let key = p256::ecdsa::VerifyingKey::from_public_key_pem(&pem.join("\n"))
+                .map_err(|_| eyre::eyre!("Only cosign keys on the P-256 curve are supported"))?;
+            return Ok(PublicKey::Cosign(key));
+        }
+
+        let line = text
+            .lines()
+            .map(str::trim)
+            .rfind(|line| !line.is_empty() && !line.starts_with("untrusted comment:"))
+            .unwrap_or_default();
+        let bytes = decode(line)?;
+        let [b'E', b'd', rest @ ..] = bytes.as_slice() else {
+            eyre::bail!("Not a minisign or PEM public key");
+        };
+        let (id, key) = rest.split_at(rest.len().min(8));
+        Ok(PublicKey::Minisign {
+            id: id
+                .try_into()
+                .context("The minisign public key is truncated")?,
+            key: key
+                .try_into()
+                .context("The minisign public key is truncated")?,
+        })
+    }
+
+    /// What signed with the key, for messages
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PublicKey::Minisign { .. } => "minisign",
+            PublicKey::Cosign(_) => "cosign",
+        }
+    }
+}
+
+/// Check that `signature`, the content of a `.minisig` file or the base64 signature
+/// `cosign sign-blob` prints, was made by `key` over `asset`
+pub fn verify(key: &PublicKey, signature: &str, asset: &Asset) -> eyre::Result<()> {
+    match key {
+        PublicKey::Minisign { id, key } => verify_minisign(id, key, signature, asset),
+        PublicKey::Cosign(key) => {
+            let signature = p256::ecdsa::Signature::from_der(&decode(signature.trim())?)
+                .map_err(|_| eyre::eyre!("Not a DER encoded ECDSA signature"))?;
+            let mut hasher = sha2::Sha256::new();
+            stream(asset, &mut |chunk| hasher.update(chunk))?;
+            key.verify_prehash(&hasher.finalize(), &signature)
+                .map_err(|_| eyre::eyre!("The signature does not match the asset"))
+        }
+    }
+}
+
+/// A signature file has an untrusted comment, the signature, a trusted comment and a signature
+/// of the signature and the trusted comment together
+fn verify_minisign(
+    key_id: &[u8; 8],
+    key: &[u8; 32],
+    signature: &str,
+    asset: &Asset,
+) -> eyre::Result<()> {
+    let lines: Vec<&str> = signature
+        .lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|line| !line.is_empty())
+        .collect();
+    let [_, signature, trusted_comment, global_signature] = lines.as_slice() else {
+        eyre::bail!("Not a minisign signature file");
+    };
+    let trusted_comment = trusted_comment
+        .strip_prefix("trusted comment: ")
+        .ok_or_else(|| eyre::eyre!("The minisign signature has no trusted comment"))?;
+
+    let signature = decode(signature)?;
+    if signature.len() != 74 {
+        eyre::bail!("The minisign signature is truncated");
+    }
+    let (algorithm, rest) = signature.split_at(2);
+    let (id, signature) = rest.split_at(8);
+    if id != key_id {
+        eyre::bail!(
+            "Signed by the minisign key {}, not by the public_key {}",
+            key_id_hex(id),
+            key_id_hex(key_id)
+        );
+    }
+    let key = ed25519_dalek::VerifyingKey::from_bytes(key)
+        .map_err(|_| eyre::eyre!("The minisign public key is not a point on Ed25519"))?;
+    let signature = ed25519_dalek::Signature::from_slice(signature).expect("length checked");
+    let valid = match algorithm {
+        // Current minisign signs the BLAKE2b-512 hash of the file
+        b"ED" => key.verify_strict(&blake2b(asset)?, &signature),
+        // Legacy signatures are of the whole file, which has to be read into memory for them
+        b"Ed" => {
+            let mut content = Vec::with_capacity(asset.len() as usize);
+            stream(asset, &mut |chunk| content.extend_from_slice(chunk))?;
+            key.verify_strict(&content, &signature)
+        }
+        _ => eyre::bail!("Unknown minisign signature algorithm {:?}", algorithm),
+    };
+    if valid.is_err() {
+        eyre::bail!("The signature does not match the asset");
+    }
+
+    let global_signature = decode(global_signature)?;
+    let global_signature = ed25519_dalek::Signature::from_slice(&global_signature)
+        .context("The minisign signature of the trusted comment is truncated")?;
+    let signed = [&signature.to_bytes()[..], trusted_comment.as_bytes()].concat();
+    if key.verify(&signed, &global_signature).is_err() {
+        eyre::bail!("The trusted comment of the signature was altered");
+    }
+    Ok(())
+}
+
+/// Key ids as minisign prints them, a little endian number in hex
+fn key_id_hex(id: &[u8]) -> String {
+    id.iter()
+        .rev()
+        .map(|byte| format!("{:02X}", byte))
+        .collect()
+}
+
+fn decode(base64: &str) -> eyre::Result<Vec<u8>> {
+    base64::engine::general_purpose::STANDARD
+        .decode(base64)
+        .context("Not valid base64")
+}
+
+/// Hand the content of `asset` to `feed` chunk by chunk
+fn stream(asset: &Asset, feed: &mut dyn FnMut(&[u8])) -> eyre::Result<()> {
+    let mut reader = asset.reader()?;
+    let mut chunk = vec![0; 64 * 1024];
+    loop {
+        let read = reader.read(&mut chunk)?;
+        if read == 0 {
+            return Ok(());
+        }
+        feed(&chunk[..read]);
+    }
+}
+
+/// The BLAKE2b-512 hash of `asset`, which current minisign signs instead of the file
+fn blake2b(asset: &Asset) -> eyre::Result<Vec<u8>> {
+    use blake2::Digest;
+    let mut hasher = blake2::Blake2b512::new();
+    stream(asset, &mut |chunk| hasher.update(chunk))?;
+    Ok(hasher.finalize().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINISIGN_KEY: &str = "untrusted comment: minisign public key 0807060504030201
+RWQBAgMEBQYHCOaCpOGdcyb05tJn82+kIKGsTG/mGvSeq72F9D3Q0Lv7";
+
+    const MINISIG: &str = "untrusted comment: signature from minisign secret key
+RUQBAgMEBQYHCLvUxSWrOJJYsO5hlVItkPTo0X+TNW3OkrNKulIxKoCnqaoO/mgQ1MLQbWC82Zf+m9v7D1sARYwYu5Bq0JkxKAI=
+trusted comment: timestamp:1700000000\tfile:tool.tar.gz\thashed
+wvekx+1DjbQnBez4md7G8TvSxaMbuoDd4tTrw0OuKN1nZKoV1Nj18+E67nx66Lz/BIExgRI1w3qgZj9dslEoAA==
+";
+
+    /// Signed by minisign before it hashed files first
+    const LEGACY_MINISIG: &str = "untrusted comment: signature from minisign secret key
+RWQBAgMEBQYHCI7pQoZhEaIwOoKEhjUxh2jwZkqjXMcuwOsREyZwWvBc+pzNa0aZiB4cBeVRMOe3HSZDKXLTPnjbekzB49u28Qc=
+trusted comment: timestamp:1700000000\tfile:tool.tar.gz\thashed
+YnYzalvtJ6Cpfz8AYODCPEa90+L1Zou5rrLmQ+JOGCxneBAEXmlvzTf0OM/puC5TAUcmPEMSRcQsDaIdxqFyBg==
+";
+
+    const COSIGN_KEY: &str = "-----BEGIN PUBLIC KEY-----
+MFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEN8zaLTtF9crjMutwEW/Z0QqIqWsy
+cfD2k9jr4Ey81b0Lqff7GBx6CI5cYLo2RYci95tdxikrjZUButYD81pUFQ==
+-----END PUBLIC KEY-----
+";
+
+    const COSIGN_SIGNATURE: &str = "MEQCIBk+BMmAhMB5aKYB83qGPVSbj4ObFqZ1/iCSNN26r5o6AiAvTbE5iZLk7MIKlknTB/qbH8fGfzklISr/xIWuWMluDA==";
+
+    fn asset(content: &[u8]) -> Asset {
+        Asset::from_bytes(content).unwrap()
+    }
+
+    #[test]
+    fn test_minisign_signatures_are_verified() {
+        let key = PublicKey::parse(MINISIGN_KEY).unwrap();
+        assert_eq!(key.kind(), "minisign");
+        // The bare key line works as well
+        assert_eq!(
+            PublicKey::parse(MINISIGN_KEY.lines().last().unwrap()).unwrap(),
+            key
+        );
+
+        verify(&key, MINISIG, &asset(b"tool 1.0\n")).unwrap();
+        verify(&key, LEGACY_MINISIG, &asset(b"tool 1.0\n")).unwrap();
+
+        let error = verify(&key, MINISIG, &asset(b"tool 1.1\n")).unwrap_err();
+        assert!(error.to_string().contains("does not match"), "{}", error);
+        let altered = MINISIG.replace("tool.tar.gz", "other.tar.gz");
+        let error = verify(&key, &altered, &asset(b"tool 1.0\n")).unwrap_err();
+        assert!(error.to_string().contains("trusted comment"), "{}", error);
+    }
+
+    #[test]
+    fn test_signatures_of_other_keys_are_refused() {
+        let PublicKey::Minisign { key, .. } = PublicKey::parse(MINISIGN_KEY).unwrap() else {
+            unreachable!();
+        };
+        let other = PublicKey::Minisign { id: [9; 8], key };
+
+        let error = verify(&other, MINISIG, &asset(b"tool 1.0\n")).unwrap_err();
+
+        assert!(error
+            .to_string()
+            .contains("Signed by the minisign key 0807060504030201"));
+    }
+
+    #[test]
+    fn test_cosign_signatures_are_verified() {
+        let key = PublicKey::parse(COSIGN_KEY).unwrap();
+        assert_eq!(key.kind(), "cosign");
+
+        verify(&key, COSIGN_SIGNATURE, &asset(b"tool 1.0\n")).unwrap();
+
+        assert!(verify(&key, COSIGN_SIGNATURE, &asset(b"tool 1.1\n")).is_err());
+        assert!(verify(&key, "not base64!", &asset(b"tool 1.0\n")).is_err());
+        assert!(PublicKey::parse("RWQ=").is_err());
+    }
+
+    #[test]
+    fn test_blake2b_matches_the_rfc() {
+        let hex = |content: &[u8]| {
+            let digest: String = blake2b(&asset(content))
+                .unwrap()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            digest
+        };
+
+        assert_eq!(
+            hex(b"abc"),
+            "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d1\
+             7d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923"
+        );
+        // Two exactly full blocks, the second one compressed as the last
+        assert_eq!(
+            hex(&[7; 256]),
+            "f1e8e929e223089902ed787c9d6cb1c12af84505c994f73ce3d38610d0624f34\
+             e7e37cf61001a3bd4298f8df2e5f664aa0ff0e542f85ea1028fec51ed1f8614f"
+        );
+    }
+}
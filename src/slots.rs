@@ -0,0 +1,88 @@
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// How many install tasks may do something at the same time, such as downloading.
+///
+/// Tasks past the limit wait for a slot to be given back before they go on, without holding a
+/// thread while they wait.
+pub struct Slots {
+    limit: usize,
+    semaphore: Semaphore,
+}
+
+/// A taken slot, given back when dropped
+pub type Slot<'a> = SemaphorePermit<'a>;
+
+impl Slots {
+    /// A `limit` of 0 is taken as 1, one past what a semaphore holds as unlimited
+    pub fn new(limit: usize) -> Self {
+        let limit = limit.clamp(1, Semaphore::MAX_PERMITS);
+        Slots {
+            limit,
+            semaphore: Semaphore::new(limit),
+        }
+    }
+
+    /// Take a slot, waiting until one is free. `waiting` is called once before waiting, with
+    /// the limit.
+    pub async fn acquire(&self, waiting: impl FnOnce(usize)) -> Slot<'_> {
+        if let Ok(slot) = self.semaphore.try_acquire() {
+            return slot;
+        }
+        waiting(self.limit);
+        self.semaphore
+            .acquire()
+            .await
+            .expect("the semaphore is never closed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_slots_are_never_exceeded() {
+        let slots = Arc::new(Slots::new(2));
+        let running = Arc::new(AtomicU64::new(0));
+        let peak = Arc::new(AtomicU64::new(0));
+        let waited = Arc::new(AtomicU64::new(0));
+
+        let mut tasks = tokio::task::JoinSet::new();
+        for _ in 0..8 {
+            let (slots, running, peak, waited) =
+                (slots.clone(), running.clone(), peak.clone(), waited.clone());
+            tasks.spawn_on(
+                async move {
+                    let _slot = slots
+                        .acquire(|limit| {
+                            assert_eq!(limit, 2);
+                            waited.fetch_add(1, Ordering::Relaxed);
+                        })
+                        .await;
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                },
+                crate::http::runtime().handle(),
+            );
+        }
+        crate::http::runtime().block_on(async {
+            while let Some(finished) = tasks.join_next().await {
+                finished.unwrap();
+            }
+        });
+
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        assert!(waited.load(Ordering::Relaxed) > 0);
+        assert_eq!(slots.semaphore.available_permits(), 2);
+    }
+}
@@ -0,0 +1,421 @@
+//! Configs encrypted with SOPS and age, decrypted in memory only.
+//!
+//! SOPS has no TOML format, `sops encrypt workstation.toml` encrypts the file as a whole and
+//! stores it as JSON. To keep only some packages private, put them in a file the config
+//! includes and encrypt that one.
+
+use std::{
+    io::Read,
+    path::PathBuf,
+    sync::{Mutex, OnceLock},
+};
+
+use aes_gcm::{
+    aead::{consts::U32, Aead, Payload},
+    aes::Aes256,
+    AesGcm, KeyInit,
+};
+use base64::Engine;
+use eyre::Context;
+use serde::Deserialize;
+use sha2::{Digest, Sha512};
+
+use crate::expand::Env;
+
+/// SOPS encrypts values with AES-256-GCM, with 32 byte nonces rather than the usual 12
+type Cipher = AesGcm<Aes256, U32>;
+
+/// Shown instead of a decrypted value wherever it would otherwise be written to disk
+pub const REDACTED: &str = "<encrypted>";
+
+static IDENTITY_FILE: OnceLock<PathBuf> = OnceLock::new();
+/// Every value decrypted in this run, see [`redact`]
+static SECRETS: Mutex<Vec<String>> = Mutex::new(vec![]);
+
+/// Decrypt with the identities in `path` instead of the standard locations, see `--age-identity`
+pub fn init_identity_file(path: PathBuf) -> eyre::Result<()> {
+    IDENTITY_FILE
+        .set(path)
+        .map_err(|_| eyre::eyre!("The age identity file was already set"))
+}
+
+#[derive(Deserialize, Debug)]
+struct Metadata {
+    #[serde(default)]
+    age: Vec<AgeKey>,
+    lastmodified: String,
+    mac: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct AgeKey {
+    recipient: String,
+    /// The data key, encrypted to `recipient` as an armored age file
+    enc: String,
+}
+
+/// Whether `source` was encrypted by SOPS, which stores it as JSON with `data` and `sops` keys
+pub fn is_encrypted(source: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(source)
+        .is_ok_and(|json| json.get("sops").is_some() && json.get("data").is_some())
+}
+
+/// Decrypt a SOPS encrypted config
+pub fn decrypt(source: &str) -> eyre::Result<String> {
+    decrypt_with(source, &identity_sources(&Env::process()))
+}
+
+fn decrypt_with(source: &str, identities: &[IdentitySource]) -> eyre::Result<String> {
+    let json: serde_json::Value = serde_json::from_str(source).context("Parsing the config")?;
+    let metadata: Metadata =
+        serde_json::from_value(json["sops"].clone()).context("Reading the sops metadata")?;
+    let data = json["data"]
+        .as_str()
+        .ok_or_else(|| eyre::eyre!("The encrypted config has no data"))?;
+    let key = data_key(&metadata, identities)?;
+    // The file is the single value `data`, which is also its key path
+    let plaintext = decrypt_value(data, &key, "data:")?;
+    verify_mac(&metadata, &key, &plaintext)?;
+    match toml::from_str(&plaintext) {
+        Ok(config) => remember_locations(&config),
+        Err(_) => remember(&plaintext),
+    }
+
+    Ok(plaintext)
+}
+
+/// Replace every value decrypted in this run with [`REDACTED`]
+pub fn redact(text: &str) -> String {
+    let secrets = SECRETS.lock().unwrap_or_else(|p| p.into_inner());
+    secrets.iter().fold(text.to_string(), |text, secret| {
+        text.replace(secret, REDACTED)
+    })
+}
+
+/// Every value of the config is private, but redacting names and versions like `latest`
+/// would mangle unrelated text. What locates packages, the URLs, repos and paths, has a `/`
+fn remember_locations(value: &toml::Value) {
+    match value {
+        toml::Value::String(text) if text.contains('/') => remember(text),
+        toml::Value::Array(values) => values.iter().for_each(remember_locations),
+        toml::Value::Table(table) => table.values().for_each(remember_locations),
+        _ => {}
+    }
+}
+
+fn remember(secret: &str) {
+    if secret.is_empty() {
+        return;
+    }
+    let mut secrets = SECRETS.lock().unwrap_or_else(|p| p.into_inner());
+    if !secrets.iter().any(|known| known == secret) {
+        secrets.push(secret.to_string());
+        // Longer secrets first, so one containing another is redacted whole
+        secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+    }
+}
+
+/// Where age identities are looked for
+#[derive(Debug, Clone, PartialEq)]
+enum IdentitySource {
+    File(PathBuf),
+    /// The identities themselves, from `SOPS_AGE_KEY`
+    Env(String),
+}
+
+impl std::fmt::Display for IdentitySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IdentitySource::File(path) => write!(f, "{}", path.display()),
+            IdentitySource::Env(_) => write!(f, "$SOPS_AGE_KEY"),
+        }
+    }
+}
+
+/// `--age-identity` alone when given, otherwise the places `sops` itself looks in
+fn identity_sources(env: &Env) -> Vec<IdentitySource> {
+    if let Some(path) = IDENTITY_FILE.get() {
+        return vec![IdentitySource::File(path.clone())];
+    }
+
+    let mut sources = vec![];
+    if let Some(keys) = env.var("SOPS_AGE_KEY") {
+        sources.push(IdentitySource::Env(keys));
+    }
+    if let Some(path) = env.var("SOPS_AGE_KEY_FILE") {
+        sources.push(IdentitySource::File(path.into()));
+    }
+    let config_home = env
+        .var("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| Some(PathBuf::from(env.var("HOME")?).join(".config")));
+    if let Some(config_home) = config_home {
+        sources.push(IdentitySource::File(
+            config_home.join("sops").join("age").join("keys.txt"),
+        ));
+    }
+    sources
+}
+
+fn load_identities(
+    sources: &[IdentitySource],
+    tried: &mut Vec<String>,
+) -> Vec<Box<dyn age::Identity + Send + Sync>> {
+    let mut identities = vec![];
+    for source in sources {
+        let file = match source {
+            IdentitySource::File(path) => match std::fs::read(path) {
+                Ok(bytes) => age::IdentityFile::from_buffer(bytes.as_slice()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    tried.push(format!("{} (missing)", source));
+                    continue;
+                }
+                Err(e) => {
+                    tried.push(format!("{} ({})", source, e));
+                    continue;
+                }
+            },
+            IdentitySource::Env(keys) => age::IdentityFile::from_buffer(keys.as_bytes()),
+        };
+        match file.map(|file| file.into_identities()) {
+            Ok(Ok(found)) => {
+                tried.push(format!("{} ({} identities)", source, found.len()));
+                identities.extend(found);
+            }
+            _ => tried.push(format!("{} (not an age identity file)", source)),
+        }
+    }
+    identities
+}
+
+/// The key every value is encrypted with, from the first age recipient an identity matches
+fn data_key(metadata: &Metadata, sources: &[IdentitySource]) -> eyre::Result<Vec<u8>> {
+    if metadata.age.is_empty() {
+        eyre::bail!(
+            "The config is encrypted without an age recipient, only age keys are supported"
+        );
+    }
+    let mut tried = vec![];
+    let identities = load_identities(sources, &mut tried);
+
+    for key in &metadata.age {
+        let reader = age::armor::ArmoredReader::new(key.enc.trim().as_bytes());
+        let Ok(decryptor) = age::Decryptor::new(reader) else {
+            continue;
+        };
+        let Ok(mut plaintext) = decryptor.decrypt(identities.iter().map(|i| i.as_ref() as _))
+        else {
+            continue;
+        };
+        let mut data_key = vec![];
+        plaintext
+            .read_to_end(&mut data_key)
+            .context("Decrypting the data key")?;
+        return Ok(data_key);
+    }
+
+    let recipients: Vec<_> = metadata.age.iter().map(|k| k.recipient.as_str()).collect();
+    eyre::bail!(
+        "No age identity for {} was found. Looked in: {}. Pass the identity file with --age-identity FILE",
+        recipients.join(", "),
+        if tried.is_empty() {
+            "nowhere, HOME is not set".to_string()
+        } else {
+            tried.join(", ")
+        }
+    )
+}
+
+/// Decrypt `ENC[AES256_GCM,data:…,iv:…,tag:…,type:str]`, `aad` is the key path of the value
+fn decrypt_value(value: &str, key: &[u8], aad: &str) -> eyre::Result<String> {
+    let fields = value
+        .strip_prefix("ENC[AES256_GCM,")
+        .and_then(|rest| rest.strip_suffix(']'))
+        .ok_or_else(|| eyre::eyre!("Not an encrypted value"))?;
+    let field = |name: &str| {
+        fields
+            .split(',')
+            .find_map(|field| field.strip_prefix(name)?.strip_prefix(':'))
+            .ok_or_else(|| eyre::eyre!("The encrypted value has no {}", name))
+    };
+    let decode = |name: &str| -> eyre::Result<Vec<u8>> {
+        base64::engine::general_purpose::STANDARD
+            .decode(field(name)?)
+            .with_context(|| format!("The {} of the encrypted value is not base64", name))
+    };
+
+    let mut ciphertext = decode("data")?;
+    ciphertext.extend(decode("tag")?);
+    let iv = decode("iv")?;
+    let cipher = Cipher::new_from_slice(key).map_err(|_| eyre::eyre!("Invalid data key"))?;
+    let nonce = iv
+        .as_slice()
+        .try_into()
+        .map_err(|_| eyre::eyre!("The encrypted value has a {} byte iv", iv.len()))?;
+    let plaintext = cipher
+        .decrypt(
+            nonce,
+            Payload {
+                msg: &ciphertext,
+                aad: aad.as_bytes(),
+            },
+        )
+        .map_err(|_| eyre::eyre!("The value does not decrypt, it was changed or moved"))?;
+    match field("type")? {
+        "str" => String::from_utf8(plaintext).context("The decrypted value is not UTF-8"),
+        other => eyre::bail!("The encrypted value is of type {}, not a string", other),
+    }
+}
+
+/// Check the SHA-512 of the decrypted file against the encrypted MAC, so the data cannot be
+/// swapped for other data encrypted with the same key
+fn verify_mac(metadata: &Metadata, key: &[u8], plaintext: &str) -> eyre::Result<()> {
+    let computed: String = Sha512::digest(plaintext.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect();
+
+    let expected =
+        decrypt_value(&metadata.mac, key, &metadata.lastmodified).context("Decrypting the MAC")?;
+    if computed != expected {
+        eyre::bail!("The MAC does not match, the file was changed after it was encrypted");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use age::secrecy::ExposeSecret;
+
+    use super::*;
+
+    const DATA_KEY: [u8; 32] = [7; 32];
+    const LAST_MODIFIED: &str = "2026-10-15T10:00:00Z";
+
+    fn encrypt(plaintext: &str, aad: &str) -> String {
+        let engine = base64::engine::general_purpose::STANDARD;
+        let iv = [aad.len() as u8; 32];
+        let mut ciphertext = Cipher::new_from_slice(&DATA_KEY)
+            .unwrap()
+            .encrypt(
+                iv.as_slice().try_into().unwrap(),
+                Payload {
+                    msg: plaintext.as_bytes(),
+                    aad: aad.as_bytes(),
+                },
+            )
+            .unwrap();
+        let tag = ciphertext.split_off(ciphertext.len() - 16);
+        format!(
+            "ENC[AES256_GCM,data:{},iv:{},tag:{},type:str]",
+            engine.encode(ciphertext),
+            engine.encode(iv),
+            engine.encode(tag),
+        )
+    }
+
+    /// A config as SOPS stores it, `data` being the encrypted file and `mac_of` what the MAC
+    /// was computed over
+    fn encrypted_config(identity: &age::x25519::Identity, data: &str, mac_of: &str) -> String {
+        let mac: String = Sha512::digest(mac_of.as_bytes())
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        let recipient = identity.to_public();
+        serde_json::json!({
+            "data": data,
+            "sops": {
+                "age": [{
+                    "recipient": recipient.to_string(),
+                    "enc": age::encrypt_and_armor(&recipient, &DATA_KEY).unwrap(),
+                }],
+                "lastmodified": LAST_MODIFIED,
+                "mac": encrypt(&mac, LAST_MODIFIED),
+            },
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn test_config_encrypted_by_sops_decrypts() {
+        let source = include_str!("../fixtures/sops_encrypted.toml");
+        assert!(is_encrypted(source));
+        let keys = IdentitySource::Env(include_str!("../fixtures/sops_age_keys.txt").to_string());
+
+        let plain = decrypt_with(source, &[keys]).unwrap();
+
+        assert!(!is_encrypted(&plain));
+        assert_eq!(
+            plain,
+            "[linux_x86_64]\npackages = [\n  \
+             { name = \"tool\", url = \"https://token@private.example.com/tool\" },\n]\n"
+        );
+        assert_eq!(
+            redact("tool from https://token@private.example.com/tool"),
+            "tool from <encrypted>"
+        );
+    }
+
+    #[test]
+    fn test_tampering_and_missing_identities_are_reported() {
+        let identity = age::x25519::Identity::generate();
+        let config = "[linux_x86_64]\npackages = []\n";
+        let keys = IdentitySource::Env(identity.to_string().expose_secret().to_string());
+        let decrypt = |source: &str| decrypt_with(source, std::slice::from_ref(&keys));
+        assert_eq!(
+            decrypt(&encrypted_config(
+                &identity,
+                &encrypt(config, "data:"),
+                config
+            ))
+            .unwrap(),
+            config
+        );
+
+        // Data encrypted under another key path fails its authentication
+        let moved = encrypted_config(&identity, &encrypt(config, "other:"), config);
+        let error = decrypt(&moved).unwrap_err();
+        assert!(
+            format!("{:?}", error).contains("does not decrypt"),
+            "{:?}",
+            error
+        );
+
+        // Data swapped for other data encrypted with the same key fails the MAC
+        let swapped = encrypted_config(&identity, &encrypt("[common]\n", "data:"), config);
+        let error = decrypt(&swapped).unwrap_err();
+        assert!(
+            error.to_string().contains("MAC does not match"),
+            "{:?}",
+            error
+        );
+
+        let missing = IdentitySource::File(PathBuf::from("/nonexistent/keys.txt"));
+        let source = encrypted_config(&identity, &encrypt(config, "data:"), config);
+        let error = decrypt_with(&source, &[missing]).unwrap_err().to_string();
+        assert!(
+            error.contains(&identity.to_public().to_string()),
+            "{}",
+            error
+        );
+        assert!(
+            error.contains("/nonexistent/keys.txt (missing)"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_identity_sources_follow_sops() {
+        let env = Env::from_vars(&[("HOME", "/home/u"), ("SOPS_AGE_KEY_FILE", "/keys.txt")]);
+
+        assert_eq!(
+            identity_sources(&env),
+            vec![
+                IdentitySource::File("/keys.txt".into()),
+                IdentitySource::File("/home/u/.config/sops/age/keys.txt".into()),
+            ]
+        );
+    }
+}
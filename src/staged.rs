@@ -0,0 +1,158 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    os::unix::fs::PermissionsExt,
+    path::Path,
+};
+
+use eyre::Context;
+use sha2::Digest;
+
+use crate::digest;
+
+/// A file about to be installed, streamed under a temporary name into the directory it is
+/// installed in. Moving it into place is then a rename, and its content is never held in memory
+/// however large the entry it was extracted from.
+///
+/// The file is removed when it is dropped without being installed.
+#[derive(Debug)]
+pub struct Staged {
+    file: tempfile::NamedTempFile,
+    len: u64,
+    hash: String,
+}
+
+/// Hashes and counts what is written through it
+struct Hashing<W> {
+    inner: W,
+    hasher: sha2::Sha256,
+    len: u64,
+}
+
+impl<W: Write> Write for Hashing<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.len += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Staged {
+    /// Stage the file to install as `path` with what `write` writes
+    pub fn new(
+        path: &Path,
+        write: impl FnOnce(&mut dyn Write) -> eyre::Result<()>,
+    ) -> eyre::Result<Self> {
+        let parent = path.parent().expect("install path has a parent");
+        let file_name = path.file_name().expect("install path has a name");
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Creating {}", parent.display()))?;
+        let file = tempfile::Builder::new()
+            .prefix(&format!(".{}.workstation-", file_name.to_string_lossy()))
+            .tempfile_in(parent)
+            .with_context(|| format!("Creating a file in {}", parent.display()))?;
+
+        let (len, hash) = {
+            let mut writer = Hashing {
+                inner: BufWriter::new(file.as_file()),
+                hasher: sha2::Sha256::new(),
+                len: 0,
+            };
+            write(&mut writer)?;
+            writer
+                .flush()
+                .with_context(|| format!("Writing {}", file.path().display()))?;
+            (writer.len, digest::finish(writer.hasher))
+        };
+        Ok(Staged { file, len, hash })
+    }
+
+    /// Stage `data`, which a build left in memory
+    pub fn from_bytes(path: &Path, data: &[u8]) -> eyre::Result<Self> {
+        Self::new(path, |file| Ok(file.write_all(data)?))
+    }
+
+    /// Stage a copy of the file at `source`
+    pub fn copy(path: &Path, source: &Path) -> eyre::Result<Self> {
+        let mut source =
+            File::open(source).with_context(|| format!("Reading {}", source.display()))?;
+        Self::new(path, |file| {
+            std::io::copy(&mut source, file)?;
+            Ok(())
+        })
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// `sha256:<hex>` of the content, see [`digest::sha256`]
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    /// Where the file is until it is installed
+    pub fn path(&self) -> &Path {
+        self.file.path()
+    }
+
+    /// The content from its start
+    pub fn reader(&self) -> eyre::Result<File> {
+        Ok(self.file.reopen()?)
+    }
+
+    /// Give the file permissions `mode` and rename it to `path`, which is in the directory it
+    /// was staged in
+    pub fn install(self, path: &Path, mode: u32) -> eyre::Result<()> {
+        let file = self.file.as_file();
+        file.set_permissions(std::fs::Permissions::from_mode(mode))?;
+        file.sync_all()?;
+        self.file
+            .persist(path)
+            .with_context(|| format!("Moving {} into place", path.display()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_staged_files_are_hashed_and_cleaned_up() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("bin").join("rg");
+
+        let staged = Staged::from_bytes(&path, b"rg 14").unwrap();
+        assert_eq!(staged.len(), 5);
+        assert_eq!(staged.hash(), digest::sha256(b"rg 14"));
+        assert_eq!(
+            std::fs::read_dir(path.parent().unwrap()).unwrap().count(),
+            1
+        );
+        drop(staged);
+        assert_eq!(
+            std::fs::read_dir(path.parent().unwrap()).unwrap().count(),
+            0
+        );
+
+        Staged::from_bytes(&path, b"rg 14")
+            .unwrap()
+            .install(&path, 0o755)
+            .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"rg 14");
+        assert_eq!(
+            std::fs::read_dir(path.parent().unwrap()).unwrap().count(),
+            1
+        );
+    }
+}
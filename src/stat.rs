@@ -0,0 +1,287 @@
+use std::{
+    collections::BTreeMap,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use eyre::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::digest;
+
+const CACHE_FILE: &str = "hashes.json";
+/// The newest version of the file this binary reads and writes, see [`crate::state`]
+const SCHEMA_VERSION: u32 = 1;
+/// Hex digits of the hash shown by `list --verbose`
+const SHORT_HASH: usize = 12;
+
+/// What `list --verbose` shows about an installed file
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FileStat {
+    pub size: u64,
+    /// Permission bits, such as 0o755
+    pub mode: u32,
+    /// Seconds since the epoch
+    pub modified: u64,
+    pub hash: String,
+}
+
+impl FileStat {
+    /// The first hex digits of the hash, enough to tell two versions apart
+    pub fn short_hash(&self) -> &str {
+        let hex = digest::validate(&self.hash).unwrap_or(&self.hash);
+        &hex[..hex.len().min(SHORT_HASH)]
+    }
+
+    pub fn modified_utc(&self) -> String {
+        format_utc(self.modified)
+    }
+}
+
+/// The stat fields that decide whether a cached hash still holds
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+struct Stamp {
+    size: u64,
+    mode: u32,
+    /// Modification time in nanoseconds since the epoch
+    modified: u128,
+    inode: u64,
+}
+
+impl Stamp {
+    fn read(path: &Path) -> std::io::Result<Self> {
+        let metadata = std::fs::metadata(path)?;
+        Ok(Stamp {
+            size: metadata.len(),
+            mode: metadata.permissions().mode() & 0o7777,
+            modified: nanos(metadata.modified()?),
+            inode: metadata.ino(),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Entry {
+    stamp: Stamp,
+    hash: String,
+    /// When the hash was taken, in nanoseconds since the epoch
+    hashed: u128,
+}
+
+fn nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|time| time.as_nanos())
+        .unwrap_or_default()
+}
+
+/// File hashes kept in the state directory, so that listing packages only hashes the files whose
+/// stat changed since the last time
+pub struct StatCache {
+    path: PathBuf,
+    entries: BTreeMap<PathBuf, Entry>,
+    changed: bool,
+}
+
+impl StatCache {
+    /// Load the cache, a missing or unreadable file only means every file is hashed again
+    pub fn load(state_dir: &Path) -> Self {
+        let path = state_dir.join(CACHE_FILE);
+        let entries = std::fs::read(&path)
+            .ok()
+            .and_then(|bytes| crate::state::parse(&bytes, SCHEMA_VERSION).ok())
+            .unwrap_or_default();
+        StatCache {
+            path,
+            entries,
+            changed: false,
+        }
+    }
+
+    /// Write the cache back when a hash was added or replaced
+    pub fn save(&self) -> eyre::Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        let directory = self.path.parent().expect("cache path has a parent");
+        std::fs::create_dir_all(directory)
+            .with_context(|| format!("Creating {}", directory.display()))?;
+        let json = crate::state::to_json(&self.entries, SCHEMA_VERSION);
+        std::fs::write(&self.path, json).with_context(|| format!("Writing {}", self.path.display()))
+    }
+
+    /// Stat `path` and hash it unless the cache has a hash for exactly this stat.
+    /// Returns `None` when the file does not exist.
+    pub fn stat(&mut self, path: &Path) -> eyre::Result<Option<FileStat>> {
+        let stamp = match Stamp::read(path) {
+            Ok(stamp) => stamp,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Reading {}", path.display())),
+        };
+
+        let hash = match self.entries.get(path) {
+            Some(entry) if entry.trusted(&stamp) => entry.hash.clone(),
+            _ => self.hash(path, stamp)?,
+        };
+
+        Ok(Some(FileStat {
+            size: stamp.size,
+            mode: stamp.mode,
+            modified: (stamp.modified / 1_000_000_000) as u64,
+            hash,
+        }))
+    }
+
+    fn hash(&mut self, path: &Path, stamp: Stamp) -> eyre::Result<String> {
+        let hashed = nanos(SystemTime::now());
+        let bytes = std::fs::read(path).with_context(|| format!("Reading {}", path.display()))?;
+        let hash = digest::sha256(&bytes);
+
+        // A file that changed while it was read gets the hash of what was read, but it is not
+        // cached, the next run hashes it again
+        if Stamp::read(path).ok() == Some(stamp) {
+            self.entries.insert(
+                path.to_path_buf(),
+                Entry {
+                    stamp,
+                    hash: hash.clone(),
+                    hashed,
+                },
+            );
+        } else {
+            self.entries.remove(path);
+        }
+        self.changed = true;
+
+        Ok(hash)
+    }
+}
+
+impl Entry {
+    /// Whether the cached hash can stand in for reading the file.
+    ///
+    /// A file modified in the same clock tick it was hashed in can change again without its
+    /// modification time moving, so such entries and malformed hashes are never trusted.
+    fn trusted(&self, stamp: &Stamp) -> bool {
+        self.stamp == *stamp
+            && self.stamp.modified < self.hashed
+            && digest::validate(&self.hash).is_ok()
+    }
+}
+
+/// `YYYY-MM-DD HH:MM` in UTC
+pub fn format_utc(seconds: u64) -> String {
+    let days = (seconds / 86_400) as i64;
+    let minutes = seconds % 86_400 / 60;
+
+    // Civil date from days since 1970-01-01, after Howard Hinnant's `civil_from_days`
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn age(path: &Path) {
+        // Move the modification time well before the hash, as for any file not edited just now
+        let file = std::fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() - std::time::Duration::from_secs(60))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_only_changed_files_are_hashed_again() {
+        let directory = tempfile::tempdir().unwrap();
+        let file = directory.path().join("rg");
+        std::fs::write(&file, b"v1").unwrap();
+        age(&file);
+
+        let mut cache = StatCache::load(directory.path());
+        let first = cache.stat(&file).unwrap().unwrap();
+        assert_eq!(first.hash, digest::sha256(b"v1"));
+        assert_eq!(first.size, 2);
+        cache.save().unwrap();
+
+        // A cached hash is used as is, even though it no longer matches what a read would give
+        let mut cache = StatCache::load(directory.path());
+        cache.entries.get_mut(&file).unwrap().hash = digest::sha256(b"cached");
+        assert_eq!(
+            cache.stat(&file).unwrap().unwrap().hash,
+            digest::sha256(b"cached")
+        );
+        assert!(!cache.changed);
+
+        std::fs::write(&file, b"v2, longer").unwrap();
+        age(&file);
+        assert_eq!(
+            cache.stat(&file).unwrap().unwrap().hash,
+            digest::sha256(b"v2, longer")
+        );
+
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o700)).unwrap();
+        cache.entries.get_mut(&file).unwrap().hash = digest::sha256(b"stale");
+        let restat = cache.stat(&file).unwrap().unwrap();
+        assert_eq!(restat.mode, 0o700);
+        assert_eq!(restat.hash, digest::sha256(b"v2, longer"));
+
+        assert_eq!(cache.stat(&directory.path().join("missing")).unwrap(), None);
+    }
+
+    #[test]
+    fn test_untrustworthy_entries_are_hashed_again() {
+        let directory = tempfile::tempdir().unwrap();
+        let file = directory.path().join("fd");
+        std::fs::write(&file, b"fd").unwrap();
+
+        // Modified after it was hashed, within the same clock tick as far as the stat can tell
+        let mut cache = StatCache::load(directory.path());
+        cache.stat(&file).unwrap();
+        let entry = cache.entries.get_mut(&file).unwrap();
+        entry.hashed = entry.stamp.modified;
+        entry.hash = digest::sha256(b"racy");
+        assert_eq!(
+            cache.stat(&file).unwrap().unwrap().hash,
+            digest::sha256(b"fd")
+        );
+
+        age(&file);
+        cache.stat(&file).unwrap();
+        cache.entries.get_mut(&file).unwrap().hash = "sha256:truncated".to_string();
+        assert_eq!(
+            cache.stat(&file).unwrap().unwrap().hash,
+            digest::sha256(b"fd")
+        );
+
+        std::fs::write(directory.path().join(CACHE_FILE), b"{ not json").unwrap();
+        let mut cache = StatCache::load(directory.path());
+        assert_eq!(
+            cache.stat(&file).unwrap().unwrap().hash,
+            digest::sha256(b"fd")
+        );
+    }
+
+    #[test]
+    fn test_format_utc() {
+        assert_eq!(format_utc(0), "1970-01-01 00:00");
+        assert_eq!(format_utc(951_782_400 + 3_660), "2000-02-29 01:01");
+        assert_eq!(format_utc(1_792_022_400), "2026-10-15 00:00");
+    }
+}
@@ -0,0 +1,93 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::migrate::VERSION_KEY;
+
+/// Key of the records in a state file that has a version
+const RECORDS_KEY: &str = "records";
+
+/// The version of a state file without `schema_version`, written before state files had one
+const UNVERSIONED: u64 = 0;
+
+/// The records of the JSON state file `bytes`, whose format this binary knows up to schema
+/// version `current`.
+///
+/// A file without `schema_version` is version 0 and holds the records alone, every later version
+/// keeps them under `records`.
+pub fn parse<T: DeserializeOwned>(bytes: &[u8], current: u32) -> eyre::Result<T> {
+    let mut file: serde_json::Value = serde_json::from_slice(bytes)?;
+    let version = match file.get(VERSION_KEY) {
+        None => UNVERSIONED,
+        Some(version) => version
+            .as_u64()
+            .filter(|version| *version > UNVERSIONED)
+            .ok_or_else(|| {
+                eyre::eyre!(
+                    "`{}` must be a positive number, not {}",
+                    VERSION_KEY,
+                    version
+                )
+            })?,
+    };
+    if version > u64::from(current) {
+        eyre::bail!(
+            "The file uses schema version {}, but this build of workstation only understands up to version {}. Upgrade workstation to use it.",
+            version,
+            current
+        );
+    }
+    let records = match version {
+        UNVERSIONED => file,
+        _ => file
+            .get_mut(RECORDS_KEY)
+            .ok_or_else(|| eyre::eyre!("`{}` is missing", RECORDS_KEY))?
+            .take(),
+    };
+    Ok(serde_json::from_value(records)?)
+}
+
+/// A state file as it is written, the version first
+#[derive(Serialize)]
+struct Versioned<'a, T> {
+    schema_version: u32,
+    records: &'a T,
+}
+
+/// `records` as the state file of schema version `current`, see [`parse`]
+pub fn to_json<T: Serialize>(records: &T, current: u32) -> String {
+    let file = Versioned {
+        schema_version: current,
+        records,
+    };
+    serde_json::to_string_pretty(&file).expect("records serialize") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[test]
+    fn test_state_files_with_and_without_a_version_are_read() {
+        let records = BTreeMap::from([("fd".to_string(), 1)]);
+
+        let json = to_json(&records, 1);
+        assert!(json.starts_with("{\n  \"schema_version\": 1,"), "{}", json);
+        assert_eq!(
+            parse::<BTreeMap<String, u32>>(json.as_bytes(), 1).unwrap(),
+            records
+        );
+        assert_eq!(
+            parse::<BTreeMap<String, u32>>(br#"{"fd": 1}"#, 1).unwrap(),
+            records
+        );
+
+        let newer = parse::<BTreeMap<String, u32>>(br#"{"schema_version": 2, "records": {}}"#, 1);
+        assert!(format!("{:#}", newer.unwrap_err()).contains("Upgrade workstation"));
+        assert!(parse::<BTreeMap<String, u32>>(br#"{"schema_version": "1"}"#, 1).is_err());
+        // Only files without a version are version 0
+        assert!(
+            parse::<BTreeMap<String, u32>>(br#"{"schema_version": 0, "records": {}}"#, 1).is_err()
+        );
+    }
+}
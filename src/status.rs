@@ -0,0 +1,287 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::{
+    config::PackageConfig,
+    ignore::IgnoreSet,
+    lock,
+    manifest::Manifest,
+    sops,
+    stat::StatCache,
+    update::Reason,
+    version::{Inferred, Versions},
+};
+
+/// Where a configured package stands against what `setup` last installed
+#[derive(Debug, Clone, PartialEq)]
+pub enum State {
+    UpToDate,
+    Missing,
+    /// Changed on disk since it was installed
+    Modified,
+    Outdated(Reason),
+    /// Matches a `workstation.install.ignore` pattern, `setup` never touches it
+    Ignored(String),
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            State::UpToDate => write!(f, "up to date"),
+            State::Missing => write!(f, "missing"),
+            State::Modified => write!(f, "modified since it was installed"),
+            State::Outdated(reason) => write!(f, "outdated, {}", reason),
+            State::Ignored(pattern) => write!(f, "ignored by {:?}", pattern),
+        }
+    }
+}
+
+impl Serialize for State {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Status {
+    pub name: String,
+    pub path: PathBuf,
+    pub installed: bool,
+    pub state: State,
+    /// Guessed at install time, see [`crate::version::infer`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<Inferred>,
+    /// Hash of the file as installed, from the manifest
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// When `setup` installed it, in seconds since the epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub installed_at: Option<u64>,
+}
+
+/// The state of every configured package, without touching the network: releases of GitHub
+/// packages without a `version` are not looked up, `update --dry-run` does that.
+pub fn status(
+    packages: &[PackageConfig],
+    manifest: &Manifest,
+    versions: &Versions,
+    hashes: &mut StatCache,
+    ignore: &IgnoreSet,
+    path_of: impl Fn(&PackageConfig) -> eyre::Result<PathBuf>,
+) -> eyre::Result<Vec<Status>> {
+    let mut statuses = vec![];
+
+    for package in packages {
+        let name = package.name();
+        let path = path_of(package)?;
+        // Directories and whatever custom commands create are not hashed, only looked for
+        let stat = match package.is_single_file() {
+            true => hashes.stat(&path)?,
+            false => None,
+        };
+        let installed = match package {
+            PackageConfig::System { .. } => crate::system::is_installed(package)?,
+            PackageConfig::Distro { .. } => crate::distro::installed_version(package)?.is_some(),
+            PackageConfig::Pipx { .. }
+            | PackageConfig::Npm { .. }
+            | PackageConfig::Flatpak { .. }
+            | PackageConfig::Snap { .. } => crate::global::is_installed(package)?,
+            _ => stat.is_some() || path.exists(),
+        };
+        let recorded = manifest
+            .get(name)
+            .filter(|recorded| recorded.path == path && installed);
+
+        let state = if let Some(pattern) = ignore.matching_pattern(Path::new(name)) {
+            State::Ignored(pattern.to_string())
+        } else if !installed {
+            State::Missing
+        } else if package.is_self_managed() {
+            // Custom commands and git clones decide themselves what they hold
+            State::UpToDate
+        } else {
+            match &recorded {
+                None => State::Outdated(Reason::Unrecorded),
+                Some(installed) if stat.as_ref().is_some_and(|s| s.hash != installed.hash) => {
+                    State::Modified
+                }
+                Some(installed) => outdated(
+                    package,
+                    installed.fields_hash.as_deref(),
+                    installed.source.as_deref(),
+                ),
+            }
+        };
+
+        statuses.push(Status {
+            name: name.to_string(),
+            installed,
+            path,
+            state,
+            version: versions.get(name).filter(|_| recorded.is_some()),
+            installed_at: recorded
+                .as_ref()
+                .and_then(|installed| installed.installed_at),
+            hash: recorded.map(|installed| installed.hash),
+        });
+    }
+
+    Ok(statuses)
+}
+
+/// Whether the config asks for something other than what was installed, as far as can be told
+/// without resolving releases
+fn outdated(package: &PackageConfig, fields_hash: Option<&str>, source: Option<&str>) -> State {
+    let (Some(fields_hash), Some(source)) = (fields_hash, source) else {
+        return State::Outdated(Reason::Unrecorded);
+    };
+    if fields_hash != lock::fields_hash(package) {
+        return State::Outdated(Reason::ConfigChanged);
+    }
+    match package.url().map(sops::redact) {
+        Some(url) if url != source => State::Outdated(Reason::NewSource {
+            installed: source.to_string(),
+            resolved: url,
+        }),
+        _ => State::UpToDate,
+    }
+}
+
+pub fn render(statuses: &[Status]) -> String {
+    let width = statuses.iter().map(|s| s.name.len()).max().unwrap_or(0);
+    let version = |status: &Status| match &status.version {
+        Some(inferred) => inferred.version.clone(),
+        None => "-".to_string(),
+    };
+    let version_width = statuses.iter().map(|s| version(s).len()).max().unwrap_or(1);
+
+    let mut rendered = String::new();
+    for status in statuses {
+        let hash = status
+            .hash
+            .as_deref()
+            .map(|hash| crate::digest::validate(hash).unwrap_or(hash))
+            .map(|hex| &hex[..hex.len().min(12)])
+            .unwrap_or("-");
+        rendered.push_str(&format!(
+            "{:width$}  {:version_width$}  {:12}  {}  {}\n",
+            status.name,
+            version(status),
+            hash,
+            status.path.display(),
+            status.state,
+        ));
+    }
+    let up_to_date = statuses
+        .iter()
+        .filter(|status| status.state == State::UpToDate)
+        .count();
+    rendered.push_str(&format!(
+        "{} of {} package(s) up to date\n",
+        up_to_date,
+        statuses.len()
+    ));
+
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{config, digest, manifest::Staged};
+
+    use super::*;
+
+    #[test]
+    fn test_status_of_each_package() {
+        let directory = tempfile::tempdir().unwrap();
+        let bin = directory.path().join("bin");
+        std::fs::create_dir(&bin).unwrap();
+        for name in ["rg", "fd", "bat", "eza", "tool.local"] {
+            std::fs::write(bin.join(name), name).unwrap();
+        }
+        std::fs::write(bin.join("bat"), "edited").unwrap();
+        let source = r#"
+            [workstation.install]
+            ignore = ["*.local"]
+
+            [linux_x86_64]
+            location = "/unused"
+            packages = [
+                { name = "rg", url = "https://example.com/rg-14" },
+                { name = "fd", url = "https://example.com/fd" },
+                { name = "bat", url = "https://example.com/bat" },
+                { name = "eza", url = "https://example.com/eza" },
+                { name = "new", url = "https://example.com/new" },
+                { name = "tool.local", url = "https://example.com/tool" },
+            ]
+            "#;
+        let target = crate::arch::Target::parse("linux_x86_64").unwrap();
+        let config = config::parse_config_for(source, &target).unwrap();
+
+        let manifest = Manifest::load(directory.path()).unwrap();
+        for package in &config.platform.packages {
+            let source = match package.name() {
+                "rg" => "https://example.com/rg-13",
+                _ => package.url().unwrap(),
+            };
+            if package.name() != "eza" {
+                manifest.stage(
+                    package.name(),
+                    Staged {
+                        hash: digest::sha256(package.name().as_bytes()),
+                        source: Some(source.to_string()),
+                        fields_hash: Some(lock::fields_hash(package)),
+                        path: None,
+                        location: None,
+                        files: vec![],
+                    },
+                );
+            }
+        }
+        let names = ["rg", "fd", "bat", "eza"].map(str::to_string);
+        manifest.commit(&names, &bin);
+
+        let statuses = status(
+            &config.platform.packages,
+            &manifest,
+            &Versions::load(directory.path()),
+            &mut StatCache::load(directory.path()),
+            &config.install.ignore_set().unwrap(),
+            |package| Ok(bin.join(package.name())),
+        )
+        .unwrap();
+
+        let states: Vec<_> = statuses
+            .iter()
+            .map(|status| (status.name.as_str(), status.state.clone()))
+            .collect();
+        assert_eq!(
+            states,
+            [
+                (
+                    "rg",
+                    State::Outdated(Reason::NewSource {
+                        installed: "https://example.com/rg-13".to_string(),
+                        resolved: "https://example.com/rg-14".to_string(),
+                    })
+                ),
+                ("fd", State::UpToDate),
+                ("bat", State::Modified),
+                ("eza", State::Outdated(Reason::Unrecorded)),
+                ("new", State::Missing),
+                ("tool.local", State::Ignored("*.local".to_string())),
+            ]
+        );
+        assert_eq!(statuses[1].hash, Some(digest::sha256(b"fd")));
+        assert!(statuses[1].installed_at.is_some());
+        assert_eq!(statuses[3].installed_at, None);
+        assert!(!statuses[4].installed);
+        let rendered = render(&statuses);
+        assert!(rendered.contains("  missing\n"));
+        assert!(rendered.ends_with("1 of 6 package(s) up to date\n"));
+    }
+}
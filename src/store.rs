@@ -0,0 +1,372 @@
+use std::{
+    fmt,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use eyre::Context;
+
+use crate::{digest, staged::Staged};
+
+/// Installed files kept once by content hash, as `<hex>/<name>`, with the install location
+/// holding symlinks into it. An entry is a hard link to the file staged for it when both are on
+/// one filesystem, so storing it copies nothing.
+///
+/// Entries are never changed once written, so an update is a new entry and a repointed link,
+/// and the previous entry stays until [`Store::collect_garbage`] finds nothing links to it.
+#[derive(Debug, Clone)]
+pub struct Store {
+    directory: PathBuf,
+}
+
+/// What [`Store::collect_garbage`] removed
+#[derive(Debug, Default, PartialEq)]
+pub struct Collected {
+    pub entries: usize,
+    pub bytes: u64,
+}
+
+/// Prefix of the files an entry is written under before it is complete
+const TEMPORARY_PREFIX: &str = ".tmp";
+
+/// What [`Store::verify`] found wrong
+#[derive(Debug, PartialEq)]
+pub enum Problem {
+    /// An installed link whose target is gone
+    Dangling { link: PathBuf, target: PathBuf },
+    /// A file of the store whose content no longer has the hash of its entry
+    Corrupt { path: PathBuf, hash: String },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Dangling { link, target } => write!(
+                f,
+                "{} links to {}, which does not exist",
+                link.display(),
+                target.display()
+            ),
+            Problem::Corrupt { path, hash } => {
+                write!(f, "{} changed, its content is {}", path.display(), hash)
+            }
+        }
+    }
+}
+
+/// What [`Store::verify`] checked and the problems it found
+#[derive(Debug, Default, PartialEq)]
+pub struct Verified {
+    pub links: usize,
+    pub files: usize,
+    pub problems: Vec<Problem>,
+}
+
+impl Store {
+    pub fn new(directory: &Path) -> Self {
+        Store {
+            directory: directory.to_path_buf(),
+        }
+    }
+
+    /// Store `data` as the file `name` with permissions `mode`, returning the path of the entry
+    pub fn put(&self, name: &str, data: &Staged, mode: u32) -> eyre::Result<PathBuf> {
+        let hash = data.hash();
+        let directory = self.directory.join(digest::validate(hash)?);
+        let path = directory.join(name);
+        let stored = std::fs::File::open(&path).and_then(digest::sha256_reader);
+        if stored.is_ok_and(|stored| stored == hash) {
+            // Only the permissions of an entry ever change, when a package asks for other ones
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode))?;
+            return Ok(path);
+        }
+
+        std::fs::create_dir_all(&directory)
+            .with_context(|| format!("Creating {}", directory.display()))?;
+        // Linked or written under a temporary name so a link never points at a truncated entry
+        let builder = tempfile::Builder::new().prefix(TEMPORARY_PREFIX).clone();
+        match builder.make_in(&directory, |temporary| {
+            std::fs::hard_link(data.path(), temporary)
+        }) {
+            Ok(file) => {
+                let entry = std::fs::File::open(file.path())?;
+                entry.set_permissions(std::fs::Permissions::from_mode(mode))?;
+                entry.sync_all()?;
+                file.persist(&path)
+                    .with_context(|| format!("Writing {}", path.display()))?;
+            }
+            // The store is on another filesystem than the install location
+            Err(_) => {
+                let mut file = builder.tempfile_in(&directory)?;
+                std::io::copy(&mut data.reader()?, &mut file)?;
+                file.as_file()
+                    .set_permissions(std::fs::Permissions::from_mode(mode))?;
+                file.as_file().sync_all()?;
+                file.persist(&path)
+                    .with_context(|| format!("Writing {}", path.display()))?;
+            }
+        }
+
+        Ok(path)
+    }
+
+    /// Check that each of `installed` that is a link points at a file, and that every file of
+    /// the store still hashes to the entry it is in
+    pub fn verify(&self, installed: &[PathBuf]) -> eyre::Result<Verified> {
+        let mut verified = Verified::default();
+        for link in installed {
+            let Ok(target) = std::fs::read_link(link) else {
+                continue;
+            };
+            verified.links += 1;
+            // Relative targets are relative to the directory of the link
+            let resolved = link.parent().unwrap_or(Path::new("/")).join(&target);
+            if !resolved.is_file() {
+                verified.problems.push(Problem::Dangling {
+                    link: link.clone(),
+                    target,
+                });
+            }
+        }
+
+        let entries = match std::fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(verified),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Reading {}", self.directory.display()))
+            }
+        };
+        for entry in entries {
+            let entry = entry?.path();
+            let name = entry.file_name().unwrap_or_default().to_string_lossy();
+            let Ok(expected) = digest::from_hex(&name) else {
+                continue;
+            };
+            for file in std::fs::read_dir(&entry)? {
+                let file = file?.path();
+                let name = file.file_name().unwrap_or_default().to_string_lossy();
+                if name.starts_with(TEMPORARY_PREFIX) {
+                    continue;
+                }
+                verified.files += 1;
+                let hash = std::fs::File::open(&file)
+                    .and_then(digest::sha256_reader)
+                    .with_context(|| format!("Reading {}", file.display()))?;
+                if hash != expected {
+                    verified
+                        .problems
+                        .push(Problem::Corrupt { path: file, hash });
+                }
+            }
+        }
+        Ok(verified)
+    }
+
+    /// Remove the entry `target` belongs to, unless one of `links` still points into it.
+    ///
+    /// Returns whether it was removed, a `target` outside the store never is.
+    pub fn release(&self, target: &Path, links: &[PathBuf]) -> eyre::Result<bool> {
+        let Some(hash) = target
+            .strip_prefix(&self.directory)
+            .ok()
+            .and_then(|relative| relative.iter().next())
+        else {
+            return Ok(false);
+        };
+        let entry = self.directory.join(hash);
+        if links.iter().any(|link| link.starts_with(&entry)) || !entry.is_dir() {
+            return Ok(false);
+        }
+        std::fs::remove_dir_all(&entry).with_context(|| format!("Removing {}", entry.display()))?;
+        Ok(true)
+    }
+
+    /// Remove every entry that none of `links` points into, links outside the store are ignored
+    pub fn collect_garbage(&self, links: &[PathBuf]) -> eyre::Result<Collected> {
+        let mut collected = Collected::default();
+        let entries = match std::fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(collected),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Reading {}", self.directory.display()))
+            }
+        };
+        let referenced: Vec<&Path> = links
+            .iter()
+            .filter_map(|link| link.strip_prefix(&self.directory).ok())
+            .filter_map(|relative| relative.iter().next().map(Path::new))
+            .collect();
+
+        for entry in entries {
+            let entry = entry?;
+            if referenced.contains(&Path::new(&entry.file_name())) {
+                continue;
+            }
+            let bytes = size(&entry.path())?;
+            std::fs::remove_dir_all(entry.path())
+                .with_context(|| format!("Removing {}", entry.path().display()))?;
+            collected.entries += 1;
+            collected.bytes += bytes;
+        }
+
+        Ok(collected)
+    }
+}
+
+/// Point `destination` at the store `entry`, replacing whatever is there in one rename
+pub fn link(entry: &Path, destination: &Path) -> eyre::Result<()> {
+    let directory = destination.parent().expect("install path has a parent");
+    let name = destination.file_name().expect("install path has a name");
+    std::fs::create_dir_all(directory)
+        .with_context(|| format!("Creating {}", directory.display()))?;
+
+    let temporary = directory.join(format!(".{}.workstation-link", name.to_string_lossy()));
+    let _ = std::fs::remove_file(&temporary);
+    std::os::unix::fs::symlink(entry, &temporary)
+        .with_context(|| format!("Linking {}", temporary.display()))?;
+    std::fs::rename(&temporary, destination)
+        .with_context(|| format!("Moving the link to {} into place", destination.display()))
+}
+
+/// Total size of the files in `directory`
+fn size(directory: &Path) -> eyre::Result<u64> {
+    let mut total = 0;
+    for entry in std::fs::read_dir(directory)? {
+        total += entry?.metadata()?.len();
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put(store: &Store, name: &str, data: &[u8]) -> PathBuf {
+        // Staged in the install location, as setup does
+        let staged = Staged::from_bytes(&store.directory.with_file_name("bin").join(name), data);
+        store.put(name, &staged.unwrap(), 0o755).unwrap()
+    }
+
+    #[test]
+    fn test_updates_repoint_the_link() {
+        let directory = tempfile::tempdir().unwrap();
+        let store = Store::new(&directory.path().join("store"));
+        let bin = directory.path().join("bin");
+
+        let old = put(&store, "rg", b"rg 13");
+        assert_eq!(put(&store, "rg", b"rg 13"), old);
+        link(&old, &bin.join("rg")).unwrap();
+        let new = put(&store, "rg", b"rg 14");
+        link(&new, &bin.join("rg")).unwrap();
+
+        assert_eq!(std::fs::read(bin.join("rg")).unwrap(), b"rg 14");
+        assert_eq!(std::fs::read_link(bin.join("rg")).unwrap(), new);
+        assert_eq!(std::fs::read(&old).unwrap(), b"rg 13");
+        let mode = std::fs::metadata(&new).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o755);
+        // Only the link is left in the install location
+        assert_eq!(std::fs::read_dir(&bin).unwrap().count(), 1);
+
+        // Rolling back is pointing the link at the old entry again
+        link(&old, &bin.join("rg")).unwrap();
+        assert_eq!(std::fs::read(bin.join("rg")).unwrap(), b"rg 13");
+
+        // Uninstalling only removes entries nothing else links to
+        let links = [std::fs::read_link(bin.join("rg")).unwrap()];
+        assert!(store.release(&new, &links).unwrap());
+        assert!(!new.exists());
+        assert!(!store.release(&old, &links).unwrap());
+        assert!(old.exists());
+        assert!(!store.release(Path::new("/usr/bin/rg"), &[]).unwrap());
+    }
+
+    #[test]
+    fn test_entries_are_hard_links_to_the_staged_file() {
+        use std::os::unix::fs::MetadataExt;
+
+        let directory = tempfile::tempdir().unwrap();
+        let store = Store::new(&directory.path().join("store"));
+        let staged =
+            Staged::from_bytes(&directory.path().join("bin").join("rg"), b"rg 14").unwrap();
+
+        let entry = store.put("rg", &staged, 0o755).unwrap();
+
+        let stored = std::fs::metadata(&entry).unwrap();
+        assert_eq!(
+            stored.ino(),
+            std::fs::metadata(staged.path()).unwrap().ino()
+        );
+        assert_eq!(stored.nlink(), 2);
+        drop(staged);
+        assert_eq!(std::fs::read(&entry).unwrap(), b"rg 14");
+        assert_eq!(std::fs::metadata(&entry).unwrap().nlink(), 1);
+    }
+
+    #[test]
+    fn test_verify_finds_dangling_links_and_changed_entries() {
+        let directory = tempfile::tempdir().unwrap();
+        let store = Store::new(&directory.path().join("store"));
+        let bin = directory.path().join("bin");
+        let rg = put(&store, "rg", b"rg 14");
+        let fd = put(&store, "fd", b"fd 10");
+        link(&rg, &bin.join("rg")).unwrap();
+        link(&fd, &bin.join("fd")).unwrap();
+        std::fs::write(bin.join("bat"), b"bat, copied").unwrap();
+        let installed = [bin.join("rg"), bin.join("fd"), bin.join("bat")];
+
+        let verified = store.verify(&installed).unwrap();
+        assert_eq!((verified.links, verified.files), (2, 2));
+        assert!(verified.problems.is_empty());
+
+        std::fs::write(&rg, b"rg 15").unwrap();
+        std::fs::remove_dir_all(fd.parent().unwrap()).unwrap();
+        let verified = store.verify(&installed).unwrap();
+        assert_eq!(
+            verified.problems,
+            [
+                Problem::Dangling {
+                    link: bin.join("fd"),
+                    target: fd,
+                },
+                Problem::Corrupt {
+                    path: rg,
+                    hash: digest::sha256(b"rg 15"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_garbage_is_what_no_link_points_to() {
+        let directory = tempfile::tempdir().unwrap();
+        let store = Store::new(&directory.path().join("store"));
+        let kept = put(&store, "rg", b"rg 14");
+        put(&store, "rg", b"rg 13");
+        put(&store, "fd", b"fd 10");
+
+        let collected = store
+            .collect_garbage(&[kept.clone(), PathBuf::from("/usr/bin/fd")])
+            .unwrap();
+
+        assert_eq!(
+            collected,
+            Collected {
+                entries: 2,
+                bytes: 10
+            }
+        );
+        assert!(kept.exists());
+        assert_eq!(
+            std::fs::read_dir(directory.path().join("store"))
+                .unwrap()
+                .count(),
+            1
+        );
+        assert_eq!(
+            Store::new(&directory.path().join("missing"))
+                .collect_garbage(&[])
+                .unwrap(),
+            Collected::default()
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use std::{
+    fmt,
+    process::{Command, Stdio},
+    sync::OnceLock,
+};
+
+use eyre::Context;
+
+use crate::{config::PackageConfig, conflicts};
+
+/// A package manager of Linux distributions, or Homebrew with its formulas and casks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Manager {
+    Apt,
+    Dnf,
+    Pacman,
+    Brew,
+    /// Homebrew casks, macOS applications
+    Cask,
+}
+
+impl Manager {
+    pub const ALL: &'static [Manager] = &[
+        Manager::Apt,
+        Manager::Dnf,
+        Manager::Pacman,
+        Manager::Brew,
+        Manager::Cask,
+    ];
+
+    /// The key of system packages naming the package for this manager
+    pub fn key(&self) -> &'static str {
+        match self {
+            Manager::Apt => "apt",
+            Manager::Dnf => "dnf",
+            Manager::Pacman => "pacman",
+            Manager::Brew => "brew",
+            Manager::Cask => "cask",
+        }
+    }
+
+    fn program(&self) -> &'static str {
+        match self {
+            Manager::Apt => "apt-get",
+            Manager::Dnf => "dnf",
+            Manager::Pacman => "pacman",
+            Manager::Brew | Manager::Cask => "brew",
+        }
+    }
+
+    /// The package managers of this machine, those of [`Manager::ALL`] on PATH, in that order.
+    /// Casks only exist on macOS.
+    pub fn available() -> &'static [Manager] {
+        static AVAILABLE: OnceLock<Vec<Manager>> = OnceLock::new();
+        AVAILABLE.get_or_init(|| {
+            let directories = conflicts::path_directories();
+            Manager::ALL
+                .iter()
+                .copied()
+                .filter(|manager| *manager != Manager::Cask || std::env::consts::OS == "macos")
+                .filter(|manager| {
+                    directories
+                        .iter()
+                        .any(|directory| directory.join(manager.program()).is_file())
+                })
+                .collect()
+        })
+    }
+
+    /// The name `package` has for this manager, if it sets one
+    pub fn package_name<'a>(&self, package: &'a PackageConfig) -> Option<&'a str> {
+        let PackageConfig::System { names, .. } = package else {
+            return None;
+        };
+        match self {
+            Manager::Apt => names.apt.as_deref(),
+            Manager::Dnf => names.dnf.as_deref(),
+            Manager::Pacman => names.pacman.as_deref(),
+            Manager::Brew => names.brew.as_deref(),
+            Manager::Cask => names.cask.as_deref(),
+        }
+    }
+
+    /// Whether the package manager has `name` installed
+    pub fn is_installed(&self, name: &str) -> eyre::Result<bool> {
+        let args: &[&str] = match self {
+            Manager::Apt => &["dpkg-query", "-W", "-f=${db:Status-Abbrev}", name],
+            Manager::Dnf => &["rpm", "-q", "--quiet", name],
+            Manager::Pacman => &["pacman", "-Q", name],
+            Manager::Brew => &["brew", "list", "--formula", name],
+            Manager::Cask => &["brew", "list", "--cask", name],
+        };
+        let output = Command::new(args[0])
+            .args(&args[1..])
+            .stdin(Stdio::null())
+            .output()
+            .with_context(|| format!("Running {}", args[0]))?;
+        Ok(match self {
+            // Removed packages keep their status, only `ii` is installed
+            Manager::Apt => output.status.success() && output.stdout.starts_with(b"ii"),
+            _ => output.status.success(),
+        })
+    }
+
+    /// The command line installing `names` without asking for confirmation. Homebrew refuses to
+    /// run as root, the others run with sudo.
+    fn install_args(&self, names: &[&str]) -> Vec<String> {
+        let args: &[&str] = match self {
+            Manager::Apt => &["apt-get", "install", "-y"],
+            Manager::Dnf => &["dnf", "install", "-y"],
+            Manager::Pacman => &["pacman", "-S", "--needed", "--noconfirm"],
+            Manager::Brew => &["brew", "install"],
+            Manager::Cask => &["brew", "install", "--cask"],
+        };
+        let mut args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        if !matches!(self, Manager::Brew | Manager::Cask) && !is_root() {
+            args.insert(0, "sudo".to_string());
+        }
+        args.extend(names.iter().map(|name| name.to_string()));
+        args
+    }
+
+    /// Install `names` in one run. sudo may ask for a password on the terminal, so the command
+    /// inherits it and its output is shown.
+    pub fn install(&self, names: &[&str]) -> eyre::Result<()> {
+        let args = self.install_args(names);
+        let status = Command::new(&args[0])
+            .args(&args[1..])
+            .status()
+            .with_context(|| format!("Running {}", args[0]))?;
+        if !status.success() {
+            eyre::bail!("`{}` failed with {}", args.join(" "), status);
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Manager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.key())
+    }
+}
+
+/// Whether this process runs as root, which needs no sudo
+pub fn is_root() -> bool {
+    std::fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            let uid = status.lines().find_map(|line| line.strip_prefix("Uid:"))?;
+            uid.split_whitespace().nth(1).map(|euid| euid == "0")
+        })
+        .unwrap_or_else(|| std::env::var("USER").is_ok_and(|user| user == "root"))
+}
+
+/// The first package manager of this machine `package` names a package for, with that name
+pub fn resolve(package: &PackageConfig) -> Option<(Manager, &str)> {
+    Manager::available()
+        .iter()
+        .find_map(|manager| Some((*manager, manager.package_name(package)?)))
+}
+
+/// Whether the system package `package` is installed, never when this machine has none of the
+/// package managers it names a package for
+pub fn is_installed(package: &PackageConfig) -> eyre::Result<bool> {
+    match resolve(package) {
+        Some((manager, name)) => manager.is_installed(name),
+        None => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_installs_are_batched_and_unattended() {
+        let mut args = Manager::Pacman.install_args(&["base-devel", "docker"]);
+        if args[0] == "sudo" {
+            args.remove(0);
+        }
+        assert_eq!(
+            args,
+            [
+                "pacman",
+                "-S",
+                "--needed",
+                "--noconfirm",
+                "base-devel",
+                "docker"
+            ]
+        );
+
+        let package: PackageConfig =
+            toml::from_str("name = \"cc\"\napt = \"build-essential\"\npacman = \"base-devel\"\n")
+                .unwrap();
+        assert_eq!(Manager::Apt.package_name(&package), Some("build-essential"));
+        assert_eq!(Manager::Dnf.package_name(&package), None);
+        assert!(toml::from_str::<PackageConfig>("name = \"cc\"\n").is_err());
+
+        let iterm: PackageConfig =
+            toml::from_str("name = \"iterm2\"\ncask = \"iterm2\"\n").unwrap();
+        assert_eq!(Manager::Cask.package_name(&iterm), Some("iterm2"));
+        assert_eq!(
+            Manager::Cask.install_args(&["iterm2"]),
+            ["brew", "install", "--cask", "iterm2"]
+        );
+    }
+}
@@ -0,0 +1,198 @@
+//! A minimal HTTP/1.1 server for exercising the download code in tests
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+};
+
+#[derive(Clone)]
+pub struct Route {
+    pub status: u16,
+    pub body: Vec<u8>,
+    /// Answer `Range` requests with 206 partial content
+    pub ranges: bool,
+    pub headers: Vec<(String, String)>,
+    /// Close the connection after this many bytes of the body, as a dropped connection would
+    pub truncate: Option<usize>,
+}
+
+impl Route {
+    pub fn ok(body: impl Into<Vec<u8>>) -> Self {
+        Route {
+            status: 200,
+            body: body.into(),
+            ranges: false,
+            headers: vec![],
+            truncate: None,
+        }
+    }
+
+    pub fn status(status: u16) -> Self {
+        Route {
+            status,
+            body: vec![],
+            ranges: false,
+            headers: vec![],
+            truncate: None,
+        }
+    }
+
+    pub fn with_ranges(mut self) -> Self {
+        self.ranges = true;
+        self
+    }
+
+    pub fn truncated(mut self, after: usize) -> Self {
+        self.truncate = Some(after);
+        self
+    }
+}
+
+/// A request as seen by the server
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: HashMap<String, String>,
+}
+
+pub struct TestServer {
+    address: String,
+    routes: Arc<Mutex<HashMap<String, Vec<Route>>>>,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl TestServer {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let routes: Arc<Mutex<HashMap<String, Vec<Route>>>> = Default::default();
+        let requests: Arc<Mutex<Vec<Request>>> = Default::default();
+
+        let server_routes = routes.clone();
+        let server_requests = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                let routes = server_routes.clone();
+                let requests = server_requests.clone();
+                std::thread::spawn(move || {
+                    let _ = handle(stream, &routes, &requests);
+                });
+            }
+        });
+
+        TestServer {
+            address,
+            routes,
+            requests,
+        }
+    }
+
+    /// Serve `route` at `path`. Registering a path several times answers the routes in order,
+    /// repeating the last one.
+    pub fn route(&self, path: &str, route: Route) {
+        self.routes
+            .lock()
+            .unwrap()
+            .entry(path.to_string())
+            .or_default()
+            .push(route);
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.address, path)
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+fn handle(
+    stream: TcpStream,
+    routes: &Mutex<HashMap<String, Vec<Route>>>,
+    requests: &Mutex<Vec<Request>>,
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+
+    let route = {
+        let mut routes = routes.lock().unwrap();
+        match routes.get_mut(&path) {
+            Some(queue) if queue.len() > 1 => Some(queue.remove(0)),
+            Some(queue) => queue.first().cloned(),
+            None => None,
+        }
+    };
+    let range = headers.get("range").cloned();
+    requests.lock().unwrap().push(Request {
+        method: method.clone(),
+        path,
+        headers,
+    });
+
+    let route = route.unwrap_or_else(|| Route::status(404));
+    let mut status = route.status;
+    let mut body = route.body.clone();
+    let mut extra = route.headers.clone();
+
+    if let (true, Some(range), 200) = (route.ranges, range, status) {
+        let (start, end) = parse_range(&range, body.len() as u64);
+        extra.push((
+            "Content-Range".to_string(),
+            format!("bytes {}-{}/{}", start, end, body.len()),
+        ));
+        body = body[start as usize..=end as usize].to_vec();
+        status = 206;
+    }
+    if route.ranges {
+        extra.push(("Accept-Ranges".to_string(), "bytes".to_string()));
+    }
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {} Status\r\nContent-Length: {}\r\nConnection: close\r\n",
+        status,
+        body.len()
+    )?;
+    for (name, value) in extra {
+        write!(stream, "{}: {}\r\n", name, value)?;
+    }
+    write!(stream, "\r\n")?;
+    if method != "HEAD" {
+        let end = route.truncate.unwrap_or(body.len()).min(body.len());
+        stream.write_all(&body[..end])?;
+    }
+    stream.flush()
+}
+
+fn parse_range(range: &str, length: u64) -> (u64, u64) {
+    let range = range.trim_start_matches("bytes=");
+    let (start, end) = range.split_once('-').unwrap_or((range, ""));
+    let start: u64 = start.parse().unwrap_or(0);
+    let end: u64 = end.parse().unwrap_or(length - 1).min(length - 1);
+    (start, end)
+}
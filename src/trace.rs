@@ -0,0 +1,315 @@
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+use eyre::Context;
+use serde::Serialize;
+
+/// A finished span
+#[derive(Debug, Clone)]
+struct Span {
+    name: String,
+    category: &'static str,
+    package: String,
+    /// Spans of one package share a track, so viewers nest its phases under it
+    track: u64,
+    start: Duration,
+    end: Duration,
+    bytes: Option<u64>,
+    outcome: &'static str,
+}
+
+/// Records timing spans of a setup run, see `setup --trace-out`
+pub struct Tracer {
+    started: Instant,
+    spans: Mutex<Vec<Span>>,
+    tracks: AtomicU64,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl Tracer {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Tracer {
+            started: Instant::now(),
+            spans: Mutex::new(vec![]),
+            tracks: AtomicU64::new(1),
+        })
+    }
+
+    /// The spans as Chrome trace-event JSON, loadable in Perfetto and about:tracing
+    pub fn render(&self) -> String {
+        let mut spans = lock(&self.spans).clone();
+        spans.sort_by_key(|span| (span.start, std::cmp::Reverse(span.end)));
+        let pid = std::process::id();
+
+        let mut events: Vec<_> = spans
+            .iter()
+            .filter(|span| span.category == PACKAGE)
+            .map(|span| TraceEvent {
+                name: "thread_name".to_string(),
+                cat: None,
+                ph: "M",
+                ts: 0,
+                dur: None,
+                pid,
+                tid: span.track,
+                args: Args {
+                    name: Some(span.package.clone()),
+                    ..Default::default()
+                },
+            })
+            .collect();
+        events.extend(spans.into_iter().map(|span| TraceEvent {
+            name: span.name,
+            cat: Some(span.category),
+            ph: "X",
+            ts: span.start.as_micros() as u64,
+            dur: Some((span.end.as_micros() - span.start.as_micros()) as u64),
+            pid,
+            tid: span.track,
+            args: Args {
+                package: Some(span.package),
+                bytes: span.bytes,
+                outcome: Some(span.outcome),
+                ..Default::default()
+            },
+        }));
+
+        serde_json::to_string(&TraceFile {
+            trace_events: events,
+            display_time_unit: "ms",
+        })
+        .expect("trace serializes")
+    }
+
+    pub fn write(&self, path: &Path) -> eyre::Result<()> {
+        std::fs::write(path, self.render() + "\n")
+            .with_context(|| format!("Writing the trace to {}", path.display()))
+    }
+}
+
+const PACKAGE: &str = "package";
+const PHASE: &str = "phase";
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TraceFile {
+    trace_events: Vec<TraceEvent>,
+    display_time_unit: &'static str,
+}
+
+#[derive(Serialize)]
+struct TraceEvent {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cat: Option<&'static str>,
+    ph: &'static str,
+    /// Microseconds since the run started
+    ts: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dur: Option<u64>,
+    pid: u32,
+    tid: u64,
+    args: Args,
+}
+
+#[derive(Serialize, Default)]
+struct Args {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    package: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    outcome: Option<&'static str>,
+}
+
+/// The spans of a single package, recording nothing when tracing is off
+#[derive(Clone)]
+pub struct PackageTrace {
+    tracer: Option<Arc<Tracer>>,
+    package: String,
+    track: u64,
+}
+
+impl PackageTrace {
+    pub fn new(tracer: Option<Arc<Tracer>>, package: &str) -> Self {
+        let track = tracer
+            .as_ref()
+            .map(|tracer| tracer.tracks.fetch_add(1, Ordering::Relaxed))
+            .unwrap_or_default();
+        PackageTrace {
+            tracer,
+            package: package.to_string(),
+            track,
+        }
+    }
+
+    /// The span covering the whole package, every phase span falls inside it
+    pub fn package(&self) -> SpanGuard<'_> {
+        self.start(self.package.clone(), PACKAGE)
+    }
+
+    /// A span for one phase of the install, such as `download` or `extract`
+    pub fn phase(&self, name: &str) -> SpanGuard<'_> {
+        self.start(name.to_string(), PHASE)
+    }
+
+    fn start(&self, name: String, category: &'static str) -> SpanGuard<'_> {
+        SpanGuard {
+            trace: self,
+            name,
+            category,
+            start: self
+                .tracer
+                .as_ref()
+                .map(|tracer| tracer.started.elapsed())
+                .unwrap_or_default(),
+            bytes: None,
+            outcome: None,
+        }
+    }
+}
+
+/// A running span, recorded when dropped.
+///
+/// A span dropped without [`SpanGuard::done`] is recorded as failed, which is what happens when
+/// `?` leaves the phase early.
+pub struct SpanGuard<'a> {
+    trace: &'a PackageTrace,
+    name: String,
+    category: &'static str,
+    start: Duration,
+    bytes: Option<u64>,
+    outcome: Option<&'static str>,
+}
+
+impl SpanGuard<'_> {
+    /// Finish the span successfully, with the number of bytes the phase produced
+    pub fn done(mut self, bytes: Option<usize>) {
+        self.bytes = bytes.map(|bytes| bytes as u64);
+        self.outcome = Some("ok");
+    }
+
+    /// Finish the span with an outcome other than success or failure, such as `skipped`
+    pub fn finish(mut self, outcome: &'static str) {
+        self.outcome = Some(outcome);
+    }
+}
+
+impl Drop for SpanGuard<'_> {
+    fn drop(&mut self) {
+        let Some(tracer) = &self.trace.tracer else {
+            return;
+        };
+        lock(&tracer.spans).push(Span {
+            name: std::mem::take(&mut self.name),
+            category: self.category,
+            package: self.trace.package.clone(),
+            track: self.trace.track,
+            start: self.start,
+            end: tracer.started.elapsed(),
+            bytes: self.bytes,
+            outcome: self.outcome.unwrap_or("failed"),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn install(tracer: &Arc<Tracer>, package: &str, fail_extract: bool) -> eyre::Result<()> {
+        let trace = PackageTrace::new(Some(tracer.clone()), package);
+        let span = trace.package();
+        let run = || -> eyre::Result<()> {
+            let download = trace.phase("download");
+            std::thread::sleep(Duration::from_millis(2));
+            download.done(Some(100));
+
+            let extract = trace.phase("extract");
+            if fail_extract {
+                eyre::bail!("Entry not found");
+            }
+            std::thread::sleep(Duration::from_millis(2));
+            extract.done(Some(50));
+            Ok(())
+        };
+        let result = run();
+        match &result {
+            Ok(()) => span.done(None),
+            Err(_) => span.finish("failed"),
+        }
+        result
+    }
+
+    #[test]
+    fn test_trace_parses_and_phases_nest() {
+        let tracer = Tracer::new();
+        let handles: Vec<_> = [("rg", false), ("fd", true)]
+            .into_iter()
+            .map(|(package, fail)| {
+                let tracer = tracer.clone();
+                std::thread::spawn(move || install(&tracer, package, fail))
+            })
+            .collect();
+        for handle in handles {
+            let _ = handle.join().unwrap();
+        }
+
+        let trace: serde_json::Value = serde_json::from_str(&tracer.render()).unwrap();
+        let events = trace["traceEvents"].as_array().unwrap();
+        let spans: Vec<_> = events.iter().filter(|e| e["ph"] == "X").collect();
+        assert_eq!(spans.len(), 6);
+
+        let packages: Vec<_> = spans.iter().filter(|e| e["cat"] == PACKAGE).collect();
+        assert_eq!(packages.len(), 2);
+        for phase in spans.iter().filter(|e| e["cat"] == PHASE) {
+            let parent = packages
+                .iter()
+                .find(|p| p["tid"] == phase["tid"])
+                .expect("phase has a package span");
+            let (start, end) = (
+                phase["ts"].as_u64().unwrap(),
+                phase["dur"].as_u64().unwrap(),
+            );
+            let (parent_start, parent_end) = (
+                parent["ts"].as_u64().unwrap(),
+                parent["dur"].as_u64().unwrap(),
+            );
+            assert_eq!(parent["args"]["package"], phase["args"]["package"]);
+            assert!(start >= parent_start);
+            assert!(start + end <= parent_start + parent_end);
+        }
+
+        let outcome = |package: &str, name: &str| {
+            spans
+                .iter()
+                .find(|e| e["args"]["package"] == package && e["name"] == name)
+                .map(|e| e["args"]["outcome"].as_str().unwrap().to_string())
+        };
+        assert_eq!(outcome("fd", "extract").as_deref(), Some("failed"));
+        assert_eq!(outcome("rg", "download").as_deref(), Some("ok"));
+        assert!(events
+            .iter()
+            .any(|e| e["ph"] == "M" && e["args"]["name"] == "rg"));
+    }
+
+    #[test]
+    fn test_disabled_trace_records_nothing() {
+        let trace = PackageTrace::new(None, "rg");
+        trace.phase("download").done(Some(1));
+        trace.package().done(None);
+    }
+}
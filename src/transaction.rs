@@ -0,0 +1,168 @@
+use std::path::{Path, PathBuf};
+
+use eyre::Context;
+
+/// Installs staged into a temporary directory next to the install location, swapped into place
+/// only once every package is ready.
+///
+/// The staging directory lives inside the install location so that every swap is a rename on
+/// the same filesystem.
+pub struct Transaction {
+    staging: tempfile::TempDir,
+    location: PathBuf,
+}
+
+/// A swap failed, everything swapped before it was put back
+#[derive(Debug)]
+pub struct CommitError {
+    pub name: String,
+    pub error: eyre::Report,
+}
+
+impl Transaction {
+    /// `location` must already be expanded
+    pub fn new(location: &Path) -> eyre::Result<Self> {
+        std::fs::create_dir_all(location)
+            .with_context(|| format!("Creating {}", location.display()))?;
+        let staging = tempfile::Builder::new()
+            .prefix(".workstation-staging-")
+            .tempdir_in(location)
+            .with_context(|| format!("Creating a staging directory in {}", location.display()))?;
+        std::fs::create_dir(staging.path().join(BACKUP_DIR))?;
+
+        Ok(Transaction {
+            staging,
+            location: location.to_path_buf(),
+        })
+    }
+
+    /// Where packages are installed before the swap
+    pub fn staging_dir(&self) -> &Path {
+        self.staging.path()
+    }
+
+    /// Move the staged `names` into the install location.
+    ///
+    /// Existing files are backed up first; when any swap fails, every file swapped so far is
+    /// restored from its backup (or removed if it did not exist before).
+    pub fn commit(self, names: &[String]) -> Result<(), CommitError> {
+        let backups = self.staging.path().join(BACKUP_DIR);
+        let mut swapped: Vec<(&str, bool)> = vec![];
+
+        for name in names {
+            match self.swap(name, &backups) {
+                Ok(had_previous) => swapped.push((name, had_previous)),
+                Err(error) => {
+                    let error = match self.roll_back(&swapped, &backups) {
+                        Ok(()) => error,
+                        Err(rollback) => error.wrap_err(format!(
+                            "Rolling back failed as well, check {}: {:?}",
+                            self.location.display(),
+                            rollback
+                        )),
+                    };
+                    // Keep the backups around if they could not all be restored
+                    if self
+                        .staging
+                        .path()
+                        .join(BACKUP_DIR)
+                        .read_dir()
+                        .is_ok_and(|mut d| d.next().is_some())
+                    {
+                        let _ = self.staging.into_path();
+                    }
+                    return Err(CommitError {
+                        name: name.clone(),
+                        error,
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether a previous file was backed up
+    fn swap(&self, name: &str, backups: &Path) -> eyre::Result<bool> {
+        let destination = self.location.join(name);
+        // A link into the store is replaced like a file, even if its entry is gone
+        let had_previous = destination.symlink_metadata().is_ok();
+        if had_previous {
+            std::fs::rename(&destination, backups.join(name))
+                .with_context(|| format!("Backing up {}", destination.display()))?;
+        }
+
+        if let Err(e) = std::fs::rename(self.staging.path().join(name), &destination) {
+            if had_previous {
+                std::fs::rename(backups.join(name), &destination)
+                    .with_context(|| format!("Restoring {}", destination.display()))?;
+            }
+            return Err(e).with_context(|| format!("Moving {} into place", name));
+        }
+
+        Ok(had_previous)
+    }
+
+    fn roll_back(&self, swapped: &[(&str, bool)], backups: &Path) -> eyre::Result<()> {
+        for (name, had_previous) in swapped.iter().rev() {
+            let destination = self.location.join(name);
+            if *had_previous {
+                std::fs::rename(backups.join(name), &destination)
+                    .with_context(|| format!("Restoring {}", destination.display()))?;
+            } else {
+                std::fs::remove_file(&destination)
+                    .with_context(|| format!("Removing {}", destination.display()))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+const BACKUP_DIR: &str = "backup";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(path: PathBuf) -> String {
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn test_commit_swaps_everything_into_place() {
+        let location = tempfile::tempdir().unwrap();
+        std::fs::write(location.path().join("rg"), "old rg").unwrap();
+
+        let transaction = Transaction::new(location.path()).unwrap();
+        std::fs::write(transaction.staging_dir().join("rg"), "new rg").unwrap();
+        std::fs::write(transaction.staging_dir().join("fd"), "new fd").unwrap();
+        transaction
+            .commit(&["rg".to_string(), "fd".to_string()])
+            .unwrap();
+
+        assert_eq!(read(location.path().join("rg")), "new rg");
+        assert_eq!(read(location.path().join("fd")), "new fd");
+        // Only the installed files are left behind
+        assert_eq!(std::fs::read_dir(location.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_failed_swap_rolls_back_earlier_swaps() {
+        let location = tempfile::tempdir().unwrap();
+        std::fs::write(location.path().join("rg"), "old rg").unwrap();
+
+        let transaction = Transaction::new(location.path()).unwrap();
+        std::fs::write(transaction.staging_dir().join("rg"), "new rg").unwrap();
+        std::fs::write(transaction.staging_dir().join("fd"), "new fd").unwrap();
+        // Never staged, so its swap fails
+        let error = transaction
+            .commit(&["rg".to_string(), "fd".to_string(), "fzf".to_string()])
+            .unwrap_err();
+
+        assert_eq!(error.name, "fzf");
+        assert_eq!(read(location.path().join("rg")), "old rg");
+        assert!(!location.path().join("fd").exists());
+        assert!(!location.path().join("fzf").exists());
+    }
+}
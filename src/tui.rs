@@ -0,0 +1,448 @@
+use std::{
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use indicatif::{HumanBytes, HumanDuration, ProgressBar};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    text::Line,
+    widgets::{Block, Borders, Cell, Paragraph, Row, Table, TableState, Wrap},
+    Frame,
+};
+
+use crate::budget::Budget;
+
+/// How often the dashboard redraws and polls for keys
+const TICK: Duration = Duration::from_millis(100);
+
+/// Lines of the focused package log shown under the table
+const LOG_LINES: u16 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Waiting,
+    Running,
+    Installed,
+    Failed,
+}
+
+impl Status {
+    fn label(&self) -> &'static str {
+        match self {
+            Status::Waiting => "waiting",
+            Status::Running => "running",
+            Status::Installed => "installed",
+            Status::Failed => "failed",
+        }
+    }
+
+    fn style(&self) -> Style {
+        match self {
+            Status::Waiting => Style::default().fg(Color::DarkGray),
+            Status::Running => Style::default().fg(Color::Cyan),
+            Status::Installed => Style::default().fg(Color::Green),
+            Status::Failed => Style::default().fg(Color::Red),
+        }
+    }
+}
+
+struct Package {
+    name: String,
+    /// A hidden bar, the workers report to it exactly like in the plain output
+    progress: ProgressBar,
+    status: Status,
+    log: Vec<String>,
+}
+
+struct State {
+    packages: Vec<Package>,
+    paused: bool,
+    /// Shown in the summary line, see [`Dashboard::track_memory`]
+    budget: Option<Arc<Budget>>,
+}
+
+/// Live state of a `setup --tui` run, shared between the install workers and the dashboard
+pub struct Dashboard {
+    state: Mutex<State>,
+    resumed: Condvar,
+}
+
+impl Dashboard {
+    pub fn new() -> Self {
+        Dashboard {
+            state: Mutex::new(State {
+                packages: vec![],
+                paused: false,
+                budget: None,
+            }),
+            resumed: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Show how much of the memory budget the workers hold
+    pub fn track_memory(&self, budget: Arc<Budget>) {
+        self.lock().budget = Some(budget);
+    }
+
+    /// Add a package row, returning the progress bar its worker reports to
+    pub fn add(&self, name: &str) -> ProgressBar {
+        let progress = ProgressBar::hidden();
+        self.lock().packages.push(Package {
+            name: name.to_string(),
+            progress: progress.clone(),
+            status: Status::Waiting,
+            log: vec![],
+        });
+        progress
+    }
+
+    /// Called by a worker before it starts, blocks while dispatch is paused
+    pub fn start(&self, name: &str) {
+        let mut state = self.lock();
+        while state.paused {
+            state = self
+                .resumed
+                .wait(state)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        if let Some(package) = state.packages.iter_mut().find(|p| p.name == name) {
+            package.status = Status::Running;
+            package.log.push("Started".to_string());
+        }
+    }
+
+    /// Called by a worker once it is done, with the rendered error if it failed
+    pub fn finish(&self, name: &str, error: Option<String>) {
+        let mut state = self.lock();
+        if let Some(package) = state.packages.iter_mut().find(|p| p.name == name) {
+            package.status = match error {
+                Some(_) => Status::Failed,
+                None => Status::Installed,
+            };
+            package.log.extend(error);
+        }
+    }
+
+    /// Prepare a failed package to run again, returning its progress bar
+    fn retry(&self, name: &str) -> Option<ProgressBar> {
+        let mut state = self.lock();
+        let package = state
+            .packages
+            .iter_mut()
+            .find(|p| p.name == name && p.status == Status::Failed)?;
+        package.status = Status::Waiting;
+        package.log.push("Retrying".to_string());
+        package.progress = ProgressBar::hidden();
+        Some(package.progress.clone())
+    }
+
+    fn toggle_pause(&self) {
+        let mut state = self.lock();
+        state.paused = !state.paused;
+        if !state.paused {
+            self.resumed.notify_all();
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.lock()
+            .packages
+            .iter()
+            .all(|p| matches!(p.status, Status::Installed | Status::Failed))
+    }
+
+    /// Copy the latest worker messages into the package logs
+    fn collect_messages(&self) {
+        for package in self.lock().packages.iter_mut() {
+            let message = package.progress.message();
+            if !message.is_empty() && package.log.last() != Some(&message) {
+                package.log.push(message);
+            }
+        }
+    }
+}
+
+/// Whether the dashboard can be drawn, it needs an interactive terminal on stdout
+pub fn is_supported() -> bool {
+    console::Term::stdout().is_term()
+}
+
+/// Run the dashboard until the user quits after every package finished.
+///
+/// `dispatch` is called with the progress bar of every retried package. Quitting while
+/// packages are still running exits the process like a second Ctrl-C.
+pub fn run(dashboard: &Dashboard, mut dispatch: impl FnMut(&str, ProgressBar)) -> eyre::Result<()> {
+    let mut terminal = ratatui::try_init()?;
+    let mut table = TableState::default().with_selected(0);
+
+    let result = loop {
+        dashboard.collect_messages();
+        if let Err(e) = terminal.draw(|frame| draw(frame, dashboard, &mut table)) {
+            break Err(e.into());
+        }
+
+        match event::poll(TICK) {
+            Ok(false) => continue,
+            Ok(true) => {}
+            Err(e) => break Err(e.into()),
+        }
+        let key = match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => key,
+            Ok(_) => continue,
+            Err(e) => break Err(e.into()),
+        };
+
+        let quit = key.code == KeyCode::Char('q')
+            || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL));
+        match key.code {
+            _ if quit => {
+                if dashboard.is_done() {
+                    break Ok(());
+                }
+                ratatui::restore();
+                eprintln!("Interrupted while packages were still installing");
+                std::process::exit(130);
+            }
+            KeyCode::Down | KeyCode::Char('j') => table.select_next(),
+            KeyCode::Up | KeyCode::Char('k') => table.select_previous(),
+            KeyCode::Char('p') => dashboard.toggle_pause(),
+            KeyCode::Char('r') => {
+                let name = table
+                    .selected()
+                    .and_then(|index| dashboard.lock().packages.get(index).map(|p| p.name.clone()));
+                if let Some(name) = name {
+                    if let Some(progress) = dashboard.retry(&name) {
+                        dispatch(&name, progress);
+                    }
+                }
+            }
+            _ => {}
+        }
+    };
+
+    ratatui::restore();
+    result
+}
+
+fn draw(frame: &mut Frame, dashboard: &Dashboard, table: &mut TableState) {
+    let state = dashboard.lock();
+    let [header, packages, log] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Min(3),
+        Constraint::Length(LOG_LINES + 2),
+    ])
+    .areas(frame.area());
+
+    frame.render_widget(
+        Paragraph::new(summary(&state)).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(" workstation setup  [↑↓] select  [p] pause  [r] retry  [q] quit "),
+        ),
+        header,
+    );
+
+    let rows = state.packages.iter().map(|package| {
+        let position = package.progress.position();
+        let length = package.progress.length().unwrap_or(0);
+        let (progress, speed, eta) = match package.status {
+            Status::Running if length > 0 => (
+                format!(
+                    "{} / {} ({}%)",
+                    HumanBytes(position),
+                    HumanBytes(length),
+                    position * 100 / length
+                ),
+                format!("{}/s", HumanBytes(package.progress.per_sec() as u64)),
+                format!("{}", HumanDuration(package.progress.eta())),
+            ),
+            _ if length > 0 => (format!("{}", HumanBytes(length)), "".into(), "".into()),
+            _ => ("".into(), "".into(), "".into()),
+        };
+        Row::new(vec![
+            Cell::from(package.name.clone()),
+            Cell::from(package.status.label()).style(package.status.style()),
+            Cell::from(progress),
+            Cell::from(speed),
+            Cell::from(eta),
+        ])
+    });
+    frame.render_stateful_widget(
+        Table::new(
+            rows,
+            [
+                Constraint::Percentage(25),
+                Constraint::Length(10),
+                Constraint::Percentage(35),
+                Constraint::Length(12),
+                Constraint::Length(10),
+            ],
+        )
+        .header(
+            Row::new(vec!["Package", "Status", "Progress", "Speed", "ETA"])
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .block(Block::default().borders(Borders::ALL)),
+        packages,
+        table,
+    );
+
+    let selected = table.selected().and_then(|index| state.packages.get(index));
+    let (title, lines) = match selected {
+        Some(package) => {
+            let skip = package.log.len().saturating_sub(LOG_LINES as usize);
+            (
+                format!(" {} ", package.name),
+                package.log[skip..]
+                    .iter()
+                    .map(|line| Line::from(line.clone()))
+                    .collect(),
+            )
+        }
+        None => (" log ".to_string(), vec![]),
+    };
+    frame.render_widget(
+        Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .block(Block::default().borders(Borders::ALL).title(title)),
+        log,
+    );
+}
+
+/// The aggregate header line
+fn summary(state: &State) -> String {
+    let count = |status| state.packages.iter().filter(|p| p.status == status).count();
+    let downloaded: u64 = state.packages.iter().map(|p| p.progress.position()).sum();
+    let total: u64 = state
+        .packages
+        .iter()
+        .filter_map(|p| p.progress.length())
+        .sum();
+    let eta = state
+        .packages
+        .iter()
+        .filter(|p| p.status == Status::Running)
+        .map(|p| p.progress.eta())
+        .max()
+        .unwrap_or_default();
+
+    let mut summary = format!(
+        "{} / {}  ETA {}  jobs {}  {} installed, {} failed, {} waiting",
+        HumanBytes(downloaded),
+        HumanBytes(total),
+        HumanDuration(eta),
+        count(Status::Running),
+        count(Status::Installed),
+        count(Status::Failed),
+        count(Status::Waiting),
+    );
+    if let Some(budget) = &state.budget {
+        summary.push_str(&format!(
+            "  mem {}/{}",
+            HumanBytes(budget.in_use()),
+            HumanBytes(budget.limit())
+        ));
+    }
+    if state.paused {
+        summary.push_str("  (paused)");
+    } else if state
+        .packages
+        .iter()
+        .all(|p| matches!(p.status, Status::Installed | Status::Failed))
+    {
+        summary.push_str("  (done, press q to see the summary)");
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{mpsc, Arc};
+
+    use ratatui::{backend::TestBackend, Terminal};
+
+    use super::*;
+
+    fn rendered(dashboard: &Dashboard) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(100, 20)).unwrap();
+        let mut table = TableState::default().with_selected(0);
+        terminal
+            .draw(|frame| draw(frame, dashboard, &mut table))
+            .unwrap();
+        let buffer = terminal.backend().buffer();
+        buffer
+            .content()
+            .chunks(buffer.area.width as usize)
+            .map(|row| row.iter().map(|cell| cell.symbol()).collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn test_rows_show_status_and_selected_error() {
+        let dashboard = Dashboard::new();
+        dashboard.add("rg");
+        let progress = dashboard.add("fd");
+        progress.set_length(2048);
+        dashboard.start("rg");
+        dashboard.finish("rg", Some("[E010] Entry rg not found".to_string()));
+        dashboard.start("fd");
+        let budget = Budget::new(512 * 1024 * 1024);
+        let reservation = budget.reservation(1);
+        reservation.grow(3 * 1024 * 1024, |_, _| {});
+        dashboard.track_memory(budget.clone());
+
+        let screen = rendered(&dashboard);
+
+        assert!(screen.contains("mem 3.00 MiB/512.00 MiB"), "{}", screen);
+        assert!(screen.contains("failed"));
+        assert!(screen.contains("running"));
+        assert!(screen.contains("[E010] Entry rg not found"));
+        assert!(screen.contains("1 failed, 0 waiting"));
+    }
+
+    #[test]
+    fn test_pause_holds_workers_until_resumed() {
+        let dashboard = Arc::new(Dashboard::new());
+        dashboard.add("rg");
+        dashboard.toggle_pause();
+
+        let (sender, receiver) = mpsc::channel();
+        let worker = {
+            let dashboard = dashboard.clone();
+            std::thread::spawn(move || {
+                dashboard.start("rg");
+                sender.send(()).unwrap();
+            })
+        };
+
+        assert!(receiver.recv_timeout(Duration::from_millis(50)).is_err());
+        dashboard.toggle_pause();
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        worker.join().unwrap();
+        assert_eq!(dashboard.lock().packages[0].status, Status::Running);
+    }
+
+    #[test]
+    fn test_only_failed_packages_are_retried() {
+        let dashboard = Dashboard::new();
+        dashboard.add("rg");
+        dashboard.add("fd");
+        dashboard.finish("rg", Some("boom".to_string()));
+        dashboard.finish("fd", None);
+
+        assert!(dashboard.retry("fd").is_none());
+        assert!(dashboard.retry("rg").is_some());
+        assert!(!dashboard.is_done());
+    }
+}
@@ -0,0 +1,301 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::{config::PackageConfig, ignore::IgnoreSet, local, lock, manifest::Manifest};
+
+/// Why a package has to be installed again
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reason {
+    NotInstalled,
+    /// Installed before the manifest recorded where packages come from
+    Unrecorded,
+    /// A field deciding what gets installed changed, such as `version` or `bin`
+    ConfigChanged,
+    /// The same config resolves to another asset, such as a new latest release
+    NewSource {
+        installed: String,
+        resolved: String,
+    },
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::NotInstalled => write!(f, "not installed"),
+            Reason::Unrecorded => write!(f, "installed before its source was recorded"),
+            Reason::ConfigChanged => write!(f, "config changed since it was installed"),
+            Reason::NewSource {
+                installed,
+                resolved,
+            } => write!(f, "{} -> {}", installed, resolved),
+        }
+    }
+}
+
+/// Which packages differ from what resolving the config gives now
+#[derive(Debug, Default, PartialEq)]
+pub struct Plan {
+    pub outdated: Vec<(String, Reason)>,
+    /// Packages from local archives, `setup` compares the archive itself and skips it if it
+    /// did not change
+    pub local: Vec<String>,
+    /// Packages whose source could not be resolved, with the error
+    pub failed: Vec<(String, String)>,
+    pub up_to_date: usize,
+}
+
+impl Plan {
+    /// The packages to hand to `setup`
+    pub fn install(&self) -> Vec<String> {
+        self.outdated
+            .iter()
+            .map(|(name, _)| name.clone())
+            .chain(self.local.iter().cloned())
+            .collect()
+    }
+}
+
+/// Compare every package with its manifest entry.
+///
+/// `resolve` gives the source a package would be installed from now. Nothing is downloaded,
+/// only GitHub releases are looked up.
+pub fn plan(
+    packages: &[PackageConfig],
+    manifest: &Manifest,
+    ignore: &IgnoreSet,
+    path_of: impl Fn(&PackageConfig) -> eyre::Result<PathBuf>,
+    resolve: impl Fn(&PackageConfig) -> eyre::Result<String>,
+) -> eyre::Result<Plan> {
+    let mut plan = Plan::default();
+
+    for package in packages {
+        let name = package.name();
+        if ignore.matching_pattern(Path::new(name)).is_some() {
+            continue;
+        }
+        let path = path_of(package)?;
+        if !path.exists() {
+            plan.outdated.push((name.to_string(), Reason::NotInstalled));
+            continue;
+        }
+        // Custom commands are skipped while `creates` exists and git clones are pulled by every
+        // `setup`, there is nothing to compare
+        if package.is_self_managed() {
+            plan.up_to_date += 1;
+            continue;
+        }
+        let url = package
+            .url()
+            .map(|url| package.expand_url(url))
+            .transpose()?;
+        if url.as_deref().and_then(local::local_path).is_some() {
+            plan.local.push(name.to_string());
+            continue;
+        }
+
+        let installed = manifest
+            .get(name)
+            .filter(|installed| installed.path == path);
+        let Some((recorded_fields, recorded_source)) =
+            installed.and_then(|installed| Some((installed.fields_hash?, installed.source?)))
+        else {
+            plan.outdated.push((name.to_string(), Reason::Unrecorded));
+            continue;
+        };
+        if recorded_fields != lock::fields_hash(package) {
+            plan.outdated
+                .push((name.to_string(), Reason::ConfigChanged));
+            continue;
+        }
+
+        // Crates and Go modules are built from what the config names, there is no asset to
+        // resolve
+        let resolved = match package {
+            PackageConfig::Cargo { .. } | PackageConfig::Go { .. } => Ok(package.source()),
+            _ => resolve(package),
+        };
+        match resolved {
+            Ok(resolved) if resolved != recorded_source => plan.outdated.push((
+                name.to_string(),
+                Reason::NewSource {
+                    installed: recorded_source,
+                    resolved,
+                },
+            )),
+            Ok(_) => plan.up_to_date += 1,
+            Err(e) => plan.failed.push((name.to_string(), format!("{:#}", e))),
+        }
+    }
+
+    Ok(plan)
+}
+
+pub fn render(plan: &Plan, dry_run: bool) -> String {
+    let mut rendered = String::new();
+    if !plan.outdated.is_empty() {
+        rendered.push_str(if dry_run {
+            "Would update:\n"
+        } else {
+            "Updating:\n"
+        });
+        for (name, reason) in &plan.outdated {
+            rendered.push_str(&format!("  {}: {}\n", name, reason));
+        }
+    }
+    if !plan.failed.is_empty() {
+        rendered.push_str("Could not check:\n");
+        for (name, error) in &plan.failed {
+            rendered.push_str(&format!("  {}: {}\n", name, error));
+        }
+    }
+    rendered.push_str(&format!("{} package(s) up to date\n", plan.up_to_date));
+
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{config, manifest::Staged};
+
+    use super::*;
+
+    #[test]
+    fn test_only_changed_packages_are_outdated() {
+        let directory = tempfile::tempdir().unwrap();
+        let bin = directory.path().join("bin");
+        std::fs::create_dir(&bin).unwrap();
+        for name in ["rg", "fd", "bat", "eza", "yazi", "tool"] {
+            std::fs::write(bin.join(name), name).unwrap();
+        }
+        let source = r#"
+            [linux_x86_64]
+            location = "/unused"
+            packages = [
+                { name = "rg", url = "https://example.com/rg" },
+                { name = "fd", url = "https://example.com/fd" },
+                { name = "bat", url = "https://example.com/bat" },
+                { name = "eza", repo = "eza-community/eza" },
+                { name = "yazi", repo = "sxyazi/yazi" },
+                { name = "tool", archive = "~/tool.tar.gz", bin = "tool" },
+                { name = "new", url = "https://example.com/new" },
+            ]
+            "#;
+        let target = crate::arch::Target::parse("linux_x86_64").unwrap();
+        let config = config::parse_config_for(source, &target).unwrap();
+        let before = config::parse_config_for(
+            &source.replace("example.com/fd", "example.com/fd-9"),
+            &target,
+        )
+        .unwrap();
+
+        let manifest = Manifest::load(directory.path()).unwrap();
+        for package in &before.platform.packages {
+            let source = match package.name() {
+                "eza" => "https://github.com/eza/v1/eza.tar.gz",
+                "yazi" => "https://github.com/yazi/v1/yazi.zip",
+                _ => package.url().unwrap(),
+            };
+            let staged = Staged {
+                hash: crate::digest::sha256(package.name().as_bytes()),
+                source: Some(source.to_string()),
+                fields_hash: Some(lock::fields_hash(package)),
+                path: None,
+                location: None,
+                files: vec![],
+            };
+            if package.name() != "bat" {
+                manifest.stage(package.name(), staged);
+            }
+        }
+        let names = ["rg", "fd", "bat", "eza", "yazi", "tool"].map(str::to_string);
+        manifest.commit(&names, &bin);
+        let resolve = |package: &PackageConfig| match package.name() {
+            "eza" => Ok("https://github.com/eza/v2/eza.tar.gz".to_string()),
+            "yazi" => Err(eyre::eyre!("rate limited")),
+            _ => Ok(package.url().unwrap().to_string()),
+        };
+
+        let plan = plan(
+            &config.platform.packages,
+            &manifest,
+            &IgnoreSet::new(&[]).unwrap(),
+            |package| Ok(bin.join(package.name())),
+            resolve,
+        )
+        .unwrap();
+
+        assert_eq!(
+            plan.outdated,
+            vec![
+                ("fd".to_string(), Reason::ConfigChanged),
+                ("bat".to_string(), Reason::Unrecorded),
+                (
+                    "eza".to_string(),
+                    Reason::NewSource {
+                        installed: "https://github.com/eza/v1/eza.tar.gz".to_string(),
+                        resolved: "https://github.com/eza/v2/eza.tar.gz".to_string(),
+                    }
+                ),
+                ("new".to_string(), Reason::NotInstalled),
+            ]
+        );
+        assert_eq!(plan.local, ["tool"]);
+        assert_eq!(plan.up_to_date, 1);
+        assert_eq!(plan.install(), ["fd", "bat", "eza", "new", "tool"]);
+        let rendered = render(&plan, true);
+        assert!(rendered.starts_with("Would update:\n  fd: config changed"));
+        assert!(rendered.contains(
+            "  eza: https://github.com/eza/v1/eza.tar.gz -> https://github.com/eza/v2/eza.tar.gz\n"
+        ));
+        assert!(rendered.contains("Could not check:\n  yazi: rate limited\n"));
+    }
+
+    #[test]
+    fn test_built_packages_are_compared_by_their_source() {
+        let directory = tempfile::tempdir().unwrap();
+        let bin = directory.path().join("bin");
+        std::fs::create_dir(&bin).unwrap();
+        let source = r#"
+            [linux_x86_64]
+            location = "/unused"
+            packages = [
+                { name = "rg", crate = "ripgrep" },
+                { name = "gopls", go = "golang.org/x/tools/gopls", version = "v0.16.1" },
+            ]
+            "#;
+        let target = crate::arch::Target::parse("linux_x86_64").unwrap();
+        let config = config::parse_config_for(source, &target).unwrap();
+        let manifest = Manifest::load(directory.path()).unwrap();
+        let mut names = vec![];
+        for package in &config.platform.packages {
+            std::fs::write(bin.join(package.name()), package.name()).unwrap();
+            manifest.stage(
+                package.name(),
+                Staged {
+                    hash: crate::digest::sha256(package.name().as_bytes()),
+                    source: Some(package.source()),
+                    fields_hash: Some(lock::fields_hash(package)),
+                    path: None,
+                    location: None,
+                    files: vec![],
+                },
+            );
+            names.push(package.name().to_string());
+        }
+        manifest.commit(&names, &bin);
+
+        let plan = plan(
+            &config.platform.packages,
+            &manifest,
+            &IgnoreSet::new(&[]).unwrap(),
+            |package| Ok(bin.join(package.name())),
+            |package| panic!("{} has no asset to resolve", package.name()),
+        )
+        .unwrap();
+
+        assert_eq!(plan.up_to_date, names.len());
+        assert!(plan.outdated.is_empty() && plan.failed.is_empty());
+    }
+}
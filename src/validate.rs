@@ -0,0 +1,335 @@
+use std::collections::BTreeMap;
+
+use serde::{de::DeserializeOwned, Deserialize};
+use toml_edit::{ImDocument, Item, Value};
+
+use crate::{
+    arch::Target,
+    check::{self, Position, Problem, Section},
+    config::{
+        self, ArchConfig, CommonConfig, Config, ConfigFile, DotfilesConfig, DownloadConfig,
+        HooksConfig, InstallConfig, ProfileConfig, RustupConfig,
+    },
+    digest, keys, migrate,
+    paths::PathsConfig,
+};
+
+/// Every problem of the config `source` found without the network, each with the line and
+/// column it is at, and the config when it parses.
+///
+/// The config is read table by table and package by package, so that one mistake does not hide
+/// the others and each is reported where it is, not at the start of the file.
+pub fn validate(source: &str) -> (Vec<Section>, Option<Config>) {
+    if let Err(e) = ImDocument::parse(source) {
+        let offset = e.span().map(|span| span.start).unwrap_or_default();
+        let problem = at(source, offset, None, e.message().trim_end().to_string());
+        return (vec![parse_section(1, vec![problem])], None);
+    }
+    let source = match config::migrated(source) {
+        Ok(source) => source,
+        Err(e) => {
+            let problem = Problem {
+                package: None,
+                message: format!("{:#}", e),
+                position: None,
+            };
+            return (vec![parse_section(1, vec![problem])], None);
+        }
+    };
+    let document = ImDocument::parse(source.as_ref()).expect("migrated config parses");
+    let values: toml::Table = toml::from_str(&source).expect("migrated config parses");
+
+    let mut problems = vec![];
+    if !config::unknown_keys_allowed() {
+        let unknown = keys::unknown_keys(&source, &config::json_schema()).unwrap_or_default();
+        problems.extend(unknown.into_iter().map(|key| Problem {
+            package: None,
+            message: key.message(),
+            position: Some(Position {
+                line: key.line,
+                column: key.column,
+            }),
+        }));
+    }
+
+    let mut checked = 0;
+    let mut parsed = true;
+    // Where each package of each section is, by section and name
+    let mut packages: BTreeMap<String, BTreeMap<String, usize>> = BTreeMap::new();
+    for (name, value) in &values {
+        if name == migrate::VERSION_KEY {
+            continue;
+        }
+        let key = document.as_table().key(name).expect("iterated key exists");
+        let offset = key.span().map(|span| span.start).unwrap_or_default();
+        let section = || format!("section {}", name);
+        checked += 1;
+        let error = match name.as_str() {
+            config::ROOT_TABLE => match value.as_table() {
+                Some(settings) => {
+                    let table = document.get(name).and_then(Item::as_table_like);
+                    for (name, value) in settings {
+                        let offset = table
+                            .and_then(|table| table.key(name))
+                            .and_then(|key| key.span())
+                            .map_or(offset, |span| span.start);
+                        if let Some(message) = check_settings(name, value) {
+                            parsed = false;
+                            problems.push(at(&source, offset, None, message));
+                        }
+                    }
+                    None
+                }
+                None => Some("must be a table".to_string()),
+            },
+            name if config::SETTINGS.contains(&name) => check_settings(name, value),
+            "profiles" => deserialize::<BTreeMap<String, ProfileConfig>>(value).err(),
+            "common" => deserialize::<CommonConfig>(&without_packages(value)).err(),
+            _ => deserialize::<ArchConfig>(&without_packages(value)).err(),
+        };
+        if let Some(message) = error {
+            parsed = false;
+            problems.push(at(&source, offset, None, message));
+        }
+
+        let entries = value.get("packages").and_then(toml::Value::as_array);
+        let offsets = package_offsets(document.get(name).and_then(|item| item.get("packages")));
+        for (index, entry) in entries.into_iter().flatten().enumerate() {
+            let offset = offsets.get(index).copied().unwrap_or(offset);
+            let name = entry.get("name").and_then(toml::Value::as_str);
+            if let Some(name) = name {
+                packages
+                    .entry(section())
+                    .or_default()
+                    .entry(name.to_string())
+                    .or_insert(offset);
+            }
+            checked += 1;
+            if let Some(message) = check_package(entry) {
+                parsed = false;
+                problems.push(at(&source, offset, name, message));
+            }
+        }
+    }
+    problems.sort_by_key(|problem| problem.position.map(|at| (at.line, at.column)));
+    // Unknown keys are only reported, the config reads without them
+    if !parsed {
+        return (vec![parse_section(checked, problems)], None);
+    }
+
+    let mut document: toml_edit::DocumentMut = source.parse().expect("migrated config parses");
+    let hoisted = config::hoist_settings(&mut document).and_then(|_| {
+        let mut table: toml::Table = toml::from_str(&document.to_string())?;
+        table.remove(migrate::VERSION_KEY);
+        Ok(table)
+    });
+    let selected = hoisted
+        .and_then(|table| {
+            ConfigFile::deserialize(toml::Value::Table(table))
+                .map_err(|e| eyre::eyre!("{}", e.message()))
+        })
+        .and_then(|file| file.select(Target::current()));
+    let config = match selected {
+        Ok(config) => config,
+        Err(e) => {
+            problems.push(Problem {
+                package: None,
+                message: format!("{:#}", e),
+                position: None,
+            });
+            return (vec![parse_section(checked, problems)], None);
+        }
+    };
+
+    // A problem of a package is where the package the platform uses is defined
+    let mut lint = check::lint(&config);
+    let sections = [
+        format!("section {}", config.section),
+        "section common".to_string(),
+    ];
+    for problem in &mut lint.problems {
+        let offset = problem.package.as_ref().and_then(|package| {
+            sections
+                .iter()
+                .find_map(|section| packages.get(section)?.get(package))
+        });
+        if let Some(&offset) = offset {
+            let (line, column) = keys::position(&source, offset);
+            problem.position = Some(Position { line, column });
+        }
+    }
+
+    (vec![parse_section(checked, problems), lint], Some(config))
+}
+
+fn parse_section(checked: usize, problems: Vec<Problem>) -> Section {
+    Section {
+        name: "parse",
+        checked,
+        problems,
+    }
+}
+
+fn at(source: &str, offset: usize, package: Option<&str>, message: String) -> Problem {
+    let (line, column) = keys::position(source, offset);
+    Problem {
+        package: package.map(str::to_string),
+        message,
+        position: Some(Position { line, column }),
+    }
+}
+
+/// What is wrong with the table `name` of the [`config::WorkstationConfig`] settings, if anything
+fn check_settings(name: &str, value: &toml::Value) -> Option<String> {
+    match name {
+        "install" => match deserialize::<InstallConfig>(value) {
+            Ok(install) => install.ignore_set().err().map(|e| format!("{:#}", e)),
+            Err(e) => Some(e),
+        },
+        "paths" => deserialize::<PathsConfig>(value).err(),
+        "download" => deserialize::<DownloadConfig>(value).err(),
+        "hooks" => deserialize::<HooksConfig>(value).err(),
+        "dotfiles" => deserialize::<DotfilesConfig>(value).err(),
+        "env" => deserialize::<BTreeMap<String, String>>(value).err(),
+        "rustup" => deserialize::<RustupConfig>(value).err(),
+        // Reported as an unknown key
+        _ => None,
+    }
+}
+
+fn deserialize<T: DeserializeOwned>(value: &toml::Value) -> Result<T, String> {
+    T::deserialize(value.clone()).map_err(|e| e.message().to_string())
+}
+
+/// A section with its packages left out, they are checked one by one
+fn without_packages(value: &toml::Value) -> toml::Value {
+    let mut value = value.clone();
+    if let Some(packages) = value.get_mut("packages") {
+        *packages = toml::Value::Array(vec![]);
+    }
+    value
+}
+
+/// What is wrong with one package entry, if anything
+fn check_package(entry: &toml::Value) -> Option<String> {
+    let package = match deserialize::<config::PackageConfig>(entry) {
+        Ok(package) => package,
+        // Untagged, serde cannot tell which kind of package was meant
+        Err(e) if e.contains("untagged enum") => {
+            return Some(
+                "is no kind of package, it needs `url`, `bin` and `archive`, `auto_arch_bin` and `archive`, `archive` and `directory`, `repo`, `git` and `dest`, `command` and `creates`, `appimage`, `crate`, `go`, `pipx`, `npm`, `flatpak`, `snap`, `deb` or `rpm`, or one of `apt`, `dnf`, `pacman`, `brew` and `cask`, each of the right type".to_string(),
+            )
+        }
+        Err(e) => return Some(e),
+    };
+    let options = package.options();
+    let pin = options
+        .pin_hash
+        .as_ref()
+        .and_then(|pin| digest::validate(pin).err())
+        .map(|e| format!("invalid pin_hash: {:#}", e));
+    pin.or_else(|| {
+        let sha256 = options.sha256.as_ref()?;
+        digest::from_hex(sha256)
+            .err()
+            .map(|e| format!("invalid sha256: {:#}", e))
+    })
+}
+
+/// Where each entry of a `packages` array starts: its `name` key, or the entry itself when it has
+/// no name
+fn package_offsets(packages: Option<&Item>) -> Vec<usize> {
+    let start = |span: Option<std::ops::Range<usize>>| span.map(|span| span.start);
+    match packages {
+        Some(Item::ArrayOfTables(array)) => array
+            .iter()
+            .map(|table| {
+                start(table.key("name").and_then(|key| key.span()))
+                    .or(start(table.span()))
+                    .unwrap_or_default()
+            })
+            .collect(),
+        Some(Item::Value(Value::Array(array))) => array
+            .iter()
+            .map(|value| match value {
+                Value::InlineTable(table) => start(table.key("name").and_then(|key| key.span()))
+                    .or(start(table.span()))
+                    .unwrap_or_default(),
+                value => start(value.span()).unwrap_or_default(),
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rendered(source: &str) -> String {
+        check::render(&validate(source).0)
+    }
+
+    #[test]
+    fn test_every_problem_is_reported_where_it_is() {
+        let source = r#"
+[workstation.download]
+segments = "many"
+
+[linux_x86_64]
+location = "/opt/bin"
+packages = [
+  { name = "rg", url = "https://example.com/rg" },
+  { name = "fd", url = 5 },
+  { name = "bat" },
+  { name = "eza", url = "https://example.com/eza", pin_hash = "md5:abc" },
+]
+
+[[macos_aarch64.packages]]
+name = "jq"
+urll = "https://example.com/jq"
+"#;
+
+        let (sections, config) = validate(source);
+        let problems = &sections[0].problems;
+        let lines: Vec<_> = problems
+            .iter()
+            .map(|problem| problem.position.unwrap().line)
+            .collect();
+
+        assert!(config.is_none());
+        assert_eq!(sections.len(), 1);
+        assert_eq!(lines, [2, 9, 10, 11, 14, 15, 16], "{:#?}", problems);
+        assert!(problems[0].message.contains("invalid type"));
+        assert_eq!(problems[1].package.as_deref(), Some("fd"));
+        assert!(problems[2].message.starts_with("is no kind of package"));
+        assert!(problems[3].message.contains("pin_hash"));
+        assert!(problems[4].message.contains("missing field `location`"));
+        assert_eq!(
+            problems[6].message,
+            "unknown key macos_aarch64.packages[0].urll, did you mean url?"
+        );
+    }
+
+    #[test]
+    fn test_lint_problems_point_at_their_package() {
+        let source = r#"
+[linux_x86_64]
+location = "/opt/bin"
+packages = [
+  { name = "rg", url = "https://example.com/rg" },
+  { name = "fd", url = "example.com/fd" },
+]
+"#;
+        let source = source.replace("linux_x86_64", &Target::current().to_string());
+
+        assert_eq!(
+            rendered(&source),
+            "parse: 3 checked, 0 problem(s)\n\
+             config: 2 checked, 1 problem(s)\n  \
+             line 6, column 5: fd: invalid URL \"example.com/fd\": relative URL without a base\n"
+        );
+        assert!(rendered("[linux\nlocation = 1")
+            .starts_with("parse: 1 checked, 1 problem(s)\n  line 1, column 7: "));
+    }
+}
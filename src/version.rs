@@ -0,0 +1,364 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+use eyre::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{archive, asset::Asset};
+
+const RECORDS_FILE: &str = "versions.json";
+/// The newest version of the file this binary reads and writes, see [`crate::state`]
+const SCHEMA_VERSION: u32 = 1;
+/// Version files larger than this are not a version number
+const MAX_VERSION_FILE: usize = 256;
+
+/// A version guessed while installing, only ever shown to the user.
+///
+/// Nothing decides whether to install based on it, it can be wrong.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Inferred {
+    pub version: String,
+    pub from: Provenance,
+}
+
+/// Where an inferred version was found
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "hint", rename_all = "snake_case")]
+pub enum Provenance {
+    /// A `version` or `VERSION` file in the archive
+    VersionFile { entry: String },
+    /// The top level directory of the archive, such as `ripgrep-14.1.0-x86_64-unknown-linux-musl`
+    Directory { name: String },
+    /// The tag of a GitHub release download URL
+    GithubTag { tag: String },
+    /// What `cargo install` reported installing
+    Cargo { krate: String },
+    /// The build info of a binary `go install` built
+    Go { module: String },
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Provenance::VersionFile { entry } => write!(f, "archive file {}", entry),
+            Provenance::Directory { name } => write!(f, "archive directory {}", name),
+            Provenance::GithubTag { tag } => write!(f, "GitHub release tag {}", tag),
+            Provenance::Cargo { krate } => write!(f, "cargo install of {}", krate),
+            Provenance::Go { module } => write!(f, "build info of {}", module),
+        }
+    }
+}
+
+impl fmt::Display for Inferred {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (inferred from {})", self.version, self.from)
+    }
+}
+
+/// The best version guess for a package downloaded from `url`, looking into the archive when
+/// there is one.
+///
+/// Hints are tried from the most to the least explicit: a version file, the top level directory
+/// name, then the release tag. An archive that cannot be read only means no archive hints.
+pub fn infer(url: &str, archive: Option<&Asset>) -> Option<Inferred> {
+    archive
+        .and_then(|asset| from_archive(url, asset))
+        .or_else(|| from_github_tag(url))
+}
+
+fn from_archive(archive: &str, asset: &Asset) -> Option<Inferred> {
+    let entries = archive::list_entries(archive, asset.reader().ok()?).ok()?;
+    let normalized: Vec<_> = entries
+        .iter()
+        .map(|entry| {
+            let name = entry.path.as_str();
+            name.strip_prefix("./")
+                .unwrap_or(name)
+                .trim_end_matches('/')
+        })
+        .filter(|name| !name.is_empty())
+        .collect();
+
+    // A version file at the top, or in the single top level directory
+    let is_version_file = |name: &str| {
+        let name = name.strip_prefix("./").unwrap_or(name);
+        let depth = name.matches('/').count();
+        let file = name.rsplit('/').next().unwrap_or(name);
+        depth <= 1 && matches!(file, "version" | "VERSION" | "VERSION.txt")
+    };
+    let files = asset.reader().and_then(|reader| {
+        // One byte more than the limit tells a file that is too long apart
+        archive::read_files(
+            archive,
+            reader,
+            is_version_file,
+            MAX_VERSION_FILE as u64 + 1,
+        )
+    });
+    if let Ok(files) = files {
+        let mut files = files;
+        files.sort_by_key(|(name, _)| name.matches('/').count());
+        for (entry, content) in files {
+            if content.len() > MAX_VERSION_FILE {
+                continue;
+            }
+            let text = String::from_utf8_lossy(&content);
+            if let Some(version) = text.split_whitespace().next().and_then(parse_version) {
+                return Some(Inferred {
+                    version,
+                    from: Provenance::VersionFile {
+                        entry: entry.strip_prefix("./").unwrap_or(&entry).to_string(),
+                    },
+                });
+            }
+        }
+    }
+
+    let top: Vec<_> = normalized
+        .iter()
+        .map(|name| name.split('/').next().unwrap_or(name))
+        .collect();
+    let directory = top.first()?;
+    let single_directory = top.iter().all(|name| name == directory)
+        && normalized.iter().any(|name| name.contains('/'));
+    if !single_directory {
+        return None;
+    }
+    let version = directory.split('-').skip(1).find_map(parse_version)?;
+    Some(Inferred {
+        version,
+        from: Provenance::Directory {
+            name: directory.to_string(),
+        },
+    })
+}
+
+/// `v1.2.3` from `https://github.com/<owner>/<repo>/releases/download/v1.2.3/<asset>`
+fn from_github_tag(url: &str) -> Option<Inferred> {
+    let path = url
+        .strip_prefix("https://github.com/")
+        .or_else(|| url.strip_prefix("http://github.com/"))?;
+    let mut parts = path.split('/');
+    let (_owner, _repo) = (parts.next()?, parts.next()?);
+    if (parts.next()?, parts.next()?) != ("releases", "download") {
+        return None;
+    }
+    let tag = parts.next()?;
+    // Tags such as `nightly` or `stable` name no version
+    let version = parse_version(tag).or_else(|| parse_version(tag.rsplit(['-', '@']).next()?))?;
+    Some(Inferred {
+        version,
+        from: Provenance::GithubTag {
+            tag: tag.to_string(),
+        },
+    })
+}
+
+/// `1.2`, `1.2.3` or `1.2.3-rc.1`, with an optional leading `v`
+fn parse_version(text: &str) -> Option<String> {
+    let text = text.trim();
+    let text = text.strip_prefix(['v', 'V']).unwrap_or(text);
+    let text = text.split('+').next().unwrap_or(text);
+    let (core, rest) = text.split_once('-').unwrap_or((text, ""));
+
+    let parts: Vec<_> = core.split('.').collect();
+    let numeric = |part: &&str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    if parts.len() < 2 || !parts.iter().all(numeric) {
+        return None;
+    }
+
+    // Keep prerelease tags, but not build targets such as `x86_64-unknown-linux-musl`
+    let pre = rest.split('-').next().unwrap_or_default();
+    if ["alpha", "beta", "rc", "pre"]
+        .iter()
+        .any(|tag| pre.starts_with(tag))
+    {
+        Some(format!("{}-{}", core, pre))
+    } else {
+        Some(core.to_string())
+    }
+}
+
+/// Inferred versions of installed packages, kept in the state directory
+pub struct Versions {
+    path: PathBuf,
+    records: Mutex<BTreeMap<String, Inferred>>,
+    /// Guesses for packages being installed, kept once the install is final
+    staged: Mutex<BTreeMap<String, Option<Inferred>>>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl Versions {
+    /// Load the records, a missing or unreadable file only means no versions are known
+    pub fn load(state_dir: &Path) -> Self {
+        let path = state_dir.join(RECORDS_FILE);
+        let records = std::fs::read(&path)
+            .ok()
+            .and_then(|bytes| crate::state::parse(&bytes, SCHEMA_VERSION).ok())
+            .unwrap_or_default();
+        Versions {
+            path,
+            records: Mutex::new(records),
+            staged: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn save(&self) -> eyre::Result<()> {
+        let directory = self.path.parent().expect("records path has a parent");
+        std::fs::create_dir_all(directory)
+            .with_context(|| format!("Creating {}", directory.display()))?;
+        let json = crate::state::to_json(&*lock(&self.records), SCHEMA_VERSION);
+        std::fs::write(&self.path, json).with_context(|| format!("Writing {}", self.path.display()))
+    }
+
+    pub fn get(&self, package: &str) -> Option<Inferred> {
+        lock(&self.records).get(package).cloned()
+    }
+
+    /// Forget the version of a package that was uninstalled
+    pub fn remove(&self, package: &str) {
+        lock(&self.records).remove(package);
+    }
+
+    /// Remember the version of a package just installed, replacing the old one once
+    /// [`Versions::commit`] confirms the install
+    pub fn stage(&self, package: &str, inferred: Option<Inferred>) {
+        lock(&self.staged).insert(package.to_string(), inferred);
+    }
+
+    /// Keep the staged versions of `installed`, dropping those of rolled back packages. A
+    /// package installed without a guess forgets its old version.
+    pub fn commit(&self, installed: &[String]) {
+        let staged = std::mem::take(&mut *lock(&self.staged));
+        let mut records = lock(&self.records);
+        for (package, inferred) in staged {
+            if !installed.contains(&package) {
+                continue;
+            }
+            match inferred {
+                Some(inferred) => records.insert(package, inferred),
+                None => records.remove(&package),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::archive::tests::{tar_gz, zip};
+
+    use super::*;
+
+    fn asset(bytes: &[u8]) -> Asset {
+        Asset::from_bytes(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_version_file() {
+        let bytes = tar_gz(&[
+            ("tool/", b""),
+            ("tool/bin/tool", b"binary"),
+            ("tool/VERSION", b"v2.4.1\n"),
+        ]);
+
+        assert_eq!(
+            infer("https://example.com/tool.tar.gz", Some(&asset(&bytes))),
+            Some(Inferred {
+                version: "2.4.1".to_string(),
+                from: Provenance::VersionFile {
+                    entry: "tool/VERSION".to_string()
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_top_level_directory() {
+        let bytes = zip(&[
+            ("ripgrep-14.1.0-x86_64-unknown-linux-musl/", b""),
+            ("ripgrep-14.1.0-x86_64-unknown-linux-musl/rg", b"binary"),
+        ]);
+
+        let inferred = infer("https://example.com/rg.zip", Some(&asset(&bytes))).unwrap();
+
+        assert_eq!(inferred.version, "14.1.0");
+        assert_eq!(
+            inferred.to_string(),
+            "14.1.0 (inferred from archive directory ripgrep-14.1.0-x86_64-unknown-linux-musl)"
+        );
+    }
+
+    #[test]
+    fn test_github_tag() {
+        let url = "https://github.com/neovim/neovim/releases/download/v0.10.1/nvim.tar.gz";
+        let unversioned = tar_gz(&[("nvim/", b""), ("nvim/bin/nvim", b"binary")]);
+
+        assert_eq!(
+            infer(url, Some(&asset(&unversioned))),
+            Some(Inferred {
+                version: "0.10.1".to_string(),
+                from: Provenance::GithubTag {
+                    tag: "v0.10.1".to_string()
+                },
+            })
+        );
+        let nightly = "https://github.com/neovim/neovim/releases/download/nightly/nvim.tar.gz";
+        assert_eq!(infer(nightly, None), None);
+    }
+
+    #[test]
+    fn test_no_hint_means_no_version() {
+        let flat = tar_gz(&[("tool", b"binary"), ("README.md", b"docs")]);
+        let two_dirs = tar_gz(&[("a-1.0/x", b""), ("b-2.0/y", b"")]);
+
+        assert_eq!(
+            infer("https://example.com/tool.tar.gz", Some(&asset(&flat))),
+            None
+        );
+        assert_eq!(
+            infer("https://example.com/t.tar.gz", Some(&asset(&two_dirs))),
+            None
+        );
+        assert_eq!(
+            infer("https://example.com/broken.tar.gz", Some(&asset(b"junk"))),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("v1.2.3").as_deref(), Some("1.2.3"));
+        assert_eq!(parse_version("1.2.3-rc.1").as_deref(), Some("1.2.3-rc.1"));
+        assert_eq!(parse_version("0.10").as_deref(), Some("0.10"));
+        assert_eq!(parse_version("14").as_deref(), None);
+        assert_eq!(parse_version("x86_64").as_deref(), None);
+    }
+
+    #[test]
+    fn test_records_round_trip() {
+        let directory = tempfile::tempdir().unwrap();
+        let versions = Versions::load(directory.path());
+        let inferred =
+            from_github_tag("https://github.com/sharkdp/fd/releases/download/v10.2.0/fd.tar.gz");
+        versions.stage("fd", inferred.clone());
+        versions.stage("rg", inferred.clone());
+        versions.commit(&["fd".to_string()]);
+        versions.save().unwrap();
+
+        let versions = Versions::load(directory.path());
+        assert_eq!(versions.get("fd"), inferred);
+        assert_eq!(versions.get("rg"), None);
+        versions.stage("fd", None);
+        versions.commit(&["fd".to_string()]);
+        assert_eq!(versions.get("fd"), None);
+    }
+}
@@ -0,0 +1,268 @@
+use std::{
+    cell::RefCell,
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    time::Duration,
+};
+
+use eyre::Context;
+use notify::{RecursiveMode, Watcher};
+
+use crate::{
+    config::{self, ArchConfig, Config, PackageConfig},
+    include::Origin,
+};
+
+/// Wait this long after the last change before re-running, editors often write several times
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+enum Event {
+    Changed,
+    Interrupted,
+}
+
+/// Run `run` with the packages of `path` that changed since the previous run and the whole
+/// config, until Ctrl-C.
+///
+/// The files the config includes are watched too, the list again after every change of it.
+pub fn watch(
+    path: &Path,
+    mut run: impl FnMut(&Config, &Config) -> eyre::Result<()>,
+) -> eyre::Result<()> {
+    let (sender, receiver) = mpsc::channel();
+
+    let interrupt_sender = sender.clone();
+    let mut interrupted = false;
+    ctrlc::set_handler(move || {
+        if interrupted {
+            std::process::exit(130);
+        }
+        interrupted = true;
+        eprintln!("Stopping after the current run, press Ctrl-C again to exit immediately");
+        let _ = interrupt_sender.send(Event::Interrupted);
+    })
+    .context("Installing the Ctrl-C handler")?;
+
+    let files = Arc::new(Mutex::new(BTreeSet::new()));
+    let watched = files.clone();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else {
+            return;
+        };
+        if event.kind.is_access() {
+            return;
+        }
+        let watched = watched
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if event.paths.iter().any(|changed| watched.contains(changed)) {
+            let _ = sender.send(Event::Changed);
+        }
+    })
+    .context("Creating the file watcher")?;
+    let (directory, _) = split(path)?;
+    watcher
+        .watch(&directory, RecursiveMode::NonRecursive)
+        .with_context(|| format!("Watching {}", directory.display()))?;
+    let mut directories = BTreeSet::from([directory]);
+
+    let mut previous: Option<Config> = None;
+    loop {
+        let (loaded, read) = load(path);
+        // The include list may have changed, watch the files read this time
+        let mut next_files = BTreeSet::new();
+        let mut next_directories = BTreeSet::new();
+        for file in &read {
+            let Ok((directory, name)) = split(file) else {
+                continue;
+            };
+            next_files.insert(directory.join(name));
+            next_directories.insert(directory);
+        }
+        for directory in directories.difference(&next_directories) {
+            let _ = watcher.unwatch(directory);
+        }
+        for directory in next_directories.difference(&directories) {
+            if let Err(e) = watcher.watch(directory, RecursiveMode::NonRecursive) {
+                eprintln!("warning: not watching {}: {}", directory.display(), e);
+            }
+        }
+        directories = next_directories;
+        *files
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = next_files;
+
+        match loaded {
+            Ok(next) => {
+                let changed = changed_packages(previous.as_ref(), &next);
+                if changed.platform.packages.is_empty() {
+                    eprintln!("No package changed");
+                } else {
+                    clear_screen();
+                    if let Err(e) = run(&changed, &next) {
+                        eprintln!("Error: {:?}", e);
+                    }
+                }
+                previous = Some(next);
+            }
+            Err(e) => eprintln!("Error: {:?}\nWaiting for the config to be fixed", e),
+        }
+
+        eprintln!("Watching {} for changes", path.display());
+        match wait_for_change(&receiver) {
+            Event::Changed => {}
+            Event::Interrupted => return Ok(()),
+        }
+    }
+}
+
+/// The config at `path` and the files it was read from, the ones read before an error too
+fn load(path: &Path) -> (eyre::Result<Config>, Vec<PathBuf>) {
+    let read = RefCell::new(vec![path.to_path_buf()]);
+    let config = config::read_config_source_tracking(None, path, &|origin| {
+        if let Origin::Path(included) = origin {
+            read.borrow_mut().push(included.clone());
+        }
+    })
+    .and_then(|source| config::parse_config(&source));
+    (config, read.into_inner())
+}
+
+/// The directory of the config file `path` as the watcher reports it, and the file name.
+///
+/// The directory is watched rather than the file, editors commonly replace the file instead of
+/// writing to it.
+fn split(path: &Path) -> eyre::Result<(PathBuf, PathBuf)> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| eyre::eyre!("{} is not a file", path.display()))?;
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let directory = std::fs::canonicalize(directory).unwrap_or_else(|_| directory.to_path_buf());
+    Ok((directory, PathBuf::from(name)))
+}
+
+fn wait_for_change(receiver: &mpsc::Receiver<Event>) -> Event {
+    let Ok(Event::Changed) = receiver.recv() else {
+        return Event::Interrupted;
+    };
+
+    loop {
+        match receiver.recv_timeout(DEBOUNCE) {
+            Ok(Event::Changed) => continue,
+            Ok(Event::Interrupted) | Err(mpsc::RecvTimeoutError::Disconnected) => {
+                return Event::Interrupted
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => return Event::Changed,
+        }
+    }
+}
+
+fn clear_screen() {
+    let _ = console::Term::stderr().clear_screen();
+}
+
+/// The config restricted to the packages that differ from `previous`.
+///
+/// Everything is considered changed when there is no previous config or the location moved.
+pub fn changed_packages(previous: Option<&Config>, next: &Config) -> Config {
+    let packages = match previous {
+        Some(previous) if previous.platform.location == next.platform.location => next
+            .platform
+            .packages
+            .iter()
+            .filter(|package| !previous.platform.packages.contains(package))
+            .cloned()
+            .collect::<Vec<PackageConfig>>(),
+        _ => next.platform.packages.clone(),
+    };
+
+    Config {
+        platform: ArchConfig {
+            location: next.platform.location.clone(),
+            packages,
+        },
+        ..next.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(location: &str, packages: &[(&str, &str)]) -> Config {
+        Config {
+            platform: ArchConfig {
+                location: PathBuf::from(location),
+                packages: packages
+                    .iter()
+                    .map(|(name, url)| PackageConfig::Binary {
+                        name: name.to_string(),
+                        url: url.to_string(),
+                        version: None,
+                        options: Default::default(),
+                    })
+                    .collect(),
+            },
+            ..Default::default()
+        }
+    }
+
+    fn names(config: &Config) -> Vec<&str> {
+        config
+            .platform
+            .packages
+            .iter()
+            .map(|package| package.name())
+            .collect()
+    }
+
+    #[test]
+    fn test_first_run_installs_everything() {
+        let next = config("~/.local/bin", &[("rg", "a"), ("fd", "b")]);
+
+        assert_eq!(names(&changed_packages(None, &next)), vec!["rg", "fd"]);
+    }
+
+    #[test]
+    fn test_only_changed_entries_are_selected() {
+        let previous = config("~/.local/bin", &[("rg", "a"), ("fd", "b")]);
+        let next = config("~/.local/bin", &[("rg", "a"), ("fd", "c"), ("fzf", "d")]);
+
+        assert_eq!(
+            names(&changed_packages(Some(&previous), &next)),
+            vec!["fd", "fzf"]
+        );
+    }
+
+    #[test]
+    fn test_included_files_are_watched_even_when_broken() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("workstation.toml");
+        std::fs::write(
+            &path,
+            "include = [\"common.toml\"]\n\n[linux_x86_64]\nlocation = \"/unused\"\npackages = []\n",
+        )
+        .unwrap();
+        std::fs::write(directory.path().join("common.toml"), "[install\n").unwrap();
+
+        let (config, read) = load(&path);
+
+        assert!(config.is_err());
+        assert_eq!(read, [path.clone(), directory.path().join("common.toml")]);
+        let (watched, name) = split(&read[1]).unwrap();
+        assert_eq!(watched, std::fs::canonicalize(directory.path()).unwrap());
+        assert_eq!(name, Path::new("common.toml"));
+    }
+
+    #[test]
+    fn test_moved_location_selects_everything() {
+        let previous = config("~/.local/bin", &[("rg", "a")]);
+        let next = config("~/bin", &[("rg", "a")]);
+
+        assert_eq!(names(&changed_packages(Some(&previous), &next)), vec!["rg"]);
+    }
+}
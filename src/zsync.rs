@@ -0,0 +1,387 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use eyre::Context;
+use indicatif::ProgressBar;
+use md4::{Digest, Md4};
+use sha1::Sha1;
+
+use crate::download::{self, ThrottledProgress};
+
+/// A parsed `.zsync` control file, as written by `zsyncmake`: a few `Key: value` header lines, an
+/// empty line, then a weak and a strong checksum for every block of the new file
+#[derive(Debug)]
+pub struct Control {
+    block_size: usize,
+    length: u64,
+    /// Bytes kept of the rolling checksum, its low end
+    rsum_bytes: usize,
+    /// Bytes kept of the MD4 of each block
+    checksum_bytes: usize,
+    /// Hex SHA-1 of the whole new file
+    sha1: String,
+    blocks: Vec<(u32, Vec<u8>)>,
+}
+
+impl Control {
+    pub fn parse(bytes: &[u8]) -> eyre::Result<Self> {
+        let end = bytes
+            .windows(2)
+            .position(|window| window == b"\n\n")
+            .ok_or_else(|| eyre::eyre!("The zsync control file has no checksums"))?;
+        let header = std::str::from_utf8(&bytes[..end]).context("Reading the zsync header")?;
+        let checksums = &bytes[end + 2..];
+
+        let fields: HashMap<_, _> = header
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .collect();
+        let field = |key: &str| {
+            fields
+                .get(key)
+                .copied()
+                .ok_or_else(|| eyre::eyre!("The zsync control file has no {} header", key))
+        };
+        let number = |key: &str| -> eyre::Result<u64> {
+            field(key)?
+                .parse()
+                .with_context(|| format!("Reading the zsync {} header", key))
+        };
+
+        let block_size = number("Blocksize")? as usize;
+        let length = number("Length")?;
+        let lengths: Vec<usize> = field("Hash-Lengths")?
+            .split(',')
+            .map(|part| part.trim().parse())
+            .collect::<Result<_, _>>()
+            .context("Reading the zsync Hash-Lengths header")?;
+        let [_seq_matches, rsum_bytes, checksum_bytes] = lengths[..] else {
+            eyre::bail!("Hash-Lengths must have three values");
+        };
+        if block_size == 0 || !(1..=4).contains(&rsum_bytes) || !(3..=16).contains(&checksum_bytes)
+        {
+            eyre::bail!("Unsupported zsync block size or hash lengths");
+        }
+
+        let count = length.div_ceil(block_size as u64) as usize;
+        let entry = rsum_bytes + checksum_bytes;
+        if checksums.len() < count * entry {
+            eyre::bail!(
+                "The zsync control file has checksums for {} of {} blocks",
+                checksums.len() / entry,
+                count
+            );
+        }
+        let blocks = checksums
+            .chunks_exact(entry)
+            .take(count)
+            .map(|entry| {
+                let (rsum, checksum) = entry.split_at(rsum_bytes);
+                let rsum = rsum
+                    .iter()
+                    .fold(0u32, |key, byte| key << 8 | u32::from(*byte));
+                (rsum, checksum.to_vec())
+            })
+            .collect();
+
+        Ok(Control {
+            block_size,
+            length,
+            rsum_bytes,
+            checksum_bytes,
+            sha1: field("SHA-1")?.to_ascii_lowercase(),
+            blocks,
+        })
+    }
+}
+
+/// The rolling checksum of zsync and rsync: the byte sum, and the sum weighted by the distance
+/// from the end of the block
+fn rsum(block: &[u8]) -> (u16, u16) {
+    block
+        .iter()
+        .enumerate()
+        .fold((0u16, 0u16), |(a, b), (index, byte)| {
+            let byte = u16::from(*byte);
+            let weight = (block.len() - index) as u16;
+            (
+                a.wrapping_add(byte),
+                b.wrapping_add(weight.wrapping_mul(byte)),
+            )
+        })
+}
+
+/// The low `rsum_bytes` of the checksum as stored in the control file
+fn rsum_key((a, b): (u16, u16), rsum_bytes: usize) -> u32 {
+    let full = u32::from(a) << 16 | u32::from(b);
+    match rsum_bytes {
+        4 => full,
+        bytes => full & ((1 << (bytes * 8)) - 1),
+    }
+}
+
+fn strong(block: &[u8], checksum_bytes: usize) -> Vec<u8> {
+    Md4::digest(block)[..checksum_bytes].to_vec()
+}
+
+/// The new file with every block found in `seed` filled in, and the inclusive byte ranges that
+/// are still missing.
+///
+/// Blocks are matched by the weak checksum and confirmed by the truncated strong one. A rare
+/// false match is caught by the SHA-1 of the whole file.
+fn reconstruct(control: &Control, seed: &[u8]) -> (Vec<u8>, Vec<(u64, u64)>) {
+    let size = control.block_size;
+    let mut data = vec![0; control.blocks.len() * size];
+    let mut found = vec![false; control.blocks.len()];
+
+    let mut index: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (block, (rsum, _)) in control.blocks.iter().enumerate() {
+        index.entry(*rsum).or_default().push(block);
+    }
+
+    if seed.len() >= size {
+        let mut offset = 0;
+        let (mut a, mut b) = rsum(&seed[..size]);
+        loop {
+            let matched = index
+                .get(&rsum_key((a, b), control.rsum_bytes))
+                .is_some_and(|candidates| {
+                    let window = &seed[offset..offset + size];
+                    let checksum = strong(window, control.checksum_bytes);
+                    let mut matched = false;
+                    for &block in candidates {
+                        if control.blocks[block].1 == checksum {
+                            if !found[block] {
+                                data[block * size..(block + 1) * size].copy_from_slice(window);
+                                found[block] = true;
+                            }
+                            matched = true;
+                        }
+                    }
+                    matched
+                });
+
+            if matched {
+                // The next block of the seed is most likely the next block of the new file
+                offset += size;
+                if offset + size > seed.len() {
+                    break;
+                }
+                (a, b) = rsum(&seed[offset..offset + size]);
+                continue;
+            }
+            if offset + size >= seed.len() {
+                break;
+            }
+            let (old, new) = (u16::from(seed[offset]), u16::from(seed[offset + size]));
+            a = a.wrapping_sub(old).wrapping_add(new);
+            b = b
+                .wrapping_sub((size as u16).wrapping_mul(old))
+                .wrapping_add(a);
+            offset += 1;
+        }
+    }
+
+    let mut missing: Vec<(u64, u64)> = vec![];
+    for block in (0..found.len()).filter(|block| !found[*block]) {
+        let start = (block * size) as u64;
+        let end = (((block + 1) * size) as u64).min(control.length) - 1;
+        match missing.last_mut() {
+            Some((_, last)) if *last + 1 == start => *last = end,
+            _ => missing.push((start, end)),
+        }
+    }
+    data.truncate(control.length as usize);
+
+    (data, missing)
+}
+
+/// An asset assembled from a previous version and the ranges that changed
+pub struct Delta {
+    pub bytes: Vec<u8>,
+    /// Bytes actually downloaded, the control file aside
+    pub downloaded: u64,
+}
+
+impl Delta {
+    pub fn saved(&self) -> u64 {
+        (self.bytes.len() as u64).saturating_sub(self.downloaded)
+    }
+}
+
+/// Download `url` by reusing the blocks of `seed`, an earlier version of the asset, that the
+/// control file at `zsync_url` says are still in it. Only the other ranges are requested.
+pub fn download(
+    client: &download::Client,
+    url: &str,
+    zsync_url: &str,
+    seed: &[u8],
+    pb: &ProgressBar,
+    reserve: &dyn Fn(u64),
+) -> eyre::Result<Delta> {
+    let response = client.get(zsync_url).send()?;
+    if !response.status().is_success() {
+        eyre::bail!("Failed to download {}: {}", zsync_url, response.status());
+    }
+    let control =
+        Control::parse(&response.bytes()?).with_context(|| format!("Reading {}", zsync_url))?;
+
+    reserve(control.length);
+    let (mut data, missing) = reconstruct(&control, seed);
+    let downloaded = missing.iter().map(|(start, end)| end - start + 1).sum();
+
+    let progress = Mutex::new(ThrottledProgress::new(pb, downloaded));
+    let position = AtomicU64::new(0);
+    let on_progress = |read: u64| {
+        let position = position.fetch_add(read, Ordering::Relaxed) + read;
+        progress
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .set_position(position);
+    };
+    for (start, end) in &missing {
+        let slice = &mut data[*start as usize..=*end as usize];
+        download::fetch_range(client, url, *start, *end, slice, &on_progress)?;
+    }
+    progress
+        .into_inner()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .flush();
+
+    let sha1: String = Sha1::digest(&data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    if sha1 != control.sha1 {
+        eyre::bail!(
+            "{} reassembled from {} does not match its SHA-1",
+            url,
+            zsync_url
+        );
+    }
+
+    Ok(Delta {
+        bytes: data,
+        downloaded,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_server::{Route, TestServer};
+
+    use super::*;
+
+    /// What `zsyncmake -b <block_size>` writes, with full length checksums
+    fn control_file(data: &[u8], block_size: usize) -> Vec<u8> {
+        let sha1: String = Sha1::digest(data)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let mut control = format!(
+            "zsync: 0.6.2\nFilename: sdk.tar.gz\nBlocksize: {}\nLength: {}\nHash-Lengths: 1,4,16\nURL: sdk.tar.gz\nSHA-1: {}\n\n",
+            block_size,
+            data.len(),
+            sha1
+        )
+        .into_bytes();
+        for block in data.chunks(block_size) {
+            let mut padded = block.to_vec();
+            padded.resize(block_size, 0);
+            let (a, b) = rsum(&padded);
+            control.extend_from_slice(&a.to_be_bytes());
+            control.extend_from_slice(&b.to_be_bytes());
+            control.extend_from_slice(&strong(&padded, 16));
+        }
+        control
+    }
+
+    fn releases() -> (Vec<u8>, Vec<u8>) {
+        let old: Vec<u8> = (0..100_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        // The new release inserts a few bytes near the start and rewrites a stretch in the middle
+        let mut new = old[..1_000].to_vec();
+        new.extend_from_slice(b"inserted");
+        new.extend_from_slice(&old[1_000..50_000]);
+        new.extend(std::iter::repeat_n(7, 3_000));
+        new.extend_from_slice(&old[53_000..]);
+        (old, new)
+    }
+
+    #[test]
+    fn test_unchanged_blocks_are_found_at_any_offset() {
+        let (old, new) = releases();
+        let control = Control::parse(&control_file(&new, 1024)).unwrap();
+
+        let (data, missing) = reconstruct(&control, &old);
+
+        let missing_bytes: u64 = missing.iter().map(|(start, end)| end - start + 1).sum();
+        assert!(missing_bytes <= 8 * 1024, "{:?}", missing);
+        for (start, end) in &missing {
+            assert!(data[*start as usize..=*end as usize]
+                .iter()
+                .all(|b| *b == 0));
+        }
+        let found = |index: usize| {
+            missing
+                .iter()
+                .all(|(s, e)| !(*s..=*e).contains(&(index as u64)))
+        };
+        for index in (0..new.len()).filter(|index| found(*index)) {
+            assert_eq!(data[index], new[index], "byte {}", index);
+        }
+    }
+
+    #[test]
+    fn test_download_fetches_only_changed_ranges() {
+        let (old, new) = releases();
+        let server = TestServer::start();
+        server.route("/sdk.tar.gz", Route::ok(new.clone()).with_ranges());
+        server.route("/sdk.tar.gz.zsync", Route::ok(control_file(&new, 1024)));
+
+        let delta = download(
+            &download::Client::new(&Default::default()),
+            &server.url("/sdk.tar.gz"),
+            &server.url("/sdk.tar.gz.zsync"),
+            &old,
+            &ProgressBar::hidden(),
+            &|_| {},
+        )
+        .unwrap();
+
+        assert_eq!(delta.bytes, new);
+        assert!(delta.saved() > 90_000, "saved {}", delta.saved());
+    }
+
+    #[test]
+    fn test_control_file_for_other_content_fails() {
+        let (old, new) = releases();
+        let server = TestServer::start();
+        server.route("/sdk.tar.gz", Route::ok(new).with_ranges());
+        server.route(
+            "/sdk.tar.gz.zsync",
+            Route::ok(control_file(&old[..90_000], 1024)),
+        );
+
+        let error = download(
+            &download::Client::new(&Default::default()),
+            &server.url("/sdk.tar.gz"),
+            &server.url("/sdk.tar.gz.zsync"),
+            &old,
+            &ProgressBar::hidden(),
+            &|_| {},
+        )
+        .err()
+        .unwrap();
+
+        assert!(error.to_string().contains("does not match its SHA-1"));
+        assert!(Control::parse(b"zsync: 0.6.2\nBlocksize: 1024\n\n").is_err());
+    }
+}